@@ -161,17 +161,33 @@ impl Export {
             .await
             .map_err(|v| format!("Exporter build error: {v}"))?;
 
-        let mut stdout = stdout();
+        let stdout = stdout();
 
         let exporter_output_path = cap_export::mp4::Mp4ExportSettings {
             fps: 60,
             resolution_base: XY::new(1920, 1080),
+            fit: None,
             compression: cap_export::mp4::ExportCompression::Minimal,
+            web_optimized: false,
+            crf: None,
+            keyframe_interval: None,
+            codec: cap_export::mp4::Mp4Codec::H264,
+            require_codec: false,
+            preset: Default::default(),
+            av1_speed: Default::default(),
+            keep_hdr: false,
+            audio_gain: None,
+            include_audio: true,
+            audio_channels: Default::default(),
+            audio: Default::default(),
+            fade_in_ms: 0,
+            fade_out_ms: 0,
+            metadata: Default::default(),
         }
-        .export(exporter_base, move |_f| {
-            // print!("\rrendered frame {f}");
+        .export(exporter_base, move |_p| {
+            // print!("\rrendered frame {p:?}");
 
-            stdout.flush().unwrap();
+            stdout.lock().flush().unwrap();
         })
         .await
         .map_err(|v| format!("Exporter error: {v}"))?;
@@ -66,6 +66,10 @@ impl RecordStart {
                 capture_system_audio: self.system_audio,
                 mic_feed: None,
                 camera_feed: None, // camera.map(|c| Arc::new(Mutex::new(c))),
+                encoder_error_policy: Default::default(),
+                segment_duration: None,
+                mic_wav_sidecar: false,
+                threaded_encoding: false,
             },
             false,
         )
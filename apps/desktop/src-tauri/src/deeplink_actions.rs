@@ -134,6 +134,10 @@ impl DeepLinkAction {
                     capture_target,
                     capture_system_audio,
                     mode,
+                    encoder_error_policy: Default::default(),
+                    segment_duration_secs: None,
+                    mic_wav_sidecar: false,
+                    threaded_encoding: false,
                 };
 
                 crate::recording::start_recording(app.clone(), state, inputs).await
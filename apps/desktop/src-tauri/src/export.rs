@@ -1,16 +1,71 @@
-use crate::{FramesRendered, get_video_metadata};
-use cap_export::ExporterBase;
+use crate::get_video_metadata;
+use cap_export::{ExportError, ExportProgress, ExporterBase, mp4::ExportCompression};
 use cap_project::{RecordingMeta, XY};
 use serde::Deserialize;
 use specta::Type;
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
 use tracing::info;
 
-#[derive(Deserialize, Clone, Copy, Debug, Type)]
+/// Tokens for the exports that are currently running, by project path.
+/// Each is tagged with an id so a finished export doesn't remove a newer one's token.
+static EXPORT_CANCEL_TOKENS: Mutex<BTreeMap<PathBuf, (u64, CancellationToken)>> =
+    Mutex::new(BTreeMap::new());
+static NEXT_EXPORT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Makes an export cancellable with [`cancel_export`] until it's dropped.
+struct ExportCancelGuard {
+    project_path: PathBuf,
+    id: u64,
+}
+
+impl ExportCancelGuard {
+    fn register(project_path: &Path, cancel_token: CancellationToken) -> Self {
+        let id = NEXT_EXPORT_ID.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut tokens) = EXPORT_CANCEL_TOKENS.lock() {
+            tokens.insert(project_path.to_path_buf(), (id, cancel_token));
+        }
+
+        Self {
+            project_path: project_path.to_path_buf(),
+            id,
+        }
+    }
+}
+
+impl Drop for ExportCancelGuard {
+    fn drop(&mut self) {
+        if let Ok(mut tokens) = EXPORT_CANCEL_TOKENS.lock()
+            && tokens
+                .get(&self.project_path)
+                .is_some_and(|(id, _)| *id == self.id)
+        {
+            tokens.remove(&self.project_path);
+        }
+    }
+}
+
+/// Seconds it took to render and encode one megapixel of one frame during the last export.
+/// Used to estimate export time, falling back to [`DEFAULT_FRAME_COST`] before any export.
+static MEASURED_FRAME_COST: Mutex<Option<f64>> = Mutex::new(None);
+const DEFAULT_FRAME_COST: f64 = 0.01;
+
+#[derive(Deserialize, Clone, Debug, Type)]
 #[serde(tag = "format")]
 pub enum ExportSettings {
     Mp4(cap_export::mp4::Mp4ExportSettings),
     Gif(cap_export::gif::GifExportSettings),
+    WebM(cap_export::webm::WebMExportSettings),
+    Apng(cap_export::apng::ApngExportSettings),
+    Audio(cap_export::audio::AudioExportSettings),
 }
 
 impl ExportSettings {
@@ -18,18 +73,40 @@ impl ExportSettings {
         match self {
             ExportSettings::Mp4(settings) => settings.fps,
             ExportSettings::Gif(settings) => settings.fps,
+            ExportSettings::WebM(settings) => settings.fps,
+            ExportSettings::Apng(settings) => settings.fps,
+            ExportSettings::Audio(_) => cap_export::audio::AudioExportSettings::PROGRESS_FPS,
         }
     }
+
+    /// `None` for exports without video.
+    fn resolution_base(&self) -> Option<XY<u32>> {
+        match self {
+            ExportSettings::Mp4(settings) => Some(settings.resolution_base),
+            ExportSettings::Gif(settings) => Some(settings.resolution_base),
+            ExportSettings::WebM(settings) => Some(settings.resolution_base),
+            ExportSettings::Apng(settings) => Some(settings.resolution_base),
+            ExportSettings::Audio(_) => None,
+        }
+    }
+}
+
+fn megapixels(resolution: XY<u32>) -> f64 {
+    (resolution.x as f64 * resolution.y as f64) / 1_000_000.0
 }
 
 #[tauri::command]
 #[specta::specta]
 pub async fn export_video(
     project_path: PathBuf,
-    progress: tauri::ipc::Channel<FramesRendered>,
+    progress: tauri::ipc::Channel<ExportProgress>,
     settings: ExportSettings,
 ) -> Result<PathBuf, String> {
+    let cancel_token = CancellationToken::new();
+    let _cancel_guard = ExportCancelGuard::register(&project_path, cancel_token.clone());
+
     let exporter_base = ExporterBase::builder(project_path)
+        .with_cancel_token(cancel_token)
         .build()
         .await
         .map_err(|e| {
@@ -39,45 +116,86 @@ pub async fn export_video(
 
     let total_frames = exporter_base.total_frames(settings.fps());
 
-    let _ = progress.send(FramesRendered {
-        rendered_count: 0,
-        total_frames,
+    let _ = progress.send(ExportProgress::Rendering {
+        done: 0,
+        total: total_frames,
+        eta: None,
     });
 
+    // audio exports don't say anything about the cost of rendering frames
+    let megapixels = settings.resolution_base().map_or(0.0, megapixels);
+    let started_at = Instant::now();
+
     let output_path = match settings {
         ExportSettings::Mp4(settings) => {
             settings
-                .export(exporter_base, move |frame_index| {
-                    // Ensure progress never exceeds total frames
-                    let _ = progress.send(FramesRendered {
-                        rendered_count: (frame_index + 1).min(total_frames),
-                        total_frames,
-                    });
+                .export(exporter_base, move |p| {
+                    let _ = progress.send(p);
                 })
                 .await
         }
         ExportSettings::Gif(settings) => {
             settings
-                .export(exporter_base, move |frame_index| {
-                    // Ensure progress never exceeds total frames
-                    let _ = progress.send(FramesRendered {
-                        rendered_count: (frame_index + 1).min(total_frames),
-                        total_frames,
-                    });
+                .export(exporter_base, move |p| {
+                    let _ = progress.send(p);
+                })
+                .await
+        }
+        ExportSettings::WebM(settings) => {
+            settings
+                .export(exporter_base, move |p| {
+                    let _ = progress.send(p);
+                })
+                .await
+        }
+        ExportSettings::Apng(settings) => {
+            settings
+                .export(exporter_base, move |p| {
+                    let _ = progress.send(p);
+                })
+                .await
+        }
+        ExportSettings::Audio(settings) => {
+            settings
+                .export(exporter_base, move |p| {
+                    let _ = progress.send(p);
                 })
                 .await
         }
     }
     .map_err(|e| {
-        sentry::capture_message(&e.to_string(), sentry::Level::Error);
+        if !matches!(e, ExportError::Cancelled) {
+            sentry::capture_message(&e.to_string(), sentry::Level::Error);
+        }
         e.to_string()
     })?;
 
     info!("Exported to {} completed", output_path.display());
 
+    if total_frames > 0 && megapixels > 0.0 {
+        let frame_cost = started_at.elapsed().as_secs_f64() / (total_frames as f64 * megapixels);
+        if let Ok(mut measured) = MEASURED_FRAME_COST.lock() {
+            *measured = Some(frame_cost);
+        }
+    }
+
     Ok(output_path)
 }
 
+/// Stops the running export of the project and removes its partial output.
+/// `export_video` then fails with [`ExportError::Cancelled`].
+#[tauri::command]
+#[specta::specta]
+pub async fn cancel_export(project_path: PathBuf) {
+    if let Some((_, token)) = EXPORT_CANCEL_TOKENS
+        .lock()
+        .ok()
+        .and_then(|mut tokens| tokens.remove(&project_path))
+    {
+        token.cancel();
+    }
+}
+
 #[derive(Debug, serde::Serialize, specta::Type)]
 pub struct ExportEstimates {
     pub duration_seconds: f64,
@@ -85,56 +203,34 @@ pub struct ExportEstimates {
     pub estimated_size_mb: f64,
 }
 
-// This will need to be refactored at some point to be more accurate.
 #[tauri::command]
 #[specta::specta]
 pub async fn get_export_estimates(
     path: PathBuf,
     resolution: XY<u32>,
     fps: u32,
+    compression: ExportCompression,
 ) -> Result<ExportEstimates, String> {
-    let metadata = get_video_metadata(path.clone()).await?;
-
-    let meta = RecordingMeta::load_for_project(&path).unwrap();
+    let meta = RecordingMeta::load_for_project(&path).map_err(|e| e.to_string())?;
     let project_config = meta.project_config();
-    let duration_seconds = if let Some(timeline) = &project_config.timeline {
-        timeline.segments.iter().map(|s| s.duration()).sum()
-    } else {
-        metadata.duration
+    let duration_seconds = match &project_config.timeline {
+        Some(timeline) => timeline.duration(),
+        None => get_video_metadata(path.clone()).await?.duration,
     };
 
-    let (width, height) = (resolution.x, resolution.y);
-
-    let base_bitrate = if width <= 1280 && height <= 720 {
-        4_000_000.0
-    } else if width <= 1920 && height <= 1080 {
-        8_000_000.0
-    } else if width <= 2560 && height <= 1440 {
-        14_000_000.0
-    } else {
-        20_000_000.0
-    };
-
-    let fps_factor = (fps as f64) / 30.0;
-    let video_bitrate = base_bitrate * fps_factor;
-
-    let audio_bitrate = 192_000.0;
-
-    let total_bitrate = video_bitrate + audio_bitrate;
-
-    let estimated_size_mb = (total_bitrate * duration_seconds) / (8.0 * 1024.0 * 1024.0);
-
-    let base_factor = match (width, height) {
-        (w, h) if w <= 1280 && h <= 720 => 0.43,
-        (w, h) if w <= 1920 && h <= 1080 => 0.64,
-        (w, h) if w <= 2560 && h <= 1440 => 0.75,
-        _ => 0.86,
-    };
+    let video_bitrate = compression.video_bitrate(resolution, fps) as f64;
+    let audio_bitrate = cap_export::mp4::AUDIO_BITRATE as f64;
 
-    let processing_time = duration_seconds * base_factor * fps_factor;
-    let overhead_time = 0.0;
+    let estimated_size_mb =
+        ((video_bitrate + audio_bitrate) * duration_seconds) / (8.0 * 1024.0 * 1024.0);
 
-    let estimated_time_seconds = processing_time + overhead_time;
+    let frame_cost = MEASURED_FRAME_COST
+        .lock()
+        .ok()
+        .and_then(|v| *v)
+        .unwrap_or(DEFAULT_FRAME_COST);
+    let total_frames = duration_seconds * fps as f64;
+    let estimated_time_seconds = total_frames * megapixels(resolution) * frame_cost;
 
     Ok(ExportEstimates {
         duration_seconds,
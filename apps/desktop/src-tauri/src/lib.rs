@@ -32,7 +32,7 @@ use cap_project::{
     ZoomSegment,
 };
 use cap_recording::{
-    RecordingMode,
+    RecordingMode, RecordingStats,
     feeds::{
         self,
         camera::{CameraFeed, DeviceOrModelID},
@@ -400,6 +400,15 @@ async fn get_current_recording(
     Ok(JsonValue::new(&Some(CurrentRecording { target, mode })))
 }
 
+/// Progress of the active recording, for the timer and for spotting stalled captures.
+#[tauri::command]
+#[specta::specta]
+async fn get_current_recording_stats(
+    state: MutableState<'_, App>,
+) -> Result<Option<RecordingStats>, ()> {
+    Ok(state.read().await.current_recording().map(|r| r.stats()))
+}
+
 #[derive(Serialize, Type, tauri_specta::Event, Clone)]
 pub struct CurrentRecordingChanged;
 
@@ -672,17 +681,19 @@ async fn copy_file_to_path(app: AppHandle, src: String, dst: String) -> Result<(
     Err(last_error.unwrap_or_else(|| "Maximum retry attempts exceeded".to_string()))
 }
 
+/// Whether a video can be opened and has a video stream, which MP4s don't have until
+/// they've finished being written.
 pub fn is_valid_mp4(path: &std::path::Path) -> bool {
-    if let Ok(file) = std::fs::File::open(path) {
-        let file_size = match file.metadata() {
-            Ok(metadata) => metadata.len(),
-            Err(_) => return false,
-        };
-        let reader = std::io::BufReader::new(file);
-        Mp4Reader::read_header(reader, file_size).is_ok()
-    } else {
-        false
-    }
+    cap_media::probe(path).is_ok_and(|info| info.video.is_some())
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn get_media_info(path: PathBuf) -> Result<cap_media::MediaInfo, String> {
+    tokio::task::spawn_blocking(move || cap_media::probe(path))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -1830,11 +1841,14 @@ pub async fn run(recording_logging_handle: LoggingHandle) {
             fake_window::remove_fake_window,
             focus_captures_panel,
             get_current_recording,
+            get_current_recording_stats,
             export::export_video,
+            export::cancel_export,
             export::get_export_estimates,
             copy_file_to_path,
             copy_video_to_clipboard,
             copy_screenshot_to_clipboard,
+            get_media_info,
             open_file_path,
             get_video_metadata,
             create_editor_instance,
@@ -1867,6 +1881,7 @@ pub async fn run(recording_logging_handle: LoggingHandle) {
             show_window,
             write_clipboard_string,
             platform::perform_haptic_feedback,
+            platform::get_platform_capabilities,
             list_fails,
             set_fail,
             update_auth_plan,
@@ -1918,7 +1933,8 @@ pub async fn run(recording_logging_handle: LoggingHandle) {
         .typ::<presets::PresetsStore>()
         .typ::<hotkeys::HotkeysStore>()
         .typ::<general_settings::GeneralSettingsStore>()
-        .typ::<cap_flags::Flags>();
+        .typ::<cap_flags::Flags>()
+        .typ::<FramesRendered>();
 
     #[cfg(debug_assertions)]
     specta_builder
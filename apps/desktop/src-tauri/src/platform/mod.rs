@@ -50,3 +50,63 @@ pub fn perform_haptic_feedback(
     #[cfg(not(target_os = "macos"))]
     Err("Haptics are only supported on macOS.".into())
 }
+
+/// What the current OS supports capturing, so unavailable options can be hidden.
+#[derive(Debug, Serialize, Type, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct PlatformCapabilities {
+    /// Audio playing on the system can be recorded alongside the screen.
+    pub system_audio: bool,
+    /// Individual windows can be captured, rather than only whole displays or areas.
+    pub window_capture: bool,
+    /// The cursor can be included in or excluded from the captured frames.
+    pub cursor_capture: bool,
+}
+
+/// Whether system audio can be recorded, which on Windows needs an output
+/// device to capture the loopback of.
+pub fn system_audio_supported() -> bool {
+    #[cfg(target_os = "macos")]
+    {
+        // ScreenCaptureKit records system audio from the same stream as the screen
+        true
+    }
+
+    #[cfg(windows)]
+    {
+        use cpal::traits::HostTrait;
+
+        cpal::default_host().default_output_device().is_some()
+    }
+
+    #[cfg(not(any(target_os = "macos", windows)))]
+    {
+        false
+    }
+}
+
+pub fn capabilities() -> PlatformCapabilities {
+    #[cfg(target_os = "macos")]
+    let (window_capture, cursor_capture) = (true, true);
+
+    #[cfg(windows)]
+    let (window_capture, cursor_capture) = (
+        scap_direct3d::is_supported().unwrap_or(false),
+        scap_direct3d::Settings::can_is_cursor_capture_enabled().unwrap_or(false),
+    );
+
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let (window_capture, cursor_capture) = (false, false);
+
+    PlatformCapabilities {
+        system_audio: system_audio_supported(),
+        window_capture,
+        cursor_capture,
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn get_platform_capabilities() -> PlatformCapabilities {
+    capabilities()
+}
@@ -5,7 +5,8 @@ use cap_project::{
     ZoomSegment, cursor::CursorEvents,
 };
 use cap_recording::{
-    CompletedStudioRecording, RecordingError, RecordingMode, StudioRecordingHandle,
+    CompletedStudioRecording, EncoderErrorPolicy, RecordingError, RecordingMode, RecordingStats,
+    StudioRecordingHandle,
     feeds::{camera, microphone},
     instant_recording::{CompletedInstantRecording, InstantRecordingHandle},
     sources::{CaptureDisplay, CaptureWindow, ScreenCaptureTarget, screen_capture},
@@ -83,6 +84,13 @@ impl InProgressRecording {
         }
     }
 
+    pub fn stats(&self) -> RecordingStats {
+        match self {
+            Self::Instant { handle, .. } => handle.stats(),
+            Self::Studio { handle, .. } => handle.stats(),
+        }
+    }
+
     pub fn recording_dir(&self) -> &PathBuf {
         match self {
             Self::Instant { recording_dir, .. } => recording_dir,
@@ -124,7 +132,8 @@ impl InProgressRecording {
 
     pub fn mode(&self) -> RecordingMode {
         match self {
-            Self::Instant { .. } => RecordingMode::Instant,
+            // also covers timelapses, which are recorded as instant recordings
+            Self::Instant { inputs, .. } => inputs.mode,
             Self::Studio { .. } => RecordingMode::Studio,
         }
     }
@@ -196,6 +205,17 @@ pub struct StartRecordingInputs {
     #[serde(default)]
     pub capture_system_audio: bool,
     pub mode: RecordingMode,
+    #[serde(default)]
+    pub encoder_error_policy: EncoderErrorPolicy,
+    /// Split studio recordings into segments of this many seconds
+    #[serde(default)]
+    pub segment_duration_secs: Option<u32>,
+    /// Write the microphone to a WAV file next to instant recordings
+    #[serde(default)]
+    pub mic_wav_sidecar: bool,
+    /// Encode audio and video on separate threads
+    #[serde(default)]
+    pub threaded_encoding: bool,
 }
 
 #[derive(tauri_specta::Event, specta::Type, Clone, Debug, serde::Serialize)]
@@ -205,6 +225,7 @@ pub enum RecordingEvent {
     Started,
     Stopped,
     Failed { error: String },
+    SegmentRollover { index: u32, error: String },
 }
 
 #[tauri::command]
@@ -248,7 +269,7 @@ pub async fn start_recording(
     }
 
     let video_upload_info = match inputs.mode {
-        RecordingMode::Instant => {
+        RecordingMode::Instant | RecordingMode::Timelapse { .. } => {
             match AuthStore::get(&app).ok().flatten() {
                 Some(_) => {
                     // Pre-create the video and get the shareable link
@@ -346,7 +367,12 @@ pub async fn start_recording(
     let (finish_upload_tx, finish_upload_rx) = flume::bounded(1);
     let progressive_upload = video_upload_info
         .as_ref()
-        .filter(|_| matches!(inputs.mode, RecordingMode::Instant))
+        .filter(|_| {
+            matches!(
+                inputs.mode,
+                RecordingMode::Instant | RecordingMode::Timelapse { .. }
+            )
+        })
         .map(|video_upload_info| {
             InstantMultipartUpload::spawn(
                 app.clone(),
@@ -364,6 +390,7 @@ pub async fn start_recording(
         spawn_actor({
             let state_mtx = Arc::clone(&state_mtx);
             let general_settings = general_settings.cloned();
+            let app = app.clone();
             async move {
                 fail!("recording::spawn_actor");
                 let mut state = state_mtx.write().await;
@@ -386,6 +413,12 @@ pub async fn start_recording(
                     capture_system_audio: inputs.capture_system_audio,
                     mic_feed,
                     camera_feed,
+                    encoder_error_policy: inputs.encoder_error_policy,
+                    segment_duration: inputs
+                        .segment_duration_secs
+                        .map(|secs| Duration::from_secs(secs.max(1) as u64)),
+                    mic_wav_sidecar: inputs.mic_wav_sidecar,
+                    threaded_encoding: inputs.threaded_encoding,
                 };
 
                 let (actor, actor_done_rx) = match inputs.mode {
@@ -404,6 +437,18 @@ pub async fn start_recording(
                             e.to_string()
                         })?;
 
+                        // ends once the recording actor stops
+                        let rollovers = handle.rollovers();
+                        tokio::spawn(async move {
+                            while let Ok(rollover) = rollovers.recv_async().await {
+                                let _ = RecordingEvent::SegmentRollover {
+                                    index: rollover.index,
+                                    error: rollover.error,
+                                }
+                                .emit(&app);
+                            }
+                        });
+
                         (
                             InProgressRecording::Studio {
                                 handle,
@@ -414,22 +459,35 @@ pub async fn start_recording(
                             actor_done_rx,
                         )
                     }
-                    RecordingMode::Instant => {
+                    RecordingMode::Instant | RecordingMode::Timelapse { .. } => {
                         let Some(video_upload_info) = video_upload_info.clone() else {
                             return Err("Video upload info not found".to_string());
                         };
 
-                        let (handle, actor_done_rx) =
-                            cap_recording::instant_recording::spawn_instant_recording_actor(
-                                id.clone(),
-                                recording_dir.clone(),
-                                base_inputs,
-                            )
-                            .await
-                            .map_err(|e| {
-                                error!("Failed to spawn studio recording actor: {e}");
-                                e.to_string()
-                            })?;
+                        let actor = match inputs.mode {
+                            RecordingMode::Timelapse { interval } => {
+                                cap_recording::instant_recording::spawn_timelapse_recording_actor(
+                                    id.clone(),
+                                    recording_dir.clone(),
+                                    base_inputs,
+                                    interval,
+                                )
+                                .await
+                            }
+                            _ => {
+                                cap_recording::instant_recording::spawn_instant_recording_actor(
+                                    id.clone(),
+                                    recording_dir.clone(),
+                                    base_inputs,
+                                )
+                                .await
+                            }
+                        };
+
+                        let (handle, actor_done_rx) = actor.map_err(|e| {
+                            error!("Failed to spawn studio recording actor: {e}");
+                            e.to_string()
+                        })?;
 
                         (
                             InProgressRecording::Instant {
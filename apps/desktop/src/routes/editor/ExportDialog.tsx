@@ -171,10 +171,11 @@ export function ExportDialog() {
 					y: settings.resolution.height,
 				},
 				fps: settings.fps,
+				compression: settings.compression,
 			},
 		] as const,
-		queryFn: ({ queryKey: [_, { resolution, fps }] }) =>
-			commands.getExportEstimates(projectPath, resolution, fps),
+		queryFn: ({ queryKey: [_, { resolution, fps, compression }] }) =>
+			commands.getExportEstimates(projectPath, resolution, fps, compression),
 	}));
 
 	const exportButtonIcon: Record<"file" | "clipboard" | "link", JSX.Element> = {
@@ -1,13 +1,25 @@
 import { Channel } from "@tauri-apps/api/core";
-import { commands, type ExportSettings, type FramesRendered } from "./tauri";
+import {
+	commands,
+	type ExportProgress,
+	type ExportSettings,
+	type FramesRendered,
+} from "./tauri";
 
 export async function exportVideo(
 	projectPath: string,
 	settings: ExportSettings,
 	onProgress: (progress: FramesRendered) => void,
+	onPhase?: (progress: ExportProgress) => void,
 ) {
-	const progress = new Channel<FramesRendered>((e) => {
-		onProgress(e);
+	const progress = new Channel<ExportProgress>((e) => {
+		onPhase?.(e);
+		if (e.type === "Rendering")
+			onProgress({
+				type: "FramesRendered",
+				renderedCount: e.done,
+				totalFrames: e.total,
+			});
 	});
 	return await commands.exportVideo(projectPath, progress, settings);
 }
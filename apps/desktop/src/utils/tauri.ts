@@ -59,11 +59,14 @@ async focusCapturesPanel() : Promise<void> {
 async getCurrentRecording() : Promise<JsonValue<CurrentRecording | null>> {
     return await TAURI_INVOKE("get_current_recording");
 },
-async exportVideo(projectPath: string, progress: TAURI_CHANNEL<FramesRendered>, settings: ExportSettings) : Promise<string> {
+async exportVideo(projectPath: string, progress: TAURI_CHANNEL<ExportProgress>, settings: ExportSettings) : Promise<string> {
     return await TAURI_INVOKE("export_video", { projectPath, progress, settings });
 },
-async getExportEstimates(path: string, resolution: XY<number>, fps: number) : Promise<ExportEstimates> {
-    return await TAURI_INVOKE("get_export_estimates", { path, resolution, fps });
+async cancelExport(projectPath: string) : Promise<void> {
+    await TAURI_INVOKE("cancel_export", { projectPath });
+},
+async getExportEstimates(path: string, resolution: XY<number>, fps: number, compression: ExportCompression) : Promise<ExportEstimates> {
+    return await TAURI_INVOKE("get_export_estimates", { path, resolution, fps, compression });
 },
 async copyFileToPath(src: string, dst: string) : Promise<null> {
     return await TAURI_INVOKE("copy_file_to_path", { src, dst });
@@ -366,6 +369,7 @@ export type DownloadProgress = { progress: number; message: string }
 export type EditorStateChanged = { playhead_position: number }
 export type ExportCompression = "Minimal" | "Social" | "Web" | "Potato"
 export type ExportEstimates = { duration_seconds: number; estimated_time_seconds: number; estimated_size_mb: number }
+export type ExportProgress = { type: "Rendering"; done: number; total: number; eta: number | null } | { type: "Encoding"; done: number; total: number } | { type: "Finalizing" }
 export type ExportSettings = ({ format: "Mp4" } & Mp4ExportSettings) | ({ format: "Gif" } & GifExportSettings)
 export type FileType = "recording" | "screenshot"
 export type Flags = { captions: boolean }
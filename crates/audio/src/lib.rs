@@ -1,8 +1,10 @@
 mod audio_data;
 mod renderer;
+mod stretch;
 
 pub use audio_data::*;
 pub use renderer::*;
+pub use stretch::*;
 
 pub trait FromSampleBytes: cpal::SizedSample + std::fmt::Debug + Send + 'static {
     const BYTE_SIZE: usize;
@@ -6,9 +6,13 @@ pub enum StereoMode {
     MonoR,
 }
 
-// Renders a combination of audio tracks into a single stereo buffer
+// Renders a combination of audio tracks into a single stereo buffer.
+//
+// Each track carries a sample offset relative to the shared timeline, so that tracks that
+// started recording at different times line up. A positive offset skips the track's leading
+// samples, a negative one delays it, rendering silence until the track begins.
 pub fn render_audio(
-    tracks: &[(&AudioData, f32, StereoMode)],
+    tracks: &[(&AudioData, f32, StereoMode, isize)],
     offset: usize,
     samples: usize,
     out_offset: usize,
@@ -17,7 +21,12 @@ pub fn render_audio(
     let samples = samples.min(
         tracks
             .iter()
-            .flat_map(|t| (t.0.samples().len() / t.0.channels() as usize).checked_sub(offset))
+            .flat_map(|t| {
+                (t.0.sample_count() as isize - t.3)
+                    .try_into()
+                    .ok()
+                    .and_then(|len: usize| len.checked_sub(offset))
+            })
             .max()
             .unwrap_or(0),
     );
@@ -33,13 +42,17 @@ pub fn render_audio(
                 continue;
             }
 
+            let Ok(index) = usize::try_from((offset + i) as isize + track.3) else {
+                continue;
+            };
+
             if track.0.channels() == 1 {
-                if let Some(sample) = track.0.samples().get(offset + i) {
+                if let Some(sample) = track.0.samples().get(index) {
                     left += sample * 0.707 * gain;
                     right += sample * 0.707 * gain;
                 }
             } else if track.0.channels() == 2 {
-                let base_idx = offset * 2 + i * 2;
+                let base_idx = index * 2;
                 let Some(l_sample) = track.0.samples().get(base_idx) else {
                     continue;
                 };
@@ -81,3 +94,55 @@ fn gain_for_db(db: f32) -> f32 {
 fn db_to_linear(db: f32) -> f32 {
     10.0_f32.powf(db / 20.0)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mono(samples: Vec<f32>) -> AudioData {
+        AudioData {
+            samples,
+            channels: 1,
+        }
+    }
+
+    #[test]
+    fn tracks_align_by_start_offset() {
+        // track `a` started recording 2 samples before the timeline begins,
+        // track `b` started 1 sample after it
+        let a = mono(vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+        let b = mono(vec![10.0, 20.0, 30.0]);
+
+        let tracks = [
+            (&a, 0.0, StereoMode::Stereo, 2),
+            (&b, 0.0, StereoMode::Stereo, -1),
+        ];
+
+        let mut out = vec![0.0; 8];
+        let rendered = render_audio(&tracks, 0, 4, 0, &mut out);
+        assert_eq!(rendered, 4);
+
+        let left = out.iter().step_by(2).map(|s| s / 0.707).collect::<Vec<_>>();
+        let expected = [3.0, 4.0 + 10.0, 5.0 + 20.0, 30.0];
+        for (actual, expected) in left.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-4, "{left:?}");
+        }
+    }
+
+    #[test]
+    fn delayed_track_extends_render_length() {
+        let a = mono(vec![1.0; 2]);
+        let b = mono(vec![1.0; 2]);
+
+        let tracks = [
+            (&a, 0.0, StereoMode::Stereo, 0),
+            (&b, 0.0, StereoMode::Stereo, -3),
+        ];
+
+        let mut out = vec![0.0; 20];
+        assert_eq!(render_audio(&tracks, 0, 10, 0, &mut out), 5);
+        // silence between the end of `a` and the start of `b`
+        assert_eq!(out[4], 0.0);
+        assert!(out[6] > 0.0);
+    }
+}
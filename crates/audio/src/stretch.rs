@@ -0,0 +1,173 @@
+use std::f32::consts::PI;
+
+/// How the audio of a sped up or slowed down part of the timeline is fitted to its new length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeStretch {
+    /// Plays the audio faster or slower like a tape, which also raises or lowers its pitch
+    #[default]
+    Resample,
+    /// Keeps the pitch by cross-fading overlapping windows of the audio taken at the new rate
+    PreservePitch,
+}
+
+// Length of the windows used when preserving pitch, around 40ms at 48kHz.
+// Shorter windows smear transients less but make low voices sound rough.
+const WINDOW: usize = 2048;
+const HOP: usize = WINDOW / 2;
+
+/// Renders `out.len() / 2` stereo samples of audio played back at `speed`.
+///
+/// `position` is how many output samples into the sped up part of the timeline the render starts,
+/// and `source_start` is the source sample that part starts at. Output only depends on these,
+/// so renders can start anywhere without any state carried over from previous ones.
+///
+/// `render_source` fills a stereo buffer with source samples from an offset, with silence where
+/// there aren't any, like [`render_audio`](crate::render_audio).
+pub fn render_stretched(
+    mut render_source: impl FnMut(isize, &mut [f32]),
+    source_start: isize,
+    position: usize,
+    speed: f64,
+    stretch: TimeStretch,
+    out: &mut [f32],
+) {
+    let samples = out.len() / 2;
+    if samples == 0 {
+        return;
+    }
+
+    match stretch {
+        TimeStretch::Resample => {
+            let source_at = |i: usize| source_start as f64 + (position + i) as f64 * speed;
+            let first = source_at(0).floor() as isize;
+            let last = source_at(samples - 1).floor() as isize;
+
+            // one more sample than is covered, to interpolate towards
+            let mut source = vec![0.0; (last - first + 2) as usize * 2];
+            render_source(first, &mut source);
+
+            for (i, frame) in out.chunks_exact_mut(2).enumerate() {
+                let at = source_at(i);
+                let index = (at.floor() as isize - first) as usize;
+                let t = (at - at.floor()) as f32;
+
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    let a = source[index * 2 + channel];
+                    let b = source[(index + 1) * 2 + channel];
+                    *sample = a + (b - a) * t;
+                }
+            }
+        }
+        TimeStretch::PreservePitch => {
+            out.fill(0.0);
+
+            let start = position as isize;
+            let end = start + samples as isize;
+            let mut window = vec![0.0; WINDOW * 2];
+
+            // windows start every `HOP` output samples, and are read from `speed` times
+            // that far into the source. Every output sample is covered by two windows,
+            // whose Hann curves add up to 1.
+            let first = (start - WINDOW as isize).div_euclid(HOP as isize) + 1;
+            let last = (end - 1).div_euclid(HOP as isize);
+
+            for index in first..=last {
+                let window_start = index * HOP as isize;
+                render_source(
+                    source_start + (window_start as f64 * speed).round() as isize,
+                    &mut window,
+                );
+
+                for m in window_start.max(start)..(window_start + WINDOW as isize).min(end) {
+                    let i = (m - window_start) as usize;
+                    let o = (m - start) as usize;
+                    let weight = 0.5 - 0.5 * (2.0 * PI * i as f32 / WINDOW as f32).cos();
+
+                    out[o * 2] += window[i * 2] * weight;
+                    out[o * 2 + 1] += window[i * 2 + 1] * weight;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48_000.0;
+
+    /// Stereo source where both channels are `f` of the sample index
+    fn source(f: impl Fn(isize) -> f32) -> impl FnMut(isize, &mut [f32]) {
+        move |offset, out| {
+            for (i, frame) in out.chunks_exact_mut(2).enumerate() {
+                frame.fill(f(offset + i as isize));
+            }
+        }
+    }
+
+    fn render(
+        f: impl Fn(isize) -> f32,
+        position: usize,
+        samples: usize,
+        speed: f64,
+        stretch: TimeStretch,
+    ) -> Vec<f32> {
+        let mut out = vec![0.0; samples * 2];
+        render_stretched(source(f), 100, position, speed, stretch, &mut out);
+        out.into_iter().step_by(2).collect()
+    }
+
+    fn zero_crossings(samples: &[f32]) -> usize {
+        samples
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count()
+    }
+
+    #[test]
+    fn resamples_at_speed() {
+        let out = render(|i| i as f32, 10, 4, 2.5, TimeStretch::Resample);
+        assert_eq!(out, [125.0, 127.5, 130.0, 132.5]);
+    }
+
+    #[test]
+    fn preserves_audio_at_normal_speed() {
+        let f = |i: isize| (i as f32 * 0.01).sin();
+        let out = render(f, 5000, 3000, 1.0, TimeStretch::PreservePitch);
+
+        for (i, sample) in out.iter().enumerate() {
+            let expected = f(100 + 5000 + i as isize);
+            assert!((sample - expected).abs() < 1e-4, "{sample} != {expected}");
+        }
+    }
+
+    #[test]
+    fn renders_the_same_in_any_chunks() {
+        let f = |i: isize| (i as f32 * 0.05).sin();
+        let whole = render(f, 0, 4000, 3.0, TimeStretch::PreservePitch);
+        let chunks = [0..1000, 1000..1600, 1600..4000]
+            .into_iter()
+            .flat_map(|r| render(f, r.start, r.len(), 3.0, TimeStretch::PreservePitch))
+            .collect::<Vec<_>>();
+
+        assert_eq!(whole, chunks);
+    }
+
+    #[test]
+    fn keeps_pitch_when_sped_up() {
+        // 480Hz for a fifth of a second of output
+        let f = |i: isize| (2.0 * PI * 480.0 * i as f32 / SAMPLE_RATE).sin();
+        let samples = SAMPLE_RATE as usize / 5;
+        let expected = 2 * 480 / 5;
+
+        let preserved = zero_crossings(&render(f, 0, samples, 2.0, TimeStretch::PreservePitch));
+        let resampled = zero_crossings(&render(f, 0, samples, 2.0, TimeStretch::Resample));
+
+        assert!(preserved.abs_diff(expected) <= expected / 10, "{preserved}");
+        assert!(
+            resampled.abs_diff(expected * 2) <= expected / 10,
+            "{resampled}"
+        );
+    }
+}
@@ -119,3 +119,17 @@ impl CapturedFrameExt for CapturedFrame {
         Ok(res)
     }
 }
+
+/// Converts the frames of one capture session. Frames don't share any state on macOS,
+/// so this only converts them one by one.
+#[derive(Default)]
+pub struct FrameConverter;
+
+impl FrameConverter {
+    pub fn convert(
+        &mut self,
+        frame: &CapturedFrame,
+    ) -> Result<ffmpeg::frame::Video, ToFfmpegError> {
+        frame.to_ffmpeg()
+    }
+}
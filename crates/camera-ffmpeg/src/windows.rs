@@ -8,11 +8,26 @@ use crate::CapturedFrameExt;
 pub enum ToFfmpegError {
     #[error("FailedToGetBytes: {0}")]
     FailedToGetBytes(windows_core::Error),
+    #[error("FailedToDecodeMJPEG: {0}")]
+    FailedToDecode(ffmpeg::Error),
 }
 
 impl CapturedFrameExt for CapturedFrame {
     fn to_ffmpeg(&self) -> Result<ffmpeg::frame::Video, ToFfmpegError> {
-        let native = self.native();
+        FrameConverter::default().convert(self)
+    }
+}
+
+/// Converts the frames of one capture session, keeping the MJPEG decoder open between
+/// frames instead of opening one for each.
+#[derive(Default)]
+pub struct FrameConverter {
+    mjpeg: Option<ffmpeg::decoder::Video>,
+}
+
+impl FrameConverter {
+    pub fn convert(&mut self, frame: &CapturedFrame) -> Result<FFVideo, ToFfmpegError> {
+        let native = frame.native();
         let width = native.width;
         let height = native.height;
 
@@ -153,6 +168,33 @@ impl CapturedFrameExt for CapturedFrame {
 
                 ff_frame
             }
+            PixelFormat::MJPEG => self
+                .decode_mjpeg(&bytes)
+                .map_err(ToFfmpegError::FailedToDecode)?,
         })
     }
+
+    /// MJPEG frames are independent JPEG images, so the decoder gives back each frame
+    /// as soon as it's sent.
+    fn decode_mjpeg(&mut self, bytes: &[u8]) -> Result<FFVideo, ffmpeg::Error> {
+        let decoder = match &mut self.mjpeg {
+            Some(decoder) => decoder,
+            mjpeg => {
+                let codec = ffmpeg::decoder::find(ffmpeg::codec::Id::MJPEG)
+                    .ok_or(ffmpeg::Error::DecoderNotFound)?;
+                mjpeg.insert(
+                    ffmpeg::codec::Context::new_with_codec(codec)
+                        .decoder()
+                        .video()?,
+                )
+            }
+        };
+
+        decoder.send_packet(&ffmpeg::Packet::copy(bytes))?;
+
+        let mut ff_frame = FFVideo::empty();
+        decoder.receive_frame(&mut ff_frame)?;
+
+        Ok(ff_frame)
+    }
 }
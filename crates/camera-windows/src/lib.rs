@@ -237,6 +237,8 @@ pub enum PixelFormat {
     YUYV422,
     /// Packed
     UYVY422,
+    /// Compressed, each frame is a JPEG image
+    MJPEG,
 }
 
 #[derive(Clone)]
@@ -464,6 +466,7 @@ impl MFPixelFormat {
                 t if t == MFVideoFormat_UYVY => PixelFormat::UYVY422,
                 t if t == MFVideoFormat_ARGB32 => PixelFormat::ARGB,
                 t if t == MFVideoFormat_NV12 => PixelFormat::NV12,
+                t if t == MFVideoFormat_MJPG => PixelFormat::MJPEG,
                 _ => return None,
             })
         };
@@ -510,6 +513,7 @@ impl DSPixelFormat {
                 t if t == MEDIASUBTYPE_UYVY => PixelFormat::UYVY422,
                 t if t == MEDIASUBTYPE_ARGB32 => PixelFormat::ARGB,
                 t if t == MEDIASUBTYPE_NV12 => PixelFormat::NV12,
+                t if t == MEDIASUBTYPE_MJPG => PixelFormat::MJPEG,
                 _ => return None,
             })
         };
@@ -1,4 +1,7 @@
-use cap_audio::{AudioData, FromSampleBytes, StereoMode, cast_f32_slice_to_bytes};
+use cap_audio::{
+    AudioData, FromSampleBytes, StereoMode, TimeStretch, cast_f32_slice_to_bytes, render_audio,
+    render_stretched,
+};
 use cap_media::MediaError;
 use cap_media_info::AudioInfo;
 use cap_project::{AudioConfiguration, ProjectConfiguration, TimelineConfiguration};
@@ -15,6 +18,7 @@ pub struct AudioRenderer {
     // sum of `frame.samples()` that have elapsed
     // this * channel count = cursor
     elapsed_samples: usize,
+    time_stretch: TimeStretch,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -34,6 +38,8 @@ pub struct AudioSegmentTrack {
     data: Arc<AudioData>,
     get_gain: fn(&AudioConfiguration) -> f32,
     get_stereo_mode: fn(&AudioConfiguration) -> StereoMode,
+    // samples into `data` that line up with the start of the segment
+    offset: isize,
 }
 
 impl AudioSegmentTrack {
@@ -46,9 +52,17 @@ impl AudioSegmentTrack {
             data,
             get_gain,
             get_stereo_mode,
+            offset: 0,
         }
     }
 
+    /// Aligns the track to the segment timeline, given the track's start offset in seconds.
+    /// See [`cap_project::MultipleSegment::start_offset`].
+    pub fn with_start_offset(mut self, offset: f64) -> Self {
+        self.offset = (offset * AudioData::SAMPLE_RATE as f64).round() as isize;
+        self
+    }
+
     pub fn data(&self) -> &Arc<AudioData> {
         &self.data
     }
@@ -60,6 +74,15 @@ impl AudioSegmentTrack {
     pub fn stereo_mode(&self, config: &AudioConfiguration) -> StereoMode {
         (self.get_stereo_mode)(config)
     }
+
+    pub fn offset(&self) -> isize {
+        self.offset
+    }
+
+    // number of samples the track spans on the segment timeline
+    pub fn sample_count(&self) -> usize {
+        (self.data.sample_count() as isize - self.offset).max(0) as usize
+    }
 }
 
 impl AudioRenderer {
@@ -79,9 +102,16 @@ impl AudioRenderer {
                 samples: 0,
             },
             elapsed_samples: 0,
+            time_stretch: TimeStretch::default(),
         }
     }
 
+    /// How audio is fitted to parts of the timeline that play faster or slower than recorded.
+    pub fn with_time_stretch(mut self, time_stretch: TimeStretch) -> Self {
+        self.time_stretch = time_stretch;
+        self
+    }
+
     pub fn set_playhead(&mut self, playhead: f64, project: &ProjectConfiguration) {
         self.elapsed_samples = self.playhead_to_samples(playhead);
 
@@ -164,11 +194,7 @@ impl AudioRenderer {
             return None;
         }
 
-        let max_samples = tracks
-            .iter()
-            .map(|t| t.data().sample_count())
-            .max()
-            .unwrap();
+        let max_samples = tracks.iter().map(|t| t.sample_count()).max().unwrap();
 
         if self.cursor.samples >= max_samples {
             self.elapsed_samples += samples;
@@ -193,12 +219,51 @@ impl AudioRenderer {
                         if g < -30.0 { f32::NEG_INFINITY } else { g }
                     },
                     t.stereo_mode(&project.audio),
+                    t.offset(),
                 )
             })
             .collect::<Vec<_>>();
 
-        let actual_sample_count =
-            cap_audio::render_audio(&track_datas, start.samples, samples, 0, &mut ret);
+        // the timeline segment being played, if it's sped up or slowed down
+        let ramp = project.timeline.as_ref().and_then(|timeline| {
+            let playhead = self.elapsed_samples_to_playhead();
+            timeline
+                .get_segment(playhead)
+                .filter(|(_, segment)| segment.timescale != 1.0)
+                .map(|(segment_start, segment)| (playhead - segment_start, segment))
+        });
+
+        if let Some((segment_playhead, segment)) = ramp {
+            let source_start = self.playhead_to_samples(segment.start) as isize;
+            let position = self.playhead_to_samples(segment_playhead);
+
+            render_stretched(
+                |offset, out| {
+                    out.fill(0.0);
+                    let skip = offset.min(0).unsigned_abs().min(out.len() / channels);
+                    render_audio(
+                        &track_datas,
+                        offset.max(0) as usize,
+                        out.len() / channels - skip,
+                        skip * channels,
+                        out,
+                    );
+                },
+                source_start,
+                position,
+                segment.timescale,
+                self.time_stretch,
+                &mut ret,
+            );
+
+            self.elapsed_samples += samples;
+            self.cursor.samples =
+                (source_start as f64 + (position + samples) as f64 * segment.timescale) as usize;
+
+            return Some((samples, ret));
+        }
+
+        let actual_sample_count = render_audio(&track_datas, start.samples, samples, 0, &mut ret);
 
         self.elapsed_samples += actual_sample_count;
         self.cursor.samples += actual_sample_count;
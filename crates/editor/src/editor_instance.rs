@@ -10,7 +10,7 @@ use cap_rendering::{
 use std::ops::Deref;
 use std::{path::PathBuf, sync::Arc};
 use tokio::sync::{Mutex, watch};
-use tracing::trace;
+use tracing::{error, trace};
 
 pub struct EditorInstance {
     pub project_path: PathBuf,
@@ -214,8 +214,12 @@ impl EditorInstance {
 
                 if let Some(segment_frames) = segment
                     .decoders
-                    .get_frames(segment_time as f32, !project.camera.hide)
+                    .get_frames(segment_time, !project.camera.hide)
                     .await
+                    .unwrap_or_else(|e| {
+                        error!("Failed to decode frames: {e}");
+                        None
+                    })
                 {
                     let uniforms = ProjectUniforms::new(
                         &self.render_constants,
@@ -275,7 +279,10 @@ pub struct EditorState {
 
 pub struct Segment {
     pub audio: Option<Arc<AudioData>>,
+    /// seconds into `audio` that line up with the start of the segment
+    pub audio_offset: f64,
     pub system_audio: Option<Arc<AudioData>>,
+    pub system_audio_offset: f64,
     pub cursor: Arc<CursorEvents>,
     pub decoders: RecordingSegmentDecoders,
 }
@@ -310,7 +317,9 @@ pub async fn create_segments(
 
             Ok(vec![Segment {
                 audio,
+                audio_offset: 0.0,
                 system_audio: None,
+                system_audio_offset: 0.0,
                 cursor: Default::default(),
                 decoders,
             }])
@@ -355,7 +364,10 @@ pub async fn create_segments(
 
                 segments.push(Segment {
                     audio,
+                    audio_offset: s.start_offset(s.mic.as_ref().and_then(|m| m.start_time)),
                     system_audio,
+                    system_audio_offset: s
+                        .start_offset(s.system_audio.as_ref().and_then(|a| a.start_time)),
                     cursor,
                     decoders,
                 });
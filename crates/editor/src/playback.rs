@@ -86,10 +86,13 @@ impl Playback {
 
                     let data = tokio::select! {
                         _ = stop_rx.changed() => { break; },
-                        data = segment.decoders.get_frames(segment_time as f32, !project.camera.hide) => { data }
+                        data = segment.decoders.get_frames(segment_time, !project.camera.hide) => { data }
                     };
 
-                    if let Some(segment_frames) = data {
+                    if let Some(segment_frames) = data.unwrap_or_else(|e| {
+                        error!("Failed to decode frames: {e}");
+                        None
+                    }) {
                         let uniforms = ProjectUniforms::new(
                             &self.render_constants,
                             &project,
@@ -18,6 +18,7 @@ pub fn get_audio_segments(segments: &[Segment]) -> Vec<AudioSegment> {
                             cap_project::StereoMode::MonoR => cap_audio::StereoMode::MonoR,
                         },
                     )
+                    .with_start_offset(s.audio_offset)
                 }),
                 s.system_audio.clone().map(|a| -> AudioSegmentTrack {
                     AudioSegmentTrack::new(
@@ -25,6 +26,7 @@ pub fn get_audio_segments(segments: &[Segment]) -> Vec<AudioSegment> {
                         |c| c.system_volume_db,
                         |_| cap_audio::StereoMode::Stereo,
                     )
+                    .with_start_offset(s.system_audio_offset)
                 }),
             ]
             .into_iter()
@@ -198,13 +198,21 @@ impl MP4Encoder {
     pub fn queue_video_frame(
         &mut self,
         frame: &cidre::cm::SampleBuf,
+    ) -> Result<(), QueueVideoFrameError> {
+        self.queue_video_frame_at(frame, frame.pts())
+    }
+
+    /// Queues a frame as if it had been captured at `time` rather than its own timestamp,
+    /// so that frames can be retimed. `time` must increase between frames.
+    pub fn queue_video_frame_at(
+        &mut self,
+        frame: &cidre::cm::SampleBuf,
+        time: cm::Time,
     ) -> Result<(), QueueVideoFrameError> {
         if self.is_paused || !self.video_input.is_ready_for_more_media_data() {
             return Ok(());
         }
 
-        let time = frame.pts();
-
         if !self.is_writing {
             self.is_writing = true;
             self.asset_writer.start_session_at_src_time(time);
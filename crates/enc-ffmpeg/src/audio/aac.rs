@@ -1,13 +1,14 @@
 use cap_media_info::{AudioInfo, FFRational};
 use ffmpeg::{
+    Rescale,
     codec::{context, encoder},
     format::{self, Sample, sample::Type},
     frame,
     threading::Config,
 };
-use std::collections::VecDeque;
+use std::{collections::VecDeque, time::Duration};
 
-use crate::AudioEncoder;
+use crate::{AudioEncoder, SampleAdjustment, SampleTimeline};
 
 #[derive(thiserror::Error, Debug)]
 pub enum AACEncoderError {
@@ -28,11 +29,17 @@ pub struct AACEncoder {
     resampled_frame: frame::Audio,
     buffer: Vec<VecDeque<u8>>,
     stream_index: usize,
+    timeline: SampleTimeline,
+    samples_sent: i64,
 }
 
 impl AACEncoder {
-    const OUTPUT_BITRATE: usize = 320 * 1000; // 128k
-    const SAMPLE_FORMAT: Sample = Sample::F32(Type::Planar);
+    pub const OUTPUT_BITRATE: usize = 320 * 1000; // 128k
+    /// Input in this format is encoded without being converted first.
+    pub const SAMPLE_FORMAT: Sample = Sample::F32(Type::Planar);
+    /// How far the input's timestamps can drift from the samples before they're realigned.
+    /// Kept below the duration of a video frame so audio never visibly drifts from video.
+    const DRIFT_TOLERANCE: Duration = Duration::from_millis(20);
 
     pub fn factory(
         tag: &'static str,
@@ -45,6 +52,17 @@ impl AACEncoder {
         tag: &'static str,
         input_config: AudioInfo,
         output: &mut format::context::Output,
+    ) -> Result<Self, AACEncoderError> {
+        Self::init_with_bit_rate(tag, input_config, Self::OUTPUT_BITRATE, output)
+    }
+
+    /// Same as [`AACEncoder::init`], encoding at `bit_rate` bits per second instead of
+    /// [`AACEncoder::OUTPUT_BITRATE`].
+    pub fn init_with_bit_rate(
+        tag: &'static str,
+        input_config: AudioInfo,
+        bit_rate: usize,
+        output: &mut format::context::Output,
     ) -> Result<Self, AACEncoderError> {
         let codec = encoder::find_by_name("aac").ok_or(AACEncoderError::CodecNotFound)?;
         let mut encoder_ctx = context::Context::new_with_codec(codec);
@@ -103,7 +121,7 @@ impl AACEncoder {
             None
         };
 
-        encoder.set_bit_rate(Self::OUTPUT_BITRATE);
+        encoder.set_bit_rate(bit_rate);
         encoder.set_rate(rate);
         encoder.set_format(output_config.sample_format);
         encoder.set_channel_layout(output_config.channel_layout());
@@ -124,10 +142,19 @@ impl AACEncoder {
             packet: ffmpeg::Packet::empty(),
             resampled_frame: frame::Audio::empty(),
             resampler,
+            timeline: SampleTimeline::new(rate as u32, Self::DRIFT_TOLERANCE),
+            samples_sent: 0,
         })
     }
 
+    /// Frames without a timestamp are encoded back to back. Frames with one, in samples at
+    /// the frame's rate, are kept at that timestamp by padding or trimming samples if the
+    /// input drifts from it.
     pub fn queue_frame(&mut self, frame: frame::Audio, output: &mut format::context::Output) {
+        let pts = frame
+            .pts()
+            .map(|pts| pts.rescale((1, frame.rate() as i32), (1, self.encoder.rate() as i32)));
+
         let frame = if let Some(resampler) = &mut self.resampler {
             resampler.run(&frame, &mut self.resampled_frame).unwrap();
             &self.resampled_frame
@@ -135,9 +162,24 @@ impl AACEncoder {
             &frame
         };
 
+        let sample_bytes = self.encoder.format().bytes();
+        let mut skip_bytes = 0;
+
+        if let Some(pts) = pts {
+            match self.timeline.adjust(pts, frame.samples()) {
+                SampleAdjustment::Keep => {}
+                SampleAdjustment::Pad(samples) => {
+                    for buffer in &mut self.buffer {
+                        buffer.extend(std::iter::repeat_n(0, samples * sample_bytes));
+                    }
+                }
+                SampleAdjustment::Skip(samples) => skip_bytes = samples * sample_bytes,
+            }
+        }
+
         for i in 0..frame.planes() {
-            self.buffer[i]
-                .extend(&frame.data(i)[0..frame_size_bytes(frame) / frame.channels() as usize]);
+            let data = &frame.data(i)[0..frame_size_bytes(frame) / frame.channels() as usize];
+            self.buffer[i].extend(&data[skip_bytes.min(data.len())..]);
         }
 
         let channel_size_bytes = self.encoder.frame_size() as usize * self.encoder.format().bytes();
@@ -162,12 +204,24 @@ impl AACEncoder {
                     .copy_from_slice(&bytes[0..channel_size_bytes]);
             }
 
-            self.encoder.send_frame(&frame).unwrap();
+            self.send_frame(&mut frame);
 
             self.process_packets(output);
         }
     }
 
+    fn send_frame(&mut self, frame: &mut frame::Audio) {
+        if let Some(start) = self.timeline.start() {
+            frame.set_pts(Some(
+                (start + self.samples_sent)
+                    .rescale((1, self.encoder.rate() as i32), self.encoder.time_base()),
+            ));
+        }
+        self.samples_sent += frame.samples() as i64;
+
+        self.encoder.send_frame(frame).unwrap();
+    }
+
     fn process_packets(&mut self, output: &mut format::context::Output) {
         while self.encoder.receive_packet(&mut self.packet).is_ok() {
             self.packet.set_stream(self.stream_index);
@@ -213,7 +267,7 @@ impl AACEncoder {
                         frame.data_mut(0)[0..frame_size_bytes].copy_from_slice(&bytes);
                     }
 
-                    self.encoder.send_frame(&frame).unwrap();
+                    self.send_frame(&mut frame);
 
                     self.process_packets(output);
                 }
@@ -239,7 +293,7 @@ impl AACEncoder {
                         .copy_from_slice(&bytes[0..channel_size_bytes]);
                 }
 
-                self.encoder.send_frame(&frame).unwrap();
+                self.send_frame(&mut frame);
 
                 self.process_packets(output);
             }
@@ -0,0 +1,275 @@
+use cap_media_info::AudioInfo;
+use ffmpeg::{
+    codec::{context, encoder},
+    format, frame,
+    software::resampling,
+};
+use std::collections::VecDeque;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AudioFileEncoderError {
+    #[error("{0:?}")]
+    FFmpeg(#[from] ffmpeg::Error),
+    #[error("Encoder {0} not found")]
+    CodecNotFound(&'static str),
+}
+
+/// Codecs for audio-only files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFileCodec {
+    /// 16-bit PCM in a WAV file
+    Wav,
+    /// Only available when FFmpeg is built with libmp3lame
+    Mp3,
+    /// AAC in an M4A file
+    Aac,
+    Flac,
+}
+
+impl AudioFileCodec {
+    pub fn encoder_name(&self) -> &'static str {
+        match self {
+            Self::Wav => "pcm_s16le",
+            Self::Mp3 => "libmp3lame",
+            Self::Aac => "aac",
+            Self::Flac => "flac",
+        }
+    }
+
+    /// Extension of the file the codec is usually stored in, which muxers are picked by.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Mp3 => "mp3",
+            Self::Aac => "m4a",
+            Self::Flac => "flac",
+        }
+    }
+
+    /// Whether the codec is encoded at a bitrate, rather than keeping every sample.
+    pub fn is_lossy(&self) -> bool {
+        matches!(self, Self::Mp3 | Self::Aac)
+    }
+}
+
+/// Encodes audio with any [`AudioFileCodec`], converting it to a sample format and rate
+/// the codec supports.
+///
+/// Frames are encoded back to back, ignoring their timestamps.
+pub struct AudioFileEncoder {
+    encoder: encoder::Audio,
+    resampler: resampling::Context,
+    // encoder-format samples waiting to fill a frame, one buffer per plane
+    buffer: Vec<VecDeque<u8>>,
+    // bytes of one sample in each plane
+    sample_stride: usize,
+    samples_sent: i64,
+    packet: ffmpeg::Packet,
+    stream_index: usize,
+}
+
+impl AudioFileEncoder {
+    /// `sample_rate` is rounded to the closest rate the codec supports, and is the input's
+    /// rate if unset. `bit_rate` is only used by lossy codecs.
+    pub fn init(
+        input_config: AudioInfo,
+        codec: AudioFileCodec,
+        sample_rate: Option<u32>,
+        bit_rate: usize,
+        output: &mut format::context::Output,
+    ) -> Result<Self, AudioFileEncoderError> {
+        let name = codec.encoder_name();
+        let ff_codec =
+            encoder::find_by_name(name).ok_or(AudioFileEncoderError::CodecNotFound(name))?;
+        let audio_codec = ff_codec.audio()?;
+
+        let rates = audio_codec
+            .rates()
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        let rate = closest_rate(sample_rate.unwrap_or(input_config.sample_rate), &rates);
+        let sample_format = audio_codec
+            .formats()
+            .and_then(|mut formats| formats.next())
+            .unwrap_or(input_config.sample_format);
+        let channel_layout = input_config.channel_layout();
+
+        let mut encoder = context::Context::new_with_codec(ff_codec)
+            .encoder()
+            .audio()?;
+        encoder.set_rate(rate as i32);
+        encoder.set_format(sample_format);
+        encoder.set_channel_layout(channel_layout);
+        encoder.set_time_base((1, rate as i32));
+        if codec.is_lossy() {
+            encoder.set_bit_rate(bit_rate);
+        }
+
+        let encoder = encoder.open()?;
+
+        let mut output_stream = output.add_stream(ff_codec)?;
+        let stream_index = output_stream.index();
+        output_stream.set_time_base((1, rate as i32));
+        output_stream.set_parameters(&encoder);
+
+        let resampler = ffmpeg::software::resampler(
+            (
+                input_config.sample_format,
+                channel_layout,
+                input_config.sample_rate,
+            ),
+            (sample_format, channel_layout, rate),
+        )?;
+
+        let planes = if sample_format.is_planar() {
+            input_config.channels
+        } else {
+            1
+        };
+
+        Ok(Self {
+            encoder,
+            resampler,
+            buffer: vec![VecDeque::new(); planes],
+            sample_stride: sample_format.bytes() * input_config.channels / planes,
+            samples_sent: 0,
+            packet: ffmpeg::Packet::empty(),
+            stream_index,
+        })
+    }
+
+    pub fn queue_frame(
+        &mut self,
+        frame: &frame::Audio,
+        output: &mut format::context::Output,
+    ) -> Result<(), ffmpeg::Error> {
+        let capacity = (frame.samples() as u64 * self.encoder.rate() as u64
+            / frame.rate().max(1) as u64) as usize;
+        let mut resampled = self.resampled_frame(capacity);
+        self.resampler.run(frame, &mut resampled)?;
+        self.buffer_samples(&resampled);
+
+        self.encode_buffered(false, output)
+    }
+
+    /// Encodes the samples the resampler and encoder are holding back. Nothing can be
+    /// queued afterwards.
+    pub fn finish(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+        while self.resampler.delay().is_some() {
+            let mut resampled = self.resampled_frame(4096);
+            self.resampler.flush(&mut resampled)?;
+            if resampled.samples() == 0 {
+                break;
+            }
+
+            self.buffer_samples(&resampled);
+        }
+
+        self.encode_buffered(true, output)?;
+
+        self.encoder.send_eof()?;
+        self.write_packets(output)
+    }
+
+    // allocated for every conversion, as the resampler only fills up to a frame's
+    // sample count and then sets it to how many it wrote
+    fn resampled_frame(&self, capacity: usize) -> frame::Audio {
+        let mut frame = frame::Audio::new(
+            self.encoder.format(),
+            capacity + 64,
+            self.encoder.channel_layout(),
+        );
+        frame.set_rate(self.encoder.rate());
+        frame
+    }
+
+    fn buffer_samples(&mut self, frame: &frame::Audio) {
+        let len = frame.samples() * self.sample_stride;
+        for (plane, buffer) in self.buffer.iter_mut().enumerate() {
+            buffer.extend(&frame.data(plane)[..len]);
+        }
+    }
+
+    /// Encodes full frames of buffered samples, and any left over if `flush` is set,
+    /// as codecs accept a shorter last frame.
+    fn encode_buffered(
+        &mut self,
+        flush: bool,
+        output: &mut format::context::Output,
+    ) -> Result<(), ffmpeg::Error> {
+        loop {
+            let buffered = self.buffer[0].len() / self.sample_stride;
+            let samples = match self.encoder.frame_size() as usize {
+                // the codec takes frames of any size
+                0 => buffered,
+                frame_size if buffered >= frame_size => frame_size,
+                _ if flush => buffered,
+                _ => break,
+            };
+
+            if samples == 0 {
+                break;
+            }
+
+            let mut frame = frame::Audio::new(
+                self.encoder.format(),
+                samples,
+                self.encoder.channel_layout(),
+            );
+            frame.set_rate(self.encoder.rate());
+            frame.set_pts(Some(self.samples_sent));
+
+            let len = samples * self.sample_stride;
+            for (plane, buffer) in self.buffer.iter_mut().enumerate() {
+                for (byte, sample) in frame.data_mut(plane)[..len]
+                    .iter_mut()
+                    .zip(buffer.drain(..len))
+                {
+                    *byte = sample;
+                }
+            }
+
+            self.samples_sent += samples as i64;
+            self.encoder.send_frame(&frame)?;
+            self.write_packets(output)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_packets(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+        while self.encoder.receive_packet(&mut self.packet).is_ok() {
+            self.packet.set_stream(self.stream_index);
+            self.packet.rescale_ts(
+                self.encoder.time_base(),
+                output.stream(self.stream_index).unwrap().time_base(),
+            );
+            self.packet.write_interleaved(output)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The rate in `rates` closest to `rate`, or `rate` itself if the codec takes any rate.
+fn closest_rate(rate: u32, rates: &[i32]) -> u32 {
+    rates
+        .iter()
+        .map(|&r| r as u32)
+        .min_by_key(|r| r.abs_diff(rate))
+        .unwrap_or(rate)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn picks_closest_supported_rate() {
+        assert_eq!(closest_rate(48_000, &[44_100, 48_000]), 48_000);
+        assert_eq!(closest_rate(46_000, &[32_000, 44_100, 48_000]), 44_100);
+        assert_eq!(closest_rate(96_000, &[44_100, 48_000]), 48_000);
+        assert_eq!(closest_rate(22_050, &[]), 22_050);
+    }
+}
@@ -6,3 +6,9 @@ pub use opus::*;
 
 mod aac;
 pub use aac::*;
+
+mod audio_file;
+pub use audio_file::*;
+
+mod timeline;
+pub use timeline::*;
@@ -31,8 +31,9 @@ pub enum OpusEncoderError {
 }
 
 impl OpusEncoder {
-    const OUTPUT_BITRATE: usize = 128 * 1000; // 128k
-    const SAMPLE_FORMAT: Sample = Sample::F32(Type::Packed);
+    pub const OUTPUT_BITRATE: usize = 128 * 1000; // 128k
+    /// Input in this format is encoded without being converted first.
+    pub const SAMPLE_FORMAT: Sample = Sample::F32(Type::Packed);
 
     pub fn factory(
         tag: &'static str,
@@ -45,6 +46,17 @@ impl OpusEncoder {
         tag: &'static str,
         input_config: AudioInfo,
         output: &mut format::context::Output,
+    ) -> Result<Self, OpusEncoderError> {
+        Self::init_with_bit_rate(tag, input_config, Self::OUTPUT_BITRATE, output)
+    }
+
+    /// Same as [`OpusEncoder::init`], encoding at `bit_rate` bits per second instead of
+    /// [`OpusEncoder::OUTPUT_BITRATE`].
+    pub fn init_with_bit_rate(
+        tag: &'static str,
+        input_config: AudioInfo,
+        bit_rate: usize,
+        output: &mut format::context::Output,
     ) -> Result<Self, OpusEncoderError> {
         let codec = encoder::find_by_name("libopus").ok_or(OpusEncoderError::CodecNotFound)?;
         let mut encoder_ctx = context::Context::new_with_codec(codec);
@@ -103,7 +115,7 @@ impl OpusEncoder {
             None
         };
 
-        encoder.set_bit_rate(Self::OUTPUT_BITRATE);
+        encoder.set_bit_rate(bit_rate);
         encoder.set_rate(rate);
         encoder.set_format(output_config.sample_format);
         encoder.set_channel_layout(output_config.channel_layout());
@@ -127,9 +139,13 @@ impl OpusEncoder {
         })
     }
 
-    pub fn queue_frame(&mut self, frame: frame::Audio, output: &mut format::context::Output) {
+    pub fn queue_frame(
+        &mut self,
+        frame: frame::Audio,
+        output: &mut format::context::Output,
+    ) -> Result<(), ffmpeg::Error> {
         if let Some(resampler) = &mut self.resampler {
-            resampler.run(&frame, &mut self.resampled_frame).unwrap();
+            resampler.run(&frame, &mut self.resampled_frame)?;
 
             self.buffer
                 .extend(&self.resampled_frame.data(0)[0..frame_size_bytes(&self.resampled_frame)]);
@@ -151,9 +167,9 @@ impl OpusEncoder {
 
                 frame.data_mut(0)[0..frame_size_bytes].copy_from_slice(&bytes);
 
-                self.encoder.send_frame(&frame).unwrap();
+                self.encoder.send_frame(&frame)?;
 
-                self.process_packets(output);
+                self.process_packets(output)?;
             }
         } else {
             self.buffer
@@ -176,32 +192,39 @@ impl OpusEncoder {
 
                 frame.data_mut(0)[0..frame_size_bytes].copy_from_slice(&bytes);
 
-                self.encoder.send_frame(&frame).unwrap();
+                self.encoder.send_frame(&frame)?;
 
-                self.process_packets(output);
+                self.process_packets(output)?;
             }
         }
+
+        Ok(())
     }
 
-    fn process_packets(&mut self, output: &mut format::context::Output) {
+    fn process_packets(
+        &mut self,
+        output: &mut format::context::Output,
+    ) -> Result<(), ffmpeg::Error> {
         while self.encoder.receive_packet(&mut self.packet).is_ok() {
             self.packet.set_stream(self.stream_index);
             self.packet.rescale_ts(
                 self.encoder.time_base(),
                 output.stream(self.stream_index).unwrap().time_base(),
             );
-            self.packet.write_interleaved(output).unwrap();
+            self.packet.write_interleaved(output)?;
         }
+
+        Ok(())
     }
 
-    pub fn finish(&mut self, output: &mut format::context::Output) {
+    pub fn finish(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
         let frame_size_bytes = self.encoder.frame_size() as usize
             * self.encoder.channels() as usize
             * self.encoder.format().bytes();
 
         if let Some(mut resampler) = self.resampler.take() {
             while resampler.delay().is_some() {
-                resampler.flush(&mut self.resampled_frame).unwrap();
+                resampler.flush(&mut self.resampled_frame)?;
                 if self.resampled_frame.samples() == 0 {
                     break;
                 }
@@ -223,9 +246,9 @@ impl OpusEncoder {
 
                     frame.data_mut(0)[0..frame_size_bytes].copy_from_slice(&bytes);
 
-                    self.encoder.send_frame(&frame).unwrap();
+                    self.encoder.send_frame(&frame)?;
 
-                    self.process_packets(output);
+                    self.process_packets(output)?;
                 }
             }
 
@@ -245,25 +268,29 @@ impl OpusEncoder {
 
                 frame.data_mut(0)[0..frame_size_bytes].copy_from_slice(&bytes);
 
-                self.encoder.send_frame(&frame).unwrap();
+                self.encoder.send_frame(&frame)?;
 
-                self.process_packets(output);
+                self.process_packets(output)?;
             }
         }
 
-        self.encoder.send_eof().unwrap();
+        self.encoder.send_eof()?;
 
-        self.process_packets(output);
+        self.process_packets(output)
     }
 }
 
 impl AudioEncoder for OpusEncoder {
     fn queue_frame(&mut self, frame: frame::Audio, output: &mut format::context::Output) {
-        self.queue_frame(frame, output);
+        if let Err(e) = self.queue_frame(frame, output) {
+            tracing::error!("Failed to encode audio frame: {e}");
+        }
     }
 
     fn finish(&mut self, output: &mut format::context::Output) {
-        self.finish(output);
+        if let Err(e) = self.finish(output) {
+            tracing::error!("Failed to finish audio encoding: {e}");
+        }
     }
 }
 
@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+/// How the samples of an incoming frame need to be adjusted to stay on the timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleAdjustment {
+    Keep,
+    /// Insert this many samples of silence before the frame.
+    Pad(usize),
+    /// Drop this many samples from the start of the frame.
+    Skip(usize),
+}
+
+/// Keeps an encoder's sample count in line with the timestamps of its input frames.
+///
+/// Encoders place samples back to back, so an audio device whose clock runs slightly fast
+/// or slow relative to the capture clock slowly drifts away from the video it was recorded
+/// with. Once the input's timestamps and the sample count disagree by more than
+/// `tolerance`, the difference is made up with silence or by dropping samples.
+#[derive(Debug, Clone)]
+pub struct SampleTimeline {
+    tolerance: i64,
+    start: Option<i64>,
+    next: i64,
+}
+
+impl SampleTimeline {
+    pub fn new(rate: u32, tolerance: Duration) -> Self {
+        Self {
+            tolerance: (tolerance.as_secs_f64() * rate as f64).round() as i64,
+            start: None,
+            next: 0,
+        }
+    }
+
+    /// Timestamp of the first frame, in samples.
+    pub fn start(&self) -> Option<i64> {
+        self.start
+    }
+
+    /// Timestamp the next sample will be encoded at, in samples.
+    pub fn next(&self) -> i64 {
+        self.next
+    }
+
+    /// `pts` is the timestamp of the frame's first sample, in samples.
+    pub fn adjust(&mut self, pts: i64, samples: usize) -> SampleAdjustment {
+        let samples = samples as i64;
+
+        if self.start.is_none() {
+            self.start = Some(pts);
+            self.next = pts + samples;
+            return SampleAdjustment::Keep;
+        }
+
+        let offset = pts - self.next;
+
+        if offset > self.tolerance {
+            self.next = pts + samples;
+            SampleAdjustment::Pad(offset as usize)
+        } else if -offset > self.tolerance {
+            let skip = (-offset).min(samples);
+            self.next += samples - skip;
+            SampleAdjustment::Skip(skip as usize)
+        } else {
+            self.next += samples;
+            SampleAdjustment::Keep
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+    const FPS: f64 = 30.0;
+
+    /// Simulates a device producing `rate_error` more samples per second than its nominal
+    /// rate, with capture timestamps taken from the capture clock, and returns the largest
+    /// gap seen between where audio ends up and where it was captured.
+    fn max_drift(rate_error: f64, duration: Duration) -> f64 {
+        const CHUNK: usize = 480;
+
+        let mut timeline = SampleTimeline::new(RATE, Duration::from_millis(20));
+        let device_rate = RATE as f64 * (1.0 + rate_error);
+        let total = (duration.as_secs_f64() * device_rate) as usize;
+
+        let mut max_drift: f64 = 0.0;
+        let mut captured = 0;
+        while captured < total {
+            // the capture clock's timestamp for the chunk, as a source would report it
+            let pts = (captured as f64 / device_rate * RATE as f64).round() as i64;
+
+            // where the first kept sample ends up, and when it was captured
+            let (encoded_at, captured_at) = match timeline.adjust(pts, CHUNK) {
+                SampleAdjustment::Skip(skip) => {
+                    (timeline.next() - (CHUNK - skip) as i64, pts + skip as i64)
+                }
+                _ => (timeline.next() - CHUNK as i64, pts),
+            };
+            let drift = (encoded_at - captured_at).abs() as f64 / RATE as f64;
+            max_drift = max_drift.max(drift);
+
+            captured += CHUNK;
+        }
+
+        max_drift
+    }
+
+    #[test]
+    fn fast_device_stays_within_a_frame_over_30_minutes() {
+        let drift = max_drift(200e-6, Duration::from_secs(30 * 60));
+        assert!(drift < 1.0 / FPS, "drifted {drift}s");
+    }
+
+    #[test]
+    fn slow_device_stays_within_a_frame_over_30_minutes() {
+        let drift = max_drift(-200e-6, Duration::from_secs(30 * 60));
+        assert!(drift < 1.0 / FPS, "drifted {drift}s");
+    }
+
+    #[test]
+    fn pads_gaps_and_skips_overlaps() {
+        let mut timeline = SampleTimeline::new(RATE, Duration::from_millis(20));
+
+        assert_eq!(timeline.adjust(1000, 480), SampleAdjustment::Keep);
+        assert_eq!(timeline.start(), Some(1000));
+        assert_eq!(
+            timeline.adjust(1480 + 4800, 480),
+            SampleAdjustment::Pad(4800)
+        );
+        assert_eq!(timeline.next(), 1480 + 4800 + 480);
+        assert_eq!(timeline.adjust(1480, 480), SampleAdjustment::Skip(480));
+        assert_eq!(timeline.next(), 1480 + 4800 + 480);
+    }
+}
@@ -0,0 +1,104 @@
+use cap_media_info::VideoInfo;
+use ffmpeg::{
+    Dictionary,
+    codec::{self, context, encoder},
+    format::{self, Pixel},
+    frame,
+};
+use std::path::PathBuf;
+use tracing::error;
+
+/// An animated PNG, encoded from RGBA frames so that transparency is preserved.
+pub struct ApngFile {
+    output: format::context::Output,
+    encoder: encoder::Video,
+    config: VideoInfo,
+    stream_index: usize,
+    packet: ffmpeg::Packet,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ApngError {
+    #[error("{0:?}")]
+    FFmpeg(#[from] ffmpeg::Error),
+    #[error("APNG codec not found")]
+    CodecNotFound,
+    #[error("Pixel format {0:?} not supported, frames must be RGBA")]
+    PixFmtNotSupported(Pixel),
+}
+
+impl ApngFile {
+    /// `loop_count` is the number of times the animation plays, with 0 looping forever.
+    pub fn init(
+        mut output: PathBuf,
+        config: VideoInfo,
+        loop_count: u32,
+    ) -> Result<Self, ApngError> {
+        if config.pixel_format != Pixel::RGBA {
+            return Err(ApngError::PixFmtNotSupported(config.pixel_format));
+        }
+
+        output.set_extension("png");
+        let mut output = format::output_as(&output, "apng")?;
+
+        let codec = encoder::find(codec::Id::APNG).ok_or(ApngError::CodecNotFound)?;
+        let mut encoder = context::Context::new_with_codec(codec).encoder().video()?;
+
+        encoder.set_width(config.width);
+        encoder.set_height(config.height);
+        encoder.set_format(Pixel::RGBA);
+        encoder.set_time_base(config.frame_rate.invert());
+        encoder.set_frame_rate(Some(config.frame_rate));
+
+        let encoder = encoder.open()?;
+
+        let mut output_stream = output.add_stream(codec)?;
+        let stream_index = output_stream.index();
+        output_stream.set_time_base(config.frame_rate.invert());
+        output_stream.set_rate(config.frame_rate);
+        output_stream.set_parameters(&encoder);
+
+        let mut options = Dictionary::new();
+        options.set("plays", &loop_count.to_string());
+        output.write_header_with(options)?;
+
+        Ok(Self {
+            output,
+            encoder,
+            config,
+            stream_index,
+            packet: ffmpeg::Packet::empty(),
+        })
+    }
+
+    pub fn queue_frame(&mut self, frame: frame::Video) -> Result<(), ApngError> {
+        self.encoder.send_frame(&frame)?;
+        self.process_packets()
+    }
+
+    fn process_packets(&mut self) -> Result<(), ApngError> {
+        while self.encoder.receive_packet(&mut self.packet).is_ok() {
+            self.packet.set_stream(self.stream_index);
+            self.packet.rescale_ts(
+                self.config.frame_rate.invert(),
+                self.output.stream(self.stream_index).unwrap().time_base(),
+            );
+            self.packet.write_interleaved(&mut self.output)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), ApngError> {
+        if let Err(e) = self.encoder.send_eof() {
+            error!("Failed to send EOF to APNG encoder: {e:?}");
+        }
+        self.process_packets()?;
+
+        self.output.write_trailer()?;
+
+        Ok(())
+    }
+}
+
+unsafe impl Send for ApngFile {}
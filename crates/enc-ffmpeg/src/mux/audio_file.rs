@@ -0,0 +1,38 @@
+use ffmpeg::{format, frame};
+use std::path::PathBuf;
+
+use crate::audio::{AudioFileEncoder, AudioFileEncoderError};
+
+/// A file with only an audio track, in the container `output`'s extension is for,
+/// e.g. from [`AudioFileCodec::extension`](crate::AudioFileCodec::extension).
+pub struct AudioFile {
+    encoder: AudioFileEncoder,
+    output: format::context::Output,
+}
+
+impl AudioFile {
+    pub fn init(
+        output: PathBuf,
+        encoder: impl FnOnce(
+            &mut format::context::Output,
+        ) -> Result<AudioFileEncoder, AudioFileEncoderError>,
+    ) -> Result<Self, AudioFileEncoderError> {
+        let mut output = format::output(&output)?;
+
+        let encoder = encoder(&mut output)?;
+
+        // make sure this happens after adding all encoders!
+        output.write_header()?;
+
+        Ok(Self { encoder, output })
+    }
+
+    pub fn queue_frame(&mut self, frame: &frame::Audio) -> Result<(), ffmpeg::Error> {
+        self.encoder.queue_frame(frame, &mut self.output)
+    }
+
+    pub fn finish(&mut self) -> Result<(), ffmpeg::Error> {
+        self.encoder.finish(&mut self.output)?;
+        self.output.write_trailer()
+    }
+}
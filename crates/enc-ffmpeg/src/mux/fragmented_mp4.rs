@@ -0,0 +1,180 @@
+use ffmpeg::{ffi, format};
+use std::{
+    ffi::{c_int, c_void},
+    io::Write,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+use tracing::error;
+
+const IO_BUFFER_SIZE: usize = 64 * 1024;
+
+type BoxedWriter = Box<dyn Write + Send>;
+
+/// An mp4 muxer that writes fragmented mp4 (fMP4/CMAF) to an arbitrary writer
+/// instead of a file, so the bytes can be consumed while encoding is still running.
+///
+/// A `moof`/`mdat` pair is emitted at every keyframe, and the output written up
+/// to any point is playable without the trailer. Finishing with `write_trailer`
+/// appends the fragment index so the complete stream is a valid, seekable file.
+pub struct FragmentedMP4Output {
+    output: format::context::Output,
+    writer: *mut BoxedWriter,
+}
+
+impl FragmentedMP4Output {
+    pub fn new(writer: impl Write + Send + 'static) -> Result<Self, ffmpeg::Error> {
+        let writer: *mut BoxedWriter = Box::into_raw(Box::new(Box::new(writer)));
+
+        unsafe {
+            let mut ctx = ptr::null_mut();
+            let ret = ffi::avformat_alloc_output_context2(
+                &mut ctx,
+                ptr::null_mut(),
+                c"mp4".as_ptr(),
+                ptr::null(),
+            );
+            if ret < 0 {
+                drop(Box::from_raw(writer));
+                return Err(ffmpeg::Error::from(ret));
+            }
+
+            // set on the muxer rather than passed to write_header, so the output can be
+            // used anywhere a regular mp4 output is
+            ffi::av_opt_set(
+                (*ctx).priv_data,
+                c"movflags".as_ptr(),
+                c"frag_keyframe+empty_moov+default_base_moof".as_ptr(),
+                0,
+            );
+
+            let buffer = ffi::av_malloc(IO_BUFFER_SIZE) as *mut u8;
+            let io = if buffer.is_null() {
+                ptr::null_mut()
+            } else {
+                ffi::avio_alloc_context(
+                    buffer,
+                    IO_BUFFER_SIZE as c_int,
+                    1,
+                    writer as *mut c_void,
+                    None,
+                    Some(write_packet),
+                    None,
+                )
+            };
+            if io.is_null() {
+                ffi::av_free(buffer as *mut c_void);
+                ffi::avformat_free_context(ctx);
+                drop(Box::from_raw(writer));
+                return Err(ffmpeg::Error::Other {
+                    errno: ffmpeg::error::ENOMEM,
+                });
+            }
+
+            (*ctx).pb = io;
+            (*ctx).flags |= ffi::AVFMT_FLAG_CUSTOM_IO as c_int;
+
+            Ok(Self {
+                output: format::context::Output::wrap(ctx),
+                writer,
+            })
+        }
+    }
+}
+
+unsafe extern "C" fn write_packet(opaque: *mut c_void, buf: *const u8, buf_size: c_int) -> c_int {
+    let writer = unsafe { &mut *(opaque as *mut BoxedWriter) };
+    let data = unsafe { std::slice::from_raw_parts(buf, buf_size.max(0) as usize) };
+
+    match writer.write_all(data) {
+        Ok(()) => buf_size,
+        Err(e) => {
+            error!("Failed to write fragmented mp4 output: {e}");
+            ffmpeg::Error::External.into()
+        }
+    }
+}
+
+impl Deref for FragmentedMP4Output {
+    type Target = format::context::Output;
+
+    fn deref(&self) -> &Self::Target {
+        &self.output
+    }
+}
+
+impl DerefMut for FragmentedMP4Output {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.output
+    }
+}
+
+impl Drop for FragmentedMP4Output {
+    fn drop(&mut self) {
+        unsafe {
+            let ctx = self.output.as_mut_ptr();
+            let mut io = (*ctx).pb;
+            // Output's own drop would try to close pb as if ffmpeg had opened it
+            (*ctx).pb = ptr::null_mut();
+
+            if !io.is_null() {
+                ffi::avio_flush(io);
+                ffi::av_freep(&mut (*io).buffer as *mut *mut u8 as *mut c_void);
+                ffi::avio_context_free(&mut io);
+            }
+
+            let mut writer = Box::from_raw(self.writer);
+            if let Err(e) = writer.flush() {
+                error!("Failed to flush fragmented mp4 output: {e}");
+            }
+        }
+    }
+}
+
+unsafe impl Send for FragmentedMP4Output {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_fragmented_header_to_writer() {
+        ffmpeg::init().unwrap();
+
+        let buffer = SharedBuffer::default();
+        let mut output = FragmentedMP4Output::new(buffer.clone()).unwrap();
+
+        let mut stream = output.add_stream(None::<ffmpeg::Codec>).unwrap();
+        stream.set_time_base((1, 30));
+        unsafe {
+            let params = stream.parameters().as_mut_ptr();
+            (*params).codec_type = ffi::AVMediaType::AVMEDIA_TYPE_VIDEO;
+            (*params).codec_id = ffi::AVCodecID::AV_CODEC_ID_MPEG4;
+            (*params).width = 64;
+            (*params).height = 64;
+        }
+
+        output.write_header().unwrap();
+        output.write_trailer().unwrap();
+        drop(output);
+
+        let bytes = buffer.0.lock().unwrap();
+        assert_eq!(&bytes[4..8], b"ftyp");
+        assert!(bytes.windows(4).any(|w| w == b"mvex"));
+    }
+}
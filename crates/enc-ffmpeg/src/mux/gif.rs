@@ -0,0 +1,199 @@
+use cap_media_info::VideoInfo;
+use ffmpeg::{
+    Dictionary,
+    codec::{self, context, encoder},
+    filter,
+    format::{self, Pixel},
+    frame,
+};
+use std::path::PathBuf;
+use tracing::error;
+
+/// Dithering applied when mapping frames onto the GIF's palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GifDither {
+    None,
+    /// Ordered dithering, which is stable between frames so it compresses well
+    Bayer,
+    /// Error diffusion, which gives the smoothest gradients
+    #[default]
+    FloydSteinberg,
+}
+
+impl GifDither {
+    fn as_filter_option(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Bayer => "bayer:bayer_scale=2",
+            Self::FloydSteinberg => "floyd_steinberg",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GifPalette {
+    /// Between 4 and 256. One of the colours is reserved for transparency.
+    pub max_colors: u16,
+    pub dither: GifDither,
+}
+
+impl Default for GifPalette {
+    fn default() -> Self {
+        Self {
+            max_colors: 256,
+            dither: GifDither::default(),
+        }
+    }
+}
+
+/// A GIF where every frame gets its own palette, using FFmpeg's `palettegen` and
+/// `paletteuse` filters.
+///
+/// A single palette for the whole GIF could only be generated once every frame had been
+/// seen, which would mean holding all of them in memory. Frames are encoded as they're
+/// queued instead, and frames that share a palette with the previous one don't repeat it.
+pub struct GifFile {
+    output: format::context::Output,
+    encoder: encoder::Video,
+    graph: filter::Graph,
+    config: VideoInfo,
+    stream_index: usize,
+    packet: ffmpeg::Packet,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum GifError {
+    #[error("{0:?}")]
+    FFmpeg(#[from] ffmpeg::Error),
+    #[error("GIF codec not found")]
+    CodecNotFound,
+    #[error("Filter '{0}' not found")]
+    FilterNotFound(&'static str),
+    #[error("Pixel format {0:?} not supported, frames must be RGBA")]
+    PixFmtNotSupported(Pixel),
+}
+
+impl GifFile {
+    pub fn init(
+        mut output: PathBuf,
+        config: VideoInfo,
+        palette: GifPalette,
+    ) -> Result<Self, GifError> {
+        if config.pixel_format != Pixel::RGBA {
+            return Err(GifError::PixFmtNotSupported(config.pixel_format));
+        }
+
+        let graph = Self::palette_graph(&config, palette)?;
+
+        output.set_extension("gif");
+        let mut output = format::output_as(&output, "gif")?;
+
+        let codec = encoder::find(codec::Id::GIF).ok_or(GifError::CodecNotFound)?;
+        let mut encoder = context::Context::new_with_codec(codec).encoder().video()?;
+
+        encoder.set_width(config.width);
+        encoder.set_height(config.height);
+        encoder.set_format(Pixel::PAL8);
+        encoder.set_time_base(config.frame_rate.invert());
+        encoder.set_frame_rate(Some(config.frame_rate));
+
+        let encoder = encoder.open()?;
+
+        let mut output_stream = output.add_stream(codec)?;
+        let stream_index = output_stream.index();
+        output_stream.set_time_base(config.frame_rate.invert());
+        output_stream.set_rate(config.frame_rate);
+        output_stream.set_parameters(&encoder);
+
+        let mut options = Dictionary::new();
+        options.set("loop", "0");
+        output.write_header_with(options)?;
+
+        Ok(Self {
+            output,
+            encoder,
+            graph,
+            config,
+            stream_index,
+            packet: ffmpeg::Packet::empty(),
+        })
+    }
+
+    fn palette_graph(config: &VideoInfo, palette: GifPalette) -> Result<filter::Graph, GifError> {
+        let find = |name| filter::find(name).ok_or(GifError::FilterNotFound(name));
+        let mut graph = filter::Graph::new();
+
+        let args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base={}:pixel_aspect=1/1",
+            config.width,
+            config.height,
+            ffmpeg::ffi::AVPixelFormat::from(config.pixel_format) as i32,
+            config.frame_rate.invert(),
+        );
+
+        graph.add(&find("buffer")?, "in", &args)?;
+        graph.add(&find("buffersink")?, "out", "")?;
+        graph.output("in", 0)?.input("out", 0)?.parse(&format!(
+            "split[frames][stats];[stats]palettegen=max_colors={}:stats_mode=single[palette];[frames][palette]paletteuse=new=1:dither={}",
+            palette.max_colors.clamp(4, 256),
+            palette.dither.as_filter_option()
+        ))?;
+        graph.validate()?;
+
+        Ok(graph)
+    }
+
+    /// Frame timestamps must be in frames, starting from 0.
+    pub fn queue_frame(&mut self, frame: frame::Video) -> Result<(), GifError> {
+        // Both are added in `palette_graph`
+        self.graph.get("in").unwrap().source().add(&frame)?;
+        self.encode_filtered()
+    }
+
+    fn encode_filtered(&mut self) -> Result<(), GifError> {
+        let mut filtered = frame::Video::empty();
+
+        while self
+            .graph
+            .get("out")
+            .unwrap()
+            .sink()
+            .frame(&mut filtered)
+            .is_ok()
+        {
+            self.encoder.send_frame(&filtered)?;
+            self.process_packets()?;
+        }
+
+        Ok(())
+    }
+
+    fn process_packets(&mut self) -> Result<(), GifError> {
+        while self.encoder.receive_packet(&mut self.packet).is_ok() {
+            self.packet.set_stream(self.stream_index);
+            self.packet.rescale_ts(
+                self.config.frame_rate.invert(),
+                self.output.stream(self.stream_index).unwrap().time_base(),
+            );
+            self.packet.write_interleaved(&mut self.output)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), GifError> {
+        self.graph.get("in").unwrap().source().flush()?;
+        self.encode_filtered()?;
+
+        if let Err(e) = self.encoder.send_eof() {
+            error!("Failed to send EOF to GIF encoder: {e:?}");
+        }
+        self.process_packets()?;
+
+        self.output.write_trailer()?;
+
+        Ok(())
+    }
+}
+
+unsafe impl Send for GifFile {}
@@ -0,0 +1,164 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+/// Keeps streams that are encoded on separate threads within `max_lookahead` of each other,
+/// so that the muxer never has to buffer more than that window to interleave their packets.
+///
+/// A stream only holds others back while it is actively encoding. Streams that are waiting
+/// for input (or have finished) are ignored, which prevents a stream that has run out of
+/// frames from stalling the rest.
+#[derive(Clone)]
+pub struct InterleaveBarrier {
+    inner: Arc<BarrierInner>,
+}
+
+struct BarrierInner {
+    positions: Mutex<Vec<Option<Duration>>>,
+    condvar: Condvar,
+    max_lookahead: Duration,
+}
+
+impl InterleaveBarrier {
+    pub fn new(max_lookahead: Duration) -> Self {
+        Self {
+            inner: Arc::new(BarrierInner {
+                positions: Mutex::new(vec![]),
+                condvar: Condvar::new(),
+                max_lookahead,
+            }),
+        }
+    }
+
+    pub fn register(&self) -> InterleaveStream {
+        let mut positions = self.inner.positions.lock().unwrap();
+        positions.push(None);
+
+        InterleaveStream {
+            inner: self.inner.clone(),
+            index: positions.len() - 1,
+        }
+    }
+}
+
+pub struct InterleaveStream {
+    inner: Arc<BarrierInner>,
+    index: usize,
+}
+
+impl InterleaveStream {
+    /// Blocks until `timestamp` is within the lookahead window of every other stream
+    /// that is currently encoding.
+    pub fn advance(&self, timestamp: Duration) {
+        let inner = &self.inner;
+        let mut positions = inner.positions.lock().unwrap();
+        positions[self.index] = Some(timestamp);
+        inner.condvar.notify_all();
+
+        let _positions = inner
+            .condvar
+            .wait_while(positions, |positions| {
+                positions.iter().enumerate().any(|(i, position)| {
+                    i != self.index && position.is_some_and(|p| p + inner.max_lookahead < timestamp)
+                })
+            })
+            .unwrap();
+    }
+
+    /// Marks the stream as waiting for input, so that it doesn't hold back other streams.
+    pub fn idle(&self) {
+        let mut positions = self.inner.positions.lock().unwrap();
+        positions[self.index] = None;
+        self.inner.condvar.notify_all();
+    }
+}
+
+impl Drop for InterleaveStream {
+    fn drop(&mut self) {
+        self.idle();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicU64, Ordering},
+        thread,
+        time::Instant,
+    };
+
+    #[test]
+    fn slow_stream_bounds_fast_stream() {
+        const LOOKAHEAD_MS: u64 = 100;
+        const FRAMES: u64 = 60;
+
+        let barrier = InterleaveBarrier::new(Duration::from_millis(LOOKAHEAD_MS));
+        let video_position = Arc::new(AtomicU64::new(0));
+        let max_audio_lead = Arc::new(AtomicU64::new(0));
+
+        let video = {
+            let stream = barrier.register();
+            let video_position = video_position.clone();
+            thread::spawn(move || {
+                for i in 0..FRAMES {
+                    let ts = i * 1000 / 30;
+                    stream.advance(Duration::from_millis(ts));
+                    video_position.store(ts, Ordering::SeqCst);
+                    // artificially slow encode, with occasional spikes
+                    thread::sleep(Duration::from_millis(if i % 10 == 0 { 20 } else { 2 }));
+                }
+            })
+        };
+
+        let audio = {
+            let stream = barrier.register();
+            let video_position = video_position.clone();
+            let max_audio_lead = max_audio_lead.clone();
+            thread::spawn(move || {
+                for i in 0..FRAMES * 2 {
+                    let ts = i * 1000 / 60;
+                    stream.advance(Duration::from_millis(ts));
+                    let lead = ts.saturating_sub(video_position.load(Ordering::SeqCst));
+                    max_audio_lead.fetch_max(lead, Ordering::SeqCst);
+                }
+            })
+        };
+
+        video.join().unwrap();
+        audio.join().unwrap();
+
+        // one video frame of slack for the position being published after advancing
+        assert!(max_audio_lead.load(Ordering::SeqCst) <= LOOKAHEAD_MS + 1000 / 30);
+    }
+
+    #[test]
+    fn idle_stream_does_not_block() {
+        let barrier = InterleaveBarrier::new(Duration::from_millis(10));
+        let idle = barrier.register();
+        idle.advance(Duration::ZERO);
+        idle.idle();
+
+        let busy = barrier.register();
+        let start = Instant::now();
+        busy.advance(Duration::from_secs(60));
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn finished_stream_releases_waiters() {
+        let barrier = InterleaveBarrier::new(Duration::from_millis(10));
+        let slow = barrier.register();
+        slow.advance(Duration::ZERO);
+
+        let fast = barrier.register();
+        let handle = thread::spawn(move || fast.advance(Duration::from_secs(5)));
+
+        thread::sleep(Duration::from_millis(20));
+        drop(slow);
+
+        handle.join().unwrap();
+    }
+}
@@ -1,5 +1,26 @@
+mod interleave;
+pub use interleave::*;
+
 mod mp4;
 pub use mp4::*;
 
 mod ogg;
 pub use ogg::*;
+
+mod audio_file;
+pub use audio_file::*;
+
+mod wav;
+pub use wav::*;
+
+mod webm;
+pub use webm::*;
+
+mod apng;
+pub use apng::*;
+
+mod gif;
+pub use gif::*;
+
+mod fragmented_mp4;
+pub use fragmented_mp4::*;
@@ -1,9 +1,15 @@
 use cap_media_info::RawVideoFormat;
-use ffmpeg::{format, frame};
-use std::path::PathBuf;
-use tracing::{info, trace};
+use ffmpeg::{Dictionary, format, frame};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex, mpsc},
+    thread::JoinHandle,
+    time::Duration,
+};
+use tracing::{error, info, trace};
 
 use crate::{
+    InterleaveBarrier, InterleaveStream,
     audio::AudioEncoder,
     video::{H264Encoder, H264EncoderError},
 };
@@ -27,16 +33,64 @@ pub enum InitError {
     AudioInit(Box<dyn std::error::Error>),
 }
 
+/// Container settings for [`MP4File::init_with_options`].
+#[derive(Default)]
+pub struct MP4Options {
+    /// Container tags such as `title`, `comment`, `encoder` and `creation_time`
+    pub metadata: Dictionary,
+    /// Writes the `moov` atom at the front of the file so playback can start before it's
+    /// fully downloaded. This rewrites the file once it's finished.
+    pub faststart: bool,
+}
+
 impl MP4File {
     pub fn init(
+        tag: &'static str,
+        output: PathBuf,
+        video: impl FnOnce(&mut format::context::Output) -> Result<H264Encoder, H264EncoderError>,
+        audio: impl FnOnce(
+            &mut format::context::Output,
+        )
+            -> Option<Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>>,
+    ) -> Result<Self, InitError> {
+        Self::init_with_metadata(tag, output, Dictionary::new(), video, audio)
+    }
+
+    /// Like [`init`](Self::init), also writing container tags such as `title`, `comment`,
+    /// `encoder` and `creation_time`.
+    pub fn init_with_metadata(
+        tag: &'static str,
+        output: PathBuf,
+        metadata: Dictionary,
+        video: impl FnOnce(&mut format::context::Output) -> Result<H264Encoder, H264EncoderError>,
+        audio: impl FnOnce(
+            &mut format::context::Output,
+        )
+            -> Option<Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>>,
+    ) -> Result<Self, InitError> {
+        let options = MP4Options {
+            metadata,
+            ..Default::default()
+        };
+
+        Self::init_with_options(tag, output, options, video, audio)
+    }
+
+    pub fn init_with_options(
         tag: &'static str,
         mut output: PathBuf,
+        options: MP4Options,
         video: impl FnOnce(&mut format::context::Output) -> Result<H264Encoder, H264EncoderError>,
         audio: impl FnOnce(
             &mut format::context::Output,
         )
             -> Option<Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>>,
     ) -> Result<Self, InitError> {
+        let MP4Options {
+            metadata,
+            faststart,
+        } = options;
+
         output.set_extension("mp4");
 
         if let Some(parent) = output.parent() {
@@ -54,8 +108,26 @@ impl MP4File {
 
         info!("Prepared encoders for mp4 file");
 
+        let encoder = metadata.get("encoder").map(str::to_owned);
+        output.set_metadata(metadata);
+
+        let mut muxer_options = Dictionary::new();
+        if faststart {
+            muxer_options.set("movflags", "+faststart");
+        }
+
         // make sure this happens after adding all encoders!
-        output.write_header().map_err(InitError::Ffmpeg)?;
+        output
+            .write_header_with(muxer_options)
+            .map_err(InitError::Ffmpeg)?;
+
+        // writing the header replaces the encoder tag with ffmpeg's own,
+        // but it isn't written out until the trailer
+        if let Some(encoder) = encoder {
+            let mut metadata = output.metadata().to_owned();
+            metadata.set("encoder", &encoder);
+            output.set_metadata(metadata);
+        }
 
         Ok(Self {
             tag,
@@ -70,12 +142,12 @@ impl MP4File {
         RawVideoFormat::YUYV420
     }
 
-    pub fn queue_video_frame(&mut self, frame: frame::Video) {
+    pub fn queue_video_frame(&mut self, frame: frame::Video) -> Result<(), ffmpeg::Error> {
         if self.is_finished {
-            return;
+            return Ok(());
         }
 
-        self.video.queue_frame(frame, &mut self.output);
+        self.video.queue_frame(frame, &mut self.output)
     }
 
     pub fn queue_audio_frame(&mut self, frame: frame::Audio) {
@@ -90,16 +162,17 @@ impl MP4File {
         audio.queue_frame(frame, &mut self.output);
     }
 
-    pub fn finish(&mut self) {
+    /// Writes the trailer even if flushing the video encoder fails, so the file stays playable.
+    pub fn finish(&mut self) -> Result<(), ffmpeg::Error> {
         if self.is_finished {
-            return;
+            return Ok(());
         }
 
         self.is_finished = true;
 
         tracing::info!("MP4Encoder: Finishing encoding");
 
-        self.video.finish(&mut self.output);
+        let flushed = self.video.finish(&mut self.output);
 
         if let Some(audio) = &mut self.audio {
             tracing::info!("MP4Encoder: Flushing audio encoder");
@@ -107,9 +180,9 @@ impl MP4File {
         }
 
         tracing::info!("MP4Encoder: Writing trailer");
-        if let Err(e) = self.output.write_trailer() {
-            tracing::error!("Failed to write MP4 trailer: {:?}", e);
-        }
+        let trailer = self.output.write_trailer();
+
+        flushed.and(trailer)
     }
 
     pub fn video(&self) -> &H264Encoder {
@@ -119,6 +192,273 @@ impl MP4File {
     pub fn video_mut(&mut self) -> &mut H264Encoder {
         &mut self.video
     }
+
+    /// Moves video and audio encoding onto their own threads, so that a slow video encode
+    /// can't starve the audio encoder. Packets are written to the shared output as each
+    /// encoder produces them, with neither stream allowed to get more than `max_lookahead`
+    /// ahead of the other.
+    pub fn into_threaded(self, max_lookahead: Duration) -> ThreadedMP4File {
+        let Self {
+            tag,
+            output,
+            video,
+            audio,
+            is_finished,
+        } = self;
+
+        ThreadedMP4File::spawn(tag, output, video, audio, is_finished, max_lookahead)
+    }
+
+    /// [`Self::into_threaded`] if `max_lookahead` is set, otherwise keeps encoding on the
+    /// thread that queues frames.
+    pub fn threaded_if(self, max_lookahead: Option<Duration>) -> AnyMP4File {
+        match max_lookahead {
+            Some(max_lookahead) => AnyMP4File::Threaded(self.into_threaded(max_lookahead)),
+            None => AnyMP4File::Inline(self),
+        }
+    }
+}
+
+/// The video side of a [`ThreadedMP4File`], so that the threading can be tested
+/// with an encoder that's slow on purpose.
+trait ThreadedVideoEncoder: Send + 'static {
+    fn time_base(&self) -> ffmpeg::Rational;
+    fn send_frame(&mut self, frame: frame::Video) -> Result<(), ffmpeg::Error>;
+    fn process_frame(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error>;
+    fn finish(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error>;
+}
+
+impl ThreadedVideoEncoder for H264Encoder {
+    fn time_base(&self) -> ffmpeg::Rational {
+        H264Encoder::time_base(self)
+    }
+
+    fn send_frame(&mut self, frame: frame::Video) -> Result<(), ffmpeg::Error> {
+        H264Encoder::send_frame(self, frame)
+    }
+
+    fn process_frame(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+        H264Encoder::process_frame(self, output)
+    }
+
+    fn finish(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+        H264Encoder::finish(self, output)
+    }
+}
+
+/// An [`MP4File`] whose video and audio streams are encoded on separate threads.
+/// Created with [`MP4File::into_threaded`].
+pub struct ThreadedMP4File {
+    #[allow(unused)]
+    tag: &'static str,
+    output: Arc<Mutex<format::context::Output>>,
+    video_tx: Option<mpsc::SyncSender<frame::Video>>,
+    audio_tx: Option<mpsc::SyncSender<frame::Audio>>,
+    video_thread: Option<JoinHandle<Result<(), ffmpeg::Error>>>,
+    audio_thread: Option<JoinHandle<()>>,
+    is_finished: bool,
+}
+
+impl ThreadedMP4File {
+    /// A lookahead that absorbs the encode spikes of scene changes without buffering much.
+    pub const DEFAULT_MAX_LOOKAHEAD: Duration = Duration::from_millis(500);
+
+    fn spawn(
+        tag: &'static str,
+        output: format::context::Output,
+        mut video: impl ThreadedVideoEncoder,
+        audio: Option<Box<dyn AudioEncoder + Send>>,
+        is_finished: bool,
+        max_lookahead: Duration,
+    ) -> Self {
+        let output = Arc::new(Mutex::new(output));
+        let barrier = InterleaveBarrier::new(max_lookahead);
+
+        let (video_tx, video_rx) = mpsc::sync_channel::<frame::Video>(4);
+        let video_thread = std::thread::spawn({
+            let output = output.clone();
+            let stream = barrier.register();
+            // stops at the first error, which is returned from `finish`
+            move || {
+                let time_base = video.time_base();
+
+                loop {
+                    stream.idle();
+                    let Ok(frame) = video_rx.recv() else {
+                        break;
+                    };
+
+                    if let Some(timestamp) = pts_to_duration(frame.pts(), time_base) {
+                        stream.advance(timestamp);
+                    }
+
+                    video.send_frame(frame)?;
+
+                    if let Ok(mut output) = output.lock() {
+                        video.process_frame(&mut output)?;
+                    }
+                }
+
+                match output.lock() {
+                    Ok(mut output) => video.finish(&mut output),
+                    Err(_) => Ok(()),
+                }
+            }
+        });
+
+        let (audio_tx, audio_thread) = match audio {
+            Some(mut audio) => {
+                let (audio_tx, audio_rx) = mpsc::sync_channel::<frame::Audio>(16);
+                let output = output.clone();
+                let stream = barrier.register();
+
+                let audio_thread = std::thread::spawn(move || {
+                    loop {
+                        stream.idle();
+                        let Ok(frame) = audio_rx.recv() else {
+                            break;
+                        };
+
+                        if let Some(timestamp) = pts_to_duration(
+                            frame.pts(),
+                            ffmpeg::Rational::new(1, frame.rate() as i32),
+                        ) {
+                            stream.advance(timestamp);
+                        }
+
+                        if let Ok(mut output) = output.lock() {
+                            audio.queue_frame(frame, &mut output);
+                        }
+                    }
+
+                    if let Ok(mut output) = output.lock() {
+                        audio.finish(&mut output);
+                    }
+                });
+
+                (Some(audio_tx), Some(audio_thread))
+            }
+            None => (None, None),
+        };
+
+        Self {
+            tag,
+            output,
+            video_tx: Some(video_tx),
+            audio_tx,
+            video_thread: Some(video_thread),
+            audio_thread,
+            is_finished,
+        }
+    }
+
+    pub fn queue_video_frame(&mut self, frame: frame::Video) {
+        if let Some(video_tx) = &self.video_tx {
+            let _ = video_tx.send(frame);
+        }
+    }
+
+    pub fn queue_audio_frame(&mut self, frame: frame::Audio) {
+        if let Some(audio_tx) = &self.audio_tx {
+            let _ = audio_tx.send(frame);
+        }
+    }
+
+    /// Returns the first error the video thread hit, or the trailer's if there wasn't one.
+    pub fn finish(&mut self) -> Result<(), ffmpeg::Error> {
+        if self.is_finished {
+            return Ok(());
+        }
+
+        self.is_finished = true;
+
+        tracing::info!("MP4Encoder: Finishing encoding threads");
+
+        drop(self.video_tx.take());
+        drop(self.audio_tx.take());
+
+        let encoded = match self.video_thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => {
+                error!("MP4Encoder: Encoding thread panicked");
+                Ok(())
+            }
+            None => Ok(()),
+        };
+
+        if let Some(thread) = self.audio_thread.take()
+            && thread.join().is_err()
+        {
+            error!("MP4Encoder: Encoding thread panicked");
+        }
+
+        tracing::info!("MP4Encoder: Writing trailer");
+        let trailer = match self.output.lock() {
+            Ok(mut output) => output.write_trailer(),
+            Err(_) => {
+                error!("Failed to write MP4 trailer: output lock poisoned");
+                Err(ffmpeg::Error::Bug)
+            }
+        };
+
+        encoded.and(trailer)
+    }
+}
+
+/// An [`MP4File`] that may have been moved onto encoding threads with
+/// [`MP4File::threaded_if`].
+pub enum AnyMP4File {
+    Inline(MP4File),
+    Threaded(ThreadedMP4File),
+}
+
+impl AnyMP4File {
+    /// A threaded file's video thread only stops early if encoding fails, in which case
+    /// the file is finished and the error returned.
+    pub fn queue_video_frame(&mut self, frame: frame::Video) -> Result<(), ffmpeg::Error> {
+        match self {
+            Self::Inline(file) => file.queue_video_frame(frame),
+            Self::Threaded(file) => {
+                file.queue_video_frame(frame);
+
+                if file
+                    .video_thread
+                    .as_ref()
+                    .is_some_and(JoinHandle::is_finished)
+                {
+                    return file.finish();
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    pub fn queue_audio_frame(&mut self, frame: frame::Audio) {
+        match self {
+            Self::Inline(file) => file.queue_audio_frame(frame),
+            Self::Threaded(file) => file.queue_audio_frame(frame),
+        }
+    }
+
+    pub fn finish(&mut self) -> Result<(), ffmpeg::Error> {
+        match self {
+            Self::Inline(file) => file.finish(),
+            Self::Threaded(file) => file.finish(),
+        }
+    }
+}
+
+fn pts_to_duration(pts: Option<i64>, time_base: ffmpeg::Rational) -> Option<Duration> {
+    let pts = pts.filter(|pts| *pts >= 0)?;
+
+    if time_base.denominator() == 0 {
+        return None;
+    }
+
+    Some(Duration::from_secs_f64(
+        pts as f64 * time_base.numerator() as f64 / time_base.denominator() as f64,
+    ))
 }
 
 pub struct MP4Input {
@@ -127,3 +467,202 @@ pub struct MP4Input {
 }
 
 unsafe impl Send for H264Encoder {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AACEncoder;
+    use cap_media_info::{AudioInfo, RawVideoFormat, VideoInfo};
+    use ffmpeg::Rescale;
+
+    /// Types of the boxes at the top level of an MP4, in file order
+    fn top_level_boxes(bytes: &[u8]) -> Vec<String> {
+        let mut boxes = vec![];
+        let mut offset = 0;
+
+        while offset + 8 <= bytes.len() {
+            let size = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+            let size = match size {
+                0 => bytes.len() - offset,
+                1 => {
+                    u64::from_be_bytes(bytes[offset + 8..offset + 16].try_into().unwrap()) as usize
+                }
+                size => size,
+            };
+
+            boxes.push(String::from_utf8_lossy(&bytes[offset + 4..offset + 8]).into_owned());
+            offset += size.max(8);
+        }
+
+        boxes
+    }
+
+    fn encode_boxes(faststart: bool) -> Vec<String> {
+        ffmpeg::init().unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "cap-enc-ffmpeg-faststart-{faststart}-{}.mp4",
+            std::process::id()
+        ));
+        let info = VideoInfo::from_raw(RawVideoFormat::YUYV420, 64, 64, 30);
+
+        let mut file = MP4File::init_with_options(
+            "test",
+            path.clone(),
+            MP4Options {
+                faststart,
+                ..Default::default()
+            },
+            |o| H264Encoder::builder("test_video", info).build(o),
+            |_| None,
+        )
+        .unwrap();
+
+        for i in 0..10 {
+            let mut frame = frame::Video::new(info.pixel_format, info.width, info.height);
+            frame.set_pts(Some(i * 1_000_000 / 30));
+            file.queue_video_frame(frame).unwrap();
+        }
+        file.finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        top_level_boxes(&bytes)
+    }
+
+    fn position(boxes: &[String], name: &str) -> usize {
+        boxes.iter().position(|b| b == name).unwrap()
+    }
+
+    #[test]
+    fn writes_moov_after_media_by_default() {
+        let boxes = encode_boxes(false);
+        assert!(
+            position(&boxes, "moov") > position(&boxes, "mdat"),
+            "{boxes:?}"
+        );
+    }
+
+    #[test]
+    fn writes_moov_first_with_faststart() {
+        let boxes = encode_boxes(true);
+        assert!(
+            position(&boxes, "moov") < position(&boxes, "mdat"),
+            "{boxes:?}"
+        );
+    }
+
+    /// Takes `delay` to encode every frame, like a software encoder falling behind
+    /// on a busy machine.
+    struct SlowVideoEncoder {
+        encoder: H264Encoder,
+        delay: Duration,
+    }
+
+    impl ThreadedVideoEncoder for SlowVideoEncoder {
+        fn time_base(&self) -> ffmpeg::Rational {
+            self.encoder.time_base()
+        }
+
+        fn send_frame(&mut self, frame: frame::Video) -> Result<(), ffmpeg::Error> {
+            std::thread::sleep(self.delay);
+            self.encoder.send_frame(frame)
+        }
+
+        fn process_frame(
+            &mut self,
+            output: &mut format::context::Output,
+        ) -> Result<(), ffmpeg::Error> {
+            self.encoder.process_frame(output)
+        }
+
+        fn finish(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+            self.encoder.finish(output)
+        }
+    }
+
+    #[test]
+    fn slow_video_encoder_drops_no_audio() {
+        const SAMPLE_RATE: u32 = 48_000;
+        const AUDIO_FRAME: usize = 1024;
+        const AUDIO_FRAMES: i64 = 90;
+        const VIDEO_FRAMES: i64 = 60;
+
+        ffmpeg::init().unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "cap-enc-ffmpeg-threaded-{}.mp4",
+            std::process::id()
+        ));
+        let video_info = VideoInfo::from_raw(RawVideoFormat::YUYV420, 64, 64, 30);
+        let audio_info = AudioInfo::new(AACEncoder::SAMPLE_FORMAT, SAMPLE_RATE, 1).unwrap();
+
+        let mut output = format::output(&path).unwrap();
+        let video = H264Encoder::builder("test_video", video_info)
+            .build(&mut output)
+            .unwrap();
+        let audio = AACEncoder::init("test_audio", audio_info, &mut output)
+            .unwrap()
+            .boxed();
+        output.write_header().unwrap();
+
+        let mut file = ThreadedMP4File::spawn(
+            "test",
+            output,
+            // twice as long as a frame lasts at 30fps
+            SlowVideoEncoder {
+                encoder: video,
+                delay: Duration::from_millis(66),
+            },
+            Some(audio),
+            false,
+            Duration::from_millis(100),
+        );
+
+        // frames arrive from separate capture threads, as they do while recording
+        let video_tx = file.video_tx.clone().unwrap();
+        let video_producer = std::thread::spawn(move || {
+            for i in 0..VIDEO_FRAMES {
+                let mut frame =
+                    frame::Video::new(video_info.pixel_format, video_info.width, video_info.height);
+                frame.set_pts(Some(i * 1_000_000 / 30));
+                video_tx.send(frame).unwrap();
+            }
+        });
+
+        let audio_tx = file.audio_tx.clone().unwrap();
+        let audio_producer = std::thread::spawn(move || {
+            for i in 0..AUDIO_FRAMES {
+                let mut frame = audio_info.empty_frame(AUDIO_FRAME);
+                frame.data_mut(0).fill(0);
+                frame.set_pts(Some(i * AUDIO_FRAME as i64));
+                audio_tx.send(frame).unwrap();
+            }
+        });
+
+        video_producer.join().unwrap();
+        audio_producer.join().unwrap();
+        file.finish().unwrap();
+
+        let mut input = format::input(&path).unwrap();
+        let stream = input.streams().best(ffmpeg::media::Type::Audio).unwrap();
+        let (index, time_base) = (stream.index(), stream.time_base());
+
+        let samples = input
+            .packets()
+            .filter(|(stream, _)| stream.index() == index)
+            .map(|(_, packet)| {
+                packet
+                    .duration()
+                    .rescale(time_base, (1, SAMPLE_RATE as i32))
+            })
+            .sum::<i64>();
+        std::fs::remove_file(&path).ok();
+
+        assert!(
+            samples >= AUDIO_FRAMES * AUDIO_FRAME as i64,
+            "only {samples} samples were written"
+        );
+    }
+}
@@ -24,12 +24,14 @@ impl OggFile {
         Ok(Self { encoder, output })
     }
 
-    pub fn queue_frame(&mut self, frame: frame::Audio) {
-        self.encoder.queue_frame(frame, &mut self.output);
+    pub fn queue_frame(&mut self, frame: frame::Audio) -> Result<(), ffmpeg::Error> {
+        self.encoder.queue_frame(frame, &mut self.output)
     }
 
-    pub fn finish(&mut self) {
-        self.encoder.finish(&mut self.output);
-        self.output.write_trailer().unwrap();
+    /// Writes the trailer even if flushing the encoder fails, so the file stays playable.
+    pub fn finish(&mut self) -> Result<(), ffmpeg::Error> {
+        let flushed = self.encoder.finish(&mut self.output);
+        let trailer = self.output.write_trailer();
+        flushed.and(trailer)
     }
 }
@@ -0,0 +1,93 @@
+use cap_media_info::AudioInfo;
+use ffmpeg::frame;
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    AudioFile, AudioFileCodec, AudioFileEncoder, AudioFileEncoderError, SampleAdjustment,
+    SampleTimeline,
+};
+
+/// A 16-bit PCM WAV file at [`WavFile::SAMPLE_RATE`] whose samples stay at the times
+/// they're queued at, so it lines up with the other tracks of a recording.
+///
+/// Time zero is the start of the file. Gaps between frames are filled with silence and
+/// overlapping or early samples are dropped. The header's sizes are only correct once
+/// [`WavFile::finish`] has been called.
+pub struct WavFile {
+    file: AudioFile,
+    input_config: AudioInfo,
+    timeline: SampleTimeline,
+}
+
+impl WavFile {
+    pub const SAMPLE_RATE: u32 = 48_000;
+    /// How far samples can be from their timestamps before they're realigned. Small enough
+    /// to be inaudible next to video, while jitter in the timestamps doesn't cause clicks.
+    const DRIFT_TOLERANCE: Duration = Duration::from_millis(2);
+
+    pub fn init(output: PathBuf, input_config: AudioInfo) -> Result<Self, AudioFileEncoderError> {
+        let file = AudioFile::init(output, |o| {
+            AudioFileEncoder::init(
+                input_config,
+                AudioFileCodec::Wav,
+                Some(Self::SAMPLE_RATE),
+                0,
+                o,
+            )
+        })?;
+
+        let mut timeline = SampleTimeline::new(input_config.sample_rate, Self::DRIFT_TOLERANCE);
+        // anchors the timeline at the start of the file, so the first frame is padded
+        // out to its timestamp like any other
+        timeline.adjust(0, 0);
+
+        Ok(Self {
+            file,
+            input_config,
+            timeline,
+        })
+    }
+
+    /// `time` is the seconds from the start of the file to the frame's first sample.
+    pub fn queue_frame(&mut self, frame: &frame::Audio, time: f64) -> Result<(), ffmpeg::Error> {
+        let rate = self.input_config.sample_rate;
+        let pts = (time * rate as f64).round() as i64;
+
+        match self.timeline.adjust(pts, frame.samples()) {
+            SampleAdjustment::Keep => self.file.queue_frame(frame),
+            SampleAdjustment::Pad(samples) => {
+                let mut silence = self.input_config.empty_frame(samples);
+                for plane in 0..silence.planes() {
+                    silence.data_mut(plane).fill(0);
+                }
+
+                self.file.queue_frame(&silence)?;
+                self.file.queue_frame(frame)
+            }
+            SampleAdjustment::Skip(samples) if samples >= frame.samples() => Ok(()),
+            SampleAdjustment::Skip(samples) => {
+                let mut trimmed = self.input_config.empty_frame(frame.samples() - samples);
+                let sample_bytes = frame.format().bytes()
+                    * if frame.is_planar() {
+                        1
+                    } else {
+                        frame.channels() as usize
+                    };
+
+                for plane in 0..frame.planes() {
+                    let len = trimmed.samples() * sample_bytes;
+                    let start = samples * sample_bytes;
+                    trimmed.data_mut(plane)[..len]
+                        .copy_from_slice(&frame.data(plane)[start..start + len]);
+                }
+
+                self.file.queue_frame(&trimmed)
+            }
+        }
+    }
+
+    /// Writes the remaining samples and the final header. Nothing can be queued afterwards.
+    pub fn finish(&mut self) -> Result<(), ffmpeg::Error> {
+        self.file.finish()
+    }
+}
@@ -0,0 +1,103 @@
+use ffmpeg::{format, frame};
+use std::path::PathBuf;
+use tracing::{error, info};
+
+use crate::{
+    audio::AudioEncoder,
+    video::{VP9Encoder, VP9EncoderError},
+};
+
+pub struct WebMFile {
+    #[allow(unused)]
+    tag: &'static str,
+    output: format::context::Output,
+    video: VP9Encoder,
+    audio: Option<Box<dyn AudioEncoder + Send>>,
+    is_finished: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum WebMInitError {
+    #[error("{0:?}")]
+    Ffmpeg(ffmpeg::Error),
+    #[error("Video/{0}")]
+    VideoInit(VP9EncoderError),
+    #[error("Audio/{0}")]
+    AudioInit(Box<dyn std::error::Error>),
+}
+
+impl WebMFile {
+    pub fn init(
+        tag: &'static str,
+        mut output: PathBuf,
+        video: impl FnOnce(&mut format::context::Output) -> Result<VP9Encoder, VP9EncoderError>,
+        audio: impl FnOnce(
+            &mut format::context::Output,
+        )
+            -> Option<Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>>,
+    ) -> Result<Self, WebMInitError> {
+        output.set_extension("webm");
+
+        if let Some(parent) = output.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+
+        let mut output = format::output(&output).map_err(WebMInitError::Ffmpeg)?;
+
+        let video = video(&mut output).map_err(WebMInitError::VideoInit)?;
+        let audio = audio(&mut output)
+            .transpose()
+            .map_err(WebMInitError::AudioInit)?;
+
+        info!("Prepared encoders for webm file");
+
+        // make sure this happens after adding all encoders!
+        output.write_header().map_err(WebMInitError::Ffmpeg)?;
+
+        Ok(Self {
+            tag,
+            output,
+            video,
+            audio,
+            is_finished: false,
+        })
+    }
+
+    pub fn queue_video_frame(&mut self, frame: frame::Video) {
+        if self.is_finished {
+            return;
+        }
+
+        self.video.queue_frame(frame, &mut self.output);
+    }
+
+    pub fn queue_audio_frame(&mut self, frame: frame::Audio) {
+        if self.is_finished {
+            return;
+        }
+
+        let Some(audio) = &mut self.audio else {
+            return;
+        };
+
+        audio.queue_frame(frame, &mut self.output);
+    }
+
+    pub fn finish(&mut self) {
+        if self.is_finished {
+            return;
+        }
+
+        self.is_finished = true;
+
+        self.video.finish(&mut self.output);
+
+        if let Some(audio) = &mut self.audio {
+            audio.finish(&mut self.output);
+        }
+
+        if let Err(e) = self.output.write_trailer() {
+            error!("Failed to write WebM trailer: {:?}", e);
+        }
+    }
+}
@@ -11,15 +11,56 @@ use tracing::{debug, error};
 pub struct H264EncoderBuilder {
     name: &'static str,
     bpp: f32,
+    crf: Option<u8>,
+    keyframe_interval: Option<u32>,
     input_config: VideoInfo,
     preset: H264Preset,
+    codec: VideoCodec,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VideoCodec {
+    #[default]
+    H264,
+    /// Requires FFmpeg to be built with libx265
+    H265,
+    /// Requires FFmpeg to be built with libsvtav1, libaom or librav1e.
+    /// Encoding is much slower than H.264, with the preset trading speed for quality.
+    Av1,
+}
+
+/// AV1 encoders FFmpeg may have been built with, in order of preference
+const AV1_ENCODERS: &[&str] = &["libsvtav1", "libaom-av1", "librav1e"];
+
+/// x264's presets, from fastest to smallest files. x265 has the same ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum H264Preset {
-    Slow,
-    Medium,
     Ultrafast,
+    Superfast,
+    Veryfast,
+    Faster,
+    Fast,
+    Medium,
+    Slow,
+    Slower,
+    Veryslow,
+}
+
+impl H264Preset {
+    /// The name x264 and x265 know the preset by
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ultrafast => "ultrafast",
+            Self::Superfast => "superfast",
+            Self::Veryfast => "veryfast",
+            Self::Faster => "faster",
+            Self::Fast => "fast",
+            Self::Medium => "medium",
+            Self::Slow => "slow",
+            Self::Slower => "slower",
+            Self::Veryslow => "veryslow",
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -40,7 +81,10 @@ impl H264EncoderBuilder {
             name,
             input_config,
             bpp: Self::QUALITY_BPP,
+            crf: None,
+            keyframe_interval: None,
             preset: H264Preset::Ultrafast,
+            codec: VideoCodec::H264,
         }
     }
 
@@ -49,27 +93,58 @@ impl H264EncoderBuilder {
         self
     }
 
+    pub fn with_codec(mut self, codec: VideoCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
     pub fn with_bpp(mut self, bpp: f32) -> Self {
         self.bpp = bpp;
         self
     }
 
+    /// Encodes at a constant rate factor instead of the bitrate derived from bits per pixel.
+    pub fn with_crf(mut self, crf: Option<u8>) -> Self {
+        self.crf = crf;
+        self
+    }
+
+    /// Frames between keyframes, instead of the encoder's default.
+    /// Shorter intervals make seeking faster at the cost of larger files.
+    pub fn with_keyframe_interval(mut self, keyframe_interval: Option<u32>) -> Self {
+        self.keyframe_interval = keyframe_interval;
+        self
+    }
+
     pub fn build(
         self,
         output: &mut format::context::Output,
     ) -> Result<H264Encoder, H264EncoderError> {
         let input_config = &self.input_config;
-        let (codec, encoder_options) = get_codec_and_options(input_config, self.preset)
-            .ok_or(H264EncoderError::CodecNotFound)?;
-
-        let (format, converter) = if !codec
-            .video()
-            .unwrap()
-            .formats()
-            .unwrap()
-            .any(|f| f == input_config.pixel_format)
-        {
-            let format = ffmpeg::format::Pixel::NV12;
+        let (codec, mut encoder_options) = get_codec_and_options(
+            input_config,
+            self.preset,
+            self.codec,
+            self.keyframe_interval,
+        )
+        .ok_or(H264EncoderError::CodecNotFound)?;
+
+        let supports = |format: ffmpeg::format::Pixel| {
+            codec
+                .video()
+                .unwrap()
+                .formats()
+                .unwrap()
+                .any(|f| f == format)
+        };
+
+        let (format, mut converter) = if !supports(input_config.pixel_format) {
+            // AV1 encoders only take planar YUV
+            let format = if supports(ffmpeg::format::Pixel::NV12) {
+                ffmpeg::format::Pixel::NV12
+            } else {
+                ffmpeg::format::Pixel::YUV420P
+            };
             debug!(
                 "Converting from {:?} to {:?} for H264 encoding",
                 input_config.pixel_format, format
@@ -84,7 +159,7 @@ impl H264EncoderBuilder {
                     )
                     .map_err(|e| {
                         error!(
-                            "Failed to create converter from {:?} to NV12: {:?}",
+                            "Failed to create converter from {:?} to {format:?}: {:?}",
                             input_config.pixel_format, e
                         );
                         H264EncoderError::PixFmtNotSupported(input_config.pixel_format)
@@ -106,16 +181,43 @@ impl H264EncoderBuilder {
         encoder.set_time_base(input_config.frame_rate.invert());
         encoder.set_frame_rate(Some(input_config.frame_rate));
 
-        // let target_bitrate = compression.bitrate();
-        let bitrate = get_bitrate(
-            input_config.width,
-            input_config.height,
-            input_config.frame_rate.0 as f32 / input_config.frame_rate.1 as f32,
-            self.bpp,
-        );
+        if let Some(keyframe_interval) = self.keyframe_interval {
+            encoder.set_gop(keyframe_interval);
+        }
 
-        encoder.set_bit_rate(bitrate);
-        encoder.set_max_bit_rate(bitrate);
+        if let Some(converter) = &mut converter
+            && is_rgb(input_config.pixel_format)
+        {
+            // RGB frames are SDR BT.709, with any HDR sources tone mapped before they're
+            // rendered, so the output is tagged as such rather than left for players to guess
+            configure_bt709_output(converter);
+            encoder.set_colorspace(ffmpeg::color::Space::BT709);
+            encoder.set_color_range(ffmpeg::color::Range::MPEG);
+            unsafe {
+                let encoder = encoder.as_mut_ptr();
+                (*encoder).color_primaries = ffmpeg::ffi::AVColorPrimaries::AVCOL_PRI_BT709;
+                (*encoder).color_trc = ffmpeg::ffi::AVColorTransferCharacteristic::AVCOL_TRC_BT709;
+            }
+        }
+
+        if let Some(crf) = self.crf {
+            // x264 ignores crf if a bitrate is set
+            encoder_options.set("crf", &crf.to_string());
+        } else {
+            // let target_bitrate = compression.bitrate();
+            let bitrate = get_bitrate(
+                input_config.width,
+                input_config.height,
+                input_config.frame_rate.0 as f32 / input_config.frame_rate.1 as f32,
+                self.bpp,
+            );
+
+            encoder.set_bit_rate(bitrate);
+            // SVT-AV1 only accepts a maximum bitrate when encoding at a constant rate factor
+            if self.codec != VideoCodec::Av1 {
+                encoder.set_max_bit_rate(bitrate);
+            }
+        }
 
         let video_encoder = encoder.open_with(encoder_options)?;
 
@@ -125,6 +227,13 @@ impl H264EncoderBuilder {
         output_stream.set_rate(input_config.frame_rate);
         output_stream.set_parameters(&video_encoder);
 
+        if self.codec == VideoCodec::H265 {
+            // QuickTime only plays HEVC in mp4 if it's tagged hvc1 rather than hev1
+            unsafe {
+                (*output_stream.parameters().as_mut_ptr()).codec_tag = u32::from_le_bytes(*b"hvc1");
+            }
+        }
+
         Ok(H264Encoder {
             tag: self.name,
             encoder: video_encoder,
@@ -136,6 +245,40 @@ impl H264EncoderBuilder {
     }
 }
 
+fn is_rgb(pixel: Pixel) -> bool {
+    matches!(
+        pixel,
+        Pixel::RGBA
+            | Pixel::BGRA
+            | Pixel::ARGB
+            | Pixel::ABGR
+            | Pixel::RGB24
+            | Pixel::BGR24
+            | Pixel::RGBZ
+            | Pixel::BGRZ
+    )
+}
+
+/// Makes a converter from full range RGB produce limited range BT.709 YUV,
+/// instead of swscale's default of BT.601.
+fn configure_bt709_output(converter: &mut ffmpeg::software::scaling::Context) {
+    use ffmpeg::ffi::{SWS_CS_ITU709, sws_getCoefficients, sws_setColorspaceDetails};
+
+    unsafe {
+        let coefficients = sws_getCoefficients(SWS_CS_ITU709 as i32);
+        let _ = sws_setColorspaceDetails(
+            converter.as_mut_ptr(),
+            coefficients,
+            1,
+            coefficients,
+            0,
+            0,
+            1 << 16,
+            1 << 16,
+        );
+    }
+}
+
 pub struct H264Encoder {
     #[allow(unused)]
     tag: &'static str,
@@ -151,65 +294,81 @@ impl H264Encoder {
         H264EncoderBuilder::new(name, input_config)
     }
 
-    pub fn queue_frame(&mut self, frame: frame::Video, output: &mut format::context::Output) {
+    pub fn queue_frame(
+        &mut self,
+        frame: frame::Video,
+        output: &mut format::context::Output,
+    ) -> Result<(), ffmpeg::Error> {
+        self.send_frame(frame)?;
+        self.process_frame(output)
+    }
+
+    /// Sends a frame to the encoder without writing any packets,
+    /// allowing encoding to happen without holding on to the output.
+    /// Call [`Self::process_frame`] afterwards to write the resulting packets.
+    pub fn send_frame(&mut self, frame: frame::Video) -> Result<(), ffmpeg::Error> {
         let frame = if let Some(converter) = &mut self.converter {
             let mut new_frame = frame::Video::empty();
-            match converter.run(&frame, &mut new_frame) {
-                Ok(_) => {
-                    new_frame.set_pts(frame.pts());
-                    new_frame
-                }
-                Err(e) => {
-                    tracing::error!(
-                        "Failed to convert frame: {} from format {:?} to {:?}",
-                        e,
-                        frame.format(),
-                        converter.output().format
-                    );
-                    // Return early as we can't process this frame
-                    return;
-                }
+            if let Err(e) = converter.run(&frame, &mut new_frame) {
+                tracing::error!(
+                    "Failed to convert frame: {} from format {:?} to {:?}",
+                    e,
+                    frame.format(),
+                    converter.output().format
+                );
+                return Err(e);
             }
+            new_frame.set_pts(frame.pts());
+            new_frame
         } else {
             frame
         };
 
-        if let Err(e) = self.encoder.send_frame(&frame) {
-            tracing::error!("Failed to send frame to encoder: {:?}", e);
-            return;
-        }
-
-        self.process_frame(output);
+        self.encoder.send_frame(&frame)
     }
 
-    fn process_frame(&mut self, output: &mut format::context::Output) {
+    pub fn process_frame(
+        &mut self,
+        output: &mut format::context::Output,
+    ) -> Result<(), ffmpeg::Error> {
         while self.encoder.receive_packet(&mut self.packet).is_ok() {
             self.packet.set_stream(self.stream_index);
             self.packet.rescale_ts(
                 self.config.time_base,
                 output.stream(self.stream_index).unwrap().time_base(),
             );
-            if let Err(e) = self.packet.write_interleaved(output) {
-                tracing::error!("Failed to write packet: {:?}", e);
-                break;
-            }
+            self.packet.write_interleaved(output)?;
         }
+
+        Ok(())
     }
 
-    pub fn finish(&mut self, output: &mut format::context::Output) {
-        if let Err(e) = self.encoder.send_eof() {
-            tracing::error!("Failed to send EOF to encoder: {:?}", e);
-            return;
-        }
-        self.process_frame(output);
+    pub fn time_base(&self) -> ffmpeg::Rational {
+        self.config.time_base
+    }
+
+    pub fn finish(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+        self.encoder.send_eof()?;
+        self.process_frame(output)
     }
 }
 
+/// `keyframe_interval` defaults to two seconds of frames. It's set through the options so it
+/// isn't overridden by them when the encoder is opened.
 fn get_codec_and_options(
     config: &VideoInfo,
     preset: H264Preset,
+    codec: VideoCodec,
+    keyframe_interval: Option<u32>,
 ) -> Option<(Codec, Dictionary<'_>)> {
-    let encoder_name = {
+    let encoder_name = if codec == VideoCodec::Av1 {
+        AV1_ENCODERS
+            .iter()
+            .copied()
+            .find(|name| encoder::find_by_name(name).is_some())?
+    } else if codec == VideoCodec::H265 {
+        "libx265"
+    } else {
         // if cfg!(target_os = "macos") {
         //     "libx264"
         //     // looks terrible rn :(
@@ -221,30 +380,45 @@ fn get_codec_and_options(
         "libx264"
     };
 
+    let keyframe_interval = keyframe_interval
+        .unwrap_or(2 * config.frame_rate.numerator() as u32)
+        .to_string();
+
     if let Some(codec) = encoder::find_by_name(encoder_name) {
         let mut options = Dictionary::new();
 
         if encoder_name == "h264_videotoolbox" {
             options.set("realtime", "true");
-        } else if encoder_name == "libx264" {
-            let keyframe_interval_secs = 2;
-            let keyframe_interval = keyframe_interval_secs * config.frame_rate.numerator();
-            let keyframe_interval_str = keyframe_interval.to_string();
-
+        } else if encoder_name == "libx264" || encoder_name == "libx265" {
+            options.set("preset", preset.as_str());
+            if let H264Preset::Ultrafast = preset {
+                options.set("tune", "zerolatency");
+            }
+            options.set("vsync", "1");
+            // keyint_min can't be more than g
+            options.set("g", &keyframe_interval);
+            options.set("keyint_min", &keyframe_interval);
+        } else if AV1_ENCODERS.contains(&encoder_name) {
+            options.set("g", &keyframe_interval);
+
+            // each encoder has its own scale, with higher numbers being faster
+            let (option, [slow, medium, fast]) = match encoder_name {
+                "libsvtav1" => ("preset", ["4", "8", "12"]),
+                "libaom-av1" => ("cpu-used", ["4", "6", "8"]),
+                _ => ("speed", ["4", "6", "10"]),
+            };
             options.set(
-                "preset",
+                option,
                 match preset {
-                    H264Preset::Slow => "slow",
-                    H264Preset::Medium => "medium",
-                    H264Preset::Ultrafast => "ultrafast",
+                    H264Preset::Slow | H264Preset::Slower | H264Preset::Veryslow => slow,
+                    H264Preset::Medium | H264Preset::Fast | H264Preset::Faster => medium,
+                    H264Preset::Veryfast | H264Preset::Superfast | H264Preset::Ultrafast => fast,
                 },
             );
-            if let H264Preset::Ultrafast = preset {
-                options.set("tune", "zerolatency");
+
+            if encoder_name == "libaom-av1" {
+                options.set("row-mt", "1");
             }
-            options.set("vsync", "1");
-            options.set("g", &keyframe_interval_str);
-            options.set("keyint_min", &keyframe_interval_str);
         } else if encoder_name == "h264_mf" {
             options.set("hw_encoding", "true");
             options.set("scenario", "4");
@@ -257,10 +431,68 @@ fn get_codec_and_options(
     None
 }
 
-fn get_bitrate(width: u32, height: u32, frame_rate: f32, bpp: f32) -> usize {
+pub fn get_bitrate(width: u32, height: u32, frame_rate: f32, bpp: f32) -> usize {
     // higher frame rates don't really need double the bitrate lets be real
     let frame_rate_multiplier = (frame_rate - 30.0).max(0.0) * 0.6 + 30.0;
     let pixels_per_second = (width * height) as f32 * frame_rate_multiplier;
 
     (pixels_per_second * bpp) as usize
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cap_media_info::RawVideoFormat;
+    use ffmpeg::Rescale;
+
+    #[test]
+    fn keyframes_follow_the_configured_interval() {
+        ffmpeg::init().unwrap();
+
+        // not every FFmpeg build has x264
+        if encoder::find_by_name("libx264").is_none() {
+            return;
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "cap-enc-ffmpeg-keyframes-{}.mp4",
+            std::process::id()
+        ));
+        let mut video_info = VideoInfo::from_raw(RawVideoFormat::YUYV420, 64, 64, 30);
+        video_info.time_base = ffmpeg::Rational::new(1, 30);
+
+        let mut output = format::output(&path).unwrap();
+        let mut encoder = H264Encoder::builder("test_video", video_info)
+            .with_keyframe_interval(Some(10))
+            .build(&mut output)
+            .unwrap();
+        output.write_header().unwrap();
+
+        for i in 0..35 {
+            let mut frame =
+                frame::Video::new(video_info.pixel_format, video_info.width, video_info.height);
+            for plane in 0..frame.planes() {
+                frame.data_mut(plane).fill(128);
+            }
+            frame.set_pts(Some(i));
+            encoder.queue_frame(frame, &mut output).unwrap();
+        }
+        encoder.finish(&mut output).unwrap();
+        output.write_trailer().unwrap();
+
+        let mut input = format::input(&path).unwrap();
+        let stream = input.streams().best(ffmpeg::media::Type::Video).unwrap();
+        let (index, time_base) = (stream.index(), stream.time_base());
+
+        let mut keyframes = input
+            .packets()
+            .filter(|(stream, packet)| stream.index() == index && packet.is_key())
+            .filter_map(|(_, packet)| packet.pts())
+            .map(|pts| pts.rescale(time_base, (1, 30)))
+            .collect::<Vec<_>>();
+        keyframes.sort();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(keyframes, [0, 10, 20, 30]);
+    }
+}
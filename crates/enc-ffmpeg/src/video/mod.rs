@@ -1,2 +1,5 @@
 mod h264;
 pub use h264::*;
+
+mod vp9;
+pub use vp9::*;
@@ -0,0 +1,203 @@
+use cap_media_info::{Pixel, VideoInfo};
+use ffmpeg::{
+    Dictionary,
+    codec::{context, encoder},
+    format::{self},
+    frame,
+    threading::Config,
+};
+use tracing::{debug, error};
+
+use super::h264::get_bitrate;
+
+pub struct VP9EncoderBuilder {
+    name: &'static str,
+    bpp: f32,
+    input_config: VideoInfo,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum VP9EncoderError {
+    #[error("{0:?}")]
+    FFmpeg(#[from] ffmpeg::Error),
+    #[error("Codec not found")]
+    CodecNotFound,
+    #[error("Pixel format {0:?} not supported")]
+    PixFmtNotSupported(Pixel),
+}
+
+impl VP9EncoderBuilder {
+    pub const QUALITY_BPP: f32 = 0.3;
+
+    pub fn new(name: &'static str, input_config: VideoInfo) -> Self {
+        Self {
+            name,
+            input_config,
+            bpp: Self::QUALITY_BPP,
+        }
+    }
+
+    pub fn with_bpp(mut self, bpp: f32) -> Self {
+        self.bpp = bpp;
+        self
+    }
+
+    pub fn build(
+        self,
+        output: &mut format::context::Output,
+    ) -> Result<VP9Encoder, VP9EncoderError> {
+        let input_config = &self.input_config;
+        let codec = encoder::find_by_name("libvpx-vp9").ok_or(VP9EncoderError::CodecNotFound)?;
+
+        let (format, converter) = if !codec
+            .video()
+            .unwrap()
+            .formats()
+            .unwrap()
+            .any(|f| f == input_config.pixel_format)
+        {
+            let format = ffmpeg::format::Pixel::YUV420P;
+            debug!(
+                "Converting from {:?} to {:?} for VP9 encoding",
+                input_config.pixel_format, format
+            );
+            (
+                format,
+                Some(
+                    ffmpeg::software::converter(
+                        (input_config.width, input_config.height),
+                        input_config.pixel_format,
+                        format,
+                    )
+                    .map_err(|e| {
+                        error!(
+                            "Failed to create converter from {:?} to YUV420P: {:?}",
+                            input_config.pixel_format, e
+                        );
+                        VP9EncoderError::PixFmtNotSupported(input_config.pixel_format)
+                    })?,
+                ),
+            )
+        } else {
+            (input_config.pixel_format, None)
+        };
+
+        let mut encoder_ctx = context::Context::new_with_codec(codec);
+
+        encoder_ctx.set_threading(Config::count(4));
+        let mut encoder = encoder_ctx.encoder().video()?;
+
+        encoder.set_width(input_config.width);
+        encoder.set_height(input_config.height);
+        encoder.set_format(format);
+        encoder.set_time_base(input_config.frame_rate.invert());
+        encoder.set_frame_rate(Some(input_config.frame_rate));
+
+        let bitrate = get_bitrate(
+            input_config.width,
+            input_config.height,
+            input_config.frame_rate.0 as f32 / input_config.frame_rate.1 as f32,
+            self.bpp,
+        );
+
+        encoder.set_bit_rate(bitrate);
+        encoder.set_max_bit_rate(bitrate);
+
+        let keyframe_interval = (2 * input_config.frame_rate.numerator()).to_string();
+
+        let mut options = Dictionary::new();
+        // realtime is far too lossy for exports, and good with cpu-used 4 is
+        // still a reasonable speed
+        options.set("deadline", "good");
+        options.set("cpu-used", "4");
+        options.set("row-mt", "1");
+        options.set("g", &keyframe_interval);
+
+        let video_encoder = encoder.open_with(options)?;
+
+        let mut output_stream = output.add_stream(codec)?;
+        let stream_index = output_stream.index();
+        output_stream.set_time_base(input_config.frame_rate.invert());
+        output_stream.set_rate(input_config.frame_rate);
+        output_stream.set_parameters(&video_encoder);
+
+        Ok(VP9Encoder {
+            tag: self.name,
+            encoder: video_encoder,
+            stream_index,
+            config: self.input_config,
+            converter,
+            packet: ffmpeg::Packet::empty(),
+        })
+    }
+}
+
+pub struct VP9Encoder {
+    #[allow(unused)]
+    tag: &'static str,
+    encoder: encoder::Video,
+    config: VideoInfo,
+    converter: Option<ffmpeg::software::scaling::Context>,
+    stream_index: usize,
+    packet: ffmpeg::Packet,
+}
+
+impl VP9Encoder {
+    pub fn builder(name: &'static str, input_config: VideoInfo) -> VP9EncoderBuilder {
+        VP9EncoderBuilder::new(name, input_config)
+    }
+
+    pub fn queue_frame(&mut self, frame: frame::Video, output: &mut format::context::Output) {
+        let frame = if let Some(converter) = &mut self.converter {
+            let mut new_frame = frame::Video::empty();
+            match converter.run(&frame, &mut new_frame) {
+                Ok(_) => {
+                    new_frame.set_pts(frame.pts());
+                    new_frame
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to convert frame: {} from format {:?} to {:?}",
+                        e,
+                        frame.format(),
+                        converter.output().format
+                    );
+                    return;
+                }
+            }
+        } else {
+            frame
+        };
+
+        if let Err(e) = self.encoder.send_frame(&frame) {
+            error!("Failed to send frame to encoder: {:?}", e);
+            return;
+        }
+
+        self.process_frame(output);
+    }
+
+    fn process_frame(&mut self, output: &mut format::context::Output) {
+        while self.encoder.receive_packet(&mut self.packet).is_ok() {
+            self.packet.set_stream(self.stream_index);
+            self.packet.rescale_ts(
+                self.config.time_base,
+                output.stream(self.stream_index).unwrap().time_base(),
+            );
+            if let Err(e) = self.packet.write_interleaved(output) {
+                error!("Failed to write packet: {:?}", e);
+                break;
+            }
+        }
+    }
+
+    pub fn finish(&mut self, output: &mut format::context::Output) {
+        if let Err(e) = self.encoder.send_eof() {
+            error!("Failed to send EOF to encoder: {:?}", e);
+            return;
+        }
+        self.process_frame(output);
+    }
+}
+
+unsafe impl Send for VP9Encoder {}
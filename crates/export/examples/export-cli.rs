@@ -1,6 +1,9 @@
 use std::{fmt::Display, path::PathBuf};
 
-use cap_export::{ExporterBase, gif::GifExportSettings, mp4::Mp4ExportSettings};
+use cap_export::{
+    ExporterBase, apng::ApngExportSettings, gif::GifExportSettings, mp4::Mp4ExportSettings,
+    webm::WebMExportSettings,
+};
 use clap::{Parser, ValueEnum};
 
 #[derive(Parser, Debug)]
@@ -16,6 +19,8 @@ struct Cli {
 enum ExportFormat {
     MP4,
     GIF,
+    WEBM,
+    APNG,
 }
 
 impl Display for ExportFormat {
@@ -23,6 +28,8 @@ impl Display for ExportFormat {
         match self {
             Self::GIF => write!(f, "gif"),
             Self::MP4 => write!(f, "mp4"),
+            Self::WEBM => write!(f, "webm"),
+            Self::APNG => write!(f, "png"),
         }
     }
 }
@@ -38,7 +45,12 @@ async fn main() {
     let format = cli.format.unwrap_or_else(|| {
         inquire::Select::new(
             "Select export format",
-            vec![ExportFormat::MP4, ExportFormat::GIF],
+            vec![
+                ExportFormat::MP4,
+                ExportFormat::GIF,
+                ExportFormat::WEBM,
+                ExportFormat::APNG,
+            ],
         )
         .prompt()
         .unwrap()
@@ -51,20 +63,36 @@ async fn main() {
     match format {
         ExportFormat::GIF => {
             let settings: GifExportSettings = serde_json::from_str(&settings_str).unwrap();
-            let total_frames = base.total_frames(settings.fps);
             settings
                 .export(base, move |progress| {
-                    print!("Exporting frame {progress} of {total_frames}\r");
+                    print!("Exporting: {progress:?}\r");
                 })
                 .await
                 .unwrap();
         }
         ExportFormat::MP4 => {
             let settings: Mp4ExportSettings = serde_json::from_str(&settings_str).unwrap();
-            let total_frames = base.total_frames(settings.fps);
             settings
                 .export(base, move |progress| {
-                    print!("Exporting frame {progress} of {total_frames}\r");
+                    print!("Exporting: {progress:?}\r");
+                })
+                .await
+                .unwrap();
+        }
+        ExportFormat::WEBM => {
+            let settings: WebMExportSettings = serde_json::from_str(&settings_str).unwrap();
+            settings
+                .export(base, move |progress| {
+                    print!("Exporting: {progress:?}\r");
+                })
+                .await
+                .unwrap();
+        }
+        ExportFormat::APNG => {
+            let settings: ApngExportSettings = serde_json::from_str(&settings_str).unwrap();
+            settings
+                .export(base, move |progress| {
+                    print!("Exporting: {progress:?}\r");
                 })
                 .await
                 .unwrap();
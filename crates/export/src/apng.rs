@@ -0,0 +1,133 @@
+use cap_enc_ffmpeg::{ApngFile, MP4Input};
+use cap_media::filters::ChannelConverter;
+use cap_media_info::{RawVideoFormat, VideoInfo};
+use cap_project::XY;
+use cap_rendering::{FitMode, RenderedFrame};
+use futures::FutureExt;
+use serde::Deserialize;
+use specta::Type;
+use std::{path::PathBuf, sync::Arc};
+use tracing::trace;
+
+use crate::{
+    ExportError, ExportProgress, ExporterBase, FrameTiming,
+    mp4::AudioChannels,
+    pipeline::{RenderLoop, encode_frames, finish_encoding},
+    temp_output::TempOutput,
+};
+
+/// Animated PNG export, which keeps the alpha channel of rendered frames.
+///
+/// Frames are stored losslessly with full 8-bit alpha, so files are typically several
+/// times larger than a GIF of the same length, which has 1-bit transparency and a
+/// 256 colour palette. Prefer GIF when transparency isn't needed.
+#[derive(Deserialize, Clone, Copy, Debug, Type)]
+pub struct ApngExportSettings {
+    pub fps: u32,
+    pub resolution_base: XY<u32>,
+    /// Exports at exactly `resolution_base`, fitting frames to it this way,
+    /// instead of at the project's aspect ratio
+    #[serde(default)]
+    pub fit: Option<FitMode>,
+    /// Number of times the animation plays, with 0 looping forever
+    #[serde(default)]
+    pub loop_count: u32,
+}
+
+impl ApngExportSettings {
+    pub async fn export(
+        self,
+        mut base: ExporterBase,
+        on_progress: impl Fn(ExportProgress) + Send + Sync + 'static,
+    ) -> Result<PathBuf, ExportError> {
+        let cancel_token = base.cancel_token.clone();
+        let on_progress = Arc::new(on_progress);
+
+        let (tx_image_data, video_rx) = tokio::sync::mpsc::channel::<(RenderedFrame, u32)>(4);
+        let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel::<MP4Input>(4);
+
+        let fps = self.fps;
+        let frame_range = base.frame_range(fps)?;
+        let total_frames = frame_range.len() as u32;
+        let start_time = frame_range.start as f64 / fps as f64;
+
+        let output_size = base.output_size(self.resolution_base, self.fit);
+        let fitter = base.frame_fitter(self.resolution_base, self.fit)?;
+
+        let output = TempOutput::new(base.output_path.with_extension("png"));
+        let output_path = output.path().to_path_buf();
+
+        std::fs::create_dir_all(output_path.parent().unwrap())?;
+
+        trace!("Creating APNG encoder at path '{}'", output_path.display());
+
+        let video_info =
+            VideoInfo::from_raw(RawVideoFormat::Rgba, output_size.0, output_size.1, fps);
+
+        let mut encoder = ApngFile::init(output_path.clone(), video_info, self.loop_count)
+            .map_err(|e| ExportError::Other(format!("Failed to create APNG encoder: {e}")))?;
+
+        let encoder_thread = tokio::task::spawn_blocking({
+            let output_path = output_path.clone();
+            let on_progress = on_progress.clone();
+            let metrics = base.metrics.clone();
+            move || {
+                let encoded_frames = encode_frames(
+                    &mut encoder,
+                    frame_rx,
+                    total_frames,
+                    &metrics,
+                    &*on_progress,
+                    |_, video| Ok(vec![video]),
+                )?;
+
+                finish_encoding(&mut encoder, encoded_frames, &metrics, &*on_progress)?;
+
+                Ok::<_, String>(output_path)
+            }
+        })
+        .then(|r| async { r.map_err(|e| e.to_string()).and_then(|v| v) });
+
+        let render_loop = RenderLoop {
+            fps,
+            start_time,
+            total_frames,
+            timing: FrameTiming::new(fps, base.deterministic_timing),
+            video_info,
+            fitter,
+            redaction: base.redaction.take(),
+            // APNGs are silent, so no audio is rendered or converted
+            audio_renderer: None,
+            channel_converter: ChannelConverter::new(AudioChannels::default().layout()),
+            project: base.project_config.clone(),
+            cancel_token: cancel_token.clone(),
+            metrics: base.metrics.clone(),
+        };
+
+        let render_task = tokio::spawn(async move {
+            render_loop
+                .run(video_rx, frame_tx, &*on_progress, |_, _| {}, |_| {})
+                .await
+        })
+        .then(|r| async { r.map_err(|e| e.to_string()).and_then(|v| v) });
+
+        let render_video_task = base
+            .render_to_channel(fps, self.resolution_base, frame_range, tx_image_data)
+            .then(|v| async { v.map_err(|e| e.to_string()) });
+
+        let (encoded, rendered, render_task) =
+            tokio::join!(encoder_thread, render_video_task, render_task);
+
+        if cancel_token.is_cancelled() {
+            return Err(ExportError::Cancelled);
+        }
+
+        rendered.map_err(ExportError::Other)?;
+        render_task.map_err(ExportError::Other)?;
+        encoded.map_err(ExportError::Other)?;
+
+        let output_path = output.commit()?;
+        base.finish_metrics(&output_path);
+        Ok(output_path)
+    }
+}
@@ -0,0 +1,152 @@
+use cap_editor::{AudioRenderer, get_audio_segments};
+use cap_enc_ffmpeg::{AudioFile, AudioFileCodec, AudioFileEncoder, AudioFileEncoderError};
+use cap_media::{MediaError, PipelineStage};
+use cap_media_info::ChannelLayout;
+use serde::Deserialize;
+use specta::Type;
+use std::{path::PathBuf, time::Instant};
+use tracing::info;
+
+use crate::{
+    ExportError, ExportProgress, ExporterBase, eta::EtaEstimator, mp4::AUDIO_BITRATE,
+    temp_output::TempOutput,
+};
+
+#[derive(Deserialize, Type, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioExportFormat {
+    Wav,
+    /// Only available when FFmpeg is built with libmp3lame
+    Mp3,
+    /// AAC in an .m4a file
+    Aac,
+    Flac,
+}
+
+impl From<AudioExportFormat> for AudioFileCodec {
+    fn from(format: AudioExportFormat) -> Self {
+        match format {
+            AudioExportFormat::Wav => AudioFileCodec::Wav,
+            AudioExportFormat::Mp3 => AudioFileCodec::Mp3,
+            AudioExportFormat::Aac => AudioFileCodec::Aac,
+            AudioExportFormat::Flac => AudioFileCodec::Flac,
+        }
+    }
+}
+
+/// Export of only the project's audio, mixed the same way as in video exports.
+#[derive(Deserialize, Type, Clone, Copy, Debug)]
+pub struct AudioExportSettings {
+    pub format: AudioExportFormat,
+    /// Resamples the audio to the closest rate the format supports.
+    /// The project's rate of 48kHz is kept if unset.
+    #[serde(default)]
+    pub sample_rate: Option<u32>,
+    /// In bits per second, only used by MP3 and AAC.
+    /// Defaults to the bitrate of audio in mp4 exports.
+    #[serde(default)]
+    pub bitrate: Option<u32>,
+}
+
+impl AudioExportSettings {
+    /// Audio is rendered in chunks of one frame at this rate, which progress is counted in.
+    pub const PROGRESS_FPS: u32 = 30;
+
+    pub async fn export(
+        self,
+        base: ExporterBase,
+        on_progress: impl Fn(ExportProgress) + Send + Sync + 'static,
+    ) -> Result<PathBuf, ExportError> {
+        let audio_segments = get_audio_segments(&base.segments);
+        if audio_segments.iter().all(|s| s.tracks.is_empty()) {
+            return Err(MediaError::MissingMedia("audio").into());
+        }
+
+        let fps = Self::PROGRESS_FPS;
+        let frame_range = base.frame_range(fps)?;
+        let total_frames = frame_range.len() as u32;
+        let start_time = frame_range.start as f64 / fps as f64;
+        info!("Expected to render {total_frames} chunks of audio");
+
+        let codec = AudioFileCodec::from(self.format);
+        let output = TempOutput::new(base.output_path.with_extension(codec.extension()));
+        let output_path = output.path().to_path_buf();
+
+        std::fs::create_dir_all(output_path.parent().unwrap())?;
+
+        let mut audio_renderer =
+            AudioRenderer::new(audio_segments).with_time_stretch(base.time_stretch);
+        let bit_rate = self.bitrate.map_or(AUDIO_BITRATE, |b| b as usize);
+        let sample_rate = self.sample_rate;
+
+        let encode = tokio::task::spawn_blocking({
+            let output_path = output_path.clone();
+            let project = base.project_config.clone();
+            let cancel_token = base.cancel_token.clone();
+            let metrics = base.metrics.clone();
+            move || {
+                let mut file = AudioFile::init(output_path, |o| {
+                    AudioFileEncoder::init(AudioRenderer::info(), codec, sample_rate, bit_rate, o)
+                })
+                .map_err(|e| match e {
+                    AudioFileEncoderError::CodecNotFound(_) => {
+                        ExportError::Media(MediaError::MissingCodec(codec.encoder_name()))
+                    }
+                    e => ExportError::FFmpeg(e.to_string()),
+                })?;
+
+                let samples_per_frame = (AudioRenderer::SAMPLE_RATE / fps) as usize;
+                let mut eta = EtaEstimator::new(total_frames);
+
+                audio_renderer.set_playhead(start_time, &project);
+
+                for done in 1..=total_frames {
+                    if cancel_token.is_cancelled() {
+                        return Err(ExportError::Cancelled);
+                    }
+
+                    // segments without audio are exported as silence, to keep the timing
+                    let frame = metrics
+                        .time(PipelineStage::Render, || {
+                            audio_renderer.render_frame(samples_per_frame, &project)
+                        })
+                        .unwrap_or_else(|| silence(samples_per_frame));
+
+                    metrics
+                        .time(PipelineStage::Encode, || file.queue_frame(&frame))
+                        .map_err(|e| ExportError::FFmpeg(e.to_string()))?;
+
+                    on_progress(ExportProgress::Rendering {
+                        done,
+                        total: total_frames,
+                        eta: eta.update(done, Instant::now()),
+                    });
+                }
+
+                metrics.add_frames(total_frames as u64);
+
+                on_progress(ExportProgress::Finalizing);
+                metrics
+                    .time(PipelineStage::Mux, || file.finish())
+                    .map_err(|e| ExportError::FFmpeg(e.to_string()))
+            }
+        });
+
+        encode.await??;
+
+        if base.cancel_token.is_cancelled() {
+            return Err(ExportError::Cancelled);
+        }
+
+        let output_path = output.commit()?;
+        base.finish_metrics(&output_path);
+        Ok(output_path)
+    }
+}
+
+fn silence(samples: usize) -> ffmpeg::frame::Audio {
+    let mut frame =
+        ffmpeg::frame::Audio::new(AudioRenderer::SAMPLE_FORMAT, samples, ChannelLayout::STEREO);
+    frame.set_rate(AudioRenderer::SAMPLE_RATE);
+    frame.data_mut(0).fill(0);
+    frame
+}
@@ -0,0 +1,89 @@
+use std::time::{Duration, Instant};
+
+// the render rate is measured over windows of at least this long, as frames come out of
+// the renderer in bursts and per-frame rates swing wildly
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+// how much each new window moves the smoothed rate towards its own
+const SMOOTHING: f64 = 0.2;
+// windows to measure before estimating, as the first frames are slowed down by warming up
+const MIN_SAMPLES: u32 = 4;
+
+/// Estimates how long an export has left from how quickly its frames are being rendered,
+/// using an exponential moving average of the rate so the estimate doesn't jitter.
+pub(crate) struct EtaEstimator {
+    total: u32,
+    last_sample: Option<(Instant, u32)>,
+    fps: f64,
+    samples: u32,
+}
+
+impl EtaEstimator {
+    pub(crate) fn new(total: u32) -> Self {
+        Self {
+            total,
+            last_sample: None,
+            fps: 0.0,
+            samples: 0,
+        }
+    }
+
+    /// Records that `done` frames have been rendered by `now`, returning the time left,
+    /// or `None` until enough of the export has been measured.
+    pub(crate) fn update(&mut self, done: u32, now: Instant) -> Option<Duration> {
+        let (sampled_at, sampled_done) = *self.last_sample.get_or_insert((now, done));
+
+        let elapsed = now.saturating_duration_since(sampled_at);
+        if elapsed >= SAMPLE_INTERVAL {
+            let fps = done.saturating_sub(sampled_done) as f64 / elapsed.as_secs_f64();
+            self.fps = match self.samples {
+                0 => fps,
+                _ => self.fps + SMOOTHING * (fps - self.fps),
+            };
+            self.samples += 1;
+            self.last_sample = Some((now, done));
+        }
+
+        if self.samples < MIN_SAMPLES || self.fps <= 0.0 {
+            return None;
+        }
+
+        Some(Duration::from_secs_f64(
+            self.total.saturating_sub(done) as f64 / self.fps,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn waits_for_enough_samples() {
+        let start = Instant::now();
+        let mut eta = EtaEstimator::new(1000);
+
+        for i in 0..MIN_SAMPLES {
+            assert_eq!(eta.update(i * 25, start + SAMPLE_INTERVAL * i), None);
+        }
+
+        let eta = eta.update(100, start + SAMPLE_INTERVAL * 4).unwrap();
+        // 100 frames a second with 900 to go
+        assert!((eta.as_secs_f64() - 9.0).abs() < 0.01, "{eta:?}");
+    }
+
+    #[test]
+    fn smooths_changes_in_rate() {
+        let start = Instant::now();
+        let mut eta = EtaEstimator::new(10_000);
+
+        for i in 0..=MIN_SAMPLES {
+            eta.update(i * 25, start + SAMPLE_INTERVAL * i);
+        }
+
+        // one window at 400 frames a second, after rendering at 100
+        let eta = eta.update(200, start + SAMPLE_INTERVAL * 5).unwrap();
+        let fps = 100.0 + SMOOTHING * 300.0;
+
+        assert!((eta.as_secs_f64() - 9800.0 / fps).abs() < 0.01, "{eta:?}");
+    }
+}
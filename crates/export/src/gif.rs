@@ -1,12 +1,21 @@
+use cap_enc_ffmpeg::{GifDither, GifFile, GifPalette, MP4Input};
+use cap_media::filters::ChannelConverter;
+use cap_media_info::{RawVideoFormat, VideoInfo};
 use cap_project::XY;
-use cap_rendering::{ProjectUniforms, RenderSegment, RenderedFrame};
+use cap_rendering::{FitMode, RenderedFrame};
+use ffmpeg::frame;
 use futures::FutureExt;
 use serde::Deserialize;
 use specta::Type;
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 use tracing::trace;
 
-use crate::{ExportError, ExporterBase};
+use crate::{
+    ExportError, ExportProgress, ExporterBase, FrameTiming,
+    mp4::AudioChannels,
+    pipeline::{ExportMuxer, RenderLoop, encode_frames, finish_encoding},
+    temp_output::TempOutput,
+};
 
 #[derive(Deserialize, Clone, Copy, Debug, Type)]
 pub struct GifQuality {
@@ -16,11 +25,39 @@ pub struct GifQuality {
     pub fast: Option<bool>,
 }
 
+#[derive(Deserialize, Clone, Copy, Debug, Type, PartialEq, Eq)]
+pub enum DitherMode {
+    None,
+    Bayer,
+    FloydSteinberg,
+}
+
+impl From<DitherMode> for GifDither {
+    fn from(mode: DitherMode) -> Self {
+        match mode {
+            DitherMode::None => GifDither::None,
+            DitherMode::Bayer => GifDither::Bayer,
+            DitherMode::FloydSteinberg => GifDither::FloydSteinberg,
+        }
+    }
+}
+
 #[derive(Deserialize, Clone, Copy, Debug, Type)]
 pub struct GifExportSettings {
     pub fps: u32,
     pub resolution_base: XY<u32>,
+    /// Exports at exactly `resolution_base`, fitting frames to it this way,
+    /// instead of at the project's aspect ratio
+    #[serde(default)]
+    pub fit: Option<FitMode>,
     pub quality: Option<GifQuality>,
+    /// Setting either `dither` or `max_colors` quantizes frames with FFmpeg's
+    /// `palettegen`/`paletteuse` instead of gifski. `quality` doesn't apply in that case.
+    #[serde(default)]
+    pub dither: Option<DitherMode>,
+    /// Colours in the palette, from 4 to 256 (default: 256)
+    #[serde(default)]
+    pub max_colors: Option<u16>,
 }
 
 impl Default for GifExportSettings {
@@ -28,28 +65,68 @@ impl Default for GifExportSettings {
         Self {
             fps: 30,
             resolution_base: XY { x: 1920, y: 1080 },
+            fit: None,
             quality: None,
+            dither: None,
+            max_colors: None,
+        }
+    }
+}
+
+/// The encoder the GIF is written with, depending on the palette settings.
+enum GifWriter {
+    /// Taken once finished, as finishing consumes gifski's encoder
+    Gifski(Option<cap_enc_gif::GifEncoderWrapper>),
+    Palette(GifFile),
+}
+
+impl ExportMuxer for GifWriter {
+    fn queue_video_frame(&mut self, frame: frame::Video) -> Result<(), String> {
+        match self {
+            Self::Gifski(Some(encoder)) => encoder
+                .add_frame(frame.data(0), frame.stride(0))
+                .map_err(|e| e.to_string()),
+            Self::Gifski(None) => Ok(()),
+            Self::Palette(encoder) => encoder.queue_frame(frame).map_err(|e| e.to_string()),
+        }
+        .map_err(|e| format!("Failed to add frame to GIF: {e}"))
+    }
+
+    // GIFs are silent
+    fn queue_audio_frame(&mut self, _: frame::Audio) {}
+
+    fn finish(&mut self) -> Result<(), String> {
+        match self {
+            Self::Gifski(encoder) => encoder.take().map_or(Ok(()), |encoder| {
+                encoder.finish().map_err(|e| e.to_string())
+            }),
+            Self::Palette(encoder) => encoder.finish().map_err(|e| e.to_string()),
         }
+        .map_err(|e| format!("Failed to finish GIF: {e}"))
     }
 }
 
 impl GifExportSettings {
     pub async fn export(
         self,
-        base: ExporterBase,
-        mut on_progress: impl FnMut(u32) + Send + 'static,
-    ) -> Result<PathBuf, String> {
-        let meta = &base.studio_meta;
+        mut base: ExporterBase,
+        on_progress: impl Fn(ExportProgress) + Send + Sync + 'static,
+    ) -> Result<PathBuf, ExportError> {
+        let cancel_token = base.cancel_token.clone();
+        let on_progress = Arc::new(on_progress);
 
-        let (tx_image_data, mut video_rx) = tokio::sync::mpsc::channel::<(RenderedFrame, u32)>(4);
+        let (tx_image_data, video_rx) = tokio::sync::mpsc::channel::<(RenderedFrame, u32)>(4);
+        let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel::<MP4Input>(4);
 
         let fps = self.fps;
+        let frame_range = base.frame_range(fps)?;
+        let total_frames = frame_range.len() as u32;
+        let start_time = frame_range.start as f64 / fps as f64;
 
-        let output_size = ProjectUniforms::get_output_size(
-            &base.render_constants.options,
-            &base.project_config,
-            self.resolution_base,
-        );
+        let output_size = base.output_size(self.resolution_base, self.fit);
+        let fitter = base.frame_fitter(self.resolution_base, self.fit)?;
+        let video_info =
+            VideoInfo::from_raw(RawVideoFormat::Rgba, output_size.0, output_size.1, fps);
 
         // Ensure the output path has .gif extension
         let mut gif_output_path = base.output_path.clone();
@@ -57,81 +134,111 @@ impl GifExportSettings {
             gif_output_path.set_extension("gif");
         }
 
-        std::fs::create_dir_all(gif_output_path.parent().unwrap()).map_err(|e| e.to_string())?;
+        std::fs::create_dir_all(gif_output_path.parent().unwrap())?;
+
+        let output = TempOutput::new(gif_output_path);
+        let gif_output_path = output.path().to_path_buf();
 
         trace!(
             "Creating GIF encoder at path '{}'",
             gif_output_path.display()
         );
 
-        // Create GIF encoder with quality settings
-        let quality = self
-            .quality
-            .map(|q| cap_enc_gif::GifQuality {
-                quality: q.quality.unwrap_or(90),
-                fast: q.fast.unwrap_or(false),
-            })
-            .unwrap_or_default();
-
-        let mut gif_encoder = cap_enc_gif::GifEncoderWrapper::new_with_quality(
-            &gif_output_path,
-            output_size.0,
-            output_size.1,
-            fps,
-            quality,
-        )
-        .map_err(|e| format!("Failed to create GIF encoder: {e}"))?;
-
-        let encoder_thread = tokio::task::spawn_blocking(move || {
-            let mut frame_count = 0;
-
-            while let Some((frame, _frame_number)) = video_rx.blocking_recv() {
-                (on_progress)(frame_count);
+        let mut gif_encoder = if self.dither.is_some() || self.max_colors.is_some() {
+            let palette = GifPalette {
+                max_colors: self.max_colors.unwrap_or(GifPalette::default().max_colors),
+                dither: self.dither.unwrap_or(DitherMode::FloydSteinberg).into(),
+            };
+
+            GifWriter::Palette(
+                GifFile::init(gif_output_path.clone(), video_info, palette).map_err(|e| {
+                    ExportError::Other(format!("Failed to create GIF encoder: {e}"))
+                })?,
+            )
+        } else {
+            // Create GIF encoder with quality settings
+            let quality = self
+                .quality
+                .map(|q| cap_enc_gif::GifQuality {
+                    quality: q.quality.unwrap_or(90),
+                    fast: q.fast.unwrap_or(false),
+                })
+                .unwrap_or_default();
+
+            GifWriter::Gifski(Some(
+                cap_enc_gif::GifEncoderWrapper::new_with_quality(
+                    &gif_output_path,
+                    output_size.0,
+                    output_size.1,
+                    fps,
+                    quality,
+                )
+                .map_err(|e| ExportError::Other(format!("Failed to create GIF encoder: {e}")))?,
+            ))
+        };
+
+        let encoder_thread = tokio::task::spawn_blocking({
+            let gif_output_path = gif_output_path.clone();
+            let on_progress = on_progress.clone();
+            let metrics = base.metrics.clone();
+            move || {
+                let encoded_frames = encode_frames(
+                    &mut gif_encoder,
+                    frame_rx,
+                    total_frames,
+                    &metrics,
+                    &*on_progress,
+                    |_, video| Ok(vec![video]),
+                )?;
+
+                finish_encoding(&mut gif_encoder, encoded_frames, &metrics, &*on_progress)?;
+
+                Ok::<_, String>(gif_output_path)
+            }
+        })
+        .then(|r| async { r.map_err(|e| e.to_string()).and_then(|v| v) });
 
-                if let Err(e) =
-                    gif_encoder.add_frame(&frame.data, frame.padded_bytes_per_row as usize)
-                {
-                    return Err(ExportError::Other(format!(
-                        "Failed to add frame to GIF: {e}"
-                    )));
-                }
+        let render_loop = RenderLoop {
+            fps,
+            start_time,
+            total_frames,
+            // GIF frame timestamps have to start from 0
+            timing: FrameTiming::new(fps, true),
+            video_info,
+            fitter,
+            redaction: base.redaction.take(),
+            // GIFs are silent, so no audio is rendered or converted
+            audio_renderer: None,
+            channel_converter: ChannelConverter::new(AudioChannels::default().layout()),
+            project: base.project_config.clone(),
+            cancel_token: cancel_token.clone(),
+            metrics: base.metrics.clone(),
+        };
+
+        let render_task = tokio::spawn(async move {
+            render_loop
+                .run(video_rx, frame_tx, &*on_progress, |_, _| {}, |_| {})
+                .await
+        })
+        .then(|r| async { r.map_err(|e| e.to_string()).and_then(|v| v) });
 
-                frame_count += 1;
-            }
+        let render_video_task = base
+            .render_to_channel(fps, self.resolution_base, frame_range, tx_image_data)
+            .then(|v| async { v.map_err(|e| e.to_string()) });
 
-            if let Err(e) = gif_encoder.finish() {
-                return Err(ExportError::Other(format!("Failed to finish GIF: {e}")));
-            }
+        let (encoded, rendered, render_task) =
+            tokio::join!(encoder_thread, render_video_task, render_task);
 
-            Ok(gif_output_path)
-        })
-        .then(|f| async {
-            f.map_err(|e| e.to_string())
-                .and_then(|v| v.map_err(|v| v.to_string()))
-        });
-
-        let render_video_task = cap_rendering::render_video_to_channel(
-            &base.render_constants,
-            &base.project_config,
-            tx_image_data,
-            &base.recording_meta,
-            meta,
-            base.segments
-                .iter()
-                .map(|s| RenderSegment {
-                    cursor: s.cursor.clone(),
-                    decoders: s.decoders.clone(),
-                })
-                .collect(),
-            fps,
-            self.resolution_base,
-            &base.recordings,
-        )
-        .then(|f| async { f.map_err(|v| v.to_string()) });
+        if cancel_token.is_cancelled() {
+            return Err(ExportError::Cancelled);
+        }
 
-        let (output_path, _) =
-            tokio::try_join!(encoder_thread, render_video_task).map_err(|e| e.to_string())?;
+        rendered.map_err(ExportError::Other)?;
+        render_task.map_err(ExportError::Other)?;
+        encoded.map_err(ExportError::Other)?;
 
+        let output_path = output.commit()?;
+        base.finish_metrics(&output_path);
         Ok(output_path)
     }
 }
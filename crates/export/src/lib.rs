@@ -1,11 +1,120 @@
+pub mod apng;
+pub mod audio;
 pub mod gif;
 pub mod mp4;
+pub mod webm;
 
+mod eta;
+mod pipeline;
+mod temp_output;
+
+use cap_audio::TimeStretch;
 use cap_editor::Segment;
-use cap_project::{ProjectConfiguration, RecordingMeta, StudioRecordingMeta};
-use cap_rendering::{ProjectRecordingsMeta, RenderVideoConstants};
-use std::{path::PathBuf, sync::Arc};
-use tracing::error;
+use cap_media::{
+    MediaError, PipelineMetrics,
+    encoders::ImageMetadata,
+    filters::{InputEvent, InputOverlayStyle, RedactionFilter, SubtitleTrack, WatermarkFilter},
+};
+use cap_project::{
+    ProjectConfiguration, RecordingMeta, SpeedRamp, SpeedRampError, StereoMode,
+    StudioRecordingMeta, XY,
+};
+use cap_rendering::{
+    FitMode, FrameFitter, ProjectRecordingsMeta, ProjectUniforms, RenderSegment,
+    RenderVideoConstants, RenderedFrame, Rotation,
+};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Upper bound on the default number of render threads, since each one
+/// holds its own set of decoders.
+pub const MAX_RENDER_THREADS: usize = 4;
+
+/// Frames are split between render threads in blocks of this many frames,
+/// so each decoder mostly reads forward through its block.
+const RENDER_BLOCK_FRAMES: u32 = 15;
+
+fn yes() -> bool {
+    true
+}
+
+fn serialize_secs<S: serde::Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    duration.map(|d| d.as_secs_f64()).serialize(serializer)
+}
+
+/// Reported through the `on_progress` callback of each format's `export`.
+#[derive(Serialize, Type, Clone, Copy, Debug)]
+#[serde(tag = "type")]
+pub enum ExportProgress {
+    /// `done` of `total` frames have been rendered, with an estimate of the time left
+    /// once the render rate has settled. The estimate is sent as seconds.
+    Rendering {
+        done: u32,
+        total: u32,
+        #[serde(serialize_with = "serialize_secs")]
+        #[specta(type = Option<f64>)]
+        eta: Option<Duration>,
+    },
+    /// `done` of `total` frames have been passed to the encoder.
+    Encoding { done: u32, total: u32 },
+    /// All frames have been encoded and the output file is being written out.
+    Finalizing,
+}
+
+/// Tags written into exported files, to make them easier to organize.
+/// The creation time and a `Cap` encoder tag are always written.
+#[derive(Deserialize, Type, Clone, Debug, Default)]
+pub struct ExportMetadata {
+    pub title: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl ExportMetadata {
+    pub(crate) const ENCODER: &'static str = "Cap";
+
+    /// Tags for ffmpeg's muxers
+    pub(crate) fn container_tags(&self) -> ffmpeg::Dictionary<'static> {
+        // parsed by the muxer when it writes the header
+        self.container_tags_at("now")
+    }
+
+    /// Tags for ffmpeg's muxers, created at `creation_time`
+    pub(crate) fn container_tags_at(&self, creation_time: &str) -> ffmpeg::Dictionary<'static> {
+        let mut tags = ffmpeg::Dictionary::new();
+        tags.set("creation_time", creation_time);
+        tags.set("encoder", Self::ENCODER);
+
+        if let Some(title) = &self.title {
+            tags.set("title", title);
+        }
+        if let Some(comment) = &self.comment {
+            tags.set("comment", comment);
+        }
+
+        tags
+    }
+
+    /// EXIF tags for still images, which only have room for one description
+    pub(crate) fn image_metadata(&self) -> ImageMetadata {
+        ImageMetadata {
+            software: Some(Self::ENCODER.to_string()),
+            created_at: Some(SystemTime::now()),
+            description: self.title.clone().or_else(|| self.comment.clone()),
+        }
+    }
+}
 
 #[derive(thiserror::Error, Debug)]
 pub enum ExportError {
@@ -29,6 +138,9 @@ pub enum ExportError {
 
     #[error("Exporting timed out")]
     Timeout(#[from] tokio::time::error::Elapsed),
+
+    #[error("Export was cancelled")]
+    Cancelled,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -47,12 +159,25 @@ pub enum ExporterBuildError {
     MediaLoad(String),
     #[error("IO error at path '{0}': {1}")]
     IO(PathBuf, std::io::Error),
+    #[error("Invalid speed ramps: {0}")]
+    SpeedRamps(#[from] SpeedRampError),
 }
 
 pub struct ExporterBuilder {
     project_path: PathBuf,
     config: Option<ProjectConfiguration>,
     output_path: Option<PathBuf>,
+    cancel_token: Option<CancellationToken>,
+    time_range: Option<Range<f64>>,
+    subtitles: Option<SubtitleTrack>,
+    watermark: Option<WatermarkFilter>,
+    redaction: Option<RedactionFilter>,
+    input_overlay: Option<(Vec<InputEvent>, InputOverlayStyle)>,
+    speed_ramps: Vec<SpeedRamp>,
+    time_stretch: TimeStretch,
+    render_threads: Option<usize>,
+    metrics: Option<PipelineMetrics>,
+    deterministic_timing: bool,
 }
 
 impl ExporterBuilder {
@@ -61,17 +186,98 @@ impl ExporterBuilder {
         self
     }
 
+    /// Cancelling the token stops the export after the frame currently being rendered,
+    /// deletes the partial output and makes `export` return [`ExportError::Cancelled`].
+    pub fn with_cancel_token(mut self, cancel_token: CancellationToken) -> Self {
+        self.cancel_token = Some(cancel_token);
+        self
+    }
+
+    /// Only export the part of the timeline between these times, in seconds.
+    /// The end is clamped to the project's duration.
+    pub fn with_time_range(mut self, time_range: Range<f64>) -> Self {
+        self.time_range = Some(time_range);
+        self
+    }
+
+    /// Burns the subtitles into the exported video. Cue times are relative to the
+    /// start of the timeline. Only applies to mp4 exports.
+    pub fn with_subtitles(mut self, subtitles: SubtitleTrack) -> Self {
+        self.subtitles = Some(subtitles);
+        self
+    }
+
+    /// Overlays a logo onto every frame of the exported video, underneath any subtitles.
+    /// Only applies to mp4 exports.
+    pub fn with_watermark(mut self, watermark: WatermarkFilter) -> Self {
+        self.watermark = Some(watermark);
+        self
+    }
+
+    /// Blurs or fills regions of the exported video, before any watermark or subtitles
+    /// are drawn. Region times are relative to the start of the timeline.
+    pub fn with_redaction(mut self, redaction: RedactionFilter) -> Self {
+        self.redaction = Some(redaction);
+        self
+    }
+
+    /// Shows clicks and keystrokes from the recording on the exported video, underneath
+    /// any subtitles. Event times are relative to the start of the timeline.
+    /// Only applies to mp4 exports.
+    pub fn with_input_overlay(mut self, events: Vec<InputEvent>, style: InputOverlayStyle) -> Self {
+        self.input_overlay = Some((events, style));
+        self
+    }
+
+    /// Plays parts of the timeline faster or slower, which changes the length of the export.
+    /// Ramps must be sorted and not overlap, and are applied before the time range.
+    pub fn with_speed_ramps(mut self, speed_ramps: Vec<SpeedRamp>) -> Self {
+        self.speed_ramps = speed_ramps;
+        self
+    }
+
+    /// How audio is fitted to parts of the timeline that play faster or slower,
+    /// which defaults to changing its pitch along with its speed.
+    pub fn with_time_stretch(mut self, time_stretch: TimeStretch) -> Self {
+        self.time_stretch = time_stretch;
+        self
+    }
+
+    /// Number of threads frames are rendered on, each with its own decoders.
+    /// Defaults to the available parallelism, up to [`MAX_RENDER_THREADS`].
+    pub fn with_render_threads(mut self, render_threads: usize) -> Self {
+        self.render_threads = Some(render_threads.max(1));
+        self
+    }
+
+    /// Records how long each stage of the export takes into `metrics`,
+    /// which can be summarized once `export` returns.
+    pub fn with_metrics(mut self, metrics: PipelineMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Times frames by their position in the export rather than on the timeline, and
+    /// stamps files with a fixed creation time instead of the current one, so exporting
+    /// the same project twice gives identical timestamps. Meant for regression tests.
+    /// Only applies to mp4 exports, which are always re-encoded with it.
+    pub fn with_deterministic_timing(mut self, deterministic_timing: bool) -> Self {
+        self.deterministic_timing = deterministic_timing;
+        self
+    }
+
     pub async fn build(self) -> Result<ExporterBase, ExporterBuildError> {
         type Error = ExporterBuildError;
 
-        let project_config = serde_json::from_reader(
+        let project_config: ProjectConfiguration = serde_json::from_reader(
             std::fs::File::open(self.project_path.join("project-config.json"))
                 .map_err(|v| Error::ConfigLoad(v.into()))?,
         )
         .map_err(|v| Error::ConfigLoad(v.into()))?;
 
-        let recording_meta =
-            RecordingMeta::load_for_project(&self.project_path).map_err(Error::MetaLoad)?;
+        // checks the recording's files exist, so missing media fails here rather than in a decoder
+        let recording_meta = RecordingMeta::load_validated(&self.project_path)
+            .map_err(|e| Error::MetaLoad(e.into()))?;
         let studio_meta = recording_meta
             .studio_meta()
             .ok_or(Error::NotStudioRecording)?;
@@ -81,6 +287,11 @@ impl ExporterBuilder {
                 .map_err(Error::RecordingsMeta)?,
         );
 
+        let project_config = project_config.with_speed_ramps(
+            &self.speed_ramps,
+            cap_rendering::get_duration(&recordings, &recording_meta, studio_meta, &project_config),
+        )?;
+
         let render_constants = Arc::new(
             RenderVideoConstants::new(
                 &recordings.segments,
@@ -113,6 +324,21 @@ impl ExporterBuilder {
             recording_meta,
             project_config,
             project_path: self.project_path,
+            cancel_token: self.cancel_token.unwrap_or_default(),
+            time_range: self.time_range,
+            subtitles: self.subtitles,
+            watermark: self.watermark,
+            redaction: self.redaction,
+            input_overlay: self.input_overlay,
+            time_stretch: self.time_stretch,
+            render_threads: self.render_threads.unwrap_or_else(|| {
+                std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(1)
+                    .min(MAX_RENDER_THREADS)
+            }),
+            metrics: self.metrics.unwrap_or_default(),
+            deterministic_timing: self.deterministic_timing,
         })
     }
 }
@@ -126,18 +352,138 @@ pub struct ExporterBase {
     render_constants: Arc<RenderVideoConstants>,
     segments: Vec<Segment>,
     output_path: PathBuf,
+    cancel_token: CancellationToken,
+    time_range: Option<Range<f64>>,
+    subtitles: Option<SubtitleTrack>,
+    watermark: Option<WatermarkFilter>,
+    redaction: Option<RedactionFilter>,
+    input_overlay: Option<(Vec<InputEvent>, InputOverlayStyle)>,
+    time_stretch: TimeStretch,
+    render_threads: usize,
+    metrics: PipelineMetrics,
+    deterministic_timing: bool,
 }
 
 impl ExporterBase {
+    /// Number of frames that will be exported, which is 0 if the time range is invalid.
     pub fn total_frames(&self, fps: u32) -> u32 {
+        self.frame_range(fps).map(|r| r.len() as u32).unwrap_or(0)
+    }
+
+    /// Frames of the timeline to export, taking the time range into account.
+    pub fn frame_range(&self, fps: u32) -> Result<Range<u32>, MediaError> {
         let duration = cap_rendering::get_duration(
             &self.recordings,
             &self.recording_meta,
             &self.studio_meta,
             &self.project_config,
         );
+        let project_frames = (fps as f64 * duration).ceil() as u32;
+
+        let Some(time_range) = &self.time_range else {
+            return Ok(0..project_frames);
+        };
+
+        let start = (time_range.start.max(0.0) * fps as f64).floor() as u32;
+        let end = ((time_range.end * fps as f64).ceil() as u32).min(project_frames);
+
+        if start >= end {
+            return Err(MediaError::Any(
+                format!(
+                    "Export range {:.2}s..{:.2}s is empty or outside of the {duration:.2}s timeline",
+                    time_range.start, time_range.end
+                )
+                .into(),
+            ));
+        }
 
-        (fps as f64 * duration).ceil() as u32
+        Ok(start..end)
+    }
+
+    /// The recorded files that would make up the export, if the project has nothing
+    /// that needs it to be re-rendered and the display is exported at its recorded
+    /// size and frame rate, so the files can be muxed into the output as-is.
+    ///
+    /// Audio files are left out if `include_audio` is false.
+    pub(crate) fn stream_copy_inputs(
+        &self,
+        fps: u32,
+        resolution_base: XY<u32>,
+        fit: Option<FitMode>,
+        include_audio: bool,
+    ) -> Option<Vec<PathBuf>> {
+        let [recording] = self.recordings.segments.as_slice() else {
+            return None;
+        };
+
+        let (display, camera, audio, cursor) = match &self.studio_meta {
+            StudioRecordingMeta::SingleSegment { segment } => (
+                &segment.display,
+                &segment.camera,
+                segment.audio.iter().collect::<Vec<_>>(),
+                &segment.cursor,
+            ),
+            StudioRecordingMeta::MultipleSegments { inner } => {
+                let [segment] = inner.segments.as_slice() else {
+                    return None;
+                };
+                (
+                    &segment.display,
+                    &segment.camera,
+                    segment.mic.iter().chain(&segment.system_audio).collect(),
+                    &segment.cursor,
+                )
+            }
+        };
+
+        let config = &self.project_config;
+        let include_audio = include_audio && !config.audio.mute;
+        let background = &config.background;
+        let audio_edited = config.audio.improve
+            || config.audio.mic_volume_db != 0.0
+            || config.audio.system_volume_db != 0.0
+            || config.audio.mic_stereo_mode != StereoMode::Stereo;
+
+        let unedited = self.time_range.is_none()
+            && self.subtitles.is_none()
+            && self.watermark.is_none()
+            && self.redaction.is_none()
+            && self.input_overlay.is_none()
+            && config.timeline.is_none()
+            && config.captions.is_none()
+            && config.aspect_ratio.is_none()
+            && background.padding == 0.0
+            && background.rounding == 0.0
+            && background.inset == 0
+            && background.crop.is_none()
+            && !background.border.as_ref().is_some_and(|b| b.enabled)
+            && (camera.is_none() || config.camera.hide)
+            && (cursor.is_none() || config.cursor.hide)
+            && (audio.is_empty() || !include_audio || !audio_edited);
+
+        let recorded_size = (recording.display.width, recording.display.height);
+
+        if !unedited
+            || recording.display.fps != fps
+            || self.output_size(resolution_base, None) != recorded_size
+            || self.output_size(resolution_base, fit) != recorded_size
+        {
+            return None;
+        }
+
+        let display_path = self.recording_meta.path(&display.path);
+        // copied streams only keep their rotation as a flag that many players ignore,
+        // so rotated recordings are rendered upright instead
+        if !matches!(Rotation::probe(&display_path), Ok(Rotation::None)) {
+            return None;
+        }
+
+        let mut inputs = vec![display_path];
+        if include_audio {
+            inputs.extend(audio.iter().map(|a| self.recording_meta.path(&a.path)));
+        }
+
+        Some(inputs)
     }
 
     pub fn builder(project_path: PathBuf) -> ExporterBuilder {
@@ -145,6 +491,296 @@ impl ExporterBase {
             project_path,
             config: None,
             output_path: None,
+            cancel_token: None,
+            time_range: None,
+            subtitles: None,
+            watermark: None,
+            redaction: None,
+            input_overlay: None,
+            speed_ramps: vec![],
+            time_stretch: TimeStretch::default(),
+            render_threads: None,
+            metrics: None,
+            deterministic_timing: false,
+        }
+    }
+
+    /// Size of the exported frames: `resolution_base` itself when they're fit to it,
+    /// otherwise the project's aspect ratio scaled to fit inside it.
+    pub(crate) fn output_size(&self, resolution_base: XY<u32>, fit: Option<FitMode>) -> (u32, u32) {
+        match fit {
+            // encoders need even dimensions
+            Some(_) => ((resolution_base.x + 1) & !1, (resolution_base.y + 1) & !1),
+            None => ProjectUniforms::get_output_size(
+                &self.render_constants.options,
+                &self.project_config,
+                resolution_base,
+            ),
         }
     }
+
+    /// Fits rendered frames to [`Self::output_size`], with letterboxing filled
+    /// by the project's background.
+    pub(crate) fn frame_fitter(
+        &self,
+        resolution_base: XY<u32>,
+        fit: Option<FitMode>,
+    ) -> Result<Option<FrameFitter>, ExportError> {
+        let Some(fit) = fit else {
+            return Ok(None);
+        };
+
+        let (width, height) = self.output_size(resolution_base, Some(fit));
+        let background = self.project_config.background.source.clone().into();
+
+        Ok(Some(FrameFitter::new(
+            fit,
+            XY::new(width, height),
+            &background,
+        )?))
+    }
+
+    /// Records the size of the finished output and logs how long each stage of the export took.
+    pub(crate) fn finish_metrics(&self, output_path: &Path) {
+        match std::fs::metadata(output_path) {
+            Ok(metadata) => self.metrics.add_bytes_written(metadata.len()),
+            Err(e) => warn!("Failed to read size of '{}': {e}", output_path.display()),
+        }
+
+        info!("Export pipeline metrics:\n{}", self.metrics.summary());
+    }
+
+    /// Renders `frame_range` into `sender`, numbering frames from the start of the range.
+    ///
+    /// The range is split into blocks that are handed out in turn to each render thread,
+    /// and the rendered frames are put back in order before being sent.
+    pub(crate) async fn render_to_channel(
+        &self,
+        fps: u32,
+        resolution_base: XY<u32>,
+        frame_range: Range<u32>,
+        sender: mpsc::Sender<(RenderedFrame, u32)>,
+    ) -> Result<(), ExportError> {
+        let blocks = render_blocks(frame_range.clone(), RENDER_BLOCK_FRAMES);
+        let threads = self.render_threads.min(blocks.len()).max(1);
+
+        if threads == 1 {
+            return Ok(cap_rendering::render_video_to_channel(
+                &self.render_constants,
+                &self.project_config,
+                sender,
+                &self.recording_meta,
+                &self.studio_meta,
+                render_segments(&self.segments),
+                fps,
+                resolution_base,
+                &self.recordings,
+                frame_range,
+                &self.metrics,
+            )
+            .await?);
+        }
+
+        info!(
+            "Rendering {} frames on {threads} threads",
+            frame_range.len()
+        );
+
+        let mut receivers = Vec::with_capacity(threads);
+        let mut workers = Vec::with_capacity(threads);
+
+        for thread in 0..threads {
+            // the first thread reuses the decoders the exporter was built with
+            let segments = if thread == 0 {
+                render_segments(&self.segments)
+            } else {
+                render_segments(
+                    &cap_editor::create_segments(&self.recording_meta, &self.studio_meta)
+                        .await
+                        .map_err(ExportError::Other)?,
+                )
+            };
+
+            let (tx, rx) = mpsc::channel(RENDER_BLOCK_FRAMES as usize);
+            receivers.push(rx);
+
+            let ranges = blocks
+                .iter()
+                .skip(thread)
+                .step_by(threads)
+                .cloned()
+                .collect();
+            let constants = self.render_constants.clone();
+            let project = self.project_config.clone();
+            let recording_meta = self.recording_meta.clone();
+            let studio_meta = self.studio_meta.clone();
+            let recordings = self.recordings.clone();
+            let metrics = self.metrics.clone();
+
+            workers.push(tokio::spawn(async move {
+                cap_rendering::render_video_ranges_to_channel(
+                    &constants,
+                    &project,
+                    tx,
+                    &recording_meta,
+                    &studio_meta,
+                    segments,
+                    fps,
+                    resolution_base,
+                    &recordings,
+                    ranges,
+                    &metrics,
+                )
+                .await
+            }));
+        }
+
+        // a frame that was received while waiting for the end of a block,
+        // but belongs to the thread's next one
+        let mut pending = (0..threads).map(|_| None).collect::<Vec<_>>();
+
+        'blocks: for (i, block) in blocks.iter().enumerate() {
+            let thread = i % threads;
+
+            loop {
+                let next = match pending[thread].take() {
+                    Some(v) => Some(v),
+                    None => receivers[thread].recv().await,
+                };
+
+                // the thread finished or failed, which is reported once it's joined
+                let Some((frame, frame_number)) = next else {
+                    continue 'blocks;
+                };
+
+                if frame_number >= block.end {
+                    pending[thread] = Some((frame, frame_number));
+                    continue 'blocks;
+                }
+
+                if sender
+                    .send((frame, frame_number - frame_range.start))
+                    .await
+                    .is_err()
+                {
+                    // the receiver stopped, so the render threads are stopped too
+                    break 'blocks;
+                }
+            }
+        }
+
+        drop(receivers);
+
+        for worker in workers {
+            match worker.await? {
+                Ok(()) | Err(cap_rendering::RenderingError::ChannelSendFrameFailed(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Fits a rendered frame with a [`FrameFitter`] from [`ExporterBase::frame_fitter`].
+pub(crate) fn fit_frame(
+    fitter: &FrameFitter,
+    frame: RenderedFrame,
+) -> Result<RenderedFrame, MediaError> {
+    Ok(fitter.apply(frame.try_into()?).into())
+}
+
+fn render_segments(segments: &[Segment]) -> Vec<RenderSegment> {
+    segments
+        .iter()
+        .map(|s| RenderSegment {
+            cursor: s.cursor.clone(),
+            decoders: s.decoders.clone(),
+        })
+        .collect()
+}
+
+/// Splits `frame_range` into consecutive blocks of at most `block_frames` frames.
+fn render_blocks(frame_range: Range<u32>, block_frames: u32) -> Vec<Range<u32>> {
+    frame_range
+        .clone()
+        .step_by(block_frames as usize)
+        .map(|start| start..start.saturating_add(block_frames).min(frame_range.end))
+        .collect()
+}
+
+/// Timestamps of exported frames, for [`ExporterBuilder::with_deterministic_timing`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FrameTiming {
+    fps: u32,
+    deterministic: bool,
+}
+
+impl FrameTiming {
+    /// Creation time written into deterministic exports.
+    pub(crate) const EPOCH: &'static str = "1970-01-01T00:00:00.000000Z";
+
+    pub(crate) fn new(fps: u32, deterministic: bool) -> Self {
+        Self { fps, deterministic }
+    }
+
+    /// Timestamp of the `index`th exported frame, which is `frame_number` on the timeline,
+    /// in frames at the export's rate.
+    pub(crate) fn video_pts(&self, index: u32, frame_number: u32) -> i64 {
+        if self.deterministic {
+            index as i64
+        } else {
+            frame_number as i64
+        }
+    }
+
+    /// Timestamp of the audio exported with that frame, in samples at `rate`.
+    pub(crate) fn audio_pts(&self, index: u32, frame_number: u32, rate: u32) -> i64 {
+        if self.deterministic {
+            index as i64 * rate as i64 / self.fps as i64
+        } else {
+            ((frame_number as i64 * rate as i64) as f64 / self.fps as f64) as i64
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn splits_range_into_blocks() {
+        assert_eq!(render_blocks(5..40, 15), vec![5..20, 20..35, 35..40]);
+    }
+
+    #[test]
+    fn empty_range_has_no_blocks() {
+        assert!(render_blocks(10..10, 15).is_empty());
+    }
+
+    #[test]
+    fn deterministic_timing_starts_at_zero() {
+        let timing = FrameTiming::new(30, true);
+
+        assert_eq!(timing.video_pts(0, 90), 0);
+        assert_eq!(timing.video_pts(45, 135), 45);
+        assert_eq!(timing.audio_pts(0, 90, 48_000), 0);
+        assert_eq!(timing.audio_pts(45, 135, 48_000), 72_000);
+    }
+
+    #[test]
+    fn timeline_timing_follows_frame_numbers() {
+        let timing = FrameTiming::new(30, false);
+
+        assert_eq!(timing.video_pts(0, 90), 90);
+        assert_eq!(timing.audio_pts(0, 90, 48_000), 144_000);
+    }
+
+    #[test]
+    fn long_timelines_dont_overflow() {
+        let timing = FrameTiming::new(30, false);
+
+        // 2 hours in, where frame number times sample rate no longer fits in a u32
+        assert_eq!(timing.audio_pts(0, 216_000, 48_000), 345_600_000);
+    }
 }
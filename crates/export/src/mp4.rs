@@ -1,14 +1,33 @@
-use crate::ExporterBase;
+use crate::{
+    ExportError, ExportMetadata, ExportProgress, ExporterBase, FrameTiming,
+    pipeline::{RenderLoop, encode_frames, finish_encoding},
+    temp_output::TempOutput,
+    yes,
+};
 use cap_editor::{AudioRenderer, get_audio_segments};
-use cap_enc_ffmpeg::{AACEncoder, AudioEncoder, H264Encoder, MP4File, MP4Input};
-use cap_media_info::{RawVideoFormat, VideoInfo};
+use cap_enc_ffmpeg::{
+    AACEncoder, AudioEncoder, H264Encoder, H264Preset, MP4File, MP4Input, MP4Options, OpusEncoder,
+    ThreadedMP4File, VideoCodec, get_bitrate,
+};
+use cap_media::{
+    MediaError, PipelineStage,
+    encoders::{
+        ImageFrame, StillImageFormat, available_encoders, container_supports,
+        encode_image_with_metadata,
+    },
+    filters::{
+        AudioFadeFilter, ChannelConverter, Fade, Gain, GainFilter, GainKeyframe,
+        InputOverlayFilter, SubtitleBurner, VideoFadeFilter,
+    },
+};
+use cap_media_info::{AudioInfo, ChannelLayout, RawVideoFormat, VideoInfo};
 use cap_project::XY;
-use cap_rendering::{ProjectUniforms, RenderSegment, RenderedFrame};
+use cap_rendering::{FitMode, RenderedFrame};
+use ffmpeg::{codec, media};
 use futures::FutureExt;
-use image::ImageBuffer;
 use serde::Deserialize;
 use specta::Type;
-use std::{path::PathBuf, time::Duration};
+use std::{path::PathBuf, sync::Arc, time::Duration};
 use tracing::{info, trace, warn};
 
 #[derive(Deserialize, Type, Clone, Copy, Debug)]
@@ -28,37 +47,467 @@ impl ExportCompression {
             Self::Potato => 0.04,
         }
     }
+
+    /// Bitrate the video encoder targets for this compression level.
+    pub fn video_bitrate(&self, resolution: XY<u32>, fps: u32) -> usize {
+        get_bitrate(
+            resolution.x,
+            resolution.y,
+            fps as f32,
+            self.bits_per_pixel(),
+        )
+    }
+}
+
+/// Bitrate of the audio track in exported mp4s, unless another is picked.
+pub const AUDIO_BITRATE: usize = AACEncoder::OUTPUT_BITRATE;
+
+#[derive(Deserialize, Type, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mp4AudioCodec {
+    #[default]
+    Aac,
+    /// Sounds better than AAC at low bitrates, especially for voice,
+    /// but some older players can't play it in mp4.
+    Opus,
+}
+
+impl Mp4AudioCodec {
+    fn codec_id(&self) -> codec::Id {
+        match self {
+            Self::Aac => codec::Id::AAC,
+            Self::Opus => codec::Id::OPUS,
+        }
+    }
+
+    fn default_bitrate(&self) -> usize {
+        match self {
+            Self::Aac => AUDIO_BITRATE,
+            Self::Opus => OpusEncoder::OUTPUT_BITRATE,
+        }
+    }
+}
+
+/// How the audio track of an mp4 is encoded.
+#[derive(Deserialize, Type, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AudioEncodeSettings {
+    #[serde(default)]
+    pub codec: Mp4AudioCodec,
+    /// In bits per second. Defaults to the codec's usual bitrate,
+    /// which is [`AUDIO_BITRATE`] for AAC.
+    #[serde(default)]
+    pub bitrate: Option<u32>,
+}
+
+impl AudioEncodeSettings {
+    pub fn effective_bitrate(&self) -> usize {
+        self.bitrate
+            .map_or(self.codec.default_bitrate(), |b| b as usize)
+    }
+
+    /// Fails if the codec's encoder is missing or it can't be stored in mp4.
+    pub fn validate(&self) -> Result<(), MediaError> {
+        if self.bitrate == Some(0) {
+            return Err(MediaError::Any("Audio bitrate must be above 0".into()));
+        }
+
+        let encoders = available_encoders();
+        let (support, name) = match self.codec {
+            Mp4AudioCodec::Aac => (encoders.aac, "aac"),
+            Mp4AudioCodec::Opus => (encoders.opus, "libopus"),
+        };
+
+        if !support.software {
+            return Err(MediaError::MissingCodec(name));
+        }
+
+        if !container_supports("mp4", self.codec.codec_id()) {
+            return Err(MediaError::Any(
+                format!("{:?} audio can't be stored in mp4", self.codec).into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Type, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mp4Codec {
+    #[default]
+    H264,
+    H265,
+    /// Much smaller files than H.264 at the same quality, but much slower to encode.
+    /// See [`Av1Speed`] for how much slower.
+    Av1,
+}
+
+impl Mp4Codec {
+    /// AV1 looks as good as H.264 at a lower bitrate, so it's given less of one
+    fn bits_per_pixel_scale(&self) -> f32 {
+        match self {
+            Self::H264 | Self::H265 => 1.0,
+            Self::Av1 => 0.6,
+        }
+    }
+}
+
+impl From<Mp4Codec> for VideoCodec {
+    fn from(codec: Mp4Codec) -> Self {
+        match codec {
+            Mp4Codec::H264 => VideoCodec::H264,
+            Mp4Codec::H265 => VideoCodec::H265,
+            Mp4Codec::Av1 => VideoCodec::Av1,
+        }
+    }
+}
+
+/// How much H.264 and HEVC encoding trades speed for file size, independently of quality.
+/// Slower presets make smaller files at the same quality.
+#[derive(Deserialize, Type, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EncoderPreset {
+    #[default]
+    Ultrafast,
+    Superfast,
+    Veryfast,
+    Faster,
+    Fast,
+    Medium,
+    Slow,
+    Slower,
+    Veryslow,
+}
+
+impl From<EncoderPreset> for H264Preset {
+    fn from(preset: EncoderPreset) -> Self {
+        match preset {
+            EncoderPreset::Ultrafast => H264Preset::Ultrafast,
+            EncoderPreset::Superfast => H264Preset::Superfast,
+            EncoderPreset::Veryfast => H264Preset::Veryfast,
+            EncoderPreset::Faster => H264Preset::Faster,
+            EncoderPreset::Fast => H264Preset::Fast,
+            EncoderPreset::Medium => H264Preset::Medium,
+            EncoderPreset::Slow => H264Preset::Slow,
+            EncoderPreset::Slower => H264Preset::Slower,
+            EncoderPreset::Veryslow => H264Preset::Veryslow,
+        }
+    }
+}
+
+/// How much AV1 encoding trades speed for quality.
+/// Encode times are rough multiples of exporting the same video as H.264.
+#[derive(Deserialize, Type, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Av1Speed {
+    /// Around 10x slower than H.264
+    Quality,
+    /// Around 3x slower than H.264
+    #[default]
+    Balanced,
+    /// Around 1.5x slower than H.264, with noticeably larger files than the other presets
+    Fast,
+}
+
+impl From<Av1Speed> for H264Preset {
+    fn from(speed: Av1Speed) -> Self {
+        match speed {
+            Av1Speed::Quality => H264Preset::Slow,
+            Av1Speed::Balanced => H264Preset::Medium,
+            Av1Speed::Fast => H264Preset::Ultrafast,
+        }
+    }
+}
+
+/// Channels of the exported audio. The timeline is mixed in stereo, so mono
+/// exports downmix it.
+#[derive(Deserialize, Type, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AudioChannels {
+    Mono,
+    #[default]
+    Stereo,
+}
+
+impl AudioChannels {
+    pub fn layout(&self) -> ChannelLayout {
+        match self {
+            Self::Mono => ChannelLayout::MONO,
+            Self::Stereo => ChannelLayout::STEREO,
+        }
+    }
+
+    /// What the audio is encoded as after converting [`AudioRenderer`]'s output to these channels
+    pub(crate) fn audio_info(&self) -> AudioInfo {
+        AudioInfo {
+            channels: self.layout().channels() as usize,
+            ..AudioRenderer::info()
+        }
+    }
+}
+
+/// Gain applied to the exported audio, in dB.
+#[derive(Deserialize, Type, Clone, Debug)]
+pub enum AudioGain {
+    Constant(f64),
+    /// Interpolated linearly between keyframes, e.g. to duck background audio
+    /// during part of the video.
+    Envelope(Vec<AudioGainKeyframe>),
 }
 
 #[derive(Deserialize, Type, Clone, Copy, Debug)]
+pub struct AudioGainKeyframe {
+    /// Seconds from the start of the export
+    pub time: f64,
+    pub gain_db: f64,
+}
+
+impl From<&AudioGain> for Gain {
+    fn from(gain: &AudioGain) -> Self {
+        match gain {
+            AudioGain::Constant(gain_db) => Gain::Constant(*gain_db),
+            AudioGain::Envelope(keyframes) => Gain::Envelope(
+                keyframes
+                    .iter()
+                    .map(|k| GainKeyframe {
+                        time: k.time,
+                        gain_db: k.gain_db,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[derive(Deserialize, Type, Clone, Debug)]
 pub struct Mp4ExportSettings {
     pub fps: u32,
     pub resolution_base: XY<u32>,
+    /// Exports at exactly `resolution_base`, fitting frames to it this way,
+    /// instead of at the project's aspect ratio
+    #[serde(default)]
+    pub fit: Option<FitMode>,
     pub compression: ExportCompression,
+    /// Move the `moov` atom to the front of the file so it can start playing on the web
+    /// before it's fully downloaded. This takes an extra pass over the file once it's written.
+    #[serde(default, alias = "faststart")]
+    pub web_optimized: bool,
+    /// Constant rate factor for x264, from 0 (lossless) to 51.
+    /// Overrides `compression` when set.
+    #[serde(default)]
+    pub crf: Option<u8>,
+    /// Frames between keyframes, for videos that will be seeked a lot, e.g. when they're
+    /// streamed or imported into an editor. Shorter intervals seek faster but make larger
+    /// files. Left to the encoder when unset.
+    #[serde(default)]
+    pub keyframe_interval: Option<u32>,
+    #[serde(default)]
+    pub codec: Mp4Codec,
+    /// Fail with [`MediaError::MissingCodec`] if `codec` isn't available,
+    /// instead of falling back to H.264
+    #[serde(default)]
+    pub require_codec: bool,
+    /// Only used when encoding with [`Mp4Codec::H264`] or [`Mp4Codec::H265`]
+    #[serde(default)]
+    pub preset: EncoderPreset,
+    /// Only used when encoding with [`Mp4Codec::Av1`]
+    #[serde(default)]
+    pub av1_speed: Av1Speed,
+    /// Keep HDR recordings in HDR instead of tone mapping them to SDR. Only possible when
+    /// encoding HEVC or AV1 and the recording can be copied without re-rendering,
+    /// as the renderer works in SDR.
+    #[serde(default)]
+    pub keep_hdr: bool,
+    /// Boosts or attenuates the audio on top of the project's own volume settings
+    #[serde(default)]
+    pub audio_gain: Option<AudioGain>,
+    /// Turning this off exports a silent video, without rendering or encoding any audio
+    #[serde(default = "yes")]
+    pub include_audio: bool,
+    #[serde(default)]
+    pub audio_channels: AudioChannels,
+    #[serde(default)]
+    pub audio: AudioEncodeSettings,
+    /// Fades the video in from black and the audio from silence over this many milliseconds
+    #[serde(default)]
+    pub fade_in_ms: u32,
+    /// Fades the video out to black and the audio to silence over this many milliseconds
+    #[serde(default)]
+    pub fade_out_ms: u32,
+    /// Written to the video and its screenshot
+    #[serde(default)]
+    pub metadata: ExportMetadata,
 }
 
 impl Mp4ExportSettings {
+    pub const MAX_CRF: u8 = 51;
+
+    pub fn validate(&self) -> Result<(), MediaError> {
+        if let Some(crf) = self.crf
+            && crf > Self::MAX_CRF
+        {
+            return Err(MediaError::Any(
+                format!("CRF must be between 0 and {}, got {crf}", Self::MAX_CRF).into(),
+            ));
+        }
+
+        if self.keyframe_interval == Some(0) {
+            return Err(MediaError::Any(
+                "Keyframe interval must be at least 1 frame".into(),
+            ));
+        }
+
+        if self.include_audio {
+            self.audio.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// The codec to encode with, after falling back if the requested one isn't available.
+    /// AV1 is never replaced, as it's only picked to get the smallest files.
+    pub fn resolve_codec(&self) -> Result<Mp4Codec, MediaError> {
+        if self.codec == Mp4Codec::Av1 && !available_encoders().av1.software {
+            return Err(MediaError::MissingCodec("av1"));
+        }
+
+        if self.codec == Mp4Codec::H265 && !available_encoders().h265.software {
+            if self.require_codec {
+                return Err(MediaError::MissingCodec("hevc"));
+            }
+
+            warn!("HEVC encoder isn't available, falling back to H.264");
+            return Ok(Mp4Codec::H264);
+        }
+
+        Ok(self.codec)
+    }
+
+    /// Fades for an export lasting `duration`, `None` if neither is set.
+    fn fade(&self, duration: Duration) -> Option<Fade> {
+        Fade::new(
+            Duration::from_millis(self.fade_in_ms.into()),
+            Duration::from_millis(self.fade_out_ms.into()),
+            duration,
+        )
+    }
+
+    /// Whether HDR video can be kept when encoding with `codec`, as H.264 is only used for SDR.
+    fn keeps_hdr(&self, codec: Mp4Codec) -> bool {
+        self.keep_hdr && matches!(codec, Mp4Codec::H265 | Mp4Codec::Av1)
+    }
+
+    /// Files that can be muxed into the output without re-encoding, which is only done
+    /// at the highest quality setting, without a keyframe interval, audio gain, fades,
+    /// channel conversion or audio encoding settings to apply, and when their codecs match
+    /// what would be encoded.
+    /// HDR files are re-rendered so they're tone mapped, unless HDR is being kept.
+    /// Deterministic exports are always re-rendered, as copies keep the recorded timestamps.
+    fn stream_copy_inputs(&self, base: &ExporterBase, codec: Mp4Codec) -> Option<Vec<PathBuf>> {
+        if base.deterministic_timing
+            || !matches!(self.compression, ExportCompression::Minimal)
+            || self.crf.is_some()
+            || self.keyframe_interval.is_some()
+            || self.audio_gain.is_some()
+            || self.audio_channels != AudioChannels::Stereo
+            || self.audio != AudioEncodeSettings::default()
+            || self.fade_in_ms > 0
+            || self.fade_out_ms > 0
+        {
+            return None;
+        }
+
+        let inputs =
+            base.stream_copy_inputs(self.fps, self.resolution_base, self.fit, self.include_audio)?;
+        let video_codec = match codec {
+            Mp4Codec::H264 => codec::Id::H264,
+            Mp4Codec::H265 => codec::Id::HEVC,
+            Mp4Codec::Av1 => codec::Id::AV1,
+        };
+
+        for input in &inputs {
+            let codecs = cap_media::stream_codecs(input)
+                .inspect_err(|e| warn!("Failed to probe '{}': {e}", input.display()))
+                .ok()?;
+
+            let compatible = codecs.iter().all(|(medium, id)| match medium {
+                media::Type::Video => *id == video_codec,
+                _ => *id == codec::Id::AAC,
+            });
+
+            if !compatible {
+                return None;
+            }
+
+            let hdr = cap_media::has_hdr_video(input)
+                .inspect_err(|e| warn!("Failed to probe '{}': {e}", input.display()))
+                .ok()?;
+
+            if hdr && !self.keeps_hdr(codec) {
+                return None;
+            }
+        }
+
+        Some(inputs)
+    }
+
     pub async fn export(
         self,
-        base: ExporterBase,
-        mut on_progress: impl FnMut(u32) + Send + 'static,
-    ) -> Result<PathBuf, String> {
-        let output_path = base.output_path.clone();
-        let meta = &base.studio_meta;
+        mut base: ExporterBase,
+        on_progress: impl Fn(ExportProgress) + Send + Sync + 'static,
+    ) -> Result<PathBuf, ExportError> {
+        let output = TempOutput::new(base.output_path.clone());
+        let output_path = output.path().to_path_buf();
+        let cancel_token = base.cancel_token.clone();
 
         info!("Exporting mp4 with settings: {:?}", &self);
-        info!("Expected to render {} frames", base.total_frames(self.fps));
+        self.validate()?;
+        let codec = self.resolve_codec()?;
+
+        if let Some(inputs) = self.stream_copy_inputs(&base, codec) {
+            info!("Recording needs no re-rendering, copying its streams into the output");
+            on_progress(ExportProgress::Finalizing);
+
+            let faststart = self.web_optimized;
+            let mux = tokio::task::spawn_blocking({
+                let output_path = output_path.clone();
+                let metadata = self.metadata.clone();
+                move || {
+                    cap_media::mux_streams(
+                        &inputs,
+                        &output_path,
+                        faststart,
+                        metadata.container_tags(),
+                    )
+                }
+            });
+            base.metrics.time_async(PipelineStage::Mux, mux).await??;
 
-        let (tx_image_data, mut video_rx) = tokio::sync::mpsc::channel::<(RenderedFrame, u32)>(4);
+            if cancel_token.is_cancelled() {
+                return Err(ExportError::Cancelled);
+            }
+
+            let output_path = output.commit()?;
+            base.finish_metrics(&output_path);
+            return Ok(output_path);
+        }
+
+        if self.keep_hdr {
+            warn!(
+                "HDR can only be kept when exporting HEVC or AV1 without re-rendering, \
+                 any HDR video will be tone mapped to SDR"
+            );
+        }
+
+        let frame_range = base.frame_range(self.fps)?;
+        let total_frames = frame_range.len() as u32;
+        let start_time = frame_range.start as f64 / self.fps as f64;
+        info!("Expected to render {total_frames} frames");
+
+        let on_progress = Arc::new(on_progress);
+
+        let (tx_image_data, video_rx) = tokio::sync::mpsc::channel::<(RenderedFrame, u32)>(4);
         let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel::<MP4Input>(4);
 
         let fps = self.fps;
 
-        let output_size = ProjectUniforms::get_output_size(
-            &base.render_constants.options,
-            &base.project_config,
-            self.resolution_base,
-        );
+        let output_size = base.output_size(self.resolution_base, self.fit);
+        let fitter = base.frame_fitter(self.resolution_base, self.fit)?;
 
         let mut video_info =
             VideoInfo::from_raw(RawVideoFormat::Rgba, output_size.0, output_size.1, fps);
@@ -66,127 +515,239 @@ impl Mp4ExportSettings {
 
         let audio_segments = get_audio_segments(&base.segments);
 
-        let mut audio_renderer = audio_segments
+        let audio_renderer = audio_segments
             .first()
-            .filter(|_| !base.project_config.audio.mute)
-            .map(|_| AudioRenderer::new(audio_segments.clone()));
+            .filter(|_| self.include_audio && !base.project_config.audio.mute)
+            .map(|_| {
+                AudioRenderer::new(audio_segments.clone()).with_time_stretch(base.time_stretch)
+            });
         let has_audio = audio_renderer.is_some();
 
-        let encoder_thread = tokio::task::spawn_blocking(move || {
-            trace!("Creating MP4File encoder");
-
-            let mut encoder = MP4File::init(
-                "output",
-                base.output_path.clone(),
-                |o| {
-                    H264Encoder::builder("output_video", video_info)
-                        .with_bpp(self.compression.bits_per_pixel())
-                        .build(o)
-                },
-                |o| {
-                    has_audio.then(|| {
-                        AACEncoder::init("output_audio", AudioRenderer::info(), o)
-                            .map(|v| v.boxed())
-                            .map_err(Into::into)
+        let image_metadata = self.metadata.image_metadata();
+        let timing = FrameTiming::new(fps, base.deterministic_timing);
+        let fade = self.fade(Duration::from_secs_f64(total_frames as f64 / fps as f64));
+
+        let encoder_thread = tokio::task::spawn_blocking({
+            let on_progress = on_progress.clone();
+            let output_path = output_path.clone();
+            let metadata = self.metadata.clone();
+            let deterministic_timing = base.deterministic_timing;
+            let subtitles = base.subtitles.take();
+            let input_overlay = base.input_overlay.take();
+            let mut watermark = base.watermark.take();
+            let video_fade = fade.map(VideoFadeFilter::new);
+            let metrics = base.metrics.clone();
+            move || {
+                trace!("Creating MP4File encoder");
+
+                let mut encoder = MP4File::init_with_options(
+                    "output",
+                    output_path.clone(),
+                    MP4Options {
+                        metadata: if deterministic_timing {
+                            metadata.container_tags_at(FrameTiming::EPOCH)
+                        } else {
+                            metadata.container_tags()
+                        },
+                        faststart: self.web_optimized,
+                    },
+                    |o| {
+                        H264Encoder::builder("output_video", video_info)
+                            .with_bpp(
+                                self.compression.bits_per_pixel() * codec.bits_per_pixel_scale(),
+                            )
+                            .with_crf(self.crf)
+                            .with_keyframe_interval(self.keyframe_interval)
+                            .with_codec(codec.into())
+                            .with_preset(match codec {
+                                Mp4Codec::Av1 => self.av1_speed.into(),
+                                Mp4Codec::H264 | Mp4Codec::H265 => self.preset.into(),
+                            })
+                            .build(o)
+                    },
+                    |o| {
+                        has_audio.then(|| {
+                            let info = self.audio_channels.audio_info();
+                            let bitrate = self.audio.effective_bitrate();
+
+                            match self.audio.codec {
+                                Mp4AudioCodec::Aac => {
+                                    AACEncoder::init_with_bit_rate("output_audio", info, bitrate, o)
+                                        .map(|v| v.boxed())
+                                        .map_err(Into::into)
+                                }
+                                Mp4AudioCodec::Opus => OpusEncoder::init_with_bit_rate(
+                                    "output_audio",
+                                    info,
+                                    bitrate,
+                                    o,
+                                )
+                                .map(|v| v.boxed())
+                                .map_err(Into::into),
+                            }
+                        })
+                    },
+                )
+                .map_err(|v| v.to_string())?
+                .into_threaded(ThreadedMP4File::DEFAULT_MAX_LOOKAHEAD);
+
+                info!("Created MP4File encoder");
+
+                let mut subtitles = subtitles
+                    .map(|track| SubtitleBurner::new(&video_info, &track))
+                    .transpose()
+                    .map_err(|e| format!("Subtitles: {e}"))?;
+
+                let mut input_overlay = input_overlay
+                    .map(|(mut events, style)| {
+                        // frames are timed from the start of the exported range
+                        for event in &mut events {
+                            event.time -= start_time;
+                        }
+                        InputOverlayFilter::new(&video_info, &events, style)
                     })
-                },
-            )
-            .map_err(|v| v.to_string())?;
-
-            info!("Created MP4File encoder");
-
-            let mut encoded_frames = 0;
-            while let Ok(frame) = frame_rx.recv() {
-                encoder.queue_video_frame(frame.video);
-                encoded_frames += 1;
-                if let Some(audio) = frame.audio {
-                    encoder.queue_audio_frame(audio);
+                    .transpose()
+                    .map_err(|e| format!("Input overlay: {e}"))?;
+
+                let encoded_frames = encode_frames(
+                    &mut encoder,
+                    frame_rx,
+                    total_frames,
+                    &metrics,
+                    &*on_progress,
+                    |index, mut video| {
+                        if let Some(watermark) = &mut watermark {
+                            metrics
+                                .time(PipelineStage::Filter, || watermark.apply(&mut video))
+                                .map_err(|e| format!("Watermark: {e}"))?;
+                        }
+
+                        if let Some(video_fade) = &video_fade {
+                            let time = index as f64 / fps as f64;
+                            metrics
+                                .time(PipelineStage::Filter, || video_fade.apply(&mut video, time))
+                                .map_err(|e| format!("Fade: {e}"))?;
+                        }
+
+                        // graph filters can hold frames back, so each passes on what it has
+                        let mut videos = vec![video];
+
+                        if let Some(input_overlay) = &mut input_overlay {
+                            for video in std::mem::take(&mut videos) {
+                                metrics
+                                    .time(PipelineStage::Filter, || input_overlay.push_frame(video))
+                                    .map_err(|e| format!("Input overlay: {e}"))?;
+                            }
+                            videos.extend(std::iter::from_fn(|| input_overlay.receive_frame()));
+                        }
+
+                        if let Some(subtitles) = &mut subtitles {
+                            for video in std::mem::take(&mut videos) {
+                                metrics
+                                    .time(PipelineStage::Filter, || subtitles.push_frame(&video))
+                                    .map_err(|e| format!("Subtitles: {e}"))?;
+                            }
+                            videos.extend(std::iter::from_fn(|| subtitles.receive_frame()));
+                        }
+
+                        Ok(videos)
+                    },
+                )?;
+
+                let mut videos = vec![];
+
+                if let Some(input_overlay) = &mut input_overlay {
+                    input_overlay
+                        .flush()
+                        .map_err(|e| format!("Input overlay: {e}"))?;
+                    videos.extend(std::iter::from_fn(|| input_overlay.receive_frame()));
+                }
+
+                if let Some(subtitles) = &mut subtitles {
+                    for video in std::mem::take(&mut videos) {
+                        subtitles
+                            .push_frame(&video)
+                            .map_err(|e| format!("Subtitles: {e}"))?;
+                    }
+                    subtitles.flush().map_err(|e| format!("Subtitles: {e}"))?;
+                    videos.extend(std::iter::from_fn(|| subtitles.receive_frame()));
                 }
-            }
 
-            info!("Encoded {encoded_frames} video frames");
+                for video in videos {
+                    encoder.queue_video_frame(video);
+                }
 
-            encoder.finish();
+                finish_encoding(&mut encoder, encoded_frames, &metrics, &*on_progress)?;
 
-            Ok::<_, String>(base.output_path)
+                Ok::<_, String>(output_path)
+            }
         })
         .then(|r| async { r.map_err(|e| e.to_string()).and_then(|v| v) });
 
+        let mut gain_filter = self
+            .audio_gain
+            .as_ref()
+            .map(|gain| GainFilter::new(gain.into()));
+        let mut audio_fade = fade.map(AudioFadeFilter::new);
+        let channel_converter = ChannelConverter::new(self.audio_channels.layout());
+
+        let render_loop = RenderLoop {
+            fps,
+            start_time,
+            total_frames,
+            timing,
+            video_info,
+            fitter,
+            redaction: base.redaction.take(),
+            audio_renderer,
+            channel_converter,
+            project: base.project_config.clone(),
+            cancel_token: cancel_token.clone(),
+            metrics: base.metrics.clone(),
+        };
+
         let render_task = tokio::spawn({
-            let project = base.project_config.clone();
             let project_path = base.project_path.clone();
             async move {
-                let mut frame_count = 0;
                 let mut first_frame = None;
 
-                let audio_samples_per_frame =
-                    (f64::from(AudioRenderer::SAMPLE_RATE) / f64::from(fps)).ceil() as usize;
-
-                loop {
-                    let (frame, frame_number) =
-                        match tokio::time::timeout(Duration::from_secs(6), video_rx.recv()).await {
-                            Err(_) => {
-                                warn!("render_task frame receive timed out");
-                                break;
+                render_loop
+                    .run(
+                        video_rx,
+                        frame_tx,
+                        &*on_progress,
+                        |frame_count, frame| {
+                            if frame_count == 0 {
+                                first_frame = Some(frame.clone());
                             }
-                            Ok(Some(v)) => v,
-                            _ => {
-                                break;
+                        },
+                        |frame| {
+                            if let Some(gain_filter) = &mut gain_filter
+                                && let Err(e) = gain_filter.apply(frame)
+                            {
+                                warn!("Failed to apply audio gain: {e}");
                             }
-                        };
-
-                    (on_progress)(frame_count);
-
-                    if frame_count == 0 {
-                        first_frame = Some(frame.clone());
-                        if let Some(audio) = &mut audio_renderer {
-                            audio.set_playhead(0.0, &project);
-                        }
-                    }
 
-                    let audio_frame = audio_renderer
-                        .as_mut()
-                        .and_then(|audio| audio.render_frame(audio_samples_per_frame, &project))
-                        .map(|mut frame| {
-                            let pts = ((frame_number * frame.rate()) as f64 / fps as f64) as i64;
-                            frame.set_pts(Some(pts));
-                            frame
-                        });
-
-                    if frame_tx
-                        .send(MP4Input {
-                            audio: audio_frame,
-                            video: video_info.wrap_frame(
-                                &frame.data,
-                                frame_number as i64,
-                                frame.padded_bytes_per_row as usize,
-                            ),
-                        })
-                        .is_err()
-                    {
-                        warn!("Renderer task sender dropped. Exiting");
-                        return Ok(());
-                    }
-
-                    frame_count += 1;
+                            if let Some(audio_fade) = &mut audio_fade
+                                && let Err(e) = audio_fade.apply(frame)
+                            {
+                                warn!("Failed to fade audio: {e}");
+                            }
+                        },
+                    )
+                    .await?;
+
+                if let Some(stats) = gain_filter.map(|filter| filter.stats())
+                    && stats.clipped()
+                {
+                    warn!(
+                        "Audio gain clipped {} samples, peaking at {:.1} dBFS",
+                        stats.clipped_samples,
+                        20.0 * stats.peak.log10()
+                    );
                 }
 
                 if let Some(frame) = first_frame {
-                    let rgb_img = ImageBuffer::<image::Rgb<u8>, Vec<u8>>::from_raw(
-                        frame.width,
-                        frame.height,
-                        frame
-                            .data
-                            .chunks(frame.padded_bytes_per_row as usize)
-                            .flat_map(|row| {
-                                row[0..(frame.width * 4) as usize]
-                                    .chunks(4)
-                                    .flat_map(|chunk| [chunk[0], chunk[1], chunk[2]])
-                            })
-                            .collect::<Vec<_>>(),
-                    )
-                    .expect("Failed to create image from frame data");
-
                     let screenshots_dir = project_path.join("screenshots");
                     std::fs::create_dir_all(&screenshots_dir).unwrap_or_else(|e| {
                         eprintln!("Failed to create screenshots directory: {e:?}");
@@ -194,7 +755,18 @@ impl Mp4ExportSettings {
 
                     // Save full-size screenshot
                     let screenshot_path = screenshots_dir.join("display.jpg");
-                    rgb_img.save(&screenshot_path).unwrap_or_else(|e| {
+                    encode_image_with_metadata(
+                        ImageFrame {
+                            data: &frame.data,
+                            width: frame.width,
+                            height: frame.height,
+                            stride: frame.padded_bytes_per_row,
+                        },
+                        StillImageFormat::Jpeg { quality: 75 },
+                        &screenshot_path,
+                        &image_metadata,
+                    )
+                    .unwrap_or_else(|e| {
                         eprintln!("Failed to save screenshot: {e:?}");
                     });
                 } else {
@@ -209,27 +781,25 @@ impl Mp4ExportSettings {
                 .and_then(|v| v.map_err(|e| e.to_string()))
         });
 
-        let render_video_task = cap_rendering::render_video_to_channel(
-            &base.render_constants,
-            &base.project_config,
-            tx_image_data,
-            &base.recording_meta,
-            meta,
-            base.segments
-                .iter()
-                .map(|s| RenderSegment {
-                    cursor: s.cursor.clone(),
-                    decoders: s.decoders.clone(),
-                })
-                .collect(),
-            fps,
-            self.resolution_base,
-            &base.recordings,
-        )
-        .then(|v| async { v.map_err(|e| e.to_string()) });
+        let render_video_task = base
+            .render_to_channel(fps, self.resolution_base, frame_range, tx_image_data)
+            .then(|v| async { v.map_err(|e| e.to_string()) });
+
+        // wait for every task to stop, so that nothing is still writing
+        // to the output if it needs to be removed
+        let (encoded, rendered, render_task) =
+            tokio::join!(encoder_thread, render_video_task, render_task);
+
+        if cancel_token.is_cancelled() {
+            return Err(ExportError::Cancelled);
+        }
 
-        tokio::try_join!(encoder_thread, render_video_task, render_task)?;
+        encoded.map_err(ExportError::Other)?;
+        rendered.map_err(ExportError::Other)?;
+        render_task.map_err(ExportError::Other)?;
 
+        let output_path = output.commit()?;
+        base.finish_metrics(&output_path);
         Ok(output_path)
     }
 }
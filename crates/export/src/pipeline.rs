@@ -0,0 +1,248 @@
+use cap_editor::AudioRenderer;
+use cap_enc_ffmpeg::{ApngFile, MP4Input, ThreadedMP4File, WebMFile};
+use cap_media::{
+    PipelineMetrics, PipelineStage,
+    filters::{ChannelConverter, RedactionFilter},
+};
+use cap_media_info::VideoInfo;
+use cap_project::ProjectConfiguration;
+use cap_rendering::{FrameFitter, RenderedFrame};
+use ffmpeg::frame;
+use std::{
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::{ExportProgress, FrameTiming, eta::EtaEstimator, fit_frame};
+
+/// A muxer the exporters encode into with [`encode_frames`].
+pub(crate) trait ExportMuxer {
+    fn queue_video_frame(&mut self, frame: frame::Video) -> Result<(), String>;
+    fn queue_audio_frame(&mut self, frame: frame::Audio);
+    fn finish(&mut self) -> Result<(), String>;
+}
+
+impl ExportMuxer for ThreadedMP4File {
+    fn queue_video_frame(&mut self, frame: frame::Video) -> Result<(), String> {
+        ThreadedMP4File::queue_video_frame(self, frame);
+        Ok(())
+    }
+
+    fn queue_audio_frame(&mut self, frame: frame::Audio) {
+        ThreadedMP4File::queue_audio_frame(self, frame);
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        ThreadedMP4File::finish(self).map_err(|e| format!("Finish: {e}"))
+    }
+}
+
+impl ExportMuxer for WebMFile {
+    fn queue_video_frame(&mut self, frame: frame::Video) -> Result<(), String> {
+        WebMFile::queue_video_frame(self, frame);
+        Ok(())
+    }
+
+    fn queue_audio_frame(&mut self, frame: frame::Audio) {
+        WebMFile::queue_audio_frame(self, frame);
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        WebMFile::finish(self);
+        Ok(())
+    }
+}
+
+impl ExportMuxer for ApngFile {
+    fn queue_video_frame(&mut self, frame: frame::Video) -> Result<(), String> {
+        ApngFile::queue_frame(self, frame).map_err(|e| format!("Failed to add frame to APNG: {e}"))
+    }
+
+    // APNGs are silent
+    fn queue_audio_frame(&mut self, _: frame::Audio) {}
+
+    fn finish(&mut self) -> Result<(), String> {
+        ApngFile::finish(self).map_err(|e| format!("Failed to finish APNG: {e}"))
+    }
+}
+
+/// Turns the frames from [`crate::ExporterBase::render_to_channel`] into encoder input,
+/// rendering the audio that goes with each one.
+pub(crate) struct RenderLoop {
+    pub fps: u32,
+    /// Timeline time of the first exported frame, in seconds.
+    pub start_time: f64,
+    pub total_frames: u32,
+    pub timing: FrameTiming,
+    pub video_info: VideoInfo,
+    pub fitter: Option<FrameFitter>,
+    pub redaction: Option<RedactionFilter>,
+    pub audio_renderer: Option<AudioRenderer>,
+    pub channel_converter: ChannelConverter,
+    pub project: ProjectConfiguration,
+    pub cancel_token: CancellationToken,
+    pub metrics: PipelineMetrics,
+}
+
+impl RenderLoop {
+    /// Runs until every frame has been rendered or the export is cancelled.
+    ///
+    /// `on_frame` is given each frame once it's been fitted, along with its index.
+    /// `process_audio` is given each audio frame before its channels are converted.
+    pub async fn run(
+        mut self,
+        mut video_rx: tokio::sync::mpsc::Receiver<(RenderedFrame, u32)>,
+        frame_tx: mpsc::SyncSender<MP4Input>,
+        on_progress: &impl Fn(ExportProgress),
+        mut on_frame: impl FnMut(u32, &RenderedFrame),
+        mut process_audio: impl FnMut(&mut frame::Audio),
+    ) -> Result<(), String> {
+        let fps = self.fps;
+        let total_frames = self.total_frames;
+        let mut frame_count = 0;
+        let mut eta = EtaEstimator::new(total_frames);
+
+        let audio_samples_per_frame =
+            (f64::from(AudioRenderer::SAMPLE_RATE) / f64::from(fps)).ceil() as usize;
+
+        loop {
+            if self.cancel_token.is_cancelled() {
+                // dropping the channels stops rendering and encoding
+                return Ok(());
+            }
+
+            let (mut frame, frame_number) =
+                match tokio::time::timeout(Duration::from_secs(6), video_rx.recv()).await {
+                    Err(_) => {
+                        warn!("render_task frame receive timed out");
+                        break;
+                    }
+                    Ok(Some(v)) => v,
+                    _ => {
+                        break;
+                    }
+                };
+
+            let done = (frame_count + 1).min(total_frames);
+            on_progress(ExportProgress::Rendering {
+                done,
+                total: total_frames,
+                eta: eta.update(done, Instant::now()),
+            });
+
+            if let Some(redaction) = &self.redaction {
+                let time = self.start_time + frame_number as f64 / fps as f64;
+                self.metrics.time(PipelineStage::Filter, || {
+                    redaction.apply_rgba(
+                        &mut frame.data,
+                        frame.padded_bytes_per_row as usize,
+                        frame.width,
+                        frame.height,
+                        time,
+                    )
+                });
+            }
+
+            if let Some(fitter) = &self.fitter {
+                frame = self
+                    .metrics
+                    .time(PipelineStage::Filter, || fit_frame(fitter, frame))
+                    .map_err(|e| e.to_string())?;
+            }
+
+            on_frame(frame_count, &frame);
+
+            if frame_count == 0
+                && let Some(audio) = &mut self.audio_renderer
+            {
+                audio.set_playhead(self.start_time, &self.project);
+            }
+
+            let audio_frame = self
+                .audio_renderer
+                .as_mut()
+                .and_then(|audio| audio.render_frame(audio_samples_per_frame, &self.project))
+                .map(|mut audio| {
+                    audio.set_pts(Some(self.timing.audio_pts(
+                        frame_count,
+                        frame_number,
+                        audio.rate(),
+                    )));
+                    process_audio(&mut audio);
+
+                    self.channel_converter.apply(audio)
+                })
+                .transpose()
+                .map_err(|e| e.to_string())?;
+
+            if frame_tx
+                .send(MP4Input {
+                    audio: audio_frame,
+                    video: self.video_info.wrap_frame(
+                        &frame.data,
+                        self.timing.video_pts(frame_count, frame_number),
+                        frame.padded_bytes_per_row as usize,
+                    ),
+                })
+                .is_err()
+            {
+                warn!("Renderer task sender dropped. Exiting");
+                return Ok(());
+            }
+
+            frame_count += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Encodes the frames from [`RenderLoop::run`] into `muxer` until rendering ends, returning
+/// how many were received. Video goes through `filter` first, which is given each frame's
+/// index and can hold frames back or return several at once.
+///
+/// Flushing frames still held by the filter and finishing the muxer is left to the caller,
+/// see [`finish_encoding`].
+pub(crate) fn encode_frames(
+    muxer: &mut impl ExportMuxer,
+    frame_rx: mpsc::Receiver<MP4Input>,
+    total_frames: u32,
+    metrics: &PipelineMetrics,
+    on_progress: &impl Fn(ExportProgress),
+    mut filter: impl FnMut(u32, frame::Video) -> Result<Vec<frame::Video>, String>,
+) -> Result<u32, String> {
+    let mut encoded_frames = 0;
+
+    while let Ok(frame) = frame_rx.recv() {
+        for video in filter(encoded_frames, frame.video)? {
+            metrics.time(PipelineStage::Encode, || muxer.queue_video_frame(video))?;
+        }
+        encoded_frames += 1;
+        if let Some(audio) = frame.audio {
+            muxer.queue_audio_frame(audio);
+        }
+
+        on_progress(ExportProgress::Encoding {
+            done: encoded_frames.min(total_frames),
+            total: total_frames,
+        });
+    }
+
+    Ok(encoded_frames)
+}
+
+/// Writes out what `muxer` still holds once [`encode_frames`] has returned `encoded_frames`.
+pub(crate) fn finish_encoding(
+    muxer: &mut impl ExportMuxer,
+    encoded_frames: u32,
+    metrics: &PipelineMetrics,
+    on_progress: &impl Fn(ExportProgress),
+) -> Result<(), String> {
+    tracing::info!("Encoded {encoded_frames} video frames");
+    metrics.add_frames(encoded_frames as u64);
+
+    on_progress(ExportProgress::Finalizing);
+    metrics.time(PipelineStage::Mux, || muxer.finish())
+}
@@ -0,0 +1,107 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+use tracing::{error, info};
+
+/// Where an export is written until it has finished successfully.
+///
+/// Encoders write to a hidden temporary file next to the output, which is only moved to
+/// the output path by [`commit`](Self::commit). If the export fails or is cancelled the
+/// guard is dropped without being committed and the temporary file is removed, so a
+/// partially written file never shows up at the output path. The temporary file keeps
+/// the output's extension, as muxers pick their format from it.
+pub(crate) struct TempOutput {
+    path: PathBuf,
+    output_path: PathBuf,
+    committed: bool,
+}
+
+impl TempOutput {
+    pub(crate) fn new(output_path: PathBuf) -> Self {
+        let mut file_name = OsString::from(".");
+        file_name.push(output_path.file_stem().unwrap_or_default());
+        file_name.push(".partial");
+        if let Some(extension) = output_path.extension() {
+            file_name.push(".");
+            file_name.push(extension);
+        }
+
+        let path = output_path.with_file_name(file_name);
+        // left behind if a previous export of the same output crashed
+        remove_if_exists(&path);
+
+        Self {
+            path,
+            output_path,
+            committed: false,
+        }
+    }
+
+    /// The path to write the export to.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the finished export to the output path, replacing anything already there.
+    /// Must only be called once everything writing to [`path`](Self::path) has stopped.
+    pub(crate) fn commit(mut self) -> std::io::Result<PathBuf> {
+        std::fs::rename(&self.path, &self.output_path)?;
+        self.committed = true;
+
+        Ok(std::mem::take(&mut self.output_path))
+    }
+}
+
+impl Drop for TempOutput {
+    fn drop(&mut self) {
+        if !self.committed {
+            info!("Removing unfinished export '{}'", self.path.display());
+            remove_if_exists(&self.path);
+        }
+    }
+}
+
+fn remove_if_exists(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path)
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        error!("Failed to remove partial export '{}': {e}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn moves_to_output_on_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_path = dir.path().join("Cap Recording.mp4");
+
+        let output = TempOutput::new(output_path.clone());
+        assert_eq!(output.path(), dir.path().join(".Cap Recording.partial.mp4"));
+        std::fs::write(output.path(), b"video").unwrap();
+        assert!(!output_path.exists());
+
+        let temp_path = output.path().to_path_buf();
+        assert_eq!(output.commit().unwrap(), output_path);
+        assert_eq!(std::fs::read(&output_path).unwrap(), b"video");
+        assert!(!temp_path.exists());
+    }
+
+    #[test]
+    fn removes_temp_file_when_not_committed() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_path = dir.path().join("recording.gif");
+        std::fs::write(&output_path, b"previous export").unwrap();
+
+        let output = TempOutput::new(output_path.clone());
+        let temp_path = output.path().to_path_buf();
+        std::fs::write(&temp_path, b"partial").unwrap();
+        drop(output);
+
+        assert!(!temp_path.exists());
+        assert_eq!(std::fs::read(&output_path).unwrap(), b"previous export");
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{
+    ExportError, ExportProgress, ExporterBase, FrameTiming,
+    mp4::{AudioChannels, ExportCompression},
+    pipeline::{RenderLoop, encode_frames, finish_encoding},
+    temp_output::TempOutput,
+    yes,
+};
+use cap_editor::{AudioRenderer, get_audio_segments};
+use cap_enc_ffmpeg::{AudioEncoder, MP4Input, OpusEncoder, VP9Encoder, WebMFile};
+use cap_media::filters::ChannelConverter;
+use cap_media_info::{RawVideoFormat, VideoInfo};
+use cap_project::XY;
+use cap_rendering::{FitMode, RenderedFrame};
+use futures::FutureExt;
+use serde::Deserialize;
+use specta::Type;
+use std::{path::PathBuf, sync::Arc};
+use tracing::info;
+
+#[derive(Deserialize, Type, Clone, Copy, Debug)]
+pub struct WebMExportSettings {
+    pub fps: u32,
+    pub resolution_base: XY<u32>,
+    /// Exports at exactly `resolution_base`, fitting frames to it this way,
+    /// instead of at the project's aspect ratio
+    #[serde(default)]
+    pub fit: Option<FitMode>,
+    pub compression: ExportCompression,
+    /// Turning this off exports a silent video, without rendering or encoding any audio
+    #[serde(default = "yes")]
+    pub include_audio: bool,
+    #[serde(default)]
+    pub audio_channels: AudioChannels,
+}
+
+impl WebMExportSettings {
+    pub async fn export(
+        self,
+        mut base: ExporterBase,
+        on_progress: impl Fn(ExportProgress) + Send + Sync + 'static,
+    ) -> Result<PathBuf, ExportError> {
+        let output = TempOutput::new(base.output_path.with_extension("webm"));
+        let output_path = output.path().to_path_buf();
+        let cancel_token = base.cancel_token.clone();
+
+        info!("Exporting webm with settings: {:?}", &self);
+
+        let frame_range = base.frame_range(self.fps)?;
+        let total_frames = frame_range.len() as u32;
+        let start_time = frame_range.start as f64 / self.fps as f64;
+        let on_progress = Arc::new(on_progress);
+
+        let (tx_image_data, video_rx) = tokio::sync::mpsc::channel::<(RenderedFrame, u32)>(4);
+        let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel::<MP4Input>(4);
+
+        let fps = self.fps;
+
+        let output_size = base.output_size(self.resolution_base, self.fit);
+        let fitter = base.frame_fitter(self.resolution_base, self.fit)?;
+
+        let mut video_info =
+            VideoInfo::from_raw(RawVideoFormat::Rgba, output_size.0, output_size.1, fps);
+        video_info.time_base = ffmpeg::Rational::new(1, fps as i32);
+
+        let audio_segments = get_audio_segments(&base.segments);
+
+        let audio_renderer = audio_segments
+            .first()
+            .filter(|_| self.include_audio && !base.project_config.audio.mute)
+            .map(|_| {
+                AudioRenderer::new(audio_segments.clone()).with_time_stretch(base.time_stretch)
+            });
+        let has_audio = audio_renderer.is_some();
+        let channel_converter = ChannelConverter::new(self.audio_channels.layout());
+
+        let encoder_thread = tokio::task::spawn_blocking({
+            let output_path = output_path.clone();
+            let on_progress = on_progress.clone();
+            let metrics = base.metrics.clone();
+            move || {
+                let mut encoder = WebMFile::init(
+                    "output",
+                    output_path.clone(),
+                    |o| {
+                        VP9Encoder::builder("output_video", video_info)
+                            .with_bpp(self.compression.bits_per_pixel())
+                            .build(o)
+                    },
+                    |o| {
+                        has_audio.then(|| {
+                            OpusEncoder::init("output_audio", self.audio_channels.audio_info(), o)
+                                .map(|v| v.boxed())
+                                .map_err(Into::into)
+                        })
+                    },
+                )
+                .map_err(|v| v.to_string())?;
+
+                let encoded_frames = encode_frames(
+                    &mut encoder,
+                    frame_rx,
+                    total_frames,
+                    &metrics,
+                    &*on_progress,
+                    |_, video| Ok(vec![video]),
+                )?;
+
+                finish_encoding(&mut encoder, encoded_frames, &metrics, &*on_progress)?;
+
+                Ok::<_, String>(output_path)
+            }
+        })
+        .then(|r| async { r.map_err(|e| e.to_string()).and_then(|v| v) });
+
+        let render_loop = RenderLoop {
+            fps,
+            start_time,
+            total_frames,
+            timing: FrameTiming::new(fps, base.deterministic_timing),
+            video_info,
+            fitter,
+            redaction: base.redaction.take(),
+            audio_renderer,
+            channel_converter,
+            project: base.project_config.clone(),
+            cancel_token: cancel_token.clone(),
+            metrics: base.metrics.clone(),
+        };
+
+        let render_task = tokio::spawn(async move {
+            render_loop
+                .run(video_rx, frame_tx, &*on_progress, |_, _| {}, |_| {})
+                .await
+        })
+        .then(|r| async { r.map_err(|e| e.to_string()).and_then(|v| v) });
+
+        let render_video_task = base
+            .render_to_channel(fps, self.resolution_base, frame_range, tx_image_data)
+            .then(|v| async { v.map_err(|e| e.to_string()) });
+
+        let (encoded, rendered, render_task) =
+            tokio::join!(encoder_thread, render_video_task, render_task);
+
+        if cancel_token.is_cancelled() {
+            return Err(ExportError::Cancelled);
+        }
+
+        rendered.map_err(ExportError::Other)?;
+        render_task.map_err(ExportError::Other)?;
+        encoded.map_err(ExportError::Other)?;
+
+        let output_path = output.commit()?;
+        base.finish_metrics(&output_path);
+        Ok(output_path)
+    }
+}
@@ -0,0 +1,5 @@
+//! Frame types shared by the stages of the pipeline.
+
+mod rgba;
+
+pub use rgba::*;
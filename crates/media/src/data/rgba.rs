@@ -0,0 +1,225 @@
+use ffmpeg::{format::Pixel, frame};
+use image::RgbaImage;
+use std::sync::Arc;
+
+use crate::MediaError;
+
+/// 8-bit RGBA pixels along with the geometry needed to read them.
+///
+/// Rows are `stride` bytes apart, which is more than `width * 4` when they're padded.
+/// The pixels are shared, so cloning a frame is cheap and changing one only copies
+/// them if another frame still has them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaFrame {
+    data: Arc<Vec<u8>>,
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+impl RgbaFrame {
+    /// Fails if `stride` is shorter than a row, or `data` doesn't hold `height` rows.
+    pub fn new(
+        data: Arc<Vec<u8>>,
+        width: u32,
+        height: u32,
+        stride: u32,
+    ) -> Result<Self, MediaError> {
+        let row = width as usize * 4;
+        // the last row doesn't need its padding
+        let required = match height as usize {
+            0 => 0,
+            height => stride as usize * (height - 1) + row,
+        };
+
+        if (stride as usize) < row || data.len() < required {
+            return Err(MediaError::Any(
+                format!(
+                    "{} bytes with a stride of {stride} are too few for {width}x{height} RGBA",
+                    data.len()
+                )
+                .into(),
+            ));
+        }
+
+        Ok(Self {
+            data,
+            width,
+            height,
+            stride,
+        })
+    }
+
+    /// A frame with rows packed right after each other.
+    pub fn packed(data: Vec<u8>, width: u32, height: u32) -> Result<Self, MediaError> {
+        Self::new(Arc::new(data), width, height, width * 4)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Bytes from the start of one row to the next.
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
+
+    pub fn is_packed(&self) -> bool {
+        self.stride == self.width * 4
+    }
+
+    pub fn data(&self) -> &Arc<Vec<u8>> {
+        &self.data
+    }
+
+    /// Copies the pixels first if another frame shares them.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        Arc::make_mut(&mut self.data).as_mut_slice()
+    }
+
+    pub fn into_data(self) -> Arc<Vec<u8>> {
+        self.data
+    }
+
+    /// The pixels of row `y`, without its padding.
+    pub fn row(&self, y: u32) -> &[u8] {
+        let start = y as usize * self.stride as usize;
+        &self.data[start..start + self.width as usize * 4]
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let offset = x as usize * 4;
+        self.row(y)[offset..offset + 4].try_into().unwrap()
+    }
+
+    /// The frame as an image, which only copies the pixels if they're padded or shared.
+    pub fn into_image(self) -> RgbaImage {
+        let (width, height) = (self.width, self.height);
+        let data = if self.is_packed() {
+            let mut data = Arc::unwrap_or_clone(self.data);
+            data.truncate(width as usize * height as usize * 4);
+            data
+        } else {
+            (0..height).flat_map(|y| self.row(y)).copied().collect()
+        };
+
+        // `new` checked there are enough pixels
+        RgbaImage::from_raw(width, height, data).unwrap()
+    }
+}
+
+impl From<RgbaImage> for RgbaFrame {
+    fn from(image: RgbaImage) -> Self {
+        let (width, height) = image.dimensions();
+
+        Self {
+            data: Arc::new(image.into_raw()),
+            width,
+            height,
+            stride: width * 4,
+        }
+    }
+}
+
+/// Copies an RGBA frame's pixels, leaving out FFmpeg's row padding.
+impl TryFrom<&frame::Video> for RgbaFrame {
+    type Error = MediaError;
+
+    fn try_from(frame: &frame::Video) -> Result<Self, Self::Error> {
+        if frame.format() != Pixel::RGBA {
+            return Err(MediaError::Any(
+                format!("Expected an RGBA frame, got {:?}", frame.format()).into(),
+            ));
+        }
+
+        let (width, height) = (frame.width(), frame.height());
+        let row = width as usize * 4;
+        let data = frame
+            .data(0)
+            .chunks(frame.stride(0))
+            .take(height as usize)
+            .flat_map(|line| &line[..row])
+            .copied()
+            .collect();
+
+        Self::packed(data, width, height)
+    }
+}
+
+impl From<&RgbaFrame> for frame::Video {
+    fn from(frame: &RgbaFrame) -> Self {
+        let mut video = frame::Video::new(Pixel::RGBA, frame.width, frame.height);
+        let (stride, row) = (video.stride(0), frame.width as usize * 4);
+
+        for (y, line) in video
+            .data_mut(0)
+            .chunks_mut(stride)
+            .take(frame.height as usize)
+            .enumerate()
+        {
+            line[..row].copy_from_slice(frame.row(y as u32));
+        }
+
+        video
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    // 2x2 with a row of red and a row of blue, each padded to 12 bytes
+    fn padded() -> RgbaFrame {
+        let data = [RED, RED, [0; 4], BLUE, BLUE].concat();
+        RgbaFrame::new(Arc::new(data), 2, 2, 12).unwrap()
+    }
+
+    #[test]
+    fn checks_geometry() {
+        assert!(RgbaFrame::new(Arc::new(vec![0; 16]), 2, 2, 4).is_err());
+        assert!(RgbaFrame::new(Arc::new(vec![0; 19]), 2, 2, 12).is_err());
+        assert!(RgbaFrame::packed(vec![], 0, 0).is_ok());
+    }
+
+    #[test]
+    fn reads_rows_without_padding() {
+        let frame = padded();
+
+        assert_eq!(frame.row(1), [BLUE, BLUE].concat());
+        assert_eq!(frame.pixel(1, 0), RED);
+        assert_eq!(
+            frame.into_image().into_raw(),
+            [RED, RED, BLUE, BLUE].concat()
+        );
+    }
+
+    #[test]
+    fn copies_shared_data_on_write() {
+        let frame = padded();
+        let mut copy = frame.clone();
+        copy.data_mut()[0] = 0;
+
+        assert_eq!(frame.pixel(0, 0), RED);
+        assert_eq!(copy.pixel(0, 0), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn round_trips_ffmpeg_frames() {
+        let video = frame::Video::from(&padded());
+        let frame = RgbaFrame::try_from(&video).unwrap();
+
+        assert!(frame.is_packed());
+        assert_eq!(
+            frame.into_image().into_raw(),
+            [RED, RED, BLUE, BLUE].concat()
+        );
+        assert!(RgbaFrame::try_from(&frame::Video::new(Pixel::NV12, 2, 2)).is_err());
+    }
+}
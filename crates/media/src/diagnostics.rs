@@ -0,0 +1,201 @@
+use std::{
+    ffi::{CStr, CString, c_char},
+    fmt,
+    ptr::null,
+};
+
+use ffmpeg::{
+    codec::{decoder, encoder},
+    sys::{
+        AVHWDeviceType, av_guess_format, av_hwdevice_get_type_name, av_hwdevice_iterate_types,
+        av_version_info, avutil_configuration,
+    },
+};
+
+/// Encoders Cap uses, by FFmpeg name. Hardware encoders are listed for every platform,
+/// so some are always missing.
+const ENCODERS: &[&str] = &[
+    "libx264",
+    "h264_videotoolbox",
+    "h264_nvenc",
+    "h264_qsv",
+    "h264_mf",
+    "libx265",
+    "hevc_videotoolbox",
+    "hevc_nvenc",
+    "hevc_qsv",
+    "libvpx-vp9",
+    "libsvtav1",
+    "libaom-av1",
+    "librav1e",
+    "aac",
+    "libopus",
+    "gif",
+    "apng",
+    "png",
+];
+
+/// Decoders for the codecs Cap records and exports, and cameras send.
+const DECODERS: &[&str] = &[
+    "h264", "hevc", "vp9", "libdav1d", "av1", "mjpeg", "aac", "opus", "png",
+];
+
+const MUXERS: &[&str] = &["mp4", "webm", "gif", "apng"];
+
+/// Whether FFmpeg was built with an encoder, decoder or muxer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FFmpegComponent {
+    pub name: &'static str,
+    pub available: bool,
+}
+
+/// How the linked FFmpeg was built, as far as Cap is concerned.
+///
+/// Displays as a report that can be pasted into a bug report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostics {
+    /// FFmpeg's release, or a git revision for development builds
+    pub ffmpeg_version: String,
+    /// The options FFmpeg was configured with
+    pub configuration: String,
+    /// Hardware device types FFmpeg supports. The hardware itself may still be missing.
+    pub hw_device_types: Vec<String>,
+    pub encoders: Vec<FFmpegComponent>,
+    pub decoders: Vec<FFmpegComponent>,
+    pub muxers: Vec<FFmpegComponent>,
+}
+
+impl Diagnostics {
+    /// Names of the muxers Cap exports with that FFmpeg was built without.
+    pub fn missing_muxers(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.muxers.iter().filter(|m| !m.available).map(|m| m.name)
+    }
+}
+
+/// Reports the linked FFmpeg's version and which of the codecs, muxers and
+/// hardware devices Cap uses it supports.
+pub fn diagnostics() -> Diagnostics {
+    Diagnostics {
+        ffmpeg_version: c_string(unsafe { av_version_info() }),
+        configuration: c_string(unsafe { avutil_configuration() }),
+        hw_device_types: hw_device_types(),
+        encoders: components(ENCODERS, |name| encoder::find_by_name(name).is_some()),
+        decoders: components(DECODERS, |name| decoder::find_by_name(name).is_some()),
+        muxers: components(MUXERS, has_muxer),
+    }
+}
+
+fn components(names: &[&'static str], available: impl Fn(&str) -> bool) -> Vec<FFmpegComponent> {
+    names
+        .iter()
+        .map(|&name| FFmpegComponent {
+            name,
+            available: available(name),
+        })
+        .collect()
+}
+
+fn hw_device_types() -> Vec<String> {
+    let mut types = vec![];
+    let mut device_type = AVHWDeviceType::AV_HWDEVICE_TYPE_NONE;
+
+    loop {
+        device_type = unsafe { av_hwdevice_iterate_types(device_type) };
+        if device_type == AVHWDeviceType::AV_HWDEVICE_TYPE_NONE {
+            return types;
+        }
+
+        types.push(c_string(unsafe { av_hwdevice_get_type_name(device_type) }));
+    }
+}
+
+fn has_muxer(name: &str) -> bool {
+    let Ok(name) = CString::new(name) else {
+        return false;
+    };
+
+    !unsafe { av_guess_format(name.as_ptr(), null(), null()) }.is_null()
+}
+
+fn c_string(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+
+    unsafe { CStr::from_ptr(ptr) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(
+            f: &mut fmt::Formatter<'_>,
+            label: &str,
+            components: &[FFmpegComponent],
+        ) -> fmt::Result {
+            let names = |available: bool| {
+                components
+                    .iter()
+                    .filter(|c| c.available == available)
+                    .map(|c| c.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+
+            write!(
+                f,
+                "\n{label}: {}\n{label} missing: {}",
+                names(true),
+                names(false)
+            )
+        }
+
+        writeln!(f, "FFmpeg {}", self.ffmpeg_version)?;
+        writeln!(f, "configuration: {}", self.configuration)?;
+        write!(f, "hardware devices: {}", self.hw_device_types.join(", "))?;
+        list(f, "encoders", &self.encoders)?;
+        list(f, "decoders", &self.decoders)?;
+        list(f, "muxers", &self.muxers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_available_and_missing_components() {
+        let diagnostics = Diagnostics {
+            ffmpeg_version: "7.1".to_string(),
+            configuration: "--enable-libx264".to_string(),
+            hw_device_types: vec!["videotoolbox".to_string()],
+            encoders: vec![],
+            decoders: vec![],
+            muxers: vec![
+                FFmpegComponent {
+                    name: "mp4",
+                    available: true,
+                },
+                FFmpegComponent {
+                    name: "apng",
+                    available: false,
+                },
+            ],
+        };
+
+        assert_eq!(diagnostics.missing_muxers().collect::<Vec<_>>(), ["apng"]);
+
+        let report = diagnostics.to_string();
+        assert!(report.starts_with("FFmpeg 7.1\n"));
+        assert!(report.contains("\nmuxers: mp4\nmuxers missing: apng"));
+    }
+
+    #[test]
+    fn finds_muxers_by_name() {
+        ffmpeg::init().unwrap();
+
+        assert!(has_muxer("mp4"));
+        assert!(!has_muxer("not-a-muxer"));
+    }
+}
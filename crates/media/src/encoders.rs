@@ -0,0 +1,83 @@
+use std::{
+    ffi::{CString, c_int},
+    ptr::null,
+};
+
+use ffmpeg::{
+    codec::{self, encoder},
+    sys::{av_guess_format, avformat_query_codec},
+};
+
+mod exif;
+mod still_image;
+
+pub use exif::ImageMetadata;
+pub use still_image::{ImageFrame, StillImageFormat, encode_image, encode_image_with_metadata};
+
+/// Suffixes FFmpeg uses for hardware encoder names, e.g. `h264_videotoolbox`.
+const HARDWARE_ENCODER_SUFFIXES: &[&str] = &["videotoolbox", "nvenc", "qsv"];
+/// `FF_COMPLIANCE_NORMAL`, which rules out codecs a muxer only supports experimentally.
+const COMPLIANCE_NORMAL: c_int = 0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CodecSupport {
+    /// FFmpeg was built with a software encoder for the codec
+    pub software: bool,
+    /// FFmpeg was built with a hardware encoder for the codec.
+    /// The hardware itself may still be missing at runtime.
+    pub hardware: bool,
+}
+
+impl CodecSupport {
+    pub fn is_available(&self) -> bool {
+        self.software || self.hardware
+    }
+}
+
+/// Encoders that FFmpeg was built with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncoderCapabilities {
+    pub h264: CodecSupport,
+    pub h265: CodecSupport,
+    pub vp9: CodecSupport,
+    pub av1: CodecSupport,
+    pub aac: CodecSupport,
+    pub opus: CodecSupport,
+}
+
+/// Checks which encoders are available, so that formats which would fail with
+/// [`MediaError::MissingCodec`](crate::MediaError::MissingCodec) can be ruled out up front.
+pub fn available_encoders() -> EncoderCapabilities {
+    EncoderCapabilities {
+        h264: probe(&["libx264"], "h264"),
+        h265: probe(&["libx265"], "hevc"),
+        vp9: probe(&["libvpx-vp9"], "vp9"),
+        av1: probe(&["libsvtav1", "libaom-av1", "librav1e"], "av1"),
+        aac: probe(&["aac"], "aac"),
+        opus: probe(&["libopus"], "opus"),
+    }
+}
+
+/// Whether FFmpeg can store `codec` in the container it muxes for `format`, e.g. `"mp4"`,
+/// without enabling experimental features.
+pub fn container_supports(format: &str, codec: codec::Id) -> bool {
+    let Ok(format) = CString::new(format) else {
+        return false;
+    };
+
+    let output = unsafe { av_guess_format(format.as_ptr(), null(), null()) };
+
+    !output.is_null()
+        && unsafe { avformat_query_codec(output, codec.into(), COMPLIANCE_NORMAL) } == 1
+}
+
+fn probe(software_encoders: &[&str], hardware_prefix: &str) -> CodecSupport {
+    CodecSupport {
+        software: software_encoders
+            .iter()
+            .any(|name| encoder::find_by_name(name).is_some()),
+        hardware: HARDWARE_ENCODER_SUFFIXES
+            .iter()
+            .any(|suffix| encoder::find_by_name(&format!("{hardware_prefix}_{suffix}")).is_some()),
+    }
+}
@@ -0,0 +1,210 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Basic EXIF tags for still images.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageMetadata {
+    /// Name of the app that created the image
+    pub software: Option<String>,
+    /// Written in UTC, as EXIF dates have no time zone
+    pub created_at: Option<SystemTime>,
+    pub description: Option<String>,
+}
+
+impl ImageMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.software.is_none() && self.created_at.is_none() && self.description.is_none()
+    }
+
+    /// A little-endian TIFF structure with a single IFD, as stored in EXIF segments and chunks.
+    fn tiff(&self) -> Vec<u8> {
+        // sorted by tag, as readers expect
+        let entries = [
+            (0x010e, self.description.clone()),
+            (0x0131, self.software.clone()),
+            (0x0132, self.created_at.map(exif_datetime)),
+        ]
+        .into_iter()
+        .filter_map(|(tag, value)| {
+            let mut value = value?.into_bytes();
+            value.push(0);
+            Some((tag as u16, value))
+        })
+        .collect::<Vec<_>>();
+
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+
+        let mut data = vec![];
+        let data_offset = 8 + 2 + entries.len() * 12 + 4;
+
+        for (tag, value) in &entries {
+            tiff.extend_from_slice(&tag.to_le_bytes());
+            // ASCII
+            tiff.extend_from_slice(&2u16.to_le_bytes());
+            tiff.extend_from_slice(&(value.len() as u32).to_le_bytes());
+
+            if value.len() <= 4 {
+                let mut inline = [0; 4];
+                inline[..value.len()].copy_from_slice(value);
+                tiff.extend_from_slice(&inline);
+            } else {
+                tiff.extend_from_slice(&((data_offset + data.len()) as u32).to_le_bytes());
+                data.extend_from_slice(value);
+                // values start on word boundaries
+                if data.len() % 2 == 1 {
+                    data.push(0);
+                }
+            }
+        }
+
+        // no further IFDs
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.extend_from_slice(&data);
+
+        tiff
+    }
+}
+
+/// Adds an APP1 EXIF segment after the JFIF header of an encoded JPEG.
+pub(super) fn insert_jpeg_exif(jpeg: &mut Vec<u8>, metadata: &ImageMetadata) {
+    if metadata.is_empty() || !jpeg.starts_with(&[0xff, 0xd8]) {
+        return;
+    }
+
+    let mut offset = 2;
+    if jpeg[offset..].starts_with(&[0xff, 0xe0]) && jpeg.len() >= offset + 4 {
+        offset += 2 + u16::from_be_bytes([jpeg[offset + 2], jpeg[offset + 3]]) as usize;
+    }
+
+    let tiff = metadata.tiff();
+    let mut segment = vec![0xff, 0xe1];
+    segment.extend_from_slice(&((2 + 6 + tiff.len()) as u16).to_be_bytes());
+    segment.extend_from_slice(b"Exif\0\0");
+    segment.extend_from_slice(&tiff);
+
+    jpeg.splice(offset..offset, segment);
+}
+
+/// Adds an `eXIf` chunk after the `IHDR` chunk of an encoded PNG.
+pub(super) fn insert_png_exif(png: &mut Vec<u8>, metadata: &ImageMetadata) {
+    // signature, then the IHDR chunk's length, type, 13 bytes of data and CRC
+    const IHDR_END: usize = 8 + 4 + 4 + 13 + 4;
+
+    if metadata.is_empty() || png.get(12..16) != Some(b"IHDR") || png.len() < IHDR_END {
+        return;
+    }
+
+    let tiff = metadata.tiff();
+    let mut chunk = (tiff.len() as u32).to_be_bytes().to_vec();
+    chunk.extend_from_slice(b"eXIf");
+    chunk.extend_from_slice(&tiff);
+    chunk.extend_from_slice(&crc32(&chunk[4..]).to_be_bytes());
+
+    png.splice(IHDR_END..IHDR_END, chunk);
+}
+
+/// `YYYY:MM:DD HH:MM:SS` in UTC.
+fn exif_datetime(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+
+    // days since the epoch to a civil date, from Howard Hinnant's `civil_from_days`
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{year:04}:{month:02}:{day:02} {:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// The CRC used by PNG chunks.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_datetime_in_utc() {
+        assert_eq!(exif_datetime(UNIX_EPOCH), "1970:01:01 00:00:00");
+        assert_eq!(
+            exif_datetime(UNIX_EPOCH + Duration::from_secs(1_709_210_096)),
+            "2024:02:29 12:34:56"
+        );
+    }
+
+    #[test]
+    fn computes_png_crc() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    }
+
+    #[test]
+    fn writes_ascii_tags() {
+        let tiff = ImageMetadata {
+            software: Some("Cap".into()),
+            created_at: None,
+            description: Some("A screenshot".into()),
+        }
+        .tiff();
+
+        assert_eq!(&tiff[0..8], b"II*\0\x08\0\0\0");
+        // two entries
+        assert_eq!(&tiff[8..10], [2, 0]);
+        // description, stored after the IFD
+        assert_eq!(&tiff[10..14], [0x0e, 0x01, 2, 0]);
+        assert_eq!(&tiff[14..18], [13, 0, 0, 0]);
+        assert_eq!(&tiff[18..22], [38, 0, 0, 0]);
+        // software, short enough to be stored inline
+        assert_eq!(
+            &tiff[22..34],
+            [0x31, 0x01, 2, 0, 4, 0, 0, 0, b'C', b'a', b'p', 0]
+        );
+        assert_eq!(&tiff[38..51], b"A screenshot\0");
+    }
+
+    #[test]
+    fn inserts_jpeg_segment_after_jfif_header() {
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0, 4, 1, 2, 0xff, 0xd9];
+        let metadata = ImageMetadata {
+            software: Some("Cap".into()),
+            ..Default::default()
+        };
+
+        insert_jpeg_exif(&mut jpeg, &metadata);
+
+        assert_eq!(&jpeg[8..10], [0xff, 0xe1]);
+        assert_eq!(&jpeg[12..18], b"Exif\0\0");
+        assert_eq!(&jpeg[jpeg.len() - 2..], [0xff, 0xd9]);
+    }
+}
@@ -0,0 +1,232 @@
+use std::path::Path;
+
+use image::{
+    ExtendedColorType, ImageEncoder, RgbaImage,
+    codecs::{jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder},
+};
+
+use super::exif::{ImageMetadata, insert_jpeg_exif, insert_png_exif};
+use crate::MediaError;
+
+/// RGBA pixels to be encoded as a still image.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageFrame<'a> {
+    pub data: &'a [u8],
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per row, which may include padding after each row's pixels
+    pub stride: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StillImageFormat {
+    /// Lossless, keeping the alpha channel
+    Png,
+    /// Lossy, dropping the alpha channel. `quality` ranges from 1 to 100.
+    Jpeg { quality: u8 },
+    /// Lossless, keeping the alpha channel
+    WebP,
+}
+
+impl StillImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg { .. } => "jpg",
+            Self::WebP => "webp",
+        }
+    }
+}
+
+/// Writes a frame to `path` as a single image, e.g. for screenshots and thumbnails.
+pub fn encode_image(
+    frame: ImageFrame,
+    format: StillImageFormat,
+    path: impl AsRef<Path>,
+) -> Result<(), MediaError> {
+    encode_image_with_metadata(frame, format, path, &ImageMetadata::default())
+}
+
+/// Like [`encode_image`], also writing `metadata` as EXIF for PNG and JPEG images.
+/// WebP images are written without it.
+pub fn encode_image_with_metadata(
+    frame: ImageFrame,
+    format: StillImageFormat,
+    path: impl AsRef<Path>,
+    metadata: &ImageMetadata,
+) -> Result<(), MediaError> {
+    let path = path.as_ref();
+    let image = pack_rows(frame)?;
+    let mut encoded = vec![];
+
+    let (width, height) = image.dimensions();
+    match format {
+        StillImageFormat::Png => PngEncoder::new(&mut encoded).write_image(
+            &image,
+            width,
+            height,
+            ExtendedColorType::Rgba8,
+        ),
+        StillImageFormat::Jpeg { quality } => {
+            let rgb = image::DynamicImage::ImageRgba8(image).into_rgb8();
+            JpegEncoder::new_with_quality(&mut encoded, quality.clamp(1, 100)).write_image(
+                &rgb,
+                width,
+                height,
+                ExtendedColorType::Rgb8,
+            )
+        }
+        StillImageFormat::WebP => WebPEncoder::new_lossless(&mut encoded).write_image(
+            &image,
+            width,
+            height,
+            ExtendedColorType::Rgba8,
+        ),
+    }
+    .map_err(|e| MediaError::Any(format!("Image/{}/{e}", path.display()).into()))?;
+
+    match format {
+        StillImageFormat::Png => insert_png_exif(&mut encoded, metadata),
+        StillImageFormat::Jpeg { .. } => insert_jpeg_exif(&mut encoded, metadata),
+        StillImageFormat::WebP => {}
+    }
+
+    std::fs::write(path, encoded)?;
+
+    Ok(())
+}
+
+/// Copies the frame's rows into a tightly packed image.
+fn pack_rows(frame: ImageFrame) -> Result<RgbaImage, MediaError> {
+    let row_length = frame.width as usize * 4;
+    let stride = frame.stride as usize;
+
+    let needed = match frame.height as usize {
+        0 => 0,
+        height => stride * (height - 1) + row_length,
+    };
+
+    if stride < row_length || frame.data.len() < needed {
+        return Err(MediaError::Any(
+            format!(
+                "Image frame of {}x{} with stride {} doesn't fit in {} bytes",
+                frame.width,
+                frame.height,
+                frame.stride,
+                frame.data.len()
+            )
+            .into(),
+        ));
+    }
+
+    let data = frame
+        .data
+        .chunks(stride)
+        .take(frame.height as usize)
+        .flat_map(|row| &row[..row_length])
+        .copied()
+        .collect();
+
+    // the length was checked above
+    Ok(RgbaImage::from_raw(frame.width, frame.height, data).unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 2x2 with 4 bytes of padding after each row
+    const PADDED: [u8; 24] = [
+        255, 0, 0, 255, 0, 255, 0, 255, 9, 9, 9, 9, //
+        0, 0, 255, 255, 255, 255, 255, 128, 9, 9, 9, 9,
+    ];
+
+    fn encode_and_read(format: StillImageFormat) -> RgbaImage {
+        let path = std::env::temp_dir().join(format!(
+            "cap-media-encode-image-{}.{}",
+            std::process::id(),
+            format.extension()
+        ));
+        let frame = ImageFrame {
+            data: &PADDED,
+            width: 2,
+            height: 2,
+            stride: 12,
+        };
+
+        encode_image(frame, format, &path).unwrap();
+        let image = image::open(&path).unwrap().into_rgba8();
+        std::fs::remove_file(&path).ok();
+
+        image
+    }
+
+    #[test]
+    fn lossless_formats_round_trip() {
+        for format in [StillImageFormat::Png, StillImageFormat::WebP] {
+            let image = encode_and_read(format);
+
+            assert_eq!(image.dimensions(), (2, 2));
+            assert_eq!(
+                image.into_raw(),
+                [
+                    255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 255, 255, 255, 128
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn writes_exif_that_decoders_skip() {
+        let metadata = ImageMetadata {
+            software: Some("Cap".into()),
+            created_at: Some(std::time::SystemTime::now()),
+            description: None,
+        };
+
+        for format in [
+            StillImageFormat::Png,
+            StillImageFormat::Jpeg { quality: 90 },
+        ] {
+            let path = std::env::temp_dir().join(format!(
+                "cap-media-encode-image-exif-{}.{}",
+                std::process::id(),
+                format.extension()
+            ));
+            let frame = ImageFrame {
+                data: &PADDED,
+                width: 2,
+                height: 2,
+                stride: 12,
+            };
+
+            encode_image_with_metadata(frame, format, &path, &metadata).unwrap();
+            let bytes = std::fs::read(&path).unwrap();
+            let image = image::open(&path).unwrap();
+            std::fs::remove_file(&path).ok();
+
+            assert_eq!((image.width(), image.height()), (2, 2));
+            assert!(bytes.windows(4).any(|w| w == b"Cap\0"));
+        }
+    }
+
+    #[test]
+    fn jpeg_drops_alpha() {
+        let image = encode_and_read(StillImageFormat::Jpeg { quality: 100 });
+
+        assert_eq!(image.dimensions(), (2, 2));
+        assert!(image.pixels().all(|p| p[3] == 255));
+    }
+
+    #[test]
+    fn rejects_short_buffers() {
+        let frame = ImageFrame {
+            data: &[0; 15],
+            width: 2,
+            height: 2,
+            stride: 8,
+        };
+
+        assert!(pack_rows(frame).is_err());
+    }
+}
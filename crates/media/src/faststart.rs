@@ -0,0 +1,15 @@
+use std::path::Path;
+
+use ffmpeg::Dictionary;
+
+use crate::{MediaError, remux::mux_streams};
+
+/// Re-muxes an MP4 so that its `moov` atom is at the front of the file, allowing web
+/// players to start playback before the whole file has downloaded.
+///
+/// Streams are copied as-is without re-encoding, equivalent to
+/// `ffmpeg -i input -c copy -movflags +faststart output`.
+/// `input` and `output` must be different files.
+pub fn faststart(input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<(), MediaError> {
+    mux_streams(&[input], output, true, Dictionary::new())
+}
@@ -0,0 +1,206 @@
+use ffmpeg::{ChannelLayout, format::Sample, frame};
+
+use crate::MediaError;
+
+// -3dB, so a sound panned to the center stays as loud after mixing two channels into one
+const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Converts audio to a different channel layout, e.g. to export mono audio from a
+/// stereo timeline.
+///
+/// Stereo is downmixed to mono by summing both channels at -3dB, and mono is upmixed to
+/// stereo by playing it on both channels. 5.1 is downmixed to stereo ITU style, with the
+/// center and surround channels at -3dB and the LFE channel dropped.
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelConverter {
+    target: ChannelLayout,
+}
+
+impl ChannelConverter {
+    pub fn new(target: ChannelLayout) -> Self {
+        Self { target }
+    }
+
+    pub fn target(&self) -> ChannelLayout {
+        self.target
+    }
+
+    /// Returns the frame mixed into the target layout, or the frame itself if it already
+    /// has as many channels. Only `f32` audio is supported.
+    pub fn apply(&self, frame: frame::Audio) -> Result<frame::Audio, MediaError> {
+        let (from, to) = (frame.channels() as usize, self.target.channels() as usize);
+        if from == to {
+            return Ok(frame);
+        }
+
+        if !matches!(frame.format(), Sample::F32(_)) {
+            return Err(MediaError::Any(
+                format!(
+                    "Unsupported sample format for channel conversion: {:?}",
+                    frame.format()
+                )
+                .into(),
+            ));
+        }
+
+        let samples = frame.samples();
+        let mixed = self.apply_samples(&interleaved(&frame), from)?;
+
+        let mut output = frame::Audio::new(frame.format(), samples, self.target);
+        output.set_rate(frame.rate());
+        output.set_pts(frame.pts());
+
+        if output.is_planar() {
+            for channel in 0..to {
+                let plane = &mut output.data_mut(channel)[..samples * 4];
+                for (bytes, instant) in plane.chunks_exact_mut(4).zip(mixed.chunks_exact(to)) {
+                    bytes.copy_from_slice(&instant[channel].to_ne_bytes());
+                }
+            }
+        } else {
+            let data = &mut output.data_mut(0)[..samples * to * 4];
+            for (bytes, sample) in data.chunks_exact_mut(4).zip(&mixed) {
+                bytes.copy_from_slice(&sample.to_ne_bytes());
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Same as [`ChannelConverter::apply`], for interleaved `f32` samples with `channels`
+    /// channels. Returns interleaved samples in the target layout.
+    pub fn apply_samples(&self, samples: &[f32], channels: usize) -> Result<Vec<f32>, MediaError> {
+        let to = self.target.channels() as usize;
+        let matrix = mix_matrix(channels, to).ok_or_else(|| {
+            MediaError::Any(
+                format!("Can't convert {channels} channel audio to {to} channels").into(),
+            )
+        })?;
+
+        let mut output = Vec::with_capacity(samples.len() / channels * to);
+        for instant in samples.chunks_exact(channels) {
+            output.extend(matrix.iter().map(|row| {
+                row.iter()
+                    .zip(instant)
+                    .map(|(weight, sample)| weight * sample)
+                    .sum::<f32>()
+                    .clamp(-1.0, 1.0)
+            }));
+        }
+
+        Ok(output)
+    }
+}
+
+/// How much of each input channel goes into each output channel, by output channel.
+fn mix_matrix(from: usize, to: usize) -> Option<Vec<Vec<f32>>> {
+    Some(match (from, to) {
+        _ if from == to => (0..to)
+            .map(|i| (0..from).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+            .collect(),
+        (1, 2) => vec![vec![1.0], vec![1.0]],
+        (2, 1) => vec![vec![MINUS_3DB, MINUS_3DB]],
+        // FL, FR, FC, LFE, then the surrounds, which are the back or side channels
+        (6, 2) => vec![
+            vec![1.0, 0.0, MINUS_3DB, 0.0, MINUS_3DB, 0.0],
+            vec![0.0, 1.0, MINUS_3DB, 0.0, 0.0, MINUS_3DB],
+        ],
+        (6, 1) => {
+            let stereo = mix_matrix(6, 2)?;
+            vec![
+                (0..6)
+                    .map(|j| MINUS_3DB * (stereo[0][j] + stereo[1][j]))
+                    .collect(),
+            ]
+        }
+        _ => return None,
+    })
+}
+
+fn interleaved(frame: &frame::Audio) -> Vec<f32> {
+    let (samples, channels) = (frame.samples(), frame.channels() as usize);
+    let sample = |bytes: &[u8]| f32::from_ne_bytes(bytes.try_into().unwrap());
+
+    if !frame.is_planar() {
+        return frame.data(0)[..samples * channels * 4]
+            .chunks_exact(4)
+            .map(sample)
+            .collect();
+    }
+
+    let mut output = vec![0.0; samples * channels];
+    for channel in 0..channels {
+        let plane = &frame.data(channel)[..samples * 4];
+        for (i, bytes) in plane.chunks_exact(4).enumerate() {
+            output[i * channels + channel] = sample(bytes);
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use ffmpeg::format::sample::Type;
+
+    use super::*;
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-4, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn downmixes_stereo_at_minus_3db() {
+        let converter = ChannelConverter::new(ChannelLayout::MONO);
+        let mono = converter
+            .apply_samples(&[0.5, 0.5, 1.0, -1.0, 1.0, 1.0], 2)
+            .unwrap();
+
+        assert_close(&mono, &[0.7071, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn upmixes_mono_to_both_channels() {
+        let converter = ChannelConverter::new(ChannelLayout::STEREO);
+        let stereo = converter.apply_samples(&[0.25, -0.5], 1).unwrap();
+
+        assert_eq!(stereo, [0.25, 0.25, -0.5, -0.5]);
+    }
+
+    #[test]
+    fn downmixes_5_1_to_stereo() {
+        let converter = ChannelConverter::new(ChannelLayout::STEREO);
+        // FL, FR, FC, LFE, BL, BR
+        let stereo = converter
+            .apply_samples(&[0.2, 0.0, 0.4, 1.0, 0.0, 0.2], 6)
+            .unwrap();
+
+        assert_close(&stereo, &[0.2 + 0.4 * 0.7071, 0.4 * 0.7071 + 0.2 * 0.7071]);
+        assert!(converter.apply_samples(&[0.0; 4], 4).is_err());
+    }
+
+    #[test]
+    fn converts_frames() {
+        let mut frame = frame::Audio::new(Sample::F32(Type::Planar), 2, ChannelLayout::STEREO);
+        frame.set_rate(48_000);
+        frame.set_pts(Some(960));
+        frame.data_mut(0)[..8].copy_from_slice(f32_bytes(&[0.5, 1.0]).as_slice());
+        frame.data_mut(1)[..8].copy_from_slice(f32_bytes(&[0.5, -1.0]).as_slice());
+
+        let mono = ChannelConverter::new(ChannelLayout::MONO)
+            .apply(frame)
+            .unwrap();
+
+        assert_eq!(mono.channels(), 1);
+        assert_eq!(mono.rate(), 48_000);
+        assert_eq!(mono.pts(), Some(960));
+        assert_close(&interleaved(&mono), &[0.7071, 0.0]);
+    }
+
+    fn f32_bytes(samples: &[f32]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_ne_bytes()).collect()
+    }
+}
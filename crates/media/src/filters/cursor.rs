@@ -0,0 +1,337 @@
+use ffmpeg::{format::Pixel, frame};
+use image::{RgbaImage, imageops};
+
+use crate::MediaError;
+
+/// Where the cursor was at a point in time, in the coordinates of the frames it's drawn onto.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CursorSample {
+    /// Seconds since the start of the recording
+    pub time: f64,
+    /// Position from the left edge, from 0 to 1
+    pub x: f64,
+    /// Position from the top edge, from 0 to 1
+    pub y: f64,
+    /// Whether a mouse button is held down
+    pub pressed: bool,
+}
+
+/// A translucent circle drawn under the cursor while a mouse button is held down.
+#[derive(Debug, Clone, Copy)]
+pub struct ClickHighlight {
+    /// Radius in pixels of the cursor image
+    pub radius: f32,
+    pub color: [u8; 4],
+}
+
+impl Default for ClickHighlight {
+    fn default() -> Self {
+        Self {
+            radius: 24.0,
+            color: [255, 214, 0, 110],
+        }
+    }
+}
+
+/// Composites a cursor image onto video frames, following a list of cursor samples.
+///
+/// The position at each frame's time is interpolated between the surrounding samples,
+/// and can be smoothed further so low-rate cursor captures don't look jittery.
+/// Like [`WatermarkFilter`](super::WatermarkFilter), only the color channels are changed.
+pub struct CursorOverlay {
+    image: RgbaImage,
+    /// Point of the image that's placed at the cursor position, in image pixels
+    hotspot: (f64, f64),
+    size: f32,
+    click_highlight: Option<ClickHighlight>,
+    /// Time constant of the smoothing in seconds, or 0 to follow the samples exactly
+    smoothing: f64,
+    samples: Vec<CursorSample>,
+    /// Time and position the cursor was last drawn at
+    smoothed: Option<(f64, (f64, f64))>,
+    /// The image scaled by `size`
+    scaled: Option<RgbaImage>,
+}
+
+impl CursorOverlay {
+    pub fn new(image: RgbaImage, hotspot: (f64, f64)) -> Self {
+        Self {
+            image,
+            hotspot,
+            size: 1.0,
+            click_highlight: None,
+            smoothing: 0.0,
+            samples: vec![],
+            smoothed: None,
+            scaled: None,
+        }
+    }
+
+    /// Scale of the cursor image, where 1 draws it at its own size.
+    pub fn with_size(mut self, size: f32) -> Self {
+        self.size = size.max(0.0);
+        self.scaled = None;
+        self
+    }
+
+    pub fn with_click_highlight(mut self, click_highlight: ClickHighlight) -> Self {
+        self.click_highlight = Some(click_highlight);
+        self
+    }
+
+    /// Eases the cursor towards each sampled position over roughly `seconds`,
+    /// instead of moving it in straight lines between samples.
+    pub fn with_smoothing(mut self, seconds: f64) -> Self {
+        self.smoothing = seconds.max(0.0);
+        self
+    }
+
+    /// Samples must be pushed in time order.
+    pub fn push_sample(&mut self, sample: CursorSample) {
+        self.samples.push(sample);
+    }
+
+    /// Draws the cursor at where it was at `time` seconds.
+    /// Supports packed 8-bit RGBA and BGRA frames.
+    pub fn apply(&mut self, frame: &mut frame::Video, time: f64) -> Result<(), MediaError> {
+        let channels = match frame.format() {
+            Pixel::RGBA | Pixel::RGBZ => [0, 1, 2],
+            Pixel::BGRA | Pixel::BGRZ => [2, 1, 0],
+            format => {
+                return Err(MediaError::Any(
+                    format!("Cursor overlay doesn't support {format:?} frames").into(),
+                ));
+            }
+        };
+
+        let (width, height, stride) = (frame.width(), frame.height(), frame.stride(0));
+        self.blend(frame.data_mut(0), stride, width, height, time, channels);
+
+        Ok(())
+    }
+
+    fn blend(
+        &mut self,
+        data: &mut [u8],
+        stride: usize,
+        width: u32,
+        height: u32,
+        time: f64,
+        channels: [usize; 3],
+    ) {
+        let Some((position, pressed)) = self.position_at(time) else {
+            return;
+        };
+        let (x, y) = (position.0 * width as f64, position.1 * height as f64);
+        let mut canvas = Canvas {
+            data,
+            stride,
+            width,
+            height,
+            channels,
+        };
+
+        if pressed && let Some(highlight) = self.click_highlight {
+            canvas.circle(x, y, (highlight.radius * self.size) as f64, highlight.color);
+        }
+
+        let size = self.size as f64;
+        let scaled = self.scaled.get_or_insert_with(|| {
+            let (image_width, image_height) = self.image.dimensions();
+            imageops::resize(
+                &self.image,
+                ((image_width as f64 * size).round() as u32).max(1),
+                ((image_height as f64 * size).round() as u32).max(1),
+                imageops::FilterType::Triangle,
+            )
+        });
+
+        let left = (x - self.hotspot.0 * size).round() as i64;
+        let top = (y - self.hotspot.1 * size).round() as i64;
+        canvas.image(left, top, scaled);
+    }
+
+    /// Interpolates the samples at `time` and applies smoothing, dropping samples
+    /// that are no longer needed. Returns the position and whether a button is pressed.
+    fn position_at(&mut self, time: f64) -> Option<((f64, f64), bool)> {
+        let next = self.samples.partition_point(|s| s.time <= time);
+        // keep the last sample before `time`, as frames only move forward
+        self.samples.drain(..next.saturating_sub(1));
+
+        let (target, pressed) = match self.samples.as_slice() {
+            [] => return None,
+            [prev, next, ..] if prev.time <= time => {
+                let t = ((time - prev.time) / (next.time - prev.time)).clamp(0.0, 1.0);
+                (
+                    (
+                        prev.x + (next.x - prev.x) * t,
+                        prev.y + (next.y - prev.y) * t,
+                    ),
+                    prev.pressed,
+                )
+            }
+            [sample, ..] => ((sample.x, sample.y), sample.pressed),
+        };
+
+        let position = match self.smoothed {
+            Some((last_time, last)) if self.smoothing > 0.0 && time > last_time => {
+                let amount = 1.0 - (-(time - last_time) / self.smoothing).exp();
+                (
+                    last.0 + (target.0 - last.0) * amount,
+                    last.1 + (target.1 - last.1) * amount,
+                )
+            }
+            _ => target,
+        };
+
+        self.smoothed = Some((time, position));
+
+        Some((position, pressed))
+    }
+}
+
+/// Packed 4-byte pixels that overlays blend shapes and images into.
+pub(super) struct Canvas<'a> {
+    pub data: &'a mut [u8],
+    pub stride: usize,
+    pub width: u32,
+    pub height: u32,
+    /// Byte offsets of red, green and blue in each pixel
+    pub channels: [usize; 3],
+}
+
+impl Canvas<'_> {
+    fn blend_pixel(&mut self, x: i64, y: i64, color: [u8; 3], alpha: f32) {
+        if alpha <= 0.0 || x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+
+        let offset = y as usize * self.stride + x as usize * 4;
+        for (i, channel) in self.channels.iter().enumerate() {
+            let value = &mut self.data[offset + channel];
+            *value = (color[i] as f32 * alpha + *value as f32 * (1.0 - alpha)).round() as u8;
+        }
+    }
+
+    fn image(&mut self, left: i64, top: i64, image: &RgbaImage) {
+        for (image_y, row) in image.rows().enumerate() {
+            for (image_x, pixel) in row.enumerate() {
+                self.blend_pixel(
+                    left + image_x as i64,
+                    top + image_y as i64,
+                    [pixel[0], pixel[1], pixel[2]],
+                    pixel[3] as f32 / 255.0,
+                );
+            }
+        }
+    }
+
+    /// Fills a circle, anti-aliasing its edge over one pixel.
+    pub fn circle(&mut self, cx: f64, cy: f64, radius: f64, color: [u8; 4]) {
+        let alpha = color[3] as f32 / 255.0;
+
+        for y in (cy - radius).floor() as i64..=(cy + radius).ceil() as i64 {
+            for x in (cx - radius).floor() as i64..=(cx + radius).ceil() as i64 {
+                let distance =
+                    ((x as f64 + 0.5 - cx).powi(2) + (y as f64 + 0.5 - cy).powi(2)).sqrt();
+                let coverage = (radius - distance + 0.5).clamp(0.0, 1.0) as f32;
+
+                self.blend_pixel(x, y, [color[0], color[1], color[2]], alpha * coverage);
+            }
+        }
+    }
+
+    /// Draws a circle's outline `thickness` pixels wide, centered on `radius`.
+    pub fn ring(&mut self, cx: f64, cy: f64, radius: f64, thickness: f64, color: [u8; 4]) {
+        let alpha = color[3] as f32 / 255.0;
+        let outer = radius + thickness / 2.0;
+
+        for y in (cy - outer).floor() as i64..=(cy + outer).ceil() as i64 {
+            for x in (cx - outer).floor() as i64..=(cx + outer).ceil() as i64 {
+                let distance =
+                    ((x as f64 + 0.5 - cx).powi(2) + (y as f64 + 0.5 - cy).powi(2)).sqrt();
+                let coverage =
+                    (thickness / 2.0 - (distance - radius).abs() + 0.5).clamp(0.0, 1.0) as f32;
+
+                self.blend_pixel(x, y, [color[0], color[1], color[2]], alpha * coverage);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    const RGBA: [usize; 3] = [0, 1, 2];
+
+    fn sample(time: f64, x: f64, y: f64) -> CursorSample {
+        CursorSample {
+            time,
+            x,
+            y,
+            pressed: false,
+        }
+    }
+
+    #[test]
+    fn interpolates_between_samples() {
+        let mut overlay = CursorOverlay::new(RgbaImage::new(1, 1), (0.0, 0.0));
+        overlay.push_sample(sample(0.0, 0.0, 0.0));
+        overlay.push_sample(sample(1.0, 1.0, 0.5));
+
+        assert_eq!(overlay.position_at(0.5), Some(((0.5, 0.25), false)));
+        assert_eq!(overlay.position_at(2.0), Some(((1.0, 0.5), false)));
+        assert_eq!(overlay.samples.len(), 1);
+    }
+
+    #[test]
+    fn smooths_towards_samples() {
+        let mut overlay = CursorOverlay::new(RgbaImage::new(1, 1), (0.0, 0.0)).with_smoothing(0.1);
+        overlay.push_sample(sample(0.0, 0.0, 0.0));
+        overlay.push_sample(sample(0.1, 1.0, 1.0));
+        overlay.push_sample(sample(10.0, 1.0, 1.0));
+
+        overlay.position_at(0.0);
+        let ((x, _), _) = overlay.position_at(0.1).unwrap();
+
+        assert!(x > 0.0 && x < 1.0);
+    }
+
+    #[test]
+    fn draws_at_hotspot() {
+        let mut overlay = CursorOverlay::new(
+            RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255])),
+            (1.0, 1.0),
+        );
+        overlay.push_sample(sample(0.0, 0.5, 0.5));
+        let mut data = vec![0; 4 * 4 * 4];
+
+        overlay.blend(&mut data, 16, 4, 4, 0.0, RGBA);
+
+        assert_eq!(&data[16 + 4..16 + 8], [255, 0, 0, 0]);
+        assert_eq!(&data[2 * 16 + 8..2 * 16 + 12], [255, 0, 0, 0]);
+        assert_eq!(&data[0..4], [0, 0, 0, 0]);
+        assert_eq!(&data[3 * 16 + 12..3 * 16 + 16], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn highlights_clicks() {
+        let mut overlay = CursorOverlay::new(RgbaImage::new(1, 1), (0.0, 0.0))
+            .with_click_highlight(ClickHighlight {
+                radius: 2.0,
+                color: [0, 0, 255, 255],
+            });
+        overlay.push_sample(CursorSample {
+            pressed: true,
+            ..sample(0.0, 0.5, 0.5)
+        });
+        let mut data = vec![0; 8 * 8 * 4];
+
+        overlay.blend(&mut data, 32, 8, 8, 0.0, RGBA);
+
+        assert_eq!(&data[3 * 32 + 12..3 * 32 + 16], [0, 0, 255, 0]);
+        assert_eq!(&data[0..4], [0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use ffmpeg::{format::Pixel, frame};
+
+use super::gain::scale_frame;
+use crate::MediaError;
+
+/// A fade in over the start of a clip and a fade out over its end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fade {
+    fade_in: f64,
+    fade_out: f64,
+    duration: f64,
+}
+
+impl Fade {
+    /// `duration` is the length of the whole clip. `None` if both fades are zero,
+    /// as there's nothing to do.
+    pub fn new(fade_in: Duration, fade_out: Duration, duration: Duration) -> Option<Self> {
+        (!fade_in.is_zero() || !fade_out.is_zero()).then(|| Self {
+            fade_in: fade_in.as_secs_f64(),
+            fade_out: fade_out.as_secs_f64(),
+            duration: duration.as_secs_f64(),
+        })
+    }
+
+    /// How far faded in the clip is at `time` seconds, from 0 to 1.
+    pub fn level_at(&self, time: f64) -> f64 {
+        let ramp = |elapsed: f64, length: f64| {
+            if length > 0.0 {
+                (elapsed / length).clamp(0.0, 1.0)
+            } else {
+                1.0
+            }
+        };
+
+        ramp(time, self.fade_in).min(ramp(self.duration - time, self.fade_out))
+    }
+}
+
+/// Fades video from and to black.
+pub struct VideoFadeFilter {
+    fade: Fade,
+}
+
+impl VideoFadeFilter {
+    pub fn new(fade: Fade) -> Self {
+        Self { fade }
+    }
+
+    /// Darkens the frame shown at `time` seconds. Supports packed 8-bit RGBA and BGRA frames,
+    /// leaving their alpha as it is.
+    pub fn apply(&self, frame: &mut frame::Video, time: f64) -> Result<(), MediaError> {
+        if !matches!(
+            frame.format(),
+            Pixel::RGBA | Pixel::RGBZ | Pixel::BGRA | Pixel::BGRZ
+        ) {
+            return Err(MediaError::Any(
+                format!("Fade doesn't support {:?} frames", frame.format()).into(),
+            ));
+        }
+
+        let (width, height, stride) = (frame.width(), frame.height(), frame.stride(0));
+        self.darken(frame.data_mut(0), stride, width, height, time);
+
+        Ok(())
+    }
+
+    fn darken(&self, data: &mut [u8], stride: usize, width: u32, height: u32, time: f64) {
+        let level = self.fade.level_at(time);
+        if level >= 1.0 {
+            return;
+        }
+
+        // fixed point, so the whole frame is scaled with integer math
+        let level = (level * 256.0).round() as u32;
+
+        for row in data.chunks_mut(stride).take(height as usize) {
+            for pixel in row[..width as usize * 4].chunks_exact_mut(4) {
+                for channel in &mut pixel[..3] {
+                    *channel = ((*channel as u32 * level) >> 8) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Fades audio in from and out to silence.
+pub struct AudioFadeFilter {
+    fade: Fade,
+    position: f64,
+}
+
+impl AudioFadeFilter {
+    pub fn new(fade: Fade) -> Self {
+        Self {
+            fade,
+            position: 0.0,
+        }
+    }
+
+    /// Fades the frame in place. Frames are expected back to back,
+    /// with the first one starting at the start of the clip.
+    pub fn apply(&mut self, frame: &mut frame::Audio) -> Result<(), MediaError> {
+        scale_frame(frame, self.position, |time| self.fade.level_at(time))?;
+        self.position += frame.samples() as f64 / frame.rate() as f64;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fade(fade_in: u64, fade_out: u64) -> Option<Fade> {
+        Fade::new(
+            Duration::from_millis(fade_in),
+            Duration::from_millis(fade_out),
+            Duration::from_secs(10),
+        )
+    }
+
+    #[test]
+    fn zero_durations_are_skipped() {
+        assert_eq!(fade(0, 0), None);
+        assert!(fade(500, 0).is_some());
+        assert!(fade(0, 500).is_some());
+    }
+
+    #[test]
+    fn ramps_over_start_and_end() {
+        let fade = fade(1000, 2000).unwrap();
+
+        assert_eq!(fade.level_at(0.0), 0.0);
+        assert_eq!(fade.level_at(0.5), 0.5);
+        assert_eq!(fade.level_at(1.0), 1.0);
+        assert_eq!(fade.level_at(5.0), 1.0);
+        assert_eq!(fade.level_at(9.0), 0.5);
+        assert_eq!(fade.level_at(10.0), 0.0);
+    }
+
+    #[test]
+    fn only_fades_in() {
+        let fade = fade(1000, 0).unwrap();
+
+        assert_eq!(fade.level_at(0.25), 0.25);
+        assert_eq!(fade.level_at(10.0), 1.0);
+    }
+
+    #[test]
+    fn darkens_color_but_not_alpha() {
+        let filter = VideoFadeFilter::new(fade(1000, 0).unwrap());
+        // 1x2 with 4 bytes of padding after each row
+        let mut data = [
+            200, 100, 50, 255, 9, 9, 9, 9, 255, 255, 255, 128, 9, 9, 9, 9,
+        ];
+
+        filter.darken(&mut data, 8, 1, 2, 0.5);
+
+        assert_eq!(
+            data,
+            [100, 50, 25, 255, 9, 9, 9, 9, 127, 127, 127, 128, 9, 9, 9, 9]
+        );
+    }
+
+    #[test]
+    fn fades_audio_across_frames() {
+        let mut filter = AudioFadeFilter::new(
+            Fade::new(
+                Duration::from_millis(100),
+                Duration::ZERO,
+                Duration::from_secs(1),
+            )
+            .unwrap(),
+        );
+        let mut samples = vec![];
+
+        for _ in 0..4 {
+            let mut frame = frame::Audio::new(
+                ffmpeg::format::Sample::F32(ffmpeg::format::sample::Type::Packed),
+                50,
+                ffmpeg::ChannelLayout::MONO,
+            );
+            frame.set_rate(1000);
+            frame.plane_mut::<f32>(0).fill(1.0);
+
+            filter.apply(&mut frame).unwrap();
+            samples.extend_from_slice(frame.plane::<f32>(0));
+        }
+
+        assert_eq!(samples[0], 0.0);
+        assert!((samples[50] - 0.5).abs() < 1e-6);
+        assert!(samples.windows(2).all(|w| w[0] <= w[1]));
+        assert!(samples[100..].iter().all(|s| *s == 1.0));
+    }
+}
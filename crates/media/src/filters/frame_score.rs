@@ -0,0 +1,166 @@
+use std::ops::Range;
+
+use crate::data::RgbaFrame;
+
+/// Picks the most representative frame of a range, so thumbnails don't land on a black
+/// intro or a faded-out transition.
+///
+/// Only every `every`th frame of the range is scored, and each scored frame is read on a
+/// grid of every [`FrameScorer::PIXEL_STEP`]th pixel, which is plenty for telling a busy
+/// frame from a blank one.
+#[derive(Debug, Clone)]
+pub struct FrameScorer {
+    range: Range<u32>,
+    every: u32,
+    best: Option<(u32, f64)>,
+}
+
+impl FrameScorer {
+    pub const DEFAULT_EVERY: u32 = 15;
+    pub const PIXEL_STEP: u32 = 4;
+
+    pub fn new(range: Range<u32>, every: u32) -> Self {
+        Self {
+            range,
+            every: every.max(1),
+            best: None,
+        }
+    }
+
+    /// Frame indices that should be pushed, in increasing order.
+    pub fn sample_frames(&self) -> impl Iterator<Item = u32> + use<> {
+        self.range.clone().step_by(self.every as usize)
+    }
+
+    /// Scores the frame at `index`, returning whether it's now the best one.
+    pub fn push_frame(&mut self, index: u32, frame: &RgbaFrame) -> bool {
+        let score = score_frame(frame);
+
+        // ties keep the earlier frame
+        if self.best.is_some_and(|(_, best)| score <= best) {
+            return false;
+        }
+
+        self.best = Some((index, score));
+        true
+    }
+
+    /// The highest scoring frame pushed so far, with its score.
+    pub fn best(&self) -> Option<(u32, f64)> {
+        self.best
+    }
+}
+
+/// Colourfulness plus edge energy, each roughly in `0..=1`. Flat frames score zero.
+///
+/// Colourfulness is Hasler and Süsstrunk's measure over the opponent channels, and edge
+/// energy is the mean absolute luma difference to the next sampled pixel right and down.
+pub fn score_frame(frame: &RgbaFrame) -> f64 {
+    let step = FrameScorer::PIXEL_STEP;
+    let (width, height) = (frame.width(), frame.height());
+
+    let luma = |[r, g, b, _]: [u8; 4]| 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+
+    let mut count = 0.0;
+    let (mut rg_sum, mut rg_sq, mut yb_sum, mut yb_sq) = (0.0, 0.0, 0.0, 0.0);
+    let (mut edge_sum, mut edge_count) = (0.0, 0.0);
+
+    for y in (0..height).step_by(step as usize) {
+        for x in (0..width).step_by(step as usize) {
+            let pixel = frame.pixel(x, y);
+            let [r, g, b, _] = pixel.map(|c| c as f64);
+
+            let rg = r - g;
+            let yb = 0.5 * (r + g) - b;
+            rg_sum += rg;
+            rg_sq += rg * rg;
+            yb_sum += yb;
+            yb_sq += yb * yb;
+            count += 1.0;
+
+            let value = luma(pixel);
+            if x + step < width {
+                edge_sum += (value - luma(frame.pixel(x + step, y))).abs();
+                edge_count += 1.0;
+            }
+            if y + step < height {
+                edge_sum += (value - luma(frame.pixel(x, y + step))).abs();
+                edge_count += 1.0;
+            }
+        }
+    }
+
+    if count == 0.0 {
+        return 0.0;
+    }
+
+    let (rg_mean, yb_mean) = (rg_sum / count, yb_sum / count);
+    let rg_var = (rg_sq / count - rg_mean * rg_mean).max(0.0);
+    let yb_var = (yb_sq / count - yb_mean * yb_mean).max(0.0);
+    let colourfulness = (rg_var + yb_var).sqrt() + 0.3 * rg_mean.hypot(yb_mean);
+
+    let edges = if edge_count > 0.0 {
+        edge_sum / edge_count
+    } else {
+        0.0
+    };
+
+    (colourfulness + edges) / 255.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn solid(colour: [u8; 4]) -> RgbaFrame {
+        RgbaFrame::packed(colour.repeat(32 * 32), 32, 32).unwrap()
+    }
+
+    // alternates colours every `PIXEL_STEP` pixels, so the sampled grid sees a checkerboard
+    fn checkerboard(a: [u8; 4], b: [u8; 4]) -> RgbaFrame {
+        let step = FrameScorer::PIXEL_STEP;
+        let data = (0..32 * 32)
+            .flat_map(|i| {
+                let (x, y) = (i % 32, i / 32);
+                if (x / step + y / step) % 2 == 0 { a } else { b }
+            })
+            .collect();
+        RgbaFrame::packed(data, 32, 32).unwrap()
+    }
+
+    #[test]
+    fn flat_frames_score_zero() {
+        assert_eq!(score_frame(&solid([0, 0, 0, 255])), 0.0);
+        assert_eq!(score_frame(&solid([128, 128, 128, 255])), 0.0);
+    }
+
+    #[test]
+    fn colour_and_edges_score_higher() {
+        let grey = checkerboard([0, 0, 0, 255], [255, 255, 255, 255]);
+        let colour = checkerboard([255, 0, 0, 255], [0, 0, 255, 255]);
+
+        assert!(score_frame(&grey) > score_frame(&solid([255, 0, 0, 255])));
+        assert!(score_frame(&colour) > score_frame(&solid([255, 0, 0, 255])));
+        assert!(score_frame(&solid([255, 0, 0, 255])) > 0.0);
+    }
+
+    #[test]
+    fn samples_every_nth_frame() {
+        let scorer = FrameScorer::new(10..40, 10);
+
+        assert_eq!(scorer.sample_frames().collect::<Vec<_>>(), vec![10, 20, 30]);
+        assert_eq!(FrameScorer::new(0..3, 0).sample_frames().count(), 3);
+    }
+
+    #[test]
+    fn keeps_best_frame() {
+        let mut scorer = FrameScorer::new(0..30, 10);
+        let busy = checkerboard([255, 0, 0, 255], [0, 0, 255, 255]);
+
+        assert!(scorer.push_frame(0, &solid([0, 0, 0, 255])));
+        assert!(scorer.push_frame(10, &busy));
+        assert!(!scorer.push_frame(20, &busy));
+
+        assert_eq!(scorer.best().map(|(index, _)| index), Some(10));
+    }
+}
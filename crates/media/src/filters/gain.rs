@@ -0,0 +1,323 @@
+use ffmpeg::{format::Sample, frame};
+
+use crate::MediaError;
+
+/// The gain at a point in time of a [`Gain::Envelope`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GainKeyframe {
+    /// Seconds from the start of the audio
+    pub time: f64,
+    pub gain_db: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Gain {
+    Constant(f64),
+    /// Interpolated linearly in dB between keyframes, and held before the first
+    /// and after the last one. No keyframes leaves the audio unchanged.
+    Envelope(Vec<GainKeyframe>),
+}
+
+impl Gain {
+    pub fn gain_db_at(&self, time: f64) -> f64 {
+        let keyframes = match self {
+            Self::Constant(gain_db) => return *gain_db,
+            Self::Envelope(keyframes) => keyframes,
+        };
+
+        let next = keyframes.partition_point(|k| k.time <= time);
+        match (
+            next.checked_sub(1).map(|i| keyframes[i]),
+            keyframes.get(next),
+        ) {
+            (None, None) => 0.0,
+            (Some(k), None) | (None, Some(&k)) => k.gain_db,
+            (Some(previous), Some(next)) => {
+                let t = (time - previous.time) / (next.time - previous.time);
+                previous.gain_db + (next.gain_db - previous.gain_db) * t
+            }
+        }
+    }
+}
+
+/// How many samples a [`GainFilter`] pushed past full scale.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GainStats {
+    /// Samples that were clamped to full scale
+    pub clipped_samples: u64,
+    /// Largest absolute sample value after gain and before clamping, where 1 is full scale
+    pub peak: f64,
+}
+
+impl GainStats {
+    pub fn clipped(&self) -> bool {
+        self.clipped_samples > 0
+    }
+
+    fn merge(&mut self, other: GainStats) {
+        self.clipped_samples += other.clipped_samples;
+        self.peak = self.peak.max(other.peak);
+    }
+}
+
+/// Boosts or attenuates audio by a [`Gain`], e.g. to make a quiet recording louder or
+/// duck background audio, keeping track of any samples it clips.
+///
+/// Envelope times are relative to the start of the first pushed frame, with the gain
+/// interpolated for every sample so it changes smoothly.
+#[derive(Debug, Clone)]
+pub struct GainFilter {
+    gain: Gain,
+    position: f64,
+    stats: GainStats,
+}
+
+impl GainFilter {
+    pub fn new(gain: Gain) -> Self {
+        let gain = match gain {
+            Gain::Envelope(mut keyframes) => {
+                keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+                Gain::Envelope(keyframes)
+            }
+            gain => gain,
+        };
+
+        Self {
+            gain,
+            position: 0.0,
+            stats: GainStats::default(),
+        }
+    }
+
+    /// Applies the gain to the frame in place, returning how much of it clipped.
+    pub fn apply(&mut self, frame: &mut frame::Audio) -> Result<GainStats, MediaError> {
+        let stats = scale_frame(frame, self.position, |time| self.linear_gain_at(time))?;
+
+        self.position += frame.samples() as f64 / frame.rate() as f64;
+        self.stats.merge(stats);
+
+        Ok(stats)
+    }
+
+    /// Same as [`GainFilter::apply`], for interleaved `f32` samples.
+    pub fn apply_samples(
+        &mut self,
+        samples: &mut [f32],
+        channels: usize,
+        sample_rate: u32,
+    ) -> GainStats {
+        let mut stats = GainStats::default();
+
+        for (i, instant) in samples.chunks_mut(channels).enumerate() {
+            let gain = self.linear_gain_at(self.position + i as f64 / sample_rate as f64);
+            for sample in instant {
+                *sample = clip(*sample as f64 * gain, &mut stats) as f32;
+            }
+        }
+
+        self.position += (samples.len() / channels) as f64 / sample_rate as f64;
+        self.stats.merge(stats);
+
+        stats
+    }
+
+    /// Totals across everything the filter has processed.
+    pub fn stats(&self) -> GainStats {
+        self.stats
+    }
+
+    fn linear_gain_at(&self, time: f64) -> f64 {
+        10f64.powf(self.gain.gain_db_at(time) / 20.0)
+    }
+}
+
+/// Multiplies every sample of a frame by `gain_at` the time it plays at, where the frame
+/// starts at `position` seconds, clamping anything that ends up past full scale.
+pub(super) fn scale_frame(
+    frame: &mut frame::Audio,
+    position: f64,
+    gain_at: impl Fn(f64) -> f64,
+) -> Result<GainStats, MediaError> {
+    let (samples, channels, rate) = (frame.samples(), frame.channels() as usize, frame.rate());
+    let planes = if frame.is_planar() { channels } else { 1 };
+    let plane_len = samples * frame.format().bytes() * channels / planes;
+    // interleaved frames have every channel's sample for an instant next to each other
+    let channels_per_plane = channels / planes;
+    let gain_at = |i: usize| gain_at(position + i as f64 / rate as f64);
+
+    let format = frame.format();
+    let mut stats = GainStats::default();
+
+    for plane in 0..planes {
+        let data = &mut frame.data_mut(plane)[..plane_len];
+
+        let plane_stats = match format {
+            Sample::F32(_) => scale(
+                data,
+                channels_per_plane,
+                gain_at,
+                |b: [u8; 4]| f32::from_ne_bytes(b) as f64,
+                |v| (v as f32).to_ne_bytes(),
+            ),
+            Sample::F64(_) => scale(
+                data,
+                channels_per_plane,
+                gain_at,
+                f64::from_ne_bytes,
+                f64::to_ne_bytes,
+            ),
+            Sample::I16(_) => scale(
+                data,
+                channels_per_plane,
+                gain_at,
+                |b| i16::from_ne_bytes(b) as f64 / i16::MAX as f64,
+                |v| ((v * i16::MAX as f64).round() as i16).to_ne_bytes(),
+            ),
+            Sample::I32(_) => scale(
+                data,
+                channels_per_plane,
+                gain_at,
+                |b| i32::from_ne_bytes(b) as f64 / i32::MAX as f64,
+                |v| ((v * i32::MAX as f64).round() as i32).to_ne_bytes(),
+            ),
+            format => {
+                return Err(MediaError::Any(
+                    format!("Unsupported sample format for gain: {format:?}").into(),
+                ));
+            }
+        };
+
+        stats.merge(plane_stats);
+    }
+
+    Ok(stats)
+}
+
+fn scale<const N: usize>(
+    data: &mut [u8],
+    channels: usize,
+    gain_at: impl Fn(usize) -> f64,
+    to_f64: impl Fn([u8; N]) -> f64,
+    from_f64: impl Fn(f64) -> [u8; N],
+) -> GainStats {
+    let mut stats = GainStats::default();
+
+    for (i, instant) in data.chunks_exact_mut(N * channels).enumerate() {
+        let gain = gain_at(i);
+        for sample in instant.chunks_exact_mut(N) {
+            let value = to_f64(sample.try_into().unwrap()) * gain;
+            sample.copy_from_slice(&from_f64(clip(value, &mut stats)));
+        }
+    }
+
+    stats
+}
+
+fn clip(value: f64, stats: &mut GainStats) -> f64 {
+    stats.peak = stats.peak.max(value.abs());
+
+    if value.abs() > 1.0 {
+        stats.clipped_samples += 1;
+        value.clamp(-1.0, 1.0)
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    fn keyframe(time: f64, gain_db: f64) -> GainKeyframe {
+        GainKeyframe { time, gain_db }
+    }
+
+    #[test]
+    fn applies_constant_gain() {
+        let mut filter = GainFilter::new(Gain::Constant(-20.0));
+        let mut samples = [0.5, -0.5, 1.0, 0.0];
+
+        let stats = filter.apply_samples(&mut samples, 2, SAMPLE_RATE);
+
+        for (actual, expected) in samples.iter().zip([0.05, -0.05, 0.1, 0.0]) {
+            assert!((actual - expected).abs() < 1e-6, "{actual} != {expected}");
+        }
+        assert!(!stats.clipped());
+        assert!((stats.peak - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn interpolates_envelope_between_keyframes() {
+        let gain = Gain::Envelope(vec![keyframe(1.0, -6.0), keyframe(3.0, 6.0)]);
+
+        assert_eq!(gain.gain_db_at(0.0), -6.0);
+        assert_eq!(gain.gain_db_at(1.0), -6.0);
+        assert_eq!(gain.gain_db_at(2.0), 0.0);
+        assert_eq!(gain.gain_db_at(2.5), 3.0);
+        assert_eq!(gain.gain_db_at(10.0), 6.0);
+        assert_eq!(Gain::Envelope(vec![]).gain_db_at(1.0), 0.0);
+    }
+
+    #[test]
+    fn follows_envelope_across_frames() {
+        // unsorted keyframes ramping from silence to unity gain over the second half second
+        let mut filter = GainFilter::new(Gain::Envelope(vec![
+            keyframe(1.0, 0.0),
+            keyframe(0.5, -120.0),
+        ]));
+        let mut samples = vec![1.0; SAMPLE_RATE as usize];
+
+        for chunk in samples.chunks_mut(100) {
+            filter.apply_samples(chunk, 1, SAMPLE_RATE);
+        }
+
+        assert!(samples[..500].iter().all(|s| *s < 1e-5));
+        assert!(samples.windows(2).all(|w| w[0] <= w[1]));
+        assert!(samples[999] > 0.95);
+    }
+
+    #[test]
+    fn reports_clipping() {
+        let mut filter = GainFilter::new(Gain::Constant(6.0));
+        let mut samples = [0.9, 0.1, -0.8, 0.2];
+
+        let stats = filter.apply_samples(&mut samples, 1, SAMPLE_RATE);
+
+        assert_eq!(stats.clipped_samples, 2);
+        assert!(stats.clipped());
+        assert!(stats.peak > 1.7);
+        assert_eq!(samples[0], 1.0);
+        assert_eq!(samples[2], -1.0);
+
+        filter.apply_samples(&mut [0.9], 1, SAMPLE_RATE);
+        assert_eq!(filter.stats().clipped_samples, 3);
+    }
+
+    #[test]
+    fn planar_frame() {
+        let mut filter = GainFilter::new(Gain::Constant(20.0));
+
+        let mut frame = frame::Audio::new(
+            Sample::F32(ffmpeg::format::sample::Type::Planar),
+            4,
+            ffmpeg::ChannelLayout::STEREO,
+        );
+        frame.set_rate(SAMPLE_RATE);
+        frame
+            .plane_mut::<f32>(0)
+            .copy_from_slice(&[0.01, 0.02, 0.03, 0.04]);
+        frame
+            .plane_mut::<f32>(1)
+            .copy_from_slice(&[0.5, 0.0, 0.0, 0.0]);
+
+        let stats = filter.apply(&mut frame).unwrap();
+
+        assert_eq!(stats.clipped_samples, 1);
+        for (actual, expected) in frame.plane::<f32>(0).iter().zip([0.1, 0.2, 0.3, 0.4]) {
+            assert!((actual - expected).abs() < 1e-6, "{actual} != {expected}");
+        }
+        assert_eq!(frame.plane::<f32>(1)[0], 1.0);
+    }
+}
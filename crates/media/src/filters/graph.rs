@@ -0,0 +1,117 @@
+use cap_media_info::{AudioInfo, VideoInfo};
+use ffmpeg::{filter, frame};
+
+use crate::MediaError;
+
+/// A single-input, single-output FFmpeg filter graph for audio.
+pub(super) struct AudioFilterGraph {
+    graph: filter::Graph,
+}
+
+impl AudioFilterGraph {
+    /// `spec` is an FFmpeg filter chain, e.g. `volume=0.5,aresample=48000`.
+    pub fn new(info: &AudioInfo, spec: &str) -> Result<Self, MediaError> {
+        let mut graph = filter::Graph::new();
+
+        let args = format!(
+            "time_base={}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+            info.time_base,
+            info.rate(),
+            info.sample_format.name(),
+            info.channel_layout().bits()
+        );
+
+        graph.add(&find_filter("abuffer")?, "in", &args)?;
+        graph.add(&find_filter("abuffersink")?, "out", "")?;
+        graph.output("in", 0)?.input("out", 0)?.parse(spec)?;
+        graph.validate()?;
+
+        Ok(Self { graph })
+    }
+
+    pub fn push(&mut self, frame: &frame::Audio) -> Result<(), MediaError> {
+        self.context("in").source().add(frame)?;
+
+        Ok(())
+    }
+
+    /// Signals the end of the input, so filters with lookahead release what they've buffered.
+    pub fn flush(&mut self) -> Result<(), MediaError> {
+        self.context("in").source().flush()?;
+
+        Ok(())
+    }
+
+    pub fn receive(&mut self) -> Option<frame::Audio> {
+        let mut frame = frame::Audio::empty();
+
+        self.context("out")
+            .sink()
+            .frame(&mut frame)
+            .ok()
+            .map(|_| frame)
+    }
+
+    fn context(&mut self, name: &str) -> filter::Context<'_> {
+        // Both are added in `new`
+        self.graph.get(name).unwrap()
+    }
+}
+
+/// A single-input, single-output FFmpeg filter graph for video.
+pub(super) struct VideoFilterGraph {
+    graph: filter::Graph,
+}
+
+impl VideoFilterGraph {
+    /// `spec` is an FFmpeg filter chain, e.g. `scale=1280:720,format=rgba`.
+    pub fn new(info: &VideoInfo, spec: &str) -> Result<Self, MediaError> {
+        let mut graph = filter::Graph::new();
+
+        let args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base={}:pixel_aspect=1/1",
+            info.width,
+            info.height,
+            ffmpeg::ffi::AVPixelFormat::from(info.pixel_format) as i32,
+            info.time_base,
+        );
+
+        graph.add(&find_filter("buffer")?, "in", &args)?;
+        graph.add(&find_filter("buffersink")?, "out", "")?;
+        graph.output("in", 0)?.input("out", 0)?.parse(spec)?;
+        graph.validate()?;
+
+        Ok(Self { graph })
+    }
+
+    pub fn push(&mut self, frame: &frame::Video) -> Result<(), MediaError> {
+        self.context("in").source().add(frame)?;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), MediaError> {
+        self.context("in").source().flush()?;
+
+        Ok(())
+    }
+
+    pub fn receive(&mut self) -> Option<frame::Video> {
+        let mut frame = frame::Video::empty();
+
+        self.context("out")
+            .sink()
+            .frame(&mut frame)
+            .ok()
+            .map(|_| frame)
+    }
+
+    fn context(&mut self, name: &str) -> filter::Context<'_> {
+        // Both are added in `new`
+        self.graph.get(name).unwrap()
+    }
+}
+
+fn find_filter(name: &'static str) -> Result<filter::Filter, MediaError> {
+    filter::find(name).ok_or_else(|| MediaError::Any(format!("Missing {name} filter").into()))
+}
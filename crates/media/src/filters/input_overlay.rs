@@ -0,0 +1,573 @@
+use cap_media_info::VideoInfo;
+use ffmpeg::{Rational, format::Pixel, frame};
+use std::{
+    fmt::Write,
+    path::PathBuf,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use super::{cursor::Canvas, graph::VideoFilterGraph, subtitles::escape_filter_path};
+use crate::MediaError;
+
+// typed text longer than this only shows its end
+const MAX_CAPTION_CHARS: usize = 32;
+// ripples start at this fraction of their radius and grow to all of it
+const RIPPLE_START: f64 = 0.3;
+// width of a ripple's ring, as a fraction of its radius
+const RIPPLE_WIDTH: f64 = 0.15;
+
+/// Something the user did while recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputEvent {
+    /// Seconds from the start of the video
+    pub time: f64,
+    pub kind: InputEventKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputEventKind {
+    /// A mouse button was pressed at this position, as fractions of the frame's
+    /// width and height from its top left corner
+    Click { x: f64, y: f64 },
+    /// A key was pressed, named like `a`, `Enter` or `Shift`, while holding `modifiers`
+    Key { key: String, modifiers: Vec<String> },
+}
+
+/// How clicks and keystrokes are drawn. Sizes are in pixels of the output video.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputOverlayStyle {
+    /// Radius ripples grow to
+    pub click_radius: f32,
+    /// RGBA color of ripples, which fade out as they grow
+    pub click_color: [u8; 4],
+    /// Seconds a ripple lasts
+    pub click_duration: f64,
+    pub caption_font: String,
+    /// Height of the caption text
+    pub caption_size: u32,
+    /// Distance of captions from the bottom edge
+    pub caption_margin: u32,
+    /// RGBA color of the caption text
+    pub caption_color: [u8; 4],
+    /// RGBA color of the box behind captions
+    pub caption_background: [u8; 4],
+    /// Seconds a caption stays on screen after its last key
+    pub caption_duration: f64,
+    /// Seconds captions take to fade out at the end of `caption_duration`
+    pub fade_out: f64,
+}
+
+impl Default for InputOverlayStyle {
+    fn default() -> Self {
+        Self {
+            click_radius: 36.0,
+            click_color: [255, 255, 255, 200],
+            click_duration: 0.5,
+            caption_font: "Arial".to_string(),
+            caption_size: 40,
+            caption_margin: 60,
+            caption_color: [255, 255, 255, 255],
+            caption_background: [0, 0, 0, 160],
+            caption_duration: 1.5,
+            fade_out: 0.3,
+        }
+    }
+}
+
+/// Draws a ripple wherever the mouse was clicked, and captions of recent keystrokes.
+///
+/// Typed characters are shown together as they're typed, while shortcuts like `Ctrl+C`
+/// and keys like `Enter` get a caption of their own. Modifier keys pressed on their own
+/// aren't shown. Captions are rendered by libass through FFmpeg's `subtitles` filter,
+/// so frames come out of [`InputOverlayFilter::receive_frame`] like with
+/// [`SubtitleBurner`](super::SubtitleBurner).
+///
+/// Event times are matched against frame timestamps using the time base of `info`.
+pub struct InputOverlayFilter {
+    /// Times and positions of clicks that haven't finished rippling, in time order
+    clicks: Vec<(f64, (f64, f64))>,
+    style: InputOverlayStyle,
+    time_base: Rational,
+    graph: VideoFilterGraph,
+    ass_path: Option<PathBuf>,
+}
+
+impl InputOverlayFilter {
+    pub fn new(
+        info: &VideoInfo,
+        events: &[InputEvent],
+        style: InputOverlayStyle,
+    ) -> Result<Self, MediaError> {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+        let mut clicks = events
+            .iter()
+            .filter_map(|event| match event.kind {
+                InputEventKind::Click { x, y } => Some((event.time, (x, y))),
+                InputEventKind::Key { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        clicks.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let captions = captions(events, &style);
+        let ass_path = if captions.is_empty() {
+            None
+        } else {
+            // the subtitles filter can only read from a file
+            let path = std::env::temp_dir().join(format!(
+                "cap-input-overlay-{}-{}.ass",
+                std::process::id(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::write(&path, ass_script(&captions, &style, info))?;
+            Some(path)
+        };
+
+        let spec = match &ass_path {
+            Some(path) => format!("subtitles=filename='{}'", escape_filter_path(path)),
+            None => "null".to_string(),
+        };
+
+        match VideoFilterGraph::new(info, &spec) {
+            Ok(graph) => Ok(Self {
+                clicks,
+                style,
+                time_base: info.time_base,
+                graph,
+                ass_path,
+            }),
+            Err(e) => {
+                if let Some(path) = &ass_path {
+                    let _ = std::fs::remove_file(path);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Draws the ripples at the frame's time and queues it for captioning.
+    /// Supports packed 8-bit RGBA and BGRA frames.
+    pub fn push_frame(&mut self, mut frame: frame::Video) -> Result<(), MediaError> {
+        let channels = match frame.format() {
+            Pixel::RGBA | Pixel::RGBZ => [0, 1, 2],
+            Pixel::BGRA | Pixel::BGRZ => [2, 1, 0],
+            format => {
+                return Err(MediaError::Any(
+                    format!("Input overlay doesn't support {format:?} frames").into(),
+                ));
+            }
+        };
+
+        let time = frame.pts().unwrap_or(0) as f64 * f64::from(self.time_base);
+        let (width, height, stride) = (frame.width(), frame.height(), frame.stride(0));
+        self.draw_clicks(frame.data_mut(0), stride, width, height, time, channels);
+
+        self.graph.push(&frame)
+    }
+
+    pub fn flush(&mut self) -> Result<(), MediaError> {
+        self.graph.flush()
+    }
+
+    pub fn receive_frame(&mut self) -> Option<frame::Video> {
+        self.graph.receive()
+    }
+
+    fn draw_clicks(
+        &mut self,
+        data: &mut [u8],
+        stride: usize,
+        width: u32,
+        height: u32,
+        time: f64,
+        channels: [usize; 3],
+    ) {
+        // frames only move forward, so finished ripples won't be needed again
+        let finished = self
+            .clicks
+            .partition_point(|c| c.0 + self.style.click_duration <= time);
+        self.clicks.drain(..finished);
+
+        let mut canvas = Canvas {
+            data,
+            stride,
+            width,
+            height,
+            channels,
+        };
+        draw_ripples(&mut canvas, &self.clicks, &self.style, time);
+    }
+}
+
+impl Drop for InputOverlayFilter {
+    fn drop(&mut self) {
+        if let Some(path) = &self.ass_path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Draws the ripples of `clicks` that have started by `time`, growing and fading out
+/// over the click duration.
+fn draw_ripples(
+    canvas: &mut Canvas,
+    clicks: &[(f64, (f64, f64))],
+    style: &InputOverlayStyle,
+    time: f64,
+) {
+    let max_radius = style.click_radius as f64;
+    let [r, g, b, a] = style.click_color;
+    let (width, height) = (canvas.width as f64, canvas.height as f64);
+
+    for &(start, (x, y)) in clicks.iter().take_while(|c| c.0 <= time) {
+        let progress = (time - start) / style.click_duration;
+        if !(0.0..1.0).contains(&progress) {
+            continue;
+        }
+
+        let eased = 1.0 - (1.0 - progress).powi(2);
+        let radius = max_radius * (RIPPLE_START + (1.0 - RIPPLE_START) * eased);
+        let alpha = (a as f64 * (1.0 - progress)).round() as u8;
+
+        canvas.ring(
+            x * width,
+            y * height,
+            radius,
+            (max_radius * RIPPLE_WIDTH).max(1.0),
+            [r, g, b, alpha],
+        );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Caption {
+    start: f64,
+    end: f64,
+    text: String,
+    /// Whether the caption ends by timing out rather than being replaced
+    fade: bool,
+}
+
+/// Captions for the key events, each shown until the next one replaces it.
+fn captions(events: &[InputEvent], style: &InputOverlayStyle) -> Vec<Caption> {
+    let mut keys = events
+        .iter()
+        .filter_map(|event| match &event.kind {
+            InputEventKind::Key { key, modifiers } => Some((event.time, key, modifiers)),
+            InputEventKind::Click { .. } => None,
+        })
+        .collect::<Vec<_>>();
+    keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut captions: Vec<Caption> = vec![];
+    // whether the last caption is typed text that more characters can be added to
+    let mut typing = false;
+
+    for (time, key, modifiers) in keys {
+        let Some(label) = key_label(key, modifiers) else {
+            continue;
+        };
+
+        let mut text = String::new();
+        if let Some(last) = captions.last_mut()
+            && last.end > time
+        {
+            if typing && let KeyLabel::Typed(_) = label {
+                text = last.text.clone();
+            }
+            last.end = time;
+            last.fade = false;
+        }
+
+        typing = matches!(label, KeyLabel::Typed(_));
+        match label {
+            KeyLabel::Typed(c) => {
+                text.push(c);
+                if let Some((i, _)) = text.char_indices().rev().nth(MAX_CAPTION_CHARS - 1) {
+                    text.drain(..i);
+                }
+            }
+            KeyLabel::Named(name) => text = name,
+        }
+
+        captions.push(Caption {
+            start: time,
+            end: time + style.caption_duration,
+            text,
+            fade: true,
+        });
+    }
+
+    captions.retain(|c| c.end > c.start);
+    captions
+}
+
+enum KeyLabel {
+    /// A character typed as text
+    Typed(char),
+    /// A shortcut or a key that doesn't type anything, like `Ctrl+C` or `Enter`
+    Named(String),
+}
+
+/// How a key is shown, or `None` for modifier keys pressed on their own.
+fn key_label(key: &str, modifiers: &[String]) -> Option<KeyLabel> {
+    if modifier_label(key).is_some() {
+        return None;
+    }
+
+    let mut held = modifiers
+        .iter()
+        .filter_map(|m| modifier_label(m))
+        .filter(|label| !label.is_empty())
+        .collect::<Vec<_>>();
+    held.sort_by_key(|m| MODIFIERS.iter().position(|(_, label)| label == m));
+    held.dedup();
+
+    let typed = match key {
+        key if key.eq_ignore_ascii_case("space") => Some(' '),
+        key => {
+            let mut chars = key.chars();
+            chars
+                .next()
+                .filter(|c| !c.is_control() && chars.next().is_none())
+        }
+    };
+
+    // shift only changes which character is typed
+    if let Some(c) = typed
+        && held.iter().all(|&m| m == "Shift")
+    {
+        return Some(KeyLabel::Typed(c));
+    }
+
+    let key = match typed {
+        Some(' ') => "Space".to_string(),
+        Some(c) => c.to_uppercase().collect(),
+        None => key.to_string(),
+    };
+
+    held.push(&key);
+    Some(KeyLabel::Named(held.join("+")))
+}
+
+/// Names modifier keys are recorded under and their labels, in the order they're shown in.
+/// Keys without a label aren't shown even when held.
+const MODIFIERS: &[(&[&str], &str)] = &[
+    (&["control", "ctrl"], "Ctrl"),
+    (&["alt", "option", "altgr"], "Alt"),
+    (&["shift"], "Shift"),
+    (
+        &["meta", "command", "cmd", "super", "win", "windows"],
+        "Cmd",
+    ),
+    (&["capslock", "fn", "function"], ""),
+];
+
+/// The label of a modifier key, or `None` if it isn't one.
+fn modifier_label(key: &str) -> Option<&'static str> {
+    let key = key.to_ascii_lowercase();
+    // e.g. ShiftLeft, RightControl
+    let key = ["left", "right"].iter().fold(key.as_str(), |key, side| {
+        key.trim_start_matches(side).trim_end_matches(side)
+    });
+
+    MODIFIERS
+        .iter()
+        .find(|(names, _)| names.contains(&key))
+        .map(|(_, label)| *label)
+}
+
+/// An ASS script showing the captions, laid out in pixels of the video.
+fn ass_script(captions: &[Caption], style: &InputOverlayStyle, info: &VideoInfo) -> String {
+    let font: String = style
+        .caption_font
+        .chars()
+        .filter(|c| !matches!(c, ',' | '\n'))
+        .collect();
+    let text_color = ass_color(style.caption_color);
+    let background = ass_color(style.caption_background);
+
+    // border style 3 draws an opaque box in the outline color, padded by the outline size
+    let mut script = format!(
+        "[Script Info]\n\
+         ScriptType: v4.00+\n\
+         PlayResX: {}\n\
+         PlayResY: {}\n\
+         ScaledBorderAndShadow: yes\n\
+         WrapStyle: 2\n\n\
+         [V4+ Styles]\n\
+         Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, \
+         BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, \
+         BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: Default,{font},{},{text_color},{text_color},{background},{background},\
+         0,0,0,0,100,100,0,0,3,{},0,2,10,10,{},1\n\n\
+         [Events]\n\
+         Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        info.width,
+        info.height,
+        style.caption_size,
+        style.caption_size / 4,
+        style.caption_margin,
+    );
+
+    for caption in captions.iter().filter(|c| c.end > 0.0) {
+        let fade = if caption.fade && style.fade_out > 0.0 {
+            let millis = (style.fade_out.min(caption.end - caption.start) * 1000.0).round();
+            format!("{{\\fad(0,{millis})}}")
+        } else {
+            String::new()
+        };
+
+        let _ = writeln!(
+            script,
+            "Dialogue: 0,{},{},Default,,0,0,0,,{fade}{}",
+            ass_time(caption.start),
+            ass_time(caption.end),
+            escape_ass_text(&caption.text)
+        );
+    }
+
+    script
+}
+
+/// `&HAABBGGRR`, where an alpha of 0 is opaque.
+fn ass_color([r, g, b, a]: [u8; 4]) -> String {
+    format!("&H{:02X}{b:02X}{g:02X}{r:02X}", 255 - a)
+}
+
+fn ass_time(seconds: f64) -> String {
+    let centis = (seconds.max(0.0) * 100.0).round() as u64;
+
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centis / 360_000,
+        centis / 6000 % 60,
+        centis / 100 % 60,
+        centis % 100
+    )
+}
+
+/// Stops typed text from being read as override blocks or line breaks.
+fn escape_ass_text(text: &str) -> String {
+    text.replace('\\', "\\\u{2060}")
+        .replace('{', "\\{")
+        .replace('}', "\\}")
+        .replace('\n', " ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(time: f64, key: &str, modifiers: &[&str]) -> InputEvent {
+        InputEvent {
+            time,
+            kind: InputEventKind::Key {
+                key: key.to_string(),
+                modifiers: modifiers.iter().map(|m| m.to_string()).collect(),
+            },
+        }
+    }
+
+    fn texts(captions: &[Caption]) -> Vec<&str> {
+        captions.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    #[test]
+    fn groups_typed_keys() {
+        let style = InputOverlayStyle::default();
+        let captions = captions(
+            &[
+                key(0.0, "h", &[]),
+                key(0.2, "I", &["ShiftLeft"]),
+                key(0.4, "Space", &[]),
+                key(5.0, "x", &[]),
+            ],
+            &style,
+        );
+
+        assert_eq!(texts(&captions), ["h", "hI", "hI ", "x"]);
+        assert_eq!(captions[0].end, 0.2);
+        assert!(!captions[0].fade);
+        assert_eq!(captions[2].end, 0.4 + style.caption_duration);
+        assert!(captions[2].fade);
+    }
+
+    #[test]
+    fn shows_shortcuts_on_their_own() {
+        let captions = captions(
+            &[
+                key(0.0, "a", &[]),
+                key(0.1, "c", &["Meta", "Control"]),
+                key(0.2, "b", &[]),
+                key(0.3, "Enter", &[]),
+                key(0.4, "Shift", &["Shift"]),
+                key(0.5, "CapsLock", &[]),
+            ],
+            &InputOverlayStyle::default(),
+        );
+
+        assert_eq!(texts(&captions), ["a", "Ctrl+Cmd+C", "b", "Enter"]);
+        assert!(captions[3].fade);
+    }
+
+    #[test]
+    fn keeps_end_of_long_text() {
+        let events = (0..40)
+            .map(|i| key(i as f64 * 0.1, if i < 20 { "a" } else { "b" }, &[]))
+            .collect::<Vec<_>>();
+        let captions = captions(&events, &InputOverlayStyle::default());
+
+        let last = &captions.last().unwrap().text;
+        assert_eq!(last.len(), MAX_CAPTION_CHARS);
+        assert!(last.starts_with("aaaaaaaaaaaa") && last.ends_with('b'));
+    }
+
+    #[test]
+    fn writes_ass_dialogue() {
+        let info = VideoInfo::from_raw(cap_media_info::RawVideoFormat::Rgba, 1280, 720, 30);
+        let captions = [Caption {
+            start: 61.5,
+            end: 63.0,
+            text: "{\\n}".to_string(),
+            fade: true,
+        }];
+
+        let script = ass_script(&captions, &InputOverlayStyle::default(), &info);
+
+        assert!(script.contains("PlayResX: 1280\nPlayResY: 720\n"));
+        assert!(script.contains(",&H00FFFFFF,&H00FFFFFF,&H5F000000,"));
+        assert!(script.ends_with(
+            "Dialogue: 0,0:01:01.50,0:01:03.00,Default,,0,0,0,,{\\fad(0,300)}\\{\\\u{2060}n\\}\n"
+        ));
+    }
+
+    #[test]
+    fn ripples_fade_out() {
+        let style = InputOverlayStyle {
+            click_radius: 4.0,
+            click_color: [255, 0, 0, 255],
+            click_duration: 1.0,
+            ..Default::default()
+        };
+        let clicks = [(1.0, (0.5, 0.5))];
+
+        let red = |time: f64| {
+            let mut data = vec![0; 16 * 16 * 4];
+            let mut canvas = Canvas {
+                data: &mut data,
+                stride: 64,
+                width: 16,
+                height: 16,
+                channels: [0, 1, 2],
+            };
+            draw_ripples(&mut canvas, &clicks, &style, time);
+            data.chunks_exact(4).map(|p| p[0] as u32).sum::<u32>()
+        };
+
+        assert_eq!(red(0.5), 0);
+        let (early, late) = (red(1.1), red(1.8));
+        assert!(early > 0 && late > 0 && late < early, "{early} {late}");
+        assert_eq!(red(2.0), 0);
+    }
+}
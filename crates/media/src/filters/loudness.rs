@@ -0,0 +1,277 @@
+use std::collections::VecDeque;
+
+use cap_media_info::AudioInfo;
+use ffmpeg::{DictionaryRef, frame};
+use tracing::debug;
+
+use super::graph::AudioFilterGraph;
+use crate::MediaError;
+
+/// EBU R128 loudness targets, see FFmpeg's `loudnorm` filter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoudnessTarget {
+    /// Integrated loudness in LUFS.
+    pub integrated: f64,
+    /// Maximum true peak in dBTP.
+    pub true_peak: f64,
+    /// Loudness range in LU.
+    pub loudness_range: f64,
+    /// How far from `integrated` a two-pass input may be, in LU, before it is processed at all.
+    pub tolerance: f64,
+}
+
+impl Default for LoudnessTarget {
+    fn default() -> Self {
+        Self {
+            integrated: -16.0,
+            true_peak: -1.5,
+            loudness_range: 11.0,
+            tolerance: 1.0,
+        }
+    }
+}
+
+/// Loudness of a complete input, as measured by [`LoudnessMeter`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoudnessMeasurement {
+    /// Integrated loudness in LUFS.
+    pub integrated: f64,
+    /// Maximum true peak in dBTP.
+    pub true_peak: f64,
+    /// Loudness range in LU.
+    pub loudness_range: f64,
+}
+
+impl LoudnessMeasurement {
+    /// Lowest true peak `loudnorm` accepts, which silence is clamped to.
+    const MIN_TRUE_PEAK: f64 = -99.0;
+
+    /// Reads the running values `ebur128` attaches to a frame. Its true peak is a linear
+    /// sample value rather than dBTP, so it's converted.
+    fn from_metadata(metadata: &DictionaryRef) -> Option<Self> {
+        let get = |key: &str| metadata.get(key).and_then(|v| v.parse::<f64>().ok());
+
+        Some(Self {
+            integrated: get("lavfi.r128.I")?,
+            true_peak: (20.0 * get("lavfi.r128.true_peak")?.log10()).max(Self::MIN_TRUE_PEAK),
+            loudness_range: get("lavfi.r128.LRA")?,
+        })
+    }
+
+    /// `ebur128` doesn't report its gating threshold, so use the relative gate
+    /// (10 LU below the integrated loudness) that R128 defines.
+    fn threshold(&self) -> f64 {
+        self.integrated - 10.0
+    }
+}
+
+/// First pass of loudness normalization. Feed it the whole input, then pass the
+/// result to [`LoudnessNormalize::two_pass`].
+pub struct LoudnessMeter {
+    graph: AudioFilterGraph,
+    measurement: Option<LoudnessMeasurement>,
+}
+
+impl LoudnessMeter {
+    pub fn new(info: &AudioInfo) -> Result<Self, MediaError> {
+        Ok(Self {
+            graph: AudioFilterGraph::new(info, "ebur128=metadata=1:peak=true")?,
+            measurement: None,
+        })
+    }
+
+    pub fn push_frame(&mut self, frame: &frame::Audio) -> Result<(), MediaError> {
+        self.graph.push(frame)?;
+        self.read_measurements();
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<LoudnessMeasurement, MediaError> {
+        self.graph.flush()?;
+        self.read_measurements();
+
+        self.measurement
+            .ok_or(MediaError::Any("No audio was measured for loudness".into()))
+    }
+
+    fn read_measurements(&mut self) {
+        // Every frame carries the running values, so the last one covers the whole input
+        while let Some(frame) = self.graph.receive() {
+            if let Some(measurement) = LoudnessMeasurement::from_metadata(&frame.metadata()) {
+                self.measurement = Some(measurement);
+            }
+        }
+    }
+}
+
+/// Normalizes audio to a [`LoudnessTarget`] using FFmpeg's `loudnorm` filter.
+/// Output keeps the sample rate, format and channel layout of the input.
+pub struct LoudnessNormalize {
+    mode: Mode,
+}
+
+enum Mode {
+    Passthrough(VecDeque<frame::Audio>),
+    Filter(AudioFilterGraph),
+}
+
+impl LoudnessNormalize {
+    /// Second pass of loudness normalization, using a [`LoudnessMeter`] measurement of the
+    /// same input. Applies a single linear gain where possible, and leaves the audio untouched
+    /// if it's already within [`LoudnessTarget::tolerance`] of the target.
+    pub fn two_pass(
+        info: &AudioInfo,
+        target: LoudnessTarget,
+        measurement: LoudnessMeasurement,
+    ) -> Result<Self, MediaError> {
+        if (measurement.integrated - target.integrated).abs() <= target.tolerance
+            && measurement.true_peak <= target.true_peak
+        {
+            debug!(
+                "Loudness {:.1} LUFS is within tolerance of {:.1} LUFS, passing through",
+                measurement.integrated, target.integrated
+            );
+
+            return Ok(Self {
+                mode: Mode::Passthrough(VecDeque::new()),
+            });
+        }
+
+        let loudnorm = format!(
+            "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:linear=true",
+            loudnorm_args(&target),
+            measurement.integrated,
+            measurement.true_peak,
+            measurement.loudness_range,
+            measurement.threshold()
+        );
+
+        Self::with_loudnorm(info, &loudnorm)
+    }
+
+    /// Single-pass normalization for live audio, where the input can't be measured up front.
+    /// Gain is adjusted continuously, which adds a few seconds of latency.
+    pub fn dynamic(info: &AudioInfo, target: LoudnessTarget) -> Result<Self, MediaError> {
+        Self::with_loudnorm(info, &loudnorm_args(&target))
+    }
+
+    fn with_loudnorm(info: &AudioInfo, loudnorm: &str) -> Result<Self, MediaError> {
+        // loudnorm always outputs 192kHz, so convert back to what we were given
+        let spec = format!(
+            "{loudnorm},aresample={},aformat=sample_fmts={}:channel_layouts=0x{:x}",
+            info.rate(),
+            info.sample_format.name(),
+            info.channel_layout().bits()
+        );
+
+        Ok(Self {
+            mode: Mode::Filter(AudioFilterGraph::new(info, &spec)?),
+        })
+    }
+
+    pub fn is_passthrough(&self) -> bool {
+        matches!(self.mode, Mode::Passthrough(_))
+    }
+
+    pub fn push_frame(&mut self, frame: &frame::Audio) -> Result<(), MediaError> {
+        match &mut self.mode {
+            Mode::Passthrough(queue) => queue.push_back(frame.clone()),
+            Mode::Filter(graph) => graph.push(frame)?,
+        }
+
+        Ok(())
+    }
+
+    /// Call once the input has ended to receive any frames still held for lookahead.
+    pub fn flush(&mut self) -> Result<(), MediaError> {
+        match &mut self.mode {
+            Mode::Passthrough(_) => Ok(()),
+            Mode::Filter(graph) => graph.flush(),
+        }
+    }
+
+    pub fn receive_frame(&mut self) -> Option<frame::Audio> {
+        match &mut self.mode {
+            Mode::Passthrough(queue) => queue.pop_front(),
+            Mode::Filter(graph) => graph.receive(),
+        }
+    }
+}
+
+fn loudnorm_args(target: &LoudnessTarget) -> String {
+    format!(
+        "loudnorm=I={}:TP={}:LRA={}",
+        target.integrated, target.true_peak, target.loudness_range
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ffmpeg::{Dictionary, format::Sample, format::sample::Type};
+
+    fn metadata(true_peak: &str) -> Dictionary<'static> {
+        let mut metadata = Dictionary::new();
+        metadata.set("lavfi.r128.I", "-23.5");
+        metadata.set("lavfi.r128.true_peak", true_peak);
+        metadata.set("lavfi.r128.LRA", "6.1");
+        metadata
+    }
+
+    fn measurement(integrated: f64, true_peak: f64) -> LoudnessMeasurement {
+        LoudnessMeasurement {
+            integrated,
+            true_peak,
+            loudness_range: 6.0,
+        }
+    }
+
+    fn normalize(measurement: LoudnessMeasurement) -> LoudnessNormalize {
+        ffmpeg::init().unwrap();
+        let info = AudioInfo::new(Sample::F32(Type::Packed), 48_000, 2).unwrap();
+
+        LoudnessNormalize::two_pass(&info, LoudnessTarget::default(), measurement).unwrap()
+    }
+
+    #[test]
+    fn converts_true_peak_to_dbtp() {
+        let measurement = LoudnessMeasurement::from_metadata(&metadata("0.5")).unwrap();
+
+        assert_eq!(measurement.integrated, -23.5);
+        assert_eq!(measurement.loudness_range, 6.1);
+        assert!((measurement.true_peak - -6.0206).abs() < 0.001);
+
+        let full_scale = LoudnessMeasurement::from_metadata(&metadata("1")).unwrap();
+        assert_eq!(full_scale.true_peak, 0.0);
+    }
+
+    #[test]
+    fn clamps_silent_true_peak() {
+        let measurement = LoudnessMeasurement::from_metadata(&metadata("0")).unwrap();
+        assert_eq!(measurement.true_peak, LoudnessMeasurement::MIN_TRUE_PEAK);
+    }
+
+    #[test]
+    fn needs_every_value() {
+        let mut metadata = Dictionary::new();
+        metadata.set("lavfi.r128.I", "-23.5");
+        metadata.set("lavfi.r128.true_peak", "0.5");
+
+        assert_eq!(LoudnessMeasurement::from_metadata(&metadata), None);
+    }
+
+    #[test]
+    fn passes_through_when_within_target() {
+        assert!(normalize(measurement(-16.5, -3.0)).is_passthrough());
+        assert!(normalize(measurement(-15.0, -1.5)).is_passthrough());
+    }
+
+    #[test]
+    fn filters_when_outside_target() {
+        // too quiet
+        assert!(!normalize(measurement(-23.0, -3.0)).is_passthrough());
+        // loud enough, but peaking above the target
+        assert!(!normalize(measurement(-16.0, -0.5)).is_passthrough());
+    }
+}
@@ -0,0 +1,28 @@
+mod channels;
+mod cursor;
+mod fade;
+mod frame_score;
+mod gain;
+mod graph;
+mod input_overlay;
+mod loudness;
+mod redaction;
+mod sample_format;
+mod silence;
+mod subtitles;
+mod tonemap;
+mod watermark;
+
+pub use channels::*;
+pub use cursor::*;
+pub use fade::*;
+pub use frame_score::*;
+pub use gain::*;
+pub use input_overlay::*;
+pub use loudness::*;
+pub use redaction::*;
+pub use sample_format::*;
+pub use silence::*;
+pub use subtitles::*;
+pub use tonemap::*;
+pub use watermark::*;
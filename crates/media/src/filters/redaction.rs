@@ -0,0 +1,304 @@
+use ffmpeg::{format::Pixel, frame};
+
+use crate::MediaError;
+
+/// How a [`RedactionRegion`] hides what's underneath it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedactionStyle {
+    /// Gaussian blur with this standard deviation, in pixels. Text usually needs
+    /// at least a quarter of its height to become unreadable.
+    Blur { sigma: f32 },
+    /// Solid RGB colour
+    Fill([u8; 3]),
+}
+
+/// A rectangle of the video hidden between two times.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RedactionRegion {
+    /// Seconds from the start of the timeline the region is hidden from
+    pub start: f64,
+    /// Seconds from the start of the timeline the region is hidden until, exclusive
+    pub end: f64,
+    /// Left edge, as a fraction of the frame's width so regions don't depend on the
+    /// export resolution
+    pub x: f64,
+    /// Top edge, as a fraction of the frame's height
+    pub y: f64,
+    /// Fraction of the frame's width
+    pub width: f64,
+    /// Fraction of the frame's height
+    pub height: f64,
+    pub style: RedactionStyle,
+}
+
+impl RedactionRegion {
+    /// Left, top, right and bottom in pixels, clamped to the frame.
+    fn bounds(&self, width: u32, height: u32) -> (usize, usize, usize, usize) {
+        let (width, height) = (width as f64, height as f64);
+        let horizontal = |v: f64| (v * width).clamp(0.0, width);
+        let vertical = |v: f64| (v * height).clamp(0.0, height);
+
+        (
+            horizontal(self.x).floor() as usize,
+            vertical(self.y).floor() as usize,
+            horizontal(self.x + self.width).ceil() as usize,
+            vertical(self.y + self.height).ceil() as usize,
+        )
+    }
+}
+
+/// Blurs or fills rectangles of each video frame, to hide things like passwords
+/// or notifications that showed up while recording.
+///
+/// Regions overlapping each other are applied in order. Only the color channels of the
+/// frame are changed, so its own alpha is preserved.
+#[derive(Debug, Clone)]
+pub struct RedactionFilter {
+    regions: Vec<RedactionRegion>,
+}
+
+impl RedactionFilter {
+    pub fn new(regions: Vec<RedactionRegion>) -> Self {
+        Self { regions }
+    }
+
+    pub fn regions(&self) -> &[RedactionRegion] {
+        &self.regions
+    }
+
+    /// Redacts the frame shown at `time` seconds from the start of the timeline.
+    /// Supports packed 8-bit RGBA and BGRA frames.
+    pub fn apply(&self, frame: &mut frame::Video, time: f64) -> Result<(), MediaError> {
+        let channels = match frame.format() {
+            Pixel::RGBA | Pixel::RGBZ => [0, 1, 2],
+            Pixel::BGRA | Pixel::BGRZ => [2, 1, 0],
+            format => {
+                return Err(MediaError::Any(
+                    format!("Redaction doesn't support {format:?} frames").into(),
+                ));
+            }
+        };
+
+        let (width, height, stride) = (frame.width(), frame.height(), frame.stride(0));
+        self.redact(frame.data_mut(0), stride, width, height, channels, time);
+
+        Ok(())
+    }
+
+    /// Same as [`RedactionFilter::apply`], for packed RGBA pixels with rows `stride` bytes apart.
+    pub fn apply_rgba(&self, data: &mut [u8], stride: usize, width: u32, height: u32, time: f64) {
+        self.redact(data, stride, width, height, [0, 1, 2], time);
+    }
+
+    /// `channels` are the byte offsets of red, green and blue in each 4-byte pixel.
+    fn redact(
+        &self,
+        data: &mut [u8],
+        stride: usize,
+        width: u32,
+        height: u32,
+        channels: [usize; 3],
+        time: f64,
+    ) {
+        for region in &self.regions {
+            if !(region.start..region.end).contains(&time) {
+                continue;
+            }
+
+            let bounds = region.bounds(width, height);
+            if bounds.0 >= bounds.2 || bounds.1 >= bounds.3 {
+                continue;
+            }
+
+            match region.style {
+                RedactionStyle::Fill(color) => fill(data, stride, bounds, channels, color),
+                RedactionStyle::Blur { sigma } => blur(data, stride, bounds, sigma),
+            }
+        }
+    }
+}
+
+fn fill(
+    data: &mut [u8],
+    stride: usize,
+    (left, top, right, bottom): (usize, usize, usize, usize),
+    channels: [usize; 3],
+    color: [u8; 3],
+) {
+    for y in top..bottom {
+        for pixel in data[y * stride + left * 4..y * stride + right * 4].chunks_exact_mut(4) {
+            for (channel, value) in channels.iter().zip(color) {
+                pixel[*channel] = value;
+            }
+        }
+    }
+}
+
+/// Approximates a gaussian blur with three box blurs, which takes the same time for any
+/// `sigma`. Pixels outside the region aren't sampled, so nothing bleeds into it.
+fn blur(
+    data: &mut [u8],
+    stride: usize,
+    (left, top, right, bottom): (usize, usize, usize, usize),
+    sigma: f32,
+) {
+    if sigma <= 0.0 {
+        return;
+    }
+
+    let (width, height) = (right - left, bottom - top);
+
+    // the color channels of the region, which are blurred the same way whatever their order
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for y in top..bottom {
+        for pixel in data[y * stride + left * 4..y * stride + right * 4].chunks_exact(4) {
+            pixels.extend(pixel[..3].iter().map(|&v| v as f32));
+        }
+    }
+    let mut scratch = vec![0.0; pixels.len()];
+
+    for size in box_sizes(sigma) {
+        let radius = (size - 1) / 2;
+        for y in 0..height {
+            for c in 0..3 {
+                box_blur_line(&pixels, &mut scratch, y * width * 3 + c, 3, width, radius);
+            }
+        }
+        for x in 0..width {
+            for c in 0..3 {
+                box_blur_line(&scratch, &mut pixels, x * 3 + c, width * 3, height, radius);
+            }
+        }
+    }
+
+    for (y, row) in pixels.chunks_exact(width * 3).enumerate() {
+        let start = (top + y) * stride + left * 4;
+        for (pixel, blurred) in data[start..start + width * 4]
+            .chunks_exact_mut(4)
+            .zip(row.chunks_exact(3))
+        {
+            for (value, blurred) in pixel.iter_mut().zip(blurred) {
+                *value = blurred.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+/// Odd widths of three box blurs that add up to a gaussian blur of `sigma`.
+fn box_sizes(sigma: f32) -> [usize; 3] {
+    let ideal = (4.0 * sigma * sigma + 1.0).sqrt();
+    let mut lower = ideal.floor().max(1.0) as usize;
+    if lower % 2 == 0 {
+        lower -= 1;
+    }
+
+    let lower_f = lower as f32;
+    let lower_count = ((12.0 * sigma * sigma - 3.0 * lower_f * lower_f - 12.0 * lower_f - 9.0)
+        / (-4.0 * lower_f - 4.0))
+        .round()
+        .max(0.0) as usize;
+
+    [0, 1, 2].map(|i| if i < lower_count { lower } else { lower + 2 })
+}
+
+/// Averages each value of a line with the `radius` values either side of it, repeating
+/// the values at the ends. The line's values are `step` apart from `start`.
+fn box_blur_line(
+    source: &[f32],
+    target: &mut [f32],
+    start: usize,
+    step: usize,
+    len: usize,
+    radius: usize,
+) {
+    let at = |i: isize| source[start + i.clamp(0, len as isize - 1) as usize * step];
+    let radius = radius as isize;
+    let scale = 1.0 / (2 * radius + 1) as f32;
+
+    let mut sum = (-radius..=radius).map(at).sum::<f32>();
+    for i in 0..len as isize {
+        target[start + i as usize * step] = sum * scale;
+        sum += at(i + radius + 1) - at(i - radius);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const STRIDE: usize = 8 * 4;
+
+    fn region(style: RedactionStyle) -> RedactionRegion {
+        RedactionRegion {
+            start: 1.0,
+            end: 2.0,
+            x: 0.25,
+            y: 0.25,
+            width: 0.5,
+            height: 0.5,
+            style,
+        }
+    }
+
+    fn pixel(data: &[u8], x: usize, y: usize) -> &[u8] {
+        &data[y * STRIDE + x * 4..y * STRIDE + x * 4 + 4]
+    }
+
+    #[test]
+    fn fills_region_during_its_time_range() {
+        let filter = RedactionFilter::new(vec![region(RedactionStyle::Fill([10, 20, 30]))]);
+        let mut data = vec![200; 8 * 8 * 4];
+
+        filter.redact(&mut data, STRIDE, 8, 8, [2, 1, 0], 0.5);
+        filter.redact(&mut data, STRIDE, 8, 8, [2, 1, 0], 2.0);
+        assert!(data.iter().all(|&v| v == 200));
+
+        filter.redact(&mut data, STRIDE, 8, 8, [2, 1, 0], 1.5);
+        assert_eq!(pixel(&data, 2, 2), [30, 20, 10, 200]);
+        assert_eq!(pixel(&data, 5, 5), [30, 20, 10, 200]);
+        assert_eq!(pixel(&data, 1, 2), [200; 4]);
+        assert_eq!(pixel(&data, 6, 5), [200; 4]);
+    }
+
+    #[test]
+    fn blurs_only_inside_region() {
+        let filter = RedactionFilter::new(vec![region(RedactionStyle::Blur { sigma: 2.0 })]);
+        let mut data = vec![0; 8 * 8 * 4];
+        data[3 * STRIDE + 3 * 4..3 * STRIDE + 4 * 4].copy_from_slice(&[255, 255, 255, 255]);
+        data[0..4].copy_from_slice(&[255, 255, 255, 255]);
+
+        filter.redact(&mut data, STRIDE, 8, 8, [0, 1, 2], 1.0);
+
+        // spread out over the region, keeping its alpha
+        assert!(pixel(&data, 3, 3)[0] < 64);
+        assert_eq!(pixel(&data, 3, 3)[3], 255);
+        assert!(pixel(&data, 5, 5)[0] > 0);
+        // outside the region
+        assert_eq!(pixel(&data, 0, 0), [255; 4]);
+        assert_eq!(pixel(&data, 6, 6), [0; 4]);
+    }
+
+    #[test]
+    fn box_blurs_approximate_gaussian() {
+        assert_eq!(box_sizes(0.1), [1, 1, 1]);
+
+        // a box of width w has a variance of (w^2 - 1) / 12
+        for sigma in [2.5, 8.0, 30.0] {
+            let variance = box_sizes(sigma)
+                .iter()
+                .map(|&w| ((w * w - 1) as f32) / 12.0)
+                .sum::<f32>();
+            assert!((variance.sqrt() - sigma).abs() < sigma * 0.1, "{sigma}");
+        }
+    }
+
+    #[test]
+    fn keeps_uniform_areas_unchanged() {
+        let mut line = vec![7.0; 10];
+        let mut target = vec![0.0; 10];
+        box_blur_line(&line, &mut target, 0, 1, 10, 3);
+        line.copy_from_slice(&target);
+
+        assert!(line.iter().all(|&v| (v - 7.0).abs() < 1e-4));
+    }
+}
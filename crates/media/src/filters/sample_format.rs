@@ -0,0 +1,211 @@
+use ffmpeg::{format::Sample, frame};
+
+use crate::MediaError;
+
+/// Converts audio to a different sample format, e.g. to hand an encoder the format it
+/// encodes from without it having to go through a resampler.
+///
+/// Converts between unsigned 8-bit, signed 16 and 32-bit and 32 and 64-bit float samples,
+/// packed or planar. Integer samples are scaled to floats between -1 and 1, and floats
+/// outside of that are clamped when converted to integers.
+#[derive(Debug, Clone, Copy)]
+pub struct SampleFormatConverter {
+    target: Sample,
+}
+
+impl SampleFormatConverter {
+    pub fn new(target: Sample) -> Self {
+        Self { target }
+    }
+
+    pub fn target(&self) -> Sample {
+        self.target
+    }
+
+    /// Returns the frame in the target format, or the frame itself if it's already in it.
+    pub fn apply(&self, frame: frame::Audio) -> Result<frame::Audio, MediaError> {
+        if frame.format() == self.target {
+            return Ok(frame);
+        }
+
+        let (samples, channels) = (frame.samples(), frame.channels() as usize);
+        let values = read(&frame)?;
+
+        let mut output = frame::Audio::new(self.target, samples, frame.channel_layout());
+        output.set_rate(frame.rate());
+        output.set_pts(frame.pts());
+
+        let size = self.target.bytes();
+        if output.is_planar() {
+            for channel in 0..channels {
+                let plane = &mut output.data_mut(channel)[..samples * size];
+                for (bytes, value) in plane
+                    .chunks_exact_mut(size)
+                    .zip(values.iter().skip(channel).step_by(channels))
+                {
+                    write_sample(self.target, *value, bytes)?;
+                }
+            }
+        } else {
+            let data = &mut output.data_mut(0)[..samples * channels * size];
+            for (bytes, value) in data.chunks_exact_mut(size).zip(&values) {
+                write_sample(self.target, *value, bytes)?;
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+/// The frame's samples interleaved, as floats.
+fn read(frame: &frame::Audio) -> Result<Vec<f64>, MediaError> {
+    let format = frame.format();
+    let (samples, channels, size) = (frame.samples(), frame.channels() as usize, format.bytes());
+
+    if !frame.is_planar() {
+        return frame.data(0)[..samples * channels * size]
+            .chunks_exact(size)
+            .map(|bytes| read_sample(format, bytes))
+            .collect();
+    }
+
+    let mut output = vec![0.0; samples * channels];
+    for channel in 0..channels {
+        let plane = &frame.data(channel)[..samples * size];
+        for (i, bytes) in plane.chunks_exact(size).enumerate() {
+            output[i * channels + channel] = read_sample(format, bytes)?;
+        }
+    }
+
+    Ok(output)
+}
+
+fn read_sample(format: Sample, bytes: &[u8]) -> Result<f64, MediaError> {
+    Ok(match format {
+        Sample::U8(_) => (bytes[0] as f64 - 128.0) / 128.0,
+        Sample::I16(_) => i16::from_ne_bytes(bytes.try_into().unwrap()) as f64 / 32768.0,
+        Sample::I32(_) => i32::from_ne_bytes(bytes.try_into().unwrap()) as f64 / 2147483648.0,
+        Sample::F32(_) => f32::from_ne_bytes(bytes.try_into().unwrap()) as f64,
+        Sample::F64(_) => f64::from_ne_bytes(bytes.try_into().unwrap()),
+        format => return Err(unsupported(format)),
+    })
+}
+
+fn write_sample(format: Sample, value: f64, bytes: &mut [u8]) -> Result<(), MediaError> {
+    let scale = |max: f64| (value * max).round().clamp(-max, max - 1.0);
+
+    match format {
+        Sample::U8(_) => bytes[0] = (scale(128.0) + 128.0) as u8,
+        Sample::I16(_) => bytes.copy_from_slice(&(scale(32768.0) as i16).to_ne_bytes()),
+        Sample::I32(_) => bytes.copy_from_slice(&(scale(2147483648.0) as i32).to_ne_bytes()),
+        Sample::F32(_) => bytes.copy_from_slice(&(value as f32).to_ne_bytes()),
+        Sample::F64(_) => bytes.copy_from_slice(&value.to_ne_bytes()),
+        format => return Err(unsupported(format)),
+    }
+
+    Ok(())
+}
+
+fn unsupported(format: Sample) -> MediaError {
+    MediaError::Any(format!("Unsupported sample format for conversion: {format:?}").into())
+}
+
+#[cfg(test)]
+mod test {
+    use ffmpeg::{ChannelLayout, format::sample::Type};
+
+    use super::*;
+
+    fn stereo(format: Sample, left: &[u8], right: &[u8]) -> frame::Audio {
+        let samples = left.len() / format.bytes();
+        let mut frame = frame::Audio::new(format, samples, ChannelLayout::STEREO);
+        frame.set_rate(48_000);
+        frame.set_pts(Some(480));
+
+        if frame.is_planar() {
+            frame.data_mut(0)[..left.len()].copy_from_slice(left);
+            frame.data_mut(1)[..right.len()].copy_from_slice(right);
+        } else {
+            let interleaved = left
+                .chunks_exact(format.bytes())
+                .zip(right.chunks_exact(format.bytes()))
+                .flat_map(|(l, r)| [l, r].concat())
+                .collect::<Vec<_>>();
+            frame.data_mut(0)[..interleaved.len()].copy_from_slice(&interleaved);
+        }
+
+        frame
+    }
+
+    fn i16_bytes(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_ne_bytes()).collect()
+    }
+
+    fn f32_bytes(samples: &[f32]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_ne_bytes()).collect()
+    }
+
+    #[test]
+    fn converts_packed_s16_to_planar_f32() {
+        let frame = stereo(
+            Sample::I16(Type::Packed),
+            &i16_bytes(&[16384, -32768]),
+            &i16_bytes(&[0, 8192]),
+        );
+
+        let output = SampleFormatConverter::new(Sample::F32(Type::Planar))
+            .apply(frame)
+            .unwrap();
+
+        assert_eq!(output.format(), Sample::F32(Type::Planar));
+        assert_eq!(output.rate(), 48_000);
+        assert_eq!(output.pts(), Some(480));
+        assert_eq!(&output.data(0)[..8], f32_bytes(&[0.5, -1.0]));
+        assert_eq!(&output.data(1)[..8], f32_bytes(&[0.0, 0.25]));
+    }
+
+    #[test]
+    fn clamps_floats_to_s16() {
+        let frame = stereo(
+            Sample::F32(Type::Planar),
+            &f32_bytes(&[1.5, -0.5]),
+            &f32_bytes(&[-2.0, 0.0]),
+        );
+
+        let output = SampleFormatConverter::new(Sample::I16(Type::Packed))
+            .apply(frame)
+            .unwrap();
+
+        assert_eq!(&output.data(0)[..8], i16_bytes(&[32767, -32768, -16384, 0]));
+    }
+
+    #[test]
+    fn round_trips_s16() {
+        let samples = i16_bytes(&[1, -1, 12345, i16::MIN]);
+        let frame = stereo(Sample::I16(Type::Planar), &samples, &samples);
+
+        let float = SampleFormatConverter::new(Sample::F64(Type::Packed))
+            .apply(frame)
+            .unwrap();
+        let output = SampleFormatConverter::new(Sample::I16(Type::Planar))
+            .apply(float)
+            .unwrap();
+
+        assert_eq!(&output.data(0)[..8], samples);
+        assert_eq!(&output.data(1)[..8], samples);
+    }
+
+    #[test]
+    fn centers_u8_silence() {
+        let frame = stereo(Sample::U8(Type::Packed), &[128, 255], &[0, 128]);
+
+        let output = SampleFormatConverter::new(Sample::F32(Type::Packed))
+            .apply(frame)
+            .unwrap();
+
+        assert_eq!(
+            &output.data(0)[..16],
+            f32_bytes(&[0.0, -1.0, 127.0 / 128.0, 0.0])
+        );
+    }
+}
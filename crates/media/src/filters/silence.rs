@@ -0,0 +1,252 @@
+use std::time::Duration;
+
+use ffmpeg::{format::Sample, frame};
+
+use crate::MediaError;
+
+/// Finds regions of audio that stay below `threshold_dbfs` for at least `min_duration`,
+/// so the editor can suggest trimming them.
+///
+/// Loudness is measured as the RMS of each pushed frame across all channels. Positions are
+/// in seconds, relative to the start of the first pushed frame.
+#[derive(Debug, Clone)]
+pub struct SilenceDetector {
+    threshold_dbfs: f64,
+    min_duration: f64,
+    position: f64,
+    silence_start: Option<f64>,
+    regions: Vec<(f64, f64)>,
+}
+
+impl SilenceDetector {
+    pub const DEFAULT_THRESHOLD_DBFS: f64 = -50.0;
+    pub const DEFAULT_MIN_DURATION: Duration = Duration::from_millis(500);
+
+    pub fn new(threshold_dbfs: f64, min_duration: Duration) -> Self {
+        Self {
+            threshold_dbfs,
+            min_duration: min_duration.as_secs_f64(),
+            position: 0.0,
+            silence_start: None,
+            regions: vec![],
+        }
+    }
+
+    /// Returns the silent region that this frame ended, if any.
+    pub fn push_frame(&mut self, frame: &frame::Audio) -> Result<Option<(f64, f64)>, MediaError> {
+        let sum_of_squares = sum_of_squares(frame)?;
+        let sample_count = frame.samples() * frame.channels() as usize;
+
+        Ok(self.push_measurement(
+            sum_of_squares,
+            sample_count,
+            frame.samples() as f64 / frame.rate() as f64,
+        ))
+    }
+
+    /// Same as [`SilenceDetector::push_frame`], for interleaved `f32` samples.
+    pub fn push_samples(
+        &mut self,
+        samples: &[f32],
+        channels: usize,
+        sample_rate: u32,
+    ) -> Option<(f64, f64)> {
+        let sum_of_squares = samples.iter().map(|&s| (s as f64).powi(2)).sum();
+
+        self.push_measurement(
+            sum_of_squares,
+            samples.len(),
+            (samples.len() / channels) as f64 / sample_rate as f64,
+        )
+    }
+
+    /// Closes any silence that runs to the end of the audio and returns every region found.
+    pub fn finish(mut self) -> Vec<(f64, f64)> {
+        if let Some(region) = self.end_silence() {
+            self.regions.push(region);
+        }
+
+        self.regions
+    }
+
+    pub fn regions(&self) -> &[(f64, f64)] {
+        &self.regions
+    }
+
+    fn push_measurement(
+        &mut self,
+        sum_of_squares: f64,
+        sample_count: usize,
+        duration: f64,
+    ) -> Option<(f64, f64)> {
+        if sample_count == 0 {
+            return None;
+        }
+
+        let rms = (sum_of_squares / sample_count as f64).sqrt();
+        let is_silent = 20.0 * rms.log10() < self.threshold_dbfs;
+
+        let ended = if is_silent {
+            self.silence_start.get_or_insert(self.position);
+            None
+        } else {
+            self.end_silence()
+        };
+
+        self.position += duration;
+
+        if let Some(region) = ended {
+            self.regions.push(region);
+        }
+
+        ended
+    }
+
+    fn end_silence(&mut self) -> Option<(f64, f64)> {
+        let start = self.silence_start.take()?;
+
+        (self.position - start >= self.min_duration).then_some((start, self.position))
+    }
+}
+
+impl Default for SilenceDetector {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_THRESHOLD_DBFS, Self::DEFAULT_MIN_DURATION)
+    }
+}
+
+fn sum_of_squares(frame: &frame::Audio) -> Result<f64, MediaError> {
+    fn sum<const N: usize>(data: &[u8], to_f64: impl Fn([u8; N]) -> f64) -> f64 {
+        data.chunks_exact(N)
+            .map(|b| to_f64(b.try_into().unwrap()).powi(2))
+            .sum()
+    }
+
+    let planes = if frame.is_planar() {
+        frame.channels() as usize
+    } else {
+        1
+    };
+    let plane_len = frame.samples() * frame.format().bytes() * frame.channels() as usize / planes;
+
+    let mut total = 0.0;
+
+    for plane in 0..planes {
+        let data = &frame.data(plane)[..plane_len];
+
+        total += match frame.format() {
+            Sample::F32(_) => sum(data, |b| f32::from_ne_bytes(b) as f64),
+            Sample::F64(_) => sum(data, f64::from_ne_bytes),
+            Sample::I16(_) => sum(data, |b| i16::from_ne_bytes(b) as f64 / i16::MAX as f64),
+            Sample::I32(_) => sum(data, |b| i32::from_ne_bytes(b) as f64 / i32::MAX as f64),
+            format => {
+                return Err(MediaError::Any(
+                    format!("Unsupported sample format for silence detection: {format:?}").into(),
+                ));
+            }
+        };
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48_000;
+    const CHUNK: usize = 1024;
+
+    fn sine(seconds: f64) -> Vec<f32> {
+        (0..(seconds * SAMPLE_RATE as f64) as usize)
+            .map(|i| {
+                0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / SAMPLE_RATE as f32).sin()
+            })
+            .collect()
+    }
+
+    fn silence(seconds: f64) -> Vec<f32> {
+        vec![0.0; (seconds * SAMPLE_RATE as f64) as usize]
+    }
+
+    fn detect(samples: &[f32], min_duration: Duration) -> Vec<(f64, f64)> {
+        let mut detector = SilenceDetector::new(-40.0, min_duration);
+        for chunk in samples.chunks(CHUNK) {
+            detector.push_samples(chunk, 1, SAMPLE_RATE);
+        }
+        detector.finish()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        let tolerance = CHUNK as f64 / SAMPLE_RATE as f64;
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{actual} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn sine_then_silence() {
+        let samples = [sine(1.0), silence(1.0)].concat();
+
+        let regions = detect(&samples, Duration::from_millis(500));
+
+        assert_eq!(regions.len(), 1);
+        assert_close(regions[0].0, 1.0);
+        assert_close(regions[0].1, 2.0);
+    }
+
+    #[test]
+    fn leading_and_trailing_silence() {
+        let samples = [silence(0.75), sine(1.0), silence(0.75)].concat();
+
+        let regions = detect(&samples, Duration::from_millis(500));
+
+        assert_eq!(regions.len(), 2);
+        assert_close(regions[0].0, 0.0);
+        assert_close(regions[0].1, 0.75);
+        assert_close(regions[1].0, 1.75);
+        assert_close(regions[1].1, 2.5);
+    }
+
+    #[test]
+    fn ignores_short_gaps() {
+        let samples = [sine(1.0), silence(0.2), sine(1.0)].concat();
+
+        assert!(detect(&samples, Duration::from_millis(500)).is_empty());
+    }
+
+    #[test]
+    fn reports_region_when_sound_resumes() {
+        let mut detector = SilenceDetector::new(-40.0, Duration::from_millis(500));
+        let mut ended = vec![];
+
+        for chunk in [silence(1.0), sine(0.5)].concat().chunks(CHUNK) {
+            ended.extend(detector.push_samples(chunk, 1, SAMPLE_RATE));
+        }
+
+        assert_eq!(ended.len(), 1);
+        assert_eq!(detector.regions(), ended.as_slice());
+    }
+
+    #[test]
+    fn planar_frame() {
+        let mut detector = SilenceDetector::new(-40.0, Duration::ZERO);
+
+        let mut frame = frame::Audio::new(
+            Sample::F32(ffmpeg::format::sample::Type::Planar),
+            CHUNK,
+            ffmpeg::ChannelLayout::STEREO,
+        );
+        frame.set_rate(SAMPLE_RATE);
+        for plane in 0..2 {
+            frame.plane_mut::<f32>(plane).fill(0.0);
+        }
+
+        assert_eq!(detector.push_frame(&frame).unwrap(), None);
+
+        let regions = detector.finish();
+        assert_eq!(regions.len(), 1);
+        assert_close(regions[0].1, CHUNK as f64 / SAMPLE_RATE as f64);
+    }
+}
@@ -0,0 +1,297 @@
+use cap_media_info::VideoInfo;
+use ffmpeg::frame;
+use std::{
+    fmt::Write,
+    path::PathBuf,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use super::graph::VideoFilterGraph;
+use crate::MediaError;
+
+/// libass lays out SRT subtitles on a script this many pixels high,
+/// and scales it to the height of the video.
+const ASS_PLAY_RES_Y: f64 = 288.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubtitleCue {
+    /// Seconds from the start of the video.
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SubtitlePosition {
+    Top,
+    Center,
+    #[default]
+    Bottom,
+}
+
+impl SubtitlePosition {
+    /// Numpad-style alignment used by ASS styles.
+    fn ass_alignment(&self) -> u8 {
+        match self {
+            Self::Top => 8,
+            Self::Center => 5,
+            Self::Bottom => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubtitleStyle {
+    pub font: String,
+    /// Height of the text in pixels of the output video.
+    pub size: u32,
+    pub position: SubtitlePosition,
+    /// Distance from the top or bottom edge in pixels of the output video.
+    pub margin: u32,
+}
+
+impl Default for SubtitleStyle {
+    fn default() -> Self {
+        Self {
+            font: "Arial".to_string(),
+            size: 48,
+            position: SubtitlePosition::default(),
+            margin: 40,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SubtitleTrack {
+    pub cues: Vec<SubtitleCue>,
+    pub style: SubtitleStyle,
+}
+
+impl SubtitleTrack {
+    pub fn new(cues: Vec<SubtitleCue>, style: SubtitleStyle) -> Self {
+        Self { cues, style }
+    }
+
+    /// Parses the contents of an `.srt` file. Cue numbers are optional.
+    pub fn from_srt(srt: &str, style: SubtitleStyle) -> Result<Self, MediaError> {
+        let srt = srt.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+        let mut cues = vec![];
+
+        for block in srt.split("\n\n").map(str::trim).filter(|b| !b.is_empty()) {
+            let mut lines = block.lines();
+            let mut timing = lines.next().unwrap_or_default();
+            if !timing.contains("-->") {
+                timing = lines.next().unwrap_or_default();
+            }
+
+            let (start, end) = timing
+                .split_once("-->")
+                .and_then(|(start, end)| {
+                    // positioning hints can follow the end time
+                    let end = end.split_whitespace().next()?;
+                    Some((parse_srt_time(start)?, parse_srt_time(end)?))
+                })
+                .ok_or_else(|| {
+                    MediaError::Any(format!("Invalid SRT timing line '{timing}'").into())
+                })?;
+
+            cues.push(SubtitleCue {
+                start,
+                end,
+                text: lines.collect::<Vec<_>>().join("\n"),
+            });
+        }
+
+        Ok(Self { cues, style })
+    }
+
+    pub fn to_srt(&self) -> String {
+        let mut srt = String::new();
+
+        for (i, cue) in self
+            .cues
+            .iter()
+            .filter(|c| c.end > c.start && !c.text.trim().is_empty())
+            .enumerate()
+        {
+            let _ = write!(
+                srt,
+                "{}\n{} --> {}\n{}\n\n",
+                i + 1,
+                format_srt_time(cue.start),
+                format_srt_time(cue.end),
+                cue.text.trim()
+            );
+        }
+
+        srt
+    }
+}
+
+fn parse_srt_time(time: &str) -> Option<f64> {
+    let (hms, millis) = time.trim().split_once([',', '.'])?;
+    let mut parts = hms.split(':').map(|v| v.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some(
+        (hours * 3600 + minutes * 60 + seconds) as f64
+            + millis.parse::<u64>().ok()? as f64 / 1000.0,
+    )
+}
+
+fn format_srt_time(seconds: f64) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Renders a [`SubtitleTrack`] onto video frames with FFmpeg's `subtitles` filter.
+/// Frame timestamps are matched against the cue times using the time base of `info`.
+pub struct SubtitleBurner {
+    graph: VideoFilterGraph,
+    srt_path: PathBuf,
+}
+
+impl SubtitleBurner {
+    pub fn new(info: &VideoInfo, track: &SubtitleTrack) -> Result<Self, MediaError> {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+        // the subtitles filter can only read from a file
+        let srt_path = std::env::temp_dir().join(format!(
+            "cap-subtitles-{}-{}.srt",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&srt_path, track.to_srt())?;
+
+        let spec = format!(
+            "subtitles=filename='{}':force_style='{}'",
+            escape_filter_path(&srt_path),
+            force_style(&track.style, info.height)
+        );
+
+        match VideoFilterGraph::new(info, &spec) {
+            Ok(graph) => Ok(Self { graph, srt_path }),
+            Err(e) => {
+                let _ = std::fs::remove_file(&srt_path);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn push_frame(&mut self, frame: &frame::Video) -> Result<(), MediaError> {
+        self.graph.push(frame)
+    }
+
+    pub fn flush(&mut self) -> Result<(), MediaError> {
+        self.graph.flush()
+    }
+
+    pub fn receive_frame(&mut self) -> Option<frame::Video> {
+        self.graph.receive()
+    }
+}
+
+impl Drop for SubtitleBurner {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.srt_path);
+    }
+}
+
+fn force_style(style: &SubtitleStyle, video_height: u32) -> String {
+    let to_script = |px: u32| (px as f64 * ASS_PLAY_RES_Y / video_height.max(1) as f64).round();
+    let font: String = style
+        .font
+        .chars()
+        .filter(|c| !matches!(c, '\'' | ',' | ':' | '\\'))
+        .collect();
+
+    format!(
+        "FontName={font},FontSize={},Alignment={},MarginV={}",
+        to_script(style.size),
+        style.position.ass_alignment(),
+        to_script(style.margin)
+    )
+}
+
+/// Escapes a path for use as a quoted filter option, which treats `:` as a separator
+/// even inside quotes.
+pub(super) fn escape_filter_path(path: &std::path::Path) -> String {
+    path.to_string_lossy()
+        .replace('\\', "/")
+        .replace(':', "\\:")
+        .replace('\'', "")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SRT: &str = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,500\r\nHello\r\nworld\r\n\r\n2\r\n00:01:00.250 --> 00:01:01,000 X1:10\r\nSecond\r\n";
+
+    #[test]
+    fn parses_srt() {
+        let track = SubtitleTrack::from_srt(SRT, SubtitleStyle::default()).unwrap();
+
+        assert_eq!(
+            track.cues,
+            vec![
+                SubtitleCue {
+                    start: 1.0,
+                    end: 2.5,
+                    text: "Hello\nworld".to_string()
+                },
+                SubtitleCue {
+                    start: 60.25,
+                    end: 61.0,
+                    text: "Second".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_timing() {
+        assert!(
+            SubtitleTrack::from_srt("1\n00:00 --> soon\nHi", SubtitleStyle::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn round_trips_srt() {
+        let track = SubtitleTrack::from_srt(SRT, SubtitleStyle::default()).unwrap();
+        let reparsed = SubtitleTrack::from_srt(&track.to_srt(), SubtitleStyle::default()).unwrap();
+
+        assert_eq!(track, reparsed);
+    }
+
+    #[test]
+    fn formats_srt_time() {
+        assert_eq!(format_srt_time(3723.004), "01:02:03,004");
+        assert_eq!(format_srt_time(-1.0), "00:00:00,000");
+    }
+
+    #[test]
+    fn scales_style_to_script_resolution() {
+        let style = SubtitleStyle {
+            font: "Comic, Sans".to_string(),
+            size: 72,
+            position: SubtitlePosition::Top,
+            margin: 36,
+        };
+
+        assert_eq!(
+            force_style(&style, 576),
+            "FontName=Comic Sans,FontSize=36,Alignment=8,MarginV=18"
+        );
+    }
+}
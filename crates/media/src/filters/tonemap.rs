@@ -0,0 +1,313 @@
+use ffmpeg::{color, format::Pixel, frame};
+
+use crate::MediaError;
+
+/// Luminance of SDR white in HDR content, in nits, from ITU-R BT.2408.
+/// HDR values are scaled so this ends up as SDR white before tone mapping.
+const REFERENCE_WHITE_NITS: f32 = 203.0;
+/// Brightest highlight that keeps some detail. HDR video is usually mastered for 1000 nit
+/// displays, which is also what HLG's system gamma of 1.2 is defined for.
+/// Brighter PQ highlights are clipped.
+const PEAK_NITS: f32 = 1000.0;
+/// Output luminance above which highlights are compressed rather than passed through.
+const SHOULDER_START: f32 = 0.8;
+
+const LINEAR_LUT_SIZE: usize = 4096;
+const OUTPUT_LUT_SIZE: usize = 4096;
+
+/// The transfer function of an HDR video.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HdrTransfer {
+    /// SMPTE ST 2084 perceptual quantizer, in absolute nits up to 10000
+    Pq,
+    /// ARIB STD-B67 hybrid log-gamma, relative to the display's peak
+    Hlg,
+}
+
+impl HdrTransfer {
+    /// `None` for SDR transfer functions.
+    pub fn from_characteristic(trc: color::TransferCharacteristic) -> Option<Self> {
+        match trc {
+            color::TransferCharacteristic::SMPTE2084 => Some(Self::Pq),
+            color::TransferCharacteristic::ARIB_STD_B67 => Some(Self::Hlg),
+            _ => None,
+        }
+    }
+
+    /// The frame's transfer function, if it's HDR.
+    pub fn of(frame: &frame::Video) -> Option<Self> {
+        Self::from_characteristic(frame.color_transfer_characteristic())
+    }
+}
+
+/// Converts HDR video to SDR BT.709, so it doesn't look washed out once it's squeezed
+/// into 8 bits.
+///
+/// Colors are linearized with the frame's transfer function, scaled so HDR reference white
+/// becomes SDR white and converted from BT.2020 or Display P3 primaries to BT.709. Anything
+/// up to 80% of SDR white passes through unchanged and brighter highlights up to 1000 nits
+/// are rolled off smoothly, so typical screen content keeps its brightness while HDR highlights keep some
+/// detail. The result is encoded with the BT.709 transfer function.
+pub struct ToneMapFilter {
+    transfer: HdrTransfer,
+    gamut: [[f32; 3]; 3],
+    /// Linear light for 16-bit code values, relative to reference white for PQ and
+    /// scene-referred from 0 to 1 for HLG
+    to_linear: Vec<f32>,
+    /// BT.709 encoded 8-bit values for linear light from 0 to 1
+    to_sdr: Vec<u8>,
+}
+
+impl ToneMapFilter {
+    /// `primaries` other than BT.709 and Display P3 are treated as BT.2020,
+    /// which is what HDR video is almost always mastered in.
+    pub fn new(transfer: HdrTransfer, primaries: color::Primaries) -> Self {
+        let gamut = match primaries {
+            color::Primaries::BT709 => [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            color::Primaries::SMPTE432 => [
+                [1.2249, -0.2247, 0.0],
+                [-0.0420, 1.0419, 0.0],
+                [-0.0197, -0.0786, 1.0979],
+            ],
+            _ => [
+                [1.6605, -0.5876, -0.0728],
+                [-0.1246, 1.1329, -0.0083],
+                [-0.0182, -0.1006, 1.1187],
+            ],
+        };
+
+        let to_linear = (0..LINEAR_LUT_SIZE)
+            .map(|i| {
+                let code = i as f32 / (LINEAR_LUT_SIZE - 1) as f32;
+                match transfer {
+                    HdrTransfer::Pq => pq_to_nits(code) / REFERENCE_WHITE_NITS,
+                    HdrTransfer::Hlg => hlg_to_scene_linear(code),
+                }
+            })
+            .collect();
+
+        let to_sdr = (0..OUTPUT_LUT_SIZE)
+            .map(|i| {
+                let linear = i as f32 / (OUTPUT_LUT_SIZE - 1) as f32;
+                (bt709_oetf(linear) * 255.0).round() as u8
+            })
+            .collect();
+
+        Self {
+            transfer,
+            gamut,
+            to_linear,
+            to_sdr,
+        }
+    }
+
+    /// Tone maps a frame of 16-bit RGB, which is what HDR YUV frames should be converted to
+    /// first so no precision is lost, into a frame of 8-bit RGBA.
+    pub fn apply(&self, input: &frame::Video) -> Result<frame::Video, MediaError> {
+        if input.format() != Pixel::RGB48LE {
+            return Err(MediaError::Any(
+                format!(
+                    "Tone mapping needs RGB48LE frames, got {:?}",
+                    input.format()
+                )
+                .into(),
+            ));
+        }
+
+        let (width, height) = (input.width() as usize, input.height() as usize);
+        let mut output = frame::Video::new(Pixel::RGBA, input.width(), input.height());
+        let (input_stride, output_stride) = (input.stride(0), output.stride(0));
+        let input_data = input.data(0);
+        let output_data = output.data_mut(0);
+
+        for y in 0..height {
+            let input_row = &input_data[y * input_stride..][..width * 6];
+            let output_row = &mut output_data[y * output_stride..][..width * 4];
+
+            for (input, output) in input_row
+                .chunks_exact(6)
+                .zip(output_row.chunks_exact_mut(4))
+            {
+                let rgb =
+                    std::array::from_fn(|i| u16::from_le_bytes([input[i * 2], input[i * 2 + 1]]));
+                output[..3].copy_from_slice(&self.map_pixel(rgb));
+                output[3] = 255;
+            }
+        }
+
+        output.set_color_space(color::Space::BT709);
+        output.set_color_primaries(color::Primaries::BT709);
+        output.set_color_transfer_characteristic(color::TransferCharacteristic::BT709);
+
+        Ok(output)
+    }
+
+    fn map_pixel(&self, rgb: [u16; 3]) -> [u8; 3] {
+        let mut linear = rgb.map(|v| self.to_linear[v as usize * (LINEAR_LUT_SIZE - 1) / 65535]);
+
+        if self.transfer == HdrTransfer::Hlg {
+            // HLG's OOTF, which depends on the luminance of the whole pixel
+            let luminance = 0.2627 * linear[0] + 0.6780 * linear[1] + 0.0593 * linear[2];
+            let scale = PEAK_NITS / REFERENCE_WHITE_NITS * luminance.max(0.0).powf(0.2);
+            linear = linear.map(|v| v * scale);
+        }
+
+        let converted = self
+            .gamut
+            .map(|row| (row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2]).max(0.0));
+
+        let luminance = 0.2126 * converted[0] + 0.7152 * converted[1] + 0.0722 * converted[2];
+        let scale = if luminance > SHOULDER_START {
+            roll_off(luminance) / luminance
+        } else {
+            1.0
+        };
+
+        converted.map(|v| {
+            let v = (v * scale).clamp(0.0, 1.0);
+            self.to_sdr[(v * (OUTPUT_LUT_SIZE - 1) as f32).round() as usize]
+        })
+    }
+}
+
+/// Compresses luminance from [`SHOULDER_START`] up to [`PEAK_NITS`] into what's left up to 1,
+/// with an extended Reinhard curve. Its slope is 1 where it starts so there's no visible edge.
+fn roll_off(luminance: f32) -> f32 {
+    let headroom = 1.0 - SHOULDER_START;
+    let x = (luminance - SHOULDER_START) / headroom;
+    let peak = (PEAK_NITS / REFERENCE_WHITE_NITS - SHOULDER_START) / headroom;
+
+    SHOULDER_START + headroom * (x * (1.0 + x / (peak * peak)) / (1.0 + x)).min(1.0)
+}
+
+/// SMPTE ST 2084 EOTF
+fn pq_to_nits(code: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+    let p = code.clamp(0.0, 1.0).powf(1.0 / M2);
+    10000.0 * ((p - C1).max(0.0) / (C2 - C3 * p)).powf(1.0 / M1)
+}
+
+/// ARIB STD-B67 inverse OETF
+fn hlg_to_scene_linear(code: f32) -> f32 {
+    const A: f32 = 0.178_832_77;
+    const B: f32 = 0.284_668_92;
+    const C: f32 = 0.559_910_73;
+
+    let code = code.clamp(0.0, 1.0);
+    if code <= 0.5 {
+        code * code / 3.0
+    } else {
+        (((code - C) / A).exp() + B) / 12.0
+    }
+}
+
+fn bt709_oetf(linear: f32) -> f32 {
+    if linear < 0.018 {
+        4.5 * linear
+    } else {
+        1.099 * linear.powf(0.45) - 0.099
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Inverse of the PQ EOTF, for building test pixels
+    fn nits_to_pq(nits: f32) -> u16 {
+        const M1: f32 = 2610.0 / 16384.0;
+        const M2: f32 = 2523.0 / 4096.0 * 128.0;
+        const C1: f32 = 3424.0 / 4096.0;
+        const C2: f32 = 2413.0 / 4096.0 * 32.0;
+        const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+        let y = (nits / 10000.0).powf(M1);
+        let code = ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2);
+        (code * 65535.0).round() as u16
+    }
+
+    fn pq_filter() -> ToneMapFilter {
+        ToneMapFilter::new(HdrTransfer::Pq, color::Primaries::BT2020)
+    }
+
+    #[test]
+    fn maps_pq_black_and_peak() {
+        let filter = pq_filter();
+
+        assert_eq!(filter.map_pixel([0; 3]), [0; 3]);
+        assert_eq!(filter.map_pixel([65535; 3]), [255; 3]);
+    }
+
+    #[test]
+    fn keeps_reference_white_bright_and_neutral() {
+        let white = nits_to_pq(REFERENCE_WHITE_NITS);
+        let [r, g, b] = pq_filter().map_pixel([white; 3]);
+
+        assert_eq!(r, g);
+        assert_eq!(g, b);
+        assert!((240..255).contains(&r), "reference white mapped to {r}");
+    }
+
+    #[test]
+    fn passes_midtones_through() {
+        // 18% grey, below where highlights start being compressed
+        let grey = nits_to_pq(REFERENCE_WHITE_NITS * 0.18);
+        let expected = (bt709_oetf(0.18) * 255.0).round() as u8;
+
+        for value in pq_filter().map_pixel([grey; 3]) {
+            assert!(value.abs_diff(expected) <= 1, "{value} != {expected}");
+        }
+    }
+
+    #[test]
+    fn rolls_off_highlights_in_order() {
+        let filter = pq_filter();
+        let levels =
+            [203.0, 400.0, 700.0, 1000.0].map(|nits| filter.map_pixel([nits_to_pq(nits); 3])[0]);
+
+        assert!(levels.windows(2).all(|w| w[0] < w[1]), "{levels:?}");
+    }
+
+    #[test]
+    fn converts_bt2020_primaries_to_bt709() {
+        // BT.2020 green is outside of BT.709, so it's clipped to BT.709 green
+        let green = nits_to_pq(REFERENCE_WHITE_NITS * 0.1);
+        let [r, g, b] = pq_filter().map_pixel([0, green, 0]);
+
+        assert_eq!(r, 0);
+        assert!(g > 0);
+        assert_eq!(b, 0);
+    }
+
+    #[test]
+    fn maps_hlg_reference_white() {
+        // BT.2408 puts HLG reference white at 75%
+        let white = (0.75 * 65535.0) as u16;
+        let filter = ToneMapFilter::new(HdrTransfer::Hlg, color::Primaries::BT2020);
+        let [r, g, b] = filter.map_pixel([white; 3]);
+
+        assert_eq!((r, g), (g, b));
+        assert!((235..255).contains(&r), "reference white mapped to {r}");
+    }
+
+    #[test]
+    fn detects_hdr_transfers() {
+        assert_eq!(
+            HdrTransfer::from_characteristic(color::TransferCharacteristic::SMPTE2084),
+            Some(HdrTransfer::Pq)
+        );
+        assert_eq!(
+            HdrTransfer::from_characteristic(color::TransferCharacteristic::ARIB_STD_B67),
+            Some(HdrTransfer::Hlg)
+        );
+        assert_eq!(
+            HdrTransfer::from_characteristic(color::TransferCharacteristic::BT709),
+            None
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use ffmpeg::{format::Pixel, frame};
+use image::{RgbaImage, imageops};
+
+use crate::{MediaError, sources::ImageSource};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+/// Alpha-blends a logo onto a corner of each video frame.
+///
+/// Logos that don't fit inside the frame's margins are scaled down, keeping their aspect ratio.
+/// Only the color channels of the frame are changed, so its own alpha is preserved.
+pub struct WatermarkFilter {
+    logo: RgbaImage,
+    corner: Corner,
+    opacity: f32,
+    margin: u32,
+    /// The logo scaled for the last frame size it was applied to
+    scaled: Option<((u32, u32), RgbaImage)>,
+}
+
+impl WatermarkFilter {
+    /// `opacity` is clamped between 0 and 1, and `margin` is in pixels from the frame's edges.
+    pub fn new(logo: RgbaImage, corner: Corner, opacity: f32, margin: u32) -> Self {
+        Self {
+            logo,
+            corner,
+            opacity: opacity.clamp(0.0, 1.0),
+            margin,
+            scaled: None,
+        }
+    }
+
+    /// Uses an image opened with [`ImageSource::open`] as the logo.
+    pub fn from_source(logo: &ImageSource, corner: Corner, opacity: f32, margin: u32) -> Self {
+        Self::new(logo.frame().clone().into_image(), corner, opacity, margin)
+    }
+
+    /// Supports packed 8-bit RGBA and BGRA frames.
+    pub fn apply(&mut self, frame: &mut frame::Video) -> Result<(), MediaError> {
+        let channels = match frame.format() {
+            Pixel::RGBA | Pixel::RGBZ => [0, 1, 2],
+            Pixel::BGRA | Pixel::BGRZ => [2, 1, 0],
+            format => {
+                return Err(MediaError::Any(
+                    format!("Watermark doesn't support {format:?} frames").into(),
+                ));
+            }
+        };
+
+        let (width, height, stride) = (frame.width(), frame.height(), frame.stride(0));
+        self.blend(frame.data_mut(0), stride, width, height, channels);
+
+        Ok(())
+    }
+
+    /// `channels` are the byte offsets of red, green and blue in each 4-byte pixel.
+    fn blend(
+        &mut self,
+        data: &mut [u8],
+        stride: usize,
+        width: u32,
+        height: u32,
+        channels: [usize; 3],
+    ) {
+        if self.opacity == 0.0 {
+            return;
+        }
+
+        let Some((x, y)) = self.position(width, height) else {
+            return;
+        };
+        let Some((_, logo)) = &self.scaled else {
+            return;
+        };
+
+        for (logo_y, logo_row) in logo.rows().enumerate() {
+            let row_start = (y as usize + logo_y) * stride + x as usize * 4;
+
+            for (logo_x, pixel) in logo_row.enumerate() {
+                let alpha = pixel[3] as f32 / 255.0 * self.opacity;
+                if alpha == 0.0 {
+                    continue;
+                }
+
+                let offset = row_start + logo_x * 4;
+                for (i, channel) in channels.iter().enumerate() {
+                    let value = &mut data[offset + channel];
+                    *value =
+                        (pixel[i] as f32 * alpha + *value as f32 * (1.0 - alpha)).round() as u8;
+                }
+            }
+        }
+    }
+
+    /// Scales the logo for the frame size if needed and returns where its top-left corner goes,
+    /// or `None` if the margins leave no room for it.
+    fn position(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let max_width = width
+            .checked_sub(self.margin.saturating_mul(2))
+            .filter(|v| *v > 0)?;
+        let max_height = height
+            .checked_sub(self.margin.saturating_mul(2))
+            .filter(|v| *v > 0)?;
+
+        if self.scaled.as_ref().map(|(size, _)| *size) != Some((width, height)) {
+            let (logo_width, logo_height) = self.logo.dimensions();
+            let scale = (max_width as f64 / logo_width as f64)
+                .min(max_height as f64 / logo_height as f64)
+                .min(1.0);
+
+            let scaled = if scale < 1.0 {
+                imageops::resize(
+                    &self.logo,
+                    ((logo_width as f64 * scale).round() as u32).clamp(1, max_width),
+                    ((logo_height as f64 * scale).round() as u32).clamp(1, max_height),
+                    imageops::FilterType::Triangle,
+                )
+            } else {
+                self.logo.clone()
+            };
+
+            self.scaled = Some(((width, height), scaled));
+        }
+
+        let (_, logo) = self.scaled.as_ref()?;
+        let (left, top) = (self.margin, self.margin);
+        let right = width - self.margin - logo.width();
+        let bottom = height - self.margin - logo.height();
+
+        Some(match self.corner {
+            Corner::TopLeft => (left, top),
+            Corner::TopRight => (right, top),
+            Corner::BottomLeft => (left, bottom),
+            Corner::BottomRight => (right, bottom),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    const RGBA: [usize; 3] = [0, 1, 2];
+
+    fn pixel(data: &[u8], stride: usize, x: usize, y: usize) -> &[u8] {
+        &data[y * stride + x * 4..y * stride + x * 4 + 4]
+    }
+
+    #[test]
+    fn blends_into_corner() {
+        let mut filter = WatermarkFilter::new(
+            RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 255])),
+            Corner::BottomRight,
+            0.5,
+            1,
+        );
+        let mut data = vec![0; 8 * 6 * 4];
+
+        filter.blend(&mut data, 8 * 4, 8, 6, RGBA);
+
+        assert_eq!(pixel(&data, 32, 5, 3), [128, 128, 128, 0]);
+        assert_eq!(pixel(&data, 32, 6, 4), [128, 128, 128, 0]);
+        assert_eq!(pixel(&data, 32, 7, 5), [0, 0, 0, 0]);
+        assert_eq!(pixel(&data, 32, 4, 3), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn preserves_frame_alpha() {
+        let mut filter = WatermarkFilter::new(
+            RgbaImage::from_pixel(1, 1, Rgba([10, 20, 30, 255])),
+            Corner::TopLeft,
+            1.0,
+            0,
+        );
+        let mut data = vec![0, 0, 0, 77];
+
+        filter.blend(&mut data, 4, 1, 1, [2, 1, 0]);
+
+        assert_eq!(data, [30, 20, 10, 77]);
+    }
+
+    #[test]
+    fn scales_down_large_logos() {
+        let mut filter = WatermarkFilter::new(
+            RgbaImage::from_pixel(40, 20, Rgba([255, 0, 0, 255])),
+            Corner::TopLeft,
+            1.0,
+            2,
+        );
+
+        assert_eq!(filter.position(24, 24), Some((2, 2)));
+        assert_eq!(filter.scaled.as_ref().unwrap().1.dimensions(), (20, 10));
+    }
+
+    #[test]
+    fn takes_logos_from_image_sources() {
+        let logo = ImageSource::from_rgba(1, 1, vec![10, 20, 30, 255]).unwrap();
+        let mut filter = WatermarkFilter::from_source(&logo, Corner::TopLeft, 1.0, 0);
+        let mut data = vec![0, 0, 0, 77];
+
+        filter.blend(&mut data, 4, 1, 1, RGBA);
+
+        assert_eq!(data, [10, 20, 30, 77]);
+    }
+
+    #[test]
+    fn skips_frames_smaller_than_margins() {
+        let mut filter = WatermarkFilter::new(RgbaImage::new(1, 1), Corner::TopLeft, 1.0, 10);
+
+        assert_eq!(filter.position(20, 100), None);
+    }
+}
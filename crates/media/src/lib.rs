@@ -6,6 +6,27 @@
 
 use std::borrow::Cow;
 
+pub mod data;
+mod diagnostics;
+pub mod encoders;
+mod faststart;
+pub mod filters;
+pub mod metrics;
+mod probe;
+mod remux;
+pub mod sources;
+mod trim;
+
+pub use diagnostics::{Diagnostics, FFmpegComponent, diagnostics};
+pub use faststart::faststart;
+pub use metrics::{PipelineMetrics, PipelineMetricsSummary, PipelineStage};
+pub use probe::{AudioStreamInfo, MediaInfo, VideoStreamInfo, probe};
+pub use remux::{has_hdr_video, mux_streams, stream_codecs};
+pub use trim::{TrimMethod, TrimSettings, trim};
+
+/// Sample rate that audio is captured, mixed and rendered at.
+pub const TARGET_SAMPLE_RATE: u32 = 48_000;
+
 use cap_media_info::AudioInfoError;
 use thiserror::Error;
 
@@ -44,6 +65,9 @@ pub enum MediaError {
     #[error("Could not find a suitable {0} stream in this file")]
     MissingMedia(&'static str),
 
+    #[error("Corrupt frame: {0}")]
+    CorruptFrame(ffmpeg::Error),
+
     #[error("AudioInfo: {0}")]
     AudioInfoError(#[from] AudioInfoError),
 }
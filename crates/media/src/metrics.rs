@@ -0,0 +1,228 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{Instrument, Span, info_span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineStage {
+    /// Reading frames from the source files.
+    Decode,
+    /// Compositing decoded frames into output frames.
+    Render,
+    /// Post-processing output frames, like watermarks and burned in subtitles.
+    Filter,
+    /// Handing output frames to the encoder. Encoders that run on their own thread only
+    /// block once their queue is full, so this is mostly time spent waiting on them.
+    Encode,
+    /// Finishing the output file once all frames are encoded.
+    Mux,
+}
+
+impl PipelineStage {
+    pub const ALL: [Self; 5] = [
+        Self::Decode,
+        Self::Render,
+        Self::Filter,
+        Self::Encode,
+        Self::Mux,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Decode => "decode",
+            Self::Render => "render",
+            Self::Filter => "filter",
+            Self::Encode => "encode",
+            Self::Mux => "mux",
+        }
+    }
+
+    fn span(&self) -> Span {
+        info_span!("pipeline_stage", stage = self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct StageTotals {
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+#[derive(Debug, Default)]
+struct MetricsState {
+    stages: [StageTotals; PipelineStage::ALL.len()],
+    frames: u64,
+    bytes_written: u64,
+}
+
+/// Collects how long each stage of a pipeline takes, to find out what's slowing it down.
+///
+/// Clones share the same totals, so a handle can be passed to every thread of the pipeline
+/// and the [`summary`](Self::summary) read from another once it completes. Each timed run
+/// of a stage is also wrapped in a `pipeline_stage` tracing span.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineMetrics(Arc<Mutex<MetricsState>>);
+
+impl PipelineMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `f` as one run of `stage`.
+    pub fn time<T>(&self, stage: PipelineStage, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let output = stage.span().in_scope(f);
+        self.record(stage, start.elapsed());
+        output
+    }
+
+    /// Awaits `future` as one run of `stage`.
+    pub async fn time_async<F: Future>(&self, stage: PipelineStage, future: F) -> F::Output {
+        let start = Instant::now();
+        let output = future.instrument(stage.span()).await;
+        self.record(stage, start.elapsed());
+        output
+    }
+
+    pub fn record(&self, stage: PipelineStage, duration: Duration) {
+        let mut state = self.0.lock().unwrap();
+        let totals = &mut state.stages[stage as usize];
+        totals.count += 1;
+        totals.total += duration;
+        totals.max = totals.max.max(duration);
+    }
+
+    /// Counts frames that made it through the whole pipeline.
+    pub fn add_frames(&self, frames: u64) {
+        self.0.lock().unwrap().frames += frames;
+    }
+
+    pub fn add_bytes_written(&self, bytes: u64) {
+        self.0.lock().unwrap().bytes_written += bytes;
+    }
+
+    pub fn summary(&self) -> PipelineMetricsSummary {
+        let state = self.0.lock().unwrap();
+
+        PipelineMetricsSummary {
+            frames: state.frames,
+            bytes_written: state.bytes_written,
+            stages: PipelineStage::ALL
+                .into_iter()
+                .map(|stage| {
+                    let totals = state.stages[stage as usize];
+                    StageMetrics {
+                        stage,
+                        count: totals.count,
+                        total: totals.total,
+                        average: totals
+                            .total
+                            .checked_div(totals.count.min(u32::MAX as u64) as u32)
+                            .unwrap_or_default(),
+                        max: totals.max,
+                    }
+                })
+                .filter(|stage| stage.count > 0)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StageMetrics {
+    pub stage: PipelineStage,
+    /// Number of times the stage ran, usually once per frame.
+    pub count: u64,
+    pub total: Duration,
+    pub average: Duration,
+    pub max: Duration,
+}
+
+/// What a [`PipelineMetrics`] collected. Stages that never ran are left out.
+///
+/// Displays as a table that can be attached to a performance bug report.
+#[derive(Debug, Clone)]
+pub struct PipelineMetricsSummary {
+    pub frames: u64,
+    pub bytes_written: u64,
+    pub stages: Vec<StageMetrics>,
+}
+
+impl PipelineMetricsSummary {
+    pub fn stage(&self, stage: PipelineStage) -> Option<&StageMetrics> {
+        self.stages.iter().find(|s| s.stage == stage)
+    }
+}
+
+impl fmt::Display for PipelineMetricsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} frames, {} bytes written",
+            self.frames, self.bytes_written
+        )?;
+        write!(
+            f,
+            "{:<8} {:>8} {:>12} {:>12} {:>12}",
+            "stage", "runs", "total", "avg", "max"
+        )?;
+
+        for stage in &self.stages {
+            write!(
+                f,
+                "\n{:<8} {:>8} {:>12} {:>12} {:>12}",
+                stage.stage.name(),
+                stage.count,
+                format!("{:.1?}", stage.total),
+                format!("{:.2?}", stage.average),
+                format!("{:.2?}", stage.max),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregates_stage_timings_across_clones() {
+        let metrics = PipelineMetrics::new();
+        let other = metrics.clone();
+
+        metrics.record(PipelineStage::Decode, Duration::from_millis(2));
+        other.record(PipelineStage::Decode, Duration::from_millis(6));
+        assert_eq!(other.time(PipelineStage::Encode, || 5), 5);
+        metrics.add_frames(2);
+        other.add_bytes_written(1024);
+
+        let summary = metrics.summary();
+        assert_eq!(summary.frames, 2);
+        assert_eq!(summary.bytes_written, 1024);
+
+        let decode = summary.stage(PipelineStage::Decode).unwrap();
+        assert_eq!(decode.count, 2);
+        assert_eq!(decode.total, Duration::from_millis(8));
+        assert_eq!(decode.average, Duration::from_millis(4));
+        assert_eq!(decode.max, Duration::from_millis(6));
+
+        assert_eq!(summary.stage(PipelineStage::Encode).unwrap().count, 1);
+        assert!(summary.stage(PipelineStage::Mux).is_none());
+        assert_eq!(summary.stages.len(), 2);
+    }
+
+    #[test]
+    fn displays_a_row_per_stage() {
+        let metrics = PipelineMetrics::new();
+        metrics.record(PipelineStage::Render, Duration::from_millis(3));
+
+        let table = metrics.summary().to_string();
+        assert_eq!(table.lines().count(), 3);
+        assert!(table.lines().last().unwrap().starts_with("render"));
+    }
+}
@@ -0,0 +1,173 @@
+use std::path::Path;
+
+use ffmpeg::{codec, format, media};
+use serde::Serialize;
+use specta::Type;
+
+use crate::MediaError;
+
+/// What a media file contains, going by its container and stream headers.
+#[derive(Debug, Clone, PartialEq, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaInfo {
+    /// In seconds, if the container records it
+    pub duration: Option<f64>,
+    pub video: Option<VideoStreamInfo>,
+    pub audio: Option<AudioStreamInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoStreamInfo {
+    pub codec: String,
+    pub width: u32,
+    pub height: u32,
+    /// Average frame rate, if the stream records it
+    pub fps: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioStreamInfo {
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+/// Opens a file and describes its best video and audio streams.
+///
+/// Fails if FFmpeg can't read the container, which includes MP4s that haven't finished
+/// being written, and with [`MediaError::MissingMedia`] if it has neither a video nor
+/// an audio stream.
+pub fn probe(path: impl AsRef<Path>) -> Result<MediaInfo, MediaError> {
+    let input = format::input(&path.as_ref())?;
+
+    let video = input
+        .streams()
+        .best(media::Type::Video)
+        .map(|stream| -> Result<_, MediaError> {
+            let decoder = codec::Context::from_parameters(stream.parameters())?
+                .decoder()
+                .video()?;
+            let rate = stream.avg_frame_rate();
+
+            Ok(VideoStreamInfo {
+                codec: decoder.id().name().to_string(),
+                width: decoder.width(),
+                height: decoder.height(),
+                fps: (rate.numerator() > 0 && rate.denominator() > 0).then(|| f64::from(rate)),
+            })
+        })
+        .transpose()?;
+
+    let audio = input
+        .streams()
+        .best(media::Type::Audio)
+        .map(|stream| -> Result<_, MediaError> {
+            let decoder = codec::Context::from_parameters(stream.parameters())?
+                .decoder()
+                .audio()?;
+
+            Ok(AudioStreamInfo {
+                codec: decoder.id().name().to_string(),
+                sample_rate: decoder.rate(),
+                channels: decoder.channels(),
+            })
+        })
+        .transpose()?;
+
+    if video.is_none() && audio.is_none() {
+        return Err(MediaError::MissingMedia("audio or video"));
+    }
+
+    Ok(MediaInfo {
+        duration: (input.duration() > 0)
+            .then(|| input.duration() as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE)),
+        video,
+        audio,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use ffmpeg::{ChannelLayout, Rational, encoder, format::Sample, frame};
+
+    use super::*;
+
+    const RATE: i32 = 8000;
+
+    fn write_wav(path: &Path, seconds: i64) {
+        let mut output = format::output(&path).unwrap();
+        let codec = encoder::find(codec::Id::PCM_S16LE).unwrap();
+        let sample_format = Sample::I16(format::sample::Type::Packed);
+
+        let mut encoder = codec::Context::new_with_codec(codec)
+            .encoder()
+            .audio()
+            .unwrap();
+        encoder.set_rate(RATE);
+        encoder.set_format(sample_format);
+        encoder.set_channel_layout(ChannelLayout::MONO);
+        encoder.set_time_base(Rational::new(1, RATE));
+        let mut encoder = encoder.open().unwrap();
+
+        output.add_stream(codec).unwrap().set_parameters(&encoder);
+        output.write_header().unwrap();
+        let time_base = output.stream(0).unwrap().time_base();
+
+        let mut packet = ffmpeg::Packet::empty();
+        let mut frame = frame::Audio::new(sample_format, RATE as usize, ChannelLayout::MONO);
+        frame.set_rate(RATE as u32);
+        frame.data_mut(0).fill(0);
+
+        for second in 0..=seconds {
+            if second < seconds {
+                frame.set_pts(Some(second * RATE as i64));
+                encoder.send_frame(&frame).unwrap();
+            } else {
+                encoder.send_eof().unwrap();
+            }
+
+            while encoder.receive_packet(&mut packet).is_ok() {
+                packet.set_stream(0);
+                packet.rescale_ts(Rational::new(1, RATE), time_base);
+                packet.write_interleaved(&mut output).unwrap();
+            }
+        }
+
+        output.write_trailer().unwrap();
+    }
+
+    #[test]
+    fn describes_audio_files() {
+        ffmpeg::init().unwrap();
+        let path = std::env::temp_dir().join("cap-media-probe-audio.wav");
+        write_wav(&path, 2);
+
+        let info = probe(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(info.video, None);
+        assert_eq!(
+            info.audio,
+            Some(AudioStreamInfo {
+                codec: "pcm_s16le".to_string(),
+                sample_rate: 8000,
+                channels: 1,
+            })
+        );
+        assert!((info.duration.unwrap() - 2.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn rejects_files_that_arent_media() {
+        ffmpeg::init().unwrap();
+        let path = std::env::temp_dir().join("cap-media-probe-text.mp4");
+        std::fs::write(&path, "not a video").unwrap();
+
+        let result = probe(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+}
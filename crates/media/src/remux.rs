@@ -0,0 +1,192 @@
+use std::path::Path;
+
+use ffmpeg::{Dictionary, Packet, Rational, codec, color, encoder, format, media};
+
+use crate::{MediaError, filters::HdrTransfer};
+
+/// The medium and codec of each audio and video stream in a file, in stream order.
+pub fn stream_codecs(path: impl AsRef<Path>) -> Result<Vec<(media::Type, codec::Id)>, MediaError> {
+    let ictx = format::input(&path.as_ref())?;
+
+    Ok(ictx
+        .streams()
+        .map(|stream| stream.parameters())
+        .filter(|params| matches!(params.medium(), media::Type::Audio | media::Type::Video))
+        .map(|params| (params.medium(), params.id()))
+        .collect())
+}
+
+/// Whether any video stream in a file is HDR, going by its transfer function.
+pub fn has_hdr_video(path: impl AsRef<Path>) -> Result<bool, MediaError> {
+    let ictx = format::input(&path.as_ref())?;
+
+    Ok(ictx
+        .streams()
+        .map(|stream| stream.parameters())
+        .filter(|params| params.medium() == media::Type::Video)
+        .any(|params| {
+            let trc = unsafe { (*params.as_ptr()).color_trc };
+            HdrTransfer::from_characteristic(color::TransferCharacteristic::from(trc)).is_some()
+        }))
+}
+
+/// Copies the audio, video and subtitle streams of every input into a single output
+/// container, without decoding or re-encoding them.
+///
+/// Packets are interleaved by timestamp across inputs, so separately recorded
+/// elementary streams (eg. a screen video and a microphone track) can be combined.
+/// The codecs of the inputs must be supported by the output's container, which can be
+/// checked beforehand with [`stream_codecs`].
+/// If `faststart` is set and the output is an MP4, its `moov` atom is written at the front.
+/// The output keeps the first input's metadata, with any tags in `metadata` added or replaced.
+pub fn mux_streams(
+    inputs: &[impl AsRef<Path>],
+    output: impl AsRef<Path>,
+    faststart: bool,
+    metadata: Dictionary,
+) -> Result<(), MediaError> {
+    let output = output.as_ref();
+
+    if inputs.is_empty() {
+        return Err(MediaError::Any("No inputs to mux".into()));
+    }
+
+    if inputs.iter().any(|input| input.as_ref() == output) {
+        return Err(MediaError::Any(
+            "Mux inputs and output must be different files".into(),
+        ));
+    }
+
+    let mut octx = format::output(&output)?;
+    let mut inputs = inputs
+        .iter()
+        .map(|path| MuxInput::open(path.as_ref(), &mut octx))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut output_metadata = inputs
+        .first()
+        .map(|first| first.ctx.metadata().to_owned())
+        .unwrap_or_else(Dictionary::new);
+    for (key, value) in metadata.iter() {
+        output_metadata.set(key, value);
+    }
+    let encoder = output_metadata.get("encoder").map(str::to_owned);
+    octx.set_metadata(output_metadata);
+
+    let mut options = Dictionary::new();
+    if faststart {
+        options.set("movflags", "+faststart");
+    }
+    octx.write_header_with(options)?;
+
+    // writing the header replaces the encoder tag with ffmpeg's own,
+    // but MP4 tags aren't written out until the trailer
+    if let Some(encoder) = encoder {
+        let mut output_metadata = octx.metadata().to_owned();
+        output_metadata.set("encoder", &encoder);
+        octx.set_metadata(output_metadata);
+    }
+
+    for input in &mut inputs {
+        input.read_packet()?;
+    }
+
+    // always write the earliest pending packet so the inputs stay interleaved
+    while let Some(input) = inputs
+        .iter_mut()
+        .filter(|input| input.pending.is_some())
+        .min_by(|a, b| a.pending_time().total_cmp(&b.pending_time()))
+    {
+        let Some((ist_index, mut packet)) = input.pending.take() else {
+            break;
+        };
+
+        if let Some((ost_index, ist_time_base)) = input.stream_mapping[ist_index] {
+            let ost_time_base = octx
+                .stream(ost_index)
+                .ok_or(MediaError::MissingMedia("output"))?
+                .time_base();
+
+            packet.rescale_ts(ist_time_base, ost_time_base);
+            packet.set_position(-1);
+            packet.set_stream(ost_index);
+            packet.write_interleaved(&mut octx)?;
+        }
+
+        input.read_packet()?;
+    }
+
+    octx.write_trailer()?;
+
+    Ok(())
+}
+
+struct MuxInput {
+    ctx: format::context::Input,
+    /// Output stream index and input time base of each input stream that gets copied
+    stream_mapping: Vec<Option<(usize, Rational)>>,
+    pending: Option<(usize, Packet)>,
+}
+
+impl MuxInput {
+    fn open(path: &Path, octx: &mut format::context::Output) -> Result<Self, MediaError> {
+        let ctx = format::input(&path)?;
+        let mut stream_mapping = vec![None; ctx.nb_streams() as usize];
+
+        for (ist_index, ist) in ctx.streams().enumerate() {
+            let medium = ist.parameters().medium();
+            if medium != media::Type::Audio
+                && medium != media::Type::Video
+                && medium != media::Type::Subtitle
+            {
+                continue;
+            }
+
+            let mut ost = octx.add_stream(encoder::find(codec::Id::None))?;
+            ost.set_parameters(ist.parameters());
+            // let the muxer pick a tag that's valid for the output container
+            unsafe {
+                (*ost.parameters().as_mut_ptr()).codec_tag = 0;
+            }
+
+            stream_mapping[ist_index] = Some((ost.index(), ist.time_base()));
+        }
+
+        Ok(Self {
+            ctx,
+            stream_mapping,
+            pending: None,
+        })
+    }
+
+    fn read_packet(&mut self) -> Result<(), MediaError> {
+        let mut packet = Packet::empty();
+
+        self.pending = match packet.read(&mut self.ctx) {
+            Ok(()) => Some((packet.stream(), packet)),
+            Err(ffmpeg::Error::Eof) => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(())
+    }
+
+    /// Decode time of the pending packet in seconds, used to interleave inputs
+    fn pending_time(&self) -> f64 {
+        let Some((ist_index, packet)) = &self.pending else {
+            return f64::INFINITY;
+        };
+
+        let time_base = self
+            .ctx
+            .stream(*ist_index)
+            .map(|s| f64::from(s.time_base()))
+            .unwrap_or(0.0);
+
+        packet
+            .dts()
+            .or(packet.pts())
+            .map(|ts| ts as f64 * time_base)
+            .unwrap_or(f64::NEG_INFINITY)
+    }
+}
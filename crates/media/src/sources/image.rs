@@ -0,0 +1,154 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use ffmpeg::frame;
+
+use crate::{MediaError, data::RgbaFrame};
+
+/// A still image (PNG/JPEG/WebP) exposed as a video source.
+///
+/// The image is decoded once into a tightly-packed RGBA frame. It can either be
+/// treated as a single frame, or held for `duration` so that it behaves like a clip
+/// on the timeline (eg. for intro slates or overlays).
+#[derive(Clone)]
+pub struct ImageSource {
+    frame: RgbaFrame,
+    duration: Option<Duration>,
+}
+
+impl ImageSource {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, MediaError> {
+        let path = path.as_ref();
+
+        let image = ::image::ImageReader::open(path)?
+            .with_guessed_format()?
+            .decode()
+            .map_err(|e| MediaError::Any(format!("Image/{}/{e}", path.display()).into()))?
+            .into_rgba8();
+
+        Self::from_rgba(image.width(), image.height(), image.into_raw())
+    }
+
+    /// Creates a source from tightly-packed RGBA pixels.
+    /// Fails if either dimension is zero or `data` isn't exactly `width * height` pixels.
+    pub fn from_rgba(width: u32, height: u32, data: Vec<u8>) -> Result<Self, MediaError> {
+        if width == 0 || height == 0 {
+            return Err(MediaError::Any(
+                format!("Image/{width}x{height} has no pixels").into(),
+            ));
+        }
+
+        let expected = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(4));
+        if expected != Some(data.len()) {
+            return Err(MediaError::Any(
+                format!("Image/{} bytes aren't {width}x{height} RGBA", data.len()).into(),
+            ));
+        }
+
+        Ok(Self {
+            frame: RgbaFrame::packed(data, width, height)?,
+            duration: None,
+        })
+    }
+
+    /// Holds the image for `duration` instead of producing a single frame.
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    pub fn width(&self) -> u32 {
+        self.frame.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.frame.height()
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    pub fn frame(&self) -> &RgbaFrame {
+        &self.frame
+    }
+
+    pub fn data(&self) -> &Arc<Vec<u8>> {
+        self.frame.data()
+    }
+
+    /// Returns the image if `time` falls within the held duration.
+    /// Single-frame sources only have a frame at time zero.
+    pub fn get_frame(&self, time: f32) -> Option<Arc<Vec<u8>>> {
+        let in_range = match self.duration {
+            Some(duration) => time >= 0.0 && time < duration.as_secs_f32(),
+            None => time == 0.0,
+        };
+
+        in_range.then(|| self.data().clone())
+    }
+
+    /// Number of frames this source produces at `fps`. Always at least one.
+    pub fn frame_count(&self, fps: u32) -> u32 {
+        self.duration
+            .map(|d| (d.as_secs_f64() * fps as f64).ceil() as u32)
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    /// Converts the image into an RGBA FFmpeg frame with the given pts.
+    pub fn to_ffmpeg(&self, pts: i64) -> frame::Video {
+        let mut frame = frame::Video::from(&self.frame);
+        frame.set_pts(Some(pts));
+        frame
+    }
+
+    /// Produces the frames of this source at `fps`, with pts in a `1/fps` time base.
+    pub fn ffmpeg_frames(&self, fps: u32) -> impl Iterator<Item = frame::Video> + '_ {
+        (0..self.frame_count(fps)).map(|i| self.to_ffmpeg(i as i64))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    #[test]
+    fn rejects_bad_geometry() {
+        assert!(ImageSource::from_rgba(0, 2, vec![]).is_err());
+        assert!(ImageSource::from_rgba(2, 0, vec![]).is_err());
+        assert!(ImageSource::from_rgba(2, 2, vec![0; 12]).is_err());
+        assert!(ImageSource::from_rgba(2, 2, vec![0; 20]).is_err());
+        assert!(ImageSource::from_rgba(u32::MAX, u32::MAX, vec![0; 4]).is_err());
+    }
+
+    #[test]
+    fn converts_to_ffmpeg_frames() {
+        let source = ImageSource::from_rgba(2, 2, [RED, RED, BLUE, BLUE].concat()).unwrap();
+        let video = source.to_ffmpeg(3);
+
+        assert_eq!(video.pts(), Some(3));
+        assert_eq!((video.width(), video.height()), (2, 2));
+        assert_eq!(
+            RgbaFrame::try_from(&video).unwrap(),
+            RgbaFrame::packed([RED, RED, BLUE, BLUE].concat(), 2, 2).unwrap()
+        );
+    }
+
+    #[test]
+    fn holds_frames_for_its_duration() {
+        let source = ImageSource::from_rgba(1, 1, RED.to_vec()).unwrap();
+        assert_eq!(source.frame_count(30), 1);
+        assert!(source.get_frame(0.0).is_some());
+        assert!(source.get_frame(0.5).is_none());
+
+        let held = source.with_duration(Duration::from_millis(500));
+        assert_eq!(held.ffmpeg_frames(30).count(), 15);
+        assert!(held.get_frame(0.4).is_some());
+        assert!(held.get_frame(0.5).is_none());
+    }
+}
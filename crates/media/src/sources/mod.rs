@@ -0,0 +1,3 @@
+mod image;
+
+pub use image::*;
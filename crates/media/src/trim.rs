@@ -0,0 +1,682 @@
+use std::{ops::Range, path::Path};
+
+use ffmpeg::{
+    Dictionary, Packet, Rational, codec, decoder, encoder, format, frame, media, picture,
+};
+use tracing::info;
+
+use crate::MediaError;
+
+#[derive(Debug, Clone)]
+pub struct TrimSettings {
+    /// Constant rate factor for any frames that have to be re-encoded,
+    /// from 0 (lossless) to 51 for x264
+    pub crf: u8,
+    /// Move the `moov` atom to the front of the file so it can be streamed on the web
+    pub faststart: bool,
+}
+
+impl Default for TrimSettings {
+    fn default() -> Self {
+        Self {
+            crf: 18,
+            faststart: false,
+        }
+    }
+}
+
+/// How [`trim`] produced its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimMethod {
+    /// The in-point was on a keyframe, so every packet was copied.
+    StreamCopy,
+    /// Frames from the in-point up to the next keyframe were re-encoded and the rest copied.
+    SmartCut,
+    /// Every frame in the range was re-encoded.
+    Reencode,
+}
+
+/// Cuts `range`, in seconds from the start of the video, out of `input` into a new file.
+///
+/// The cut is frame accurate however it's made: the output starts with the video frame
+/// on screen at `range.start` and ends with the last frame that starts before `range.end`.
+/// Audio is copied as is, starting with its first packet at or after that first frame,
+/// so it can start up to one packet late (about 21ms for AAC) but stays in sync.
+///
+/// As little as possible is re-encoded:
+/// - If the in-point lands on a keyframe, every packet is copied.
+/// - For H.264, which is what recordings are encoded with, the frames from the in-point
+///   up to the next keyframe are re-encoded with libx264 and everything after is copied.
+///   The re-encoded frames carry their own parameter sets, and the source's are repeated
+///   on the first copied keyframe, so both halves decode with the right ones.
+/// - Otherwise, including any video with B-frames as its end can't be cut by dropping
+///   packets, the whole range is re-encoded with the source's codec.
+///
+/// `output` should be an MP4 or MOV file, as copied H.264 packets are kept as they're
+/// stored in MP4.
+pub fn trim(
+    input: impl AsRef<Path>,
+    range: Range<f64>,
+    output: impl AsRef<Path>,
+    settings: &TrimSettings,
+) -> Result<TrimMethod, MediaError> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+
+    if !(range.start >= 0.0 && range.start < range.end) {
+        return Err(MediaError::Any(
+            format!("Invalid trim range {range:?}").into(),
+        ));
+    }
+
+    if input == output {
+        return Err(MediaError::Any(
+            "Trim input and output must be different files".into(),
+        ));
+    }
+
+    let plan = TrimPlan::probe(input, &range)?;
+    info!(
+        "Trimming '{}' to {range:?} with {:?}",
+        input.display(),
+        plan.method
+    );
+    plan.write(input, output, settings)?;
+
+    Ok(plan.method)
+}
+
+/// Frames a trim starts and ends at, as video timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cut {
+    /// The frame on screen at the in-point
+    in_pts: i64,
+    /// Frames starting at or after this are left out
+    end_pts: i64,
+    /// The keyframe decoding has to start from to get the first frame
+    gop_start: i64,
+    next_keyframe: Option<i64>,
+    /// Frames are stored out of presentation order, i.e. there are B-frames
+    reordered: bool,
+}
+
+impl Cut {
+    /// `frames` are the presentation timestamps of the video's packets in decode order,
+    /// and whether each is a keyframe. `None` if `range` ends before the first frame
+    /// or starts after the last one.
+    fn find(frames: &[(i64, bool)], range: Range<i64>) -> Option<Self> {
+        let first = frames.iter().map(|(pts, _)| *pts).min()?;
+        let last = frames.iter().map(|(pts, _)| *pts).max()?;
+        let in_pts = frames
+            .iter()
+            .map(|(pts, _)| *pts)
+            .filter(|pts| *pts <= range.start)
+            .max()
+            .unwrap_or(first);
+
+        if in_pts >= range.end || range.start > last {
+            return None;
+        }
+
+        let keyframes = || frames.iter().filter(|(_, key)| *key).map(|(pts, _)| *pts);
+
+        Some(Self {
+            in_pts,
+            end_pts: range.end,
+            gop_start: keyframes()
+                .filter(|pts| *pts <= in_pts)
+                .max()
+                .unwrap_or(first),
+            next_keyframe: keyframes().filter(|pts| *pts > in_pts).min(),
+            reordered: frames.windows(2).any(|w| w[1].0 <= w[0].0),
+        })
+    }
+
+    fn starts_on_keyframe(&self) -> bool {
+        self.gop_start == self.in_pts
+    }
+}
+
+struct TrimPlan {
+    method: TrimMethod,
+    cut: Cut,
+    video_index: usize,
+    /// The source's SPS and PPS, when smart cutting H.264
+    parameter_sets: Option<ParameterSets>,
+}
+
+impl TrimPlan {
+    fn probe(input: &Path, range: &Range<f64>) -> Result<Self, MediaError> {
+        let mut ictx = format::input(&input)?;
+        let video = ictx
+            .streams()
+            .best(media::Type::Video)
+            .ok_or(MediaError::MissingMedia("video"))?;
+        let video_index = video.index();
+        let time_base = f64::from(video.time_base());
+        let start_time = match video.start_time() {
+            ffmpeg::ffi::AV_NOPTS_VALUE => 0,
+            start_time => start_time,
+        };
+        let params = video.parameters();
+        let to_pts = |time: f64| start_time + (time / time_base).round() as i64;
+        let range = to_pts(range.start)..to_pts(range.end);
+
+        let mut frames = vec![];
+        let mut packet = Packet::empty();
+        loop {
+            match packet.read(&mut ictx) {
+                Ok(()) => {}
+                Err(ffmpeg::Error::Eof) => break,
+                Err(e) => return Err(e.into()),
+            }
+
+            if packet.stream() == video_index
+                && let Some(pts) = packet.pts().or(packet.dts())
+            {
+                frames.push((pts, packet.is_key()));
+            }
+        }
+
+        let cut = Cut::find(&frames, range)
+            .ok_or_else(|| MediaError::Any("Trim range doesn't contain any video frames".into()))?;
+
+        let parameter_sets = (params.id() == codec::Id::H264)
+            .then(|| ParameterSets::from_avcc(extradata(&params)))
+            .flatten();
+
+        let method = if cut.reordered {
+            TrimMethod::Reencode
+        } else if cut.starts_on_keyframe() {
+            TrimMethod::StreamCopy
+        } else if parameter_sets.is_some()
+            && cut.next_keyframe.is_some_and(|pts| pts < cut.end_pts)
+            && encoder::find_by_name("libx264").is_some()
+        {
+            TrimMethod::SmartCut
+        } else {
+            TrimMethod::Reencode
+        };
+
+        Ok(Self {
+            method,
+            cut,
+            video_index,
+            parameter_sets,
+        })
+    }
+
+    fn write(
+        &self,
+        input: &Path,
+        output: &Path,
+        settings: &TrimSettings,
+    ) -> Result<(), MediaError> {
+        let cut = self.cut;
+        let mut ictx = format::input(&input)?;
+        let mut octx = format::output(&output)?;
+        let global_header = octx.format().flags().contains(format::Flags::GLOBAL_HEADER);
+
+        let video = ictx
+            .stream(self.video_index)
+            .ok_or(MediaError::MissingMedia("video"))?;
+        let video_time_base = video.time_base();
+        // audio is kept in sync with the first frame, which is what the output starts with
+        let in_time = cut.in_pts as f64 * f64::from(video_time_base);
+        let end_time = cut.end_pts as f64 * f64::from(video_time_base);
+
+        let mut reencoder = match self.method {
+            TrimMethod::StreamCopy => None,
+            TrimMethod::SmartCut => Some(Reencoder::new(
+                &video,
+                encoder::find_by_name("libx264").ok_or(MediaError::MissingCodec("h264"))?,
+                settings,
+                // the re-encoded frames' parameter sets are written in band instead
+                false,
+                self.parameter_sets
+                    .as_ref()
+                    .map(|sets| sets.nal_length_size),
+                cut.in_pts..cut.next_keyframe.unwrap_or(cut.end_pts),
+            )?),
+            TrimMethod::Reencode => Some(Reencoder::new(
+                &video,
+                encoder::find(video.parameters().id())
+                    .ok_or(MediaError::MissingCodec("trimming"))?,
+                settings,
+                global_header,
+                None,
+                cut.in_pts..cut.end_pts,
+            )?),
+        };
+
+        // output stream index and input time base of each input stream that gets written
+        let mut stream_mapping = vec![None; ictx.nb_streams() as usize];
+        for (ist_index, ist) in ictx.streams().enumerate() {
+            let medium = ist.parameters().medium();
+            if ist_index != self.video_index && medium != media::Type::Audio {
+                continue;
+            }
+
+            let mut ost = octx.add_stream(encoder::find(codec::Id::None))?;
+            ost.set_time_base(ist.time_base());
+            match &reencoder {
+                Some(reencoder)
+                    if self.method == TrimMethod::Reencode && ist_index == self.video_index =>
+                {
+                    ost.set_parameters(&reencoder.encoder);
+                }
+                _ => ost.set_parameters(ist.parameters()),
+            }
+            // let the muxer pick a tag that's valid for the output container
+            unsafe {
+                (*ost.parameters().as_mut_ptr()).codec_tag = 0;
+            }
+
+            stream_mapping[ist_index] = Some((ost.index(), ist.time_base()));
+        }
+
+        let Some((video_ost, _)) = stream_mapping[self.video_index] else {
+            return Err(MediaError::MissingMedia("video"));
+        };
+        if let Some(reencoder) = &mut reencoder {
+            reencoder.ost_index = video_ost;
+        }
+
+        let mut options = Dictionary::new();
+        if settings.faststart {
+            options.set("movflags", "+faststart");
+        }
+        octx.write_header_with(options)?;
+
+        // decoding starts from the keyframe before the in-point
+        let mut reached_gop = false;
+        let mut packet = Packet::empty();
+        loop {
+            match packet.read(&mut ictx) {
+                Ok(()) => {}
+                Err(ffmpeg::Error::Eof) => break,
+                Err(e) => return Err(e.into()),
+            }
+
+            let Some((ost_index, time_base)) = stream_mapping[packet.stream()] else {
+                continue;
+            };
+            let Some(pts) = packet.pts().or(packet.dts()) else {
+                continue;
+            };
+
+            if packet.stream() != self.video_index {
+                let time = pts as f64 * f64::from(time_base);
+                if (in_time..end_time).contains(&time) {
+                    let offset = (in_time / f64::from(time_base)).round() as i64;
+                    shift(&mut packet, offset);
+                    write_packet(&mut octx, packet.clone(), ost_index, time_base)?;
+                }
+                continue;
+            }
+
+            reached_gop |= pts == cut.gop_start;
+            if !reached_gop {
+                continue;
+            }
+
+            match self.method {
+                TrimMethod::Reencode => {
+                    // frames before the end can't reference anything decoded after it
+                    if packet.dts().unwrap_or(pts) < cut.end_pts
+                        && let Some(reencoder) = &mut reencoder
+                    {
+                        reencoder.send_packet(&packet, &mut octx)?;
+                    }
+                }
+                TrimMethod::SmartCut if cut.next_keyframe.is_some_and(|key| pts < key) => {
+                    if let Some(reencoder) = &mut reencoder {
+                        reencoder.send_packet(&packet, &mut octx)?;
+                    }
+                }
+                _ if (cut.in_pts..cut.end_pts).contains(&pts) => {
+                    // the copied frames need the source's parameter sets back
+                    // after the re-encoded ones replaced them
+                    if let Some(mut reencoder) = reencoder.take() {
+                        reencoder.finish(&mut octx)?;
+
+                        if let Some(parameter_sets) = &self.parameter_sets {
+                            packet = parameter_sets.prepend_to(&packet);
+                        }
+                    }
+
+                    shift(&mut packet, cut.in_pts);
+                    write_packet(&mut octx, packet.clone(), ost_index, time_base)?;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(mut reencoder) = reencoder.take() {
+            reencoder.finish(&mut octx)?;
+        }
+
+        octx.write_trailer()?;
+
+        Ok(())
+    }
+}
+
+fn shift(packet: &mut Packet, offset: i64) {
+    packet.set_pts(packet.pts().map(|pts| pts - offset));
+    packet.set_dts(packet.dts().map(|dts| dts - offset));
+}
+
+fn write_packet(
+    octx: &mut format::context::Output,
+    mut packet: Packet,
+    ost_index: usize,
+    time_base: Rational,
+) -> Result<(), MediaError> {
+    let ost_time_base = octx
+        .stream(ost_index)
+        .ok_or(MediaError::MissingMedia("output"))?
+        .time_base();
+
+    packet.rescale_ts(time_base, ost_time_base);
+    packet.set_position(-1);
+    packet.set_stream(ost_index);
+    packet.write_interleaved(octx)?;
+
+    Ok(())
+}
+
+/// Decodes video packets and re-encodes the frames within a range,
+/// with timestamps relative to the start of the range.
+struct Reencoder {
+    decoder: decoder::Video,
+    encoder: encoder::Video,
+    range: Range<i64>,
+    time_base: Rational,
+    ost_index: usize,
+    /// Whether the encoder writes annex B packets that have to be converted to match the
+    /// copied packets, whose NAL units are prefixed with their length in this many bytes
+    nal_length_size: Option<usize>,
+    frame: frame::Video,
+    packet: Packet,
+}
+
+impl Reencoder {
+    fn new(
+        stream: &format::stream::Stream,
+        codec: codec::Codec,
+        settings: &TrimSettings,
+        global_header: bool,
+        nal_length_size: Option<usize>,
+        range: Range<i64>,
+    ) -> Result<Self, MediaError> {
+        let decoder = codec::context::Context::from_parameters(stream.parameters())?
+            .decoder()
+            .video()?;
+        let time_base = stream.time_base();
+
+        let mut encoder = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .video()?;
+        encoder.set_width(decoder.width());
+        encoder.set_height(decoder.height());
+        encoder.set_format(decoder.format());
+        encoder.set_colorspace(decoder.color_space());
+        encoder.set_color_range(decoder.color_range());
+        encoder.set_time_base(time_base);
+        encoder.set_frame_rate(Some(stream.avg_frame_rate()));
+        // copied packets can only follow re-encoded ones if they're in presentation order
+        encoder.set_max_b_frames(0);
+        if global_header {
+            encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let mut options = Dictionary::new();
+        options.set("crf", &settings.crf.to_string());
+        let encoder = encoder.open_with(options)?;
+
+        Ok(Self {
+            decoder,
+            encoder,
+            range,
+            time_base,
+            ost_index: 0,
+            nal_length_size,
+            frame: frame::Video::empty(),
+            packet: Packet::empty(),
+        })
+    }
+
+    fn send_packet(
+        &mut self,
+        packet: &Packet,
+        octx: &mut format::context::Output,
+    ) -> Result<(), MediaError> {
+        self.decoder.send_packet(packet)?;
+        self.receive_frames(octx)
+    }
+
+    fn finish(&mut self, octx: &mut format::context::Output) -> Result<(), MediaError> {
+        self.decoder.send_eof()?;
+        self.receive_frames(octx)?;
+        self.encoder.send_eof()?;
+        self.receive_packets(octx)
+    }
+
+    fn receive_frames(&mut self, octx: &mut format::context::Output) -> Result<(), MediaError> {
+        while self.decoder.receive_frame(&mut self.frame).is_ok() {
+            let Some(pts) = self.frame.timestamp() else {
+                continue;
+            };
+            if !self.range.contains(&pts) {
+                continue;
+            }
+
+            self.frame.set_pts(Some(pts - self.range.start));
+            self.frame.set_kind(picture::Type::None);
+            self.encoder.send_frame(&self.frame)?;
+            self.receive_packets(octx)?;
+        }
+
+        Ok(())
+    }
+
+    fn receive_packets(&mut self, octx: &mut format::context::Output) -> Result<(), MediaError> {
+        while self.encoder.receive_packet(&mut self.packet).is_ok() {
+            let packet = match self.nal_length_size {
+                Some(size) => {
+                    let data =
+                        annex_b_to_length_prefixed(self.packet.data().unwrap_or_default(), size);
+                    let mut packet = Packet::copy(&data);
+                    packet.set_pts(self.packet.pts());
+                    packet.set_dts(self.packet.dts());
+                    packet.set_duration(self.packet.duration());
+                    packet.set_flags(self.packet.flags());
+                    packet
+                }
+                None => self.packet.clone(),
+            };
+
+            write_packet(octx, packet, self.ost_index, self.time_base)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn extradata(params: &codec::Parameters) -> &[u8] {
+    unsafe {
+        let params = params.as_ptr();
+        if (*params).extradata.is_null() {
+            return &[];
+        }
+
+        std::slice::from_raw_parts((*params).extradata, (*params).extradata_size as usize)
+    }
+}
+
+/// An H.264 stream's SPS and PPS NAL units, which MP4 stores out of band.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ParameterSets {
+    nal_length_size: usize,
+    nal_units: Vec<Vec<u8>>,
+}
+
+impl ParameterSets {
+    /// Parses an `avcC` box, which is what H.264 extradata is in MP4.
+    /// `None` for annex B extradata or if it's malformed.
+    fn from_avcc(data: &[u8]) -> Option<Self> {
+        if data.len() < 6 || data[0] != 1 {
+            return None;
+        }
+
+        let nal_length_size = (data[4] & 0b11) as usize + 1;
+        let mut nal_units = vec![];
+        let mut rest = &data[5..];
+
+        // SPS count is in the low 5 bits, followed by the PPS count as a whole byte
+        for mask in [0b1_1111, 0xff] {
+            let (&count, tail) = rest.split_first()?;
+            rest = tail;
+
+            for _ in 0..(count & mask) {
+                let length = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]) as usize;
+                nal_units.push(rest.get(2..2 + length)?.to_vec());
+                rest = &rest[2 + length..];
+            }
+        }
+
+        Some(Self {
+            nal_length_size,
+            nal_units,
+        })
+    }
+
+    fn prepend_to(&self, packet: &Packet) -> Packet {
+        let mut data = vec![];
+        for nal in &self.nal_units {
+            push_length_prefixed(&mut data, nal, self.nal_length_size);
+        }
+        data.extend_from_slice(packet.data().unwrap_or_default());
+
+        let mut output = Packet::copy(&data);
+        output.set_pts(packet.pts());
+        output.set_dts(packet.dts());
+        output.set_duration(packet.duration());
+        output.set_flags(packet.flags());
+        output
+    }
+}
+
+fn push_length_prefixed(data: &mut Vec<u8>, nal: &[u8], nal_length_size: usize) {
+    data.extend_from_slice(&(nal.len() as u32).to_be_bytes()[4 - nal_length_size..]);
+    data.extend_from_slice(nal);
+}
+
+/// Converts NAL units separated by annex B start codes to ones prefixed with their length.
+fn annex_b_to_length_prefixed(data: &[u8], nal_length_size: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut nal_start = None;
+    let mut i = 0;
+
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            if let Some(start) = nal_start {
+                // a 4 byte start code's leading zero isn't part of the previous NAL unit
+                let end = if data[i - 1] == 0 { i - 1 } else { i };
+                push_length_prefixed(&mut output, &data[start..end.max(start)], nal_length_size);
+            }
+            i += 3;
+            nal_start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+
+    if let Some(start) = nal_start {
+        push_length_prefixed(&mut output, &data[start..], nal_length_size);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 30fps in a 1/15360 time base, with a keyframe every 10 frames
+    fn frames(count: i64) -> Vec<(i64, bool)> {
+        (0..count).map(|i| (i * 512, i % 10 == 0)).collect()
+    }
+
+    #[test]
+    fn starts_on_the_keyframe_at_the_in_point() {
+        let cut = Cut::find(&frames(60), 5120..20480).unwrap();
+
+        assert_eq!(cut.in_pts, 5120);
+        assert!(cut.starts_on_keyframe());
+        assert!(!cut.reordered);
+    }
+
+    #[test]
+    fn cuts_mid_gop_at_the_frame_on_screen() {
+        // between frames 13 and 14
+        let cut = Cut::find(&frames(60), 7000..20480).unwrap();
+
+        assert_eq!(cut.in_pts, 13 * 512);
+        assert_eq!(cut.gop_start, 10 * 512);
+        assert_eq!(cut.next_keyframe, Some(20 * 512));
+        assert!(!cut.starts_on_keyframe());
+    }
+
+    #[test]
+    fn rejects_ranges_past_the_last_frame() {
+        assert_eq!(Cut::find(&frames(10), 10_000..20_000), None);
+        assert_eq!(Cut::find(&[], 0..20_000), None);
+    }
+
+    #[test]
+    fn detects_b_frames() {
+        let frames = [(0, true), (1024, false), (512, false), (2048, false)];
+
+        assert!(Cut::find(&frames, 0..4096).unwrap().reordered);
+    }
+
+    #[test]
+    fn parses_avcc_parameter_sets() {
+        let avcc = [
+            1, 0x64, 0, 0x1f, 0xff, // version, profile, compatibility, level, length size
+            0xe1, 0, 3, 0x67, 1, 2, // one SPS
+            1, 0, 2, 0x68, 3, // one PPS
+        ];
+
+        assert_eq!(
+            ParameterSets::from_avcc(&avcc),
+            Some(ParameterSets {
+                nal_length_size: 4,
+                nal_units: vec![vec![0x67, 1, 2], vec![0x68, 3]],
+            })
+        );
+        assert_eq!(ParameterSets::from_avcc(&avcc[..10]), None);
+        assert_eq!(ParameterSets::from_avcc(&[0, 0, 0, 1, 0x67, 1]), None);
+    }
+
+    #[test]
+    fn converts_annex_b_to_length_prefixed() {
+        let annex_b = [
+            0, 0, 0, 1, 0x67, 1, 2, 0, 0, 1, 0x68, 3, 0, 0, 0, 1, 0x65, 4, 5,
+        ];
+
+        assert_eq!(
+            annex_b_to_length_prefixed(&annex_b, 4),
+            [
+                0, 0, 0, 3, 0x67, 1, 2, //
+                0, 0, 0, 2, 0x68, 3, //
+                0, 0, 0, 3, 0x65, 4, 5,
+            ]
+        );
+        assert_eq!(
+            annex_b_to_length_prefixed(&annex_b[..12], 2),
+            [0, 3, 0x67, 1, 2, 0, 2, 0x68, 3]
+        );
+    }
+}
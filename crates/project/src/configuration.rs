@@ -1,4 +1,6 @@
 use std::{
+    error::Error,
+    fmt,
     ops::{Add, Div, Mul, Sub, SubAssign},
     path::Path,
 };
@@ -509,13 +511,20 @@ pub struct TimelineConfiguration {
 
 impl TimelineConfiguration {
     pub fn get_segment_time(&self, frame_time: f64) -> Option<(f64, u32)> {
+        let (start, segment) = self.get_segment(frame_time)?;
+
+        segment
+            .interpolate_time(frame_time - start)
+            .map(|t| (t, segment.recording_segment))
+    }
+
+    /// The segment playing at `frame_time`, along with the time it starts at on the timeline.
+    pub fn get_segment(&self, frame_time: f64) -> Option<(f64, &TimelineSegment)> {
         let mut accum_duration = 0.0;
 
         for segment in self.segments.iter() {
             if frame_time < accum_duration + segment.duration() {
-                return segment
-                    .interpolate_time(frame_time - accum_duration)
-                    .map(|t| (t, segment.recording_segment));
+                return Some((accum_duration, segment));
             }
 
             accum_duration += segment.duration();
@@ -524,11 +533,129 @@ impl TimelineConfiguration {
         None
     }
 
+    /// Splits segments where `ramps` start and end, and speeds up the parts they cover.
+    fn apply_speed_ramps(&mut self, ramps: &[SpeedRamp]) {
+        let mut segments = vec![];
+        let mut segment_start = 0.0;
+
+        for segment in &self.segments {
+            let segment_end = segment_start + segment.duration();
+
+            // every point the speed can change within the segment
+            let mut cuts = vec![segment_start];
+            cuts.extend(
+                ramps
+                    .iter()
+                    .flat_map(|r| [r.start, r.end])
+                    .filter(|t| *t > segment_start && *t < segment_end),
+            );
+            cuts.push(segment_end);
+
+            for piece in cuts.windows(2) {
+                let speed = ramps
+                    .iter()
+                    .find(|r| r.start <= piece[0] && piece[1] <= r.end)
+                    .map_or(1.0, |r| r.speed);
+
+                segments.push(TimelineSegment {
+                    recording_segment: segment.recording_segment,
+                    timescale: segment.timescale * speed,
+                    start: segment.start + (piece[0] - segment_start) * segment.timescale,
+                    end: segment.start + (piece[1] - segment_start) * segment.timescale,
+                });
+            }
+
+            segment_start = segment_end;
+        }
+
+        self.segments = segments;
+
+        for zoom in &mut self.zoom_segments {
+            zoom.start = SpeedRamp::ramped_time(ramps, zoom.start);
+            zoom.end = SpeedRamp::ramped_time(ramps, zoom.end);
+        }
+        for scene in &mut self.scene_segments {
+            scene.start = SpeedRamp::ramped_time(ramps, scene.start);
+            scene.end = SpeedRamp::ramped_time(ramps, scene.end);
+        }
+    }
+
     pub fn duration(&self) -> f64 {
         self.segments.iter().map(|s| s.duration()).sum()
     }
 }
 
+/// Plays part of the timeline at a different speed, e.g. 4x while typing.
+/// Times are in seconds on the timeline before any ramps are applied.
+#[derive(Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SpeedRamp {
+    pub start: f64,
+    pub end: f64,
+    /// Above 1 speeds the range up, below 1 slows it down
+    pub speed: f64,
+}
+
+impl SpeedRamp {
+    /// Checks that every ramp covers some time at a positive speed,
+    /// and that they're sorted and don't overlap.
+    pub fn validate(ramps: &[Self]) -> Result<(), SpeedRampError> {
+        let mut previous_end = 0.0;
+
+        for (index, ramp) in ramps.iter().enumerate() {
+            if !(ramp.start >= 0.0 && ramp.start < ramp.end && ramp.end.is_finite()) {
+                return Err(SpeedRampError::EmptyRange(index));
+            }
+            if !(ramp.speed > 0.0 && ramp.speed.is_finite()) {
+                return Err(SpeedRampError::InvalidSpeed(index));
+            }
+            if ramp.start < previous_end {
+                return Err(SpeedRampError::Overlapping(index));
+            }
+
+            previous_end = ramp.end;
+        }
+
+        Ok(())
+    }
+
+    /// Where `time` on the original timeline ends up once `ramps` are applied.
+    pub fn ramped_time(ramps: &[Self], time: f64) -> f64 {
+        ramps.iter().fold(time, |ramped, ramp| {
+            let covered = time.min(ramp.end) - ramp.start;
+            if covered > 0.0 {
+                ramped - covered + covered / ramp.speed
+            } else {
+                ramped
+            }
+        })
+    }
+}
+
+/// Why [`SpeedRamp::validate`] failed, with the index of the offending ramp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeedRampError {
+    EmptyRange(usize),
+    InvalidSpeed(usize),
+    /// Starts before the previous ramp ends
+    Overlapping(usize),
+}
+
+impl fmt::Display for SpeedRampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyRange(i) => write!(f, "Speed ramp {i} doesn't cover any time"),
+            Self::InvalidSpeed(i) => write!(f, "Speed ramp {i} must have a positive speed"),
+            Self::Overlapping(i) => write!(
+                f,
+                "Speed ramp {i} starts before the previous one ends, ramps must be sorted and not overlap"
+            ),
+        }
+    }
+}
+
+impl Error for SpeedRampError {}
+
 pub const WALLPAPERS_PATH: &str = "assets/backgrounds/macOS";
 
 #[derive(Type, Serialize, Deserialize, Clone, Debug, Default)]
@@ -620,6 +747,45 @@ impl ProjectConfiguration {
         )
     }
 
+    /// A copy of the project with `ramps` applied to its timeline, which changes its length.
+    /// `duration` is the length of the recording, used as the timeline if the project
+    /// doesn't have one. Zooms, scenes and captions are moved to stay in sync.
+    pub fn with_speed_ramps(
+        &self,
+        ramps: &[SpeedRamp],
+        duration: f64,
+    ) -> Result<Self, SpeedRampError> {
+        SpeedRamp::validate(ramps)?;
+
+        let mut config = self.clone();
+        if ramps.is_empty() {
+            return Ok(config);
+        }
+
+        config
+            .timeline
+            .get_or_insert_with(|| TimelineConfiguration {
+                segments: vec![TimelineSegment {
+                    recording_segment: 0,
+                    timescale: 1.0,
+                    start: 0.0,
+                    end: duration,
+                }],
+                zoom_segments: vec![],
+                scene_segments: vec![],
+            })
+            .apply_speed_ramps(ramps);
+
+        if let Some(captions) = &mut config.captions {
+            for caption in &mut captions.segments {
+                caption.start = SpeedRamp::ramped_time(ramps, caption.start as f64) as f32;
+                caption.end = SpeedRamp::ramped_time(ramps, caption.end as f64) as f32;
+            }
+        }
+
+        Ok(config)
+    }
+
     pub fn get_segment_time(&self, frame_time: f64) -> Option<(f64, u32)> {
         self.timeline
             .as_ref()
@@ -635,3 +801,100 @@ pub const FAST_SMOOTHING_SAMPLES: usize = 10;
 pub const SLOW_VELOCITY_THRESHOLD: f64 = 0.003;
 pub const REGULAR_VELOCITY_THRESHOLD: f64 = 0.008;
 pub const FAST_VELOCITY_THRESHOLD: f64 = 0.015;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn segment(recording_segment: u32, start: f64, end: f64) -> TimelineSegment {
+        TimelineSegment {
+            recording_segment,
+            timescale: 1.0,
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn plays_segments_in_order_across_recordings() {
+        let timeline = TimelineConfiguration {
+            segments: vec![
+                segment(1, 2.0, 4.0),
+                segment(0, 10.0, 11.0),
+                segment(1, 0.0, 1.0),
+            ],
+            zoom_segments: vec![],
+            scene_segments: vec![],
+        };
+
+        assert_eq!(timeline.duration(), 4.0);
+        assert_eq!(timeline.get_segment_time(0.0), Some((2.0, 1)));
+        assert_eq!(timeline.get_segment_time(1.5), Some((3.5, 1)));
+        assert_eq!(timeline.get_segment_time(2.0), Some((10.0, 0)));
+        assert_eq!(timeline.get_segment_time(3.25), Some((0.25, 1)));
+        assert_eq!(timeline.get_segment_time(4.0), None);
+    }
+
+    fn ramp(start: f64, end: f64, speed: f64) -> SpeedRamp {
+        SpeedRamp { start, end, speed }
+    }
+
+    #[test]
+    fn speed_ramps_change_duration() {
+        let timeline = TimelineConfiguration {
+            segments: vec![segment(0, 0.0, 10.0), segment(1, 5.0, 9.0)],
+            zoom_segments: vec![],
+            scene_segments: vec![],
+        };
+        let config = ProjectConfiguration {
+            timeline: Some(timeline),
+            ..Default::default()
+        };
+
+        // 4x across the cut between the segments, and half speed near the end
+        let ramps = [ramp(8.0, 12.0, 4.0), ramp(13.0, 14.0, 0.5)];
+        let ramped = config.with_speed_ramps(&ramps, 0.0).unwrap();
+        let timeline = ramped.timeline.unwrap();
+
+        assert_eq!(timeline.duration(), 8.0 + 1.0 + 1.0 + 2.0);
+        assert_eq!(timeline.get_segment_time(8.25), Some((9.0, 0)));
+        assert_eq!(timeline.get_segment_time(8.75), Some((6.0, 1)));
+        assert_eq!(timeline.get_segment_time(10.5), Some((8.25, 1)));
+        assert_eq!(timeline.get_segment_time(11.5), Some((8.75, 1)));
+        assert_eq!(SpeedRamp::ramped_time(&ramps, 14.0), 12.0);
+    }
+
+    #[test]
+    fn speed_ramps_without_timeline() {
+        let config = ProjectConfiguration::default()
+            .with_speed_ramps(&[ramp(2.0, 6.0, 2.0)], 10.0)
+            .unwrap();
+
+        assert_eq!(config.timeline.unwrap().duration(), 8.0);
+    }
+
+    #[test]
+    fn validates_speed_ramps() {
+        assert_eq!(SpeedRamp::validate(&[]), Ok(()));
+        assert_eq!(
+            SpeedRamp::validate(&[ramp(0.0, 1.0, 2.0), ramp(1.0, 2.0, 0.5)]),
+            Ok(())
+        );
+        assert_eq!(
+            SpeedRamp::validate(&[ramp(1.0, 1.0, 2.0)]),
+            Err(SpeedRampError::EmptyRange(0))
+        );
+        assert_eq!(
+            SpeedRamp::validate(&[ramp(0.0, 1.0, 0.0)]),
+            Err(SpeedRampError::InvalidSpeed(0))
+        );
+        assert_eq!(
+            SpeedRamp::validate(&[ramp(0.0, 2.0, 2.0), ramp(1.0, 3.0, 2.0)]),
+            Err(SpeedRampError::Overlapping(1))
+        );
+        assert_eq!(
+            SpeedRamp::validate(&[ramp(4.0, 5.0, 2.0), ramp(1.0, 3.0, 2.0)]),
+            Err(SpeedRampError::Overlapping(1))
+        );
+    }
+}
@@ -5,6 +5,7 @@ use specta::Type;
 use std::{
     collections::HashMap,
     error::Error,
+    fmt,
     path::{Path, PathBuf},
 };
 use tracing::{debug, info, warn};
@@ -99,6 +100,39 @@ impl RecordingMeta {
         Ok(meta)
     }
 
+    /// Like [`RecordingMeta::load_for_project`], but also checks that every media file
+    /// the recording refers to exists, so a broken project fails up front with an error
+    /// that says what's wrong instead of partway through decoding it.
+    pub fn load_validated(project_path: &Path) -> Result<Self, RecordingMetaError> {
+        let meta_path = project_path.join("recording-meta.json");
+        let json = std::fs::read_to_string(&meta_path)
+            .map_err(|e| RecordingMetaError::Read(meta_path.clone(), e))?;
+        let mut meta: Self =
+            serde_json::from_str(&json).map_err(|e| RecordingMetaError::Parse(meta_path, e))?;
+        meta.project_path = project_path.to_path_buf();
+
+        meta.validate()?;
+
+        Ok(meta)
+    }
+
+    /// Checks that the video and audio files of a studio recording exist.
+    /// Instant recordings are a single file that's checked when it's opened.
+    pub fn validate(&self) -> Result<(), RecordingMetaError> {
+        let Some(studio_meta) = self.studio_meta() else {
+            return Ok(());
+        };
+
+        for (track, path) in studio_meta.media_paths() {
+            let path = self.path(path);
+            if !path.is_file() {
+                return Err(RecordingMetaError::MissingMedia { track, path });
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn save_for_project(&self) -> Result<(), Either<serde_json::Error, std::io::Error>> {
         let meta_path = &self.project_path.join("recording-meta.json");
         let meta = serde_json::to_string_pretty(&self).map_err(Either::Left)?;
@@ -146,6 +180,43 @@ impl RecordingMeta {
     }
 }
 
+/// Why [`RecordingMeta::load_validated`] failed.
+#[derive(Debug)]
+pub enum RecordingMetaError {
+    Read(PathBuf, std::io::Error),
+    /// The file isn't valid JSON or doesn't match any version of the meta
+    Parse(PathBuf, serde_json::Error),
+    /// A media file the meta refers to doesn't exist
+    MissingMedia {
+        track: &'static str,
+        path: PathBuf,
+    },
+}
+
+impl fmt::Display for RecordingMetaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(path, e) => write!(f, "Failed to read '{}': {e}", path.display()),
+            Self::Parse(path, e) => {
+                write!(f, "'{}' isn't a valid recording meta: {e}", path.display())
+            }
+            Self::MissingMedia { track, path } => {
+                write!(f, "The {track} file '{}' is missing", path.display())
+            }
+        }
+    }
+}
+
+impl Error for RecordingMetaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Read(_, e) => Some(e),
+            Self::Parse(_, e) => Some(e),
+            Self::MissingMedia { .. } => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Type)]
 #[serde(untagged, rename_all = "camelCase")]
 pub enum StudioRecordingMeta {
@@ -174,6 +245,34 @@ impl StudioRecordingMeta {
         }
     }
 
+    /// Every video and audio file the recording is made of, along with which track it is.
+    pub fn media_paths(&self) -> Vec<(&'static str, &RelativePathBuf)> {
+        let mut paths = vec![];
+
+        match self {
+            StudioRecordingMeta::SingleSegment { segment } => {
+                paths.push(("display", &segment.display.path));
+                paths.extend(segment.camera.as_ref().map(|c| ("camera", &c.path)));
+                paths.extend(segment.audio.as_ref().map(|a| ("audio", &a.path)));
+            }
+            StudioRecordingMeta::MultipleSegments { inner, .. } => {
+                for segment in &inner.segments {
+                    paths.push(("display", &segment.display.path));
+                    paths.extend(segment.camera.as_ref().map(|c| ("camera", &c.path)));
+                    paths.extend(segment.mic.as_ref().map(|a| ("microphone", &a.path)));
+                    paths.extend(
+                        segment
+                            .system_audio
+                            .as_ref()
+                            .map(|a| ("system audio", &a.path)),
+                    );
+                }
+            }
+        }
+
+        paths
+    }
+
     pub fn min_fps(&self) -> u32 {
         match self {
             StudioRecordingMeta::SingleSegment { segment } => segment.display.fps,
@@ -319,11 +418,21 @@ impl MultipleSegment {
 
         Some(value)
     }
+
+    /// Seconds between a track's first frame and the segment's shared start,
+    /// which is the point at which every track has started recording.
+    /// Tracks are read this far in so that they line up on a common timeline.
+    pub fn start_offset(&self, start_time: Option<f64>) -> f64 {
+        self.latest_start_time()
+            .zip(start_time)
+            .map(|(latest_start_time, start_time)| latest_start_time - start_time)
+            .unwrap_or(0.0)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::RecordingMeta;
+    use super::*;
 
     fn test_meta_deserialize(s: &str) {
         let _: RecordingMeta = serde_json::from_str(s).unwrap();
@@ -430,4 +539,83 @@ mod test {
 		        }"#,
         );
     }
+
+    #[test]
+    fn validates_media_files_exist() {
+        let project_path =
+            std::env::temp_dir().join(format!("cap-project-meta-{}", std::process::id()));
+        let segment_path = project_path.join("content/segments/segment-0");
+        std::fs::create_dir_all(&segment_path).unwrap();
+        std::fs::write(segment_path.join("display.mp4"), []).unwrap();
+        std::fs::write(
+            project_path.join("recording-meta.json"),
+            r#"{
+              "pretty_name": "Cap",
+              "segments": [
+                {
+                  "display": { "path": "content/segments/segment-0/display.mp4" },
+                  "mic": { "path": "content/segments/segment-0/audio-input.ogg" }
+                }
+              ]
+            }"#,
+        )
+        .unwrap();
+
+        let result = RecordingMeta::load_validated(&project_path);
+        assert!(matches!(
+            result,
+            Err(RecordingMetaError::MissingMedia { track: "microphone", ref path })
+                if *path == segment_path.join("audio-input.ogg")
+        ));
+
+        std::fs::write(segment_path.join("audio-input.ogg"), []).unwrap();
+        let meta = RecordingMeta::load_validated(&project_path).unwrap();
+        assert_eq!(meta.project_path, project_path);
+
+        std::fs::write(project_path.join("recording-meta.json"), "{}").unwrap();
+        assert!(matches!(
+            RecordingMeta::load_validated(&project_path),
+            Err(RecordingMetaError::Parse(..))
+        ));
+
+        std::fs::remove_dir_all(&project_path).unwrap();
+    }
+
+    #[test]
+    fn start_offsets_align_tracks() {
+        let segment: MultipleSegment = serde_json::from_str(
+            r#"{
+              "display": { "path": "display.mp4", "start_time": 100.0 },
+              "camera": { "path": "camera.mp4", "start_time": 100.2 },
+              "mic": { "path": "audio-input.ogg", "start_time": 99.9 }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(segment.latest_start_time(), Some(100.2));
+
+        let display = segment.start_offset(segment.display.start_time);
+        let camera = segment.start_offset(segment.camera.as_ref().unwrap().start_time);
+        let mic = segment.start_offset(segment.mic.as_ref().unwrap().start_time);
+
+        assert!((display - 0.2).abs() < 1e-9);
+        assert_eq!(camera, 0.0);
+        assert!((mic - 0.3).abs() < 1e-9);
+
+        // at composited time t every track is read at the same wall-clock instant
+        for t in [0.0, 1.5, 10.0] {
+            let display_wall = 100.0 + t + display;
+            assert!((display_wall - (100.2 + t + camera)).abs() < 1e-9);
+            assert!((display_wall - (99.9 + t + mic)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn start_offset_without_start_times() {
+        let segment: MultipleSegment =
+            serde_json::from_str(r#"{ "display": { "path": "display.mp4" } }"#).unwrap();
+
+        assert_eq!(segment.start_offset(None), 0.0);
+        assert_eq!(segment.start_offset(Some(5.0)), 0.0);
+    }
 }
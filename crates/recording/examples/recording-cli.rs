@@ -32,6 +32,10 @@ pub async fn main() {
             capture_system_audio: true,
             camera_feed: None,
             mic_feed: None,
+            encoder_error_policy: Default::default(),
+            segment_duration: None,
+            mic_wav_sidecar: false,
+            threaded_encoding: false,
         },
         false,
         // true,
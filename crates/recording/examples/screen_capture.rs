@@ -1,9 +1,9 @@
 use cap_recording::{
+    CaptureClock,
     pipeline::{control::PipelineControlSignal, task::PipelineSourceTask},
     sources::{CMSampleBufferCapture, ScreenCaptureSource, ScreenCaptureTarget},
 };
 use scap_targets::Window;
-use std::time::SystemTime;
 
 #[tokio::main]
 async fn main() {
@@ -25,7 +25,7 @@ async fn main() {
         60,
         video_tx,
         None,
-        SystemTime::now(),
+        CaptureClock::new(),
         tokio::runtime::Handle::current(),
     )
     .await
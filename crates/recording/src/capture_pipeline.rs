@@ -1,12 +1,15 @@
 use crate::{
     RecordingError,
+    clock::CaptureClock,
     feeds::microphone::MicrophoneFeedLock,
     pipeline::builder::PipelineBuilder,
     sources::{
         AudioInputSource, AudioMixer, ScreenCaptureFormat, ScreenCaptureSource,
         ScreenCaptureTarget, screen_capture,
     },
+    stats::RecordingStatsTracker,
 };
+use cap_enc_ffmpeg::{InterleaveBarrier, InterleaveStream, ThreadedMP4File, WavFile};
 use cap_media::MediaError;
 use cap_media_info::AudioInfo;
 use flume::{Receiver, Sender};
@@ -14,9 +17,12 @@ use std::{
     future::Future,
     path::PathBuf,
     sync::{Arc, atomic::AtomicBool},
-    time::SystemTime,
+    time::Duration,
 };
 
+#[cfg(windows)]
+use std::io::Write;
+
 pub trait MakeCapturePipeline: ScreenCaptureFormat + std::fmt::Debug + 'static {
     fn make_studio_mode_pipeline(
         builder: PipelineBuilder,
@@ -25,10 +31,12 @@ pub trait MakeCapturePipeline: ScreenCaptureFormat + std::fmt::Debug + 'static {
             flume::Receiver<(Self::VideoFormat, f64)>,
         ),
         output_path: PathBuf,
+        stats: Arc<RecordingStatsTracker>,
     ) -> Result<(PipelineBuilder, flume::Receiver<f64>), MediaError>
     where
         Self: Sized;
 
+    #[allow(clippy::too_many_arguments)]
     fn make_instant_mode_pipeline(
         builder: PipelineBuilder,
         source: (
@@ -36,14 +44,140 @@ pub trait MakeCapturePipeline: ScreenCaptureFormat + std::fmt::Debug + 'static {
             flume::Receiver<(Self::VideoFormat, f64)>,
         ),
         audio: Option<Arc<MicrophoneFeedLock>>,
+        mic_wav_path: Option<PathBuf>,
         system_audio: Option<(Receiver<(ffmpeg::frame::Audio, f64)>, AudioInfo)>,
         output_path: PathBuf,
         pause_flag: Arc<AtomicBool>,
+        timelapse: Option<TimelapseDecimator>,
+        live_output: Option<Sender<Vec<u8>>>,
+        threaded_encoding: bool,
+        stats: Arc<RecordingStatsTracker>,
     ) -> impl Future<Output = Result<PipelineBuilder, MediaError>> + Send
     where
         Self: Sized;
 }
 
+/// Keeps one screen frame per `interval` seconds of capture and retimes the kept frames
+/// to be `1 / fps` apart, turning a long capture into a fast-motion video.
+pub struct TimelapseDecimator {
+    interval: f64,
+    frame_duration: f64,
+    next: Option<f64>,
+    kept_frames: u64,
+}
+
+impl TimelapseDecimator {
+    pub fn new(interval: f64, fps: u32) -> Self {
+        Self {
+            interval: interval.max(1.0 / fps.max(1) as f64),
+            frame_duration: 1.0 / fps.max(1) as f64,
+            next: None,
+            kept_frames: 0,
+        }
+    }
+
+    /// Returns the time the frame captured at `timestamp` should be shown at,
+    /// in seconds from the first kept frame, or `None` if it should be dropped.
+    pub fn admit(&mut self, timestamp: f64) -> Option<f64> {
+        if let Some(next) = self.next
+            && timestamp < next
+        {
+            return None;
+        }
+
+        // Re-anchor after gaps such as pauses rather than keeping a burst of frames
+        self.next = Some(match self.next {
+            Some(next) if next + self.interval > timestamp => next + self.interval,
+            _ => timestamp + self.interval,
+        });
+
+        let time = self.kept_frames as f64 * self.frame_duration;
+        self.kept_frames += 1;
+
+        Some(time)
+    }
+}
+
+/// Keeps the audio and video encoding tasks of a recording within
+/// [`ThreadedMP4File::DEFAULT_MAX_LOOKAHEAD`] of each other, if `threaded_encoding` is set.
+/// Each task registers its own stream.
+fn interleave_barrier(threaded_encoding: bool) -> Option<InterleaveBarrier> {
+    threaded_encoding.then(|| InterleaveBarrier::new(ThreadedMP4File::DEFAULT_MAX_LOOKAHEAD))
+}
+
+/// Marks an encoding task as waiting for its next frame, see [`InterleaveStream::idle`].
+fn interleave_idle(stream: &Option<InterleaveStream>) {
+    if let Some(stream) = stream {
+        stream.idle();
+    }
+}
+
+/// Waits for the other encoding tasks to catch up to `time`, in capture clock seconds.
+fn interleave_advance(stream: &Option<InterleaveStream>, time: f64) {
+    if let Some(stream) = stream {
+        stream.advance(Duration::from_secs_f64(time.max(0.0)));
+    }
+}
+
+/// Spawns the microphone source, sending its samples to `sink`.
+///
+/// With a `wav_path`, the samples are also written there as they're captured, before any
+/// mixing. The file starts at the capture clock time `wav_start` receives, and samples
+/// captured before then are left out, so it lines up with the video.
+fn spawn_microphone(
+    builder: &mut PipelineBuilder,
+    feed: Arc<MicrophoneFeedLock>,
+    sink: Sender<(ffmpeg::frame::Audio, f64)>,
+    clock: CaptureClock,
+    wav: Option<(PathBuf, tokio::sync::oneshot::Receiver<f64>)>,
+) -> Result<(), MediaError> {
+    let Some((wav_path, mut wav_start_rx)) = wav else {
+        builder.spawn_source(
+            "microphone_capture",
+            AudioInputSource::init(feed, sink, clock),
+        );
+        return Ok(());
+    };
+
+    let (tx, rx) = flume::bounded(32);
+    let source = AudioInputSource::init(feed, tx, clock);
+
+    let mut wav = WavFile::init(wav_path, source.info())
+        .map_err(|e| MediaError::Any(format!("MicrophoneWav/{e}").into()))?;
+
+    builder.spawn_source("microphone_capture", source);
+
+    builder.spawn_task("microphone_wav", move |ready| {
+        let _ = ready.send(Ok(()));
+        let mut start = None;
+        let mut failed = false;
+
+        while let Ok((frame, timestamp)) = rx.recv() {
+            if let Ok(time) = wav_start_rx.try_recv() {
+                start = Some(time);
+            }
+
+            // the mixed track doesn't depend on the sidecar, so a failed write only stops it
+            if let Some(start) = start
+                && !failed
+                && let Err(e) = wav.queue_frame(&frame, timestamp - start)
+            {
+                tracing::error!("Failed to write microphone WAV: {e}");
+                failed = true;
+            }
+
+            if sink.send((frame, timestamp)).is_err() {
+                break;
+            }
+        }
+
+        wav.finish()
+            .map_err(|e| format!("MicrophoneWavFinish: {e}"))
+    });
+
+    Ok(())
+}
+
 #[cfg(target_os = "macos")]
 impl MakeCapturePipeline for screen_capture::CMSampleBufferCapture {
     fn make_studio_mode_pipeline(
@@ -53,6 +187,7 @@ impl MakeCapturePipeline for screen_capture::CMSampleBufferCapture {
             flume::Receiver<(Self::VideoFormat, f64)>,
         ),
         output_path: PathBuf,
+        stats: Arc<RecordingStatsTracker>,
     ) -> Result<(PipelineBuilder, flume::Receiver<f64>), MediaError> {
         let screen_config = source.0.info();
         tracing::info!("screen config: {:?}", screen_config);
@@ -84,9 +219,10 @@ impl MakeCapturePipeline for screen_capture::CMSampleBufferCapture {
 
             let result = loop {
                 match source.1.recv() {
-                    Ok(frame) => {
-                        let _ = screen_encoder.queue_video_frame(frame.0.as_ref());
-                    }
+                    Ok(frame) => match screen_encoder.queue_video_frame(frame.0.as_ref()) {
+                        Ok(()) => stats.record_video_frame(),
+                        Err(e) => break Err(format!("QueueVideoFrame: {e}")),
+                    },
                     // Err(RecvTimeoutError::Timeout) => {
                     //     break Err("Frame receive timeout".to_string());
                     // }
@@ -111,10 +247,22 @@ impl MakeCapturePipeline for screen_capture::CMSampleBufferCapture {
             flume::Receiver<(Self::VideoFormat, f64)>,
         ),
         audio: Option<Arc<MicrophoneFeedLock>>,
+        mic_wav_path: Option<PathBuf>,
         system_audio: Option<(Receiver<(ffmpeg::frame::Audio, f64)>, AudioInfo)>,
         output_path: PathBuf,
         pause_flag: Arc<AtomicBool>,
+        mut timelapse: Option<TimelapseDecimator>,
+        live_output: Option<Sender<Vec<u8>>>,
+        threaded_encoding: bool,
+        stats: Arc<RecordingStatsTracker>,
     ) -> Result<PipelineBuilder, MediaError> {
+        // AVAssetWriter only writes finished files, so there's nothing to stream
+        if live_output.is_some() {
+            return Err(MediaError::Any(
+                "Live output isn't supported by the AVFoundation encoder".into(),
+            ));
+        }
+
         let (audio_tx, audio_rx) = flume::bounded(64);
         let mut audio_mixer = AudioMixer::new(audio_tx);
 
@@ -122,11 +270,19 @@ impl MakeCapturePipeline for screen_capture::CMSampleBufferCapture {
             audio_mixer.add_source(system_audio.1, system_audio.0);
         }
 
+        // the mp4 starts at the first screen frame, so the sidecar does too
+        let (wav_start_tx, wav_start_rx) = tokio::sync::oneshot::channel();
+        let mut wav_start_tx = mic_wav_path.is_some().then_some(wav_start_tx);
+
         if let Some(audio) = audio {
             let sink = audio_mixer.sink(*audio.audio_info());
-            let source = AudioInputSource::init(audio, sink.tx, SystemTime::now());
-
-            builder.spawn_source("microphone_capture", source);
+            spawn_microphone(
+                &mut builder,
+                audio,
+                sink.tx,
+                source.0.clock(),
+                mic_wav_path.map(|path| (path, wav_start_rx)),
+            )?;
         }
 
         let has_audio_sources = audio_mixer.has_sources();
@@ -149,15 +305,24 @@ impl MakeCapturePipeline for screen_capture::CMSampleBufferCapture {
         let (first_frame_tx, mut first_frame_rx) =
             tokio::sync::oneshot::channel::<(cm::Time, f64)>();
 
+        let barrier = interleave_barrier(threaded_encoding);
+
         if has_audio_sources {
             builder.spawn_source("audio_mixer", audio_mixer);
 
             let mp4 = mp4.clone();
+            let stats = stats.clone();
+            let stream = barrier.as_ref().map(InterleaveBarrier::register);
             builder.spawn_task("audio_encoding", move |ready| {
                 let _ = ready.send(Ok(()));
                 let mut time = None;
 
-                while let Ok(mut frame) = audio_rx.recv() {
+                loop {
+                    interleave_idle(&stream);
+                    let Ok(mut frame) = audio_rx.recv() else {
+                        break;
+                    };
+
                     let pts = frame.pts().unwrap();
 
                     if let Ok(first_time) = first_frame_rx.try_recv() {
@@ -168,6 +333,8 @@ impl MakeCapturePipeline for screen_capture::CMSampleBufferCapture {
                         continue;
                     };
 
+                    interleave_advance(&stream, pts as f64 / AV_TIME_BASE_Q.den as f64);
+
                     let elapsed = (pts as f64 / AV_TIME_BASE_Q.den as f64) - time.1;
 
                     let time = time.0.add(cm::Time::new(
@@ -177,11 +344,14 @@ impl MakeCapturePipeline for screen_capture::CMSampleBufferCapture {
 
                     frame.set_pts(Some(time.value / (time.scale / AV_TIME_BASE_Q.den) as i64));
 
-                    if let Ok(mut mp4) = mp4.lock()
-                        && let Err(e) = mp4.queue_audio_frame(frame)
-                    {
-                        error!("{e}");
-                        return Ok(());
+                    let samples = frame.samples();
+                    if let Ok(mut mp4) = mp4.lock() {
+                        if let Err(e) = mp4.queue_audio_frame(frame) {
+                            error!("{e}");
+                            return Ok(());
+                        }
+
+                        stats.record_audio_samples(samples);
                     }
                 }
 
@@ -190,9 +360,46 @@ impl MakeCapturePipeline for screen_capture::CMSampleBufferCapture {
         }
 
         let mut first_frame_tx = Some(first_frame_tx);
+        let stream = barrier.as_ref().map(InterleaveBarrier::register);
         builder.spawn_task("screen_capture_encoder", move |ready| {
             let _ = ready.send(Ok(()));
-            while let Ok((frame, unix_time)) = source.1.recv() {
+            let mut timelapse_start = None;
+
+            loop {
+                interleave_idle(&stream);
+                let Ok((frame, unix_time)) = source.1.recv() else {
+                    break;
+                };
+                interleave_advance(&stream, unix_time);
+
+                if let Some(timelapse) = &mut timelapse {
+                    // Frames are retimed, so pausing is done by not keeping any
+                    // rather than by pausing the encoder's clock
+                    if pause_flag.load(std::sync::atomic::Ordering::Relaxed) {
+                        continue;
+                    }
+
+                    let Some(offset) = timelapse.admit(unix_time) else {
+                        continue;
+                    };
+                    let start: cm::Time = *timelapse_start.get_or_insert(frame.pts());
+                    let time = start.add(cm::Time::new(
+                        (offset * start.scale as f64).round() as i64,
+                        start.scale,
+                    ));
+
+                    if let Ok(mut mp4) = mp4.lock()
+                        && mp4
+                            .queue_video_frame_at(frame.as_ref(), time)
+                            .map_err(|err| error!("Error queueing video frame: {err}"))
+                            .is_ok()
+                    {
+                        stats.record_video_frame();
+                    }
+
+                    continue;
+                }
+
                 if let Ok(mut mp4) = mp4.lock() {
                     if pause_flag.load(std::sync::atomic::Ordering::Relaxed) {
                         mp4.pause();
@@ -203,10 +410,17 @@ impl MakeCapturePipeline for screen_capture::CMSampleBufferCapture {
                     if let Some(first_frame_tx) = first_frame_tx.take() {
                         let _ = first_frame_tx.send((frame.pts(), unix_time));
                     }
+                    if let Some(wav_start_tx) = wav_start_tx.take() {
+                        let _ = wav_start_tx.send(unix_time);
+                    }
 
-                    mp4.queue_video_frame(frame.as_ref())
+                    if mp4
+                        .queue_video_frame(frame.as_ref())
                         .map_err(|err| error!("Error queueing video frame: {err}"))
-                        .ok();
+                        .is_ok()
+                    {
+                        stats.record_video_frame();
+                    }
                 }
             }
             if let Ok(mut mp4) = mp4.lock() {
@@ -231,6 +445,7 @@ impl MakeCapturePipeline for screen_capture::Direct3DCapture {
             flume::Receiver<(Self::VideoFormat, f64)>,
         ),
         output_path: PathBuf,
+        stats: Arc<RecordingStatsTracker>,
     ) -> Result<(PipelineBuilder, flume::Receiver<f64>), MediaError>
     where
         Self: Sized,
@@ -299,80 +514,90 @@ impl MakeCapturePipeline for screen_capture::Direct3DCapture {
         let (timestamp_tx, timestamp_rx) = flume::bounded(1);
 
         builder.spawn_task("screen_capture_encoder", move |ready| {
-            match screen_encoder {
-                either::Left((mut encoder, mut muxer)) => {
-                    use windows::Win32::Media::MediaFoundation;
-
-                    cap_mediafoundation_utils::thread_init();
-
-                    let _ = ready.send(Ok(()));
-
-                    let mut timestamp_tx = Some(timestamp_tx);
-
-                    while let Ok(e) = encoder.get_event() {
-                        match e {
-                            MediaFoundation::METransformNeedInput => {
-                                let Ok((frame, timestamp)) = source.1.recv() else {
-                                    break;
-                                };
-
-                                if let Some(timestamp_tx) = timestamp_tx.take() {
-                                    timestamp_tx.send(timestamp).unwrap();
+            // the trailer is written even if encoding fails, so the segment stays playable
+            let result = (|| -> Result<(), String> {
+                match screen_encoder {
+                    either::Left((mut encoder, mut muxer)) => {
+                        use windows::Win32::Media::MediaFoundation;
+
+                        cap_mediafoundation_utils::thread_init();
+
+                        let _ = ready.send(Ok(()));
+
+                        let mut timestamp_tx = Some(timestamp_tx);
+
+                        while let Ok(e) = encoder.get_event() {
+                            match e {
+                                MediaFoundation::METransformNeedInput => {
+                                    let Ok((frame, timestamp)) = source.1.recv() else {
+                                        break;
+                                    };
+
+                                    if let Some(timestamp_tx) = timestamp_tx.take() {
+                                        timestamp_tx.send(timestamp).unwrap();
+                                    }
+
+                                    let frame_time = frame
+                                        .inner()
+                                        .SystemRelativeTime()
+                                        .map_err(|e| format!("FrameTime: {e}"))?;
+
+                                    encoder
+                                        .handle_needs_input(frame.texture(), frame_time)
+                                        .map_err(|e| format!("NeedsInput: {e}"))?;
+                                    stats.record_video_frame();
                                 }
-
-                                let frame_time = frame
-                                    .inner()
-                                    .SystemRelativeTime()
-                                    .map_err(|e| format!("FrameTime: {e}"))?;
-
-                                encoder
-                                    .handle_needs_input(frame.texture(), frame_time)
-                                    .map_err(|e| format!("NeedsInput: {e}"))?;
-                            }
-                            MediaFoundation::METransformHaveOutput => {
-                                if let Some(output_sample) = encoder
-                                    .handle_has_output()
-                                    .map_err(|e| format!("HasOutput: {e}"))?
-                                {
-                                    muxer
-                                        .write_sample(&output_sample, &mut output)
-                                        .map_err(|e| format!("WriteSample: {e}"))?;
+                                MediaFoundation::METransformHaveOutput => {
+                                    if let Some(output_sample) = encoder
+                                        .handle_has_output()
+                                        .map_err(|e| format!("HasOutput: {e}"))?
+                                    {
+                                        muxer
+                                            .write_sample(&output_sample, &mut output)
+                                            .map_err(|e| format!("WriteSample: {e}"))?;
+                                    }
                                 }
+                                _ => {}
                             }
-                            _ => {}
                         }
-                    }
 
-                    encoder
-                        .finish()
-                        .map_err(|e| format!("EncoderFinish: {e}"))?;
-                }
-                either::Right(mut encoder) => {
-                    let mut timestamp_tx = Some(timestamp_tx);
-                    let _ = ready.send(Ok(()));
+                        encoder
+                            .finish()
+                            .map_err(|e| format!("EncoderFinish: {e}"))?;
+                    }
+                    either::Right(mut encoder) => {
+                        let mut timestamp_tx = Some(timestamp_tx);
+                        let _ = ready.send(Ok(()));
 
-                    while let Ok((frame, timestamp)) = source.1.recv() {
-                        use scap_ffmpeg::AsFFmpeg;
+                        while let Ok((frame, timestamp)) = source.1.recv() {
+                            use scap_ffmpeg::AsFFmpeg;
 
-                        if let Some(timestamp_tx) = timestamp_tx.take() {
-                            let _ = timestamp_tx.send(timestamp);
-                        }
+                            if let Some(timestamp_tx) = timestamp_tx.take() {
+                                let _ = timestamp_tx.send(timestamp);
+                            }
 
-                        let ff_frame = frame
-                            .as_ffmpeg()
-                            .map_err(|e| format!("FrameAsFfmpeg: {e}"))?;
+                            let ff_frame = frame
+                                .as_ffmpeg()
+                                .map_err(|e| format!("FrameAsFfmpeg: {e}"))?;
 
-                        encoder.queue_frame(ff_frame, &mut output);
+                            encoder
+                                .queue_frame(ff_frame, &mut output)
+                                .map_err(|e| format!("QueueFrame: {e}"))?;
+                            stats.record_video_frame();
+                        }
+                        encoder
+                            .finish(&mut output)
+                            .map_err(|e| format!("EncoderFinish: {e}"))?;
                     }
-                    encoder.finish(&mut output);
                 }
-            }
+                Ok(())
+            })();
 
-            output
+            let trailer = output
                 .write_trailer()
-                .map_err(|e| format!("WriteTrailer: {e}"))?;
+                .map_err(|e| format!("WriteTrailer: {e}"));
 
-            Ok(())
+            result.and(trailer)
         });
 
         Ok((builder, timestamp_rx))
@@ -385,14 +610,20 @@ impl MakeCapturePipeline for screen_capture::Direct3DCapture {
             flume::Receiver<(Self::VideoFormat, f64)>,
         ),
         audio: Option<Arc<MicrophoneFeedLock>>,
+        mic_wav_path: Option<PathBuf>,
         system_audio: Option<(Receiver<(ffmpeg::frame::Audio, f64)>, AudioInfo)>,
         output_path: PathBuf,
         _pause_flag: Arc<AtomicBool>,
+        mut timelapse: Option<TimelapseDecimator>,
+        live_output: Option<Sender<Vec<u8>>>,
+        threaded_encoding: bool,
+        stats: Arc<RecordingStatsTracker>,
     ) -> Result<PipelineBuilder, MediaError>
     where
         Self: Sized,
     {
         use cap_enc_ffmpeg::{AACEncoder, AudioEncoder};
+        use ffmpeg::Rescale;
         use windows::Graphics::SizeInt32;
 
         cap_mediafoundation_utils::thread_init();
@@ -406,16 +637,36 @@ impl MakeCapturePipeline for screen_capture::Direct3DCapture {
 
         if let Some(audio) = audio {
             let sink = audio_mixer.sink(*audio.audio_info());
-            let source = AudioInputSource::init(audio, sink.tx, SystemTime::now());
-
-            builder.spawn_source("microphone_capture", source);
+            // screen frames are timestamped with the capture clock, which the mp4 starts at
+            let wav = mic_wav_path.map(|path| {
+                let (tx, rx) = tokio::sync::oneshot::channel();
+                let _ = tx.send(0.0);
+                (path, rx)
+            });
+            spawn_microphone(&mut builder, audio, sink.tx, source.0.clock(), wav)?;
         }
 
         let has_audio_sources = audio_mixer.has_sources();
         let screen_config = source.0.info();
 
-        let mut output = ffmpeg::format::output(&output_path)
-            .map_err(|e| MediaError::Any(format!("CreateOutput: {e}").into()))?;
+        let mut output: InstantOutput = match live_output {
+            Some(tx) => {
+                let file = std::fs::File::create(&output_path)
+                    .map_err(|e| MediaError::Any(format!("CreateOutput: {e}").into()))?;
+
+                Box::new(
+                    cap_enc_ffmpeg::FragmentedMP4Output::new(LiveOutputWriter {
+                        file: std::io::BufWriter::new(file),
+                        tx,
+                    })
+                    .map_err(|e| MediaError::Any(format!("CreateOutput: {e}").into()))?,
+                )
+            }
+            None => Box::new(Box::new(
+                ffmpeg::format::output(&output_path)
+                    .map_err(|e| MediaError::Any(format!("CreateOutput: {e}").into()))?,
+            )),
+        };
 
         let screen_encoder = {
             let native_encoder = cap_enc_mediafoundation::H264Encoder::new_with_scaled_output(
@@ -481,16 +732,38 @@ impl MakeCapturePipeline for screen_capture::Direct3DCapture {
             .map_err(|e| MediaError::Any(format!("OutputHeader/{e}").into()))?;
 
         let output = Arc::new(std::sync::Mutex::new(output));
+        let barrier = interleave_barrier(threaded_encoding);
 
         if let Some(mut audio_encoder) = audio_encoder {
             builder.spawn_source("audio_mixer", audio_mixer);
 
             // let is_done = is_done.clone();
             let output = output.clone();
+            let stats = stats.clone();
+            let stream = barrier.as_ref().map(InterleaveBarrier::register);
             builder.spawn_task("audio_encoding", move |ready| {
                 let _ = ready.send(Ok(()));
-                while let Ok(frame) = audio_rx.recv() {
+                loop {
+                    interleave_idle(&stream);
+                    let Ok(mut frame) = audio_rx.recv() else {
+                        break;
+                    };
+                    interleave_advance(
+                        &stream,
+                        frame.pts().unwrap_or_default() as f64
+                            / ffmpeg::ffi::AV_TIME_BASE_Q.den as f64,
+                    );
+
+                    // The mixer's timestamps are in microseconds, but the encoder expects samples
+                    let rate = frame.rate() as i32;
+                    frame.set_pts(
+                        frame
+                            .pts()
+                            .map(|pts| pts.rescale(ffmpeg::ffi::AV_TIME_BASE_Q, (1, rate))),
+                    );
+
                     if let Ok(mut output) = output.lock() {
+                        stats.record_audio_samples(frame.samples());
                         audio_encoder.queue_frame(frame, &mut *output);
                     }
                 }
@@ -500,6 +773,7 @@ impl MakeCapturePipeline for screen_capture::Direct3DCapture {
 
         builder.spawn_source("screen_capture", source.0);
 
+        let stream = barrier.as_ref().map(InterleaveBarrier::register);
         builder.spawn_task("screen_encoder", move |ready| {
             match screen_encoder {
                 either::Left((mut encoder, mut muxer)) => {
@@ -512,18 +786,23 @@ impl MakeCapturePipeline for screen_capture::Direct3DCapture {
                     while let Ok(e) = encoder.get_event() {
                         match e {
                             MediaFoundation::METransformNeedInput => {
-                                let Ok((frame, _)) = source.1.recv() else {
+                                interleave_idle(&stream);
+                                let Ok((frame, timestamp)) =
+                                    recv_timelapse_frame(&source.1, timelapse.as_mut())
+                                else {
                                     break;
                                 };
+                                interleave_advance(&stream, timestamp);
 
-                                let frame_time = frame
-                                    .inner()
-                                    .SystemRelativeTime()
-                                    .map_err(|e| format!("Frame Time: {e}"))?;
+                                // TimeSpan durations are in 100ns units
+                                let frame_time = windows::Foundation::TimeSpan {
+                                    Duration: (timestamp * 10_000_000.0).round() as i64,
+                                };
 
                                 encoder
                                     .handle_needs_input(frame.texture(), frame_time)
                                     .map_err(|e| format!("NeedsInput: {e}"))?;
+                                stats.record_video_frame();
                             }
                             MediaFoundation::METransformHaveOutput => {
                                 if let Some(output_sample) = encoder
@@ -550,10 +829,14 @@ impl MakeCapturePipeline for screen_capture::Direct3DCapture {
 
                     let _ = ready.send(Ok(()));
 
-                    while let Ok((frame, _unix_time)) = source.1.recv() {
-                        let Ok(mut output) = output.lock() else {
-                            continue;
+                    loop {
+                        interleave_idle(&stream);
+                        let Ok((frame, timestamp)) =
+                            recv_timelapse_frame(&source.1, timelapse.as_mut())
+                        else {
+                            break;
                         };
+                        interleave_advance(&stream, timestamp);
 
                         // if pause_flag.load(std::sync::atomic::Ordering::Relaxed) {
                         //     mp4.pause();
@@ -563,12 +846,36 @@ impl MakeCapturePipeline for screen_capture::Direct3DCapture {
 
                         use scap_ffmpeg::AsFFmpeg;
 
-                        encoder.queue_frame(
-                            frame
-                                .as_ffmpeg()
-                                .map_err(|e| format!("FrameAsFFmpeg: {e}"))?,
-                            &mut output,
-                        );
+                        let mut ff_frame = frame
+                            .as_ffmpeg()
+                            .map_err(|e| format!("FrameAsFFmpeg: {e}"))?;
+
+                        let time_base = encoder.time_base();
+                        ff_frame.set_pts(Some(
+                            (timestamp * time_base.denominator() as f64
+                                / time_base.numerator() as f64)
+                                .round() as i64,
+                        ));
+
+                        // encoding happens outside the lock, so the audio task can keep
+                        // writing while a frame takes a while
+                        encoder
+                            .send_frame(ff_frame)
+                            .map_err(|e| format!("SendFrame: {e}"))?;
+                        stats.record_video_frame();
+
+                        let Ok(mut output) = output.lock() else {
+                            continue;
+                        };
+                        encoder
+                            .process_frame(&mut output)
+                            .map_err(|e| format!("ProcessFrame: {e}"))?;
+                    }
+
+                    if let Ok(mut output) = output.lock() {
+                        encoder
+                            .finish(&mut output)
+                            .map_err(|e| format!("EncoderFinish: {e}"))?;
                     }
                 }
             }
@@ -586,6 +893,52 @@ impl MakeCapturePipeline for screen_capture::Direct3DCapture {
     }
 }
 
+/// Either a regular mp4 file or a fragmented one that's also streamed live.
+#[cfg(windows)]
+type InstantOutput = Box<dyn std::ops::DerefMut<Target = ffmpeg::format::context::Output> + Send>;
+
+/// Writes fragmented mp4 bytes to the recording file while also handing them to a
+/// live consumer. The file is always written, even once the consumer goes away.
+#[cfg(windows)]
+struct LiveOutputWriter {
+    file: std::io::BufWriter<std::fs::File>,
+    tx: Sender<Vec<u8>>,
+}
+
+#[cfg(windows)]
+impl Write for LiveOutputWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.write(buf)?;
+        let _ = self.tx.send(buf[..written].to_vec());
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Receives the next screen frame, skipping the ones a timelapse doesn't keep.
+/// Frames come with the time they should be shown at, which is their capture clock
+/// timestamp unless they're retimed by a timelapse.
+#[cfg(windows)]
+fn recv_timelapse_frame<T>(
+    rx: &Receiver<(T, f64)>,
+    mut timelapse: Option<&mut TimelapseDecimator>,
+) -> Result<(T, f64), flume::RecvError> {
+    loop {
+        let (frame, timestamp) = rx.recv()?;
+
+        let Some(timelapse) = timelapse.as_deref_mut() else {
+            return Ok((frame, timestamp));
+        };
+
+        if let Some(offset) = timelapse.admit(timestamp) {
+            return Ok((frame, offset));
+        }
+    }
+}
+
 type ScreenCaptureReturn<T> = (
     ScreenCaptureSource<T>,
     Receiver<(<T as ScreenCaptureFormat>::VideoFormat, f64)>,
@@ -602,7 +955,7 @@ pub async fn create_screen_capture(
     force_show_cursor: bool,
     max_fps: u32,
     audio_tx: Option<Sender<(ffmpeg::frame::Audio, f64)>>,
-    start_time: SystemTime,
+    clock: CaptureClock,
     #[cfg(windows)] d3d_device: ::windows::Win32::Graphics::Direct3D11::ID3D11Device,
 ) -> Result<ScreenCaptureReturn<ScreenCaptureMethod>, RecordingError> {
     let (video_tx, video_rx) = flume::bounded(16);
@@ -613,7 +966,7 @@ pub async fn create_screen_capture(
         max_fps,
         video_tx,
         audio_tx,
-        start_time,
+        clock,
         tokio::runtime::Handle::current(),
         #[cfg(windows)]
         d3d_device,
@@ -682,3 +1035,75 @@ pub fn create_d3d_device()
 
     Ok(device.unwrap())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_one_frame_per_interval() {
+        let mut timelapse = TimelapseDecimator::new(1.0, 4);
+
+        let times = [0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 3.0].map(|timestamp| timelapse.admit(timestamp));
+
+        assert_eq!(
+            times,
+            [
+                Some(0.0),
+                None,
+                Some(0.25),
+                None,
+                Some(0.5),
+                None,
+                Some(0.75)
+            ]
+        );
+    }
+
+    #[test]
+    fn late_frames_stay_on_grid() {
+        let mut timelapse = TimelapseDecimator::new(1.0, 4);
+
+        assert_eq!(timelapse.admit(0.0), Some(0.0));
+        assert_eq!(timelapse.admit(1.05), Some(0.25));
+        // the next slot is still at 2.0, not 2.05
+        assert_eq!(timelapse.admit(2.01), Some(0.5));
+        assert_eq!(timelapse.admit(2.99), None);
+        assert_eq!(timelapse.admit(3.0), Some(0.75));
+    }
+
+    #[test]
+    fn reanchors_after_gap() {
+        let mut timelapse = TimelapseDecimator::new(1.0, 4);
+
+        assert_eq!(timelapse.admit(0.0), Some(0.0));
+        assert_eq!(timelapse.admit(10.0), Some(0.25));
+        // without re-anchoring, a frame would be kept for every slot missed during the gap
+        assert_eq!(timelapse.admit(10.5), None);
+        assert_eq!(timelapse.admit(11.0), Some(0.5));
+    }
+
+    #[test]
+    fn kept_frames_are_evenly_timed() {
+        let mut timelapse = TimelapseDecimator::new(0.5, 4);
+
+        // 30fps capture for ten seconds
+        let times = (0..300)
+            .filter_map(|i| timelapse.admit(i as f64 / 30.0))
+            .collect::<Vec<_>>();
+
+        assert_eq!(times.len(), 20);
+        for (i, time) in times.iter().enumerate() {
+            assert_eq!(*time, i as f64 * 0.25);
+        }
+    }
+
+    #[test]
+    fn interval_is_at_least_one_frame() {
+        let mut timelapse = TimelapseDecimator::new(0.0, 4);
+
+        assert_eq!(timelapse.admit(0.0), Some(0.0));
+        assert_eq!(timelapse.admit(0.1), None);
+        assert_eq!(timelapse.admit(0.25), Some(0.25));
+    }
+}
@@ -0,0 +1,45 @@
+use std::time::{Duration, Instant};
+
+/// The clock every source in a recording timestamps its frames against, so that screen,
+/// camera, microphone and system audio timestamps can be compared and muxed directly.
+///
+/// The epoch is the moment the clock was created, when the recording starts, and
+/// timestamps are the time elapsed since then. Time is measured on the monotonic clock,
+/// so changes to the system time during a recording don't shift any source.
+#[derive(Debug, Clone, Copy)]
+pub struct CaptureClock {
+    start: Instant,
+}
+
+impl CaptureClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+
+    /// A clock that's already at `elapsed`, so a recording that's added to carries on
+    /// from where it ended.
+    pub fn continuing_from(elapsed: Duration) -> Self {
+        let now = Instant::now();
+
+        Self {
+            start: now.checked_sub(elapsed).unwrap_or(now),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Times before the epoch are clamped to it.
+    pub fn elapsed_at(&self, instant: Instant) -> Duration {
+        instant.saturating_duration_since(self.start)
+    }
+}
+
+impl Default for CaptureClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,10 +1,12 @@
 use cap_cursor_capture::CursorCropBounds;
 use cap_cursor_info::CursorShape;
 use cap_project::{CursorClickEvent, CursorMoveEvent, XY};
-use std::{collections::HashMap, path::PathBuf, time::SystemTime};
+use std::{collections::HashMap, path::PathBuf};
 use tokio::sync::oneshot;
 use tokio_util::sync::CancellationToken;
 
+use crate::clock::CaptureClock;
+
 pub struct Cursor {
     pub file_name: String,
     pub id: u32,
@@ -41,7 +43,7 @@ pub fn spawn_cursor_recorder(
     cursors_dir: PathBuf,
     prev_cursors: Cursors,
     next_cursor_id: u32,
-    start_time: SystemTime,
+    clock: CaptureClock,
 ) -> CursorActor {
     use cap_utils::spawn_actor;
     use device_query::{DeviceQuery, DeviceState};
@@ -81,10 +83,7 @@ pub fn spawn_cursor_recorder(
                 break;
             };
 
-            let Ok(elapsed) = start_time.elapsed() else {
-                continue;
-            };
-            let elapsed = elapsed.as_secs_f64() * 1000.0;
+            let elapsed = clock.elapsed().as_secs_f64() * 1000.0;
             let mouse_state = device_state.get_mouse();
 
             let cursor_data = get_cursor_data();
@@ -1,5 +1,6 @@
 use cap_camera::CameraInfo;
 use cap_fail::fail_err;
+use cap_media::MediaError;
 use cap_media_info::VideoInfo;
 use ffmpeg::frame;
 use futures::{FutureExt, future::BoxFuture};
@@ -16,6 +17,8 @@ use tracing::{debug, error, trace, warn};
 
 use cap_camera_ffmpeg::*;
 
+use super::{DeviceEvent, OnDeviceEvent, REATTACH_POLL_INTERVAL};
+
 const CAMERA_INIT_TIMEOUT: Duration = Duration::from_secs(4);
 
 #[derive(Clone)]
@@ -23,6 +26,9 @@ pub struct RawCameraFrame {
     pub frame: frame::Video,
     pub timestamp: Duration,
     pub reference_time: Instant,
+    /// Changes when the feed starts a new capture, e.g. after reattaching a lost camera.
+    /// `timestamp`s from different captures can't be compared.
+    pub input_id: u32,
 }
 
 #[derive(Actor)]
@@ -31,6 +37,8 @@ pub struct CameraFeed {
     senders: Vec<flume::Sender<RawCameraFrame>>,
     on_ready: Vec<oneshot::Sender<()>>,
     on_disconnect: Vec<Box<dyn Fn() + Send>>,
+    on_device_event: Vec<Box<dyn Fn(&DeviceEvent) + Send>>,
+    input_id_counter: u32,
 }
 
 enum State {
@@ -46,6 +54,13 @@ impl State {
             Err(FeedLockedError)
         }
     }
+
+    fn attached_mut(&mut self) -> Option<&mut AttachedState> {
+        match self {
+            Self::Open(open_state) => open_state.attached.as_mut(),
+            Self::Locked { inner } => Some(inner),
+        }
+    }
 }
 
 struct OpenState {
@@ -60,9 +75,11 @@ impl OpenState {
         {
             self.attached = Some(AttachedState {
                 id,
+                input_id: data.input_id,
                 camera_info: data.camera_info,
                 video_info: data.video_info,
                 done_tx: data.done_tx,
+                lost: false,
             });
             self.connecting = None;
         }
@@ -75,11 +92,13 @@ struct ConnectingState {
 }
 
 struct AttachedState {
-    #[allow(dead_code)]
     id: DeviceOrModelID,
+    input_id: u32,
     camera_info: cap_camera::CameraInfo,
     video_info: VideoInfo,
     done_tx: mpsc::SyncSender<()>,
+    /// The camera has disappeared and we're waiting for it to come back
+    lost: bool,
 }
 
 impl Default for CameraFeed {
@@ -92,6 +111,8 @@ impl Default for CameraFeed {
             senders: Vec::new(),
             on_ready: Vec::new(),
             on_disconnect: Vec::new(),
+            on_device_event: Vec::new(),
+            input_id_counter: 0,
         }
     }
 }
@@ -165,6 +186,7 @@ pub struct Lock;
 #[derive(Clone)]
 struct InputConnected {
     done_tx: SyncSender<()>,
+    input_id: u32,
     camera_info: cap_camera::CameraInfo,
     video_info: VideoInfo,
 }
@@ -173,6 +195,14 @@ struct InputConnectFailed {
     id: DeviceOrModelID,
 }
 
+struct InputLost {
+    input_id: u32,
+}
+
+struct Reattach {
+    id: DeviceOrModelID,
+}
+
 struct NewFrame(RawCameraFrame);
 
 struct Unlock;
@@ -217,6 +247,7 @@ struct SetupCameraResult {
 
 async fn setup_camera(
     id: &DeviceOrModelID,
+    input_id: u32,
     recipient: Recipient<NewFrame>,
 ) -> Result<SetupCameraResult, SetInputError> {
     let camera = find_camera(id).ok_or(SetInputError::DeviceNotFound)?;
@@ -257,13 +288,23 @@ async fn setup_camera(
     let format = ideal_formats.swap_remove(0);
     let frame_rate = format.frame_rate() as u32;
 
-    let (ready_tx, ready_rx) = oneshot::channel();
+    let (ready_tx, ready_rx) = oneshot::channel::<Result<VideoInfo, MediaError>>();
     let mut ready_signal = Some(ready_tx);
+    let mut converter = FrameConverter::default();
 
     let capture_handle = camera
         .start_capturing(format.clone(), move |frame| {
-            let Ok(mut ff_frame) = frame.to_ffmpeg() else {
-                return;
+            let mut ff_frame = match converter.convert(&frame) {
+                Ok(ff_frame) => ff_frame,
+                Err(e) => {
+                    // if the first frame can't be converted, none of them can
+                    if let Some(signal) = ready_signal.take() {
+                        let _ = signal.send(Err(MediaError::Any(
+                            format!("Unsupported camera pixel format: {e}").into(),
+                        )));
+                    }
+                    return;
+                }
             };
 
             ff_frame.set_pts(Some(frame.timestamp.as_micros() as i64));
@@ -276,7 +317,7 @@ async fn setup_camera(
                     frame_rate,
                 );
 
-                let _ = signal.send(video_info);
+                let _ = signal.send(Ok(video_info));
             }
 
             let _ = recipient
@@ -284,6 +325,7 @@ async fn setup_camera(
                     frame: ff_frame,
                     timestamp: frame.timestamp,
                     reference_time: frame.reference_time,
+                    input_id,
                 }))
                 .try_send();
         })
@@ -292,7 +334,8 @@ async fn setup_camera(
     let video_info = tokio::time::timeout(CAMERA_INIT_TIMEOUT, ready_rx)
         .await
         .map_err(|e| SetInputError::Timeout(e.to_string()))?
-        .map_err(|_| SetInputError::Initialisation)?;
+        .map_err(|_| SetInputError::Initialisation)?
+        .map_err(|e| SetInputError::StartCapturing(e.to_string()))?;
 
     Ok(SetupCameraResult {
         handle: capture_handle,
@@ -330,49 +373,223 @@ impl Message<SetInput> for CameraFeed {
             ready: ready.clone().boxed(),
         });
 
-        let id = msg.id.clone();
-        let actor_ref = ctx.actor_ref();
-        let new_frame_recipient = actor_ref.clone().recipient();
-
-        let rt = Runtime::new().expect("Failed to get Tokio runtime!");
-        std::thread::spawn(move || {
-            LocalSet::new().block_on(&rt, async move {
-                let handle = match setup_camera(&id, new_frame_recipient).await {
-                    Ok(r) => {
-                        let _ = ready_tx.send(Ok(InputConnected {
-                            camera_info: r.camera_info.clone(),
-                            video_info: r.video_info.clone(),
-                            done_tx: done_tx.clone(),
-                        }));
-
-                        let _ = actor_ref
-                            .ask(InputConnected {
-                                camera_info: r.camera_info.clone(),
-                                video_info: r.video_info.clone(),
-                                done_tx: done_tx.clone(),
-                            })
-                            .await;
-
-                        r.handle
+        let input_id = self.input_id_counter;
+        self.input_id_counter += 1;
+
+        spawn_capture(
+            msg.id.clone(),
+            input_id,
+            ctx.actor_ref(),
+            ready_tx,
+            (done_tx, done_rx),
+            true,
+        );
+
+        Ok(ready
+            .map(|v| v.map(|v| (v.camera_info, v.video_info)))
+            .boxed())
+    }
+}
+
+/// Starts capturing on a dedicated thread, which stops when `done` is signalled or the camera
+/// disappears. If `notify_connected` is set, the feed is told about the new input.
+fn spawn_capture(
+    id: DeviceOrModelID,
+    input_id: u32,
+    actor_ref: ActorRef<CameraFeed>,
+    ready_tx: oneshot::Sender<Result<InputConnected, SetInputError>>,
+    (done_tx, done_rx): (SyncSender<()>, mpsc::Receiver<()>),
+    notify_connected: bool,
+) {
+    let new_frame_recipient = actor_ref.clone().recipient();
+
+    let rt = Runtime::new().expect("Failed to get Tokio runtime!");
+    std::thread::spawn(move || {
+        LocalSet::new().block_on(&rt, async move {
+            let handle = match setup_camera(&id, input_id, new_frame_recipient).await {
+                Ok(r) => {
+                    let connected = InputConnected {
+                        camera_info: r.camera_info.clone(),
+                        video_info: r.video_info,
+                        input_id,
+                        done_tx: done_tx.clone(),
+                    };
+
+                    let _ = ready_tx.send(Ok(connected.clone()));
+
+                    if notify_connected {
+                        let _ = actor_ref.ask(connected).await;
                     }
-                    Err(e) => {
-                        let _ = ready_tx.send(Err(e.clone()));
 
+                    r.handle
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e.clone()));
+
+                    if notify_connected {
                         let _ = actor_ref.tell(InputConnectFailed { id }).await;
+                    }
+
+                    return;
+                }
+            };
 
-                        return;
+            // Cameras don't report being unplugged, so check that it's still around
+            loop {
+                match done_rx.recv_timeout(REATTACH_POLL_INTERVAL) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        if find_camera(&id).is_none() {
+                            let _ = actor_ref.tell(InputLost { input_id }).await;
+                            break;
+                        }
                     }
-                };
+                    _ => break,
+                }
+            }
 
-                let _ = done_rx.recv();
+            let _ = handle.stop_capturing();
+        })
+    });
+}
 
-                let _ = handle.stop_capturing();
-            })
-        });
+impl CameraFeed {
+    fn emit_device_event(&self, event: DeviceEvent) {
+        for cb in &self.on_device_event {
+            (cb)(&event);
+        }
+    }
+}
 
-        Ok(ready
-            .map(|v| v.map(|v| (v.camera_info, v.video_info)))
-            .boxed())
+fn poll_for_camera(id: DeviceOrModelID, actor_ref: ActorRef<CameraFeed>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(REATTACH_POLL_INTERVAL).await;
+
+            if !actor_ref.is_alive() {
+                return;
+            }
+
+            if find_camera(&id).is_some() {
+                let _ = actor_ref.tell(Reattach { id }).await;
+                return;
+            }
+        }
+    });
+}
+
+impl Message<InputLost> for CameraFeed {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: InputLost,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        trace!("CameraFeed.InputLost");
+
+        let Some(attached) = self.state.attached_mut() else {
+            return;
+        };
+
+        if attached.input_id != msg.input_id || attached.lost {
+            return;
+        }
+
+        warn!(
+            "Camera '{}' is unreachable",
+            attached.camera_info.display_name()
+        );
+
+        attached.lost = true;
+        let id = attached.id.clone();
+        let name = attached.camera_info.display_name().to_string();
+
+        self.emit_device_event(DeviceEvent::Unreachable(name));
+
+        poll_for_camera(id, ctx.actor_ref());
+    }
+}
+
+impl Message<Reattach> for CameraFeed {
+    type Reply = ();
+
+    async fn handle(&mut self, msg: Reattach, ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        trace!("CameraFeed.Reattach('{:?}')", &msg.id);
+
+        let input_id = self.input_id_counter;
+
+        let Some(attached) = self.state.attached_mut() else {
+            return;
+        };
+
+        // The input was removed or changed while we were waiting
+        if !attached.lost || attached.id != msg.id {
+            return;
+        }
+
+        self.input_id_counter += 1;
+
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let (done_tx, done_rx) = mpsc::sync_channel(0);
+
+        spawn_capture(
+            msg.id.clone(),
+            input_id,
+            ctx.actor_ref(),
+            ready_tx,
+            (done_tx, done_rx),
+            false,
+        );
+
+        let connected = match ready_rx.await {
+            Ok(Ok(connected)) => connected,
+            result => {
+                if let Ok(Err(e)) = result {
+                    warn!("Failed to reattach camera: {e}");
+                }
+                poll_for_camera(msg.id, ctx.actor_ref());
+                return;
+            }
+        };
+
+        let Some(attached) = self.state.attached_mut() else {
+            return;
+        };
+
+        // Consumers of a locked feed assume the format doesn't change
+        if (
+            connected.video_info.pixel_format,
+            connected.video_info.width,
+            connected.video_info.height,
+        ) != (
+            attached.video_info.pixel_format,
+            attached.video_info.width,
+            attached.video_info.height,
+        ) {
+            warn!("Camera came back with a different format, not reattaching");
+            let _ = connected.done_tx.try_send(());
+            return;
+        }
+
+        attached.input_id = input_id;
+        attached.done_tx = connected.done_tx;
+        attached.lost = false;
+
+        let name = attached.camera_info.display_name().to_string();
+        debug!("Camera '{name}' reattached");
+        self.emit_device_event(DeviceEvent::Reattached(name));
+    }
+}
+
+impl Message<OnDeviceEvent> for CameraFeed {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: OnDeviceEvent,
+        _: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.on_device_event.push(msg.0);
     }
 }
 
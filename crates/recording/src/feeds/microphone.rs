@@ -3,6 +3,7 @@ use cpal::{
     Device, InputCallbackInfo, SampleFormat, StreamError, SupportedStreamConfig,
     traits::{DeviceTrait, HostTrait, StreamTrait},
 };
+use ffmpeg::{ChannelLayout, frame, software::resampling};
 use flume::TrySendError;
 use futures::{FutureExt, channel::oneshot, future::BoxFuture};
 use indexmap::IndexMap;
@@ -14,13 +15,24 @@ use std::{
 };
 use tracing::{debug, error, info, trace, warn};
 
+use super::{DeviceEvent, OnDeviceEvent, REATTACH_POLL_INTERVAL};
+
 pub type MicrophonesMap = IndexMap<String, (Device, SupportedStreamConfig)>;
 
+/// Rate that samples are delivered to senders at, regardless of the device's native rate.
+pub use cap_media::TARGET_SAMPLE_RATE;
+
 #[derive(Clone)]
 pub struct MicrophoneSamples {
     pub data: Vec<u8>,
     pub format: SampleFormat,
     pub info: InputCallbackInfo,
+    /// Changes when the feed switches to a new stream, e.g. after reattaching a lost device.
+    /// Capture timestamps from different streams can't be compared.
+    pub input_id: u32,
+    /// Always [`TARGET_SAMPLE_RATE`] once the samples have left the feed.
+    pub sample_rate: u32,
+    pub channels: u16,
 }
 
 #[derive(Actor)]
@@ -29,6 +41,8 @@ pub struct MicrophoneFeed {
     state: State,
     senders: Vec<flume::Sender<MicrophoneSamples>>,
     error_sender: flume::Sender<StreamError>,
+    resampler: Option<SamplesResampler>,
+    on_device_event: Vec<Box<dyn Fn(&DeviceEvent) + Send>>,
 }
 
 enum State {
@@ -44,6 +58,13 @@ impl State {
             Err(FeedLockedError)
         }
     }
+
+    fn attached_mut(&mut self) -> Option<&mut AttachedState> {
+        match self {
+            Self::Open(open_state) => open_state.attached.as_mut(),
+            Self::Locked { inner } => Some(inner),
+        }
+    }
 }
 
 struct OpenState {
@@ -58,8 +79,10 @@ impl OpenState {
         {
             self.attached = Some(AttachedState {
                 id: data.id,
+                label: data.label,
                 config: data.config.clone(),
                 done_tx: data.done_tx,
+                lost: false,
             });
             self.connecting = None;
         }
@@ -72,10 +95,12 @@ struct ConnectingState {
 }
 
 struct AttachedState {
-    #[allow(dead_code)]
     id: u32,
+    label: String,
     config: SupportedStreamConfig,
     done_tx: mpsc::SyncSender<()>,
+    /// The device has stopped responding and we're waiting for it to come back
+    lost: bool,
 }
 
 impl MicrophoneFeed {
@@ -88,6 +113,8 @@ impl MicrophoneFeed {
             }),
             senders: Vec::new(),
             error_sender,
+            resampler: None,
+            on_device_event: Vec::new(),
         }
     }
 
@@ -170,6 +197,13 @@ impl MicrophoneFeedLock {
     }
 }
 
+impl MicrophoneFeedLock {
+    /// Rate the device captures at, before being resampled to [`TARGET_SAMPLE_RATE`].
+    pub fn native_sample_rate(&self) -> u32 {
+        self.config.sample_rate().0
+    }
+}
+
 impl Deref for MicrophoneFeedLock {
     type Target = ActorRef<MicrophoneFeed>;
 
@@ -202,10 +236,21 @@ pub struct Lock;
 
 struct InputConnected {
     id: u32,
+    label: String,
     config: SupportedStreamConfig,
     done_tx: SyncSender<()>,
 }
 
+struct InputLost {
+    id: u32,
+}
+
+struct Reattach {
+    label: String,
+    device: Device,
+    config: SupportedStreamConfig,
+}
+
 struct InputConnectFailed {
     id: u32,
 }
@@ -249,12 +294,9 @@ impl Message<SetInput> for MicrophoneFeed {
             return Err(SetInputError::DeviceNotFound);
         };
 
-        let sample_format = config.sample_format();
-
         let (ready_tx, ready_rx) = oneshot::channel();
         let (done_tx, done_rx) = mpsc::sync_channel(0);
 
-        let actor_ref = ctx.actor_ref();
         let ready = {
             let config = config.clone();
             ready_rx
@@ -264,17 +306,18 @@ impl Message<SetInput> for MicrophoneFeed {
                 })
                 .shared()
         };
-        let error_sender = self.error_sender.clone();
 
         state.connecting = Some(ConnectingState {
             id,
             ready: {
                 let done_tx = done_tx.clone();
+                let label = msg.label.clone();
                 ready
                     .clone()
                     .map(move |v| {
                         v.map(|config| InputConnected {
                             id,
+                            label,
                             config,
                             done_tx,
                         })
@@ -283,56 +326,15 @@ impl Message<SetInput> for MicrophoneFeed {
             },
         });
 
-        std::thread::spawn({
-            let config = config.clone();
-            move || {
-                let stream = match device.build_input_stream_raw(
-                    &config.into(),
-                    sample_format,
-                    {
-                        let actor_ref = actor_ref.clone();
-                        move |data, info| {
-                            let _ = actor_ref
-                                .tell(MicrophoneSamples {
-                                    data: data.bytes().to_vec(),
-                                    format: data.sample_format(),
-                                    info: info.clone(),
-                                })
-                                .try_send();
-                        }
-                    },
-                    move |e| {
-                        error!("Microphone stream error: {e}");
-
-                        let _ = error_sender.send(e).is_err();
-                        actor_ref.kill();
-                    },
-                    None,
-                ) {
-                    Ok(stream) => stream,
-                    Err(e) => {
-                        let _ = ready_tx.send(Err(SetInputError::BuildStream(e.to_string())));
-                        return;
-                    }
-                };
-
-                if let Err(e) = stream.play() {
-                    let _ = ready_tx.send(Err(SetInputError::PlayStream(e.to_string())));
-                    return;
-                }
-
-                let _ = ready_tx.send(Ok(()));
-
-                match done_rx.recv() {
-                    Ok(_) => {
-                        info!("Microphone actor shut down, ending stream");
-                    }
-                    Err(_) => {
-                        info!("Microphone actor unreachable, ending stream");
-                    }
-                }
-            }
-        });
+        spawn_input_stream(
+            id,
+            device,
+            config,
+            ctx.actor_ref(),
+            self.error_sender.clone(),
+            ready_tx,
+            done_rx,
+        );
 
         tokio::spawn({
             let ready = ready.clone();
@@ -343,6 +345,7 @@ impl Message<SetInput> for MicrophoneFeed {
                         let _ = actor
                             .tell(InputConnected {
                                 id,
+                                label: msg.label,
                                 config,
                                 done_tx,
                             })
@@ -359,6 +362,208 @@ impl Message<SetInput> for MicrophoneFeed {
     }
 }
 
+fn spawn_input_stream(
+    id: u32,
+    device: Device,
+    config: SupportedStreamConfig,
+    actor_ref: ActorRef<MicrophoneFeed>,
+    error_sender: flume::Sender<StreamError>,
+    ready_tx: oneshot::Sender<Result<(), SetInputError>>,
+    done_rx: mpsc::Receiver<()>,
+) {
+    let sample_format = config.sample_format();
+    let (sample_rate, channels) = (config.sample_rate().0, config.channels());
+
+    std::thread::spawn(move || {
+        let stream = match device.build_input_stream_raw(
+            &config.into(),
+            sample_format,
+            {
+                let actor_ref = actor_ref.clone();
+                move |data, info| {
+                    let _ = actor_ref
+                        .tell(MicrophoneSamples {
+                            data: data.bytes().to_vec(),
+                            format: data.sample_format(),
+                            info: info.clone(),
+                            input_id: id,
+                            sample_rate,
+                            channels,
+                        })
+                        .try_send();
+                }
+            },
+            move |e| {
+                error!("Microphone stream error: {e}");
+
+                let _ = error_sender.send(e).is_err();
+                let _ = actor_ref.tell(InputLost { id }).try_send();
+            },
+            None,
+        ) {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = ready_tx.send(Err(SetInputError::BuildStream(e.to_string())));
+                return;
+            }
+        };
+
+        if let Err(e) = stream.play() {
+            let _ = ready_tx.send(Err(SetInputError::PlayStream(e.to_string())));
+            return;
+        }
+
+        let _ = ready_tx.send(Ok(()));
+
+        match done_rx.recv() {
+            Ok(_) => {
+                info!("Microphone actor shut down, ending stream");
+            }
+            Err(_) => {
+                info!("Microphone actor unreachable, ending stream");
+            }
+        }
+    });
+}
+
+impl MicrophoneFeed {
+    fn emit_device_event(&self, event: DeviceEvent) {
+        for cb in &self.on_device_event {
+            (cb)(&event);
+        }
+    }
+}
+
+fn poll_for_device(label: String, actor_ref: ActorRef<MicrophoneFeed>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(REATTACH_POLL_INTERVAL).await;
+
+            if !actor_ref.is_alive() {
+                return;
+            }
+
+            if let Some((device, config)) = MicrophoneFeed::list().swap_remove(&label) {
+                let _ = actor_ref
+                    .tell(Reattach {
+                        label,
+                        device,
+                        config,
+                    })
+                    .await;
+                return;
+            }
+        }
+    });
+}
+
+impl Message<InputLost> for MicrophoneFeed {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: InputLost,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        trace!("MicrophoneFeed.InputLost");
+
+        let Some(attached) = self.state.attached_mut() else {
+            return;
+        };
+
+        if attached.id != msg.id || attached.lost {
+            return;
+        }
+
+        warn!("Microphone '{}' is unreachable", attached.label);
+
+        attached.lost = true;
+        let _ = attached.done_tx.try_send(());
+        let label = attached.label.clone();
+
+        self.emit_device_event(DeviceEvent::Unreachable(label.clone()));
+
+        poll_for_device(label, ctx.actor_ref());
+    }
+}
+
+impl Message<Reattach> for MicrophoneFeed {
+    type Reply = ();
+
+    async fn handle(&mut self, msg: Reattach, ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        trace!("MicrophoneFeed.Reattach('{}')", &msg.label);
+
+        let id = self.input_id_counter;
+
+        let Some(attached) = self.state.attached_mut() else {
+            return;
+        };
+
+        // The input was removed or changed while we were waiting
+        if !attached.lost || attached.label != msg.label {
+            return;
+        }
+
+        // Consumers of a locked feed assume the sample format doesn't change
+        if msg.config.sample_format() != attached.config.sample_format() {
+            warn!(
+                "Microphone '{}' came back with a different sample format, not reattaching",
+                &msg.label
+            );
+            return;
+        }
+
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let (done_tx, done_rx) = mpsc::sync_channel(0);
+
+        spawn_input_stream(
+            id,
+            msg.device,
+            msg.config.clone(),
+            ctx.actor_ref(),
+            self.error_sender.clone(),
+            ready_tx,
+            done_rx,
+        );
+        self.input_id_counter += 1;
+
+        match ready_rx.await {
+            Ok(Ok(())) => {}
+            result => {
+                if let Ok(Err(e)) = result {
+                    warn!("Failed to reattach microphone '{}': {e}", &msg.label);
+                }
+                poll_for_device(msg.label, ctx.actor_ref());
+                return;
+            }
+        }
+
+        // Checked above, and nothing else can run while we wait
+        let Some(attached) = self.state.attached_mut() else {
+            return;
+        };
+        attached.id = id;
+        attached.config = msg.config;
+        attached.done_tx = done_tx;
+        attached.lost = false;
+
+        info!("Microphone '{}' reattached", &msg.label);
+        self.emit_device_event(DeviceEvent::Reattached(msg.label));
+    }
+}
+
+impl Message<OnDeviceEvent> for MicrophoneFeed {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: OnDeviceEvent,
+        _: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.on_device_event.push(msg.0);
+    }
+}
+
 impl Message<RemoveInput> for MicrophoneFeed {
     type Reply = Result<(), FeedLockedError>;
 
@@ -385,14 +590,55 @@ impl Message<AddSender> for MicrophoneFeed {
     }
 }
 
-impl Message<MicrophoneSamples> for MicrophoneFeed {
-    type Reply = ();
+impl MicrophoneFeed {
+    fn resample(&mut self, samples: MicrophoneSamples) -> Vec<MicrophoneSamples> {
+        let mut out = Vec::with_capacity(2);
 
-    async fn handle(
-        &mut self,
-        msg: MicrophoneSamples,
-        _: &mut Context<Self, Self::Reply>,
-    ) -> Self::Reply {
+        if let Some(resampler) = &self.resampler
+            && !resampler.accepts(&samples)
+        {
+            let mut resampler = self.resampler.take().unwrap();
+
+            // Flush whatever the old resampler was holding on to so there's no gap at the boundary
+            debug!(
+                "Microphone input changed from {}Hz to {}Hz, reinitializing resampler",
+                resampler.input.1, samples.sample_rate
+            );
+            match resampler.flush() {
+                Ok(Some(tail)) => out.push(tail),
+                Ok(None) => {}
+                Err(e) => warn!("Failed to flush microphone resampler: {e}"),
+            }
+        }
+
+        if self.resampler.is_none() && SamplesResampler::is_required(&samples) {
+            info!(
+                "Resampling microphone input from {}Hz to {TARGET_SAMPLE_RATE}Hz",
+                samples.sample_rate
+            );
+
+            match SamplesResampler::new(&samples) {
+                Ok(resampler) => self.resampler = Some(resampler),
+                Err(e) => {
+                    error!("Failed to create microphone resampler: {e}");
+                    return out;
+                }
+            }
+        }
+
+        match &mut self.resampler {
+            Some(resampler) => match resampler.process(samples) {
+                Ok(Some(samples)) => out.push(samples),
+                Ok(None) => {}
+                Err(e) => warn!("Failed to resample microphone samples: {e}"),
+            },
+            None => out.push(samples),
+        }
+
+        out
+    }
+
+    fn send_samples(&mut self, msg: MicrophoneSamples) {
         let mut to_remove = vec![];
 
         for (i, sender) in self.senders.iter().enumerate() {
@@ -411,6 +657,115 @@ impl Message<MicrophoneSamples> for MicrophoneFeed {
     }
 }
 
+impl Message<MicrophoneSamples> for MicrophoneFeed {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: MicrophoneSamples,
+        _: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        for msg in self.resample(msg) {
+            self.send_samples(msg);
+        }
+    }
+}
+
+/// Converts interleaved samples to [`TARGET_SAMPLE_RATE`], downmixing to at most
+/// [`AudioInfo::MAX_AUDIO_CHANNELS`] along the way. The sample format is left as-is.
+struct SamplesResampler {
+    input: (SampleFormat, u32, u16),
+    context: resampling::Context,
+    last_info: InputCallbackInfo,
+    last_input_id: u32,
+}
+
+// The resampling context is only ever used from the feed actor
+unsafe impl Send for SamplesResampler {}
+
+impl SamplesResampler {
+    fn is_required(samples: &MicrophoneSamples) -> bool {
+        samples.sample_rate != TARGET_SAMPLE_RATE
+            || samples.channels > AudioInfo::MAX_AUDIO_CHANNELS
+    }
+
+    fn new(samples: &MicrophoneSamples) -> Result<Self, ffmpeg::Error> {
+        let format = ffmpeg_sample_format_for(samples.format)
+            .ok_or(ffmpeg::Error::InvalidData)?
+            .packed();
+        let output_channels = samples.channels.clamp(1, AudioInfo::MAX_AUDIO_CHANNELS);
+
+        let context = ffmpeg::software::resampler(
+            (
+                format,
+                ChannelLayout::default(samples.channels as i32),
+                samples.sample_rate,
+            ),
+            (
+                format,
+                ChannelLayout::default(output_channels as i32),
+                TARGET_SAMPLE_RATE,
+            ),
+        )?;
+
+        Ok(Self {
+            input: (samples.format, samples.sample_rate, samples.channels),
+            context,
+            last_info: samples.info.clone(),
+            last_input_id: samples.input_id,
+        })
+    }
+
+    fn accepts(&self, samples: &MicrophoneSamples) -> bool {
+        self.input == (samples.format, samples.sample_rate, samples.channels)
+    }
+
+    fn process(
+        &mut self,
+        samples: MicrophoneSamples,
+    ) -> Result<Option<MicrophoneSamples>, ffmpeg::Error> {
+        let input = self.context.input();
+        let sample_count =
+            samples.data.len() / (input.format.bytes() * input.channel_layout.channels() as usize);
+
+        let mut frame = frame::Audio::new(input.format, sample_count, input.channel_layout);
+        frame.set_rate(input.rate);
+        frame.data_mut(0)[..samples.data.len()].copy_from_slice(&samples.data);
+
+        let mut output = frame::Audio::empty();
+        self.context.run(&frame, &mut output)?;
+        self.last_info = samples.info;
+        self.last_input_id = samples.input_id;
+
+        Ok(self.wrap_output(&output))
+    }
+
+    fn flush(&mut self) -> Result<Option<MicrophoneSamples>, ffmpeg::Error> {
+        let mut output = frame::Audio::empty();
+        self.context.flush(&mut output)?;
+
+        Ok(self.wrap_output(&output))
+    }
+
+    fn wrap_output(&self, output: &frame::Audio) -> Option<MicrophoneSamples> {
+        if output.samples() == 0 {
+            return None;
+        }
+
+        let channels = self.context.output().channel_layout.channels() as u16;
+        let len = output.samples() * channels as usize * self.context.output().format.bytes();
+
+        Some(MicrophoneSamples {
+            data: output.data(0)[..len].to_vec(),
+            format: self.input.0,
+            info: self.last_info.clone(),
+            input_id: self.last_input_id,
+            sample_rate: TARGET_SAMPLE_RATE,
+            channels,
+        })
+    }
+}
+
 #[derive(Clone, Debug, thiserror::Error)]
 pub enum LockFeedError {
     #[error(transparent)]
@@ -453,7 +808,10 @@ impl Message<Lock> for MicrophoneFeed {
         });
 
         Ok(MicrophoneFeedLock {
-            audio_info: AudioInfo::from_stream_config(&config),
+            audio_info: AudioInfo {
+                sample_rate: TARGET_SAMPLE_RATE,
+                ..AudioInfo::from_stream_config(&config)
+            },
             actor: ctx.actor_ref(),
             config,
             drop_tx: Some(drop_tx),
@@ -1,2 +1,28 @@
+use std::time::Duration;
+
+use cap_media::MediaError;
+
 pub mod camera;
 pub mod microphone;
+
+/// How often a feed checks whether its lost device has come back.
+const REATTACH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Reported by a feed when its device disappears or comes back while it's attached.
+/// The feed keeps running in between, so a recording using it can carry on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Unreachable(String),
+    Reattached(String),
+}
+
+impl DeviceEvent {
+    pub fn to_error(&self) -> Option<MediaError> {
+        match self {
+            Self::Unreachable(name) => Some(MediaError::DeviceUnreachable(name.clone())),
+            Self::Reattached(_) => None,
+        }
+    }
+}
+
+pub struct OnDeviceEvent(pub Box<dyn Fn(&DeviceEvent) + Send>);
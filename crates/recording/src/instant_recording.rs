@@ -1,5 +1,5 @@
 use cap_media::MediaError;
-use cap_media_info::{AudioInfo, VideoInfo};
+use cap_media_info::VideoInfo;
 use cap_project::InstantRecordingMeta;
 use cap_utils::{ensure_dir, spawn_actor};
 use flume::Receiver;
@@ -13,10 +13,12 @@ use tracing::{Instrument, debug, error, info, trace};
 
 use crate::{
     ActorError, RecordingBaseInputs, RecordingError,
-    capture_pipeline::{MakeCapturePipeline, create_screen_capture},
+    capture_pipeline::{MakeCapturePipeline, TimelapseDecimator, create_screen_capture},
+    clock::CaptureClock,
     feeds::microphone::MicrophoneFeedLock,
     pipeline::Pipeline,
     sources::{ScreenCaptureSource, ScreenCaptureTarget},
+    stats::{RecordingStats, RecordingStatsTracker},
 };
 
 struct InstantRecordingPipeline {
@@ -42,6 +44,7 @@ enum InstantRecordingActorState {
 #[derive(Clone)]
 pub struct InstantRecordingHandle {
     ctrl_tx: flume::Sender<InstantRecordingActorControlMessage>,
+    stats: Arc<RecordingStatsTracker>,
     pub capture_target: ScreenCaptureTarget,
     // pub bounds: Bounds,
 }
@@ -73,6 +76,11 @@ impl InstantRecordingHandle {
     pub async fn cancel(&self) -> Result<(), RecordingError> {
         send_message!(self.ctrl_tx, InstantRecordingActorControlMessage::Cancel)
     }
+
+    /// Can be called at any time, including while the recording is paused.
+    pub fn stats(&self) -> RecordingStats {
+        self.stats.snapshot()
+    }
 }
 
 pub enum InstantRecordingActorControlMessage {
@@ -108,6 +116,7 @@ pub struct CompletedInstantRecording {
 }
 
 #[tracing::instrument(skip_all, name = "instant")]
+#[allow(clippy::too_many_arguments)]
 async fn create_pipeline<TCaptureFormat: MakeCapturePipeline>(
     output_path: PathBuf,
     screen_source: (
@@ -115,7 +124,12 @@ async fn create_pipeline<TCaptureFormat: MakeCapturePipeline>(
         flume::Receiver<(TCaptureFormat::VideoFormat, f64)>,
     ),
     mic_feed: Option<Arc<MicrophoneFeedLock>>,
+    mic_wav_path: Option<PathBuf>,
     system_audio: Option<Receiver<(ffmpeg::frame::Audio, f64)>>,
+    timelapse: Option<TimelapseDecimator>,
+    live_output: Option<flume::Sender<Vec<u8>>>,
+    threaded_encoding: bool,
+    stats: Arc<RecordingStatsTracker>,
 ) -> Result<
     (
         InstantRecordingPipeline,
@@ -125,22 +139,29 @@ async fn create_pipeline<TCaptureFormat: MakeCapturePipeline>(
 > {
     if let Some(mic_feed) = &mic_feed {
         debug!(
-            "mic audio info: {:#?}",
-            AudioInfo::from_stream_config(mic_feed.config())
+            "mic audio info: {:#?} (native rate {}Hz)",
+            mic_feed.audio_info(),
+            mic_feed.native_sample_rate()
         );
     };
 
     let pipeline_builder = Pipeline::builder();
 
     let pause_flag = Arc::new(AtomicBool::new(false));
+    stats.track_dropped_frames(screen_source.0.dropped_frames_counter());
     let system_audio = system_audio.map(|v| (v, screen_source.0.audio_info()));
     let pipeline_builder = TCaptureFormat::make_instant_mode_pipeline(
         pipeline_builder,
         screen_source,
         mic_feed,
+        mic_wav_path,
         system_audio,
         output_path.clone(),
         pause_flag.clone(),
+        timelapse,
+        live_output,
+        threaded_encoding,
+        stats,
     )
     .await?;
 
@@ -168,10 +189,74 @@ pub async fn spawn_instant_recording_actor(
         tokio::sync::oneshot::Receiver<Result<(), String>>,
     ),
     RecordingError,
+> {
+    spawn_actor_with_timelapse(id, recording_dir, inputs, None, None).await
+}
+
+/// Records an instant recording that keeps one frame every `interval` seconds.
+/// No audio is recorded, as it wouldn't line up with the sped up video.
+pub async fn spawn_timelapse_recording_actor(
+    id: String,
+    recording_dir: PathBuf,
+    mut inputs: RecordingBaseInputs,
+    interval: f64,
+) -> Result<
+    (
+        InstantRecordingHandle,
+        tokio::sync::oneshot::Receiver<Result<(), String>>,
+    ),
+    RecordingError,
+> {
+    inputs.capture_system_audio = false;
+    inputs.mic_feed = None;
+
+    spawn_actor_with_timelapse(id, recording_dir, inputs, Some(interval), None).await
+}
+
+/// Records an instant recording as fragmented mp4, returning the bytes as they're
+/// written so they can be uploaded while the recording continues.
+/// The stream ends once the recording stops, and concatenating every chunk gives the
+/// same streamable file as the one written to disk.
+///
+/// Only supported on Windows. On macOS this fails, as the AVFoundation encoder can't
+/// stream what it writes.
+pub async fn spawn_live_instant_recording_actor(
+    id: String,
+    recording_dir: PathBuf,
+    inputs: RecordingBaseInputs,
+) -> Result<
+    (
+        InstantRecordingHandle,
+        tokio::sync::oneshot::Receiver<Result<(), String>>,
+        flume::r#async::RecvStream<'static, Vec<u8>>,
+    ),
+    RecordingError,
+> {
+    let (live_tx, live_rx) = flume::unbounded();
+
+    let (handle, done_rx) =
+        spawn_actor_with_timelapse(id, recording_dir, inputs, None, Some(live_tx)).await?;
+
+    Ok((handle, done_rx, live_rx.into_stream()))
+}
+
+async fn spawn_actor_with_timelapse(
+    id: String,
+    recording_dir: PathBuf,
+    inputs: RecordingBaseInputs,
+    timelapse_interval: Option<f64>,
+    live_output: Option<flume::Sender<Vec<u8>>>,
+) -> Result<
+    (
+        InstantRecordingHandle,
+        tokio::sync::oneshot::Receiver<Result<(), String>>,
+    ),
+    RecordingError,
 > {
     ensure_dir(&recording_dir)?;
 
-    let start_time = SystemTime::now();
+    let clock = CaptureClock::new();
+    let stats = Arc::new(RecordingStatsTracker::new(clock));
 
     let (done_tx, done_rx) = oneshot::channel();
 
@@ -195,7 +280,7 @@ pub async fn spawn_instant_recording_actor(
         true,
         30,
         system_audio.0,
-        start_time,
+        clock,
         #[cfg(windows)]
         d3d_device,
     )
@@ -203,11 +288,21 @@ pub async fn spawn_instant_recording_actor(
 
     debug!("screen capture: {screen_source:#?}");
 
+    let timelapse = timelapse_interval
+        .map(|interval| TimelapseDecimator::new(interval, screen_source.info().fps()));
+
     let (pipeline, pipeline_done_rx) = create_pipeline(
         content_dir.join("output.mp4"),
         (screen_source.clone(), screen_rx.clone()),
         inputs.mic_feed.clone(),
+        inputs
+            .mic_wav_sidecar
+            .then(|| content_dir.join("microphone.wav")),
         system_audio.1,
+        timelapse,
+        live_output,
+        inputs.threaded_encoding,
+        stats.clone(),
     )
     .await?;
 
@@ -255,6 +350,7 @@ pub async fn spawn_instant_recording_actor(
     Ok((
         InstantRecordingHandle {
             ctrl_tx,
+            stats,
             capture_target: inputs.capture_target,
             // bounds: *screen_source.get_bounds(),
         },
@@ -1,17 +1,23 @@
 mod capture_pipeline;
+pub mod clock;
 pub mod cursor;
 pub mod feeds;
 pub mod instant_recording;
 pub mod pipeline;
 pub mod sources;
+pub mod stats;
 pub mod studio_recording;
 
+pub use clock::CaptureClock;
 pub use instant_recording::{
     CompletedInstantRecording, InstantRecordingActor, spawn_instant_recording_actor,
+    spawn_live_instant_recording_actor, spawn_timelapse_recording_actor,
 };
 pub use sources::{camera, screen_capture};
+pub use stats::RecordingStats;
 pub use studio_recording::{
-    CompletedStudioRecording, StudioRecordingHandle, spawn_studio_recording_actor,
+    CompletedStudioRecording, SegmentRollover, StudioRecordingHandle, append_to_studio_recording,
+    spawn_studio_recording_actor,
 };
 
 use cap_media::MediaError;
@@ -19,7 +25,7 @@ use feeds::microphone::MicrophoneFeedLock;
 use scap_targets::bounds::LogicalBounds;
 use serde::{Deserialize, Serialize};
 use sources::*;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use thiserror::Error;
 
 use crate::feeds::camera::CameraFeedLock;
@@ -29,6 +35,11 @@ use crate::feeds::camera::CameraFeedLock;
 pub enum RecordingMode {
     Studio,
     Instant,
+    /// An instant recording that keeps one screen frame every `interval` seconds
+    /// and plays them back at the capture frame rate, without audio.
+    Timelapse {
+        interval: f64,
+    },
 }
 
 #[derive(specta::Type, Serialize, Deserialize, Clone, Debug)]
@@ -43,12 +54,35 @@ pub struct RecordingOptions {
     pub mode: RecordingMode,
 }
 
+/// What a recording does when one of its encoders fails.
+#[derive(specta::Type, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum EncoderErrorPolicy {
+    /// Stop the recording with the error.
+    #[default]
+    Fail,
+    /// Finish the current segment and continue recording in a new one, at most
+    /// `max_rollovers` times. Only studio recordings can roll over, as instant
+    /// recordings are a single file.
+    Rollover { max_rollovers: u32 },
+}
+
 #[derive(Clone)]
 pub struct RecordingBaseInputs {
     pub capture_target: ScreenCaptureTarget,
     pub capture_system_audio: bool,
     pub mic_feed: Option<Arc<MicrophoneFeedLock>>,
     pub camera_feed: Option<Arc<CameraFeedLock>>,
+    pub encoder_error_policy: EncoderErrorPolicy,
+    /// Start a new segment of a studio recording after this long, so each segment's files
+    /// are finalized as the recording goes and a crash loses at most one of them.
+    pub segment_duration: Option<Duration>,
+    /// Also write the microphone to an uncompressed WAV next to an instant recording's
+    /// mp4, for mixing in other software. Studio recordings already keep it separate.
+    pub mic_wav_sidecar: bool,
+    /// Encode a recording's audio and video on separate threads that are kept in step,
+    /// so a slow video encode can't hold up audio until samples are dropped.
+    pub threaded_encoding: bool,
 }
 
 #[derive(specta::Type, Serialize, Deserialize, Clone, Debug)]
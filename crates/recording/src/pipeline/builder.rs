@@ -8,24 +8,124 @@ use tokio::sync::oneshot;
 use tracing::{error, info};
 
 use crate::pipeline::{
-    MediaError, Pipeline,
+    MediaError, Pipeline, PipelineState,
     control::ControlBroadcast,
     task::{PipelineReadySignal, PipelineSourceTask},
 };
 
+/// The role a task plays in the pipeline, used to check that a pipeline's stages
+/// can actually be connected before it's built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineStage {
+    Source,
+    Filter,
+    Encoder,
+    Muxer,
+}
+
 struct Task {
     ready_signal: Receiver<Result<(), MediaError>>,
     join_handle: JoinHandle<()>,
     done_rx: tokio::sync::oneshot::Receiver<Result<(), String>>,
 }
 
+/// Tasks are spawned as they're added, and [`build`](Self::build) waits for all of them to
+/// signal that they're ready.
+///
+/// Stages added with `add_source`, `add_filter`, `add_encoder` and `add_muxer` are checked
+/// to be in a workable order before they're spawned: filters and encoders need a source
+/// before them, and muxers need an encoder. Tasks added with `spawn_task` aren't checked.
+///
+/// ```no_run
+/// # use cap_recording::pipeline::{Pipeline, task::PipelineSourceTask};
+/// # async fn example(
+/// #     demuxer: impl PipelineSourceTask + 'static,
+/// #     packets: flume::Receiver<ffmpeg::Packet>,
+/// #     mut decoder: ffmpeg::decoder::Video,
+/// #     mut encoder: cap_enc_ffmpeg::H264Encoder,
+/// #     mut output: ffmpeg::format::context::Output,
+/// # ) -> Result<(), cap_media::MediaError> {
+/// let (frame_tx, frame_rx) = flume::bounded(8);
+/// let mut builder = Pipeline::builder();
+///
+/// builder.add_source("demuxer", demuxer);
+/// builder.add_filter("decoder", move |ready| {
+///     let _ = ready.send(Ok(()));
+///     let mut frame = ffmpeg::frame::Video::empty();
+///     for packet in packets {
+///         decoder.send_packet(&packet).map_err(|e| e.to_string())?;
+///         while decoder.receive_frame(&mut frame).is_ok() {
+///             let _ = frame_tx.send(frame.clone());
+///         }
+///     }
+///     Ok(())
+/// })?;
+/// builder.add_encoder("encoder", move |ready| {
+///     let _ = ready.send(Ok(()));
+///     for frame in frame_rx {
+///         encoder.queue_frame(frame, &mut output);
+///     }
+///     encoder.finish(&mut output);
+///     Ok(())
+/// })?;
+///
+/// let (mut pipeline, _done_rx) = builder.build().await?;
+/// pipeline.play().await?;
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Default)]
 pub struct PipelineBuilder {
     control: ControlBroadcast,
     tasks: IndexMap<String, Task>,
+    stages: Vec<PipelineStage>,
 }
 
 impl PipelineBuilder {
+    pub fn add_source(&mut self, name: impl Into<String>, task: impl PipelineSourceTask + 'static) {
+        self.stages.push(PipelineStage::Source);
+        self.spawn_source(name, task);
+    }
+
+    pub fn add_filter(
+        &mut self,
+        name: impl Into<String>,
+        launch: impl FnOnce(PipelineReadySignal) -> Result<(), String> + Send + 'static,
+    ) -> Result<(), MediaError> {
+        self.add_stage(name.into(), PipelineStage::Filter, launch)
+    }
+
+    pub fn add_encoder(
+        &mut self,
+        name: impl Into<String>,
+        launch: impl FnOnce(PipelineReadySignal) -> Result<(), String> + Send + 'static,
+    ) -> Result<(), MediaError> {
+        self.add_stage(name.into(), PipelineStage::Encoder, launch)
+    }
+
+    pub fn add_muxer(
+        &mut self,
+        name: impl Into<String>,
+        launch: impl FnOnce(PipelineReadySignal) -> Result<(), String> + Send + 'static,
+    ) -> Result<(), MediaError> {
+        self.add_stage(name.into(), PipelineStage::Muxer, launch)
+    }
+
+    /// Fails without spawning anything if the stage can't follow the ones added so far.
+    fn add_stage(
+        &mut self,
+        name: String,
+        stage: PipelineStage,
+        launch: impl FnOnce(PipelineReadySignal) -> Result<(), String> + Send + 'static,
+    ) -> Result<(), MediaError> {
+        validate_stage(&self.stages, &name, stage)?;
+
+        self.stages.push(stage);
+        self.spawn_task(name, launch);
+
+        Ok(())
+    }
+
     pub fn spawn_source(
         &mut self,
         name: impl Into<String>,
@@ -108,7 +208,7 @@ impl PipelineBuilder {
     pub async fn build(
         self,
     ) -> Result<(Pipeline, oneshot::Receiver<Result<(), String>>), MediaError> {
-        let Self { control, tasks } = self;
+        let Self { control, tasks, .. } = self;
 
         if tasks.is_empty() {
             return Err(MediaError::EmptyPipeline);
@@ -157,14 +257,85 @@ impl PipelineBuilder {
             Pipeline {
                 control,
                 task_handles,
-                is_shutdown: false,
+                state: PipelineState::Running,
             },
             done_rx,
         ))
     }
 }
 
+/// Checks that `stage` can be added after `previous`.
+fn validate_stage(
+    previous: &[PipelineStage],
+    name: &str,
+    stage: PipelineStage,
+) -> Result<(), MediaError> {
+    match stage {
+        PipelineStage::Filter | PipelineStage::Encoder
+            if !previous.contains(&PipelineStage::Source) =>
+        {
+            Err(MediaError::Any(
+                format!("{stage:?} '{name}' must be added after a source").into(),
+            ))
+        }
+        PipelineStage::Muxer if !previous.contains(&PipelineStage::Encoder) => Err(
+            MediaError::Any(format!("Muxer '{name}' must be added after an encoder").into()),
+        ),
+        _ => Ok(()),
+    }
+}
+
 // pub struct PipelinePathBuilder<Clock, PreviousOutput: Send> {
 //     pipeline: PipelineBuilder<Clock>,
 //     next_input: Receiver<PreviousOutput>,
 // }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::control::PipelineControlSignal;
+
+    struct ReadySource;
+
+    impl PipelineSourceTask for ReadySource {
+        fn run(
+            &mut self,
+            ready_signal: PipelineReadySignal,
+            _: PipelineControlSignal,
+        ) -> Result<(), String> {
+            let _ = ready_signal.send(Ok(()));
+            Ok(())
+        }
+    }
+
+    fn ready(ready_signal: PipelineReadySignal) -> Result<(), String> {
+        let _ = ready_signal.send(Ok(()));
+        Ok(())
+    }
+
+    #[test]
+    fn accepts_source_filter_encoder_muxer() {
+        let mut builder = PipelineBuilder::default();
+
+        builder.add_source("source", ReadySource);
+        builder.add_filter("filter", ready).unwrap();
+        builder.add_encoder("encoder", ready).unwrap();
+        builder.add_muxer("muxer", ready).unwrap();
+
+        assert_eq!(builder.tasks.len(), 4);
+    }
+
+    #[test]
+    fn rejects_stages_out_of_order_without_spawning_them() {
+        let mut builder = PipelineBuilder::default();
+
+        assert!(builder.add_filter("filter", ready).is_err());
+        assert!(builder.add_encoder("encoder", ready).is_err());
+
+        builder.add_source("source", ReadySource);
+        assert!(builder.add_muxer("muxer", ready).is_err());
+
+        assert_eq!(builder.tasks.keys().collect::<Vec<_>>(), ["source"]);
+        assert_eq!(builder.stages, [PipelineStage::Source]);
+    }
+}
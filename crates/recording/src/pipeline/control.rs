@@ -1,10 +1,16 @@
 use flume::{Receiver, Sender, TryRecvError};
 use indexmap::IndexMap;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use tracing::debug;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Control {
     Play,
+    /// Sources stop pulling frames until the next [`Control::Play`].
+    Pause,
     Shutdown,
 }
 
@@ -57,6 +63,45 @@ impl PipelineControlSignal {
     }
 }
 
+/// Accumulates the time a source has spent paused, so that frames captured after resuming
+/// can have their timestamps shifted back to continue where the source left off.
+#[derive(Debug, Default, Clone)]
+pub struct PauseClock {
+    inner: Arc<Mutex<PauseClockInner>>,
+}
+
+#[derive(Debug, Default)]
+struct PauseClockInner {
+    paused_at: Option<Instant>,
+    total: Duration,
+}
+
+impl PauseClock {
+    pub fn pause(&self) {
+        self.inner
+            .lock()
+            .unwrap()
+            .paused_at
+            .get_or_insert_with(Instant::now);
+    }
+
+    pub fn resume(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(paused_at) = inner.paused_at.take() {
+            inner.total += paused_at.elapsed();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.inner.lock().unwrap().paused_at.is_some()
+    }
+
+    /// Total time spent paused, excluding a pause that is still in progress.
+    pub fn paused_duration(&self) -> Duration {
+        self.inner.lock().unwrap().total
+    }
+}
+
 /// An extremely naive broadcast channel. Sends values synchronously to all receivers,
 /// might block if one receiver takes too long to receive value.
 #[derive(Debug, Default, Clone)]
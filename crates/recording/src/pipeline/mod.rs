@@ -12,10 +12,17 @@ use crate::MediaError;
 use builder::PipelineBuilder;
 use control::{Control, ControlBroadcast, PipelineControlSignal};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineState {
+    Running,
+    Paused,
+    ShutDown,
+}
+
 pub struct Pipeline {
     control: ControlBroadcast,
     task_handles: IndexMap<String, JoinHandle<()>>,
-    is_shutdown: bool,
+    state: PipelineState,
 }
 
 impl Pipeline {
@@ -23,20 +30,56 @@ impl Pipeline {
         PipelineBuilder::default()
     }
 
+    pub fn state(&self) -> PipelineState {
+        self.state
+    }
+
     pub async fn play(&mut self) -> Result<(), MediaError> {
-        if self.is_shutdown {
+        if self.state == PipelineState::ShutDown {
             return Err(MediaError::ShutdownPipeline);
         };
 
         self.control.broadcast(Control::Play).await;
+        self.state = PipelineState::Running;
+
+        Ok(())
+    }
+
+    /// Stops sources from pulling new frames until [`Pipeline::resume`] is called.
+    /// Timestamps of frames captured after resuming exclude the time spent paused.
+    pub async fn pause(&mut self) -> Result<(), MediaError> {
+        match self.state {
+            PipelineState::ShutDown => return Err(MediaError::ShutdownPipeline),
+            PipelineState::Paused => return Ok(()),
+            PipelineState::Running => {}
+        }
+
+        trace!("Pausing pipeline");
+        self.control.broadcast(Control::Pause).await;
+        self.state = PipelineState::Paused;
+
+        Ok(())
+    }
+
+    pub async fn resume(&mut self) -> Result<(), MediaError> {
+        match self.state {
+            PipelineState::ShutDown => return Err(MediaError::ShutdownPipeline),
+            PipelineState::Running => return Ok(()),
+            PipelineState::Paused => {}
+        }
+
+        trace!("Resuming pipeline");
+        self.control.broadcast(Control::Play).await;
+        self.state = PipelineState::Running;
 
         Ok(())
     }
 
     pub async fn shutdown(&mut self) -> Result<(), MediaError> {
-        if self.is_shutdown {
+        if self.state == PipelineState::ShutDown {
             return Err(MediaError::ShutdownPipeline);
         };
+        self.state = PipelineState::ShutDown;
 
         trace!("Shutting down pipeline");
         self.control.broadcast(Control::Shutdown).await;
@@ -1,78 +1,152 @@
 use crate::{
+    clock::CaptureClock,
     feeds::microphone::{self, MicrophoneFeedLock, MicrophoneSamples},
-    pipeline::{control::Control, task::PipelineSourceTask},
+    pipeline::{
+        control::{Control, PauseClock},
+        task::PipelineSourceTask,
+    },
 };
 use cap_fail::fail;
-use cap_media::MediaError;
+use cap_media::{MediaError, filters::SampleFormatConverter};
 use cap_media_info::AudioInfo;
 use cpal::{Device, StreamInstant, SupportedStreamConfig};
-use ffmpeg::{frame::Audio as FFAudio, sys::AV_TIME_BASE_Q};
-use flume::{Receiver, Sender};
+use ffmpeg::{format::Sample, frame::Audio as FFAudio, sys::AV_TIME_BASE_Q};
+use flume::{Receiver, RecvTimeoutError, Sender};
 use indexmap::IndexMap;
-use std::{sync::Arc, time::SystemTime};
-use tracing::{error, info};
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info, warn};
 
 pub type AudioInputDeviceMap = IndexMap<String, (Device, SupportedStreamConfig)>;
 
+/// Gaps in the input longer than this (e.g. while a device is reattached) are filled with silence.
+const MAX_GAP: Duration = Duration::from_millis(100);
+const SILENCE_CHUNK_SAMPLES: usize = 1024;
+
 pub struct AudioInputSource {
     feed: Arc<MicrophoneFeedLock>,
     audio_info: AudioInfo,
+    converter: Option<SampleFormatConverter>,
     tx: Sender<(FFAudio, f64)>,
-    start_timestamp: Option<(StreamInstant, SystemTime)>,
-    start_time: f64,
+    clock: CaptureClock,
+    stream_anchor: Option<StreamAnchor>,
+    next_elapsed: Option<Duration>,
+    pause_clock: PauseClock,
+}
+
+/// Maps a stream's capture times onto the capture clock.
+#[derive(Clone, Copy)]
+struct StreamAnchor {
+    input_id: u32,
+    capture: StreamInstant,
+    elapsed: Duration,
 }
 
 impl AudioInputSource {
     pub fn init(
         feed: Arc<MicrophoneFeedLock>,
         tx: Sender<(FFAudio, f64)>,
-        start_time: SystemTime,
+        clock: CaptureClock,
     ) -> Self {
         Self {
             audio_info: *feed.audio_info(),
+            converter: None,
             feed,
             tx,
-            start_timestamp: None,
-            start_time: start_time
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_secs_f64(),
+            clock,
+            stream_anchor: None,
+            next_elapsed: None,
+            pause_clock: PauseClock::default(),
         }
     }
 
+    /// Sends samples in `format` rather than the one the device captures in, so whatever
+    /// receives them doesn't have to convert them itself.
+    pub fn with_sample_format(mut self, format: Sample) -> Self {
+        let native = self.native_sample_format();
+        self.converter = (format != native).then(|| SampleFormatConverter::new(format));
+        self.audio_info.sample_format = format;
+
+        info!("Microphone samples captured as {native:?}, sent as {format:?}");
+
+        self
+    }
+
     pub fn info(&self) -> AudioInfo {
         self.audio_info
     }
 
+    /// The format the device captures samples in, which `info` differs from if
+    /// they're converted.
+    pub fn native_sample_format(&self) -> Sample {
+        self.feed.audio_info().sample_format
+    }
+
     fn process_frame(&mut self, samples: MicrophoneSamples) -> Result<(), MediaError> {
-        let start_timestamp = match self.start_timestamp {
-            None => *self
-                .start_timestamp
-                .insert((samples.info.timestamp().capture, SystemTime::now())),
-            Some(v) => v,
+        let capture = samples.info.timestamp().capture;
+
+        let anchor = match self.stream_anchor {
+            Some(anchor) if anchor.input_id == samples.input_id => anchor,
+            // Capture times from a new stream can't be compared with the old one's,
+            // so line it up using the capture clock instead
+            _ => *self.stream_anchor.insert(StreamAnchor {
+                input_id: samples.input_id,
+                capture,
+                elapsed: self.clock.elapsed(),
+            }),
         };
 
-        let elapsed = samples
-            .info
-            .timestamp()
-            .capture
-            .duration_since(&start_timestamp.0)
-            .unwrap();
-
-        let timestamp = start_timestamp
-            .1
-            .checked_add(elapsed)
-            .unwrap()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs_f64()
-            - self.start_time;
-
-        let frame = self.audio_info.wrap_frame(
-            &samples.data,
-            (elapsed.as_secs_f64() * AV_TIME_BASE_Q.den as f64) as i64,
-        );
-        if self.tx.send((frame, timestamp)).is_err() {
+        let elapsed = (anchor.elapsed
+            + capture.duration_since(&anchor.capture).unwrap_or_default())
+        .saturating_sub(self.pause_clock.paused_duration());
+
+        if let Some(expected) = self.next_elapsed
+            && elapsed > expected + MAX_GAP
+        {
+            warn!(
+                "Filling {:?} gap in microphone input with silence",
+                elapsed - expected
+            );
+            self.send_silence(expected, elapsed)?;
+        }
+
+        let frame = self
+            .feed
+            .audio_info()
+            .wrap_frame(&samples.data, Self::pts(elapsed));
+        let frame = match &self.converter {
+            Some(converter) => converter.apply(frame)?,
+            None => frame,
+        };
+        self.next_elapsed = Some(elapsed + self.frame_duration(&frame));
+
+        self.send(frame, elapsed)
+    }
+
+    fn send_silence(&self, from: Duration, to: Duration) -> Result<(), MediaError> {
+        let mut elapsed = from;
+
+        while elapsed < to {
+            let remaining =
+                ((to - elapsed).as_secs_f64() * self.audio_info.sample_rate as f64).ceil() as usize;
+
+            let mut frame = self
+                .audio_info
+                .empty_frame(remaining.min(SILENCE_CHUNK_SAMPLES));
+            for plane in 0..frame.planes() {
+                frame.data_mut(plane).fill(0);
+            }
+            frame.set_pts(Some(Self::pts(elapsed)));
+
+            let duration = self.frame_duration(&frame);
+            self.send(frame, elapsed)?;
+            elapsed += duration;
+        }
+
+        Ok(())
+    }
+
+    fn send(&self, frame: FFAudio, elapsed: Duration) -> Result<(), MediaError> {
+        if self.tx.send((frame, elapsed.as_secs_f64())).is_err() {
             return Err(MediaError::Any(
                 "Pipeline is unreachable! Stopping capture".into(),
             ));
@@ -81,6 +155,14 @@ impl AudioInputSource {
         Ok(())
     }
 
+    fn frame_duration(&self, frame: &FFAudio) -> Duration {
+        Duration::from_secs_f64(frame.samples() as f64 / self.audio_info.sample_rate as f64)
+    }
+
+    fn pts(elapsed: Duration) -> i64 {
+        (elapsed.as_secs_f64() * AV_TIME_BASE_Q.den as f64) as i64
+    }
+
     fn pause_and_drain_frames(&mut self, frames_rx: Receiver<MicrophoneSamples>) {
         let frames: Vec<MicrophoneSamples> = frames_rx.drain().collect();
 
@@ -109,25 +191,36 @@ impl PipelineSourceTask for AudioInputSource {
         let res = loop {
             match control_signal.last() {
                 Some(Control::Play) => {
+                    self.pause_clock.resume();
+
                     let samples = samples_rx.get_or_insert_with(|| {
                         let (tx, rx) = flume::bounded(5);
                         let _ = self.feed.ask(microphone::AddSender(tx)).blocking_send();
                         rx
                     });
 
-                    match samples.recv() {
+                    // Don't block indefinitely, the device may be gone while the feed reattaches it
+                    match samples.recv_timeout(Duration::from_millis(100)) {
                         Ok(samples) => {
                             if let Err(error) = self.process_frame(samples) {
                                 error!("{error}");
                                 break Err(error.to_string());
                             }
                         }
-                        Err(_) => {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => {
                             error!("Lost connection with the camera feed");
                             break Err("Lost connection with the camera feed".to_string());
                         }
                     }
                 }
+                Some(Control::Pause) => {
+                    // Dropping the receiver detaches us from the feed until we resume
+                    if let Some(rx) = samples_rx.take() {
+                        self.pause_and_drain_frames(rx);
+                    }
+                    self.pause_clock.pause();
+                }
                 Some(Control::Shutdown) | None => {
                     if let Some(rx) = samples_rx.take() {
                         self.pause_and_drain_frames(rx);
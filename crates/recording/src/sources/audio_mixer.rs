@@ -1,14 +1,28 @@
 use cap_media_info::AudioInfo;
 use ffmpeg::sys::AV_TIME_BASE_Q;
 use flume::{Receiver, Sender};
-use std::time::Duration;
+use std::{
+    ffi::CString,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    time::Duration,
+};
 use tracing::{debug, warn};
 
 use crate::pipeline::task::PipelineSourceTask;
 
+/// Mixes any number of audio sources into a single 48kHz stereo stream.
+///
+/// Each source has its own gain and mute toggle, see [`AudioMixerInput`].
+/// Sources are summed without being scaled down by the number of inputs, with a limiter on
+/// the output to keep the sum from clipping. `amix` holds on to each input until every
+/// other input has caught up, which absorbs small differences in latency between sources.
 pub struct AudioMixer {
     sources: Vec<AudioMixerSource>,
     output: Sender<ffmpeg::frame::Audio>,
+    limit_output: bool,
 }
 
 impl AudioMixer {
@@ -16,19 +30,37 @@ impl AudioMixer {
         Self {
             sources: Vec::new(),
             output,
+            limit_output: true,
         }
     }
 
+    /// Enabled by default.
+    pub fn set_limit_output(&mut self, limit_output: bool) {
+        self.limit_output = limit_output;
+    }
+
     pub fn sink(&mut self, info: AudioInfo) -> AudioMixerSink {
         let (tx, rx) = flume::bounded(32);
 
-        self.sources.push(AudioMixerSource { rx, info });
+        let input = self.add_source(info, rx);
 
-        AudioMixerSink { tx }
+        AudioMixerSink { tx, input }
     }
 
-    pub fn add_source(&mut self, info: AudioInfo, rx: Receiver<(ffmpeg::frame::Audio, f64)>) {
-        self.sources.push(AudioMixerSource { rx, info })
+    pub fn add_source(
+        &mut self,
+        info: AudioInfo,
+        rx: Receiver<(ffmpeg::frame::Audio, f64)>,
+    ) -> AudioMixerInput {
+        let input = AudioMixerInput::default();
+
+        self.sources.push(AudioMixerSource {
+            rx,
+            info,
+            input: input.clone(),
+        });
+
+        input
     }
 
     pub fn has_sources(&self) -> bool {
@@ -44,6 +76,37 @@ impl AudioMixer {
         .unwrap()
     }
 
+    fn volume_filter_name(index: usize) -> String {
+        format!("volume{index}")
+    }
+
+    fn set_volume(
+        filter_graph: &mut ffmpeg::filter::Graph,
+        index: usize,
+        gain: f32,
+    ) -> Result<(), ffmpeg::Error> {
+        let target = CString::new(Self::volume_filter_name(index)).unwrap();
+        let value = CString::new(gain.to_string()).unwrap();
+
+        let ret = unsafe {
+            ffmpeg::sys::avfilter_graph_send_command(
+                filter_graph.as_mut_ptr(),
+                target.as_ptr(),
+                c"volume".as_ptr(),
+                value.as_ptr(),
+                std::ptr::null_mut(),
+                0,
+                0,
+            )
+        };
+
+        if ret < 0 {
+            return Err(ffmpeg::Error::from(ret));
+        }
+
+        Ok(())
+    }
+
     pub fn run(&mut self, mut get_is_stopped: impl FnMut() -> bool, on_ready: impl FnOnce()) {
         let mut filter_graph = ffmpeg::filter::Graph::new();
 
@@ -73,17 +136,48 @@ impl AudioMixer {
             })
             .collect::<Vec<_>>();
 
+        let mut volumes = self
+            .sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| {
+                filter_graph
+                    .add(
+                        &ffmpeg::filter::find("volume").expect("Failed to find volume filter"),
+                        &Self::volume_filter_name(i),
+                        &format!("volume={}", source.input.effective_gain()),
+                    )
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let mut applied_gains = self
+            .sources
+            .iter()
+            .map(|source| source.input.effective_gain())
+            .collect::<Vec<_>>();
+
         let mut amix = filter_graph
             .add(
                 &ffmpeg::filter::find("amix").expect("Failed to find amix filter"),
                 "amix",
                 &format!(
-                    "inputs={}:duration=first:dropout_transition=0",
+                    "inputs={}:duration=first:dropout_transition=0:normalize=0",
                     abuffers.len()
                 ),
             )
             .unwrap();
 
+        let mut alimiter = self.limit_output.then(|| {
+            filter_graph
+                .add(
+                    &ffmpeg::filter::find("alimiter").expect("Failed to find alimiter filter"),
+                    "alimiter",
+                    "limit=0.98:level=false",
+                )
+                .expect("Failed to add alimiter filter")
+        });
+
         let aformat_args = "sample_fmts=flt:sample_rates=48000:channel_layouts=stereo";
         debug!("aformat args: {aformat_args}");
 
@@ -103,11 +197,18 @@ impl AudioMixer {
             )
             .expect("Failed to add abuffersink filter");
 
-        for (i, abuffer) in abuffers.iter_mut().enumerate() {
-            abuffer.link(0, &mut amix, i as u32);
+        for (i, (abuffer, volume)) in abuffers.iter_mut().zip(volumes.iter_mut()).enumerate() {
+            abuffer.link(0, volume, 0);
+            volume.link(0, &mut amix, i as u32);
         }
 
-        amix.link(0, &mut aformat, 0);
+        match &mut alimiter {
+            Some(alimiter) => {
+                amix.link(0, alimiter, 0);
+                alimiter.link(0, &mut aformat, 0);
+            }
+            None => amix.link(0, &mut aformat, 0),
+        }
         aformat.link(0, &mut abuffersink, 0);
 
         filter_graph
@@ -123,6 +224,15 @@ impl AudioMixer {
             }
 
             for (i, source) in self.sources.iter().enumerate() {
+                let gain = source.input.effective_gain();
+                if gain != applied_gains[i] {
+                    debug!("audio mixer input {i} gain changed to {gain}");
+                    if let Err(e) = Self::set_volume(&mut filter_graph, i, gain) {
+                        warn!("Failed to set gain of audio mixer input {i}: {e}");
+                    }
+                    applied_gains[i] = gain;
+                }
+
                 loop {
                     let value = match source.rx.try_recv() {
                         Ok(v) => v,
@@ -153,11 +263,52 @@ impl AudioMixer {
 
 pub struct AudioMixerSink {
     pub tx: flume::Sender<(ffmpeg::frame::Audio, f64)>,
+    pub input: AudioMixerInput,
 }
 
 pub struct AudioMixerSource {
     rx: flume::Receiver<(ffmpeg::frame::Audio, f64)>,
     info: AudioInfo,
+    input: AudioMixerInput,
+}
+
+/// Per-source controls for an [`AudioMixer`], which can be changed while it's running.
+#[derive(Debug, Clone)]
+pub struct AudioMixerInput {
+    gain: Arc<AtomicU32>,
+    muted: Arc<AtomicBool>,
+}
+
+impl AudioMixerInput {
+    /// Linear gain, where `1.0` leaves the source as-is.
+    pub fn set_gain(&self, gain: f32) {
+        self.gain.store(gain.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    fn effective_gain(&self) -> f32 {
+        if self.is_muted() { 0.0 } else { self.gain() }
+    }
+}
+
+impl Default for AudioMixerInput {
+    fn default() -> Self {
+        Self {
+            gain: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            muted: Arc::new(AtomicBool::new(false)),
+        }
+    }
 }
 
 impl PipelineSourceTask for AudioMixer {
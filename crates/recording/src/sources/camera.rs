@@ -1,40 +1,54 @@
 use cap_media_info::VideoInfo;
 use ffmpeg::frame;
-use flume::{Receiver, Sender};
+use flume::{Receiver, RecvTimeoutError, Sender};
 use std::{
     sync::Arc,
     time::{Duration, Instant},
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::{
     MediaError,
+    clock::CaptureClock,
     feeds::camera::{self, CameraFeedLock, RawCameraFrame},
-    pipeline::{control::Control, task::PipelineSourceTask},
+    pipeline::{
+        control::{Control, PauseClock},
+        task::PipelineSourceTask,
+    },
 };
 
 pub struct CameraSource {
     feed: Arc<CameraFeedLock>,
     video_info: VideoInfo,
     output: Sender<(frame::Video, f64)>,
-    first_frame_instant: Option<Instant>,
-    first_frame_timestamp: Option<Duration>,
-    start_instant: Instant,
+    anchor: Option<FrameAnchor>,
+    last_sent: Option<Duration>,
+    clock: CaptureClock,
+    pause_clock: PauseClock,
+}
+
+/// The first frame of a capture, which later frames' timestamps are relative to.
+#[derive(Clone, Copy)]
+struct FrameAnchor {
+    input_id: u32,
+    instant: Instant,
+    timestamp: Duration,
 }
 
 impl CameraSource {
     pub fn init(
         feed: Arc<CameraFeedLock>,
         output: Sender<(frame::Video, f64)>,
-        start_instant: Instant,
+        clock: CaptureClock,
     ) -> Self {
         Self {
             video_info: *feed.video_info(),
             feed,
             output,
-            first_frame_instant: None,
-            first_frame_timestamp: None,
-            start_instant,
+            anchor: None,
+            last_sent: None,
+            clock,
+            pause_clock: PauseClock::default(),
         }
     }
 
@@ -42,12 +56,7 @@ impl CameraSource {
         self.video_info
     }
 
-    fn process_frame(
-        &self,
-        camera_frame: RawCameraFrame,
-        first_frame_instant: Instant,
-        first_frame_timestamp: Duration,
-    ) -> Result<(), MediaError> {
+    fn process_frame(&mut self, camera_frame: RawCameraFrame) -> Result<(), MediaError> {
         let check_skip_send = || {
             cap_fail::fail_err!("media::sources::camera::skip_send", ());
 
@@ -58,21 +67,73 @@ impl CameraSource {
             return Ok(());
         }
 
-        let relative_timestamp = camera_frame.timestamp - first_frame_timestamp;
+        // Timestamps from a new capture (e.g. after the camera was reattached) start from
+        // scratch, so realign them using the frame's reference time
+        let anchor = match self.anchor {
+            Some(anchor) if anchor.input_id == camera_frame.input_id => anchor,
+            previous => {
+                if previous.is_some()
+                    && let Err(e) = self.send_black_frame()
+                {
+                    warn!("Failed to fill camera gap: {e}");
+                }
+
+                *self.anchor.insert(FrameAnchor {
+                    input_id: camera_frame.input_id,
+                    instant: camera_frame.reference_time,
+                    timestamp: camera_frame.timestamp,
+                })
+            }
+        };
+
+        let relative_timestamp = camera_frame.timestamp - anchor.timestamp;
+        let timestamp = self
+            .clock
+            .elapsed_at(anchor.instant + relative_timestamp)
+            .saturating_sub(self.pause_clock.paused_duration());
+
+        self.send(camera_frame.frame, timestamp)
+    }
+
+    /// Covers the time the camera was gone with black, rather than freezing on its last frame.
+    fn send_black_frame(&mut self) -> Result<(), MediaError> {
+        let Some(last_sent) = self.last_sent else {
+            return Ok(());
+        };
+
+        let info = self.video_info;
+        let mut black = frame::Video::new(ffmpeg::format::Pixel::RGB24, info.width, info.height);
+        for plane in 0..black.planes() {
+            black.data_mut(plane).fill(0);
+        }
+
+        let mut frame = frame::Video::empty();
+        ffmpeg::software::scaling::Context::get(
+            black.format(),
+            info.width,
+            info.height,
+            info.pixel_format,
+            info.width,
+            info.height,
+            ffmpeg::software::scaling::Flags::POINT,
+        )?
+        .run(&black, &mut frame)?;
+
+        self.send(
+            frame,
+            last_sent + Duration::from_secs_f64(1.0 / info.fps().max(1) as f64),
+        )
+    }
 
-        if self
-            .output
-            .send((
-                camera_frame.frame,
-                (first_frame_instant + relative_timestamp - self.start_instant).as_secs_f64(),
-            ))
-            .is_err()
-        {
+    fn send(&mut self, frame: frame::Video, timestamp: Duration) -> Result<(), MediaError> {
+        if self.output.send((frame, timestamp.as_secs_f64())).is_err() {
             return Err(MediaError::Any(
                 "Pipeline is unreachable! Stopping capture".into(),
             ));
         }
 
+        self.last_sent = Some(timestamp);
+
         Ok(())
     }
 
@@ -81,12 +142,7 @@ impl CameraSource {
         drop(frames_rx);
 
         for frame in frames {
-            let first_frame_instant = *self.first_frame_instant.get_or_insert(frame.reference_time);
-            let first_frame_timestamp = *self.first_frame_timestamp.get_or_insert(frame.timestamp);
-
-            if let Err(error) =
-                self.process_frame(frame, first_frame_instant, first_frame_timestamp)
-            {
+            if let Err(error) = self.process_frame(frame) {
                 eprintln!("{error}");
                 break;
             }
@@ -100,39 +156,52 @@ impl PipelineSourceTask for CameraSource {
         ready_signal: crate::pipeline::task::PipelineReadySignal,
         mut control_signal: crate::pipeline::control::PipelineControlSignal,
     ) -> Result<(), String> {
-        let mut frames_rx: Option<Receiver<RawCameraFrame>> = None;
-
         info!("Camera source ready");
 
-        let frames = frames_rx.get_or_insert_with(|| {
+        let add_sender = |feed: &CameraFeedLock| {
             let (tx, rx) = flume::bounded(5);
-            let _ = self.feed.ask(camera::AddSender(tx)).blocking_send();
+            let _ = feed.ask(camera::AddSender(tx)).blocking_send();
             rx
-        });
+        };
+
+        let mut frames_rx: Option<Receiver<RawCameraFrame>> = Some(add_sender(&self.feed));
 
         ready_signal.send(Ok(())).unwrap();
 
         loop {
             match control_signal.last() {
-                Some(Control::Play) => match frames.drain().last().or_else(|| frames.recv().ok()) {
-                    Some(frame) => {
-                        let first_frame_instant =
-                            *self.first_frame_instant.get_or_insert(frame.reference_time);
-                        let first_frame_timestamp =
-                            *self.first_frame_timestamp.get_or_insert(frame.timestamp);
-
-                        if let Err(error) =
-                            self.process_frame(frame, first_frame_instant, first_frame_timestamp)
-                        {
-                            eprintln!("{error}");
+                Some(Control::Play) => {
+                    self.pause_clock.resume();
+
+                    let frames = frames_rx.get_or_insert_with(|| add_sender(&self.feed));
+
+                    // Don't block indefinitely, the camera may be gone while the feed reattaches it
+                    let frame = match frames.drain().last() {
+                        Some(frame) => Ok(frame),
+                        None => frames.recv_timeout(Duration::from_millis(100)),
+                    };
+
+                    match frame {
+                        Ok(frame) => {
+                            if let Err(error) = self.process_frame(frame) {
+                                eprintln!("{error}");
+                                break;
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => {
+                            error!("Lost connection with the camera feed");
                             break;
                         }
                     }
-                    None => {
-                        error!("Lost connection with the camera feed");
-                        break;
+                }
+                Some(Control::Pause) => {
+                    // Dropping the receiver detaches us from the feed until we resume
+                    if let Some(rx) = frames_rx.take() {
+                        self.pause_and_drain_frames(rx);
                     }
-                },
+                    self.pause_clock.pause();
+                }
                 Some(Control::Shutdown) | None => {
                     if let Some(rx) = frames_rx.take() {
                         self.pause_and_drain_frames(rx);
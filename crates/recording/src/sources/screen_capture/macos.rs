@@ -25,11 +25,13 @@ impl ScreenCaptureFormat for CMSampleBufferCapture {
 
 #[derive(Actor)]
 struct FrameHandler {
-    start_time_unix: f64,
+    /// Host time at which the capture clock read `start_elapsed`
     start_cmtime: f64,
-    start_time_f64: f64,
+    start_elapsed: f64,
     video_tx: Sender<(arc::R<cm::SampleBuf>, f64)>,
     audio_tx: Option<Sender<(ffmpeg::frame::Audio, f64)>>,
+    pause_clock: PauseClock,
+    throttle: FrameThrottle,
 }
 
 impl Message<NewFrame> for FrameHandler {
@@ -40,12 +42,17 @@ impl Message<NewFrame> for FrameHandler {
         msg: NewFrame,
         _: &mut kameo::prelude::Context<Self, Self::Reply>,
     ) -> Self::Reply {
+        if self.pause_clock.is_paused() {
+            return;
+        }
+
         let frame = msg.0;
         let sample_buffer = frame.sample_buf();
 
         let frame_time = sample_buffer.pts().value as f64 / sample_buffer.pts().scale as f64;
-        let unix_timestamp = self.start_time_unix + frame_time - self.start_cmtime;
-        let relative_time = unix_timestamp - self.start_time_f64;
+        let relative_time = self.start_elapsed + frame_time
+            - self.start_cmtime
+            - self.pause_clock.paused_duration().as_secs_f64();
 
         match &frame {
             scap_screencapturekit::Frame::Screen(frame) => {
@@ -53,6 +60,10 @@ impl Message<NewFrame> for FrameHandler {
                     return;
                 }
 
+                if !self.throttle.admit(relative_time) {
+                    return;
+                }
+
                 let check_skip_send = || {
                     cap_fail::fail_err!("media::sources::screen_capture::skip_send", ());
 
@@ -128,33 +139,26 @@ impl PipelineSourceTask for ScreenCaptureSource<CMSampleBufferCapture> {
         ready_signal: crate::pipeline::task::PipelineReadySignal,
         control_signal: crate::pipeline::control::PipelineControlSignal,
     ) -> Result<(), String> {
-        let start = std::time::SystemTime::now();
-        let start_time_unix = start
-            .duration_since(std::time::UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs_f64();
+        let start_elapsed = self.clock.elapsed().as_secs_f64();
         let start_cmtime = cidre::cm::Clock::host_time_clock().time();
         let start_cmtime = start_cmtime.value as f64 / start_cmtime.scale as f64;
 
-        let start_time_f64 = self
-            .start_time
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs_f64();
-
         let video_tx = self.video_tx.clone();
         let audio_tx = self.audio_tx.clone();
         let config = self.config.clone();
+        let dropped_frames = self.dropped_frames.clone();
 
         self.tokio_handle
             .block_on(async move {
                 let captures_audio = audio_tx.is_some();
+                let pause_clock = PauseClock::default();
                 let frame_handler = FrameHandler::spawn(FrameHandler {
                     video_tx,
                     audio_tx,
-                    start_time_unix,
                     start_cmtime,
-                    start_time_f64,
+                    start_elapsed,
+                    pause_clock: pause_clock.clone(),
+                    throttle: FrameThrottle::new(config.fps, dropped_frames),
                 });
 
                 let display = Display::from_id(&config.display)
@@ -239,12 +243,14 @@ impl PipelineSourceTask for ScreenCaptureSource<CMSampleBufferCapture> {
                             stop.await;
                             return Err(SourceError::DidStopWithError(error));
                         }
-                        Either::Right((Ok(ctrl), _)) => {
-                            if let Control::Shutdown = ctrl {
+                        Either::Right((Ok(ctrl), _)) => match ctrl {
+                            Control::Play => pause_clock.resume(),
+                            Control::Pause => pause_clock.pause(),
+                            Control::Shutdown => {
                                 stop.await;
                                 return Ok(());
                             }
-                        }
+                        },
                         _ => {
                             warn!("Screen capture recv channels shutdown, exiting.");
 
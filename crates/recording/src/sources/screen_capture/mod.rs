@@ -1,14 +1,24 @@
 use cap_cursor_capture::CursorCropBounds;
+use cap_media::MediaError;
 use cap_media_info::{AudioInfo, VideoInfo};
 use ffmpeg::sys::AV_TIME_BASE_Q;
 use flume::Sender;
 use scap_targets::{Display, DisplayId, Window, WindowId, bounds::*};
 use serde::{Deserialize, Serialize};
 use specta::Type;
-use std::time::SystemTime;
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
 use tracing::{error, warn};
 
-use crate::pipeline::{control::Control, task::PipelineSourceTask};
+use crate::{
+    clock::CaptureClock,
+    pipeline::{
+        control::{Control, PauseClock},
+        task::PipelineSourceTask,
+    },
+};
 
 #[cfg(windows)]
 mod windows;
@@ -33,13 +43,16 @@ pub struct CaptureWindow {
     pub owner_name: String,
     pub name: String,
     pub bounds: LogicalBounds,
+    pub physical_size: Option<PhysicalSize>,
     pub refresh_rate: u32,
+    pub is_minimized: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Type)]
 pub struct CaptureDisplay {
     pub id: DisplayId,
     pub name: String,
+    pub physical_size: Option<PhysicalSize>,
     pub refresh_rate: u32,
 }
 
@@ -181,6 +194,24 @@ impl ScreenCaptureTarget {
         }
     }
 
+    /// Checks that the target still exists, so a recording isn't started
+    /// against a display that was unplugged or a window that was closed.
+    pub fn validate(&self) -> Result<(), MediaError> {
+        let exists = match self {
+            Self::Display { id } => Display::from_id(id).is_some(),
+            Self::Window { id } => Window::from_id(id).is_some(),
+            Self::Area { screen, .. } => Display::from_id(screen).is_some(),
+        };
+
+        if exists {
+            Ok(())
+        } else {
+            Err(MediaError::DeviceUnreachable(format!(
+                "Capture target {self:?} is no longer available"
+            )))
+        }
+    }
+
     pub fn title(&self) -> Option<String> {
         match self {
             Self::Display { id } => Display::from_id(id).and_then(|d| d.name()),
@@ -196,7 +227,8 @@ pub struct ScreenCaptureSource<TCaptureFormat: ScreenCaptureFormat> {
     tokio_handle: tokio::runtime::Handle,
     video_tx: Sender<(TCaptureFormat::VideoFormat, f64)>,
     audio_tx: Option<Sender<(ffmpeg::frame::Audio, f64)>>,
-    start_time: SystemTime,
+    clock: CaptureClock,
+    dropped_frames: Arc<AtomicU64>,
     _phantom: std::marker::PhantomData<TCaptureFormat>,
     #[cfg(windows)]
     d3d_device: ::windows::Win32::Graphics::Direct3D11::ID3D11Device,
@@ -209,6 +241,7 @@ impl<T: ScreenCaptureFormat> std::fmt::Debug for ScreenCaptureSource<T> {
             // .field("output_resolution", &self.output_resolution)
             .field("fps", &self.config.fps)
             .field("video_info", &self.video_info)
+            .field("dropped_frames", &self.dropped_frames())
             .field(
                 "audio_info",
                 &self.audio_tx.as_ref().map(|_| self.audio_info()),
@@ -236,7 +269,8 @@ impl<TCaptureFormat: ScreenCaptureFormat> Clone for ScreenCaptureSource<TCapture
             video_tx: self.video_tx.clone(),
             audio_tx: self.audio_tx.clone(),
             tokio_handle: self.tokio_handle.clone(),
-            start_time: self.start_time,
+            clock: self.clock,
+            dropped_frames: self.dropped_frames.clone(),
             _phantom: std::marker::PhantomData,
             #[cfg(windows)]
             d3d_device: self.d3d_device.clone(),
@@ -279,7 +313,7 @@ impl<TCaptureFormat: ScreenCaptureFormat> ScreenCaptureSource<TCaptureFormat> {
         max_fps: u32,
         video_tx: Sender<(TCaptureFormat::VideoFormat, f64)>,
         audio_tx: Option<Sender<(ffmpeg::frame::Audio, f64)>>,
-        start_time: SystemTime,
+        clock: CaptureClock,
         tokio_handle: tokio::runtime::Handle,
         #[cfg(windows)] d3d_device: ::windows::Win32::Graphics::Direct3D11::ID3D11Device,
     ) -> Result<Self, ScreenCaptureInitError> {
@@ -404,7 +438,8 @@ impl<TCaptureFormat: ScreenCaptureFormat> ScreenCaptureSource<TCaptureFormat> {
             video_tx,
             audio_tx,
             tokio_handle,
-            start_time,
+            clock,
+            dropped_frames: Default::default(),
             _phantom: std::marker::PhantomData,
             #[cfg(windows)]
             d3d_device,
@@ -416,6 +451,11 @@ impl<TCaptureFormat: ScreenCaptureFormat> ScreenCaptureSource<TCaptureFormat> {
         &self.d3d_device
     }
 
+    /// The clock the source's frames are timestamped against.
+    pub fn clock(&self) -> CaptureClock {
+        self.clock
+    }
+
     pub fn config(&self) -> &Config {
         &self.config
     }
@@ -427,6 +467,66 @@ impl<TCaptureFormat: ScreenCaptureFormat> ScreenCaptureSource<TCaptureFormat> {
     pub fn audio_info(&self) -> AudioInfo {
         TCaptureFormat::audio_info()
     }
+
+    /// Number of captured frames that never reached the pipeline, either
+    /// because they arrived faster than the target fps or the pipeline
+    /// couldn't keep up.
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn dropped_frames_counter(&self) -> Arc<AtomicU64> {
+        self.dropped_frames.clone()
+    }
+}
+
+/// Drops frames that arrive faster than the target fps, keeping the ones
+/// that are let through on an evenly spaced grid.
+struct FrameThrottle {
+    interval: f64,
+    next: Option<f64>,
+    dropped_frames: Arc<AtomicU64>,
+}
+
+impl FrameThrottle {
+    /// How early a frame may arrive relative to its slot and still be kept,
+    /// as a fraction of the frame interval. Absorbs jitter in capture timestamps.
+    const TOLERANCE: f64 = 0.1;
+
+    fn new(fps: u32, dropped_frames: Arc<AtomicU64>) -> Self {
+        Self {
+            interval: 1.0 / fps.max(1) as f64,
+            next: None,
+            dropped_frames,
+        }
+    }
+
+    fn admit(&mut self, timestamp: f64) -> bool {
+        let Some(next) = self.next else {
+            self.next = Some(timestamp + self.interval);
+            return true;
+        };
+
+        if timestamp < next - self.interval * Self::TOLERANCE {
+            self.record_drop();
+            return false;
+        }
+
+        // Stay on the grid unless we've fallen more than a frame behind it,
+        // otherwise a stall would be followed by a burst of catch-up frames.
+        let next = next + self.interval;
+        self.next = Some(if next <= timestamp {
+            timestamp + self.interval
+        } else {
+            next
+        });
+
+        true
+    }
+
+    fn record_drop(&self) {
+        self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 pub fn list_displays() -> Vec<(CaptureDisplay, Display)> {
@@ -437,6 +537,7 @@ pub fn list_displays() -> Vec<(CaptureDisplay, Display)> {
                 CaptureDisplay {
                     id: display.id(),
                     name: display.name()?,
+                    physical_size: display.physical_size(),
                     refresh_rate: display.raw_handle().refresh_rate() as u32,
                 },
                 display,
@@ -477,10 +578,76 @@ pub fn list_windows() -> Vec<(CaptureWindow, Window)> {
                     name,
                     owner_name: v.owner_name()?,
                     bounds: v.display_relative_logical_bounds()?,
+                    physical_size: v.physical_size(),
                     refresh_rate: v.display()?.raw_handle().refresh_rate() as u32,
+                    is_minimized: v.is_minimized(),
                 },
                 v,
             ))
         })
         .collect()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn throttle(fps: u32) -> (FrameThrottle, Arc<AtomicU64>) {
+        let dropped_frames = Arc::new(AtomicU64::new(0));
+        (
+            FrameThrottle::new(fps, dropped_frames.clone()),
+            dropped_frames,
+        )
+    }
+
+    #[test]
+    fn keeps_frames_on_grid() {
+        let (mut throttle, _) = throttle(10);
+
+        let kept =
+            [0.0, 0.05, 0.1, 0.15, 0.2, 0.25, 0.3].map(|timestamp| throttle.admit(timestamp));
+
+        assert_eq!(kept, [true, false, true, false, true, false, true]);
+    }
+
+    #[test]
+    fn tolerates_jitter() {
+        let (mut throttle, _) = throttle(10);
+
+        assert!(throttle.admit(0.0));
+        // slightly early for the 0.1 slot
+        assert!(throttle.admit(0.095));
+        // too early for the 0.2 slot
+        assert!(!throttle.admit(0.185));
+        // slightly early again, so the grid didn't drift to the early frame
+        assert!(throttle.admit(0.195));
+        // late, but still within the 0.3 slot
+        assert!(throttle.admit(0.34));
+        assert!(throttle.admit(0.395));
+    }
+
+    #[test]
+    fn reanchors_after_stall() {
+        let (mut throttle, _) = throttle(10);
+
+        assert!(throttle.admit(0.0));
+        assert!(throttle.admit(1.0));
+        // without re-anchoring, these would all be let through to catch up
+        assert!(!throttle.admit(1.02));
+        assert!(!throttle.admit(1.05));
+        assert!(throttle.admit(1.1));
+    }
+
+    #[test]
+    fn counts_dropped_frames() {
+        let (mut throttle, dropped_frames) = throttle(30);
+
+        // 120fps capture for one second
+        let kept = (0..120)
+            .filter(|i| throttle.admit(*i as f64 / 120.0))
+            .count();
+
+        assert_eq!(kept, 30);
+        assert_eq!(dropped_frames.load(Ordering::Relaxed), 90);
+    }
+}
@@ -47,12 +47,14 @@ impl ScreenCaptureFormat for Direct3DCapture {
 
 struct FrameHandler {
     capturer: WeakActorRef<ScreenCaptureActor>,
-    start_time: SystemTime,
+    clock: CaptureClock,
     frames_dropped: u32,
     last_cleanup: Instant,
     last_log: Instant,
     frame_events: VecDeque<(Instant, bool)>,
     video_tx: Sender<(scap_direct3d::Frame, f64)>,
+    pause_clock: PauseClock,
+    throttle: FrameThrottle,
 }
 
 impl Actor for FrameHandler {
@@ -127,9 +129,18 @@ impl Message<NewFrame> for FrameHandler {
         msg: NewFrame,
         ctx: &mut kameo::prelude::Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        let Ok(elapsed) = msg.display_time.duration_since(self.start_time) else {
+        if self.pause_clock.is_paused() {
             return;
-        };
+        }
+
+        let elapsed = self
+            .clock
+            .elapsed_at(msg.display_time)
+            .saturating_sub(self.pause_clock.paused_duration());
+
+        if !self.throttle.admit(elapsed.as_secs_f64()) {
+            return;
+        }
 
         let now = Instant::now();
         let frame_dropped = match self.video_tx.try_send((msg.frame, elapsed.as_secs_f64())) {
@@ -141,6 +152,7 @@ impl Message<NewFrame> for FrameHandler {
             Err(flume::TrySendError::Full(_)) => {
                 warn!("Screen capture sender is full, dropping frame");
                 self.frames_dropped += 1;
+                self.throttle.record_drop();
                 true
             }
             _ => false,
@@ -211,11 +223,12 @@ impl PipelineSourceTask for ScreenCaptureSource<Direct3DCapture> {
         let video_tx = self.video_tx.clone();
         let audio_tx = self.audio_tx.clone();
 
-        let start_time = self.start_time;
+        let clock = self.clock;
         let d3d_device = self.d3d_device.clone();
 
         // Frame drop rate tracking state
         let config = self.config.clone();
+        let dropped_frames = self.dropped_frames.clone();
 
         self.tokio_handle
             .block_on(async move {
@@ -223,14 +236,17 @@ impl PipelineSourceTask for ScreenCaptureSource<Direct3DCapture> {
                 let capturer =
                     ScreenCaptureActor::spawn(ScreenCaptureActor::new(error_tx, d3d_device));
 
+                let pause_clock = PauseClock::default();
                 let frame_handler = FrameHandler::spawn(FrameHandler {
                     capturer: capturer.downgrade(),
                     video_tx,
-                    start_time,
+                    clock,
                     frame_events: Default::default(),
                     frames_dropped: Default::default(),
                     last_cleanup: Instant::now(),
                     last_log: Instant::now(),
+                    pause_clock: pause_clock.clone(),
+                    throttle: FrameThrottle::new(config.fps, dropped_frames),
                 });
 
                 let mut settings = scap_direct3d::Settings {
@@ -284,7 +300,7 @@ impl PipelineSourceTask for ScreenCaptureSource<Direct3DCapture> {
 
                 let audio_capture = if let Some(audio_tx) = audio_tx {
                     let audio_capture = WindowsAudioCapture::spawn(
-                        WindowsAudioCapture::new(audio_tx, start_time)
+                        WindowsAudioCapture::new(audio_tx, clock, pause_clock.clone())
                             .map_err(SourceError::CreateAudioCapture)?,
                     );
 
@@ -324,12 +340,14 @@ impl PipelineSourceTask for ScreenCaptureSource<Direct3DCapture> {
                             stop.await;
                             return Err(SourceError::Closed);
                         }
-                        Either::Right((Ok(ctrl), _)) => {
-                            if let Control::Shutdown = ctrl {
+                        Either::Right((Ok(ctrl), _)) => match ctrl {
+                            Control::Play => pause_clock.resume(),
+                            Control::Pause => pause_clock.pause(),
+                            Control::Shutdown => {
                                 stop.await;
                                 return Ok(());
                             }
-                        }
+                        },
                         _ => {
                             warn!("Screen capture recv channels shutdown, exiting.");
 
@@ -381,7 +399,7 @@ pub enum StartCapturingError {
 
 pub struct NewFrame {
     pub frame: scap_direct3d::Frame,
-    pub display_time: SystemTime,
+    pub display_time: Instant,
 }
 
 impl Message<StartCapturing> for ScreenCaptureActor {
@@ -409,7 +427,7 @@ impl Message<StartCapturing> for ScreenCaptureActor {
             msg.target,
             msg.settings,
             move |frame| {
-                let display_time = SystemTime::now();
+                let display_time = Instant::now();
 
                 let _ = msg
                     .frame_handler
@@ -479,27 +497,28 @@ pub mod audio {
     impl WindowsAudioCapture {
         pub fn new(
             audio_tx: Sender<(ffmpeg::frame::Audio, f64)>,
-            start_time: SystemTime,
+            clock: CaptureClock,
+            pause_clock: PauseClock,
         ) -> Result<Self, scap_cpal::CapturerError> {
-            let mut i = 0;
             let capturer = scap_cpal::create_capturer(
                 move |data, _: &cpal::InputCallbackInfo, config| {
                     use scap_ffmpeg::*;
 
-                    let timestamp = SystemTime::now();
+                    if pause_clock.is_paused() {
+                        return;
+                    }
+
                     let mut ff_frame = data.as_ffmpeg(config);
 
-                    let Ok(elapsed) = timestamp.duration_since(start_time) else {
-                        warn!("Skipping audio frame {i} as elapsed time is invalid");
-                        return;
-                    };
+                    let elapsed = clock
+                        .elapsed()
+                        .saturating_sub(pause_clock.paused_duration());
 
                     let rate = ff_frame.rate();
 
                     ff_frame.set_pts(Some((elapsed.as_secs_f64() * rate as f64) as i64));
 
                     let _ = audio_tx.send((ff_frame, elapsed.as_secs_f64()));
-                    i += 1;
                 },
                 move |e| {
                     dbg!(e);
@@ -0,0 +1,71 @@
+use serde::Serialize;
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+
+use crate::clock::CaptureClock;
+
+/// A snapshot of a recording's progress, taken while it's still running.
+#[derive(specta::Type, Serialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingStats {
+    /// Wall time since the recording started, including any time spent paused.
+    pub elapsed_secs: f64,
+    /// Screen frames passed to the encoder.
+    pub video_frames: u64,
+    /// Audio samples per channel passed to the encoders, across all audio tracks.
+    pub audio_samples: u64,
+    /// Screen frames that were captured but dropped before reaching the encoder.
+    pub dropped_frames: u64,
+}
+
+/// Counters shared with a recording's pipelines, which update them as they encode.
+#[derive(Debug)]
+pub struct RecordingStatsTracker {
+    clock: CaptureClock,
+    video_frames: AtomicU64,
+    audio_samples: AtomicU64,
+    /// Drop counters of each screen capture the recording has used,
+    /// as studio recordings start a new one for every segment.
+    screen_dropped_frames: Mutex<Vec<Arc<AtomicU64>>>,
+}
+
+impl RecordingStatsTracker {
+    pub fn new(clock: CaptureClock) -> Self {
+        Self {
+            clock,
+            video_frames: AtomicU64::new(0),
+            audio_samples: AtomicU64::new(0),
+            screen_dropped_frames: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn record_video_frame(&self) {
+        self.video_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_audio_samples(&self, samples: usize) {
+        self.audio_samples
+            .fetch_add(samples as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn track_dropped_frames(&self, dropped_frames: Arc<AtomicU64>) {
+        if let Ok(mut counters) = self.screen_dropped_frames.lock() {
+            counters.push(dropped_frames);
+        }
+    }
+
+    pub fn snapshot(&self) -> RecordingStats {
+        RecordingStats {
+            elapsed_secs: self.clock.elapsed().as_secs_f64(),
+            video_frames: self.video_frames.load(Ordering::Relaxed),
+            audio_samples: self.audio_samples.load(Ordering::Relaxed),
+            dropped_frames: self
+                .screen_dropped_frames
+                .lock()
+                .map(|counters| counters.iter().map(|c| c.load(Ordering::Relaxed)).sum())
+                .unwrap_or(0),
+        }
+    }
+}
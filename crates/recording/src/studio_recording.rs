@@ -1,14 +1,16 @@
 use crate::{
-    ActorError, MediaError, RecordingBaseInputs, RecordingError,
+    ActorError, EncoderErrorPolicy, MediaError, RecordingBaseInputs, RecordingError,
     capture_pipeline::{MakeCapturePipeline, ScreenCaptureMethod, create_screen_capture},
+    clock::CaptureClock,
     cursor::{CursorActor, Cursors, spawn_cursor_recorder},
     feeds::{camera::CameraFeedLock, microphone::MicrophoneFeedLock},
     pipeline::Pipeline,
     sources::{AudioInputSource, CameraSource, ScreenCaptureFormat, ScreenCaptureTarget},
+    stats::{RecordingStats, RecordingStatsTracker},
 };
-use cap_enc_ffmpeg::{H264Encoder, MP4File, OggFile, OpusEncoder};
+use cap_enc_ffmpeg::{H264Encoder, MP4File, OggFile, OpusEncoder, ThreadedMP4File};
 use cap_media_info::VideoInfo;
-use cap_project::{CursorEvents, StudioRecordingMeta};
+use cap_project::{CursorEvents, CursorMeta, StudioRecordingMeta};
 use cap_utils::spawn_actor;
 use flume::Receiver;
 use relative_path::RelativePathBuf;
@@ -19,7 +21,7 @@ use std::{
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::sync::oneshot;
-use tracing::{debug, info, trace};
+use tracing::{debug, info, trace, warn};
 
 #[allow(clippy::large_enum_variant)]
 enum StudioRecordingActorState {
@@ -50,12 +52,48 @@ pub struct StudioRecordingActor {
     fps: u32,
     segments: Vec<StudioRecordingSegment>,
     #[allow(unused)]
-    start_instant: Instant,
+    clock: CaptureClock,
+    encoder_error_policy: EncoderErrorPolicy,
+    rollovers: u32,
+    rollover_tx: flume::Sender<SegmentRollover>,
+    segment_duration: Option<Duration>,
+    appended: Option<AppendedRecording>,
+}
+
+/// A stopped recording that new segments are being added to.
+struct AppendedRecording {
+    meta: cap_project::RecordingMeta,
+    segments: Vec<cap_project::MultipleSegment>,
+    cursors: HashMap<String, CursorMeta>,
+    /// Where the last segment ended on the recording's clock.
+    end: Duration,
+    display_size: (u32, u32),
+}
+
+impl StudioRecordingActor {
+    fn should_roll_over(&self, error: &str) -> bool {
+        match self.encoder_error_policy {
+            EncoderErrorPolicy::Fail => false,
+            EncoderErrorPolicy::Rollover { max_rollovers } => {
+                self.rollovers < max_rollovers && is_recoverable_error(error)
+            }
+        }
+    }
+}
+
+/// Sent when an encoder error ends a segment early and recording continues in a new one.
+#[derive(specta::Type, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentRollover {
+    /// Index of the segment that recording continues in.
+    pub index: u32,
+    pub error: String,
 }
 
 pub struct StudioRecordingSegment {
     pub start: f64,
     pub end: f64,
+    meta: cap_project::MultipleSegment,
     pipeline: StudioRecordingPipeline,
 }
 
@@ -86,6 +124,8 @@ struct CursorPipeline {
 #[derive(Clone)]
 pub struct StudioRecordingHandle {
     ctrl_tx: flume::Sender<StudioRecordingActorControlMessage>,
+    stats: Arc<RecordingStatsTracker>,
+    rollover_rx: flume::Receiver<SegmentRollover>,
     pub capture_target: ScreenCaptureTarget,
 }
 
@@ -116,6 +156,17 @@ impl StudioRecordingHandle {
     pub async fn cancel(&self) -> Result<(), RecordingError> {
         send_message!(self.ctrl_tx, StudioRecordingActorControlMessage::Cancel)
     }
+
+    /// Can be called at any time, including while the recording is paused.
+    /// Counts are totals across all segments.
+    pub fn stats(&self) -> RecordingStats {
+        self.stats.snapshot()
+    }
+    /// Receives a [`SegmentRollover`] each time the recording recovers from an encoder error,
+    /// as allowed by its [`EncoderErrorPolicy`].
+    pub fn rollovers(&self) -> flume::Receiver<SegmentRollover> {
+        self.rollover_rx.clone()
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -124,6 +175,24 @@ pub enum SpawnStudioRecordingError {
     Media(#[from] MediaError),
     #[error("{0}")]
     PipelineCreationError(#[from] CreateSegmentPipelineError),
+    #[error("Failed to load the recording's meta: {0}")]
+    Meta(String),
+    #[error("Only studio recordings can be appended to")]
+    NotStudio,
+    #[error("Recordings made by older versions can't be appended to")]
+    Outdated,
+    #[error("Failed to read the recording's display video: {0}")]
+    Probe(#[from] ffmpeg::Error),
+    #[error("Can't append H264 video to a recording encoded as {0:?}")]
+    IncompatibleCodec(ffmpeg::codec::Id),
+    #[error(
+        "Can't append a {}x{} display to a recording at {}x{}",
+        .new.0, .new.1, .existing.0, .existing.1
+    )]
+    IncompatibleResolution {
+        existing: (u32, u32),
+        new: (u32, u32),
+    },
 }
 
 pub async fn spawn_studio_recording_actor(
@@ -132,6 +201,101 @@ pub async fn spawn_studio_recording_actor(
     base_inputs: RecordingBaseInputs,
     custom_cursor_capture: bool,
 ) -> Result<(StudioRecordingHandle, oneshot::Receiver<Result<(), String>>), SpawnStudioRecordingError>
+{
+    spawn_actor_inner(id, recording_dir, base_inputs, custom_cursor_capture, None).await
+}
+
+/// Records new segments onto the end of a stopped studio recording, as if it had been
+/// paused and resumed. Timestamps carry on from where the last segment ended, and
+/// stopping saves the recording's meta with the new segments added to it.
+///
+/// Fails if the display's video would be a different resolution or codec than the last
+/// segment's, in which case nothing is added to the recording.
+pub async fn append_to_studio_recording(
+    id: String,
+    recording_dir: PathBuf,
+    base_inputs: RecordingBaseInputs,
+    custom_cursor_capture: bool,
+) -> Result<(StudioRecordingHandle, oneshot::Receiver<Result<(), String>>), SpawnStudioRecordingError>
+{
+    let meta = cap_project::RecordingMeta::load_for_project(&recording_dir)
+        .map_err(|e| SpawnStudioRecordingError::Meta(e.to_string()))?;
+
+    let cap_project::RecordingMetaInner::Studio(studio) = &meta.inner else {
+        return Err(SpawnStudioRecordingError::NotStudio);
+    };
+    let StudioRecordingMeta::MultipleSegments { inner } = studio else {
+        return Err(SpawnStudioRecordingError::Outdated);
+    };
+    let cursors = match &inner.cursors {
+        cap_project::Cursors::Correct(cursors) => cursors.clone(),
+        cap_project::Cursors::Old(cursors) if cursors.is_empty() => HashMap::new(),
+        cap_project::Cursors::Old(_) => return Err(SpawnStudioRecordingError::Outdated),
+    };
+    let last = inner
+        .segments
+        .last()
+        .ok_or(SpawnStudioRecordingError::Outdated)?;
+
+    let display = DisplayVideo::probe(&last.display.path.to_path(&recording_dir))?;
+    if display.codec != ffmpeg::codec::Id::H264 {
+        return Err(SpawnStudioRecordingError::IncompatibleCodec(display.codec));
+    }
+
+    let end = last.display.start_time.unwrap_or_default() + display.duration;
+
+    let appended = AppendedRecording {
+        segments: inner.segments.clone(),
+        cursors,
+        end: Duration::from_secs_f64(end.max(0.0)),
+        display_size: (display.width, display.height),
+        meta,
+    };
+
+    spawn_actor_inner(
+        id,
+        recording_dir,
+        base_inputs,
+        custom_cursor_capture,
+        Some(appended),
+    )
+    .await
+}
+
+struct DisplayVideo {
+    codec: ffmpeg::codec::Id,
+    width: u32,
+    height: u32,
+    duration: f64,
+}
+
+impl DisplayVideo {
+    fn probe(path: &Path) -> Result<Self, ffmpeg::Error> {
+        let input = ffmpeg::format::input(path)?;
+        let stream = input
+            .streams()
+            .best(ffmpeg::media::Type::Video)
+            .ok_or(ffmpeg::Error::StreamNotFound)?;
+        let decoder = ffmpeg::codec::Context::from_parameters(stream.parameters())?
+            .decoder()
+            .video()?;
+
+        Ok(Self {
+            codec: decoder.id(),
+            width: decoder.width(),
+            height: decoder.height(),
+            duration: input.duration() as f64 / 1_000_000.0,
+        })
+    }
+}
+
+async fn spawn_actor_inner(
+    id: String,
+    recording_dir: PathBuf,
+    base_inputs: RecordingBaseInputs,
+    custom_cursor_capture: bool,
+    appended: Option<AppendedRecording>,
+) -> Result<(StudioRecordingHandle, oneshot::Receiver<Result<(), String>>), SpawnStudioRecordingError>
 {
     ensure_dir(&recording_dir)?;
 
@@ -144,9 +308,11 @@ pub async fn spawn_studio_recording_actor(
     let segments_dir = ensure_dir(&content_dir.join("segments"))?;
     let cursors_dir = ensure_dir(&content_dir.join("cursors"))?;
 
-    // TODO: move everything to start_instant
-    let start_time = SystemTime::now();
-    let start_instant = Instant::now();
+    let clock = match &appended {
+        Some(appended) => CaptureClock::continuing_from(appended.end),
+        None => CaptureClock::new(),
+    };
+    let stats = Arc::new(RecordingStatsTracker::new(clock));
 
     if let Some(camera_feed) = &base_inputs.camera_feed {
         debug!("camera device info: {:#?}", camera_feed.camera_info());
@@ -158,22 +324,56 @@ pub async fn spawn_studio_recording_actor(
     };
 
     let mut segment_pipeline_factory = SegmentPipelineFactory::new(
-        segments_dir,
+        segments_dir.clone(),
         cursors_dir,
         base_inputs.clone(),
         custom_cursor_capture,
-        start_time,
-        start_instant,
+        clock,
+        stats.clone(),
     );
 
-    let index = 0;
-    let (pipeline, pipeline_done_rx) = segment_pipeline_factory
-        .create_next(Default::default(), 0)
+    // cursors that are already in the recording keep their ids
+    let next_cursor_id = appended.as_ref().map_or(0, |appended| {
+        appended
+            .cursors
+            .keys()
+            .filter_map(|id| id.parse::<u32>().ok())
+            .map(|id| id + 1)
+            .max()
+            .unwrap_or_default()
+    });
+
+    let index = appended.as_ref().map_or(0, |a| a.segments.len() as u32);
+    segment_pipeline_factory.index = index;
+
+    let (mut pipeline, pipeline_done_rx) = segment_pipeline_factory
+        .create_next(Default::default(), next_cursor_id)
         .await?;
 
+    if let Some(appended) = &appended {
+        let video_info = &pipeline.screen.video_info;
+        let new = (video_info.width, video_info.height);
+
+        if new != appended.display_size {
+            if let Some(cursor) = &mut pipeline.cursor
+                && let Some(actor) = cursor.actor.take()
+            {
+                actor.stop().await;
+            }
+            let _ = pipeline.inner.shutdown().await;
+            let _ = std::fs::remove_dir_all(segments_dir.join(format!("segment-{index}")));
+
+            return Err(SpawnStudioRecordingError::IncompatibleResolution {
+                existing: appended.display_size,
+                new,
+            });
+        }
+    }
+
     let segment_start_time = current_time_f64();
 
     let (ctrl_tx, ctrl_rx) = flume::bounded(1);
+    let (rollover_tx, rollover_rx) = flume::unbounded();
 
     trace!("spawning recording actor");
 
@@ -186,7 +386,12 @@ pub async fn spawn_studio_recording_actor(
             recording_dir,
             fps,
             segments: Vec::new(),
-            start_instant,
+            clock,
+            encoder_error_policy: base_inputs.encoder_error_policy,
+            rollovers: 0,
+            rollover_tx,
+            segment_duration: base_inputs.segment_duration,
+            appended,
         };
 
         let mut state = StudioRecordingActorState::Recording {
@@ -216,6 +421,8 @@ pub async fn spawn_studio_recording_actor(
     Ok((
         StudioRecordingHandle {
             ctrl_tx,
+            stats,
+            rollover_rx,
             capture_target: base_inputs.capture_target,
         },
         done_rx,
@@ -285,12 +492,49 @@ async fn run_actor_iteration(
         actor.segments.push(StudioRecordingSegment {
             start: segment_start_time,
             end: segment_stop_time,
+            meta: segment_meta(&actor.recording_dir, actor.fps, &pipeline),
             pipeline,
         });
 
         Ok(cursors)
     }
 
+    // Finishes the current segment and continues recording in the next one
+    async fn roll_over(
+        pipeline: StudioRecordingPipeline,
+        index: u32,
+        segment_start_time: f64,
+        mut actor: StudioRecordingActor,
+        segment_pipeline_factory: &mut SegmentPipelineFactory,
+    ) -> Result<Option<(StudioRecordingActorState, StudioRecordingActor)>, StudioRecordingActorError>
+    {
+        let (cursors, next_cursor_id) = shutdown(pipeline, &mut actor, segment_start_time)
+            .await
+            .map_err(|e| StudioRecordingActorError::Other(e.to_string()))?;
+
+        if actor.segment_duration.is_some()
+            && let Err(e) = write_unfinished_meta(&actor, &cursors)
+        {
+            warn!("Failed to save meta for finished segments: {e}");
+        }
+
+        let (pipeline, pipeline_done_rx) = segment_pipeline_factory
+            .create_next(cursors, next_cursor_id)
+            .await
+            .map_err(|e| StudioRecordingActorError::Other(e.to_string()))?;
+
+        Ok(Some((
+            StudioRecordingActorState::Recording {
+                pipeline,
+                pipeline_done_rx,
+                index: index + 1,
+                segment_start_time: current_time_f64(),
+                segment_start_instant: Instant::now(),
+            },
+            actor,
+        )))
+    }
+
     // Log current state
     info!(
         "recording actor state: {:?}",
@@ -311,6 +555,26 @@ async fn run_actor_iteration(
         } => {
             tokio::select! {
                 result = &mut pipeline_done_rx => {
+                    if let Ok(Err(error)) = &result
+                        && actor.should_roll_over(error)
+                    {
+                        warn!("Rolling over to segment {} after encoder error: {error}", index + 1);
+                        actor.rollovers += 1;
+                        let _ = actor.rollover_tx.send(SegmentRollover {
+                            index: index + 1,
+                            error: error.clone(),
+                        });
+
+                        return roll_over(
+                            pipeline,
+                            index,
+                            segment_start_time,
+                            actor,
+                            segment_pipeline_factory,
+                        )
+                        .await;
+                    }
+
                     let res = match result {
                         Ok(Ok(())) => Ok(None),
                         Ok(Err(e)) => Err(StudioRecordingActorError::Other(e)),
@@ -324,6 +588,18 @@ async fn run_actor_iteration(
 
                     return res;
                 },
+                _ = segment_deadline(actor.segment_duration, segment_start_instant) => {
+                    info!("Segment {index} reached its maximum duration");
+
+                    return roll_over(
+                        pipeline,
+                        index,
+                        segment_start_time,
+                        actor,
+                        segment_pipeline_factory,
+                    )
+                    .await;
+                },
                 msg = ctrl_rx.recv_async() => {
                     match msg {
                         Ok(msg) => (
@@ -483,70 +759,22 @@ async fn stop_recording(
     actor: StudioRecordingActor,
     cursors: Cursors,
 ) -> Result<CompletedStudioRecording, RecordingError> {
-    use cap_project::*;
-
-    let make_relative = |path: &PathBuf| {
-        RelativePathBuf::from_path(path.strip_prefix(&actor.recording_dir).unwrap()).unwrap()
-    };
-
-    let recv_timestamp = |pipeline: &PipelineOutput| pipeline.first_timestamp_rx.try_recv().ok();
+    let meta = studio_meta(&actor, &cursors);
 
-    let meta = StudioRecordingMeta::MultipleSegments {
-        inner: MultipleSegments {
-            segments: {
-                actor
-                    .segments
-                    .iter()
-                    .map(|s| MultipleSegment {
-                        display: VideoMeta {
-                            path: make_relative(&s.pipeline.screen.inner.path),
-                            fps: actor.fps,
-                            start_time: recv_timestamp(&s.pipeline.screen.inner),
-                        },
-                        camera: s.pipeline.camera.as_ref().map(|camera| VideoMeta {
-                            path: make_relative(&camera.inner.path),
-                            fps: camera.fps,
-                            start_time: recv_timestamp(&camera.inner),
-                        }),
-                        mic: s.pipeline.microphone.as_ref().map(|mic| AudioMeta {
-                            path: make_relative(&mic.path),
-                            start_time: recv_timestamp(mic),
-                        }),
-                        cursor: s
-                            .pipeline
-                            .cursor
-                            .as_ref()
-                            .map(|cursor| make_relative(&cursor.output_path)),
-                        system_audio: s.pipeline.system_audio.as_ref().map(|audio| AudioMeta {
-                            path: make_relative(&audio.path),
-                            start_time: recv_timestamp(audio),
-                        }),
-                    })
-                    .collect()
-            },
-            cursors: cap_project::Cursors::Correct(
-                cursors
-                    .into_values()
-                    .map(|cursor| {
-                        (
-                            cursor.id.to_string(),
-                            CursorMeta {
-                                image_path: RelativePathBuf::from("content/cursors")
-                                    .join(&cursor.file_name),
-                                hotspot: cursor.hotspot,
-                                shape: cursor.shape,
-                            },
-                        )
-                    })
-                    .collect(),
-            ),
-        },
-    };
-
-    let project_config = cap_project::ProjectConfiguration::default();
-    project_config
-        .write(&actor.recording_dir)
-        .map_err(RecordingError::from)?;
+    if let Some(appended) = &actor.appended {
+        // the caller only saves the meta of new recordings
+        cap_project::RecordingMeta {
+            inner: cap_project::RecordingMetaInner::Studio(meta.clone()),
+            ..appended.meta.clone()
+        }
+        .save_for_project()
+        .map_err(|e| e.either(RecordingError::from, RecordingError::from))?;
+    } else {
+        let project_config = cap_project::ProjectConfiguration::default();
+        project_config
+            .write(&actor.recording_dir)
+            .map_err(RecordingError::from)?;
+    }
 
     Ok(CompletedStudioRecording {
         id: actor.id,
@@ -558,13 +786,124 @@ async fn stop_recording(
     })
 }
 
+/// Includes the segments and cursors of the recording being appended to, if there is one.
+fn studio_meta(actor: &StudioRecordingActor, cursors: &Cursors) -> StudioRecordingMeta {
+    use cap_project::MultipleSegments;
+
+    let (mut segments, mut cursor_metas) = match &actor.appended {
+        Some(appended) => (appended.segments.clone(), appended.cursors.clone()),
+        None => Default::default(),
+    };
+
+    segments.extend(actor.segments.iter().map(|s| s.meta.clone()));
+    cursor_metas.extend(cursors.values().map(|cursor| {
+        (
+            cursor.id.to_string(),
+            CursorMeta {
+                image_path: RelativePathBuf::from("content/cursors").join(&cursor.file_name),
+                hotspot: cursor.hotspot,
+                shape: cursor.shape,
+            },
+        )
+    }));
+
+    StudioRecordingMeta::MultipleSegments {
+        inner: MultipleSegments {
+            segments,
+            cursors: cap_project::Cursors::Correct(cursor_metas),
+        },
+    }
+}
+
+/// Must only be called once the segment's pipeline has stopped,
+/// as it takes the first timestamps each output received.
+fn segment_meta(
+    recording_dir: &Path,
+    fps: u32,
+    pipeline: &StudioRecordingPipeline,
+) -> cap_project::MultipleSegment {
+    use cap_project::{AudioMeta, MultipleSegment, VideoMeta};
+
+    let make_relative = |path: &PathBuf| {
+        RelativePathBuf::from_path(path.strip_prefix(recording_dir).unwrap()).unwrap()
+    };
+
+    let recv_timestamp = |pipeline: &PipelineOutput| pipeline.first_timestamp_rx.try_recv().ok();
+
+    MultipleSegment {
+        display: VideoMeta {
+            path: make_relative(&pipeline.screen.inner.path),
+            fps,
+            start_time: recv_timestamp(&pipeline.screen.inner),
+        },
+        camera: pipeline.camera.as_ref().map(|camera| VideoMeta {
+            path: make_relative(&camera.inner.path),
+            fps: camera.fps,
+            start_time: recv_timestamp(&camera.inner),
+        }),
+        mic: pipeline.microphone.as_ref().map(|mic| AudioMeta {
+            path: make_relative(&mic.path),
+            start_time: recv_timestamp(mic),
+        }),
+        cursor: pipeline
+            .cursor
+            .as_ref()
+            .map(|cursor| make_relative(&cursor.output_path)),
+        system_audio: pipeline.system_audio.as_ref().map(|audio| AudioMeta {
+            path: make_relative(&audio.path),
+            start_time: recv_timestamp(audio),
+        }),
+    }
+}
+
+/// Name given to recordings that were never stopped, until they're renamed.
+const UNFINISHED_RECORDING_NAME: &str = "Unfinished Recording";
+
+/// Saves the meta of the segments finished so far, so that the recording can still be
+/// opened if the app exits before it's stopped. Stopping the recording overwrites it.
+fn write_unfinished_meta(
+    actor: &StudioRecordingActor,
+    cursors: &Cursors,
+) -> Result<(), RecordingError> {
+    let inner = cap_project::RecordingMetaInner::Studio(studio_meta(actor, cursors));
+    let meta = match &actor.appended {
+        Some(appended) => cap_project::RecordingMeta {
+            inner,
+            ..appended.meta.clone()
+        },
+        None => cap_project::RecordingMeta {
+            platform: Some(cap_project::Platform::default()),
+            project_path: actor.recording_dir.clone(),
+            pretty_name: UNFINISHED_RECORDING_NAME.to_string(),
+            sharing: None,
+            inner,
+        },
+    };
+
+    meta.save_for_project()
+        .map_err(|e| e.either(RecordingError::from, RecordingError::from))?;
+
+    if !actor.recording_dir.join("project-config.json").exists() {
+        cap_project::ProjectConfiguration::default().write(&actor.recording_dir)?;
+    }
+
+    Ok(())
+}
+
+async fn segment_deadline(segment_duration: Option<Duration>, segment_start: Instant) {
+    match segment_duration {
+        Some(duration) => tokio::time::sleep_until((segment_start + duration).into()).await,
+        None => std::future::pending().await,
+    }
+}
+
 struct SegmentPipelineFactory {
     segments_dir: PathBuf,
     cursors_dir: PathBuf,
     base_inputs: RecordingBaseInputs,
     custom_cursor_capture: bool,
-    start_time: SystemTime,
-    start_instant: Instant,
+    clock: CaptureClock,
+    stats: Arc<RecordingStatsTracker>,
     index: u32,
 }
 
@@ -575,16 +914,16 @@ impl SegmentPipelineFactory {
         cursors_dir: PathBuf,
         base_inputs: RecordingBaseInputs,
         custom_cursor_capture: bool,
-        start_time: SystemTime,
-        start_instant: Instant,
+        clock: CaptureClock,
+        stats: Arc<RecordingStatsTracker>,
     ) -> Self {
         Self {
             segments_dir,
             cursors_dir,
             base_inputs,
             custom_cursor_capture,
-            start_time,
-            start_instant,
+            clock,
+            stats,
             index: 0,
         }
     }
@@ -608,11 +947,12 @@ impl SegmentPipelineFactory {
             self.base_inputs.mic_feed.clone(),
             self.base_inputs.capture_system_audio,
             self.base_inputs.camera_feed.clone(),
+            self.base_inputs.threaded_encoding,
             cursors,
             next_cursors_id,
             self.custom_cursor_capture,
-            self.start_time,
-            self.start_instant,
+            self.clock,
+            self.stats.clone(),
         )
         .await?;
 
@@ -650,11 +990,12 @@ async fn create_segment_pipeline(
     mic_feed: Option<Arc<MicrophoneFeedLock>>,
     capture_system_audio: bool,
     camera_feed: Option<Arc<CameraFeedLock>>,
+    threaded_encoding: bool,
     prev_cursors: Cursors,
     next_cursors_id: u32,
     custom_cursor_capture: bool,
-    start_time: SystemTime,
-    start_instant: Instant,
+    clock: CaptureClock,
+    stats: Arc<RecordingStatsTracker>,
 ) -> Result<
     (
         StudioRecordingPipeline,
@@ -684,7 +1025,7 @@ async fn create_segment_pipeline(
         !custom_cursor_capture,
         120,
         system_audio.0,
-        start_time,
+        clock,
         #[cfg(windows)]
         d3d_device,
     )
@@ -701,12 +1042,14 @@ async fn create_segment_pipeline(
 
     let screen = {
         let video_info = screen_source.info();
+        stats.track_dropped_frames(screen_source.dropped_frames_counter());
 
         let (pipeline_builder_, screen_timestamp_rx) =
             ScreenCaptureMethod::make_studio_mode_pipeline(
                 pipeline_builder,
                 (screen_source, screen_rx),
                 screen_output_path.clone(),
+                stats.clone(),
             )
             .unwrap();
         pipeline_builder = pipeline_builder_;
@@ -731,7 +1074,8 @@ async fn create_segment_pipeline(
     let microphone = if let Some(mic_feed) = mic_feed {
         let (tx, rx) = flume::bounded(8);
 
-        let mic_source = AudioInputSource::init(mic_feed, tx, start_time);
+        let mic_source = AudioInputSource::init(mic_feed, tx, clock)
+            .with_sample_format(OpusEncoder::SAMPLE_FORMAT);
 
         let mic_config = mic_source.info();
         let output_path = dir.join("audio-input.ogg");
@@ -746,19 +1090,28 @@ async fn create_segment_pipeline(
 
         let (timestamp_tx, timestamp_rx) = flume::bounded(1);
 
+        let stats = stats.clone();
         pipeline_builder.spawn_task("microphone_encoder", move |ready| {
             let mut timestamp_tx = Some(timestamp_tx);
             let _ = ready.send(Ok(()));
 
-            while let Ok(frame) = rx.recv() {
+            let result = loop {
+                let Ok(frame) = rx.recv() else {
+                    break Ok(());
+                };
+
                 if let Some(timestamp_tx) = timestamp_tx.take() {
                     timestamp_tx.send(frame.1).unwrap();
                 }
 
-                mic_encoder.queue_frame(frame.0);
-            }
-            mic_encoder.finish();
-            Ok(())
+                stats.record_audio_samples(frame.0.samples());
+                if let Err(e) = mic_encoder.queue_frame(frame.0) {
+                    break Err(format!("QueueFrame: {e}"));
+                }
+            };
+
+            let finished = mic_encoder.finish().map_err(|e| format!("Finish: {e}"));
+            result.and(finished)
         });
 
         info!(
@@ -787,19 +1140,30 @@ async fn create_segment_pipeline(
 
         let (timestamp_tx, timestamp_rx) = flume::bounded(1);
 
+        let stats = stats.clone();
         pipeline_builder.spawn_task("system_audio_encoder", move |ready| {
             let mut timestamp_tx = Some(timestamp_tx);
             let _ = ready.send(Ok(()));
 
-            while let Ok(frame) = channel.recv() {
+            let result = loop {
+                let Ok(frame) = channel.recv() else {
+                    break Ok(());
+                };
+
                 if let Some(timestamp_tx) = timestamp_tx.take() {
                     timestamp_tx.send(frame.1).unwrap();
                 }
 
-                system_audio_encoder.queue_frame(frame.0);
-            }
-            system_audio_encoder.finish();
-            Ok(())
+                stats.record_audio_samples(frame.0.samples());
+                if let Err(e) = system_audio_encoder.queue_frame(frame.0) {
+                    break Err(format!("QueueFrame: {e}"));
+                }
+            };
+
+            let finished = system_audio_encoder
+                .finish()
+                .map_err(|e| format!("Finish: {e}"));
+            result.and(finished)
         });
 
         Some(PipelineOutput {
@@ -813,7 +1177,7 @@ async fn create_segment_pipeline(
     let camera = if let Some(camera_feed) = camera_feed {
         let (tx, rx) = flume::bounded(8);
 
-        let camera_source = CameraSource::init(camera_feed, tx, start_instant);
+        let camera_source = CameraSource::init(camera_feed, tx, clock);
         let camera_config = camera_source.info();
         let output_path = dir.join("camera.mp4");
 
@@ -823,7 +1187,8 @@ async fn create_segment_pipeline(
             |o| H264Encoder::builder("camera", camera_config).build(o),
             |_| None,
         )
-        .map_err(|e| MediaError::Any(e.to_string().into()))?;
+        .map_err(|e| MediaError::Any(e.to_string().into()))?
+        .threaded_if(threaded_encoding.then_some(ThreadedMP4File::DEFAULT_MAX_LOOKAHEAD));
 
         pipeline_builder.spawn_source("camera_capture", camera_source);
 
@@ -834,7 +1199,11 @@ async fn create_segment_pipeline(
             let _ = ready.send(Ok(()));
 
             let mut start = None;
-            while let Ok(mut frame) = rx.recv() {
+            let result = loop {
+                let Ok(mut frame) = rx.recv() else {
+                    break Ok(());
+                };
+
                 if let Some(timestamp_tx) = timestamp_tx.take() {
                     timestamp_tx.send(frame.1).unwrap();
                 }
@@ -850,10 +1219,13 @@ async fn create_segment_pipeline(
                     frame.0.set_pts(Some(0));
                 }
 
-                camera_encoder.queue_video_frame(frame.0);
-            }
-            camera_encoder.finish();
-            Ok(())
+                if let Err(e) = camera_encoder.queue_video_frame(frame.0) {
+                    break Err(format!("QueueVideoFrame: {e}"));
+                }
+            };
+
+            let finished = camera_encoder.finish().map_err(|e| format!("Finish: {e}"));
+            result.and(finished)
         });
 
         info!(
@@ -889,7 +1261,7 @@ async fn create_segment_pipeline(
             cursors_dir.to_path_buf(),
             prev_cursors,
             next_cursors_id,
-            start_time,
+            clock,
         );
 
         CursorPipeline {
@@ -913,6 +1285,18 @@ async fn create_segment_pipeline(
     ))
 }
 
+/// Pipeline errors look like `Task/<name>/<error>`. Errors returned by encoder tasks can be
+/// recovered from by starting a new segment, but panics and failing sources can't.
+fn is_recoverable_error(error: &str) -> bool {
+    let mut parts = error.splitn(3, '/');
+
+    matches!(
+        (parts.next(), parts.next(), parts.next()),
+        (Some("Task"), Some(task), Some(error))
+            if task.ends_with("_encoder") && !error.starts_with("Panicked") && error != "Unknown"
+    )
+}
+
 struct CameraPipelineInfo {
     inner: PipelineOutput,
     fps: u32,
@@ -929,3 +1313,30 @@ fn current_time_f64() -> f64 {
         .unwrap()
         .as_secs_f64()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encoder_errors_are_recoverable() {
+        assert!(is_recoverable_error(
+            "Task/screen_capture_encoder/QueueVideoFrame: AppendError/failed"
+        ));
+        assert!(is_recoverable_error(
+            "Task/camera_encoder/Finish: Invalid data"
+        ));
+    }
+
+    #[test]
+    fn panics_and_unknown_results_are_not_recoverable() {
+        assert!(!is_recoverable_error("Task/camera_encoder/Panicked: boom"));
+        assert!(!is_recoverable_error("Task/microphone_encoder/Unknown"));
+    }
+
+    #[test]
+    fn errors_from_other_tasks_are_not_recoverable() {
+        assert!(!is_recoverable_error("Task/screen_capture/Failed"));
+        assert!(!is_recoverable_error("Task/camera_encoder"));
+    }
+}
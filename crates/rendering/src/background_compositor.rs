@@ -0,0 +1,334 @@
+use cap_media::{MediaError, data::RgbaFrame};
+use cap_project::{BackgroundConfiguration, XY};
+use image::{Rgba, RgbaImage, imageops};
+
+use crate::{
+    SCREEN_MAX_PADDING,
+    layers::{Background, Gradient},
+};
+
+/// A shadow cast by the frame onto the background, in pixels of the output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowParams {
+    /// How far the shadow extends past the frame before it starts fading out
+    pub size: f64,
+    /// Distance the shadow fades out over
+    pub blur: f64,
+    /// From 0 to 1
+    pub opacity: f32,
+}
+
+/// Places RGBA frames on a background with padding, rounded corners and a shadow,
+/// without needing a GPU.
+///
+/// Frames are scaled to fit inside the padding while keeping their aspect ratio, and
+/// the edges of the rounded corners and shadow are anti-aliased.
+pub struct BackgroundCompositor {
+    /// The background already drawn at the output size
+    background: RgbaImage,
+    padding: f64,
+    corner_radius: f64,
+    shadow: Option<ShadowParams>,
+}
+
+impl BackgroundCompositor {
+    /// Fails if the background is an image that can't be loaded.
+    pub fn new(background: &Background, output_size: XY<u32>) -> Result<Self, MediaError> {
+        Ok(Self {
+            background: draw_background(background, output_size)?,
+            padding: 0.0,
+            corner_radius: 0.0,
+            shadow: None,
+        })
+    }
+
+    /// Uses a project's background, padding, rounding and shadow, matching how
+    /// they're sized by the GPU renderer.
+    pub fn from_project(
+        config: &BackgroundConfiguration,
+        output_size: XY<u32>,
+    ) -> Result<Self, MediaError> {
+        let padding =
+            output_size.x.max(output_size.y) as f64 * config.padding / 100.0 * SCREEN_MAX_PADDING;
+        let min_axis = (output_size.x.min(output_size.y) as f64 - padding * 2.0).max(0.0);
+        let shadow = config.advanced_shadow.clone().unwrap_or_default();
+
+        let mut compositor = Self::new(&config.source.clone().into(), output_size)?
+            .with_padding(padding)
+            .with_corner_radius(config.rounding / 100.0 * 0.5 * min_axis);
+
+        if config.shadow > 0.0 {
+            compositor = compositor.with_shadow(ShadowParams {
+                size: shadow.size as f64 / 100.0 * padding,
+                blur: (shadow.blur as f64 / 100.0 * padding).max(1.0),
+                opacity: shadow.opacity / 100.0 * config.shadow / 100.0,
+            });
+        }
+
+        Ok(compositor)
+    }
+
+    /// Minimum space in pixels between the frame and the output's edges.
+    pub fn with_padding(mut self, padding: f64) -> Self {
+        self.padding = padding.max(0.0);
+        self
+    }
+
+    pub fn with_corner_radius(mut self, corner_radius: f64) -> Self {
+        self.corner_radius = corner_radius.max(0.0);
+        self
+    }
+
+    pub fn with_shadow(mut self, shadow: ShadowParams) -> Self {
+        self.shadow = Some(shadow);
+        self
+    }
+
+    pub fn output_size(&self) -> XY<u32> {
+        XY::new(self.background.width(), self.background.height())
+    }
+
+    /// Where the frame is drawn in the output, as its top-left corner and size.
+    pub fn frame_bounds(&self, frame_size: XY<u32>) -> (XY<f64>, XY<f64>) {
+        let output = self.output_size();
+        let output = XY::new(output.x as f64, output.y as f64);
+        let available = XY::new(
+            (output.x - self.padding * 2.0).max(1.0),
+            (output.y - self.padding * 2.0).max(1.0),
+        );
+
+        let scale = (available.x / frame_size.x.max(1) as f64)
+            .min(available.y / frame_size.y.max(1) as f64);
+        let size = XY::new(
+            (frame_size.x as f64 * scale).round(),
+            (frame_size.y as f64 * scale).round(),
+        );
+
+        (
+            XY::new(
+                ((output.x - size.x) / 2.0).round(),
+                ((output.y - size.y) / 2.0).round(),
+            ),
+            size,
+        )
+    }
+
+    /// Composites a frame onto the background at the output size.
+    pub fn composite(&self, frame: RgbaFrame) -> RgbaFrame {
+        let (top_left, size) = self.frame_bounds(XY::new(frame.width(), frame.height()));
+        let scaled = imageops::resize(
+            &frame.into_image(),
+            size.x as u32,
+            size.y as u32,
+            imageops::FilterType::Triangle,
+        );
+
+        let mut output = self.background.clone();
+        let rect = RoundedRect {
+            top_left,
+            size,
+            radius: self.corner_radius.min(size.x / 2.0).min(size.y / 2.0),
+        };
+
+        for (x, y, pixel) in output.enumerate_pixels_mut() {
+            let distance = rect.distance(x as f64 + 0.5, y as f64 + 0.5);
+
+            if let Some(shadow) = self.shadow
+                && distance > -1.0
+            {
+                let fade = ((distance - shadow.size) / shadow.blur).clamp(0.0, 1.0);
+                let alpha = shadow.opacity * (1.0 - smoothstep(fade as f32));
+                blend(pixel, [0, 0, 0], alpha);
+            }
+
+            let coverage = (0.5 - distance).clamp(0.0, 1.0) as f32;
+            if coverage > 0.0 {
+                let frame_pixel = scaled.get_pixel(
+                    (x as f64 - top_left.x).clamp(0.0, size.x - 1.0) as u32,
+                    (y as f64 - top_left.y).clamp(0.0, size.y - 1.0) as u32,
+                );
+                blend(
+                    pixel,
+                    [frame_pixel[0], frame_pixel[1], frame_pixel[2]],
+                    coverage * frame_pixel[3] as f32 / 255.0,
+                );
+            }
+        }
+
+        output.into()
+    }
+}
+
+struct RoundedRect {
+    top_left: XY<f64>,
+    size: XY<f64>,
+    radius: f64,
+}
+
+impl RoundedRect {
+    /// Signed distance from the edge, negative inside the rectangle.
+    fn distance(&self, x: f64, y: f64) -> f64 {
+        let half = XY::new(self.size.x / 2.0, self.size.y / 2.0);
+        let dx = (x - self.top_left.x - half.x).abs() - (half.x - self.radius);
+        let dy = (y - self.top_left.y - half.y).abs() - (half.y - self.radius);
+
+        let outside = (dx.max(0.0).powi(2) + dy.max(0.0).powi(2)).sqrt();
+        let inside = dx.max(dy).min(0.0);
+
+        outside + inside - self.radius
+    }
+}
+
+pub(crate) fn draw_background(
+    background: &Background,
+    size: XY<u32>,
+) -> Result<RgbaImage, MediaError> {
+    Ok(match background {
+        Background::Color(color) => RgbaImage::from_pixel(size.x, size.y, linear_to_rgba(*color)),
+        Background::Gradient(gradient) => draw_gradient(gradient, size),
+        Background::Image { path } => {
+            let image = image::open(path)
+                .map_err(|e| MediaError::Any(format!("Background image '{path}' / {e}").into()))?
+                .into_rgba8();
+
+            // cover the output, cropping whichever axis overflows
+            let scale =
+                (size.x as f64 / image.width() as f64).max(size.y as f64 / image.height() as f64);
+            let scaled = imageops::resize(
+                &image,
+                ((image.width() as f64 * scale).ceil() as u32).max(size.x),
+                ((image.height() as f64 * scale).ceil() as u32).max(size.y),
+                imageops::FilterType::Triangle,
+            );
+
+            imageops::crop_imm(
+                &scaled,
+                (scaled.width() - size.x) / 2,
+                (scaled.height() - size.y) / 2,
+                size.x,
+                size.y,
+            )
+            .to_image()
+        }
+    })
+}
+
+/// Matches the gradient shader, which mixes the linear colors along the angle.
+fn draw_gradient(gradient: &Gradient, size: XY<u32>) -> RgbaImage {
+    let angle = (gradient.angle as f64 + 270.0).to_radians();
+    let direction = (angle.cos(), angle.sin());
+
+    RgbaImage::from_fn(size.x, size.y, |x, y| {
+        let u = (x as f64 + 0.5) / size.x as f64 - 0.5;
+        let v = (y as f64 + 0.5) / size.y as f64 - 0.5;
+        let t = ((u * direction.0 + v * direction.1) + 0.5).clamp(0.0, 1.0) as f32;
+
+        let mut color = [0.0; 4];
+        for (i, c) in color.iter_mut().enumerate() {
+            *c = gradient.start[i] + (gradient.end[i] - gradient.start[i]) * t;
+        }
+        color[3] = 1.0;
+
+        linear_to_rgba(color)
+    })
+}
+
+fn linear_to_rgba(color: [f32; 4]) -> Rgba<u8> {
+    let encode = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        let c = if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        };
+        (c * 255.0).round() as u8
+    };
+
+    Rgba([
+        encode(color[0]),
+        encode(color[1]),
+        encode(color[2]),
+        (color[3].clamp(0.0, 1.0) * 255.0).round() as u8,
+    ])
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Blends the color channels, keeping the output opaque.
+fn blend(pixel: &mut Rgba<u8>, color: [u8; 3], alpha: f32) {
+    for (value, color) in pixel.0.iter_mut().zip(color) {
+        *value = (color as f32 * alpha + *value as f32 * (1.0 - alpha)).round() as u8;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(width: u32, height: u32, color: [u8; 4]) -> RgbaFrame {
+        RgbaFrame::packed(color.repeat((width * height) as usize), width, height).unwrap()
+    }
+
+    #[test]
+    fn fits_frame_inside_padding() {
+        let compositor =
+            BackgroundCompositor::new(&Background::Color([0.0, 0.0, 0.0, 1.0]), XY::new(200, 100))
+                .unwrap()
+                .with_padding(10.0);
+
+        assert_eq!(
+            compositor.frame_bounds(XY::new(400, 200)),
+            (XY::new(20.0, 10.0), XY::new(160.0, 80.0))
+        );
+    }
+
+    #[test]
+    fn rounds_frame_corners() {
+        let compositor =
+            BackgroundCompositor::new(&Background::Color([0.0, 0.0, 0.0, 1.0]), XY::new(20, 20))
+                .unwrap()
+                .with_corner_radius(5.0);
+
+        let output = compositor.composite(frame(20, 20, [255; 4]));
+        let pixel = |x: u32, y: u32| output.pixel(x, y)[0];
+
+        assert_eq!(pixel(0, 0), 0);
+        assert_eq!(pixel(10, 10), 255);
+        assert_eq!(pixel(10, 0), 255);
+        assert!(pixel(1, 1) < 255);
+    }
+
+    #[test]
+    fn casts_shadow_outside_frame() {
+        let compositor =
+            BackgroundCompositor::new(&Background::Color([1.0, 1.0, 1.0, 1.0]), XY::new(40, 40))
+                .unwrap()
+                .with_padding(10.0)
+                .with_shadow(ShadowParams {
+                    size: 2.0,
+                    blur: 4.0,
+                    opacity: 1.0,
+                });
+
+        let output = compositor.composite(frame(20, 20, [255; 4]));
+        let pixel = |x: u32, y: u32| output.pixel(x, y)[0];
+
+        assert_eq!(pixel(8, 20), 0);
+        assert!(pixel(5, 20) > 0 && pixel(5, 20) < 255);
+        assert_eq!(pixel(0, 20), 255);
+    }
+
+    #[test]
+    fn draws_gradient_along_angle() {
+        let gradient = Gradient {
+            start: [0.0, 0.0, 0.0, 1.0],
+            end: [1.0, 1.0, 1.0, 1.0],
+            angle: 90.0,
+        };
+        let image = draw_gradient(&gradient, XY::new(10, 1));
+
+        assert!(image.get_pixel(0, 0)[0] < image.get_pixel(9, 0)[0]);
+    }
+}
@@ -0,0 +1,361 @@
+use cap_media::{data::RgbaFrame, filters::Corner};
+use std::collections::VecDeque;
+
+/// How far a camera frame's timestamp can be after a screen frame's and still be shown
+/// with it, to absorb the float error of timestamps computed from frame numbers.
+const TIME_EPSILON: f64 = 1e-6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraOverlayShape {
+    Circle,
+    /// `radius` is a fraction of the overlay's size,
+    /// from 0 for square corners to 0.5, which makes a circle.
+    RoundedRect {
+        radius: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraOverlayOptions {
+    pub shape: CameraOverlayShape,
+    pub corner: Corner,
+    /// Width and height of the overlay, as a fraction of the screen frame's shorter side.
+    pub size: f32,
+    /// Distance from the edges of the screen frame, in pixels.
+    pub margin: u32,
+    /// Width of the border drawn inside the edge of the shape, in pixels.
+    pub border_width: u32,
+    pub border_color: [u8; 3],
+    /// Flips the camera horizontally, so it looks like a mirror.
+    pub mirror: bool,
+}
+
+impl Default for CameraOverlayOptions {
+    fn default() -> Self {
+        Self {
+            shape: CameraOverlayShape::Circle,
+            corner: Corner::BottomRight,
+            size: 0.25,
+            margin: 32,
+            border_width: 0,
+            border_color: [255, 255, 255],
+            mirror: false,
+        }
+    }
+}
+
+/// Composites the camera feed onto screen frames on the CPU, as a circle or rounded square
+/// in a corner of the frame. The camera is cropped to a square around its center.
+///
+/// Camera frames are matched to screen frames by timestamp, with each screen frame showing
+/// the latest camera frame captured at or before it. When the camera has a lower frame rate
+/// than the screen its frames are held until the next one is due, and when it's higher
+/// the frames in between are skipped.
+pub struct CameraOverlayFilter {
+    options: CameraOverlayOptions,
+    /// Camera frames and their timestamps, with the first one being the latest that's due.
+    camera: VecDeque<(f64, RgbaFrame)>,
+}
+
+impl CameraOverlayFilter {
+    pub fn new(options: CameraOverlayOptions) -> Self {
+        Self {
+            options,
+            camera: VecDeque::new(),
+        }
+    }
+
+    /// Queues a camera frame to be shown from `time`, in seconds on the same timeline as
+    /// the screen frames. Frames must be pushed in order.
+    pub fn push_camera_frame(&mut self, time: f64, frame: RgbaFrame) {
+        self.camera.push_back((time, frame));
+    }
+
+    /// Blends the camera frame due at `time` onto a copy of `screen`. The screen frame is
+    /// returned as is when no camera frame is due yet.
+    pub fn apply(&mut self, screen: &RgbaFrame, time: f64) -> RgbaFrame {
+        while self
+            .camera
+            .get(1)
+            .is_some_and(|(next, _)| *next <= time + TIME_EPSILON)
+        {
+            self.camera.pop_front();
+        }
+
+        let Some((_, camera)) = self
+            .camera
+            .front()
+            .filter(|(start, _)| *start <= time + TIME_EPSILON)
+        else {
+            return screen.clone();
+        };
+
+        let Some((x, y, size)) = self.placement(screen.width(), screen.height()) else {
+            return screen.clone();
+        };
+
+        let mut output = screen.clone();
+        let stride = output.stride() as usize;
+        self.blend(
+            output.data_mut(),
+            stride,
+            camera,
+            (x as usize, y as usize),
+            size,
+        );
+
+        output
+    }
+
+    /// Top-left corner and size of the overlay, or `None` if it doesn't fit in the frame.
+    fn placement(&self, width: u32, height: u32) -> Option<(u32, u32, u32)> {
+        let margin = self.options.margin;
+        let max_size = width
+            .min(height)
+            .checked_sub(margin.saturating_mul(2))
+            .filter(|v| *v > 0)?;
+        let size = ((width.min(height) as f32 * self.options.size.clamp(0.0, 1.0)).round() as u32)
+            .min(max_size);
+        if size == 0 {
+            return None;
+        }
+
+        let (right, bottom) = (width - margin - size, height - margin - size);
+
+        Some(match self.options.corner {
+            Corner::TopLeft => (margin, margin, size),
+            Corner::TopRight => (right, margin, size),
+            Corner::BottomLeft => (margin, bottom, size),
+            Corner::BottomRight => (right, bottom, size),
+        })
+    }
+
+    fn blend(
+        &self,
+        data: &mut [u8],
+        stride: usize,
+        camera: &RgbaFrame,
+        (x, y): (usize, usize),
+        size: u32,
+    ) {
+        let half = size as f32 / 2.0;
+        let radius = match self.options.shape {
+            CameraOverlayShape::Circle => half,
+            CameraOverlayShape::RoundedRect { radius } => radius.clamp(0.0, 0.5) * size as f32,
+        };
+        let border = self.options.border_width as f32;
+
+        // a square from the middle of the camera frame, scaled to the overlay
+        let side = camera.width().min(camera.height()) as f32;
+        let crop = (
+            (camera.width() as f32 - side) / 2.0,
+            (camera.height() as f32 - side) / 2.0,
+        );
+        let scale = side / size as f32;
+
+        for overlay_y in 0..size as usize {
+            let row_start = (y + overlay_y) * stride + x * 4;
+            let center_y = overlay_y as f32 + 0.5;
+
+            for overlay_x in 0..size as usize {
+                let center_x = overlay_x as f32 + 0.5;
+
+                let distance =
+                    rounded_square_distance(center_x - half, center_y - half, half, radius);
+                let coverage = (0.5 - distance).clamp(0.0, 1.0);
+                if coverage == 0.0 {
+                    continue;
+                }
+                let inside_border = (0.5 - (distance + border)).clamp(0.0, 1.0);
+
+                let u = if self.options.mirror {
+                    side - center_x * scale
+                } else {
+                    center_x * scale
+                };
+                let color = sample(camera, crop.0 + u - 0.5, crop.1 + center_y * scale - 0.5);
+
+                let offset = row_start + overlay_x * 4;
+                let border_color = self.options.border_color;
+                for (channel, (color, border_color)) in color.iter().zip(border_color).enumerate() {
+                    let overlay =
+                        color * inside_border + border_color as f32 * (1.0 - inside_border);
+                    let value = &mut data[offset + channel];
+                    *value = (overlay * coverage + *value as f32 * (1.0 - coverage)).round() as u8;
+                }
+                let alpha = &mut data[offset + 3];
+                *alpha = (255.0 * coverage + *alpha as f32 * (1.0 - coverage)).round() as u8;
+            }
+        }
+    }
+}
+
+/// Signed distance from a point to the edge of a square with rounded corners, centered on
+/// the origin. Negative inside the square.
+fn rounded_square_distance(x: f32, y: f32, half: f32, radius: f32) -> f32 {
+    let qx = x.abs() - (half - radius);
+    let qy = y.abs() - (half - radius);
+
+    qx.max(0.0).hypot(qy.max(0.0)) + qx.max(qy).min(0.0) - radius
+}
+
+/// Bilinearly samples the RGB color of an RGBA frame, clamping to its edges.
+fn sample(frame: &RgbaFrame, x: f32, y: f32) -> [f32; 3] {
+    let max_x = frame.width().saturating_sub(1) as f32;
+    let max_y = frame.height().saturating_sub(1) as f32;
+    let (x, y) = (x.clamp(0.0, max_x), y.clamp(0.0, max_y));
+
+    let (x0, y0) = (x.floor(), y.floor());
+    let (x1, y1) = ((x0 + 1.0).min(max_x), (y0 + 1.0).min(max_y));
+    let (fx, fy) = (x - x0, y - y0);
+
+    let pixel = |x: f32, y: f32| frame.pixel(x as u32, y as u32);
+    let (top_left, top_right) = (pixel(x0, y0), pixel(x1, y0));
+    let (bottom_left, bottom_right) = (pixel(x0, y1), pixel(x1, y1));
+
+    std::array::from_fn(|i| {
+        let top = top_left[i] as f32 * (1.0 - fx) + top_right[i] as f32 * fx;
+        let bottom = bottom_left[i] as f32 * (1.0 - fx) + bottom_right[i] as f32 * fx;
+        top * (1.0 - fy) + bottom * fy
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn solid(width: u32, height: u32, color: [u8; 4]) -> RgbaFrame {
+        RgbaFrame::packed(color.repeat((width * height) as usize), width, height).unwrap()
+    }
+
+    fn pixel(frame: &RgbaFrame, x: u32, y: u32) -> [u8; 4] {
+        frame.pixel(x, y)
+    }
+
+    fn full_frame(shape: CameraOverlayShape) -> CameraOverlayOptions {
+        CameraOverlayOptions {
+            shape,
+            size: 1.0,
+            margin: 0,
+            ..Default::default()
+        }
+    }
+
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+
+    #[test]
+    fn holds_camera_frames_for_faster_screen() {
+        let mut filter =
+            CameraOverlayFilter::new(full_frame(CameraOverlayShape::RoundedRect { radius: 0.0 }));
+        let screen = solid(8, 8, BLACK);
+
+        // 30fps camera onto a 60fps screen
+        filter.push_camera_frame(0.0, solid(4, 4, RED));
+        filter.push_camera_frame(1.0 / 30.0, solid(4, 4, GREEN));
+
+        let colors = (0..4)
+            .map(|frame| {
+                let output = filter.apply(&screen, frame as f64 / 60.0);
+                pixel(&output, 4, 4)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(colors, [RED, RED, GREEN, GREEN]);
+    }
+
+    #[test]
+    fn skips_camera_frames_for_slower_screen() {
+        let mut filter = CameraOverlayFilter::new(full_frame(CameraOverlayShape::Circle));
+        let screen = solid(8, 8, BLACK);
+
+        for (frame, color) in [RED, GREEN, RED, GREEN].into_iter().enumerate() {
+            filter.push_camera_frame(frame as f64 / 60.0, solid(4, 4, color));
+        }
+
+        assert_eq!(pixel(&filter.apply(&screen, 0.0), 4, 4), RED);
+        assert_eq!(pixel(&filter.apply(&screen, 1.0 / 30.0), 4, 4), RED);
+        assert_eq!(filter.camera.len(), 2);
+    }
+
+    #[test]
+    fn leaves_screen_alone_until_camera_starts() {
+        let mut filter = CameraOverlayFilter::new(full_frame(CameraOverlayShape::Circle));
+        let screen = solid(8, 8, BLACK);
+        filter.push_camera_frame(0.5, solid(4, 4, RED));
+
+        assert_eq!(pixel(&filter.apply(&screen, 0.25), 4, 4), BLACK);
+        assert_eq!(pixel(&filter.apply(&screen, 0.5), 4, 4), RED);
+    }
+
+    #[test]
+    fn masks_camera_to_a_circle_with_a_border() {
+        let mut filter = CameraOverlayFilter::new(CameraOverlayOptions {
+            border_width: 2,
+            border_color: [0, 0, 255],
+            ..full_frame(CameraOverlayShape::Circle)
+        });
+        filter.push_camera_frame(0.0, solid(16, 16, RED));
+
+        let output = filter.apply(&solid(16, 16, BLACK), 0.0);
+
+        assert_eq!(pixel(&output, 0, 0), BLACK);
+        assert_eq!(pixel(&output, 15, 15), BLACK);
+        assert_eq!(pixel(&output, 8, 1), [0, 0, 255, 255]);
+        assert_eq!(pixel(&output, 8, 8), RED);
+    }
+
+    #[test]
+    fn places_overlay_in_corner() {
+        let mut filter = CameraOverlayFilter::new(CameraOverlayOptions {
+            shape: CameraOverlayShape::RoundedRect { radius: 0.0 },
+            corner: Corner::TopRight,
+            size: 0.25,
+            margin: 2,
+            ..Default::default()
+        });
+        filter.push_camera_frame(0.0, solid(4, 4, RED));
+
+        let output = filter.apply(&solid(32, 16, BLACK), 0.0);
+
+        // 4x4, 2 pixels in from the top and right edges
+        assert_eq!(pixel(&output, 26, 2), RED);
+        assert_eq!(pixel(&output, 29, 5), RED);
+        assert_eq!(pixel(&output, 30, 2), BLACK);
+        assert_eq!(pixel(&output, 26, 6), BLACK);
+        assert_eq!(pixel(&output, 2, 2), BLACK);
+    }
+
+    #[test]
+    fn mirrors_camera() {
+        let camera = RgbaFrame::packed([RED, GREEN, RED, GREEN].concat(), 2, 2).unwrap();
+        let options = full_frame(CameraOverlayShape::RoundedRect { radius: 0.0 });
+        let screen = solid(2, 2, BLACK);
+
+        let mut filter = CameraOverlayFilter::new(options);
+        filter.push_camera_frame(0.0, camera.clone());
+        let output = filter.apply(&screen, 0.0);
+        assert_eq!([pixel(&output, 0, 0), pixel(&output, 1, 0)], [RED, GREEN]);
+
+        let mut filter = CameraOverlayFilter::new(CameraOverlayOptions {
+            mirror: true,
+            ..options
+        });
+        filter.push_camera_frame(0.0, camera);
+        let output = filter.apply(&screen, 0.0);
+        assert_eq!([pixel(&output, 0, 0), pixel(&output, 1, 0)], [GREEN, RED]);
+    }
+
+    #[test]
+    fn rejects_frames_that_arent_rgba() {
+        let nv12 = crate::DecodedFrame {
+            data: std::sync::Arc::new(vec![0; 8 * 8 * 3 / 2]),
+            width: 8,
+            height: 8,
+            stride: 8,
+        };
+
+        assert!(RgbaFrame::try_from(nv12).is_err());
+    }
+}
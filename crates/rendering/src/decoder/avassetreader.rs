@@ -1,24 +1,32 @@
 use std::{
     cell::RefCell,
-    collections::BTreeMap,
     path::PathBuf,
     rc::Rc,
-    sync::{Arc, mpsc},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
 };
 
 use cidre::{
     arc::R,
     cv::{self, pixel_buffer::LockFlags},
 };
-use ffmpeg::{Rational, format, frame};
+use ffmpeg::{Rational, color, format};
 use tokio::{runtime::Handle as TokioHandle, sync::oneshot};
 
-use super::{FRAME_CACHE_SIZE, VideoDecoderMessage, pts_to_frame};
+use super::{
+    CACHE_KEEP_MARGIN, ColorInfo, CorruptFramePolicy, DecodedFrame, DecoderError, DecoderOptions,
+    DecoderOutputFormat, FRAME_CACHE_SIZE, FRAME_POOL_SIZE, FrameCache, FramePool, Rotation,
+    VideoDecoderMessage, convert_frame, first_uncached, pack_frame, pts_to_frame, rotate_frame,
+};
+use crate::FrameRate;
 
 #[derive(Clone)]
 struct ProcessedFrame {
     number: u32,
-    data: Arc<Vec<u8>>,
+    data: DecodedFrame,
 }
 
 #[derive(Clone)]
@@ -31,20 +39,24 @@ enum CachedFrame {
 }
 
 impl CachedFrame {
-    fn process(&mut self) -> ProcessedFrame {
+    fn process(
+        &mut self,
+        color: ColorInfo,
+        output_format: DecoderOutputFormat,
+        rotation: Rotation,
+        pool: &mut FramePool,
+    ) -> Result<ProcessedFrame, DecoderError> {
         match self {
             CachedFrame::Raw { image_buf, number } => {
                 let format = cap_video_decode::avassetreader::pixel_format_to_pixel(
                     image_buf.pixel_format(),
                 );
 
-                let data = if matches!(format, format::Pixel::RGBA) {
-                    unsafe {
-                        image_buf
-                            .lock_base_addr(LockFlags::READ_ONLY)
-                            .result()
-                            .unwrap()
-                    };
+                let data = if matches!(format, format::Pixel::RGBA)
+                    && output_format == DecoderOutputFormat::Rgba
+                {
+                    unsafe { image_buf.lock_base_addr(LockFlags::READ_ONLY).result() }
+                        .map_err(|e| DecoderError::Decode(format!("lock image buffer / {e:?}")))?;
 
                     let bytes_per_row = image_buf.plane_bytes_per_row(0);
                     let width = image_buf.width();
@@ -57,33 +69,39 @@ impl CachedFrame {
                         )
                     };
 
-                    let mut bytes = Vec::with_capacity(width * height * 4);
+                    let mut bytes = pool.take(width * height * 4);
 
                     let row_length = width * 4;
 
                     for i in 0..height {
-                        bytes.as_mut_slice()[i * row_length..((i + 1) * row_length)]
-                            .copy_from_slice(
-                                &slice[i * bytes_per_row..(i * bytes_per_row + row_length)],
-                            )
+                        bytes.extend_from_slice(
+                            &slice[i * bytes_per_row..(i * bytes_per_row + row_length)],
+                        )
                     }
 
                     unsafe { image_buf.unlock_lock_base_addr(LockFlags::READ_ONLY) };
 
-                    bytes
+                    DecodedFrame {
+                        data: pool.track(bytes),
+                        width: width as u32,
+                        height: height as u32,
+                        stride: row_length as u32,
+                    }
                 } else {
+                    if !matches!(format, format::Pixel::NV12 | format::Pixel::YUV420P) {
+                        return Err(DecoderError::Decode(format!(
+                            "unsupported pixel format {format:?}"
+                        )));
+                    }
+
                     let mut ffmpeg_frame = ffmpeg::frame::Video::new(
                         format,
                         image_buf.width() as u32,
                         image_buf.height() as u32,
                     );
 
-                    unsafe {
-                        image_buf
-                            .lock_base_addr(LockFlags::READ_ONLY)
-                            .result()
-                            .unwrap()
-                    };
+                    unsafe { image_buf.lock_base_addr(LockFlags::READ_ONLY).result() }
+                        .map_err(|e| DecoderError::Decode(format!("lock image buffer / {e:?}")))?;
 
                     match ffmpeg_frame.format() {
                         format::Pixel::NV12 => {
@@ -136,51 +154,30 @@ impl CachedFrame {
                                 }
                             }
                         }
-                        format => todo!("implement {:?}", format),
+                        _ => unreachable!(),
                     }
 
                     unsafe { image_buf.unlock_lock_base_addr(LockFlags::READ_ONLY) };
 
-                    let mut converter = ffmpeg::software::converter(
-                        (ffmpeg_frame.width(), ffmpeg_frame.height()),
-                        ffmpeg_frame.format(),
-                        format::Pixel::RGBA,
-                    )
-                    .unwrap();
-
-                    let mut rgb_frame = frame::Video::empty();
-                    converter.run(&ffmpeg_frame, &mut rgb_frame).unwrap();
-
-                    let slice = rgb_frame.data(0);
-                    let width = rgb_frame.width();
-                    let height = rgb_frame.height();
-                    let bytes_per_row = rgb_frame.stride(0);
-                    let row_length = width * 4;
-
-                    let mut bytes = vec![0; (width * height * 4) as usize];
-
-                    // TODO: allow for decoded frames to have stride, handle stride in shaders
-                    for i in 0..height as usize {
-                        bytes.as_mut_slice()[i * row_length as usize..(i + 1) * row_length as usize]
-                            .copy_from_slice(
-                                &slice
-                                    [(i * bytes_per_row)..i * bytes_per_row + row_length as usize],
-                            )
-                    }
+                    let output_frame = if ffmpeg_frame.format() != output_format.pixel() {
+                        convert_frame(&ffmpeg_frame, output_format, color)?
+                    } else {
+                        ffmpeg_frame
+                    };
 
-                    bytes
+                    pack_frame(&output_frame, output_format, pool)
                 };
 
                 let data = ProcessedFrame {
                     number: *number,
-                    data: Arc::new(data),
+                    data: rotate_frame(data, output_format, rotation, pool),
                 };
 
                 *self = Self::Processed(data.clone());
 
-                data
+                Ok(data)
             }
-            CachedFrame::Processed(data) => data.clone(),
+            CachedFrame::Processed(data) => Ok(data.clone()),
         }
     }
 }
@@ -203,25 +200,48 @@ impl AVAssetReaderDecoder {
     }
 
     pub fn spawn(
-        name: &'static str,
         path: PathBuf,
-        fps: u32,
+        frame_rate: FrameRate,
+        rotation: Rotation,
+        options: DecoderOptions,
         rx: mpsc::Receiver<VideoDecoderMessage>,
         ready_tx: oneshot::Sender<Result<(), String>>,
-    ) {
+        skipped_frames: Arc<AtomicUsize>,
+    ) -> std::thread::JoinHandle<()> {
         let handle = tokio::runtime::Handle::current();
 
-        std::thread::spawn(move || Self::run(name, path, fps, rx, ready_tx, handle));
+        std::thread::spawn(move || {
+            Self::run(
+                path,
+                frame_rate,
+                rotation,
+                options,
+                rx,
+                ready_tx,
+                handle,
+                skipped_frames,
+            )
+        })
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn run(
-        _name: &'static str,
         path: PathBuf,
-        fps: u32,
+        frame_rate: FrameRate,
+        rotation: Rotation,
+        options: DecoderOptions,
         rx: mpsc::Receiver<VideoDecoderMessage>,
         ready_tx: oneshot::Sender<Result<(), String>>,
         tokio_handle: tokio::runtime::Handle,
+        skipped_frames: Arc<AtomicUsize>,
     ) {
+        let DecoderOptions {
+            output_format,
+            corrupt_frame_policy,
+            color_override,
+            ..
+        } = options;
+
         let mut this = match AVAssetReaderDecoder::new(path, tokio_handle) {
             Ok(v) => {
                 ready_tx.send(Ok(())).ok();
@@ -233,163 +253,259 @@ impl AVAssetReaderDecoder {
             }
         };
 
-        let mut cache = BTreeMap::<u32, CachedFrame>::new();
+        let mut cache = FrameCache::<CachedFrame>::new(FRAME_CACHE_SIZE, CACHE_KEEP_MARGIN);
+        let mut pool = FramePool::new(FRAME_POOL_SIZE);
+        // the reader doesn't expose the stream's color metadata, so it's filled in per frame
+        let color = color_override.unwrap_or(ColorInfo {
+            space: color::Space::Unspecified,
+            range: color::Range::Unspecified,
+        });
 
-        #[allow(unused)]
-        let mut last_active_frame = None::<u32>;
         let last_sent_frame = Rc::new(RefCell::new(None::<ProcessedFrame>));
 
         let mut frames = this.inner.frames();
+        // a message that arrived while prefetching, handled before waiting for the next one
+        let mut pending = None::<VideoDecoderMessage>;
 
-        while let Ok(r) = rx.recv() {
+        while let Some(r) = pending.take().or_else(|| rx.recv().ok()) {
             match r {
-                VideoDecoderMessage::GetFrame(requested_time, sender) => {
-                    let requested_frame = (requested_time * fps as f32).floor() as u32;
-
-                    let mut sender = if let Some(cached) = cache.get_mut(&requested_frame) {
-                        let data = cached.process();
-
-                        sender.send(data.data.clone()).ok();
-                        *last_sent_frame.borrow_mut() = Some(data);
-                        continue;
-                    } else {
-                        let last_sent_frame = last_sent_frame.clone();
-                        Some(move |data: ProcessedFrame| {
-                            *last_sent_frame.borrow_mut() = Some(data.clone());
-                            let _ = sender.send(data.data);
-                        })
-                    };
-
-                    let cache_min = requested_frame.saturating_sub(FRAME_CACHE_SIZE as u32 / 2);
-                    let cache_max = requested_frame + FRAME_CACHE_SIZE as u32 / 2;
-
-                    if requested_frame == 0
-                        || last_sent_frame
-                            .borrow()
-                            .as_ref()
-                            .map(|last| {
-                                requested_frame < last.number
-                                // seek forward for big jumps. this threshold is arbitrary but should be derived from i-frames in future
-                                || requested_frame - last.number > FRAME_CACHE_SIZE as u32
-                            })
-                            .unwrap_or(true)
-                    {
-                        this.reset(requested_time);
-                        frames = this.inner.frames();
-                    }
-
-                    last_active_frame = Some(requested_frame);
+                VideoDecoderMessage::GetFrames(range, sender) => {
+                    for requested_frame in range {
+                        // the stream was dropped
+                        if sender.is_closed() {
+                            break;
+                        }
 
-                    let mut exit = false;
+                        let requested_time = frame_rate.frame_time(requested_frame) as f32;
 
-                    for frame in &mut frames {
-                        let Ok(frame) = frame.map_err(|e| format!("read frame / {e}")) else {
+                        let mut sender = if let Some(cached) = cache.get_mut(requested_frame) {
+                            match cached.process(color, output_format, rotation, &mut pool) {
+                                Ok(data) => {
+                                    sender.send(Ok((requested_frame, data.data.clone())));
+                                    *last_sent_frame.borrow_mut() = Some(data);
+                                }
+                                Err(e) => sender.send(Err(e)),
+                            }
                             continue;
+                        } else {
+                            let last_sent_frame = last_sent_frame.clone();
+                            let sender = &sender;
+                            Some(
+                                move |data: Result<ProcessedFrame, DecoderError>| match data {
+                                    Ok(data) => {
+                                        *last_sent_frame.borrow_mut() = Some(data.clone());
+                                        sender.send(Ok((requested_frame, data.data)));
+                                    }
+                                    Err(e) => sender.send(Err(e)),
+                                },
+                            )
                         };
 
-                        let current_frame = pts_to_frame(
-                            frame.pts().value,
-                            Rational::new(1, frame.pts().scale),
-                            fps,
-                        );
-
-                        let Some(frame) = frame.image_buf() else {
-                            continue;
-                        };
+                        let cache_min = requested_frame.saturating_sub(FRAME_CACHE_SIZE as u32 / 2);
+                        let cache_max = requested_frame + FRAME_CACHE_SIZE as u32 / 2;
+
+                        if requested_frame == 0
+                            || last_sent_frame
+                                .borrow()
+                                .as_ref()
+                                .map(|last| {
+                                    requested_frame < last.number
+                                    // seek forward for big jumps. this threshold is arbitrary but should be derived from i-frames in future
+                                    || requested_frame - last.number > FRAME_CACHE_SIZE as u32
+                                })
+                                .unwrap_or(true)
+                        {
+                            this.reset(requested_time);
+                            frames = this.inner.frames();
+                        }
 
-                        let mut cache_frame = CachedFrame::Raw {
-                            image_buf: frame.retained(),
-                            number: current_frame,
-                        };
+                        let mut exit = false;
+                        let mut previous_frame = None::<u32>;
+
+                        for frame in &mut frames {
+                            let frame = match frame {
+                                Ok(frame) => frame,
+                                Err(e) if corrupt_frame_policy == CorruptFramePolicy::Fail => {
+                                    if let Some(sender) = sender.take() {
+                                        (sender)(Err(DecoderError::Decode(format!(
+                                            "read frame / {e}"
+                                        ))));
+                                    }
+                                    break;
+                                }
+                                Err(_) => {
+                                    skipped_frames.fetch_add(1, Ordering::Relaxed);
+                                    continue;
+                                }
+                            };
+
+                            let current_frame = pts_to_frame(
+                                frame.pts().value,
+                                Rational::new(1, frame.pts().scale),
+                                frame_rate,
+                            );
+                            // variable frame rate videos can have several frames on
+                            // one frame number, which keeps the first, see `FrameTiming`
+                            if previous_frame.replace(current_frame) == Some(current_frame) {
+                                continue;
+                            }
 
-                        this.is_done = false;
+                            let Some(frame) = frame.image_buf() else {
+                                continue;
+                            };
 
-                        // Handles frame skips.
-                        // We use the cache instead of last_sent_frame as newer non-matching frames could have been decoded.
-                        if let Some(most_recent_prev_frame) =
-                            cache.iter_mut().rev().find(|v| *v.0 < requested_frame)
-                            && let Some(sender) = sender.take()
-                        {
-                            (sender)(most_recent_prev_frame.1.process());
-                        }
+                            let mut cache_frame = CachedFrame::Raw {
+                                image_buf: frame.retained(),
+                                number: current_frame,
+                            };
 
-                        let exceeds_cache_bounds = current_frame > cache_max;
-                        let too_small_for_cache_bounds = current_frame < cache_min;
+                            this.is_done = false;
 
-                        if !too_small_for_cache_bounds {
-                            if current_frame == requested_frame
+                            // Handles frame skips.
+                            // We use the cache instead of last_sent_frame as newer non-matching frames could have been decoded.
+                            if let Some(most_recent_prev_frame) =
+                                cache.latest_before(requested_frame)
                                 && let Some(sender) = sender.take()
                             {
-                                let data = cache_frame.process();
-                                // info!("sending frame {requested_frame}");
+                                (sender)(most_recent_prev_frame.process(
+                                    color,
+                                    output_format,
+                                    rotation,
+                                    &mut pool,
+                                ));
+                            }
 
-                                (sender)(data);
+                            let exceeds_cache_bounds = current_frame > cache_max;
+                            let too_small_for_cache_bounds = current_frame < cache_min;
 
-                                break;
-                            }
+                            if !too_small_for_cache_bounds {
+                                if current_frame == requested_frame
+                                    && let Some(sender) = sender.take()
+                                {
+                                    let data = cache_frame.process(
+                                        color,
+                                        output_format,
+                                        rotation,
+                                        &mut pool,
+                                    );
+                                    decode_trace!("sending frame {requested_frame}");
+
+                                    (sender)(data);
 
-                            if cache.len() >= FRAME_CACHE_SIZE {
-                                if let Some(last_active_frame) = &last_active_frame {
-                                    let frame = if requested_frame > *last_active_frame {
-                                        *cache.keys().next().unwrap()
-                                    } else if requested_frame < *last_active_frame {
-                                        *cache.keys().next_back().unwrap()
-                                    } else {
-                                        let min = *cache.keys().min().unwrap();
-                                        let max = *cache.keys().max().unwrap();
-
-                                        if current_frame > max { min } else { max }
-                                    };
-
-                                    cache.remove(&frame);
-                                } else {
-                                    cache.clear()
+                                    break;
                                 }
+
+                                cache.insert(current_frame, cache_frame.clone(), requested_frame);
                             }
 
-                            cache.insert(current_frame, cache_frame.clone());
-                        }
+                            if current_frame > requested_frame && sender.is_some() {
+                                // not inlining this is important so that last_sent_frame is dropped before the sender is invoked
+                                let last_sent_frame = last_sent_frame.borrow().clone();
+
+                                if let Some((sender, last_sent_frame)) =
+                                    last_sent_frame.and_then(|l| Some((sender.take()?, l)))
+                                {
+                                    decode_trace!(
+                                        "sending previous frame {} for {requested_frame}",
+                                        last_sent_frame.number
+                                    );
+
+                                    (sender)(Ok(last_sent_frame));
+                                } else if let Some(sender) = sender.take() {
+                                    decode_trace!(
+                                        "sending forward frame {current_frame} for {requested_frame}",
+                                    );
+
+                                    (sender)(cache_frame.process(
+                                        color,
+                                        output_format,
+                                        rotation,
+                                        &mut pool,
+                                    ));
+                                }
+                            }
 
-                        if current_frame > requested_frame && sender.is_some() {
-                            // not inlining this is important so that last_sent_frame is dropped before the sender is invoked
-                            let last_sent_frame = last_sent_frame.borrow().clone();
+                            exit = exit || exceeds_cache_bounds;
 
-                            if let Some((sender, last_sent_frame)) =
-                                last_sent_frame.and_then(|l| Some((sender.take()?, l)))
-                            {
-                                // info!(
-                                //     "sending previous frame {} for {requested_frame}",
-                                //     last_sent_frame.0
-                                // );
-
-                                (sender)(last_sent_frame);
-                            } else if let Some(sender) = sender.take() {
-                                // info!(
-                                //     "sending forward frame {current_frame} for {requested_frame}",
-                                // );
-
-                                (sender)(cache_frame.process());
+                            if exit {
+                                break;
                             }
                         }
 
-                        exit = exit || exceeds_cache_bounds;
+                        this.is_done = true;
 
-                        if exit {
-                            break;
+                        // not inlining this is important so that last_sent_frame is dropped before the sender is invoked
+                        let last_sent_frame = last_sent_frame.borrow().clone();
+                        if let Some((sender, last_sent_frame)) = sender.take().zip(last_sent_frame)
+                        {
+                            decode_trace!(
+                                "sending hail mary frame {} for {requested_frame}",
+                                last_sent_frame.number
+                            );
+
+                            (sender)(Ok(last_sent_frame));
                         }
                     }
+                }
+                VideoDecoderMessage::Prefetch(range) => {
+                    let Some(start) = first_uncached(range.clone(), &cache) else {
+                        continue;
+                    };
+
+                    // the reader is only moved when a frame is requested,
+                    // so frames are only prefetched if they're just ahead of it
+                    let ahead = last_sent_frame.borrow().as_ref().is_some_and(|last| {
+                        start > last.number && start - last.number <= FRAME_CACHE_SIZE as u32
+                    });
+                    if !ahead {
+                        continue;
+                    }
 
-                    this.is_done = true;
+                    let mut previous_frame = None::<u32>;
+                    for frame in &mut frames {
+                        let Ok(frame) = frame else {
+                            if corrupt_frame_policy == CorruptFramePolicy::Fail {
+                                break;
+                            }
+                            skipped_frames.fetch_add(1, Ordering::Relaxed);
+                            continue;
+                        };
 
-                    // not inlining this is important so that last_sent_frame is dropped before the sender is invoked
-                    let last_sent_frame = last_sent_frame.borrow().clone();
-                    if let Some((sender, last_sent_frame)) = sender.take().zip(last_sent_frame) {
-                        // info!(
-                        //     "sending hail mary frame {} for {requested_frame}",
-                        //     last_sent_frame.0
-                        // );
+                        let current_frame = pts_to_frame(
+                            frame.pts().value,
+                            Rational::new(1, frame.pts().scale),
+                            frame_rate,
+                        );
+                        if previous_frame.replace(current_frame) == Some(current_frame) {
+                            continue;
+                        }
 
-                        (sender)(last_sent_frame);
+                        let Some(frame) = frame.image_buf() else {
+                            continue;
+                        };
+
+                        if current_frame >= start && !cache.contains(current_frame) {
+                            let cache_frame = CachedFrame::Raw {
+                                image_buf: frame.retained(),
+                                number: current_frame,
+                            };
+                            // errors are sent when the frame is requested
+                            let _ = cache.insert(current_frame, cache_frame, start).process(
+                                color,
+                                output_format,
+                                rotation,
+                                &mut pool,
+                            );
+                        }
+
+                        if current_frame + 1 >= range.end {
+                            break;
+                        }
+
+                        if let Ok(message) = rx.try_recv() {
+                            pending = Some(message);
+                            break;
+                        }
                     }
                 }
             }
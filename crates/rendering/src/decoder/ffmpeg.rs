@@ -1,63 +1,64 @@
-use ffmpeg::{format, frame, software, sys::AVHWDeviceType};
-use log::debug;
+use cap_media::filters::HdrTransfer;
+use cap_video_decode::ffmpeg::TimestampedFrame;
+use ffmpeg::{format, frame};
 use std::{
     cell::RefCell,
-    collections::BTreeMap,
     path::PathBuf,
     rc::Rc,
-    sync::{Arc, mpsc},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
 };
 use tokio::sync::oneshot;
 
-use super::{FRAME_CACHE_SIZE, VideoDecoderMessage, pts_to_frame};
+use super::{
+    CACHE_KEEP_MARGIN, ColorInfo, DecodedFrame, DecoderError, DecoderOptions, DecoderOutputFormat,
+    FRAME_CACHE_SIZE, FRAME_POOL_SIZE, FrameCache, FramePool, Rotation, VideoDecoderMessage,
+    convert_frame, first_uncached, needs_seek, pack_frame, pts_to_frame, rotate_frame,
+};
+use crate::FrameRate;
 
 #[derive(Clone)]
 struct ProcessedFrame {
     number: u32,
-    data: Arc<Vec<u8>>,
+    data: DecodedFrame,
 }
 
 impl CachedFrame {
-    fn process(&mut self, width: u32, height: u32) -> ProcessedFrame {
+    fn process(
+        &mut self,
+        color: ColorInfo,
+        output_format: DecoderOutputFormat,
+        rotation: Rotation,
+        pool: &mut FramePool,
+    ) -> Result<ProcessedFrame, DecoderError> {
         match self {
             Self::Raw { frame, number } => {
-                let rgb_frame = if frame.format() != format::Pixel::RGBA {
-                    // Reinitialize the scaler with the new input format
-                    let mut scaler =
-                        software::converter((width, height), frame.format(), format::Pixel::RGBA)
-                            .unwrap();
-
-                    let mut rgb_frame = frame::Video::empty();
-                    scaler.run(frame, &mut rgb_frame).unwrap();
-                    rgb_frame
+                let output_frame = if frame.format() != output_format.pixel()
+                    || HdrTransfer::of(frame).is_some()
+                {
+                    convert_frame(frame, output_format, color)?
                 } else {
                     std::mem::replace(frame, frame::Video::empty())
                 };
 
-                let width = rgb_frame.width() as usize;
-                let height = rgb_frame.height() as usize;
-                let stride = rgb_frame.stride(0);
-                let data = rgb_frame.data(0);
-
-                let expected_size = width * height * 4;
-
-                let mut frame_buffer = Vec::with_capacity(expected_size);
-
-                // account for stride > width
-                for line_data in data.chunks_exact(stride) {
-                    frame_buffer.extend_from_slice(&line_data[0..width * 4]);
-                }
-
                 let data = ProcessedFrame {
-                    data: Arc::new(frame_buffer),
+                    data: rotate_frame(
+                        pack_frame(&output_frame, output_format, pool),
+                        output_format,
+                        rotation,
+                        pool,
+                    ),
                     number: *number,
                 };
 
                 *self = Self::Processed(data.clone());
 
-                data
+                Ok(data)
             }
-            Self::Processed(data) => data.clone(),
+            Self::Processed(data) => Ok(data.clone()),
         }
     }
 }
@@ -72,192 +73,298 @@ pub struct FfmpegDecoder;
 
 impl FfmpegDecoder {
     pub fn spawn(
-        _name: &'static str,
         path: PathBuf,
-        fps: u32,
+        frame_rate: FrameRate,
+        rotation: Rotation,
+        options: DecoderOptions,
         rx: mpsc::Receiver<VideoDecoderMessage>,
         ready_tx: oneshot::Sender<Result<(), String>>,
-    ) -> Result<(), String> {
-        let mut this = cap_video_decode::FFmpegDecoder::new(
-            path,
-            Some(if cfg!(target_os = "macos") {
-                AVHWDeviceType::AV_HWDEVICE_TYPE_VIDEOTOOLBOX
-            } else {
-                AVHWDeviceType::AV_HWDEVICE_TYPE_D3D12VA
-            }),
-        )?;
-
-        let time_base = this.decoder().time_base();
-        let start_time = this.start_time();
-        let width = this.decoder().width();
-        let height = this.decoder().height();
+        skipped_frames: Arc<AtomicUsize>,
+    ) -> std::thread::JoinHandle<()> {
+        let DecoderOptions {
+            output_format,
+            hw_device_type,
+            threads,
+            corrupt_frame_policy,
+            color_override,
+            allow_truncated,
+        } = options;
 
         std::thread::spawn(move || {
-            let mut cache = BTreeMap::<u32, CachedFrame>::new();
-            // active frame is a frame that triggered decode.
-            // frames that are within render_more_margin of this frame won't trigger decode.
-            #[allow(unused)]
-            let mut last_active_frame = None::<u32>;
+            // hardware decoding state is thread-local,
+            // so the decoder needs to be created on the thread that uses it
+            let mut this = match cap_video_decode::FFmpegDecoder::new_with_threads(
+                path,
+                hw_device_type,
+                threads,
+            ) {
+                Ok(v) => v
+                    .with_allow_truncated(allow_truncated)
+                    .with_corrupt_frame_policy(corrupt_frame_policy),
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+
+            let time_base = this.decoder().time_base();
+            let start_time = this.start_time();
+            let color = color_override.unwrap_or(ColorInfo {
+                space: this.decoder().color_space(),
+                range: this.decoder().color_range(),
+            });
+
+            // without an index, `needs_seek` falls back to how far ahead the request is
+            let keyframes = this
+                .keyframes()
+                .into_iter()
+                .map(|pts| pts_to_frame(pts - start_time, time_base, frame_rate))
+                .collect::<Vec<_>>();
+
+            let mut cache = FrameCache::<CachedFrame>::new(FRAME_CACHE_SIZE, CACHE_KEEP_MARGIN);
+            let mut pool = FramePool::new(FRAME_POOL_SIZE);
 
             let last_sent_frame = Rc::new(RefCell::new(None::<ProcessedFrame>));
+            // the decoder's position, used to decide whether a request can be decoded forwards
+            let mut last_decoded_frame = None::<u32>;
 
             let mut frames = this.frames();
+            // a message that arrived while prefetching, handled before waiting for the next one
+            let mut pending = None::<VideoDecoderMessage>;
 
             let _ = ready_tx.send(Ok(()));
 
-            while let Ok(r) = rx.recv() {
+            while let Some(r) = pending.take().or_else(|| rx.recv().ok()) {
                 match r {
-                    VideoDecoderMessage::GetFrame(requested_time, sender) => {
-                        let requested_frame = (requested_time * fps as f32).floor() as u32;
-                        // sender.send(black_frame.clone()).ok();
-                        // continue;
-
-                        let mut sender = if let Some(cached) = cache.get_mut(&requested_frame) {
-                            let data = cached.process(width, height);
-
-                            sender.send(data.data.clone()).ok();
-                            *last_sent_frame.borrow_mut() = Some(data);
-                            continue;
-                        } else {
-                            let last_sent_frame = last_sent_frame.clone();
-                            Some(move |data: ProcessedFrame| {
-                                *last_sent_frame.borrow_mut() = Some(data.clone());
-                                let _ = sender.send(data.data);
-                            })
-                        };
-
-                        let cache_min = requested_frame.saturating_sub(FRAME_CACHE_SIZE as u32 / 2);
-                        let cache_max = requested_frame + FRAME_CACHE_SIZE as u32 / 2;
-
-                        if requested_frame == 0
-                            || last_sent_frame
-                                .borrow()
-                                .as_ref()
-                                .map(|last| {
-                                    requested_frame < last.number
-                                    // seek forward for big jumps. this threshold is arbitrary but should be derived from i-frames in future
-                                    || requested_frame - last.number > FRAME_CACHE_SIZE as u32
-                                })
-                                .unwrap_or(true)
-                        {
-                            debug!("seeking to {requested_frame}");
-
-                            let _ = this.reset(requested_time);
-                            frames = this.frames();
-                        }
-
-                        last_active_frame = Some(requested_frame);
+                    VideoDecoderMessage::GetFrames(range, sender) => {
+                        for requested_frame in range {
+                            // the stream was dropped
+                            if sender.is_closed() {
+                                break;
+                            }
 
-                        let mut exit = false;
+                            let requested_time = frame_rate.frame_time(requested_frame) as f32;
+                            // sender.send(black_frame.clone()).ok();
+                            // continue;
 
-                        for frame in &mut frames {
-                            let Ok(frame) = frame.map_err(|e| format!("read frame / {e}")) else {
+                            let mut sender = if let Some(cached) = cache.get_mut(requested_frame) {
+                                match cached.process(color, output_format, rotation, &mut pool) {
+                                    Ok(data) => {
+                                        sender.send(Ok((requested_frame, data.data.clone())));
+                                        *last_sent_frame.borrow_mut() = Some(data);
+                                    }
+                                    Err(e) => sender.send(Err(e)),
+                                }
                                 continue;
+                            } else {
+                                let last_sent_frame = last_sent_frame.clone();
+                                let sender = &sender;
+                                Some(
+                                    move |data: Result<ProcessedFrame, DecoderError>| match data {
+                                        Ok(data) => {
+                                            *last_sent_frame.borrow_mut() = Some(data.clone());
+                                            sender.send(Ok((requested_frame, data.data)));
+                                        }
+                                        Err(e) => sender.send(Err(e)),
+                                    },
+                                )
                             };
 
-                            let current_frame =
-                                pts_to_frame(frame.pts().unwrap() - start_time, time_base, fps);
+                            let cache_min =
+                                requested_frame.saturating_sub(FRAME_CACHE_SIZE as u32 / 2);
+                            let cache_max = requested_frame + FRAME_CACHE_SIZE as u32 / 2;
 
-                            let mut cache_frame = CachedFrame::Raw {
-                                frame,
-                                number: current_frame,
-                            };
+                            if needs_seek(requested_frame, last_decoded_frame, &keyframes) {
+                                decode_trace!("seeking to {requested_frame}");
 
-                            // Handles frame skips.
-                            // We use the cache instead of last_sent_frame as newer non-matching frames could have been decoded.
-                            if let Some(most_recent_prev_frame) =
-                                cache.iter_mut().rev().find(|v| *v.0 < requested_frame)
-                                && let Some(sender) = sender.take()
-                            {
-                                (sender)(most_recent_prev_frame.1.process(width, height));
+                                last_decoded_frame = None;
+                                let reset = this.reset(requested_time);
+                                frames = this.frames();
+
+                                if let Err(e) = reset {
+                                    if let Some(sender) = sender.take() {
+                                        (sender)(Err(DecoderError::Decode(format!("seek / {e}"))));
+                                    }
+                                    continue;
+                                }
                             }
 
-                            let exceeds_cache_bounds = current_frame > cache_max;
-                            let too_small_for_cache_bounds = current_frame < cache_min;
+                            let mut exit = false;
+
+                            for frame in &mut frames {
+                                let TimestampedFrame { frame, pts, .. } = match frame {
+                                    Ok(frame) => frame,
+                                    Err(e) => {
+                                        if let Some(sender) = sender.take() {
+                                            (sender)(Err(DecoderError::Decode(format!(
+                                                "read frame / {e}"
+                                            ))));
+                                        }
+                                        break;
+                                    }
+                                };
+
+                                let current_frame =
+                                    pts_to_frame(pts - start_time, time_base, frame_rate);
+                                // variable frame rate videos can have several frames on
+                                // one frame number, which keeps the first, see `FrameTiming`
+                                if last_decoded_frame == Some(current_frame) {
+                                    continue;
+                                }
+                                last_decoded_frame = Some(current_frame);
 
-                            let cache_frame = if !too_small_for_cache_bounds {
-                                if current_frame == requested_frame
+                                let mut cache_frame = CachedFrame::Raw {
+                                    frame,
+                                    number: current_frame,
+                                };
+
+                                // Handles frame skips.
+                                // We use the cache instead of last_sent_frame as newer non-matching frames could have been decoded.
+                                if let Some(most_recent_prev_frame) =
+                                    cache.latest_before(requested_frame)
                                     && let Some(sender) = sender.take()
                                 {
-                                    let data = cache_frame.process(width, height);
-                                    // info!("sending frame {requested_frame}");
+                                    (sender)(most_recent_prev_frame.process(
+                                        color,
+                                        output_format,
+                                        rotation,
+                                        &mut pool,
+                                    ));
+                                }
 
-                                    (sender)(data);
+                                let exceeds_cache_bounds = current_frame > cache_max;
+                                let too_small_for_cache_bounds = current_frame < cache_min;
 
-                                    break;
-                                }
+                                let cache_frame = if !too_small_for_cache_bounds {
+                                    if current_frame == requested_frame
+                                        && let Some(sender) = sender.take()
+                                    {
+                                        let data = cache_frame.process(
+                                            color,
+                                            output_format,
+                                            rotation,
+                                            &mut pool,
+                                        );
+                                        decode_trace!("sending frame {requested_frame}");
 
-                                if cache.len() >= FRAME_CACHE_SIZE {
-                                    if let Some(last_active_frame) = &last_active_frame {
-                                        let frame = if requested_frame > *last_active_frame {
-                                            *cache.keys().next().unwrap()
-                                        } else if requested_frame < *last_active_frame {
-                                            *cache.keys().next_back().unwrap()
-                                        } else {
-                                            let min = *cache.keys().min().unwrap();
-                                            let max = *cache.keys().max().unwrap();
-
-                                            if current_frame > max { min } else { max }
-                                        };
-
-                                        cache.remove(&frame);
-                                    } else {
-                                        cache.clear()
+                                        (sender)(data);
+
+                                        break;
                                     }
-                                }
 
-                                cache.insert(current_frame, cache_frame);
-                                cache.get_mut(&current_frame).unwrap()
-                            } else {
-                                &mut cache_frame
-                            };
+                                    cache.insert(current_frame, cache_frame, requested_frame)
+                                } else {
+                                    &mut cache_frame
+                                };
+
+                                if current_frame > requested_frame && sender.is_some() {
+                                    // not inlining this is important so that last_sent_frame is dropped before the sender is invoked
+                                    let last_sent_frame = last_sent_frame.borrow().clone();
+
+                                    if let Some((sender, last_sent_frame)) =
+                                        last_sent_frame.and_then(|l| Some((sender.take()?, l)))
+                                    {
+                                        decode_trace!(
+                                            "sending previous frame {} for {requested_frame}",
+                                            last_sent_frame.number
+                                        );
+
+                                        (sender)(Ok(last_sent_frame));
+                                    } else if let Some(sender) = sender.take() {
+                                        decode_trace!(
+                                            "sending forward frame {current_frame} for {requested_frame}",
+                                        );
+
+                                        (sender)(cache_frame.process(
+                                            color,
+                                            output_format,
+                                            rotation,
+                                            &mut pool,
+                                        ));
+                                    }
+                                }
 
-                            if current_frame > requested_frame && sender.is_some() {
-                                // not inlining this is important so that last_sent_frame is dropped before the sender is invoked
-                                let last_sent_frame = last_sent_frame.borrow().clone();
+                                exit = exit || exceeds_cache_bounds;
 
-                                if let Some((sender, last_sent_frame)) =
-                                    last_sent_frame.and_then(|l| Some((sender.take()?, l)))
-                                {
-                                    // info!(
-                                    //     "sending previous frame {} for {requested_frame}",
-                                    //     last_sent_frame.0
-                                    // );
-
-                                    (sender)(last_sent_frame);
-                                } else if let Some(sender) = sender.take() {
-                                    // info!(
-                                    //     "sending forward frame {current_frame} for {requested_frame}",
-                                    // );
-
-                                    (sender)(cache_frame.process(width, height));
+                                if exit {
+                                    break;
                                 }
                             }
 
-                            exit = exit || exceeds_cache_bounds;
+                            skipped_frames.store(frames.skipped_frames(), Ordering::Relaxed);
 
-                            if exit {
-                                break;
+                            // not inlining this is important so that last_sent_frame is dropped before the sender is invoked
+                            let last_sent_frame = last_sent_frame.borrow().clone();
+                            if let Some((sender, last_sent_frame)) =
+                                sender.take().zip(last_sent_frame)
+                            {
+                                decode_trace!(
+                                    "sending hail mary frame {} for {requested_frame}",
+                                    last_sent_frame.number
+                                );
+
+                                (sender)(Ok(last_sent_frame));
+                            }
+                        }
+                    }
+                    VideoDecoderMessage::Prefetch(range) => {
+                        let Some(start) = first_uncached(range.clone(), &cache) else {
+                            continue;
+                        };
+
+                        if needs_seek(start, last_decoded_frame, &keyframes) {
+                            decode_trace!("seeking to {start} to prefetch");
+
+                            last_decoded_frame = None;
+                            let reset = this.reset(frame_rate.frame_time(start) as f32);
+                            frames = this.frames();
+
+                            if reset.is_err() {
+                                continue;
                             }
                         }
 
-                        // not inlining this is important so that last_sent_frame is dropped before the sender is invoked
-                        let last_sent_frame = last_sent_frame.borrow().clone();
-                        if let Some((sender, last_sent_frame)) = sender.take().zip(last_sent_frame)
-                        {
-                            // info!(
-                            //     "sending hail mary frame {} for {requested_frame}",
-                            //     last_sent_frame.0
-                            // );
+                        for frame in &mut frames {
+                            let Ok(TimestampedFrame { frame, pts, .. }) = frame else {
+                                break;
+                            };
+
+                            let current_frame =
+                                pts_to_frame(pts - start_time, time_base, frame_rate);
+                            if last_decoded_frame == Some(current_frame) {
+                                continue;
+                            }
+                            last_decoded_frame = Some(current_frame);
+
+                            if current_frame >= start && !cache.contains(current_frame) {
+                                let cache_frame = CachedFrame::Raw {
+                                    frame,
+                                    number: current_frame,
+                                };
+                                // errors are sent when the frame is requested
+                                let _ = cache.insert(current_frame, cache_frame, start).process(
+                                    color,
+                                    output_format,
+                                    rotation,
+                                    &mut pool,
+                                );
+                            }
+
+                            if current_frame + 1 >= range.end {
+                                break;
+                            }
 
-                            (sender)(last_sent_frame);
+                            if let Ok(message) = rx.try_recv() {
+                                pending = Some(message);
+                                break;
+                            }
                         }
+
+                        skipped_frames.store(frames.skipped_frames(), Ordering::Relaxed);
                     }
                 }
             }
-        });
-
-        Ok(())
+        })
     }
 }
 
@@ -1,65 +1,1750 @@
-use ::ffmpeg::Rational;
+use ::ffmpeg::{Rational, color, format, frame, software, sys::AVHWDeviceType};
+use cap_media::{
+    MediaError,
+    data::RgbaFrame,
+    filters::{HdrTransfer, ToneMapFilter},
+};
+pub use cap_video_decode::{CorruptFramePolicy, DecoderThreads, Rotation};
+use futures::{Stream, StreamExt};
 use std::{
-    path::PathBuf,
-    sync::{Arc, mpsc},
+    collections::{BTreeMap, VecDeque},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread::JoinHandle,
+    time::Duration,
 };
 use tokio::sync::oneshot;
 
+use crate::FrameRate;
+
+/// Per-frame decoder tracing is very noisy, so it's only emitted when `CAP_DECODE_TRACE`
+/// is set, regardless of the subscriber's level filter.
+pub(crate) static VERBOSE_DECODE_TRACING: LazyLock<bool> =
+    LazyLock::new(|| std::env::var_os("CAP_DECODE_TRACE").is_some_and(|v| v != "0"));
+
+macro_rules! decode_trace {
+    ($($arg:tt)*) => {
+        if *$crate::decoder::VERBOSE_DECODE_TRACING {
+            tracing::trace!(target: "cap_rendering::decode", $($arg)*);
+        }
+    };
+}
+
 #[cfg(target_os = "macos")]
 mod avassetreader;
 mod ffmpeg;
 
-pub type DecodedFrame = Arc<Vec<u8>>;
+#[derive(Clone)]
+pub struct DecodedFrame {
+    pub data: Arc<Vec<u8>>,
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per row of the first plane. Rows are tightly packed, with no padding.
+    pub stride: u32,
+}
+
+/// Fails for frames decoded to a format other than [`DecoderOutputFormat::Rgba`].
+impl TryFrom<DecodedFrame> for RgbaFrame {
+    type Error = MediaError;
+
+    fn try_from(frame: DecodedFrame) -> Result<Self, Self::Error> {
+        RgbaFrame::new(frame.data, frame.width, frame.height, frame.stride)
+    }
+}
+
+impl From<RgbaFrame> for DecodedFrame {
+    fn from(frame: RgbaFrame) -> Self {
+        let frame = if frame.is_packed() {
+            frame
+        } else {
+            RgbaFrame::from(frame.into_image())
+        };
+
+        Self {
+            width: frame.width(),
+            height: frame.height(),
+            stride: frame.stride(),
+            data: frame.into_data(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum DecoderError {
+    #[error("Decode/{0}")]
+    Decode(String),
+    #[error("Decoder thread exited")]
+    Disconnected,
+}
+
+pub type FrameResult = Result<(u32, DecodedFrame), DecoderError>;
 
 pub enum VideoDecoderMessage {
-    GetFrame(f32, tokio::sync::oneshot::Sender<DecodedFrame>),
+    /// Decodes a contiguous range of frames in a single pass,
+    /// sending a frame for each number in the range.
+    GetFrames(Range<u32>, FrameSender),
+    /// Decodes a range of frames into the cache without sending them anywhere, so requests
+    /// for them are served straight from it. Stops early if another message arrives.
+    Prefetch(Range<u32>),
+}
+
+/// Sends the results of a [`VideoDecoderMessage::GetFrames`] request.
+/// If the decoder thread panics while handling the request, the caller is sent
+/// [`DecoderError::Disconnected`] instead of the frames just stopping.
+pub struct FrameSender(tokio::sync::mpsc::Sender<FrameResult>);
+
+impl FrameSender {
+    pub fn send(&self, result: FrameResult) {
+        let _ = self.0.blocking_send(result);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+}
+
+impl Drop for FrameSender {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            let _ = self.0.try_send(Err(DecoderError::Disconnected));
+        }
+    }
+}
+
+/// Pixel layout of the frames returned by [`AsyncVideoDecoderHandle::get_frame`].
+/// Frames are tightly packed, with each plane following the previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecoderOutputFormat {
+    #[default]
+    Rgba,
+    /// Y plane followed by an interleaved UV plane at half resolution
+    Nv12,
+    /// Y, U and V planes, with U and V at half resolution
+    Yuv420p,
+}
+
+impl DecoderOutputFormat {
+    pub fn pixel(&self) -> format::Pixel {
+        match self {
+            Self::Rgba => format::Pixel::RGBA,
+            Self::Nv12 => format::Pixel::NV12,
+            Self::Yuv420p => format::Pixel::YUV420P,
+        }
+    }
+
+    /// Bytes per pixel of each plane
+    fn pixel_sizes(&self) -> &'static [usize] {
+        match self {
+            Self::Rgba => &[4],
+            Self::Nv12 => &[1, 2],
+            Self::Yuv420p => &[1, 1, 1],
+        }
+    }
+
+    /// Bytes per row and number of rows of each plane
+    pub fn planes(&self, width: usize, height: usize) -> Vec<(usize, usize)> {
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+
+        match self {
+            Self::Rgba => vec![(width * 4, height)],
+            Self::Nv12 => vec![(width, height), (chroma_width * 2, chroma_height)],
+            Self::Yuv420p => vec![
+                (width, height),
+                (chroma_width, chroma_height),
+                (chroma_width, chroma_height),
+            ],
+        }
+    }
+}
+
+/// The color space and range that YUV frames are converted to RGB with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorInfo {
+    pub space: color::Space,
+    pub range: color::Range,
+}
+
+impl ColorInfo {
+    /// Fills in anything `self` leaves unspecified from the frame's metadata, and then the
+    /// way most players do: BT.709 for HD frames, BT.601 below that, and limited range.
+    fn resolve(self, frame: &frame::Video) -> Self {
+        let space = [self.space, frame.color_space()]
+            .into_iter()
+            .find(|s| !matches!(s, color::Space::Unspecified | color::Space::Reserved))
+            .unwrap_or(if frame.height() >= 720 {
+                color::Space::BT709
+            } else {
+                color::Space::BT470BG
+            });
+        let range = [self.range, frame.color_range()]
+            .into_iter()
+            .find(|r| *r != color::Range::Unspecified)
+            .unwrap_or(color::Range::MPEG);
+
+        Self { space, range }
+    }
+
+    /// Sets the coefficients and input range of a scaler converting to full range RGB.
+    fn configure(&self, scaler: &mut software::scaling::Context) {
+        use ::ffmpeg::ffi::{AVColorSpace, sws_getCoefficients, sws_setColorspaceDetails};
+
+        unsafe {
+            let coefficients = sws_getCoefficients(AVColorSpace::from(self.space) as i32);
+            // fails for conversions that don't use the coefficients, which are fine as they are
+            let _ = sws_setColorspaceDetails(
+                scaler.as_mut_ptr(),
+                coefficients,
+                (self.range == color::Range::JPEG) as i32,
+                coefficients,
+                1,
+                0,
+                1 << 16,
+                1 << 16,
+            );
+        }
+    }
+
+    /// Sets the coefficients and output range of a scaler converting from full range RGB.
+    fn configure_output(&self, scaler: &mut software::scaling::Context) {
+        use ::ffmpeg::ffi::{AVColorSpace, sws_getCoefficients, sws_setColorspaceDetails};
+
+        unsafe {
+            let coefficients = sws_getCoefficients(AVColorSpace::from(self.space) as i32);
+            let _ = sws_setColorspaceDetails(
+                scaler.as_mut_ptr(),
+                coefficients,
+                1,
+                coefficients,
+                (self.range == color::Range::JPEG) as i32,
+                0,
+                1 << 16,
+                1 << 16,
+            );
+        }
+    }
+}
+
+/// Converts a decoded frame to `output_format`, using `color` if it's converted to RGB.
+///
+/// HDR frames are tone mapped to SDR BT.709 on the way, as they're composited and encoded
+/// as 8-bit SDR. They're converted to 16-bit RGB first so none of the precision of 10-bit
+/// video is lost before tone mapping.
+fn convert_frame(
+    frame: &frame::Video,
+    output_format: DecoderOutputFormat,
+    color: ColorInfo,
+) -> Result<frame::Video, DecoderError> {
+    let Some(transfer) = HdrTransfer::of(frame) else {
+        let color = (output_format == DecoderOutputFormat::Rgba).then(|| color.resolve(frame));
+        return scale_frame(frame, output_format.pixel(), color, None);
+    };
+
+    let rgb = scale_frame(
+        frame,
+        format::Pixel::RGB48LE,
+        Some(color.resolve(frame)),
+        None,
+    )?;
+    let sdr = ToneMapFilter::new(transfer, frame.color_primaries())
+        .apply(&rgb)
+        .map_err(|e| DecoderError::Decode(format!("tone map frame / {e}")))?;
+
+    if output_format == DecoderOutputFormat::Rgba {
+        return Ok(sdr);
+    }
+
+    scale_frame(
+        &sdr,
+        output_format.pixel(),
+        None,
+        Some(ColorInfo {
+            space: color::Space::BT709,
+            range: color::Range::MPEG,
+        }),
+    )
+}
+
+/// Runs a frame through swscale, configuring it with `input_color` when converting YUV to RGB
+/// or `output_color` when converting RGB to YUV.
+fn scale_frame(
+    frame: &frame::Video,
+    pixel: format::Pixel,
+    input_color: Option<ColorInfo>,
+    output_color: Option<ColorInfo>,
+) -> Result<frame::Video, DecoderError> {
+    let mut scaler = software::converter((frame.width(), frame.height()), frame.format(), pixel)
+        .map_err(|e| DecoderError::Decode(format!("create scaler / {e}")))?;
+
+    if let Some(color) = input_color {
+        color.configure(&mut scaler);
+    }
+    if let Some(color) = output_color {
+        color.configure_output(&mut scaler);
+    }
+
+    let mut output_frame = frame::Video::empty();
+    scaler
+        .run(frame, &mut output_frame)
+        .map_err(|e| DecoderError::Decode(format!("scale frame / {e}")))?;
+
+    Ok(output_frame)
+}
+
+/// Copies the planes of a frame that's already in `output_format` into a single buffer,
+/// dropping any padding ffmpeg adds to the end of each row.
+fn pack_frame(
+    frame: &frame::Video,
+    output_format: DecoderOutputFormat,
+    pool: &mut FramePool,
+) -> DecodedFrame {
+    let planes = output_format.planes(frame.width() as usize, frame.height() as usize);
+    let mut buffer = pool.take(planes.iter().map(|(row, rows)| row * rows).sum());
+
+    for (i, (row_length, rows)) in planes.iter().enumerate() {
+        for row in frame.data(i).chunks(frame.stride(i)).take(*rows) {
+            buffer.extend_from_slice(&row[0..*row_length]);
+        }
+    }
+
+    DecodedFrame {
+        data: pool.track(buffer),
+        width: frame.width(),
+        height: frame.height(),
+        stride: planes[0].0 as u32,
+    }
+}
+
+/// Turns a packed frame upright, see [`Rotation`].
+fn rotate_frame(
+    frame: DecodedFrame,
+    output_format: DecoderOutputFormat,
+    rotation: Rotation,
+    pool: &mut FramePool,
+) -> DecodedFrame {
+    if rotation == Rotation::None {
+        return frame;
+    }
+
+    let planes = output_format.planes(frame.width as usize, frame.height as usize);
+    let mut buffer = pool.take(frame.data.len());
+    let mut offset = 0;
+
+    for ((row_length, rows), pixel_size) in planes.into_iter().zip(output_format.pixel_sizes()) {
+        let plane = &frame.data[offset..offset + row_length * rows];
+        rotate_plane(
+            plane,
+            row_length / pixel_size,
+            rows,
+            *pixel_size,
+            rotation,
+            &mut buffer,
+        );
+        offset += row_length * rows;
+    }
+
+    let (width, height) = if rotation.swaps_dimensions() {
+        (frame.height, frame.width)
+    } else {
+        (frame.width, frame.height)
+    };
+
+    DecodedFrame {
+        data: pool.track(buffer),
+        width,
+        height,
+        stride: output_format.planes(width as usize, height as usize)[0].0 as u32,
+    }
+}
+
+/// Appends a tightly packed plane of `width` by `height` pixels to `out`, turned clockwise.
+fn rotate_plane(
+    plane: &[u8],
+    width: usize,
+    height: usize,
+    pixel_size: usize,
+    rotation: Rotation,
+    out: &mut Vec<u8>,
+) {
+    let (out_width, out_height) = if rotation.swaps_dimensions() {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let (source_x, source_y) = match rotation {
+                Rotation::None => (x, y),
+                Rotation::Clockwise90 => (y, height - 1 - x),
+                Rotation::Clockwise180 => (width - 1 - x, height - 1 - y),
+                Rotation::Clockwise270 => (width - 1 - y, x),
+            };
+            let i = (source_y * width + source_x) * pixel_size;
+            out.extend_from_slice(&plane[i..i + pixel_size]);
+        }
+    }
+}
+
+/// Length of a video, read from the container at spawn time without decoding it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct VideoLength {
+    frame_count: u32,
+    duration: Duration,
+    /// The rate frames are numbered at, see [`decoding_frame_rate`].
+    frame_rate: FrameRate,
+    /// `None` if the video has too few frames to measure
+    timing: Option<FrameTiming>,
+}
+
+impl VideoLength {
+    /// `fallback_fps` is the frame rate from the recording's metadata. If that's zero the
+    /// video's own rate is used, erroring if it doesn't have one as its frames can't be numbered.
+    fn probe(path: &Path, fallback_fps: u32) -> Result<Self, MediaError> {
+        let mut input = format::input(&path)?;
+        let stream = input
+            .streams()
+            .best(::ffmpeg::media::Type::Video)
+            .ok_or(MediaError::MissingMedia("video"))?;
+
+        let time_base = stream.time_base();
+        let duration = if stream.duration() > 0 {
+            stream.duration() as f64 * f64::from(time_base)
+        } else if input.duration() > 0 {
+            input.duration() as f64 / f64::from(::ffmpeg::ffi::AV_TIME_BASE)
+        } else {
+            0.0
+        };
+        let nb_frames = stream.frames();
+        let stream_index = stream.index();
+
+        let stream_frame_rate = if fallback_fps > 0 {
+            Some(decoding_frame_rate(&stream, fallback_fps))
+        } else {
+            stream_frame_rate(&stream)
+        };
+
+        let timestamps = packet_timestamps(&mut input, stream_index, FRAME_TIMING_PROBE_PACKETS);
+        let timing = FrameTiming::measure(&timestamps, time_base);
+        let frame_rate = stream_frame_rate
+            .or_else(|| frame_rate_from_timestamps(timestamps, time_base))
+            .ok_or(MediaError::MissingMedia("frame rate"))?;
+
+        // the container counts the frames that were written, which for a variable frame rate
+        // video is fewer than the frames it's numbered with
+        let nb_frames = if timing.is_some_and(|t| t.variable) {
+            0
+        } else {
+            nb_frames
+        };
+
+        Ok(Self {
+            frame_count: estimate_frame_count(nb_frames, duration, frame_rate.as_f64()),
+            duration: Duration::from_secs_f64(duration.max(0.0)),
+            frame_rate,
+            timing,
+        })
+    }
+}
+
+fn stream_frame_rate(stream: &format::stream::Stream) -> Option<FrameRate> {
+    [stream.avg_frame_rate(), stream.rate()]
+        .into_iter()
+        .find_map(|r| FrameRate::try_from(r).ok())
+}
+
+/// Number of packets read to work out the frame rate of a stream that doesn't have one.
+const FRAME_RATE_PROBE_PACKETS: usize = 64;
+
+/// Frame rate of a stream whose header doesn't have one, which happens with image sequences
+/// and some WebM files, worked out from the timestamps of its first packets.
+fn packet_frame_rate(input: &mut format::context::Input, stream_index: usize) -> Option<FrameRate> {
+    let time_base = input.stream(stream_index)?.time_base();
+    let timestamps = packet_timestamps(input, stream_index, FRAME_RATE_PROBE_PACKETS);
+
+    frame_rate_from_timestamps(timestamps, time_base)
+}
+
+/// Timestamps of the first `count` packets of a stream, in the order they're stored.
+fn packet_timestamps(
+    input: &mut format::context::Input,
+    stream_index: usize,
+    count: usize,
+) -> Vec<i64> {
+    input
+        .packets()
+        .filter(|(stream, _)| stream.index() == stream_index)
+        .filter_map(|(_, packet)| packet.pts())
+        .take(count)
+        .collect()
+}
+
+/// Number of packets measured to tell whether a video has a variable frame rate,
+/// around 2 seconds of a 60fps recording.
+const FRAME_TIMING_PROBE_PACKETS: usize = 120;
+
+/// How evenly a video's frames are spaced, measured from the timestamps of its first packets.
+///
+/// Screen recordings often have a variable frame rate, as frames are only written when
+/// the screen changes. Their frames are still numbered at a constant rate, each going to
+/// the frame number nearest to its timestamp. Frame numbers without a frame of their own
+/// show the frame before them, and when several frames land on the same number the first
+/// one is kept, so the video is effectively converted to a constant frame rate as it's
+/// decoded. Exports render at their own constant rate on top of that.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameTiming {
+    /// Frames per second across the measured frames
+    pub average_fps: f64,
+    /// Frames per second at the smallest gap between two frames
+    pub peak_fps: f64,
+    /// Whether the gaps between frames vary by more than the odd dropped frame
+    pub variable: bool,
+}
+
+impl FrameTiming {
+    /// Gaps further than this fraction from the median gap are uneven
+    const GAP_TOLERANCE: f64 = 0.25;
+    /// Fraction of uneven gaps above which the frame rate is variable, rather than
+    /// some frames having been dropped
+    const VARIABLE_FRACTION: f64 = 0.1;
+
+    /// `None` if there are less than two distinct timestamps.
+    fn measure(timestamps: &[i64], time_base: Rational) -> Option<Self> {
+        let mut timestamps = timestamps.to_vec();
+        timestamps.sort_unstable();
+        timestamps.dedup();
+
+        let mut gaps = timestamps
+            .windows(2)
+            .map(|w| w[1] - w[0])
+            .collect::<Vec<_>>();
+        if gaps.is_empty() {
+            return None;
+        }
+
+        let tick = f64::from(time_base);
+        let span = (timestamps[timestamps.len() - 1] - timestamps[0]) as f64 * tick;
+
+        gaps.sort_unstable();
+        let median = gaps[gaps.len() / 2] as f64;
+        // a tick either way is rounding to the time base
+        let tolerance = (median * Self::GAP_TOLERANCE).max(1.0);
+        let uneven = gaps
+            .iter()
+            .filter(|&&gap| (gap as f64 - median).abs() > tolerance)
+            .count();
+
+        Some(Self {
+            average_fps: gaps.len() as f64 / span,
+            peak_fps: 1.0 / (gaps[0] as f64 * tick),
+            variable: uneven as f64 > gaps.len() as f64 * Self::VARIABLE_FRACTION,
+        })
+    }
+}
+
+/// Uses the median gap between consecutive timestamps,
+/// so a few dropped or duplicated frames don't throw it off.
+fn frame_rate_from_timestamps(mut timestamps: Vec<i64>, time_base: Rational) -> Option<FrameRate> {
+    timestamps.sort_unstable();
+
+    let mut gaps = timestamps
+        .windows(2)
+        .map(|w| w[1] - w[0])
+        .filter(|gap| *gap > 0)
+        .collect::<Vec<_>>();
+    if gaps.is_empty() {
+        return None;
+    }
+    gaps.sort_unstable();
+    let gap = gaps[gaps.len() / 2];
+
+    // frames per second = 1 / (gap * time base)
+    let numerator = u32::try_from(time_base.denominator()).ok()?;
+    let denominator = u32::try_from(gap * time_base.numerator() as i64).ok()?;
+    FrameRate::new(numerator, denominator)
+}
+
+/// The exact rate of a stream whose nominal rate is `fps`, so that fractional rates like
+/// 29.97fps get frame numbers that line up with their timestamps instead of drifting a frame
+/// every 1000. Rates that don't round to `fps`, like the average rate of a variable frame rate
+/// recording, fall back to `fps`.
+fn decoding_frame_rate(stream: &format::stream::Stream, fps: u32) -> FrameRate {
+    [stream.rate(), stream.avg_frame_rate()]
+        .into_iter()
+        .filter_map(|r| FrameRate::try_from(r).ok())
+        .find(|r| r.rounded_fps() == fps)
+        .unwrap_or(FrameRate::from_fps(fps))
+}
+
+/// Frame rate of a video's best video stream, rounded to a whole number of frames,
+/// for spawning a decoder on a file that has no recording metadata.
+pub fn probe_fps(path: &Path) -> Result<u32, MediaError> {
+    let mut input = format::input(&path)?;
+    let stream = input
+        .streams()
+        .best(::ffmpeg::media::Type::Video)
+        .ok_or(MediaError::MissingMedia("video"))?;
+    let stream_index = stream.index();
+
+    stream_frame_rate(&stream)
+        .or_else(|| packet_frame_rate(&mut input, stream_index))
+        .map(|r| r.rounded_fps())
+        .ok_or(MediaError::MissingMedia("frame rate"))
+}
+
+/// Uses the container's frame count if it has one,
+/// otherwise estimates it from the duration and frame rate.
+fn estimate_frame_count(nb_frames: i64, duration: f64, frame_rate: f64) -> u32 {
+    if nb_frames > 0 {
+        nb_frames as u32
+    } else {
+        (duration * frame_rate).round().max(0.0) as u32
+    }
 }
 
-pub fn pts_to_frame(pts: i64, time_base: Rational, fps: u32) -> u32 {
-    (fps as f64 * ((pts as f64 * time_base.numerator() as f64) / (time_base.denominator() as f64)))
-        .round() as u32
+/// The hardware decoder to try using on this platform, if there is one.
+/// Decoding falls back to software if it isn't available.
+pub fn default_hw_device_type() -> Option<AVHWDeviceType> {
+    if cfg!(target_os = "macos") {
+        Some(AVHWDeviceType::AV_HWDEVICE_TYPE_VIDEOTOOLBOX)
+    } else if cfg!(windows) {
+        Some(AVHWDeviceType::AV_HWDEVICE_TYPE_D3D12VA)
+    } else if cfg!(target_os = "linux") {
+        Some(AVHWDeviceType::AV_HWDEVICE_TYPE_VAAPI)
+    } else {
+        None
+    }
+}
+
+/// Rounds to the nearest frame, clamping timestamps before the start to frame 0.
+///
+/// `pts * numerator * fps` is computed exactly in `i128`, as it can overflow `i64` for long
+/// recordings with fine time bases, and `f64` rounds to the wrong frame well before that.
+pub fn pts_to_frame(pts: i64, time_base: Rational, frame_rate: FrameRate) -> u32 {
+    let mut numerator =
+        pts as i128 * time_base.numerator() as i128 * frame_rate.numerator() as i128;
+    let mut denominator = time_base.denominator() as i128 * frame_rate.denominator() as i128;
+
+    if denominator == 0 {
+        return 0;
+    }
+    if denominator < 0 {
+        numerator = -numerator;
+        denominator = -denominator;
+    }
+
+    // floor(n / d + 0.5)
+    let frame = (2 * numerator + denominator).div_euclid(2 * denominator);
+
+    frame.clamp(0, u32::MAX as i128) as u32
 }
 
 pub const FRAME_CACHE_SIZE: usize = 100;
 
+/// Enough buffers for a full [`FrameCache`] plus the frames that have been sent
+/// but not yet dropped by whoever requested them.
+const FRAME_POOL_SIZE: usize = FRAME_CACHE_SIZE + 8;
+
+/// Recycles the buffers of decoded frames once nothing else holds them, so that decoding
+/// doesn't allocate a new multi-megabyte buffer for every frame while scrubbing.
+///
+/// At most `max_buffers` are kept for reuse, and buffers that are still in use when they're
+/// pushed out of the pool are freed as usual once dropped.
+struct FramePool {
+    /// Buffers that have been handed out, oldest first
+    buffers: VecDeque<Arc<Vec<u8>>>,
+    max_buffers: usize,
+    /// Number of buffers that had to be allocated, rather than reused
+    allocations: usize,
+}
+
+impl FramePool {
+    fn new(max_buffers: usize) -> Self {
+        Self {
+            buffers: VecDeque::with_capacity(max_buffers),
+            max_buffers,
+            allocations: 0,
+        }
+    }
+
+    /// An empty buffer with room for at least `capacity` bytes, reusing one that's
+    /// only held by the pool if there is one.
+    fn take(&mut self, capacity: usize) -> Vec<u8> {
+        let reusable = self
+            .buffers
+            .iter()
+            .position(|b| Arc::strong_count(b) == 1 && b.capacity() >= capacity);
+
+        if let Some(buffer) = reusable.and_then(|i| self.buffers.remove(i))
+            && let Ok(mut buffer) = Arc::try_unwrap(buffer)
+        {
+            buffer.clear();
+            return buffer;
+        }
+
+        self.allocations += 1;
+        Vec::with_capacity(capacity)
+    }
+
+    /// Shares a filled buffer, keeping a reference so it can be reused once it's dropped.
+    fn track(&mut self, buffer: Vec<u8>) -> Arc<Vec<u8>> {
+        let buffer = Arc::new(buffer);
+
+        if self.buffers.len() >= self.max_buffers {
+            self.buffers.pop_front();
+        }
+        self.buffers.push_back(buffer.clone());
+
+        buffer
+    }
+}
+
+/// Most frames a single [`AsyncVideoDecoderHandle::prefetch`] decodes ahead. This is as far
+/// ahead as a request caches frames, so prefetched frames aren't evicted before they're used.
+pub const MAX_PREFETCH_FRAMES: u32 = FRAME_CACHE_SIZE as u32 / 2;
+
+/// The first frame of a prefetch that isn't cached yet, where decoding has to start from.
+fn first_uncached<T>(mut range: Range<u32>, cache: &FrameCache<T>) -> Option<u32> {
+    range.find(|frame| !cache.contains(*frame))
+}
+
+/// Frames within this distance of the frame being requested are never evicted from the
+/// [`FrameCache`], since scrubbing is likely to ask for them next.
+pub const CACHE_KEEP_MARGIN: u32 = 10;
+
+/// Decoded frames, kept so that scrubbing back over them doesn't need another decode.
+///
+/// When full, the least recently used frame is evicted, skipping frames within
+/// `keep_margin` of the frame being requested unless nothing else can be evicted.
+pub struct FrameCache<T> {
+    frames: BTreeMap<u32, (T, u64)>,
+    capacity: usize,
+    keep_margin: u32,
+    /// Incremented on every access, to order frames by when they were last used
+    clock: u64,
+}
+
+impl<T> FrameCache<T> {
+    pub fn new(capacity: usize, keep_margin: u32) -> Self {
+        Self {
+            frames: BTreeMap::new(),
+            capacity: capacity.max(1),
+            keep_margin,
+            clock: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn contains(&self, frame: u32) -> bool {
+        self.frames.contains_key(&frame)
+    }
+
+    pub fn get_mut(&mut self, frame: u32) -> Option<&mut T> {
+        let now = self.tick();
+
+        self.frames.get_mut(&frame).map(|(value, used)| {
+            *used = now;
+            value
+        })
+    }
+
+    /// The closest cached frame before `frame`.
+    pub fn latest_before(&mut self, frame: u32) -> Option<&mut T> {
+        let now = self.tick();
+
+        self.frames
+            .range_mut(..frame)
+            .next_back()
+            .map(|(_, (value, used))| {
+                *used = now;
+                value
+            })
+    }
+
+    /// Adds a decoded frame, evicting another if the cache is full.
+    /// `requested` is the frame the decoder is currently trying to produce.
+    pub fn insert(&mut self, frame: u32, value: T, requested: u32) -> &mut T {
+        if !self.frames.contains_key(&frame) && self.frames.len() >= self.capacity {
+            self.evict(frame, requested);
+        }
+
+        let now = self.tick();
+        self.frames.insert(frame, (value, now));
+
+        // just inserted
+        &mut self.frames.get_mut(&frame).unwrap().0
+    }
+
+    fn evict(&mut self, inserting: u32, requested: u32) {
+        let near_requested = |frame: u32| frame.abs_diff(requested) <= self.keep_margin;
+
+        let least_recent = |keep: &dyn Fn(u32) -> bool| {
+            self.frames
+                .iter()
+                .filter(|(frame, _)| !keep(**frame))
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(frame, _)| *frame)
+        };
+
+        let frame = least_recent(&near_requested)
+            .or_else(|| least_recent(&|frame| frame == requested || frame == inserting));
+
+        if let Some(frame) = frame {
+            self.frames.remove(&frame);
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+/// Whether the decoder has to seek to produce `requested`, given the last frame it decoded
+/// and the frame numbers of the video's keyframes (sorted).
+///
+/// Decoding can only move forward, so going backwards always requires a seek.
+/// Going forwards, a seek only helps if there's a keyframe after the decoder's position
+/// that it can jump to - otherwise the seek would land in the current GOP and
+/// decode the same frames again.
+/// Without a keyframe index, big forward jumps are assumed to cross a GOP.
+pub fn needs_seek(requested: u32, last_decoded: Option<u32>, keyframes: &[u32]) -> bool {
+    let Some(last_decoded) = last_decoded else {
+        return true;
+    };
+
+    if requested <= last_decoded {
+        return requested < last_decoded;
+    }
+
+    if keyframes.is_empty() {
+        return requested - last_decoded > FRAME_CACHE_SIZE as u32;
+    }
+
+    // first keyframe after the decoder's position
+    let next_keyframe = keyframes.partition_point(|k| *k <= last_decoded);
+    keyframes
+        .get(next_keyframe)
+        .is_some_and(|keyframe| *keyframe <= requested)
+}
+
+/// The decoder thread, which is shut down and joined when the last
+/// [`AsyncVideoDecoderHandle`] is dropped.
+struct DecoderWorker {
+    sender: Mutex<Option<mpsc::Sender<VideoDecoderMessage>>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl DecoderWorker {
+    /// Returns `false` if the decoder has been closed or its thread has exited.
+    fn send(&self, msg: VideoDecoderMessage) -> bool {
+        self.sender
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|sender| sender.send(msg).is_ok())
+    }
+
+    fn close(&self) {
+        // the thread exits once its receiver disconnects
+        drop(self.sender.lock().unwrap().take());
+
+        if let Some(thread) = self.thread.lock().unwrap().take()
+            && thread.join().is_err()
+        {
+            tracing::error!("Decoder thread panicked");
+        }
+    }
+}
+
+impl Drop for DecoderWorker {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
 #[derive(Clone)]
 pub struct AsyncVideoDecoderHandle {
-    sender: mpsc::Sender<VideoDecoderMessage>,
+    worker: Arc<DecoderWorker>,
     offset: f64,
+    skipped_frames: Arc<AtomicUsize>,
+    output_format: DecoderOutputFormat,
+    length: VideoLength,
+    rotation: Rotation,
 }
 
 impl AsyncVideoDecoderHandle {
-    pub async fn get_frame(&self, time: f32) -> Option<DecodedFrame> {
-        let (tx, rx) = tokio::sync::oneshot::channel();
-        self.sender
-            .send(VideoDecoderMessage::GetFrame(self.get_time(time), tx))
-            .unwrap();
-        rx.await.ok()
+    /// Resolves to `None` if the video has no frame to show at `time`.
+    ///
+    /// The frame is the one on screen at `time`, so rendering at a different frame rate than the
+    /// video's drops or repeats its frames evenly, see [`FrameRate`].
+    pub async fn get_frame(&self, time: f64) -> Result<Option<DecodedFrame>, DecoderError> {
+        let frame = self.length.frame_rate.frame_at(self.get_time(time));
+
+        let mut frames = std::pin::pin!(self.request_frames(frame..frame + 1));
+        frames
+            .next()
+            .await
+            .transpose()
+            .map(|v| v.map(|(_, frame)| frame))
+    }
+
+    /// Decodes a single frame, numbered from the start of the segment like in [`Self::get_frames`],
+    /// blocking the current thread until it's ready. `None` means the video has no such frame.
+    ///
+    /// For callers that aren't running in an async runtime. This must not be called from
+    /// within an async context, as it panics there just like
+    /// [`tokio::sync::mpsc::Receiver::blocking_recv`].
+    pub fn blocking_get_frame(&self, frame: u32) -> Result<Option<DecodedFrame>, DecoderError> {
+        let frame = frame + self.offset_frames();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+
+        if !self.worker.send(VideoDecoderMessage::GetFrames(
+            frame..frame + 1,
+            FrameSender(tx),
+        )) {
+            return Err(DecoderError::Disconnected);
+        }
+
+        rx.blocking_recv()
+            .transpose()
+            .map(|v| v.map(|(_, frame)| frame))
+    }
+
+    /// Decodes a contiguous range of frames in one pass, which avoids a round-trip to the
+    /// decoder thread per frame. Frame numbers are relative to the start of the segment.
+    /// Frames are decoded as the stream is polled, and decoding stops if it's dropped.
+    pub fn get_frames(&self, range: Range<u32>) -> impl Stream<Item = FrameResult> + use<> {
+        let offset = self.offset_frames();
+
+        self.request_frames(range.start + offset..range.end + offset)
+            .map(move |v| v.map(|(frame, data)| (frame - offset, data)))
+    }
+
+    /// Hints that frames `from..from + count` are about to be requested in order, like during
+    /// playback, so the decoder can decode them into its cache before they're needed.
+    /// Frame numbers are relative to the start of the segment like in [`Self::get_frames`],
+    /// and at most [`MAX_PREFETCH_FRAMES`] are decoded.
+    ///
+    /// Prefetching gives way to requests, which wait at most for the frame being prefetched
+    /// when they arrive.
+    pub fn prefetch(&self, from: u32, count: u32) {
+        let from = from + self.offset_frames();
+
+        self.worker.send(VideoDecoderMessage::Prefetch(
+            from..from + count.min(MAX_PREFETCH_FRAMES),
+        ));
+    }
+
+    fn request_frames(&self, range: Range<u32>) -> impl Stream<Item = FrameResult> + use<> {
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+
+        let disconnected = !self
+            .worker
+            .send(VideoDecoderMessage::GetFrames(range, FrameSender(tx)));
+
+        futures::stream::iter(disconnected.then_some(Err(DecoderError::Disconnected))).chain(
+            futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|v| (v, rx)) }),
+        )
+    }
+
+    pub fn get_time(&self, time: f64) -> f64 {
+        time + self.offset
+    }
+
+    fn offset_frames(&self) -> u32 {
+        (self.offset * self.length.frame_rate.as_f64()).round() as u32
+    }
+
+    /// Number of corrupt frames the decoder has skipped over so far.
+    /// The last good frame is returned in their place.
+    pub fn skipped_frames(&self) -> usize {
+        self.skipped_frames.load(Ordering::Relaxed)
+    }
+
+    pub fn output_format(&self) -> DecoderOutputFormat {
+        self.output_format
+    }
+
+    /// How far the video's frames are turned to make them upright.
+    /// Frames are returned already rotated, with their width and height swapped if need be.
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    /// The rate frames are numbered at. This is the video's own rate when it's a fractional
+    /// version of the one it was spawned with, like 29.97fps for 30fps, and that rate otherwise.
+    pub fn frame_rate(&self) -> FrameRate {
+        self.length.frame_rate
+    }
+
+    /// Number of frames in the video.
+    ///
+    /// This is the frame count stored in the container when there is one, which is exact.
+    /// Otherwise, or if the video has a variable frame rate, it's estimated from the duration
+    /// and the rate frames are numbered at.
+    pub fn frame_count(&self) -> u32 {
+        self.length.frame_count
+    }
+
+    /// Duration of the video stream, falling back to the container's duration.
+    pub fn duration(&self) -> Duration {
+        self.length.duration
+    }
+
+    /// How evenly the video's frames are spaced, `None` if it has too few frames to tell.
+    /// See [`FrameTiming`] for how variable frame rate videos are decoded.
+    pub fn frame_timing(&self) -> Option<FrameTiming> {
+        self.length.timing
+    }
+
+    /// Stops the decoder thread and waits for it to exit, so the video file is closed
+    /// by the time this returns. This also happens when the last clone of the handle is dropped.
+    ///
+    /// Affects every clone of the handle, with later requests failing with
+    /// [`DecoderError::Disconnected`].
+    pub fn close(&self) {
+        self.worker.close();
+    }
+}
+
+/// How [`spawn_decoder`] decodes a video. The defaults decode to RGBA using this
+/// platform's hardware decoder, if there is one.
+#[derive(Debug, Clone, Copy)]
+pub struct DecoderOptions {
+    output_format: DecoderOutputFormat,
+    hw_device_type: Option<AVHWDeviceType>,
+    threads: DecoderThreads,
+    corrupt_frame_policy: CorruptFramePolicy,
+    color_override: Option<ColorInfo>,
+    allow_truncated: bool,
+}
+
+impl Default for DecoderOptions {
+    fn default() -> Self {
+        Self {
+            output_format: DecoderOutputFormat::default(),
+            hw_device_type: default_hw_device_type(),
+            threads: DecoderThreads::default(),
+            corrupt_frame_policy: CorruptFramePolicy::default(),
+            color_override: None,
+            allow_truncated: false,
+        }
+    }
+}
+
+impl DecoderOptions {
+    pub fn with_output_format(mut self, output_format: DecoderOutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// The hardware decoder to try using, or `None` to always decode in software.
+    /// Only applies to FFmpeg's decoding.
+    pub fn with_hw_device_type(mut self, hw_device_type: Option<AVHWDeviceType>) -> Self {
+        self.hw_device_type = hw_device_type;
+        self
+    }
+
+    /// Only applies to FFmpeg's software decoding.
+    pub fn with_threads(mut self, threads: DecoderThreads) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// With [`CorruptFramePolicy::Skip`], corrupt frames are counted in
+    /// [`AsyncVideoDecoderHandle::skipped_frames`] and the last good frame is returned in
+    /// their place. With [`CorruptFramePolicy::Fail`], the request that reaches one gets a
+    /// [`DecoderError::Decode`].
+    pub fn with_corrupt_frame_policy(mut self, corrupt_frame_policy: CorruptFramePolicy) -> Self {
+        self.corrupt_frame_policy = corrupt_frame_policy;
+        self
     }
 
-    pub fn get_time(&self, time: f32) -> f32 {
-        time + self.offset as f32
+    /// Converts YUV frames to RGB with this color space and range instead of the ones
+    /// the video is tagged with.
+    pub fn with_color_override(mut self, color_override: ColorInfo) -> Self {
+        self.color_override = Some(color_override);
+        self
+    }
+
+    /// Decodes a truncated file like a recording that wasn't finalized up to where it was
+    /// cut off, see [`cap_video_decode::FFmpegDecoder::with_allow_truncated`].
+    /// Requests for frames past that point get the last frame that could be decoded.
+    pub fn with_allow_truncated(mut self, allow_truncated: bool) -> Self {
+        self.allow_truncated = allow_truncated;
+        self
     }
 }
 
+/// Spawns a thread that decodes the video at `path`.
 pub async fn spawn_decoder(
     name: &'static str,
     path: PathBuf,
     fps: u32,
     offset: f64,
-) -> Result<AsyncVideoDecoderHandle, String> {
+    options: DecoderOptions,
+) -> Result<AsyncVideoDecoderHandle, MediaError> {
     let (ready_tx, ready_rx) = oneshot::channel::<Result<(), String>>();
     let (tx, rx) = mpsc::channel();
 
-    let handle = AsyncVideoDecoderHandle { sender: tx, offset };
+    let skipped_frames = Arc::new(AtomicUsize::new(0));
 
-    if cfg!(target_os = "macos") {
+    let length = VideoLength::probe(&path, fps).map_err(|e| match e {
+        MediaError::MissingMedia(_) => e,
+        e => MediaError::Any(format!("'{name}' decoder / probe length / {e}").into()),
+    })?;
+
+    let rotation = Rotation::probe(&path)
+        .map_err(|e| MediaError::Any(format!("'{name}' decoder / probe rotation / {e}").into()))?;
+
+    // AVAssetReader can't read truncated files
+    let thread = if cfg!(target_os = "macos") && !options.allow_truncated {
         #[cfg(target_os = "macos")]
-        avassetreader::AVAssetReaderDecoder::spawn(name, path, fps, rx, ready_tx);
+        {
+            avassetreader::AVAssetReaderDecoder::spawn(
+                path,
+                length.frame_rate,
+                rotation,
+                options,
+                rx,
+                ready_tx,
+                skipped_frames.clone(),
+            )
+        }
+        #[cfg(not(target_os = "macos"))]
+        unreachable!()
     } else {
-        ffmpeg::FfmpegDecoder::spawn(name, path, fps, rx, ready_tx)
-            .map_err(|e| format!("'{name}' decoder / {e}"))?;
+        ffmpeg::FfmpegDecoder::spawn(
+            path,
+            length.frame_rate,
+            rotation,
+            options,
+            rx,
+            ready_tx,
+            skipped_frames.clone(),
+        )
+    };
+
+    // created before waiting for setup, so the thread is still joined if setup fails
+    let handle = AsyncVideoDecoderHandle {
+        worker: Arc::new(DecoderWorker {
+            sender: Mutex::new(Some(tx)),
+            thread: Mutex::new(Some(thread)),
+        }),
+        offset,
+        skipped_frames,
+        output_format: options.output_format,
+        length,
+        rotation,
+    };
+
+    ready_rx
+        .await
+        .map_err(|_| MediaError::Any(format!("'{name}' decoder exited during setup").into()))?
+        .map_err(|e| MediaError::Any(format!("'{name}' decoder / {e}").into()))?;
+
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEYFRAMES: &[u32] = &[0, 30, 60, 90];
+
+    #[test]
+    fn seeks_without_decoded_frames() {
+        assert!(needs_seek(0, None, KEYFRAMES));
+        assert!(needs_seek(45, None, KEYFRAMES));
     }
 
-    ready_rx.await.map_err(|e| e.to_string())?.map(|()| handle)
+    #[test]
+    fn decodes_forward_within_gop() {
+        assert!(!needs_seek(10, Some(10), KEYFRAMES));
+        assert!(!needs_seek(11, Some(10), KEYFRAMES));
+        assert!(!needs_seek(29, Some(10), KEYFRAMES));
+    }
+
+    #[test]
+    fn seeks_forward_into_later_gop() {
+        assert!(needs_seek(30, Some(10), KEYFRAMES));
+        assert!(needs_seek(75, Some(10), KEYFRAMES));
+        assert!(!needs_seek(200, Some(95), KEYFRAMES));
+    }
+
+    #[test]
+    fn seeks_backward() {
+        assert!(needs_seek(35, Some(40), KEYFRAMES));
+        assert!(needs_seek(0, Some(1), KEYFRAMES));
+    }
+
+    #[test]
+    fn frame_count_prefers_container_value() {
+        assert_eq!(estimate_frame_count(299, 10.0, 30.0), 299);
+    }
+
+    #[test]
+    fn frame_count_falls_back_to_duration() {
+        assert_eq!(estimate_frame_count(0, 10.0, 30.0), 300);
+        assert_eq!(estimate_frame_count(0, 2.5, 29.97), 75);
+        assert_eq!(estimate_frame_count(0, 0.0, 60.0), 0);
+    }
+
+    /// A 3x2 plane whose pixels are 2 bytes, numbered in reading order
+    const PLANE: &[u8] = &[0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5];
+
+    fn rotated(rotation: Rotation) -> Vec<u8> {
+        let mut out = vec![];
+        rotate_plane(PLANE, 3, 2, 2, rotation, &mut out);
+        out.into_iter().step_by(2).collect()
+    }
+
+    #[test]
+    fn rotates_planes_clockwise() {
+        assert_eq!(rotated(Rotation::None), [0, 1, 2, 3, 4, 5]);
+        // 0 1 2    3 0
+        // 3 4 5 -> 4 1
+        //          5 2
+        assert_eq!(rotated(Rotation::Clockwise90), [3, 0, 4, 1, 5, 2]);
+        assert_eq!(rotated(Rotation::Clockwise180), [5, 4, 3, 2, 1, 0]);
+        assert_eq!(rotated(Rotation::Clockwise270), [2, 5, 1, 4, 0, 3]);
+    }
+
+    #[test]
+    fn rotated_nv12_frames_swap_dimensions() {
+        let (width, height) = (4, 2);
+        let frame = DecodedFrame {
+            // 4x2 Y plane, and a row of 2 UV pairs
+            data: Arc::new(vec![0; 8 + 4]),
+            width,
+            height,
+            stride: width,
+        };
+
+        let rotated = rotate_frame(
+            frame,
+            DecoderOutputFormat::Nv12,
+            Rotation::Clockwise90,
+            &mut FramePool::new(1),
+        );
+
+        assert_eq!((rotated.width, rotated.height, rotated.stride), (2, 4, 2));
+        assert_eq!(rotated.data.len(), 8 + 4);
+    }
+
+    #[test]
+    fn packed_frame_rows_line_up_with_odd_width() {
+        const WIDTH: u32 = 1282;
+        const HEIGHT: u32 = 3;
+
+        let mut frame = frame::Video::new(format::Pixel::RGBA, WIDTH, HEIGHT);
+        let stride = frame.stride(0);
+        // ffmpeg pads rows to its alignment, which 1282 * 4 bytes doesn't meet
+        assert!(stride > WIDTH as usize * 4);
+
+        for (y, row) in frame.data_mut(0).chunks_mut(stride).enumerate() {
+            row.fill(0xff);
+            for (x, pixel) in row[0..WIDTH as usize * 4].chunks_mut(4).enumerate() {
+                pixel.copy_from_slice(&[y as u8, (x % 256) as u8, (x / 256) as u8, 0]);
+            }
+        }
+
+        let packed = pack_frame(&frame, DecoderOutputFormat::Rgba, &mut FramePool::new(1));
+
+        assert_eq!(packed.width, WIDTH);
+        assert_eq!(packed.height, HEIGHT);
+        assert_eq!(packed.stride, WIDTH * 4);
+        assert_eq!(packed.data.len(), (WIDTH * HEIGHT * 4) as usize);
+
+        for (y, row) in packed.data.chunks(packed.stride as usize).enumerate() {
+            for (x, pixel) in row.chunks(4).enumerate() {
+                assert_eq!(pixel, [y as u8, (x % 256) as u8, (x / 256) as u8, 0]);
+            }
+        }
+    }
+
+    #[test]
+    fn packs_planar_formats() {
+        let mut frame = frame::Video::new(format::Pixel::YUV420P, 1281, 5);
+        for plane in 0..3 {
+            frame.data_mut(plane).fill(plane as u8 + 1);
+        }
+
+        let packed = pack_frame(&frame, DecoderOutputFormat::Yuv420p, &mut FramePool::new(1));
+
+        let luma = 1281 * 5;
+        let chroma = 641 * 3;
+        assert_eq!(packed.data.len(), luma + chroma * 2);
+        assert!(packed.data[..luma].iter().all(|v| *v == 1));
+        assert!(packed.data[luma..luma + chroma].iter().all(|v| *v == 2));
+        assert!(packed.data[luma + chroma..].iter().all(|v| *v == 3));
+    }
+
+    fn yuv_to_rgba(yuv: [u8; 3], color: ColorInfo) -> [u8; 4] {
+        let mut frame = frame::Video::new(format::Pixel::YUV420P, 16, 16);
+        for (plane, value) in yuv.into_iter().enumerate() {
+            frame.data_mut(plane).fill(value);
+        }
+
+        let output = convert_frame(&frame, DecoderOutputFormat::Rgba, color).unwrap();
+        output.data(0)[0..4].try_into().unwrap()
+    }
+
+    fn assert_close(actual: [u8; 4], expected: [u8; 3]) {
+        for (actual, expected) in actual.into_iter().zip(expected) {
+            assert!(
+                actual.abs_diff(expected) <= 3,
+                "{actual:?} isn't close to {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn converts_limited_range_bt709() {
+        let color = ColorInfo {
+            space: color::Space::BT709,
+            range: color::Range::MPEG,
+        };
+
+        // limited range BT.709 red, black and white
+        assert_close(yuv_to_rgba([63, 102, 240], color), [255, 0, 0]);
+        assert_close(yuv_to_rgba([16, 128, 128], color), [0, 0, 0]);
+        assert_close(yuv_to_rgba([235, 128, 128], color), [255, 255, 255]);
+    }
+
+    #[test]
+    fn converts_full_range() {
+        let color = ColorInfo {
+            space: color::Space::BT709,
+            range: color::Range::JPEG,
+        };
+
+        assert_close(yuv_to_rgba([235, 128, 128], color), [235, 235, 235]);
+        assert_close(yuv_to_rgba([0, 128, 128], color), [0, 0, 0]);
+    }
+
+    #[test]
+    fn resolves_unspecified_color_info() {
+        let unspecified = ColorInfo {
+            space: color::Space::Unspecified,
+            range: color::Range::Unspecified,
+        };
+        let mut frame = frame::Video::new(format::Pixel::NV12, 1920, 1080);
+
+        assert_eq!(
+            unspecified.resolve(&frame),
+            ColorInfo {
+                space: color::Space::BT709,
+                range: color::Range::MPEG,
+            }
+        );
+
+        frame.set_color_space(color::Space::BT470BG);
+        frame.set_color_range(color::Range::JPEG);
+        assert_eq!(
+            unspecified.resolve(&frame),
+            ColorInfo {
+                space: color::Space::BT470BG,
+                range: color::Range::JPEG,
+            }
+        );
+
+        let bt709 = ColorInfo {
+            space: color::Space::BT709,
+            range: color::Range::MPEG,
+        };
+        assert_eq!(bt709.resolve(&frame), bt709);
+    }
+
+    #[test]
+    fn pool_reuses_buffers_evicted_from_cache() {
+        const FRAMES: u32 = 500;
+
+        let frame = frame::Video::new(format::Pixel::RGBA, 1280, 720);
+        let mut cache = FrameCache::new(FRAME_CACHE_SIZE, CACHE_KEEP_MARGIN);
+        let mut pool = FramePool::new(FRAME_POOL_SIZE);
+
+        for number in 0..FRAMES {
+            let packed = pack_frame(&frame, DecoderOutputFormat::Rgba, &mut pool);
+            cache.insert(number, packed, number);
+        }
+
+        // only the frames held by the cache at once need their own buffer
+        assert!(pool.allocations <= FRAME_POOL_SIZE);
+        assert!(cache.len() <= FRAME_CACHE_SIZE);
+    }
+
+    #[test]
+    fn prefetching_during_playback_serves_frames_from_cache() {
+        const FRAMES: u32 = 1000;
+
+        // plays frames in order like the preview does, counting the requests that have to
+        // wait for a decode. Prefetches decode into the cache like the decoder threads do.
+        let waits = |prefetch: bool| {
+            let mut cache = FrameCache::new(FRAME_CACHE_SIZE, CACHE_KEEP_MARGIN);
+            let mut waits = 0;
+
+            for frame in 0..FRAMES {
+                if cache.get_mut(frame).is_none() {
+                    waits += 1;
+                    cache.insert(frame, (), frame);
+                }
+
+                if prefetch
+                    && let Some(start) =
+                        first_uncached(frame + 1..frame + 1 + MAX_PREFETCH_FRAMES, &cache)
+                {
+                    for prefetched in start..frame + 1 + MAX_PREFETCH_FRAMES {
+                        cache.insert(prefetched, (), start);
+                    }
+                }
+            }
+
+            waits
+        };
+
+        assert_eq!(waits(false), FRAMES);
+        assert_eq!(waits(true), 1);
+    }
+
+    #[test]
+    fn maps_long_recording_timestamps() {
+        // 3 hours at 60fps, in a 90kHz time base
+        let time_base = Rational::new(1, 90_000);
+        for frame in [647_999, 648_000] {
+            assert_eq!(
+                pts_to_frame(frame as i64 * 1500, time_base, FrameRate::from_fps(60)),
+                frame
+            );
+            assert_eq!(
+                pts_to_frame(
+                    frame as i64 * 1500 + 749,
+                    time_base,
+                    FrameRate::from_fps(60)
+                ),
+                frame
+            );
+            assert_eq!(
+                pts_to_frame(
+                    frame as i64 * 1500 + 750,
+                    time_base,
+                    FrameRate::from_fps(60)
+                ),
+                frame + 1
+            );
+        }
+    }
+
+    #[test]
+    fn rounds_exactly_near_overflow() {
+        // pts * fps is close to i64::MAX, where f64 can't tell which side of
+        // the half-frame boundary a timestamp is on
+        const TICKS: i64 = i32::MAX as i64;
+        let time_base = Rational::new(1, i32::MAX);
+        let frame = 4_000_000_000u32;
+        let start = frame as i64 * TICKS;
+
+        assert!(start.checked_mul(2).is_none());
+        assert_eq!(
+            pts_to_frame(start, time_base, FrameRate::from_fps(1)),
+            frame
+        );
+        assert_eq!(
+            pts_to_frame(start + TICKS / 2, time_base, FrameRate::from_fps(1)),
+            frame
+        );
+        assert_eq!(
+            pts_to_frame(start + TICKS / 2 + 1, time_base, FrameRate::from_fps(1)),
+            frame + 1
+        );
+        assert_eq!(
+            pts_to_frame(i64::MAX, time_base, FrameRate::from_fps(60)),
+            u32::MAX
+        );
+    }
+
+    #[test]
+    fn maps_fractional_rate_timestamps() {
+        // 29.97fps, in the 1/30000 time base it's usually stored in
+        let frame_rate = FrameRate::new(30_000, 1001).unwrap();
+        let time_base = Rational::new(1, 30_000);
+        for frame in [0, 999, 1000, 1001, 107_892] {
+            assert_eq!(
+                pts_to_frame(frame as i64 * 1001, time_base, frame_rate),
+                frame
+            );
+        }
+    }
+
+    #[test]
+    fn derives_frame_rate_from_timestamps() {
+        // 25fps in WebM's 1ms time base, out of order, with a dropped and a repeated frame
+        let timestamps = vec![0, 80, 40, 160, 200, 200, 240, 280];
+        let frame_rate = frame_rate_from_timestamps(timestamps, Rational::new(1, 1000)).unwrap();
+        assert_eq!(frame_rate.as_f64(), 25.0);
+
+        let timestamps = (0..10).map(|frame| frame * 1001).collect();
+        let frame_rate = frame_rate_from_timestamps(timestamps, Rational::new(1, 30_000)).unwrap();
+        assert_eq!(frame_rate, FrameRate::new(30_000, 1001).unwrap());
+
+        assert_eq!(
+            frame_rate_from_timestamps(vec![0], Rational::new(1, 1000)),
+            None
+        );
+        assert_eq!(
+            frame_rate_from_timestamps(vec![5, 5], Rational::new(1, 1000)),
+            None
+        );
+    }
+
+    #[test]
+    fn measures_frame_timing() {
+        let time_base = Rational::new(1, 15_360);
+
+        // 60fps, with a dropped frame and timestamps rounded either way
+        let mut timestamps = (0..60).map(|frame| frame * 256).collect::<Vec<_>>();
+        timestamps.remove(30);
+        timestamps[10] += 1;
+        timestamps[20] -= 1;
+        let timing = FrameTiming::measure(&timestamps, time_base).unwrap();
+        assert!(!timing.variable);
+        assert!((timing.average_fps - 58.0 / (59.0 / 60.0)).abs() < 0.01);
+
+        // a screen recording that's only written frames while something moved
+        let timestamps = [0, 256, 512, 3072, 3328, 3584, 3840, 10_240, 10_496, 15_360];
+        let timing = FrameTiming::measure(&timestamps, time_base).unwrap();
+        assert!(timing.variable);
+        assert!((timing.peak_fps - 60.0).abs() < 1e-9);
+        assert!((timing.average_fps - 9.0).abs() < 1e-9);
+
+        assert_eq!(FrameTiming::measure(&[100, 100], time_base), None);
+    }
+
+    #[test]
+    fn clamps_timestamps_before_start() {
+        assert_eq!(
+            pts_to_frame(-3000, Rational::new(1, 90_000), FrameRate::from_fps(60)),
+            0
+        );
+    }
+
+    /// Requests each frame like the decoders do, decoding forward from the frame's
+    /// keyframe when it isn't cached. Returns the number of frames decoded.
+    fn scrub(cache: &mut FrameCache<()>, gop: u32, requests: &[u32]) -> usize {
+        let mut decoded = 0;
+
+        for &requested in requests {
+            if cache.get_mut(requested).is_some() {
+                continue;
+            }
+
+            for frame in requested / gop * gop..=requested {
+                cache.insert(frame, (), requested);
+                decoded += 1;
+            }
+        }
+
+        decoded
+    }
+
+    #[test]
+    fn scrubbing_across_gop_boundary_reuses_cache() {
+        let mut cache = FrameCache::new(6, 2);
+
+        // 0..=4 from the first GOP, then 5..=6 from the second
+        assert_eq!(scrub(&mut cache, 5, &[4, 6]), 7);
+        assert_eq!(cache.len(), 6);
+        // 0 was evicted to make room for 6, so it's decoded again
+        assert_eq!(scrub(&mut cache, 5, &[0]), 1);
+        // both ends of the scrub are recently used, so neither is evicted
+        assert_eq!(scrub(&mut cache, 5, &[6, 0, 4, 6, 0, 5, 6, 0]), 0);
+    }
+
+    #[test]
+    fn never_evicts_requested_frame() {
+        let mut cache = FrameCache::new(2, 5);
+
+        cache.insert(10, (), 10);
+        cache.insert(11, (), 10);
+        cache.insert(12, (), 10);
+
+        assert!(cache.get_mut(10).is_some());
+        assert!(cache.get_mut(12).is_some());
+        assert!(cache.get_mut(11).is_none());
+    }
+
+    #[test]
+    fn finds_latest_frame_before() {
+        let mut cache = FrameCache::new(10, 2);
+        for frame in [3, 7, 9] {
+            cache.insert(frame, frame, frame);
+        }
+
+        assert_eq!(cache.latest_before(9).copied(), Some(7));
+        assert_eq!(cache.latest_before(3).copied(), None);
+    }
+
+    #[test]
+    fn closing_joins_decoder_thread() {
+        let (tx, rx) = mpsc::channel::<VideoDecoderMessage>();
+        let exited = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let thread = std::thread::spawn({
+            let exited = exited.clone();
+            move || {
+                while rx.recv().is_ok() {}
+                std::thread::sleep(Duration::from_millis(50));
+                exited.store(true, Ordering::SeqCst);
+            }
+        });
+
+        let worker = DecoderWorker {
+            sender: Mutex::new(Some(tx)),
+            thread: Mutex::new(Some(thread)),
+        };
+        worker.close();
+
+        assert!(exited.load(Ordering::SeqCst));
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        assert!(!worker.send(VideoDecoderMessage::GetFrames(0..1, FrameSender(tx))));
+    }
+
+    #[test]
+    fn falls_back_to_distance_without_keyframes() {
+        assert!(!needs_seek(50, Some(10), &[]));
+        assert!(needs_seek(10 + FRAME_CACHE_SIZE as u32 + 1, Some(10), &[]));
+    }
+
+    /// Writes a 30fps PNG video whose packet `corrupt` is replaced with bytes that aren't a PNG,
+    /// like a frame of a damaged recording.
+    fn write_corrupt_video(name: &str, frames: i64, corrupt: i64) -> PathBuf {
+        ::ffmpeg::init().unwrap();
+
+        let path =
+            std::env::temp_dir().join(format!("cap-rendering-{name}-{}.mov", std::process::id()));
+        let mut output = format::output(&path).unwrap();
+        let codec = ::ffmpeg::encoder::find(::ffmpeg::codec::Id::PNG).unwrap();
+
+        let mut encoder = ::ffmpeg::codec::Context::new_with_codec(codec)
+            .encoder()
+            .video()
+            .unwrap();
+        encoder.set_width(32);
+        encoder.set_height(32);
+        encoder.set_format(format::Pixel::RGB24);
+        encoder.set_time_base(Rational::new(1, 30));
+        let mut encoder = encoder.open().unwrap();
+
+        output.add_stream(codec).unwrap().set_parameters(&encoder);
+        output.write_header().unwrap();
+        let time_base = output.stream(0).unwrap().time_base();
+
+        let mut frame = frame::Video::new(format::Pixel::RGB24, 32, 32);
+        frame.data_mut(0).fill(128);
+        let mut packet = ::ffmpeg::Packet::empty();
+
+        for i in 0..=frames {
+            if i < frames {
+                frame.set_pts(Some(i));
+                encoder.send_frame(&frame).unwrap();
+            } else {
+                encoder.send_eof().unwrap();
+            }
+
+            while encoder.receive_packet(&mut packet).is_ok() {
+                let mut packet = if packet.pts() == Some(corrupt) {
+                    let mut garbage = ::ffmpeg::Packet::copy(&[0xAB; 64]);
+                    garbage.set_pts(packet.pts());
+                    garbage.set_dts(packet.dts());
+                    garbage.set_duration(packet.duration());
+                    garbage.set_flags(packet.flags());
+                    garbage
+                } else {
+                    packet.clone()
+                };
+
+                packet.set_stream(0);
+                packet.rescale_ts(Rational::new(1, 30), time_base);
+                packet.write_interleaved(&mut output).unwrap();
+            }
+        }
+
+        output.write_trailer().unwrap();
+        path
+    }
+
+    /// Requests every frame from an FFmpeg decoder thread, returning the results
+    /// and how many frames were skipped.
+    fn decode_frames(
+        path: PathBuf,
+        frames: u32,
+        policy: CorruptFramePolicy,
+    ) -> (Vec<FrameResult>, usize) {
+        let (tx, rx) = mpsc::channel();
+        let (ready_tx, mut ready_rx) = oneshot::channel();
+        let skipped_frames = Arc::new(AtomicUsize::new(0));
+
+        let thread = super::ffmpeg::FfmpegDecoder::spawn(
+            path,
+            FrameRate::from_fps(30),
+            Rotation::None,
+            DecoderOptions::default()
+                .with_hw_device_type(None)
+                .with_threads(DecoderThreads::Frame(1))
+                .with_corrupt_frame_policy(policy),
+            rx,
+            ready_tx,
+            skipped_frames.clone(),
+        );
+
+        let (frame_tx, mut frame_rx) = tokio::sync::mpsc::channel(frames as usize);
+        tx.send(VideoDecoderMessage::GetFrames(
+            0..frames,
+            FrameSender(frame_tx),
+        ))
+        .unwrap();
+        drop(tx);
+
+        let mut results = vec![];
+        while let Some(result) = frame_rx.blocking_recv() {
+            results.push(result);
+        }
+        thread.join().unwrap();
+        ready_rx.try_recv().unwrap().unwrap();
+
+        (results, skipped_frames.load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn skips_corrupt_frames() {
+        let path = write_corrupt_video("skip", 10, 5);
+        let (results, skipped) = decode_frames(path.clone(), 10, CorruptFramePolicy::Skip);
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(results.len(), 10);
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn fails_on_corrupt_frames() {
+        let path = write_corrupt_video("fail", 10, 5);
+        let (results, skipped) = decode_frames(path.clone(), 10, CorruptFramePolicy::Fail);
+        std::fs::remove_file(&path).ok();
+
+        assert!(results[..5].iter().all(Result::is_ok));
+        assert!(matches!(results[5], Err(DecoderError::Decode(_))));
+        assert_eq!(skipped, 0);
+    }
 }
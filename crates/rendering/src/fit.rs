@@ -0,0 +1,155 @@
+use cap_media::{MediaError, data::RgbaFrame};
+use cap_project::XY;
+use image::{RgbaImage, imageops};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+use crate::{background_compositor::draw_background, layers::Background};
+
+/// How frames are fit to an output size with a different aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub enum FitMode {
+    /// Scale each axis separately to fill the output, distorting the frame
+    Stretch,
+    /// Scale the frame to fit inside the output, filling the bars beside it with the background
+    Contain,
+    /// Scale the frame to fill the output, cropping whichever axis overflows
+    Cover,
+}
+
+/// Scales rendered frames to an exact output size without a GPU.
+pub struct FrameFitter {
+    mode: FitMode,
+    size: XY<u32>,
+    /// The background already drawn at the output size
+    background: RgbaImage,
+}
+
+impl FrameFitter {
+    /// `background` fills the bars left by [`FitMode::Contain`].
+    /// Fails if it's an image that can't be loaded.
+    pub fn new(mode: FitMode, size: XY<u32>, background: &Background) -> Result<Self, MediaError> {
+        Ok(Self {
+            mode,
+            size,
+            background: draw_background(background, size)?,
+        })
+    }
+
+    pub fn mode(&self) -> FitMode {
+        self.mode
+    }
+
+    pub fn size(&self) -> XY<u32> {
+        self.size
+    }
+
+    /// Frames that are already the output size are returned as they are.
+    pub fn apply(&self, frame: RgbaFrame) -> RgbaFrame {
+        let (width, height) = (frame.width(), frame.height());
+        if (width, height) == (self.size.x, self.size.y) || width == 0 || height == 0 {
+            return frame;
+        }
+
+        let image = frame.into_image();
+        let scale_x = self.size.x as f64 / width as f64;
+        let scale_y = self.size.y as f64 / height as f64;
+        let resize = |scale: f64, round: fn(f64) -> f64| {
+            imageops::resize(
+                &image,
+                (round(width as f64 * scale) as u32).max(1),
+                (round(height as f64 * scale) as u32).max(1),
+                imageops::FilterType::Triangle,
+            )
+        };
+
+        let output = match self.mode {
+            FitMode::Stretch => imageops::resize(
+                &image,
+                self.size.x,
+                self.size.y,
+                imageops::FilterType::Triangle,
+            ),
+            FitMode::Contain => {
+                let scaled = resize(scale_x.min(scale_y), f64::round);
+                let mut output = self.background.clone();
+
+                imageops::overlay(
+                    &mut output,
+                    &scaled,
+                    (self.size.x.saturating_sub(scaled.width()) / 2) as i64,
+                    (self.size.y.saturating_sub(scaled.height()) / 2) as i64,
+                );
+                output
+            }
+            FitMode::Cover => {
+                let scaled = resize(scale_x.max(scale_y), f64::ceil);
+
+                imageops::crop_imm(
+                    &scaled,
+                    scaled.width().saturating_sub(self.size.x) / 2,
+                    scaled.height().saturating_sub(self.size.y) / 2,
+                    self.size.x,
+                    self.size.y,
+                )
+                .to_image()
+            }
+        };
+
+        RgbaFrame::from(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+    fn red(width: u32, height: u32) -> RgbaFrame {
+        RgbaFrame::packed(RED.repeat((width * height) as usize), width, height).unwrap()
+    }
+
+    fn fitter(mode: FitMode, width: u32, height: u32) -> FrameFitter {
+        FrameFitter::new(mode, XY::new(width, height), &Background::Color(BLUE)).unwrap()
+    }
+
+    #[test]
+    fn stretches_to_output_size() {
+        let output = fitter(FitMode::Stretch, 100, 100).apply(red(160, 90));
+
+        assert_eq!((output.width(), output.height()), (100, 100));
+        assert_eq!(output.pixel(0, 0), RED);
+        assert_eq!(output.pixel(99, 99), RED);
+    }
+
+    #[test]
+    fn letterboxes_with_background() {
+        let output = fitter(FitMode::Contain, 100, 100).apply(red(160, 90));
+
+        assert_eq!((output.width(), output.height()), (100, 100));
+        // 100x56 frame centered with 22 pixel bars above and below
+        assert_eq!(output.pixel(50, 10), [0, 0, 255, 255]);
+        assert_eq!(output.pixel(50, 50), RED);
+        assert_eq!(output.pixel(0, 22), RED);
+        assert_eq!(output.pixel(50, 90), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn crops_to_fill() {
+        let output = fitter(FitMode::Cover, 100, 100).apply(red(160, 90));
+
+        assert_eq!((output.width(), output.height()), (100, 100));
+        assert_eq!(output.pixel(0, 0), RED);
+        assert_eq!(output.pixel(99, 99), RED);
+    }
+
+    #[test]
+    fn leaves_frames_at_output_size() {
+        let frame = red(100, 100);
+        let output = fitter(FitMode::Cover, 100, 100).apply(frame.clone());
+
+        assert_eq!(output, frame);
+    }
+}
@@ -1,4 +1,6 @@
+use cap_media::{MediaError, data::RgbaFrame};
 use futures_intrusive::channel::shared::oneshot_channel;
+use std::sync::Arc;
 use wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
 
 use crate::{ProjectUniforms, RenderSession, RenderingError};
@@ -39,6 +41,30 @@ pub struct RenderedFrame {
     pub padded_bytes_per_row: u32,
 }
 
+impl TryFrom<RenderedFrame> for RgbaFrame {
+    type Error = MediaError;
+
+    fn try_from(frame: RenderedFrame) -> Result<Self, Self::Error> {
+        RgbaFrame::new(
+            Arc::new(frame.data),
+            frame.width,
+            frame.height,
+            frame.padded_bytes_per_row,
+        )
+    }
+}
+
+impl From<RgbaFrame> for RenderedFrame {
+    fn from(frame: RgbaFrame) -> Self {
+        Self {
+            width: frame.width(),
+            height: frame.height(),
+            padded_bytes_per_row: frame.stride(),
+            data: Arc::unwrap_or_clone(frame.into_data()),
+        }
+    }
+}
+
 // impl FramePipelineEncoder {
 //     pub fn new(state: &FramePipelineState) -> Self {
 //         Self {
@@ -0,0 +1,161 @@
+use ffmpeg::Rational;
+
+/// How far before a frame's start a time can fall and still be treated as showing that frame.
+/// Absorbs the float error of converting an output frame number to a time and back.
+const FRAME_EPSILON: f64 = 1e-6;
+
+/// A frame rate as an exact fraction of frames per second,
+/// so fractional rates like 29.97fps (30000/1001) aren't rounded.
+///
+/// Converting between videos of different rates goes through time: an output frame's
+/// [`frame_time`](Self::frame_time) is looked up in the source with [`frame_at`](Self::frame_at),
+/// which shows whichever source frame is on screen at that moment. Source frames are
+/// dropped or repeated at an even cadence, e.g. every other frame of a 60fps source
+/// exported at 30fps, or one repeated frame every 1001 when exporting 29.97fps at 30fps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRate {
+    numerator: u32,
+    denominator: u32,
+}
+
+impl FrameRate {
+    /// `None` if either part is zero.
+    pub fn new(numerator: u32, denominator: u32) -> Option<Self> {
+        (numerator > 0 && denominator > 0).then_some(Self {
+            numerator,
+            denominator,
+        })
+    }
+
+    /// A whole number of frames per second. Zero is treated as 1fps.
+    pub const fn from_fps(fps: u32) -> Self {
+        Self {
+            numerator: if fps == 0 { 1 } else { fps },
+            denominator: 1,
+        }
+    }
+
+    pub fn numerator(&self) -> u32 {
+        self.numerator
+    }
+
+    pub fn denominator(&self) -> u32 {
+        self.denominator
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+
+    /// Rounded to the nearest whole frame, and at least 1.
+    pub fn rounded_fps(&self) -> u32 {
+        (self.as_f64().round() as u32).max(1)
+    }
+
+    /// When `frame` starts, in seconds.
+    pub fn frame_time(&self, frame: u32) -> f64 {
+        frame as f64 * self.denominator as f64 / self.numerator as f64
+    }
+
+    /// The frame on screen at `time` in seconds, which is the last one to start at or
+    /// before it. Times before the start map to frame 0.
+    pub fn frame_at(&self, time: f64) -> u32 {
+        (time * self.numerator as f64 / self.denominator as f64 + FRAME_EPSILON)
+            .floor()
+            .max(0.0) as u32
+    }
+}
+
+impl TryFrom<Rational> for FrameRate {
+    type Error = ();
+
+    fn try_from(value: Rational) -> Result<Self, Self::Error> {
+        let (numerator, denominator) = (value.numerator(), value.denominator());
+        if numerator <= 0 || denominator <= 0 {
+            return Err(());
+        }
+
+        Self::new(numerator as u32, denominator as u32).ok_or(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NTSC_30: FrameRate = FrameRate {
+        numerator: 30_000,
+        denominator: 1001,
+    };
+
+    /// The source frame shown for each of the first `count` output frames.
+    fn convert(source: FrameRate, output: FrameRate, count: u32) -> Vec<u32> {
+        (0..count)
+            .map(|frame| source.frame_at(output.frame_time(frame)))
+            .collect()
+    }
+
+    #[test]
+    fn drops_every_other_frame_exporting_60_at_30() {
+        let frames = convert(
+            FrameRate::from_fps(60),
+            FrameRate::from_fps(30),
+            30 * 60 * 10,
+        );
+
+        for (output, source) in frames.into_iter().enumerate() {
+            assert_eq!(source, output as u32 * 2);
+        }
+    }
+
+    #[test]
+    fn repeats_every_frame_exporting_30_at_60() {
+        let frames = convert(FrameRate::from_fps(30), FrameRate::from_fps(60), 8);
+
+        assert_eq!(frames, [0, 0, 1, 1, 2, 2, 3, 3]);
+    }
+
+    #[test]
+    fn repeats_one_frame_in_1001_exporting_29_97_at_30() {
+        let frames = convert(NTSC_30, FrameRate::from_fps(30), 30 * 60 * 10);
+
+        // source frames run 1001/1000 as long as output ones, so 18 of them are shown twice
+        let repeats = frames.windows(2).filter(|w| w[0] == w[1]).count();
+        assert!(frames.windows(2).all(|w| w[1] - w[0] <= 1));
+        assert_eq!(repeats, 18);
+        assert_eq!(frames[1], 0);
+        assert_eq!(frames[1000], 999);
+        assert_eq!(frames[1001], 1000);
+    }
+
+    #[test]
+    fn drops_one_frame_in_1000_exporting_30_at_29_97() {
+        let frames = convert(FrameRate::from_fps(30), NTSC_30, 30 * 60 * 10);
+
+        // a source frame is skipped at every 1000th output frame
+        let drops = frames.windows(2).filter(|w| w[1] - w[0] == 2).count();
+        assert!(frames.windows(2).all(|w| (1..=2).contains(&(w[1] - w[0]))));
+        assert_eq!(drops, 17);
+        assert_eq!(frames[999..=1000], [999, 1001]);
+    }
+
+    #[test]
+    fn keeps_every_frame_at_the_same_fractional_rate() {
+        let frames = convert(NTSC_30, NTSC_30, 30 * 60 * 60);
+
+        for (output, source) in frames.into_iter().enumerate() {
+            assert_eq!(source, output as u32);
+        }
+    }
+
+    #[test]
+    fn converts_from_stream_rates() {
+        assert_eq!(
+            FrameRate::try_from(Rational::new(30_000, 1001)),
+            Ok(NTSC_30)
+        );
+        assert_eq!(NTSC_30.rounded_fps(), 30);
+        assert!(FrameRate::try_from(Rational::new(0, 1)).is_err());
+        assert!(FrameRate::try_from(Rational::new(30, 0)).is_err());
+    }
+}
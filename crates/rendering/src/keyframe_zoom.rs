@@ -0,0 +1,152 @@
+use cap_media::data::RgbaFrame;
+use cap_project::XY;
+use image::{RgbaImage, imageops};
+
+/// The part of the frame to show at a point in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZoomKeyframe {
+    /// Seconds from the start of the video
+    pub time: f64,
+    /// Point to zoom in on, from 0 to 1 in each axis
+    pub center: XY<f64>,
+    /// How far to zoom in, where 1 shows the whole frame
+    pub scale: f64,
+}
+
+/// Zooms and pans decoded frames along a list of keyframes, easing in and out between them.
+///
+/// Frames keep their size, so this can be applied to decoded frames before they're encoded.
+/// Before the first and after the last keyframe, the nearest keyframe is held.
+pub struct KeyframeZoom {
+    keyframes: Vec<ZoomKeyframe>,
+}
+
+impl KeyframeZoom {
+    pub fn new(mut keyframes: Vec<ZoomKeyframe>) -> Self {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { keyframes }
+    }
+
+    /// The interpolated center and scale at `time`, or `None` if there are no keyframes.
+    pub fn interpolate(&self, time: f64) -> Option<(XY<f64>, f64)> {
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+
+        let (prev, next) = match (
+            next.checked_sub(1).map(|i| &self.keyframes[i]),
+            self.keyframes.get(next),
+        ) {
+            (None, None) => return None,
+            (Some(k), None) | (None, Some(k)) => return Some((k.center, k.scale)),
+            (Some(prev), Some(next)) => (prev, next),
+        };
+
+        let t = ease_in_out((time - prev.time) / (next.time - prev.time));
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+
+        Some((
+            XY::new(
+                lerp(prev.center.x, next.center.x),
+                lerp(prev.center.y, next.center.y),
+            ),
+            lerp(prev.scale, next.scale),
+        ))
+    }
+
+    /// The top-left corner and size of the area of a `frame_size` frame to show at `time`,
+    /// moved as needed to stay inside the frame.
+    pub fn crop_at(&self, time: f64, frame_size: XY<u32>) -> (XY<f64>, XY<f64>) {
+        let frame_size = XY::new(frame_size.x as f64, frame_size.y as f64);
+        let Some((center, scale)) = self.interpolate(time) else {
+            return (XY::new(0.0, 0.0), frame_size);
+        };
+
+        let size = XY::new(frame_size.x / scale.max(1.0), frame_size.y / scale.max(1.0));
+        let top_left = XY::new(
+            (center.x * frame_size.x - size.x / 2.0).clamp(0.0, frame_size.x - size.x),
+            (center.y * frame_size.y - size.y / 2.0).clamp(0.0, frame_size.y - size.y),
+        );
+
+        (top_left, size)
+    }
+
+    /// Crops an RGBA frame to the area shown at `time` and scales it back up to the frame's size.
+    pub fn apply(&self, frame: RgbaFrame, time: f64) -> RgbaFrame {
+        let (width, height) = (frame.width(), frame.height());
+        let (top_left, size) = self.crop_at(time, XY::new(width, height));
+
+        if size.x >= width as f64 && size.y >= height as f64 {
+            return frame;
+        }
+
+        let image = frame.into_image();
+        let cropped = imageops::crop_imm(
+            &image,
+            top_left.x.round() as u32,
+            top_left.y.round() as u32,
+            (size.x.round() as u32).max(1),
+            (size.y.round() as u32).max(1),
+        )
+        .to_image();
+        let zoomed: RgbaImage =
+            imageops::resize(&cropped, width, height, imageops::FilterType::Triangle);
+
+        zoomed.into()
+    }
+}
+
+/// Cubic ease-in-out of `t`, clamped between 0 and 1.
+fn ease_in_out(t: f64) -> f64 {
+    let t = t.clamp(0.0, 1.0);
+
+    if t < 0.5 {
+        4.0 * t * t * t
+    } else {
+        1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keyframe(time: f64, x: f64, y: f64, scale: f64) -> ZoomKeyframe {
+        ZoomKeyframe {
+            time,
+            center: XY::new(x, y),
+            scale,
+        }
+    }
+
+    #[test]
+    fn eases_between_keyframes() {
+        let zoom = KeyframeZoom::new(vec![
+            keyframe(1.0, 0.5, 0.5, 3.0),
+            keyframe(0.0, 0.5, 0.5, 1.0),
+        ]);
+
+        assert_eq!(zoom.interpolate(0.5).unwrap().1, 2.0);
+        assert!(zoom.interpolate(0.25).unwrap().1 < 1.5);
+        assert_eq!(zoom.interpolate(-1.0).unwrap().1, 1.0);
+        assert_eq!(zoom.interpolate(2.0).unwrap().1, 3.0);
+    }
+
+    #[test]
+    fn clamps_crop_to_frame() {
+        let zoom = KeyframeZoom::new(vec![keyframe(0.0, 1.0, 0.0, 2.0)]);
+
+        assert_eq!(
+            zoom.crop_at(0.0, XY::new(200, 100)),
+            (XY::new(100.0, 0.0), XY::new(100.0, 50.0))
+        );
+    }
+
+    #[test]
+    fn shows_whole_frame_without_keyframes() {
+        let zoom = KeyframeZoom::new(vec![]);
+
+        assert_eq!(
+            zoom.crop_at(3.0, XY::new(200, 100)),
+            (XY::new(0.0, 0.0), XY::new(200.0, 100.0))
+        );
+    }
+}
@@ -12,9 +12,9 @@ use crate::{
 
 #[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize, Type)]
 pub struct Gradient {
-    start: [f32; 4],
-    end: [f32; 4],
-    angle: f32,
+    pub(crate) start: [f32; 4],
+    pub(crate) end: [f32; 4],
+    pub(crate) angle: f32,
 }
 
 #[derive(PartialEq)]
@@ -76,10 +76,10 @@ impl CameraLayer {
                 origin: wgpu::Origin3d::ZERO,
                 aspect: wgpu::TextureAspect::All,
             },
-            camera_frame,
+            &camera_frame.data,
             wgpu::TexelCopyBufferLayout {
                 offset: 0,
-                bytes_per_row: Some(frame_size.x * 4),
+                bytes_per_row: Some(camera_frame.stride),
                 rows_per_image: None,
             },
             wgpu::Extent3d {
@@ -11,6 +11,8 @@ pub struct DisplayLayer {
     uniforms_buffer: wgpu::Buffer,
     pipeline: CompositeVideoFramePipeline,
     bind_group: Option<wgpu::BindGroup>,
+    /// Whether the last prepared frame had a screen frame to show
+    visible: bool,
 }
 
 impl DisplayLayer {
@@ -28,6 +30,7 @@ impl DisplayLayer {
             uniforms_buffer,
             pipeline,
             bind_group,
+            visible: false,
         }
     }
 
@@ -55,31 +58,43 @@ impl DisplayLayer {
             ));
         }
 
-        queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: &self.frame_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &segment_frames.screen_frame,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(frame_size.x * 4),
-                rows_per_image: None,
-            },
-            wgpu::Extent3d {
-                width: frame_size.x,
-                height: frame_size.y,
-                depth_or_array_layers: 1,
-            },
-        );
+        self.visible = segment_frames.screen_frame.is_some();
+        if let Some(screen_frame) = &segment_frames.screen_frame {
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &self.frame_texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &screen_frame.data,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(screen_frame.stride),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width: frame_size.x,
+                    height: frame_size.y,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
 
         // Update existing uniform buffer in place; bind group remains valid.
         uniforms.write_to_buffer(queue, &self.uniforms_buffer);
     }
 
+    /// Nothing is drawn for gaps in the recording, leaving just the background.
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
     pub fn render(&self, pass: &mut wgpu::RenderPass<'_>) {
+        if !self.visible {
+            return;
+        }
+
         if let Some(bind_group) = &self.bind_group {
             pass.set_pipeline(&self.pipeline.render_pipeline);
             pass.set_bind_group(0, bind_group, &[]);
@@ -1,4 +1,5 @@
 use anyhow::Result;
+use cap_media::{PipelineMetrics, PipelineStage};
 use cap_project::{
     AspectRatio, CameraShape, CameraXPosition, CameraYPosition, Crop, CursorEvents,
     ProjectConfiguration, RecordingMeta, StudioRecordingMeta, XY,
@@ -6,35 +7,52 @@ use cap_project::{
 use composite_frame::CompositeVideoFrameUniforms;
 use core::f64;
 use cursor_interpolation::{InterpolatedCursorPosition, interpolate_cursor};
-use decoder::{AsyncVideoDecoderHandle, spawn_decoder};
+use decoder::{AsyncVideoDecoderHandle, DecoderOptions, spawn_decoder};
 use frame_pipeline::finish_encoder;
 use futures::FutureExt;
 use futures::future::OptionFuture;
-use layers::{
-    Background, BackgroundLayer, BlurLayer, CameraLayer, CaptionsLayer, CursorLayer, DisplayLayer,
-};
+use layers::{BackgroundLayer, BlurLayer, CameraLayer, CaptionsLayer, CursorLayer, DisplayLayer};
 use specta::Type;
 use spring_mass_damper::SpringMassDamperSimulationConfig;
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, ops::Range, sync::Arc};
 use std::{path::PathBuf, time::Instant};
 use tokio::sync::mpsc;
 use tracing::error;
 
+mod background_compositor;
+mod camera_overlay;
 mod composite_frame;
 mod coord;
 mod cursor_interpolation;
 pub mod decoder;
+mod fit;
 mod frame_pipeline;
+mod frame_rate;
+mod keyframe_zoom;
 mod layers;
 mod project_recordings;
 mod scene;
+mod screenshot;
 mod spring_mass_damper;
+mod storyboard;
+mod thumbnail;
 mod zoom;
 
+pub use background_compositor::{BackgroundCompositor, ShadowParams};
+pub use camera_overlay::{CameraOverlayFilter, CameraOverlayOptions, CameraOverlayShape};
 pub use coord::*;
-pub use decoder::DecodedFrame;
+pub use decoder::{
+    ColorInfo, DecodedFrame, DecoderError, DecoderOutputFormat, FrameTiming, Rotation,
+};
+pub use fit::{FitMode, FrameFitter};
 pub use frame_pipeline::RenderedFrame;
+pub use frame_rate::FrameRate;
+pub use keyframe_zoom::{KeyframeZoom, ZoomKeyframe};
+pub use layers::Background;
 pub use project_recordings::{ProjectRecordingsMeta, SegmentRecordings};
+pub use screenshot::screenshot_at_frame;
+pub use storyboard::{Storyboard, StoryboardCell, StoryboardMetadata, generate_storyboard};
+pub use thumbnail::{ThumbnailSize, extract_best_thumbnail, extract_thumbnail};
 
 use scene::*;
 use zoom::*;
@@ -86,13 +104,10 @@ impl RecordingSegmentDecoders {
                 StudioRecordingMeta::SingleSegment { .. } => 0.0,
                 StudioRecordingMeta::MultipleSegments { inner, .. } => {
                     let segment = &inner.segments[segment_i];
-
-                    latest_start_time
-                        .zip(segment.display.start_time)
-                        .map(|(latest_start_time, display_time)| latest_start_time - display_time)
-                        .unwrap_or(0.0)
+                    segment.start_offset(segment.display.start_time)
                 }
             },
+            DecoderOptions::default(),
         )
         .await
         .map_err(|e| format!("Screen:{e}"))?;
@@ -112,13 +127,10 @@ impl RecordingSegmentDecoders {
                     StudioRecordingMeta::SingleSegment { .. } => 0.0,
                     StudioRecordingMeta::MultipleSegments { inner, .. } => {
                         let segment = &inner.segments[segment_i];
-
-                        latest_start_time
-                            .zip(segment.camera.as_ref().and_then(|c| c.start_time))
-                            .map(|(latest_start_time, start_time)| latest_start_time - start_time)
-                            .unwrap_or(0.0)
+                        segment.start_offset(segment.camera.as_ref().and_then(|c| c.start_time))
                     }
                 },
+                DecoderOptions::default(),
             )
             .then(|r| async { r.map_err(|e| format!("Camera:{e}")) })
         }))
@@ -134,9 +146,9 @@ impl RecordingSegmentDecoders {
 
     pub async fn get_frames(
         &self,
-        segment_time: f32,
+        segment_time: f64,
         needs_camera: bool,
-    ) -> Option<DecodedSegmentFrames> {
+    ) -> Result<Option<DecodedSegmentFrames>, DecoderError> {
         let (screen, camera) = tokio::join!(
             self.screen.get_frame(segment_time),
             OptionFuture::from(
@@ -146,12 +158,27 @@ impl RecordingSegmentDecoders {
             )
         );
 
-        Some(DecodedSegmentFrames {
-            screen_frame: screen?,
-            camera_frame: camera.flatten(),
-            segment_time,
-            recording_time: segment_time + self.segment_offset as f32,
-        })
+        let Some(screen_frame) = screen? else {
+            return Ok(None);
+        };
+
+        Ok(Some(DecodedSegmentFrames {
+            screen_frame: Some(screen_frame),
+            camera_frame: camera.transpose()?.flatten(),
+            segment_time: segment_time as f32,
+            recording_time: (segment_time + self.segment_offset) as f32,
+        }))
+    }
+
+    /// Frames for a point in the timeline the recording has no frame for, like past the end
+    /// of a segment that's shorter than its timeline clip, which render as just the background.
+    pub fn gap_frames(&self, segment_time: f64) -> DecodedSegmentFrames {
+        DecodedSegmentFrames {
+            screen_frame: None,
+            camera_frame: None,
+            segment_time: segment_time as f32,
+            recording_time: (segment_time + self.segment_offset) as f32,
+        }
     }
 }
 
@@ -171,6 +198,8 @@ pub enum RenderingError {
     ImageLoadError(String),
     #[error("Error polling wgpu: {0}")]
     PollError(#[from] wgpu::PollError),
+    #[error("Decoder: {0}")]
+    Decoder(#[from] DecoderError),
 }
 
 pub struct RenderSegment {
@@ -178,6 +207,8 @@ pub struct RenderSegment {
     pub decoders: RecordingSegmentDecoders,
 }
 
+/// Only frames within `frame_range` are rendered.
+/// They're sent numbered relative to the start of the range.
 #[allow(clippy::too_many_arguments)]
 pub async fn render_video_to_channel(
     constants: &RenderVideoConstants,
@@ -189,44 +220,114 @@ pub async fn render_video_to_channel(
     fps: u32,
     resolution_base: XY<u32>,
     recordings: &ProjectRecordingsMeta,
+    frame_range: Range<u32>,
+    metrics: &PipelineMetrics,
+) -> Result<(), RenderingError> {
+    let number_offset = frame_range.start;
+
+    render_ranges_to_channel(
+        constants,
+        project,
+        sender,
+        recording_meta,
+        meta,
+        segments,
+        fps,
+        resolution_base,
+        recordings,
+        vec![frame_range],
+        number_offset,
+        metrics,
+    )
+    .await
+}
+
+/// Renders each of `ranges` in turn with the same decoders, which lets several renderers
+/// split up the timeline between them. Frames are sent with their timeline frame number.
+#[allow(clippy::too_many_arguments)]
+pub async fn render_video_ranges_to_channel(
+    constants: &RenderVideoConstants,
+    project: &ProjectConfiguration,
+    sender: mpsc::Sender<(RenderedFrame, u32)>,
+    recording_meta: &RecordingMeta,
+    meta: &StudioRecordingMeta,
+    segments: Vec<RenderSegment>,
+    fps: u32,
+    resolution_base: XY<u32>,
+    recordings: &ProjectRecordingsMeta,
+    ranges: Vec<Range<u32>>,
+    metrics: &PipelineMetrics,
+) -> Result<(), RenderingError> {
+    render_ranges_to_channel(
+        constants,
+        project,
+        sender,
+        recording_meta,
+        meta,
+        segments,
+        fps,
+        resolution_base,
+        recordings,
+        ranges,
+        0,
+        metrics,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn render_ranges_to_channel(
+    constants: &RenderVideoConstants,
+    project: &ProjectConfiguration,
+    sender: mpsc::Sender<(RenderedFrame, u32)>,
+    recording_meta: &RecordingMeta,
+    meta: &StudioRecordingMeta,
+    segments: Vec<RenderSegment>,
+    fps: u32,
+    resolution_base: XY<u32>,
+    recordings: &ProjectRecordingsMeta,
+    ranges: Vec<Range<u32>>,
+    number_offset: u32,
+    metrics: &PipelineMetrics,
 ) -> Result<(), RenderingError> {
     ffmpeg::init().unwrap();
 
     let start_time = Instant::now();
 
     let duration = get_duration(recordings, recording_meta, meta, project);
-
-    let total_frames = (fps as f64 * duration).ceil() as u32;
-
-    let mut frame_number = 0;
+    let project_frames = (fps as f64 * duration).ceil() as u32;
+    let frame_rate = FrameRate::from_fps(fps);
 
     let mut frame_renderer = FrameRenderer::new(constants);
 
     let mut layers = RendererLayers::new(&constants.device, &constants.queue);
 
-    loop {
-        if frame_number >= total_frames {
-            break;
-        }
+    let mut rendered_frames = 0;
 
-        let Some((segment_time, segment_i)) =
-            project.get_segment_time(frame_number as f64 / fps as f64)
-        else {
-            break;
-        };
+    'ranges: for frame_range in ranges {
+        for frame_number in frame_range.start..frame_range.end.min(project_frames) {
+            let Some((segment_time, segment_i)) =
+                project.get_segment_time(frame_rate.frame_time(frame_number))
+            else {
+                break 'ranges;
+            };
 
-        let segment = &segments[segment_i as usize];
+            let segment = &segments[segment_i as usize];
 
-        let frame_number = {
-            let prev = frame_number;
-            std::mem::replace(&mut frame_number, prev + 1)
-        };
+            rendered_frames += 1;
+
+            // a frame is rendered for every frame number, so the output stays continuous
+            // across segment boundaries and anywhere the recording's missing frames
+            let segment_frames = metrics
+                .time_async(
+                    PipelineStage::Decode,
+                    segment
+                        .decoders
+                        .get_frames(segment_time, !project.camera.hide),
+                )
+                .await?
+                .unwrap_or_else(|| segment.decoders.gap_frames(segment_time));
 
-        if let Some(segment_frames) = segment
-            .decoders
-            .get_frames(segment_time as f32, !project.camera.hide)
-            .await
-        {
             let uniforms = ProjectUniforms::new(
                 constants,
                 project,
@@ -237,21 +338,24 @@ pub async fn render_video_to_channel(
                 &segment_frames,
             );
 
-            let frame = frame_renderer
-                .render(segment_frames, uniforms, &segment.cursor, &mut layers)
+            let frame = metrics
+                .time_async(
+                    PipelineStage::Render,
+                    frame_renderer.render(segment_frames, uniforms, &segment.cursor, &mut layers),
+                )
                 .await?;
 
             if frame.width == 0 || frame.height == 0 {
                 continue;
             }
 
-            sender.send((frame, frame_number)).await?;
+            sender.send((frame, frame_number - number_offset)).await?;
         }
     }
 
     let total_time = start_time.elapsed();
     println!(
-        "Render complete. Processed {frame_number} frames in {:?} seconds",
+        "Render complete. Processed {rendered_frames} frames in {:?} seconds",
         total_time.as_secs_f32()
     );
 
@@ -864,7 +968,8 @@ impl ProjectUniforms {
 }
 
 pub struct DecodedSegmentFrames {
-    pub screen_frame: DecodedFrame,
+    /// `None` in gaps, see [`RecordingSegmentDecoders::gap_frames`]
+    pub screen_frame: Option<DecodedFrame>,
     pub camera_frame: Option<DecodedFrame>,
     pub segment_time: f32,
     pub recording_time: f32,
@@ -1050,7 +1155,7 @@ impl RendererLayers {
             self.display.render(&mut pass);
         }
 
-        if uniforms.scene.should_render_screen() {
+        if uniforms.scene.should_render_screen() && self.display.is_visible() {
             let mut pass = render_pass!(session.current_texture_view(), wgpu::LoadOp::Load);
             self.cursor.render(&mut pass);
         }
@@ -7,6 +7,8 @@ use cap_project::{AudioMeta, StudioRecordingMeta, VideoMeta};
 use serde::Serialize;
 use specta::Type;
 
+use crate::Rotation;
+
 #[derive(Debug, Clone, Copy, Serialize, Type)]
 pub struct Video {
     pub duration: f64,
@@ -35,9 +37,16 @@ impl Video {
             let rate = stream.avg_frame_rate();
             let fps = rate.numerator() as f64 / rate.denominator() as f64;
 
+            // frames are decoded upright, see `Rotation`
+            let (width, height) = if Rotation::of_stream(&stream).swaps_dimensions() {
+                (video_decoder.height(), video_decoder.width())
+            } else {
+                (video_decoder.width(), video_decoder.height())
+            };
+
             Ok(Video {
-                width: video_decoder.width(),
-                height: video_decoder.height(),
+                width,
+                height,
                 duration: input.duration() as f64 / 1_000_000.0,
                 fps: fps.round() as u32,
                 start_time,
@@ -0,0 +1,63 @@
+use cap_media::{
+    MediaError,
+    encoders::{ImageFrame, StillImageFormat, encode_image},
+};
+use futures::StreamExt;
+use std::path::Path;
+
+use crate::thumbnail::spawn_thumbnail_decoder;
+
+/// Decodes exactly frame `frame_number` of a video, counting from 0, and writes it
+/// to `output_png` at full size, like the editor's "copy current frame".
+///
+/// Fails with [`MediaError::MissingMedia`] if the file has no video. Frames past the end
+/// of the video are an error too, rather than the last frame being written in their place.
+pub async fn screenshot_at_frame(
+    path: &Path,
+    frame_number: u32,
+    output_png: &Path,
+) -> Result<(), MediaError> {
+    let decoder = spawn_thumbnail_decoder("screenshot", path).await?;
+    check_frame_number(frame_number, decoder.frame_count())?;
+
+    let mut frames = std::pin::pin!(decoder.get_frames(frame_number..frame_number + 1));
+    let (_, frame) = frames
+        .next()
+        .await
+        .ok_or(MediaError::MissingMedia("video frame"))?
+        .map_err(|e| MediaError::Any(format!("Screenshot / decode / {e}").into()))?;
+
+    encode_image(
+        ImageFrame {
+            data: &frame.data,
+            width: frame.width,
+            height: frame.height,
+            stride: frame.stride,
+        },
+        StillImageFormat::Png,
+        output_png,
+    )
+}
+
+fn check_frame_number(frame_number: u32, frame_count: u32) -> Result<(), MediaError> {
+    if frame_number < frame_count {
+        return Ok(());
+    }
+
+    Err(MediaError::Any(
+        format!("Frame {frame_number} is out of range, the video has {frame_count} frames").into(),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rejects_frames_past_the_end() {
+        assert!(check_frame_number(0, 30).is_ok());
+        assert!(check_frame_number(29, 30).is_ok());
+        assert!(check_frame_number(30, 30).is_err());
+        assert!(check_frame_number(0, 0).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use cap_media::MediaError;
+use cap_project::XY;
+use futures::StreamExt;
+use image::{Rgba, RgbaImage, imageops};
+use serde::Serialize;
+use specta::Type;
+use std::path::Path;
+
+use crate::thumbnail::{ThumbnailSize, scale_frame, spawn_thumbnail_decoder};
+
+/// A grid of evenly spaced frames from a video, for previewing while scrubbing.
+pub struct Storyboard {
+    pub image: RgbaImage,
+    pub metadata: StoryboardMetadata,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct StoryboardMetadata {
+    pub cols: u32,
+    pub rows: u32,
+    pub cell_size: XY<u32>,
+    /// In order of increasing time, laid out left to right then top to bottom.
+    pub cells: Vec<StoryboardCell>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct StoryboardCell {
+    /// Time of the frame shown in this cell, in seconds.
+    pub time: f64,
+    /// Top-left corner of the cell in the storyboard image.
+    pub position: XY<u32>,
+}
+
+/// Decodes `cols * rows` evenly spaced frames and packs them into one image,
+/// letterboxing each frame to `cell_size`.
+///
+/// Frames are requested in increasing order from a single decoder,
+/// so it only has to seek when the next frame is in a later GOP.
+pub async fn generate_storyboard(
+    path: &Path,
+    cols: u32,
+    rows: u32,
+    cell_size: XY<u32>,
+) -> Result<Storyboard, MediaError> {
+    if cols == 0 || rows == 0 || cell_size.x == 0 || cell_size.y == 0 {
+        return Err(MediaError::Any(
+            "Storyboard / needs at least one cell with a non-zero size".into(),
+        ));
+    }
+
+    let decoder = spawn_thumbnail_decoder("storyboard", path).await?;
+    let frame_rate = decoder.frame_rate();
+    let frame_count = decoder.frame_count().max(1);
+
+    let mut image =
+        RgbaImage::from_pixel(cols * cell_size.x, rows * cell_size.y, Rgba([0, 0, 0, 255]));
+    let mut cells = Vec::with_capacity((cols * rows) as usize);
+
+    for (i, frame) in cell_frames(cols * rows, frame_count)
+        .into_iter()
+        .enumerate()
+    {
+        let i = i as u32;
+        let position = XY::new((i % cols) * cell_size.x, (i / cols) * cell_size.y);
+
+        let mut frames = std::pin::pin!(decoder.get_frames(frame..frame + 1));
+        match frames.next().await {
+            Some(Ok((_, decoded))) => {
+                let cell = scale_frame(decoded, ThumbnailSize::Letterbox(cell_size))?;
+                imageops::replace(&mut image, &cell, position.x as i64, position.y as i64);
+            }
+            Some(Err(e)) => {
+                return Err(MediaError::Any(format!("Storyboard / decode / {e}").into()));
+            }
+            // Past the last decodable frame, which can happen when the frame count is
+            // estimated. The cell is left blank rather than failing the whole storyboard.
+            None => {}
+        }
+
+        cells.push(StoryboardCell {
+            time: frame_rate.frame_time(frame),
+            position,
+        });
+    }
+
+    Ok(Storyboard {
+        image,
+        metadata: StoryboardMetadata {
+            cols,
+            rows,
+            cell_size,
+            cells,
+        },
+    })
+}
+
+/// Frame numbers at the middle of `cells` equal slices of the video, in increasing order.
+fn cell_frames(cells: u32, frame_count: u32) -> Vec<u32> {
+    (0..cells)
+        .map(|i| {
+            let frame = (i as f64 + 0.5) * frame_count as f64 / cells as f64;
+            (frame.floor() as u32).min(frame_count - 1)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spaces_frames_evenly() {
+        assert_eq!(cell_frames(4, 400), vec![50, 150, 250, 350]);
+    }
+
+    #[test]
+    fn repeats_frames_for_short_videos() {
+        assert_eq!(cell_frames(4, 2), vec![0, 0, 1, 1]);
+    }
+}
@@ -0,0 +1,250 @@
+use cap_media::{MediaError, data::RgbaFrame, filters::FrameScorer};
+use cap_project::XY;
+use futures::StreamExt;
+use image::{Rgba, RgbaImage, imageops};
+use std::{ops::Range, path::Path, time::Duration};
+
+use crate::FrameRate;
+use crate::decoder::{
+    AsyncVideoDecoderHandle, DecodedFrame, DecoderOptions, probe_fps, spawn_decoder,
+};
+
+/// How a frame is fitted into the requested thumbnail size.
+/// The frame's aspect ratio is always preserved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThumbnailSize {
+    /// Scales the frame to fit within the size, so one side may come out smaller.
+    Fit(XY<u32>),
+    /// Scales the frame to fit within the size and pads it with black bars,
+    /// so the image is always exactly the requested size.
+    Letterbox(XY<u32>),
+}
+
+impl ThumbnailSize {
+    fn bounds(&self) -> XY<u32> {
+        match *self {
+            Self::Fit(bounds) | Self::Letterbox(bounds) => bounds,
+        }
+    }
+
+    fn validate(&self) -> Result<(), MediaError> {
+        let bounds = self.bounds();
+        if bounds.x == 0 || bounds.y == 0 {
+            return Err(MediaError::Any("Thumbnail / size must be non-zero".into()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes the frame nearest to `at_time` and scales it down to a thumbnail,
+/// without needing a project or render pipeline.
+pub async fn extract_thumbnail(
+    path: &Path,
+    at_time: Duration,
+    size: ThumbnailSize,
+) -> Result<RgbaImage, MediaError> {
+    size.validate()?;
+
+    let decoder = spawn_thumbnail_decoder("thumbnail", path).await?;
+
+    let frame_count = decoder.frame_count().max(1);
+    let frame = nearest_frame(at_time, decoder.frame_rate()).min(frame_count - 1);
+
+    let mut frames = std::pin::pin!(decoder.get_frames(frame..frame + 1));
+    let (_, frame) = frames
+        .next()
+        .await
+        .ok_or(MediaError::MissingMedia("video frame"))?
+        .map_err(|e| MediaError::Any(format!("Thumbnail / decode / {e}").into()))?;
+
+    scale_frame(frame, size)
+}
+
+/// Decodes every `every`th frame within `range` and scales the most
+/// representative one down to a thumbnail, skipping past black or faded frames.
+///
+/// See [`FrameScorer`] for how frames are scored.
+pub async fn extract_best_thumbnail(
+    path: &Path,
+    range: Range<Duration>,
+    every: u32,
+    size: ThumbnailSize,
+) -> Result<RgbaImage, MediaError> {
+    size.validate()?;
+
+    let decoder = spawn_thumbnail_decoder("best thumbnail", path).await?;
+
+    let frame_count = decoder.frame_count().max(1);
+    let start = nearest_frame(range.start, decoder.frame_rate()).min(frame_count - 1);
+    let end = nearest_frame(range.end, decoder.frame_rate()).clamp(start + 1, frame_count);
+
+    let mut scorer = FrameScorer::new(start..end, every);
+    let mut best = None;
+
+    for frame in scorer.sample_frames() {
+        let mut frames = std::pin::pin!(decoder.get_frames(frame..frame + 1));
+        let decoded = match frames.next().await {
+            Some(Ok((_, decoded))) => decoded,
+            Some(Err(e)) => {
+                return Err(MediaError::Any(
+                    format!("Best thumbnail / decode / {e}").into(),
+                ));
+            }
+            // past the last decodable frame of an estimated frame count
+            None => break,
+        };
+
+        if scorer.push_frame(frame, &RgbaFrame::try_from(decoded.clone())?) {
+            best = Some(decoded);
+        }
+    }
+
+    scale_frame(best.ok_or(MediaError::MissingMedia("video frame"))?, size)
+}
+
+/// Spawns an RGBA decoder for a standalone video file,
+/// using the frame rate stored in the file.
+pub(crate) async fn spawn_thumbnail_decoder(
+    name: &'static str,
+    path: &Path,
+) -> Result<AsyncVideoDecoderHandle, MediaError> {
+    let fps = probe_fps(path).map_err(|e| match e {
+        MediaError::MissingMedia(_) => e,
+        e => MediaError::Any(format!("'{name}' decoder / probe fps / {e}").into()),
+    })?;
+
+    spawn_decoder(
+        name,
+        path.to_path_buf(),
+        fps,
+        0.0,
+        DecoderOptions::default(),
+    )
+    .await
+}
+
+pub(crate) fn nearest_frame(time: Duration, frame_rate: FrameRate) -> u32 {
+    (time.as_secs_f64() * frame_rate.as_f64()).round() as u32
+}
+
+/// Scales a decoded RGBA frame to the thumbnail size.
+pub(crate) fn scale_frame(
+    frame: DecodedFrame,
+    size: ThumbnailSize,
+) -> Result<RgbaImage, MediaError> {
+    let (width, height) = (frame.width, frame.height);
+    let image = frame_image(frame)?;
+
+    let scaled_size = fit_within(XY::new(width, height), size.bounds());
+    let scaled = imageops::resize(
+        &image,
+        scaled_size.x,
+        scaled_size.y,
+        imageops::FilterType::Triangle,
+    );
+
+    Ok(match size {
+        ThumbnailSize::Fit(_) => scaled,
+        ThumbnailSize::Letterbox(bounds) => {
+            let mut padded = RgbaImage::from_pixel(bounds.x, bounds.y, Rgba([0, 0, 0, 255]));
+            imageops::overlay(
+                &mut padded,
+                &scaled,
+                ((bounds.x - scaled_size.x) / 2) as i64,
+                ((bounds.y - scaled_size.y) / 2) as i64,
+            );
+            padded
+        }
+    })
+}
+
+/// Wraps a decoded RGBA frame's data in an image, without copying it unless it's shared.
+pub(crate) fn frame_image(frame: DecodedFrame) -> Result<RgbaImage, MediaError> {
+    Ok(RgbaFrame::try_from(frame)?.into_image())
+}
+
+/// Largest size with the same aspect ratio as `size` that fits within `bounds`,
+/// never smaller than 1x1.
+fn fit_within(size: XY<u32>, bounds: XY<u32>) -> XY<u32> {
+    if size.x == 0 || size.y == 0 {
+        return XY::new(bounds.x.max(1), bounds.y.max(1));
+    }
+
+    let scale = (bounds.x as f64 / size.x as f64).min(bounds.y as f64 / size.y as f64);
+
+    XY::new(
+        ((size.x as f64 * scale).round() as u32).clamp(1, bounds.x.max(1)),
+        ((size.y as f64 * scale).round() as u32).clamp(1, bounds.y.max(1)),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn fits_wide_frame_by_width() {
+        assert_eq!(
+            fit_within(XY::new(1920, 1080), XY::new(320, 320)),
+            XY::new(320, 180)
+        );
+    }
+
+    #[test]
+    fn fits_tall_frame_by_height() {
+        assert_eq!(
+            fit_within(XY::new(1080, 1920), XY::new(320, 320)),
+            XY::new(180, 320)
+        );
+    }
+
+    #[test]
+    fn upscales_small_frames() {
+        assert_eq!(
+            fit_within(XY::new(100, 50), XY::new(400, 400)),
+            XY::new(400, 200)
+        );
+    }
+
+    #[test]
+    fn letterboxes_to_exact_size() {
+        let frame = DecodedFrame {
+            data: Arc::new(vec![255; 160 * 90 * 4]),
+            width: 160,
+            height: 90,
+            stride: 160 * 4,
+        };
+
+        let image = scale_frame(frame, ThumbnailSize::Letterbox(XY::new(100, 100))).unwrap();
+
+        assert_eq!(image.dimensions(), (100, 100));
+        // bars above and below the 100x56 frame
+        assert_eq!(image.get_pixel(50, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(image.get_pixel(50, 50), &Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn rejects_zero_size() {
+        assert!(
+            ThumbnailSize::Letterbox(XY::new(0, 100))
+                .validate()
+                .is_err()
+        );
+        assert!(ThumbnailSize::Fit(XY::new(100, 0)).validate().is_err());
+        assert!(ThumbnailSize::Letterbox(XY::new(1, 1)).validate().is_ok());
+    }
+
+    #[test]
+    fn picks_nearest_frame() {
+        assert_eq!(
+            nearest_frame(Duration::from_millis(1020), FrameRate::from_fps(30)),
+            31
+        );
+        assert_eq!(
+            nearest_frame(Duration::from_millis(1010), FrameRate::from_fps(30)),
+            30
+        );
+    }
+}
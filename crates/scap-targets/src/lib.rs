@@ -135,6 +135,10 @@ impl Window {
         self.0.owner_name()
     }
 
+    pub fn is_minimized(&self) -> bool {
+        self.0.is_minimized()
+    }
+
     pub fn app_icon(&self) -> Option<Vec<u8>> {
         self.0.app_icon()
     }
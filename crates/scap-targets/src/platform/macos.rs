@@ -2,15 +2,17 @@ use std::{ffi::c_void, str::FromStr};
 
 use cidre::{arc, ns, sc};
 use cocoa::appkit::NSScreen;
-use core_foundation::{array::CFArray, base::FromVoid, number::CFNumber, string::CFString};
+use core_foundation::{
+    array::CFArray, base::FromVoid, boolean::CFBoolean, number::CFNumber, string::CFString,
+};
 use core_graphics::{
     display::{
         CFDictionary, CGDirectDisplayID, CGDisplay, CGDisplayBounds, CGDisplayCopyDisplayMode,
         CGRect, kCGWindowListOptionIncludingWindow,
     },
     window::{
-        CGWindowID, kCGWindowBounds, kCGWindowLayer, kCGWindowName, kCGWindowNumber,
-        kCGWindowOwnerName,
+        CGWindowID, kCGWindowBounds, kCGWindowIsOnscreen, kCGWindowLayer, kCGWindowName,
+        kCGWindowNumber, kCGWindowOwnerName,
     },
 };
 
@@ -331,6 +333,28 @@ impl WindowImpl {
         }
     }
 
+    /// Treats any window that isn't on screen as minimized.
+    pub fn is_minimized(&self) -> bool {
+        let Some(windows) =
+            core_graphics::window::copy_window_info(kCGWindowListOptionIncludingWindow, self.0)
+        else {
+            return false;
+        };
+
+        let Some(window) = windows.get(0) else {
+            return false;
+        };
+
+        let window_dict = unsafe { CFDictionary::<CFString, *const c_void>::from_void(*window) };
+
+        unsafe {
+            window_dict
+                .find(kCGWindowIsOnscreen)
+                .map(|v| !bool::from((*CFBoolean::from_void(*v)).clone()))
+                .unwrap_or(true)
+        }
+    }
+
     pub fn logical_bounds(&self) -> Option<LogicalBounds> {
         let windows =
             core_graphics::window::copy_window_info(kCGWindowListOptionIncludingWindow, self.0)?;
@@ -953,6 +953,10 @@ impl WindowImpl {
         .ok()
     }
 
+    pub fn is_minimized(&self) -> bool {
+        unsafe { IsIconic(self.0) }.as_bool()
+    }
+
     pub fn is_on_screen(&self) -> bool {
         if !unsafe { IsWindowVisible(self.0) }.as_bool() {
             return false;
@@ -0,0 +1,98 @@
+//! Decodes a video in software and in hardware and prints how fast each was,
+//! to check whether hardware decoding is working.
+//!
+//! With `--threads`, software decoding on one thread is compared against decoding
+//! with `auto`, `<count>` frame threads or `slice:<count>` slice threads instead.
+//!
+//! Usage: `decode_benchmark <path> [--threads <auto|count|slice:count>]`
+
+use cap_video_decode::{DecoderThreads, FFmpegDecoder};
+use ffmpeg::sys::AVHWDeviceType;
+
+const USAGE: &str = "Usage: decode_benchmark <path> [--threads <auto|count|slice:count>]";
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let Some(path) = args.next() else {
+        eprintln!("{USAGE}");
+        std::process::exit(1);
+    };
+
+    let threads = match (args.next().as_deref(), args.next()) {
+        (None, _) => None,
+        (Some("--threads"), Some(threads)) => match parse_threads(&threads) {
+            Some(threads) => Some(threads),
+            None => {
+                eprintln!("Invalid thread setting '{threads}'\n{USAGE}");
+                std::process::exit(1);
+            }
+        },
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(1);
+        }
+    };
+
+    ffmpeg::init().unwrap();
+
+    if let Some(threads) = threads {
+        compare_threads(&path, threads);
+    } else {
+        compare_hw_decode(&path);
+    }
+}
+
+fn parse_threads(threads: &str) -> Option<DecoderThreads> {
+    if threads == "auto" {
+        return Some(DecoderThreads::Auto);
+    }
+
+    match threads.strip_prefix("slice:") {
+        Some(count) => count.parse().ok().map(DecoderThreads::Slice),
+        None => threads.parse().ok().map(DecoderThreads::Frame),
+    }
+}
+
+fn compare_threads(path: &str, threads: DecoderThreads) {
+    let benchmark = |threads| match FFmpegDecoder::benchmark(path, None, threads) {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("Failed to decode '{path}': {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let single = benchmark(DecoderThreads::Frame(1));
+    let threaded = benchmark(threads);
+
+    println!("1 thread: {single}");
+    println!("{threads}: {threaded}");
+    println!("{threads} is {:.1}x faster", threaded.fps / single.fps);
+}
+
+fn compare_hw_decode(path: &str) {
+    let hw_device_type = if cfg!(target_os = "macos") {
+        AVHWDeviceType::AV_HWDEVICE_TYPE_VIDEOTOOLBOX
+    } else if cfg!(windows) {
+        AVHWDeviceType::AV_HWDEVICE_TYPE_D3D12VA
+    } else {
+        AVHWDeviceType::AV_HWDEVICE_TYPE_VAAPI
+    };
+
+    let comparison = match FFmpegDecoder::compare_hw_decode(path, hw_device_type) {
+        Ok(comparison) => comparison,
+        Err(e) => {
+            eprintln!("Failed to decode '{path}': {e}");
+            std::process::exit(1);
+        }
+    };
+
+    println!("software: {}", comparison.software);
+    println!("{hw_device_type:?}: {}", comparison.hardware);
+
+    if comparison.hardware_working() {
+        println!("hardware decoding is {:.1}x faster", comparison.speedup());
+    } else {
+        println!("hardware decoding isn't working, it fell back to software or dropped frames");
+    }
+}
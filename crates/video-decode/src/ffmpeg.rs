@@ -1,13 +1,227 @@
+use cap_media::MediaError;
 use ffmpeg::{
-    codec as avcodec,
-    format::{self as avformat, context::input::PacketIter},
+    ChannelLayout, Rational,
+    codec::{self as avcodec, threading},
+    format::{self as avformat, context::input::PacketIter, stream::Stream},
     frame as avframe,
+    software::resampling,
     sys::{AVHWDeviceType, EAGAIN},
     util as avutil,
 };
 use ffmpeg_hw_device::{CodecContextExt, HwDevice};
-use std::path::PathBuf;
-use tracing::debug;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+pub use cap_media::TARGET_SAMPLE_RATE;
+
+/// What to do when a packet fails to decode because its data is invalid,
+/// as is common in recovered or truncated recordings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CorruptFramePolicy {
+    /// Drop the corrupt frame and keep decoding.
+    #[default]
+    Skip,
+    /// Yield a [`MediaError::CorruptFrame`] and stop decoding.
+    Fail,
+}
+
+/// How many threads software decoding uses. Hardware decoding mostly ignores this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecoderThreads {
+    /// As many as FFmpeg picks for the machine's cores, with whichever kinds of
+    /// threading the codec supports.
+    #[default]
+    Auto,
+    /// Each thread decodes a different frame. This speeds up most codecs but adds a
+    /// frame of latency per thread, which slows down decoding single frames after seeking.
+    Frame(usize),
+    /// Each thread decodes part of a frame, for codecs and files that support it.
+    Slice(usize),
+}
+
+impl DecoderThreads {
+    /// Has to be called before the decoder is opened.
+    fn apply(self, context: &mut avcodec::Context) {
+        let (kind, count) = match self {
+            // FFmpeg defaults to one thread, and to both kinds of threading
+            Self::Auto => {
+                unsafe { (*context.as_mut_ptr()).thread_count = 0 };
+                return;
+            }
+            Self::Frame(count) => (threading::Type::Frame, count),
+            Self::Slice(count) => (threading::Type::Slice, count),
+        };
+
+        context.set_threading(threading::Config { kind, count });
+    }
+}
+
+impl fmt::Display for DecoderThreads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto threads"),
+            Self::Frame(count) => write!(f, "{count} frame threads"),
+            Self::Slice(count) => write!(f, "{count} slice threads"),
+        }
+    }
+}
+
+/// A decoded frame and when it should be presented.
+///
+/// Frames come out of the decoder in presentation order, which for streams with B-frames
+/// differs from the order their packets are stored in.
+pub struct TimestampedFrame {
+    pub frame: avframe::Video,
+    /// Presentation timestamp, in `time_base` units.
+    /// This is ffmpeg's best-effort timestamp, which is the pts if the stream provides one.
+    pub pts: i64,
+    pub time_base: Rational,
+}
+
+impl TimestampedFrame {
+    pub fn seconds(&self) -> f64 {
+        self.pts as f64 * f64::from(self.time_base)
+    }
+}
+
+/// How fast a video decoded, measured by [`FFmpegDecoder::benchmark`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodeStats {
+    pub frames: usize,
+    pub elapsed: Duration,
+    /// Frames decoded per second
+    pub fps: f64,
+    /// Whether the frames were decoded in hardware. Decoding falls back to software
+    /// if the requested hardware device can't be used.
+    pub hardware: bool,
+}
+
+impl fmt::Display for DecodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} frames in {:.2?} ({:.1} fps, {})",
+            self.frames,
+            self.elapsed,
+            self.fps,
+            if self.hardware {
+                "hardware"
+            } else {
+                "software"
+            }
+        )
+    }
+}
+
+/// How much of a possibly truncated video [`FFmpegDecoder::recover`] decoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Recovery {
+    pub frames: usize,
+    /// Corrupt frames that were dropped
+    pub skipped_frames: usize,
+    /// Time of the last recovered frame, from the start of the video
+    pub duration: Duration,
+    /// Decoding stopped at data that couldn't be read, rather than at the end of the file.
+    /// Files cut off at a packet boundary just end early, so this can be `false` for them too.
+    pub truncated: bool,
+}
+
+/// Software and hardware decoding of the same video, from [`FFmpegDecoder::compare_hw_decode`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HwDecodeComparison {
+    pub software: DecodeStats,
+    pub hardware: DecodeStats,
+}
+
+impl HwDecodeComparison {
+    /// The hardware device was used and decoded every frame software did.
+    pub fn hardware_working(&self) -> bool {
+        self.hardware.hardware && self.hardware.frames == self.software.frames
+    }
+
+    /// How many times faster hardware decoding was.
+    pub fn speedup(&self) -> f64 {
+        self.hardware.fps / self.software.fps
+    }
+}
+
+/// How far a video's frames have to be turned clockwise to be upright.
+///
+/// Phones and rotated displays record frames as the sensor or display sees them, and store
+/// how to rotate them in a display matrix that many players and decoders ignore.
+/// Rotations that aren't a quarter turn are rounded to the nearest one, and flips are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    #[default]
+    None,
+    Clockwise90,
+    Clockwise180,
+    Clockwise270,
+}
+
+impl Rotation {
+    /// Reads the rotation of the best video stream in the file at `path`.
+    pub fn probe(path: impl AsRef<Path>) -> Result<Self, MediaError> {
+        let input = avformat::input(&path)?;
+        let stream = input
+            .streams()
+            .best(avutil::media::Type::Video)
+            .ok_or(MediaError::MissingMedia("video"))?;
+
+        Ok(Self::of_stream(&stream))
+    }
+
+    /// The rotation in the stream's display matrix, or [`Rotation::None`] if it doesn't have one.
+    pub fn of_stream(stream: &Stream) -> Self {
+        stream
+            .side_data()
+            .find(|side_data| side_data.kind() == ffmpeg::packet::side_data::Type::DisplayMatrix)
+            .and_then(|side_data| Self::from_display_matrix(side_data.data()))
+            .unwrap_or_default()
+    }
+
+    /// Parses a display matrix, which is 9 native endian 32-bit fixed point numbers
+    /// in row-major order, like ffmpeg's `av_display_rotation_get`.
+    pub fn from_display_matrix(data: &[u8]) -> Option<Self> {
+        let value = |i: usize| {
+            data.get(i * 4..i * 4 + 4)
+                .map(|b| i32::from_ne_bytes([b[0], b[1], b[2], b[3]]) as f64)
+        };
+        let (a, b) = (value(0)?, value(1)?);
+
+        // the angle of the first row is how far the matrix turns frames clockwise,
+        // with the scale of each axis cancelling out
+        (a != 0.0 || b != 0.0).then(|| Self::from_degrees(b.atan2(a).to_degrees()))
+    }
+
+    /// The quarter turn nearest to turning `degrees` clockwise.
+    pub fn from_degrees(degrees: f64) -> Self {
+        match ((degrees / 90.0).round() as i64).rem_euclid(4) {
+            1 => Self::Clockwise90,
+            2 => Self::Clockwise180,
+            3 => Self::Clockwise270,
+            _ => Self::None,
+        }
+    }
+
+    pub fn degrees(&self) -> u32 {
+        match self {
+            Self::None => 0,
+            Self::Clockwise90 => 90,
+            Self::Clockwise180 => 180,
+            Self::Clockwise270 => 270,
+        }
+    }
+
+    /// Whether rotated frames are as wide as unrotated ones are tall.
+    pub fn swaps_dimensions(&self) -> bool {
+        matches!(self, Self::Clockwise90 | Self::Clockwise270)
+    }
+}
 
 pub struct FFmpegDecoder {
     input: avformat::context::Input,
@@ -15,16 +229,31 @@ pub struct FFmpegDecoder {
     stream_index: usize,
     hw_device: Option<HwDevice>,
     start_time: i64,
+    rotation: Rotation,
+    corrupt_frame_policy: CorruptFramePolicy,
+    skipped_frames: usize,
+    allow_truncated: bool,
+    truncated: bool,
 }
 
 impl FFmpegDecoder {
     pub fn new(
         path: impl Into<PathBuf>,
         hw_device_type: Option<AVHWDeviceType>,
+    ) -> Result<Self, String> {
+        Self::new_with_threads(path, hw_device_type, DecoderThreads::default())
+    }
+
+    /// Decodes in software with `threads`, which has to be set before the decoder is opened.
+    pub fn new_with_threads(
+        path: impl Into<PathBuf>,
+        hw_device_type: Option<AVHWDeviceType>,
+        threads: DecoderThreads,
     ) -> Result<Self, String> {
         fn inner(
             path: PathBuf,
             hw_device_type: Option<AVHWDeviceType>,
+            threads: DecoderThreads,
         ) -> Result<FFmpegDecoder, String> {
             let input = ffmpeg::format::input(&path).map_err(|e| format!("open file / {e}"))?;
 
@@ -34,32 +263,44 @@ impl FFmpegDecoder {
                 .ok_or_else(|| "no video stream".to_string())?;
 
             let start_time = input_stream.start_time();
+            let rotation = Rotation::of_stream(&input_stream);
 
             let stream_index = input_stream.index();
 
             let mut decoder = avcodec::Context::from_parameters(input_stream.parameters())
                 .map_err(|e| format!("decoder context / {e}"))?
-                .decoder()
-                .video()
-                .map_err(|e| format!("video decoder / {e}"))?;
-
-            decoder.set_time_base(input_stream.time_base());
+                .decoder();
+            threads.apply(&mut decoder);
 
-            let width = decoder.width();
-            let height = decoder.height();
+            let parameters = input_stream.parameters();
+            let (width, height) =
+                unsafe { ((*parameters.as_ptr()).width, (*parameters.as_ptr()).height) };
 
             let exceeds_common_hw_limits = width > 4096 || height > 4096;
 
+            // the hardware device has to be attached before the decoder is opened
             let hw_device = hw_device_type
-                .and_then(|_| {
-		                if exceeds_common_hw_limits{
-				                debug!("Video dimensions {width}x{height} exceed common hardware decoder limits (4096x4096), not using hardware acceleration");
-				                None
-		                } else {
-			               		None
-		                }
+                .filter(|_| {
+                    if exceeds_common_hw_limits {
+                        debug!("Video dimensions {width}x{height} exceed common hardware decoder limits (4096x4096), not using hardware acceleration");
+                    }
+
+                    !exceeds_common_hw_limits
                 })
-                .and_then(|hw_device_type| decoder.try_use_hw_device(hw_device_type).ok());
+                .and_then(|hw_device_type| {
+                    decoder
+                        .try_use_hw_device(hw_device_type)
+                        .inspect_err(|e| {
+                            warn!("Failed to use {hw_device_type:?} for decoding, falling back to software: {e}");
+                        })
+                        .ok()
+                });
+
+            let mut decoder = decoder
+                .video()
+                .map_err(|e| format!("video decoder / {e}"))?;
+
+            decoder.set_time_base(input_stream.time_base());
 
             Ok(FFmpegDecoder {
                 input,
@@ -67,10 +308,55 @@ impl FFmpegDecoder {
                 stream_index,
                 hw_device,
                 start_time,
+                rotation,
+                corrupt_frame_policy: CorruptFramePolicy::default(),
+                skipped_frames: 0,
+                allow_truncated: false,
+                truncated: false,
             })
         }
 
-        inner(path.into(), hw_device_type)
+        inner(path.into(), hw_device_type, threads)
+    }
+
+    /// Decodes every frame of the video at `path` as fast as possible to measure throughput,
+    /// including copying frames back from the hardware device if one's used.
+    pub fn benchmark(
+        path: impl Into<PathBuf>,
+        hw_device_type: Option<AVHWDeviceType>,
+        threads: DecoderThreads,
+    ) -> Result<DecodeStats, String> {
+        let mut decoder = Self::new_with_threads(path, hw_device_type, threads)?;
+        let hardware = decoder.hw_device.is_some();
+
+        let start = Instant::now();
+        let mut frames = 0;
+        for frame in decoder.frames() {
+            frame.map_err(|e| format!("decode frame {frames} / {e}"))?;
+            frames += 1;
+        }
+        let elapsed = start.elapsed();
+
+        Ok(DecodeStats {
+            frames,
+            elapsed,
+            fps: frames as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+            hardware,
+        })
+    }
+
+    /// Benchmarks software decoding against `hw_device_type`,
+    /// to check whether hardware decoding works on this machine and how much it helps.
+    pub fn compare_hw_decode(
+        path: impl Into<PathBuf>,
+        hw_device_type: AVHWDeviceType,
+    ) -> Result<HwDecodeComparison, String> {
+        let path = path.into();
+
+        Ok(HwDecodeComparison {
+            software: Self::benchmark(&path, None, DecoderThreads::Auto)?,
+            hardware: Self::benchmark(&path, Some(hw_device_type), DecoderThreads::Auto)?,
+        })
     }
 
     pub fn reset(&mut self, requested_time: f32) -> Result<(), ffmpeg::Error> {
@@ -82,15 +368,122 @@ impl FFmpegDecoder {
         self.input.seek(position, ..position)
     }
 
+    /// Moves to the last keyframe at or before `to`, measured from the start of the video,
+    /// so that [`Self::frames`] resumes decoding from there.
+    ///
+    /// Decoding has to start from a keyframe, so the first frames returned after seeking
+    /// may precede `to`. Callers that need to land exactly on it should discard frames whose
+    /// pts is earlier than `to` in the stream's time base, offset by [`Self::start_time`].
+    pub fn seek(&mut self, to: Duration) -> Result<(), String> {
+        use ffmpeg::rescale::{self, Rescale};
+
+        let time_base = self
+            .input
+            .stream(self.stream_index)
+            .ok_or_else(|| "no video stream".to_string())?
+            .time_base();
+
+        let start_time = if self.start_time == ffmpeg::ffi::AV_NOPTS_VALUE {
+            0
+        } else {
+            self.start_time.rescale(time_base, rescale::TIME_BASE)
+        };
+        let position =
+            (to.as_micros() as i64).rescale((1, 1_000_000), rescale::TIME_BASE) + start_time;
+
+        self.input
+            .seek(position, ..position)
+            .map_err(|e| format!("seek to {:.3}s / {e}", to.as_secs_f64()))?;
+        self.decoder.flush();
+
+        Ok(())
+    }
+
+    /// Timestamps of the keyframes in the video stream's index, in the stream's time base.
+    ///
+    /// The demuxer reads the index when the file is opened, so this doesn't read any packets.
+    /// It's empty for containers without one, and some containers index decode timestamps,
+    /// which can be a frame or two before the pts.
+    pub fn keyframes(&self) -> Vec<i64> {
+        let Some(stream) = self.input.stream(self.stream_index) else {
+            return vec![];
+        };
+        let stream = unsafe { stream.as_ptr() } as *mut ffmpeg::ffi::AVStream;
+
+        let count = unsafe { ffmpeg::ffi::avformat_index_get_entries_count(stream) };
+        let mut keyframes = (0..count)
+            .filter_map(|i| unsafe { ffmpeg::ffi::avformat_index_get_entry(stream, i).as_ref() })
+            .filter(|entry| entry.flags() & ffmpeg::ffi::AVINDEX_KEYFRAME as i32 != 0)
+            .map(|entry| entry.timestamp)
+            .collect::<Vec<_>>();
+        keyframes.sort_unstable();
+
+        keyframes
+    }
+
+    pub fn with_corrupt_frame_policy(mut self, policy: CorruptFramePolicy) -> Self {
+        self.corrupt_frame_policy = policy;
+        self
+    }
+
+    /// Decodes as much of a truncated file as possible, like a recording that wasn't
+    /// finalized because the app crashed. Errors reading the rest of the file end decoding
+    /// as if it had ended there, and the frames still buffered in the decoder are returned.
+    ///
+    /// The file still has to open, so MP4s that were cut off before their `moov` atom was
+    /// written can't be recovered. Fragmented MP4s, and MP4s with it at the front, can be.
+    pub fn with_allow_truncated(mut self, allow_truncated: bool) -> Self {
+        self.allow_truncated = allow_truncated;
+        self
+    }
+
+    /// Decodes every frame of a possibly truncated video at `path`,
+    /// reporting how much of it could be recovered. See [`Self::with_allow_truncated`].
+    pub fn recover(
+        path: impl Into<PathBuf>,
+        hw_device_type: Option<AVHWDeviceType>,
+    ) -> Result<Recovery, String> {
+        let mut decoder = Self::new(path, hw_device_type)?.with_allow_truncated(true);
+        let start_time = decoder.start_time.max(0);
+
+        let mut frames = 0;
+        let mut duration = Duration::ZERO;
+        for frame in decoder.frames() {
+            let frame = frame.map_err(|e| format!("decode frame {frames} / {e}"))?;
+            frames += 1;
+
+            let time = (frame.pts - start_time) as f64 * f64::from(frame.time_base);
+            duration = duration.max(Duration::from_secs_f64(time.max(0.0)));
+        }
+
+        Ok(Recovery {
+            frames,
+            skipped_frames: decoder.skipped_frames,
+            duration,
+            truncated: decoder.truncated,
+        })
+    }
+
     pub fn frames(&mut self) -> FramesIter<'_> {
         FramesIter {
             packets: self.input.packets(),
             decoder: &mut self.decoder,
             stream_index: self.stream_index,
             hw_device: self.hw_device.as_mut(),
+            corrupt_frame_policy: self.corrupt_frame_policy,
+            skipped_frames: &mut self.skipped_frames,
+            allow_truncated: self.allow_truncated,
+            truncated: &mut self.truncated,
+            draining: false,
+            failed: false,
         }
     }
 
+    /// Number of corrupt frames that have been skipped since the decoder was created.
+    pub fn skipped_frames(&self) -> usize {
+        self.skipped_frames
+    }
+
     pub fn decoder(&self) -> &avcodec::decoder::Video {
         &self.decoder
     }
@@ -98,6 +491,11 @@ impl FFmpegDecoder {
     pub fn start_time(&self) -> i64 {
         self.start_time
     }
+
+    /// How far frames have to be turned to be upright. They're decoded unrotated.
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
 }
 
 unsafe impl Send for FFmpegDecoder {}
@@ -107,34 +505,111 @@ pub struct FramesIter<'a> {
     packets: PacketIter<'a>,
     stream_index: usize,
     hw_device: Option<&'a mut HwDevice>,
+    corrupt_frame_policy: CorruptFramePolicy,
+    skipped_frames: &'a mut usize,
+    allow_truncated: bool,
+    truncated: &'a mut bool,
+    /// The packets have run out, and the frames left in the decoder are being returned
+    draining: bool,
+    failed: bool,
 }
 
 impl FramesIter<'_> {
     pub fn decoder(&self) -> &avcodec::decoder::Video {
         self.decoder
     }
+
+    pub fn skipped_frames(&self) -> usize {
+        *self.skipped_frames
+    }
+
+    /// Applies the corrupt frame policy to a decode error.
+    /// Returns `None` if the error was skipped and decoding should continue.
+    fn handle_error(&mut self, error: avutil::error::Error) -> Option<MediaError> {
+        if error != ffmpeg::Error::InvalidData {
+            self.failed = true;
+
+            if self.allow_truncated {
+                warn!("Stopping at unreadable data, treating the file as truncated: {error}");
+                *self.truncated = true;
+                return None;
+            }
+
+            return Some(error.into());
+        }
+
+        match self.corrupt_frame_policy {
+            CorruptFramePolicy::Skip => {
+                *self.skipped_frames += 1;
+                warn!(
+                    "Skipping corrupt frame ({} skipped so far): {error}",
+                    self.skipped_frames
+                );
+                None
+            }
+            CorruptFramePolicy::Fail => {
+                self.failed = true;
+                Some(MediaError::CorruptFrame(error))
+            }
+        }
+    }
 }
 
 impl<'a> Iterator for FramesIter<'a> {
-    type Item = Result<avframe::Video, avutil::error::Error>;
+    type Item = Result<TimestampedFrame, MediaError>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
         let mut frame = avframe::Video::empty();
 
         loop {
             match self.decoder.receive_frame(&mut frame) {
                 Ok(()) => {
-                    return match &self.hw_device {
-                        Some(hw_device) => Some(Ok(hw_device.get_hwframe(&frame).unwrap_or(frame))),
-                        None => Some(Ok(frame)),
+                    // read before transferring from the hardware device, which doesn't copy it
+                    let Some(pts) = frame.timestamp().or(frame.pts()) else {
+                        debug!("Dropping frame without a timestamp");
+                        continue;
+                    };
+
+                    let frame = match &self.hw_device {
+                        Some(hw_device) => hw_device.get_hwframe(&frame).unwrap_or(frame),
+                        None => frame,
                     };
+
+                    return Some(Ok(TimestampedFrame {
+                        frame,
+                        pts,
+                        time_base: self.decoder.time_base(),
+                    }));
                 }
                 Err(ffmpeg::Error::Eof) => return None,
                 Err(ffmpeg::Error::Other { errno }) if errno == EAGAIN => {}
-                Err(e) => return Some(Err(e)),
+                Err(e) => {
+                    if let Some(e) = self.handle_error(e) {
+                        return Some(Err(e));
+                    }
+                    if self.failed {
+                        return None;
+                    }
+                }
             }
 
-            let (stream, packet) = self.packets.next()?;
+            let Some((stream, packet)) = self.packets.next() else {
+                if self.draining {
+                    return None;
+                }
+
+                // the last frames are still buffered in the decoder, which holds back
+                // a frame per thread with frame threading
+                self.draining = true;
+                if self.decoder.send_eof().is_err() {
+                    return None;
+                }
+                continue;
+            };
 
             if stream.index() != self.stream_index {
                 continue;
@@ -144,8 +619,388 @@ impl<'a> Iterator for FramesIter<'a> {
                 Ok(_) => {}
                 Err(ffmpeg::Error::Eof) => return None,
                 Err(ffmpeg::Error::Other { errno }) if errno == EAGAIN => {}
-                Err(e) => return Some(Err(e)),
+                Err(e) => {
+                    if let Some(e) = self.handle_error(e) {
+                        return Some(Err(e));
+                    }
+                    if self.failed {
+                        return None;
+                    }
+                }
             }
         }
     }
 }
+
+/// Decodes the best audio stream of a file into interleaved `f32` samples.
+pub struct FFmpegAudioDecoder {
+    input: avformat::context::Input,
+    decoder: avcodec::decoder::Audio,
+    resampler: resampling::Context,
+    stream_index: usize,
+    start_time: i64,
+}
+
+impl FFmpegAudioDecoder {
+    pub const SAMPLE_FORMAT: avformat::Sample =
+        avformat::Sample::F32(avformat::sample::Type::Packed);
+
+    /// Output keeps the stream's channel layout. If `sample_rate` is `None` the stream's own
+    /// rate is kept, otherwise the audio is resampled to it (usually [`TARGET_SAMPLE_RATE`]).
+    pub fn new(path: impl Into<PathBuf>, sample_rate: Option<u32>) -> Result<Self, MediaError> {
+        let input = ffmpeg::format::input(&path.into())?;
+
+        let input_stream = input
+            .streams()
+            .best(avutil::media::Type::Audio)
+            .ok_or(MediaError::MissingMedia("audio"))?;
+
+        let start_time = input_stream.start_time();
+        let stream_index = input_stream.index();
+
+        let mut decoder = avcodec::Context::from_parameters(input_stream.parameters())?
+            .decoder()
+            .audio()?;
+
+        if decoder.channel_layout().is_empty() {
+            decoder.set_channel_layout(ChannelLayout::default(decoder.channels() as i32));
+        }
+        decoder.set_packet_time_base(input_stream.time_base());
+
+        let resampler = ffmpeg::software::resampler(
+            (decoder.format(), decoder.channel_layout(), decoder.rate()),
+            (
+                Self::SAMPLE_FORMAT,
+                decoder.channel_layout(),
+                sample_rate.unwrap_or(decoder.rate()),
+            ),
+        )?;
+
+        Ok(Self {
+            input,
+            decoder,
+            resampler,
+            stream_index,
+            start_time,
+        })
+    }
+
+    /// Sample rate of the decoded output.
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.output().rate
+    }
+
+    /// Number of interleaved channels in each decoded frame.
+    pub fn channels(&self) -> u16 {
+        self.resampler.output().channel_layout.channels() as u16
+    }
+
+    pub fn decoder(&self) -> &avcodec::decoder::Audio {
+        &self.decoder
+    }
+
+    pub fn start_time(&self) -> i64 {
+        self.start_time
+    }
+
+    pub fn frames(&mut self) -> AudioFramesIter<'_> {
+        AudioFramesIter {
+            packets: self.input.packets(),
+            decoder: &mut self.decoder,
+            resampler: &mut self.resampler,
+            stream_index: self.stream_index,
+            decoded: avframe::Audio::empty(),
+            resampled: avframe::Audio::empty(),
+            sent_eof: false,
+            draining_resampler: false,
+            failed: false,
+        }
+    }
+}
+
+unsafe impl Send for FFmpegAudioDecoder {}
+
+/// Yields one buffer of interleaved samples per decoded frame,
+/// followed by whatever the resampler had buffered once the stream ends.
+pub struct AudioFramesIter<'a> {
+    packets: PacketIter<'a>,
+    decoder: &'a mut avcodec::decoder::Audio,
+    resampler: &'a mut resampling::Context,
+    stream_index: usize,
+    decoded: avframe::Audio,
+    resampled: avframe::Audio,
+    sent_eof: bool,
+    draining_resampler: bool,
+    failed: bool,
+}
+
+impl AudioFramesIter<'_> {
+    fn resampled_samples(&self) -> Vec<f32> {
+        let len = self.resampled.samples() * self.resampled.channels() as usize * 4;
+
+        self.resampled.data(0)[..len]
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    }
+
+    fn fail(&mut self, error: impl Into<MediaError>) -> Option<Result<Vec<f32>, MediaError>> {
+        self.failed = true;
+        Some(Err(error.into()))
+    }
+}
+
+impl Iterator for AudioFramesIter<'_> {
+    type Item = Result<Vec<f32>, MediaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        loop {
+            if self.draining_resampler {
+                return match self.resampler.flush(&mut self.resampled) {
+                    Ok(_) if self.resampled.samples() > 0 => Some(Ok(self.resampled_samples())),
+                    Ok(_) => None,
+                    Err(e) => self.fail(e),
+                };
+            }
+
+            match self.decoder.receive_frame(&mut self.decoded) {
+                Ok(()) => {
+                    if self.decoded.channel_layout().is_empty() {
+                        self.decoded
+                            .set_channel_layout(self.decoder.channel_layout());
+                    }
+
+                    if let Err(e) = self.resampler.run(&self.decoded, &mut self.resampled) {
+                        return self.fail(e);
+                    }
+
+                    if self.resampled.samples() > 0 {
+                        return Some(Ok(self.resampled_samples()));
+                    }
+
+                    continue;
+                }
+                Err(ffmpeg::Error::Eof) => {
+                    self.draining_resampler = true;
+                    continue;
+                }
+                Err(ffmpeg::Error::Other { errno }) if errno == EAGAIN => {}
+                Err(e) => return self.fail(e),
+            }
+
+            let Some((stream, packet)) = self.packets.next() else {
+                if self.sent_eof {
+                    return None;
+                }
+
+                self.sent_eof = true;
+                if let Err(e) = self.decoder.send_eof() {
+                    return self.fail(e);
+                }
+                continue;
+            };
+
+            if stream.index() != self.stream_index {
+                continue;
+            }
+
+            match self.decoder.send_packet(&packet) {
+                Ok(_) => {}
+                Err(ffmpeg::Error::Other { errno }) if errno == EAGAIN => {}
+                Err(e) => return self.fail(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ffmpeg::{Rescale, codec::encoder, format::Pixel};
+
+    const FPS: i32 = 30;
+
+    /// Writes an MPEG-4 video of `frames` grey 64x64 frames with B-frames and a keyframe
+    /// every 10 frames, which every FFmpeg build can encode and decode with frame threads.
+    fn write_video(name: &str, frames: i64) -> PathBuf {
+        ffmpeg::init().unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "cap-video-decode-{name}-{}.mp4",
+            std::process::id()
+        ));
+        let mut output = avformat::output(&path).unwrap();
+        let codec = encoder::find(avcodec::Id::MPEG4).unwrap();
+
+        let mut encoder = avcodec::Context::new_with_codec(codec)
+            .encoder()
+            .video()
+            .unwrap();
+        encoder.set_width(64);
+        encoder.set_height(64);
+        encoder.set_format(Pixel::YUV420P);
+        encoder.set_time_base(Rational::new(1, FPS));
+        encoder.set_max_b_frames(2);
+        encoder.set_gop(10);
+        let mut encoder = encoder.open().unwrap();
+
+        output.add_stream(codec).unwrap().set_parameters(&encoder);
+        output.write_header().unwrap();
+        let time_base = output.stream(0).unwrap().time_base();
+
+        let mut packet = ffmpeg::Packet::empty();
+        let mut frame = avframe::Video::new(Pixel::YUV420P, 64, 64);
+        for plane in 0..frame.planes() {
+            frame.data_mut(plane).fill(128);
+        }
+
+        for i in 0..=frames {
+            if i < frames {
+                frame.set_pts(Some(i));
+                encoder.send_frame(&frame).unwrap();
+            } else {
+                encoder.send_eof().unwrap();
+            }
+
+            while encoder.receive_packet(&mut packet).is_ok() {
+                packet.set_stream(0);
+                packet.rescale_ts(Rational::new(1, FPS), time_base);
+                packet.write_interleaved(&mut output).unwrap();
+            }
+        }
+
+        output.write_trailer().unwrap();
+        path
+    }
+
+    /// Writes a second of a constant mono signal at `rate` as 16-bit PCM in Matroska,
+    /// starting `start_ms` milliseconds in.
+    fn write_audio(name: &str, rate: i32, start_ms: i64) -> PathBuf {
+        ffmpeg::init().unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "cap-video-decode-{name}-{}.mkv",
+            std::process::id()
+        ));
+        let mut output = avformat::output(&path).unwrap();
+        let codec = encoder::find(avcodec::Id::PCM_S16LE).unwrap();
+        let format = avformat::Sample::I16(avformat::sample::Type::Packed);
+
+        let mut encoder = avcodec::Context::new_with_codec(codec)
+            .encoder()
+            .audio()
+            .unwrap();
+        encoder.set_rate(rate);
+        encoder.set_channel_layout(ChannelLayout::MONO);
+        encoder.set_format(format);
+        encoder.set_time_base(Rational::new(1, rate));
+        let mut encoder = encoder.open().unwrap();
+
+        output.add_stream(codec).unwrap().set_parameters(&encoder);
+        output.write_header().unwrap();
+        let time_base = output.stream(0).unwrap().time_base();
+
+        // 100 frames make up the second
+        let frame_size = rate as usize / 100;
+        let start = start_ms * rate as i64 / 1000;
+
+        let mut packet = ffmpeg::Packet::empty();
+        let mut frame = avframe::Audio::new(format, frame_size, ChannelLayout::MONO);
+        frame.set_rate(rate as u32);
+        for sample in frame.data_mut(0).chunks_exact_mut(2) {
+            sample.copy_from_slice(&(i16::MAX / 4 + 1).to_le_bytes());
+        }
+
+        for i in 0..=100 {
+            if i < 100 {
+                frame.set_pts(Some(start + (i * frame_size) as i64));
+                encoder.send_frame(&frame).unwrap();
+            } else {
+                encoder.send_eof().unwrap();
+            }
+
+            while encoder.receive_packet(&mut packet).is_ok() {
+                packet.set_stream(0);
+                packet.rescale_ts(Rational::new(1, rate), time_base);
+                packet.write_interleaved(&mut output).unwrap();
+            }
+        }
+
+        output.write_trailer().unwrap();
+        path
+    }
+
+    #[test]
+    fn audio_is_resampled_and_keeps_its_start_time() {
+        let path = write_audio("resample", 44_100, 500);
+
+        let mut decoder = FFmpegAudioDecoder::new(&path, Some(TARGET_SAMPLE_RATE)).unwrap();
+        let (rate, channels, start_time) = (
+            decoder.sample_rate(),
+            decoder.channels(),
+            decoder.start_time(),
+        );
+        let samples = decoder
+            .frames()
+            .map(|frame| frame.unwrap())
+            .collect::<Vec<_>>()
+            .concat();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(rate, TARGET_SAMPLE_RATE);
+        assert_eq!(channels, 1);
+        // Matroska timestamps are in milliseconds
+        assert_eq!(start_time, 500);
+
+        // a second of audio, give or take the resampler's rounding
+        assert!(samples.len().abs_diff(48_000) <= 32, "{}", samples.len());
+        assert!((samples[24_000] - 0.25).abs() < 0.001);
+    }
+
+    fn count_frames(path: &Path, threads: DecoderThreads) -> usize {
+        let mut decoder = FFmpegDecoder::new_with_threads(path, None, threads).unwrap();
+        decoder.frames().map(|frame| frame.unwrap()).count()
+    }
+
+    #[test]
+    fn frame_threads_return_every_frame() {
+        let path = write_video("threads", 45);
+
+        let auto = count_frames(&path, DecoderThreads::Auto);
+        let single = count_frames(&path, DecoderThreads::Frame(1));
+        let several = count_frames(&path, DecoderThreads::Frame(4));
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(single, 45);
+        assert_eq!(auto, single);
+        assert_eq!(several, single);
+    }
+
+    #[test]
+    fn keyframes_come_from_the_index() {
+        let path = write_video("keyframes", 45);
+
+        let decoder = FFmpegDecoder::new(&path, None).unwrap();
+        let time_base = decoder
+            .input
+            .stream(decoder.stream_index)
+            .unwrap()
+            .time_base();
+        let keyframes = decoder
+            .keyframes()
+            .into_iter()
+            .map(|ts| ts.rescale(time_base, (1, FPS)))
+            .collect::<Vec<_>>();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(keyframes.len(), 5, "{keyframes:?}");
+        assert!(
+            keyframes.windows(2).all(|pair| pair[1] - pair[0] == 10),
+            "{keyframes:?}"
+        );
+    }
+}
@@ -4,4 +4,7 @@ pub mod ffmpeg;
 
 #[cfg(target_os = "macos")]
 pub use avassetreader::AVAssetReaderDecoder;
-pub use ffmpeg::FFmpegDecoder;
+pub use ffmpeg::{
+    CorruptFramePolicy, DecodeStats, DecoderThreads, FFmpegAudioDecoder, FFmpegDecoder,
+    HwDecodeComparison, Recovery, Rotation,
+};